@@ -1,10 +1,17 @@
-use anda_core::{AgentInput, AgentOutput, BoxError, HttpFeatures, ToolInput, ToolOutput};
-use anda_web3_client::client::{Client as Web3Client, load_identity};
+use anda_core::{
+    AgentEvent, AgentInput, AgentOutput, BoxError, ByteArrayB64, ByteBufB64, HttpFeatures,
+    RequestMeta, Resource, SseParser, ToolInput, ToolOutput, Xid,
+};
+use anda_engine::context::Information;
+use anda_web3_client::client::{Client as Web3Client, Identity, load_identity};
 use base64::{Engine, prelude::BASE64_URL_SAFE};
+use candid::Principal;
 use ciborium::value::Value;
 use clap::{Parser, Subcommand};
+use ic_cose_types::cose::sha3_256;
 use rand::RngCore;
-use std::sync::Arc;
+use std::{io::Write, str::FromStr, sync::Arc};
+use url::Url;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -48,6 +55,12 @@ pub enum Commands {
         data: String,
     },
 
+    /// List the agents and tools of the engine on the endpoint.
+    Info {
+        #[arg(short, long, default_value = "http://127.0.0.1:8042/default")]
+        endpoint: String,
+    },
+
     /// Run an AI agent with the given prompt and name on the endpoint.
     AgentRun {
         #[arg(short, long, default_value = "http://127.0.0.1:8042/default")]
@@ -58,6 +71,18 @@ pub enum Commands {
 
         #[arg(short, long)]
         name: Option<String>,
+
+        /// Path to a file to attach, can be repeated.
+        #[arg(short, long)]
+        attachment: Vec<String>,
+
+        /// Thread ID of the session to continue, a new session is created if not set.
+        #[arg(short, long)]
+        thread: Option<String>,
+
+        /// Stream the output of the agent as it is generated.
+        #[arg(short, long)]
+        stream: bool,
     },
 
     /// Call a tool with the given name and args on the endpoint.
@@ -71,8 +96,32 @@ pub enum Commands {
         #[arg(short, long)]
         args: String,
     },
+
+    /// Manage the sessions (threads) of the caller on the endpoint.
+    Thread {
+        #[arg(short, long, default_value = "http://127.0.0.1:8042/default")]
+        endpoint: String,
+
+        #[command(subcommand)]
+        action: ThreadAction,
+    },
 }
 
+#[derive(Subcommand)]
+pub enum ThreadAction {
+    /// Get the metadata of the thread.
+    Get { id: String },
+    /// Delete the thread.
+    Delete { id: String },
+    /// Add a participant to the thread.
+    AddParticipant { id: String, user: String },
+    /// Remove a participant from the thread.
+    RemoveParticipant { id: String, user: String },
+}
+
+/// The name of the engine's built-in tool managing the threads.
+const THREAD_TOOL: &str = "sys_my_threads";
+
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
     dotenv::dotenv().ok();
@@ -103,14 +152,7 @@ async fn main() -> Result<(), BoxError> {
             method,
             data,
         }) => {
-            let web3 = Web3Client::builder()
-                .with_ic_host(&cli.host)
-                .with_identity(Arc::new(identity))
-                .with_allow_http(true, None)
-                .build()
-                .await?;
-
-            println!("principal: {}", web3.get_principal());
+            let web3 = build_client(&cli.host, identity).await?;
             let args: serde_json::Value = serde_json::from_str(data)?;
             let args = if args.is_array() {
                 args
@@ -122,32 +164,71 @@ async fn main() -> Result<(), BoxError> {
             println!("{:?}", res);
         }
 
+        Some(Commands::Info { endpoint }) => {
+            let web3 = build_client(&cli.host, identity).await?;
+            let info: Information = web3.https_signed_rpc(endpoint, "information", &()).await?;
+            println!("engine: {} ({})", info.name, info.id.to_text());
+            println!("{}", info.description);
+            println!("\nagents:");
+            for agent in &info.agents {
+                println!(
+                    "  {}: {}",
+                    agent.definition.name, agent.definition.description
+                );
+            }
+            println!("\ntools:");
+            for tool in &info.tools {
+                println!(
+                    "  {}: {}",
+                    tool.definition.name, tool.definition.description
+                );
+            }
+        }
+
         Some(Commands::AgentRun {
             endpoint,
             name,
             prompt,
+            attachment,
+            thread,
+            stream,
         }) => {
-            let web3 = Web3Client::builder()
-                .with_ic_host(&cli.host)
-                .with_identity(Arc::new(identity))
-                .with_allow_http(true, None)
-                .build()
-                .await?;
-
-            println!("principal: {}", web3.get_principal());
+            let web3 = build_client(&cli.host, identity).await?;
+            let (url, engine) = stream_endpoint(endpoint)?;
+            let mut resources = Vec::with_capacity(attachment.len());
+            for path in attachment {
+                resources.push(load_attachment(path)?);
+            }
+            let input = AgentInput {
+                name: name.clone().unwrap_or_else(|| "".to_string()),
+                prompt: prompt.clone(),
+                resources: if resources.is_empty() {
+                    None
+                } else {
+                    Some(resources)
+                },
+                meta: Some(RequestMeta {
+                    engine,
+                    thread: thread.as_deref().map(Xid::from_str).transpose()?,
+                    ..Default::default()
+                }),
+            };
 
-            let res: AgentOutput = web3
-                .https_signed_rpc(
-                    endpoint,
-                    "agent_run",
-                    &(&AgentInput {
-                        name: name.clone().unwrap_or_else(|| "".to_string()),
-                        prompt: prompt.clone(),
-                        ..Default::default()
-                    },),
-                )
-                .await?;
-            println!("{:?}", res);
+            let res = if *stream {
+                agent_run_stream(&web3, &url, &input).await?
+            } else {
+                web3.https_signed_rpc(endpoint, "agent_run", &(&input,))
+                    .await?
+            };
+            if let Some(reason) = &res.failed_reason {
+                println!("failed: {}", reason);
+            }
+            if !*stream {
+                println!("{}", res.content);
+            }
+            if let Some(thread) = &res.thread {
+                println!("thread: {}", thread);
+            }
         }
 
         Some(Commands::ToolCall {
@@ -155,14 +236,7 @@ async fn main() -> Result<(), BoxError> {
             name,
             args,
         }) => {
-            let web3 = Web3Client::builder()
-                .with_ic_host(&cli.host)
-                .with_identity(Arc::new(identity))
-                .with_allow_http(true, None)
-                .build()
-                .await?;
-
-            println!("principal: {}", web3.get_principal());
+            let web3 = build_client(&cli.host, identity).await?;
             let args: serde_json::Value = serde_json::from_str(args)?;
 
             let res: ToolOutput<serde_json::Value> = web3
@@ -179,6 +253,37 @@ async fn main() -> Result<(), BoxError> {
             println!("{}", serde_json::to_string_pretty(&res)?);
         }
 
+        Some(Commands::Thread { endpoint, action }) => {
+            let web3 = build_client(&cli.host, identity).await?;
+            let args = match action {
+                ThreadAction::Get { id } => {
+                    serde_json::json!({"method": "get_thread_meta", "thread_id": id})
+                }
+                ThreadAction::Delete { id } => {
+                    serde_json::json!({"method": "delete_thread_meta", "thread_id": id})
+                }
+                ThreadAction::AddParticipant { id, user } => serde_json::json!({
+                    "method": "add_participant", "thread_id": id, "user_id": user,
+                }),
+                ThreadAction::RemoveParticipant { id, user } => serde_json::json!({
+                    "method": "remove_participant", "thread_id": id, "user_id": user,
+                }),
+            };
+
+            let res: ToolOutput<serde_json::Value> = web3
+                .https_signed_rpc(
+                    endpoint,
+                    "tool_call",
+                    &(&ToolInput {
+                        name: THREAD_TOOL.to_string(),
+                        args,
+                        ..Default::default()
+                    },),
+                )
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&res.output)?);
+        }
+
         None => {
             println!("no command");
         }
@@ -186,3 +291,124 @@ async fn main() -> Result<(), BoxError> {
 
     Ok(())
 }
+
+async fn build_client(host: &str, identity: Box<dyn Identity>) -> Result<Web3Client, BoxError> {
+    let web3 = Web3Client::builder()
+        .with_ic_host(host)
+        .with_identity(Arc::new(identity))
+        .with_allow_http(true, None)
+        .build()
+        .await?;
+
+    println!("principal: {}", web3.get_principal());
+    Ok(web3)
+}
+
+/// Resolves the RPC endpoint of an engine, e.g. `http://127.0.0.1:8042/default`,
+/// to the streaming endpoint of the server and the engine id, None for the default engine.
+fn stream_endpoint(endpoint: &str) -> Result<(Url, Option<Principal>), BoxError> {
+    let url = Url::parse(endpoint)?;
+    let engine = match url.path_segments().and_then(|mut s| s.next_back()) {
+        None | Some("") | Some("default") => None,
+        Some(id) => Some(Principal::from_text(id)?),
+    };
+    Ok((url.join("/v1/agent_run")?, engine))
+}
+
+/// Loads a file as a resource, tagged by the type of its MIME type, e.g. "image".
+fn load_attachment(path: &str) -> Result<Resource, BoxError> {
+    let data = std::fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string());
+    let ext = std::path::Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let mime_type = match ext.as_str() {
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    };
+    Ok(Resource {
+        tag: mime_type.split('/').next().unwrap_or_default().to_string(),
+        name,
+        mime_type: Some(mime_type.to_string()),
+        size: Some(data.len()),
+        hash: Some(ByteArrayB64(sha3_256(&data))),
+        blob: Some(ByteBufB64(data)),
+        ..Default::default()
+    })
+}
+
+/// Runs the agent with the streaming endpoint of the server, prints its content
+/// and tool calls as they arrive, and returns the final output.
+async fn agent_run_stream(
+    web3: &Web3Client,
+    url: &Url,
+    input: &AgentInput,
+) -> Result<AgentOutput, BoxError> {
+    let body = serde_json::to_vec(input)?;
+    let mut headers = http::HeaderMap::new();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        http::header::ACCEPT,
+        http::HeaderValue::from_static("text/event-stream"),
+    );
+    let mut res = web3
+        .https_signed_call(
+            url.as_str(),
+            http::Method::POST,
+            sha3_256(&body),
+            Some(headers),
+            Some(body),
+        )
+        .await?;
+    let status = res.status();
+    if !status.is_success() {
+        let msg = res.text().await.unwrap_or_default();
+        return Err(format!("agent run failed, status: {}, body: {}", status, msg).into());
+    }
+
+    let mut parser = SseParser::new();
+    let mut stdout = std::io::stdout();
+    while let Some(chunk) = res.chunk().await? {
+        for event in parser.feed(&chunk) {
+            match serde_json::from_str::<AgentEvent>(&event.data)? {
+                // the whole content is printed when the round completes
+                AgentEvent::Delta { .. } => {}
+                AgentEvent::Content { content, .. } => {
+                    print!("{}", content);
+                    stdout.flush()?;
+                }
+                AgentEvent::ToolCallStart { name, args, .. } => {
+                    println!("\n> {}({})", name, args);
+                }
+                AgentEvent::ToolCallEnd { name, error, .. } => match error {
+                    Some(err) => println!("> {} failed: {}", name, err),
+                    None => println!("> {} done", name),
+                },
+                AgentEvent::Output(output) => {
+                    println!();
+                    return Ok(output);
+                }
+                AgentEvent::Error { error } => return Err(error.into()),
+            }
+        }
+    }
+    Err("agent run stream ended without output".into())
+}
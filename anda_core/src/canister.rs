@@ -0,0 +1,311 @@
+//! Structured errors and batches of canister calls.
+//!
+//! [`CanisterCaller`](crate::CanisterCaller) returns a [`BoxError`](crate::BoxError).
+//! Implementations in the Anda engine box a [`CanisterCallError`] so callers can
+//! recover the IC reject code and decide whether a call is safe to retry:
+//!
+//! ```rust,ignore
+//! match ctx.canister_update::<_, Nat>(&ledger, "icrc1_transfer", (args,)).await {
+//!     Err(err) => match canister_call_error(&err) {
+//!         Some(e) if e.retryable => { /* retry later */ }
+//!         _ => return Err(err),
+//!     },
+//!     Ok(idx) => { /* ... */ }
+//! }
+//! ```
+//!
+//! [`CanisterBatchCaller`] pipelines several calls concurrently, e.g. to read the balances of
+//! an account on several ledgers in one round trip:
+//!
+//! ```rust,ignore
+//! let calls = ledgers
+//!     .iter()
+//!     .map(|ledger| CanisterCall::new(*ledger, "icrc1_balance_of", (account,)))
+//!     .collect();
+//! let balances: Vec<Result<Nat, BoxError>> = ctx.canister_query_batch(calls).await;
+//! ```
+
+use candid::{CandidType, Principal, utils::ArgumentEncoder};
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+use crate::{BoxError, CanisterCaller};
+
+/// The maximum number of calls of a batch in flight at once.
+pub const MAX_BATCH_CONCURRENCY: usize = 16;
+
+/// Reject codes of the Internet Computer.
+/// See <https://internetcomputer.org/docs/references/ic-interface-spec#reject-codes>.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum RejectCode {
+    /// Fatal system error, retry unlikely to be useful.
+    SysFatal = 1,
+    /// Transient system error, retry might be possible.
+    SysTransient = 2,
+    /// Invalid destination (e.g. canister/account does not exist).
+    DestinationInvalid = 3,
+    /// Explicit reject by the canister.
+    CanisterReject = 4,
+    /// Canister error (e.g., trap, no response).
+    CanisterError = 5,
+    /// Response unknown; system stopped waiting for it (e.g., timed out, or system under high load).
+    SysUnknown = 6,
+}
+
+impl RejectCode {
+    /// Converts a numeric reject code, returns `None` for unknown codes.
+    pub fn from_u64(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(Self::SysFatal),
+            2 => Some(Self::SysTransient),
+            3 => Some(Self::DestinationInvalid),
+            4 => Some(Self::CanisterReject),
+            5 => Some(Self::CanisterError),
+            6 => Some(Self::SysUnknown),
+            _ => None,
+        }
+    }
+}
+
+/// A structured canister call error.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "canister call {}.{method} failed: {}{message}",
+    .canister.to_text(),
+    .reject_code.map(|c| format!("{c:?}, ")).unwrap_or_default()
+)]
+pub struct CanisterCallError {
+    /// The target canister.
+    pub canister: Principal,
+    /// The called method.
+    pub method: String,
+    /// Whether the call was an update call.
+    pub update: bool,
+    /// The IC reject code, `None` for transport or encoding errors.
+    pub reject_code: Option<RejectCode>,
+    /// The IC error code, e.g. "IC0503".
+    pub error_code: Option<String>,
+    /// The reject or error message.
+    pub message: String,
+    /// Whether the call is safe to retry.
+    pub retryable: bool,
+}
+
+impl CanisterCallError {
+    /// Creates an error without reject code, e.g. for transport errors.
+    /// Query calls are retryable, update calls are not since they may have been executed.
+    pub fn new(canister: Principal, method: String, update: bool, message: String) -> Self {
+        Self {
+            canister,
+            method,
+            update,
+            reject_code: None,
+            error_code: None,
+            message,
+            retryable: !update,
+        }
+    }
+
+    /// Creates an error from an IC reject response.
+    pub fn from_reject(
+        canister: Principal,
+        method: String,
+        update: bool,
+        reject_code: u64,
+        message: String,
+        error_code: Option<String>,
+    ) -> Self {
+        let reject_code = RejectCode::from_u64(reject_code);
+        let retryable = match reject_code {
+            Some(RejectCode::SysTransient) => true,
+            // the update call may have been executed
+            Some(RejectCode::SysUnknown) => !update,
+            _ => false,
+        };
+
+        Self {
+            canister,
+            method,
+            update,
+            reject_code,
+            error_code,
+            message,
+            retryable,
+        }
+    }
+
+    /// Sets whether the call is safe to retry.
+    pub fn with_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+}
+
+/// Returns the [`CanisterCallError`] if the error is one.
+pub fn canister_call_error(err: &BoxError) -> Option<&CanisterCallError> {
+    err.downcast_ref::<CanisterCallError>()
+}
+
+/// A call of a canister method, see [`CanisterBatchCaller`].
+#[derive(Debug, Clone)]
+pub struct CanisterCall<In> {
+    /// The target canister.
+    pub canister: Principal,
+    /// The method to call.
+    pub method: String,
+    /// The arguments, encoded in Candid format.
+    pub args: In,
+}
+
+impl<In> CanisterCall<In> {
+    pub fn new(canister: Principal, method: impl Into<String>, args: In) -> Self {
+        Self {
+            canister,
+            method: method.into(),
+            args,
+        }
+    }
+}
+
+/// Batches of canister calls, pipelined concurrently (at most [`MAX_BATCH_CONCURRENCY`] at
+/// once) instead of paying the round-trip latency of each call in turn.
+/// It is implemented for all [`CanisterCaller`]s, so the calls of a context are checked
+/// against its capabilities and policies one by one.
+pub trait CanisterBatchCaller: CanisterCaller + Sync {
+    /// Performs query calls concurrently. The results are in the order of the calls,
+    /// a failed call doesn't fail the others.
+    fn canister_query_batch<In, Out>(
+        &self,
+        calls: Vec<CanisterCall<In>>,
+    ) -> impl Future<Output = Vec<Result<Out, BoxError>>> + Send
+    where
+        In: ArgumentEncoder + Send,
+        Out: CandidType + for<'a> candid::Deserialize<'a> + Send,
+    {
+        stream::iter(calls)
+            .map(move |call| async move {
+                self.canister_query(&call.canister, &call.method, call.args)
+                    .await
+            })
+            .buffered(MAX_BATCH_CONCURRENCY)
+            .collect()
+    }
+
+    /// Performs update calls concurrently. The results are in the order of the calls,
+    /// a failed call doesn't fail the others. The calls are not atomic, and their order of
+    /// execution is not guaranteed: dependent calls must not be in the same batch.
+    fn canister_update_batch<In, Out>(
+        &self,
+        calls: Vec<CanisterCall<In>>,
+    ) -> impl Future<Output = Vec<Result<Out, BoxError>>> + Send
+    where
+        In: ArgumentEncoder + Send,
+        Out: CandidType + for<'a> candid::Deserialize<'a> + Send,
+    {
+        stream::iter(calls)
+            .map(move |call| async move {
+                self.canister_update(&call.canister, &call.method, call.args)
+                    .await
+            })
+            .buffered(MAX_BATCH_CONCURRENCY)
+            .collect()
+    }
+}
+
+impl<T: CanisterCaller + Sync> CanisterBatchCaller for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Echo;
+
+    impl CanisterCaller for Echo {
+        async fn canister_query<
+            In: ArgumentEncoder + Send,
+            Out: CandidType + for<'a> candid::Deserialize<'a>,
+        >(
+            &self,
+            _canister: &Principal,
+            method: &str,
+            args: In,
+        ) -> Result<Out, BoxError> {
+            if method == "fail" {
+                return Err("failed".into());
+            }
+            let args = candid::utils::encode_args(args)?;
+            let (n,): (u64,) = candid::decode_args(&args)?;
+            // the later calls complete first
+            tokio::time::sleep(Duration::from_millis(50 - n * 10)).await;
+            Ok(candid::decode_one(&candid::encode_one(n * 2)?)?)
+        }
+
+        async fn canister_update<
+            In: ArgumentEncoder + Send,
+            Out: CandidType + for<'a> candid::Deserialize<'a>,
+        >(
+            &self,
+            canister: &Principal,
+            method: &str,
+            args: In,
+        ) -> Result<Out, BoxError> {
+            self.canister_query(canister, method, args).await
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_canister_batch() {
+        let canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let calls = vec![
+            CanisterCall::new(canister, "double", (1u64,)),
+            CanisterCall::new(canister, "fail", (2u64,)),
+            CanisterCall::new(canister, "double", (3u64,)),
+        ];
+        let res: Vec<Result<u64, BoxError>> = Echo.canister_query_batch(calls.clone()).await;
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].as_ref().unwrap(), &2);
+        assert_eq!(res[1].as_ref().unwrap_err().to_string(), "failed");
+        assert_eq!(res[2].as_ref().unwrap(), &6);
+
+        let res: Vec<Result<u64, BoxError>> = Echo.canister_update_batch(calls).await;
+        assert!(res[1].is_err());
+        assert_eq!(res[2].as_ref().unwrap(), &6);
+    }
+
+    #[test]
+    fn test_canister_call_error() {
+        let canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let err = CanisterCallError::from_reject(
+            canister,
+            "icrc1_transfer".to_string(),
+            true,
+            5,
+            "canister trapped".to_string(),
+            Some("IC0503".to_string()),
+        );
+        assert_eq!(err.reject_code, Some(RejectCode::CanisterError));
+        assert!(!err.retryable);
+        assert_eq!(
+            err.to_string(),
+            "canister call ryjl3-tyaaa-aaaaa-aaaba-cai.icrc1_transfer failed: CanisterError, canister trapped"
+        );
+
+        let err: BoxError = CanisterCallError::from_reject(
+            canister,
+            "icrc1_balance_of".to_string(),
+            false,
+            6,
+            "timeout".to_string(),
+            None,
+        )
+        .into();
+        let e = canister_call_error(&err).unwrap();
+        assert!(e.retryable);
+        assert_eq!(e.reject_code, Some(RejectCode::SysUnknown));
+
+        let err: BoxError = "other error".into();
+        assert!(canister_call_error(&err).is_none());
+    }
+}
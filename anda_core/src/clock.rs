@@ -0,0 +1,179 @@
+//! Time and randomness of the contexts.
+//!
+//! The contexts read the time from a [`Clock`] and random bytes from a [`RandomSource`] of
+//! the engine, so that tests can replace them with a [`MockClock`], which only advances when
+//! told to, and a [`SeededRandom`], which generates the same bytes for the same seed. Then the
+//! elapsed and remaining time of requests, their deadlines, the backoff of retries and the
+//! generated nonces are deterministic:
+//!
+//! ```rust,ignore
+//! let clock = MockClock::new(1_700_000_000_000);
+//! let engine = EngineBuilder::new()
+//!     .with_clock(Arc::new(clock.clone()))
+//!     .with_random(Arc::new(SeededRandom::new(42)));
+//!
+//! clock.advance(Duration::from_secs(5));
+//! assert_eq!(ctx.time_elapsed(), Duration::from_secs(5));
+//! ```
+//!
+//! Agents and tools should use [`StateFeatures::now_ms`](crate::StateFeatures::now_ms) and
+//! [`StateFeatures::fill_random`](crate::StateFeatures::fill_random) instead of the system
+//! clock and RNG. The expiries of the in-memory cache still follow the system clock.
+
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::BoxPinFut;
+
+/// A source of time.
+pub trait Clock: Send + Sync {
+    /// Returns the current unix timestamp in milliseconds.
+    fn now_ms(&self) -> u64;
+
+    /// Returns the current monotonic instant.
+    fn instant(&self) -> Instant;
+
+    /// Sleeps for the duration.
+    fn sleep(&self, duration: Duration) -> BoxPinFut<()>;
+}
+
+/// The system clock, this is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxPinFut<()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock for tests, which only advances with [`MockClock::advance`] or when sleeping.
+/// Its clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start_ms: u64,
+    start_at: Instant,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock starting at the unix timestamp in milliseconds.
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            start_ms,
+            start_at: Instant::now(),
+            elapsed_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Advances the time by the duration.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Returns the time elapsed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.start_ms + self.elapsed().as_millis() as u64
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_at + self.elapsed()
+    }
+
+    /// Advances the time by the duration and returns immediately.
+    fn sleep(&self, duration: Duration) -> BoxPinFut<()> {
+        self.advance(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// A source of random bytes.
+pub trait RandomSource: Send + Sync {
+    /// Fills the buffer with random bytes.
+    fn fill_bytes(&self, buf: &mut [u8]);
+}
+
+/// The thread-local cryptographically secure RNG, this is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRandom;
+
+impl RandomSource for SystemRandom {
+    fn fill_bytes(&self, buf: &mut [u8]) {
+        rand::rng().fill_bytes(buf);
+    }
+}
+
+/// A deterministic RNG for tests, which generates the same bytes for the same seed.
+#[derive(Debug)]
+pub struct SeededRandom(Mutex<StdRng>);
+
+impl SeededRandom {
+    /// Creates the RNG with the seed.
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn fill_bytes(&self, buf: &mut [u8]) {
+        self.0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .fill_bytes(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_clock() {
+        let clock = MockClock::new(1_000);
+        let start_at = clock.instant();
+        let clock2 = clock.clone();
+        clock2.advance(Duration::from_millis(1_500));
+        assert_eq!(clock.now_ms(), 2_500);
+        assert_eq!(clock.instant() - start_at, Duration::from_millis(1_500));
+
+        clock.sleep(Duration::from_secs(3600)).await;
+        assert_eq!(clock2.now_ms(), 3_602_500);
+        assert_eq!(clock2.elapsed(), Duration::from_millis(3_601_500));
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let (mut a, mut b) = ([0u8; 32], [0u8; 32]);
+        SeededRandom::new(42).fill_bytes(&mut a);
+        SeededRandom::new(42).fill_bytes(&mut b);
+        assert_eq!(a, b);
+
+        let rng = SeededRandom::new(42);
+        rng.fill_bytes(&mut b);
+        rng.fill_bytes(&mut b);
+        assert_ne!(a, b);
+    }
+}
@@ -26,6 +26,7 @@
 //! - [`StoreFeatures`]: Persistent storage capabilities;
 //! - [`CacheFeatures`]: In-memory caching with expiration policies;
 //! - [`HttpFeatures`]: HTTP communication capabilities;
+//! - [`LogFeatures`]: Structured logging tagged with the context;
 //! - [`VectorSearchFeatures`]: Semantic search functionality.
 //!
 //! ## Usage
@@ -35,9 +36,10 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use ciborium::from_reader;
+use futures::stream::BoxStream;
 use ic_cose_types::to_cbor_bytes;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, future::Future, ops::Range, sync::Arc, time::Duration};
 
 pub use candid::Principal;
 pub use ic_cose_types::{CanisterCaller, types::object_store::UpdateVersion};
@@ -45,8 +47,8 @@ pub use object_store::{ObjectMeta, PutMode, PutResult, UpdateVersion as OsVersio
 pub use serde_json::Value;
 pub use tokio_util::sync::CancellationToken;
 
-use crate::BoxError;
 use crate::model::*;
+use crate::{BoxError, Error, Extensions, FeatureFlags, HttpOptions, WebSocket, WsOptions};
 
 /// AgentContext provides the execution environment for Agents.
 /// It combines core functionality with AI-specific features:
@@ -166,8 +168,16 @@ pub trait AgentContext: BaseContext + CompletionFeatures + EmbeddingFeatures {
 /// - [`CacheFeatures`]: In-memory caching.
 /// - [`HttpFeatures`]: HTTP request capabilities.
 /// - [`CanisterCaller`]: ICP blockchain smart contract interactions.
+/// - [`LogFeatures`]: Structured logging.
 pub trait BaseContext:
-    Sized + StateFeatures + KeysFeatures + StoreFeatures + CacheFeatures + HttpFeatures + CanisterCaller
+    Sized
+    + StateFeatures
+    + KeysFeatures
+    + StoreFeatures
+    + CacheFeatures
+    + HttpFeatures
+    + CanisterCaller
+    + LogFeatures
 {
     /// Executes a remote tool call via HTTP RPC.
     ///
@@ -207,10 +217,106 @@ pub trait StateFeatures: Sized {
     /// a child token of the agent's token.
     /// Cancelling the agent's token will cancel all its child calls,
     /// but cancelling a tool's token won't affect its parent agent.
+    /// The in-flight model requests, HTTP requests and RPC calls of a cancelled context
+    /// are aborted, see [`with_cancellation`].
     fn cancellation_token(&self) -> CancellationToken;
 
     /// Gets the time elapsed since the original context was created.
     fn time_elapsed(&self) -> Duration;
+
+    /// Gets the time remaining before the deadline of the request, if it has one,
+    /// see [`RequestMeta::timeout_ms`]. Child contexts inherit the deadline.
+    fn time_remaining(&self) -> Option<Duration>;
+
+    /// Gets the request-scoped extensions, such as a request ID or the locale of the user.
+    /// Child contexts inherit the extensions.
+    fn extensions(&self) -> &Extensions;
+
+    /// Gets the current unix timestamp in milliseconds from the clock of the engine,
+    /// which tests may replace with a [`MockClock`](crate::MockClock).
+    fn now_ms(&self) -> u64;
+
+    /// Fills the buffer with random bytes from the random source of the engine,
+    /// which tests may replace with a [`SeededRandom`](crate::SeededRandom).
+    fn fill_random(&self, buf: &mut [u8]);
+
+    /// Generates N random bytes, e.g. for a nonce, see [`StateFeatures::fill_random`].
+    fn random_bytes<const N: usize>(&self) -> [u8; N] {
+        let mut buf = [0u8; N];
+        self.fill_random(&mut buf);
+        buf
+    }
+
+    /// Returns true if the feature flag is enabled for the caller of the request,
+    /// see [`FeatureFlags`].
+    fn is_feature_enabled(&self, name: &str) -> bool {
+        self.extensions()
+            .get::<FeatureFlags>()
+            .is_some_and(|flags| flags.is_enabled(name))
+    }
+
+    /// Gets the value of the feature flag if it is enabled for the caller of the request,
+    /// e.g. the name of a model variant.
+    fn feature_value(&self, name: &str) -> Option<&Value> {
+        self.extensions()
+            .get::<FeatureFlags>()
+            .and_then(|flags| flags.value(name))
+    }
+
+    /// Gets the locale of the user, see [`RequestMeta::locale`].
+    fn locale(&self) -> Option<&str> {
+        self.meta().locale.as_deref()
+    }
+}
+
+/// The ID of the run serving a request, set by the engine in the request-scoped
+/// [`Extensions`], so that it is inherited by the nested agent runs and tool calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+/// The severity of a log entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// LogFeatures is one of the context feature sets available when calling Agent or Tool.
+///
+/// The entries are written to the logger of the engine, tagged with the engine, the path of
+/// the agent or tool, the caller, the user and the [`RequestId`] of the context, so that the
+/// logs of a request can be correlated. Agents and tools should use it instead of `println!`.
+///
+/// # Example
+/// ```rust,ignore
+/// ctx.log_info("page fetched", &[("url", json!(url)), ("bytes", json!(body.len()))]);
+/// ```
+pub trait LogFeatures: Sized {
+    /// Writes a log entry with the message and the structured fields.
+    fn log(&self, level: LogLevel, message: &str, fields: &[(&str, Value)]);
+
+    /// Writes a debug entry.
+    fn log_debug(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(LogLevel::Debug, message, fields)
+    }
+
+    /// Writes an info entry.
+    fn log_info(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(LogLevel::Info, message, fields)
+    }
+
+    /// Writes a warning entry.
+    fn log_warn(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(LogLevel::Warn, message, fields)
+    }
+
+    /// Writes an error entry.
+    fn log_error(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(LogLevel::Error, message, fields)
+    }
 }
 
 /// Provides vector search capabilities for semantic similarity search.
@@ -311,6 +417,84 @@ pub trait KeysFeatures: Sized {
     ) -> impl Future<Output = Result<[u8; 33], BoxError>> + Send;
 }
 
+/// Options of [`StoreFeatures::store_list_page`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreListOptions {
+    /// Path prefix to filter results.
+    pub prefix: Option<Path>,
+    /// Maximum number of objects of the page. Default is 100, at most 1000.
+    pub page_size: Option<usize>,
+    /// Continuation token of the previous page, to list the next one.
+    pub page_token: Option<String>,
+    /// Minimum size of the objects in bytes, inclusive.
+    pub min_size: Option<u64>,
+    /// Maximum size of the objects in bytes, inclusive.
+    pub max_size: Option<u64>,
+    /// Only objects modified at or after this time, in unix milliseconds.
+    pub modified_after: Option<u64>,
+    /// Only objects modified before this time, in unix milliseconds.
+    pub modified_before: Option<u64>,
+    /// Only objects with all these user-defined metadata, see
+    /// [`StoreFeatures::store_put_with_metadata`].
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl StoreListOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_prefix(mut self, prefix: Path) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    pub fn with_page_token(mut self, page_token: String) -> Self {
+        self.page_token = Some(page_token);
+        self
+    }
+
+    pub fn with_size(mut self, min_size: Option<u64>, max_size: Option<u64>) -> Self {
+        self.min_size = min_size;
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn with_modified(mut self, after: Option<u64>, before: Option<u64>) -> Self {
+        self.modified_after = after;
+        self.modified_before = before;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: String, value: String) -> Self {
+        self.metadata.insert(key, value);
+        self
+    }
+
+    /// Returns true if the object matches the size and time filters.
+    pub fn matches(&self, meta: &ObjectMeta) -> bool {
+        let modified = meta.last_modified.timestamp_millis().max(0) as u64;
+        self.min_size.is_none_or(|min| meta.size >= min)
+            && self.max_size.is_none_or(|max| meta.size <= max)
+            && self.modified_after.is_none_or(|t| modified >= t)
+            && self.modified_before.is_none_or(|t| modified < t)
+    }
+}
+
+/// A page of objects listed by [`StoreFeatures::store_list_page`].
+#[derive(Debug, Clone, Default)]
+pub struct StoreListPage {
+    /// Objects of the page, ordered by path.
+    pub objects: Vec<ObjectMeta>,
+    /// Continuation token of the next page, `None` on the last page.
+    pub next_page_token: Option<String>,
+}
+
 /// StoreFeatures is one of the context feature sets available when calling Agent or Tool.
 ///
 /// Provides persistent storage capabilities for Agents and Tools to store and manage data.
@@ -322,6 +506,18 @@ pub trait StoreFeatures: Sized {
         path: &Path,
     ) -> impl Future<Output = Result<(bytes::Bytes, ObjectMeta), BoxError>> + Send;
 
+    /// Retrieves a byte range of the object at the specified path, to read a large object
+    /// in chunks. The returned meta has the size of the whole object.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the object;
+    /// * `range` - Byte range to read, its end is clamped to the size of the object.
+    fn store_get_range(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+    ) -> impl Future<Output = Result<(bytes::Bytes, ObjectMeta), BoxError>> + Send;
+
     /// Lists objects in storage with optional prefix and offset filters.
     ///
     /// # Arguments
@@ -333,6 +529,16 @@ pub trait StoreFeatures: Sized {
         offset: &Path,
     ) -> impl Future<Output = Result<Vec<ObjectMeta>, BoxError>> + Send;
 
+    /// Lists a page of objects in storage, ordered by path and filtered by size,
+    /// modification time and user-defined metadata.
+    ///
+    /// # Arguments
+    /// * `options` - Prefix, page size, continuation token and filters of the listing.
+    fn store_list_page(
+        &self,
+        options: &StoreListOptions,
+    ) -> impl Future<Output = Result<StoreListPage, BoxError>> + Send;
+
     /// Stores data at the specified path with a given write mode.
     ///
     /// # Arguments
@@ -346,6 +552,35 @@ pub trait StoreFeatures: Sized {
         value: bytes::Bytes,
     ) -> impl Future<Output = Result<PutResult, BoxError>> + Send;
 
+    /// Stores data at the specified path with user-defined metadata, that
+    /// [`StoreFeatures::store_list_page`] filters on. The metadata are not encrypted.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `mode` - Write mode (Create, Overwrite, etc.);
+    /// * `metadata` - User-defined metadata of the object;
+    /// * `value` - Data to store as bytes.
+    fn store_put_with_metadata(
+        &self,
+        path: &Path,
+        mode: PutMode,
+        metadata: BTreeMap<String, String>,
+        value: bytes::Bytes,
+    ) -> impl Future<Output = Result<PutResult, BoxError>> + Send;
+
+    /// Stores the chunks of a stream at the specified path with a multipart upload, so that
+    /// a large object is never held in memory. The object is overwritten, and only visible
+    /// once the stream ended successfully.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `chunks` - Stream of the chunks of the object.
+    fn store_put_stream(
+        &self,
+        path: &Path,
+        chunks: BoxStream<'static, Result<bytes::Bytes, BoxError>>,
+    ) -> impl Future<Output = Result<PutResult, BoxError>> + Send;
+
     /// Renames a storage object if the target path doesn't exist.
     ///
     /// # Arguments
@@ -446,6 +681,23 @@ pub trait HttpFeatures: Sized {
         body: Option<Vec<u8>>, // default is empty
     ) -> impl Future<Output = Result<reqwest::Response, BoxError>> + Send;
 
+    /// Makes an HTTPs request with per-request options.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`;
+    /// * `method` - HTTP method (GET, POST, etc.);
+    /// * `headers` - Optional HTTP headers;
+    /// * `body` - Optional request body (default empty);
+    /// * `opts` - Timeouts, redirect policy and response size limit, see [`HttpOptions`].
+    fn https_call_with_options(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> impl Future<Output = Result<reqwest::Response, BoxError>> + Send;
+
     /// Makes a signed HTTPs request with message authentication.
     ///
     /// # Arguments
@@ -477,6 +729,17 @@ pub trait HttpFeatures: Sized {
     ) -> impl Future<Output = Result<T, BoxError>> + Send
     where
         T: DeserializeOwned;
+
+    /// Connects to a WebSocket server.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `wss://`;
+    /// * `opts` - Handshake headers, keepalive and buffer options, see [`WsOptions`].
+    fn websocket_connect(
+        &self,
+        url: &str,
+        opts: WsOptions,
+    ) -> impl Future<Output = Result<WebSocket, BoxError>> + Send;
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -617,3 +880,16 @@ pub fn derivation_path_with(path: &Path, derivation_path: Vec<Vec<u8>>) -> Vec<V
     dp.extend(derivation_path);
     dp
 }
+
+/// Runs the future until the token is cancelled, the future is dropped on cancellation,
+/// e.g. aborting an in-flight HTTP request, and an error is returned.
+pub async fn with_cancellation<T>(
+    token: &CancellationToken,
+    fut: impl Future<Output = Result<T, BoxError>>,
+) -> Result<T, BoxError> {
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(Error::Cancelled("operation cancelled".to_string()).into()),
+        res = fut => res,
+    }
+}
@@ -0,0 +1,117 @@
+//! Structured errors of the Anda engine.
+//!
+//! The core traits return a [`BoxError`](crate::BoxError), so that agents and tools may return
+//! any error. The Anda engine boxes an [`Error`] for the failures that callers may handle,
+//! such as a missing tool, an exceeded deadline or an invalid argument, so that they can
+//! branch on its kind instead of matching the error message:
+//!
+//! ```rust,ignore
+//! match ctx.tool_call(input).await {
+//!     Err(err) => match anda_error(&err) {
+//!         Some(Error::NotFound(_)) => { /* try another tool */ }
+//!         Some(e) if e.is_retryable() => { /* retry later */ }
+//!         _ => return Err(err),
+//!     },
+//!     Ok(output) => { /* ... */ }
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::BoxError;
+
+/// A structured error of the Anda engine.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, thiserror::Error)]
+#[serde(rename_all = "snake_case")]
+pub enum Error {
+    /// An agent, tool, tenant or other resource was not found.
+    #[error("{0} not found")]
+    NotFound(String),
+    /// The caller is not allowed to perform the operation.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    /// A rate limit or quota was exceeded.
+    #[error("rate limited: {message}")]
+    RateLimited {
+        message: String,
+        /// When the request may be retried, in milliseconds.
+        retry_after_ms: Option<u64>,
+    },
+    /// The deadline of the request was exceeded.
+    #[error("{0}")]
+    Timeout(String),
+    /// The request was cancelled.
+    #[error("{0}")]
+    Cancelled(String),
+    /// A model provider failed, with the HTTP status of its response if any.
+    #[error("{provider} error: {message}")]
+    Provider {
+        provider: String,
+        status: Option<u16>,
+        message: String,
+    },
+    /// An argument is invalid, such as malformed tool arguments generated by a model.
+    #[error("invalid {0}")]
+    Validation(String),
+    /// A call to a remote engine failed.
+    #[error("remote engine {endpoint} error: {message}")]
+    Remote { endpoint: String, message: String },
+}
+
+impl Error {
+    /// Returns true if the operation may succeed when retried later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimited { .. } => true,
+            Error::Provider { status, .. } => {
+                status.is_none_or(|s| s == 408 || s == 429 || s >= 500)
+            }
+            _ => false,
+        }
+    }
+
+    /// Boxes the error.
+    pub fn boxed(self) -> BoxError {
+        Box::new(self)
+    }
+}
+
+/// Returns the [`Error`] if the error is one.
+pub fn anda_error(err: &BoxError) -> Option<&Error> {
+    err.downcast_ref::<Error>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anda_error() {
+        let err: BoxError = Error::NotFound("tool weather".to_string()).into();
+        assert_eq!(err.to_string(), "tool weather not found");
+        assert_eq!(
+            anda_error(&err),
+            Some(&Error::NotFound("tool weather".to_string()))
+        );
+
+        let err = Error::Provider {
+            provider: "openai".to_string(),
+            status: Some(503),
+            message: "overloaded".to_string(),
+        }
+        .boxed();
+        assert_eq!(err.to_string(), "openai error: overloaded");
+        assert!(anda_error(&err).unwrap().is_retryable());
+        assert!(
+            !Error::Provider {
+                provider: "openai".to_string(),
+                status: Some(400),
+                message: "bad request".to_string(),
+            }
+            .is_retryable()
+        );
+
+        let err: BoxError = "tool weather not found".into();
+        assert!(anda_error(&err).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Typed request-scoped values carried by the contexts.
+//!
+//! [`Extensions`] hold cross-cutting data of a request, such as a request ID, the locale of the
+//! user or an experiment variant, so that tools and hooks can read them instead of parsing
+//! them from prompts. A child context inherits the extensions of its parent, and changes made
+//! to a child's extensions are not visible to its parent. Extensions are not sent to remote
+//! engines.
+//!
+//! ```rust,ignore
+//! #[derive(Clone)]
+//! struct Locale(String);
+//!
+//! ctx.extensions_mut().insert(Locale("fr".to_string()));
+//! let locale = ctx.extensions().get::<Locale>().map_or("en", |l| l.0.as_str());
+//! ```
+
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+    sync::Arc,
+};
+
+/// A typed map of request-scoped values, one value per type.
+///
+/// Cloning is cheap, the values are shared until a clone is modified.
+#[derive(Clone, Default)]
+pub struct Extensions(Arc<BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>>);
+
+impl Extensions {
+    /// Creates empty extensions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value, returning the previous value of the same type if any.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<Arc<T>> {
+        Arc::make_mut(&mut self.0)
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|prev| prev.downcast::<T>().ok())
+    }
+
+    /// Gets the value of the given type.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    /// Returns true if there is a value of the given type.
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Removes the value of the given type, returning it if any.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<Arc<T>> {
+        if !self.contains::<T>() {
+            return None;
+        }
+        Arc::make_mut(&mut self.0)
+            .remove(&TypeId::of::<T>())
+            .and_then(|prev| prev.downcast::<T>().ok())
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there is no value.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct RequestId(u64);
+
+    #[derive(Debug, PartialEq)]
+    struct Locale(&'static str);
+
+    #[test]
+    fn test_extensions() {
+        let mut parent = Extensions::new();
+        assert!(parent.is_empty());
+        assert!(parent.insert(RequestId(1)).is_none());
+        assert!(parent.insert(Locale("en")).is_none());
+        assert_eq!(parent.get::<RequestId>(), Some(&RequestId(1)));
+        assert_eq!(parent.len(), 2);
+
+        let mut child = parent.clone();
+        assert_eq!(child.get::<Locale>(), Some(&Locale("en")));
+        assert_eq!(child.insert(Locale("fr")).as_deref(), Some(&Locale("en")));
+        assert_eq!(child.remove::<RequestId>().as_deref(), Some(&RequestId(1)));
+        assert!(child.remove::<RequestId>().is_none());
+
+        // the parent is unchanged
+        assert_eq!(child.get::<Locale>(), Some(&Locale("fr")));
+        assert_eq!(parent.get::<Locale>(), Some(&Locale("en")));
+        assert_eq!(parent.get::<RequestId>(), Some(&RequestId(1)));
+        assert!(!child.contains::<RequestId>());
+    }
+}
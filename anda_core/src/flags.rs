@@ -0,0 +1,35 @@
+//! Feature flags of a request.
+//!
+//! The engine evaluates its feature flags for the caller when the context of a request is
+//! created, and puts the enabled ones in the [`Extensions`](crate::Extensions) of the context,
+//! so that agents and tools can gate risky new tools, prompts or models:
+//!
+//! ```rust,ignore
+//! let model = match ctx.feature_value("summary_model") {
+//!     Some(Value::String(model)) => model.clone(),
+//!     _ => "gpt-4o-mini".to_string(),
+//! };
+//! if ctx.is_feature_enabled("new_search") {
+//!     // ...
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The feature flags enabled for the caller of a request, with their values.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct FeatureFlags(pub BTreeMap<String, Value>);
+
+impl FeatureFlags {
+    /// Returns true if the flag is enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Gets the value of the flag if it is enabled.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+}
@@ -4,27 +4,35 @@
 //! - Making CBOR-encoded RPC calls;
 //! - Making Candid-encoded canister calls;
 //! - Handling HTTP requests and responses;
+//! - Per-request HTTP options (timeouts, redirects and response size limits);
 //! - Error handling for RPC operations.
 //!
 //! The main types are:
 //! - [`RPCRequest`]: Represents a generic RPC request with CBOR-encoded parameters;
 //! - [`CanisterRequest`]: Represents a canister-specific request with Candid-encoded parameters;
 //! - [`RPCResponse`]: Represents a response from an RPC call;
-//! - [`HttpRPCError`]: Represents possible errors during RPC operations.
+//! - [`HttpRPCError`]: Represents possible errors during RPC operations;
+//! - [`HttpOptions`]: Per-request options for `https_call_with_options`.
 //!
 //! The main functions are:
 //! - [`http_rpc`]: Makes a generic CBOR-encoded RPC call;
 //! - [`canister_rpc`]: Makes a canister-specific RPC call with Candid encoding;
-//! - [`cbor_rpc`]: Internal function for making CBOR-encoded HTTP requests.
+//! - [`cbor_rpc`]: Internal function for making CBOR-encoded HTTP requests;
+//! - [`apply_http_options`]: Applies [`HttpOptions`] to a pending HTTP request;
+//! - [`follow_redirect`]: Prepares the request to follow a redirect response;
+//! - [`http_retry`]: Retries an HTTP request according to a [`RetryPolicy`];
+//! - [`rpc_retry_with_clock`]: Retries a signed RPC call according to a [`RpcRetryPolicy`].
 
 use candid::{CandidType, Principal, decode_args, encode_args, utils::ArgumentEncoder};
 use ciborium::from_reader;
 use http::header;
 use ic_cose_types::to_cbor_bytes;
-use reqwest::Client;
+use reqwest::{Client, ResponseBuilderExt};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_bytes::ByteBuf;
-use std::fmt::Display;
+use std::{collections::BTreeSet, fmt::Display, future::Future, time::Duration};
+
+use crate::{BoxError, CancellationToken, Clock, SystemClock};
 
 pub static CONTENT_TYPE_CBOR: &str = "application/cbor";
 pub static CONTENT_TYPE_JSON: &str = "application/json";
@@ -264,3 +272,831 @@ pub async fn cbor_rpc(
         error: format!("{e:?}"),
     })
 }
+
+/// Redirect policy of an HTTP request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RedirectPolicy {
+    /// Follows up to 10 redirects.
+    #[default]
+    Default,
+    /// Does not follow redirects.
+    None,
+    /// Follows up to the given number of redirects.
+    Limited(usize),
+}
+
+impl RedirectPolicy {
+    /// Converts the policy to a [`reqwest::redirect::Policy`].
+    /// Redirects from `https` to another scheme are rejected with an error.
+    pub fn to_reqwest_policy(&self) -> reqwest::redirect::Policy {
+        if *self == RedirectPolicy::None {
+            return reqwest::redirect::Policy::none();
+        }
+        let max = self.max_redirects();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max {
+                return attempt.error(format!("too many redirects, max {}", max));
+            }
+            match attempt.previous().last() {
+                Some(from) if !redirect_scheme_allowed(from, attempt.url()) => {
+                    let err = format!(
+                        "redirect from {} to {} is not allowed",
+                        from.scheme(),
+                        attempt.url().scheme()
+                    );
+                    attempt.error(err)
+                }
+                _ => attempt.follow(),
+            }
+        })
+    }
+
+    /// Returns the maximum number of redirects to follow.
+    pub fn max_redirects(&self) -> usize {
+        match self {
+            RedirectPolicy::Default => 10,
+            RedirectPolicy::None => 0,
+            RedirectPolicy::Limited(max) => *max,
+        }
+    }
+}
+
+/// Returns true if a redirect from the URL may be followed to the target URL:
+/// `https` only to `https`, and `http` to `http` or `https`.
+pub fn redirect_scheme_allowed(from: &reqwest::Url, to: &reqwest::Url) -> bool {
+    match from.scheme() {
+        "https" => to.scheme() == "https",
+        _ => matches!(to.scheme(), "http" | "https"),
+    }
+}
+
+/// Prepares the request to follow a redirect response, like `reqwest` does:
+/// 303 responses, and 301 or 302 responses to a POST, are followed with a GET without body,
+/// and credentials are not sent to another origin.
+///
+/// Returns the URL to request, `None` if the response is not a redirect
+/// or has no valid `Location` header.
+/// Returns an error if the redirect leaves `https`, or targets a scheme other than `http(s)`.
+pub fn follow_redirect(
+    res: &reqwest::Response,
+    method: &mut http::Method,
+    headers: &mut Option<http::HeaderMap>,
+    body: &mut Option<Vec<u8>>,
+) -> Result<Option<reqwest::Url>, BoxError> {
+    let status = res.status();
+    if !matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return Ok(None);
+    }
+    let url = res
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| res.url().join(v).ok());
+    let url = match url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    if !redirect_scheme_allowed(res.url(), &url) {
+        return Err(format!(
+            "redirect from {} to {} is not allowed",
+            res.url().scheme(),
+            url.scheme()
+        )
+        .into());
+    }
+
+    let to_get = status == http::StatusCode::SEE_OTHER && *method != http::Method::HEAD
+        || matches!(status.as_u16(), 301 | 302) && *method == http::Method::POST;
+    if to_get {
+        *method = http::Method::GET;
+        *body = None;
+    }
+    if let Some(headers) = headers {
+        if to_get {
+            headers.remove(header::CONTENT_TYPE);
+            headers.remove(header::CONTENT_LENGTH);
+            headers.remove(header::CONTENT_ENCODING);
+        }
+        if url.origin() != res.url().origin() {
+            headers.remove(header::AUTHORIZATION);
+            headers.remove(header::COOKIE);
+            headers.remove(header::PROXY_AUTHORIZATION);
+            headers.remove(header::WWW_AUTHENTICATE);
+        }
+    }
+    Ok(Some(url))
+}
+
+/// Normalizes a domain pattern of a domain list, e.g. `*.Example.com.` to `example.com`.
+pub fn normalize_domain(domain: &str) -> Result<String, BoxError> {
+    let domain = domain
+        .trim()
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    if domain.is_empty() || domain.contains('/') || domain.contains(':') {
+        return Err(format!("invalid domain: {:?}", domain).into());
+    }
+    Ok(domain)
+}
+
+/// Returns true if the host is the normalized domain or one of its subdomains.
+pub fn domain_match(domain: &str, host: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+/// Per-request options for HTTP calls, so tools can't be stalled or memory-bombed
+/// by a slow or malicious endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// Timeout for establishing the connection.
+    pub connect_timeout: Option<Duration>,
+    /// Timeout for each read of the response.
+    pub read_timeout: Option<Duration>,
+    /// Total timeout of the request, including reading a limited response body.
+    pub timeout: Option<Duration>,
+    /// Maximum size of the response body in bytes.
+    /// When set, the body is read and buffered before the response is returned.
+    pub max_response_bytes: Option<usize>,
+    /// Redirect policy.
+    pub redirect: RedirectPolicy,
+    /// Opt-in retry policy for transient failures.
+    pub retry: Option<RetryPolicy>,
+}
+
+impl HttpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_max_response_bytes(mut self, max: usize) -> Self {
+        self.max_response_bytes = Some(max);
+        self
+    }
+
+    pub fn with_redirect(mut self, redirect: RedirectPolicy) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Returns true if the options need client-level settings
+    /// (connect timeout, read timeout or redirect policy).
+    pub fn requires_client(&self) -> bool {
+        self.connect_timeout.is_some()
+            || self.read_timeout.is_some()
+            || self.redirect != RedirectPolicy::Default
+    }
+}
+
+/// Applies the total timeout and response size limit of [`HttpOptions`] to a pending HTTP request.
+///
+/// Connect timeout and redirect policy are client-level settings
+/// and must be applied by the HTTP client itself.
+pub async fn apply_http_options<F>(
+    fut: F,
+    opts: &HttpOptions,
+) -> Result<reqwest::Response, BoxError>
+where
+    F: Future<Output = Result<reqwest::Response, BoxError>>,
+{
+    let call = async {
+        let res = fut.await?;
+        match opts.max_response_bytes {
+            Some(max) => buffer_response(res, max, opts.read_timeout).await,
+            None => Ok(res),
+        }
+    };
+
+    match opts.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, call)
+            .await
+            .map_err(|_| format!("HTTP request timed out after {:?}", timeout))?,
+        None => call.await,
+    }
+}
+
+/// Reads the response body up to `max_bytes` and returns a buffered response.
+///
+/// Fails if the body is larger than `max_bytes`, or if a read exceeds `read_timeout`.
+pub async fn buffer_response(
+    mut res: reqwest::Response,
+    max_bytes: usize,
+    read_timeout: Option<Duration>,
+) -> Result<reqwest::Response, BoxError> {
+    if res
+        .content_length()
+        .is_some_and(|len| len > max_bytes as u64)
+    {
+        return Err(format!(
+            "HTTP response from {} is too large, exceeds the limit {} bytes",
+            res.url(),
+            max_bytes
+        )
+        .into());
+    }
+
+    let mut builder = http::Response::builder()
+        .status(res.status())
+        .version(res.version())
+        .url(res.url().clone());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = res.headers().clone();
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    loop {
+        let chunk = match read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, res.chunk())
+                .await
+                .map_err(|_| {
+                    format!(
+                        "HTTP response from {} read timed out after {:?}",
+                        res.url(),
+                        timeout
+                    )
+                })??,
+            None => res.chunk().await?,
+        };
+        match chunk {
+            Some(chunk) => {
+                if body.len() + chunk.len() > max_bytes {
+                    return Err(format!(
+                        "HTTP response from {} is too large, exceeds the limit {} bytes",
+                        res.url(),
+                        max_bytes
+                    )
+                    .into());
+                }
+                body.extend_from_slice(&chunk);
+            }
+            None => break,
+        }
+    }
+
+    Ok(builder.body(body)?.into())
+}
+
+/// Retry policy for transient HTTP failures: connection errors, timeouts,
+/// and 408, 429, 502, 503 or 504 responses.
+///
+/// Only idempotent methods (GET, HEAD, OPTIONS, TRACE, PUT, DELETE) are retried,
+/// unless the caller declares the request idempotent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled on each retry.
+    pub initial_backoff: Duration,
+    /// Maximum backoff, also caps the `Retry-After` delay.
+    pub max_backoff: Duration,
+    /// Declares the request idempotent, so it is retried regardless of its method.
+    pub idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn with_idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    /// Returns true if requests with the method can be retried.
+    pub fn can_retry(&self, method: &http::Method) -> bool {
+        self.idempotent
+            || matches!(
+                *method,
+                http::Method::GET
+                    | http::Method::HEAD
+                    | http::Method::OPTIONS
+                    | http::Method::TRACE
+                    | http::Method::PUT
+                    | http::Method::DELETE
+            )
+    }
+
+    /// Returns the backoff before the retry `attempt` (starting from 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Returns true if the response status is transient.
+pub fn is_retryable_status(status: http::StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 502 | 503 | 504)
+}
+
+/// Returns true if the error is a transient network failure.
+pub fn is_retryable_error(err: &BoxError) -> bool {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+        None => false,
+    }
+}
+
+/// Parses the `Retry-After` header in delay-seconds form.
+pub fn retry_after(headers: &http::HeaderMap) -> Option<Duration> {
+    headers
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Calls `f` and retries transient failures according to the policy,
+/// waiting for the `Retry-After` delay or an exponential backoff between attempts.
+/// Stops waiting and returns an error when the cancellation token is cancelled.
+pub async fn http_retry<F, Fut>(
+    policy: &RetryPolicy,
+    method: &http::Method,
+    cancellation_token: &CancellationToken,
+    f: F,
+) -> Result<reqwest::Response, BoxError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, BoxError>>,
+{
+    http_retry_with_clock(policy, method, cancellation_token, &SystemClock, f).await
+}
+
+/// Like [`http_retry`], waiting between attempts with the clock.
+pub async fn http_retry_with_clock<F, Fut>(
+    policy: &RetryPolicy,
+    method: &http::Method,
+    cancellation_token: &CancellationToken,
+    clock: &dyn Clock,
+    mut f: F,
+) -> Result<reqwest::Response, BoxError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, BoxError>>,
+{
+    let can_retry = policy.can_retry(method);
+    let mut attempt = 0;
+    loop {
+        let res = f().await;
+        if !can_retry || attempt >= policy.max_retries {
+            return res;
+        }
+
+        let delay = match &res {
+            Ok(res) if is_retryable_status(res.status()) => retry_after(res.headers())
+                .map(|d| d.min(policy.max_backoff))
+                .unwrap_or_else(|| policy.backoff(attempt + 1)),
+            Err(err) if is_retryable_error(err) => policy.backoff(attempt + 1),
+            _ => return res,
+        };
+
+        attempt += 1;
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {
+                return Err("HTTP request retry cancelled".into());
+            }
+            _ = clock.sleep(delay) => {}
+        }
+    }
+}
+
+/// Retry policy of the signed RPC calls between engines, see [`rpc_retry_with_clock`].
+///
+/// The idempotent methods are retried on any transient failure: send errors and 408, 429,
+/// 502, 503 or 504 responses. The other methods, such as `agent_run`, are only retried on
+/// 429 and 503 responses, which reject the request before it is processed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcRetryPolicy {
+    /// Maximum number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Backoff before the first retry in milliseconds, doubled on each retry.
+    pub initial_backoff_ms: u64,
+    /// Maximum backoff in milliseconds.
+    pub max_backoff_ms: u64,
+    /// The RPC methods without side effects, "information" by default.
+    pub idempotent_methods: BTreeSet<String>,
+}
+
+impl Default for RpcRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 5000,
+            idempotent_methods: BTreeSet::from(["information".to_string()]),
+        }
+    }
+}
+
+impl RpcRetryPolicy {
+    /// Returns true if the failed call of the method may be retried.
+    pub fn can_retry(&self, method: &str, err: &BoxError) -> bool {
+        let idempotent = self.idempotent_methods.contains(method);
+        match err.downcast_ref::<HttpRPCError>() {
+            Some(HttpRPCError::RequestError { .. }) => idempotent,
+            Some(HttpRPCError::ResponseError { status, .. }) => {
+                matches!(status, 429 | 503)
+                    || (idempotent
+                        && http::StatusCode::from_u16(*status).is_ok_and(is_retryable_status))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the backoff before the retry `attempt` (starting from 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+/// Calls `f` and retries the transient failures of the RPC method according to the policy,
+/// waiting with the clock for an exponential backoff between attempts.
+/// Stops waiting and returns an error when the cancellation token is cancelled.
+pub async fn rpc_retry_with_clock<T, F, Fut>(
+    policy: &RpcRetryPolicy,
+    method: &str,
+    cancellation_token: &CancellationToken,
+    clock: &dyn Clock,
+    mut f: F,
+) -> Result<T, BoxError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, BoxError>>,
+{
+    let mut attempt = 0;
+    loop {
+        // the failed result is dropped before the backoff, so that the future doesn't
+        // hold a `T` across the await and is `Send` for any `T`.
+        match f().await {
+            Err(err) if attempt < policy.max_retries && policy.can_retry(method, &err) => {}
+            res => return res,
+        }
+
+        attempt += 1;
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {
+                return Err("RPC call retry cancelled".into());
+            }
+            _ = clock.sleep(policy.backoff(attempt)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_apply_http_options() {
+        let response = |body: &str| -> reqwest::Response {
+            http::Response::builder()
+                .status(200)
+                .header("x-test", "1")
+                .body(body.to_string())
+                .unwrap()
+                .into()
+        };
+
+        let opts = HttpOptions::new().with_max_response_bytes(5);
+        assert!(!opts.requires_client());
+        let res = apply_http_options(async { Ok(response("hello")) }, &opts)
+            .await
+            .unwrap();
+        assert_eq!(res.headers().get("x-test").unwrap(), "1");
+        assert_eq!(res.text().await.unwrap(), "hello");
+
+        let err = apply_http_options(async { Ok(response("hello world")) }, &opts)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too large"));
+
+        let opts = HttpOptions::new().with_timeout(Duration::from_millis(10));
+        let err = apply_http_options(
+            async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(response("hello"))
+            },
+            &opts,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        let opts = HttpOptions::new().with_redirect(RedirectPolicy::None);
+        assert!(opts.requires_client());
+    }
+
+    #[test]
+    fn test_follow_redirect() {
+        let response = |status: u16, location: Option<&str>| -> reqwest::Response {
+            let mut res = http::Response::builder()
+                .status(status)
+                .url("https://example.com/a/b".parse().unwrap());
+            if let Some(v) = location {
+                res = res.header(header::LOCATION, v);
+            }
+            res.body("").unwrap().into()
+        };
+        let request = || {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, "Bearer token".parse().unwrap());
+            headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+            (http::Method::POST, Some(headers), Some(b"{}".to_vec()))
+        };
+
+        let (mut method, mut headers, mut body) = request();
+        assert!(
+            follow_redirect(&response(200, None), &mut method, &mut headers, &mut body)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            follow_redirect(&response(302, None), &mut method, &mut headers, &mut body)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            follow_redirect(
+                &response(302, Some("file:///etc/passwd")),
+                &mut method,
+                &mut headers,
+                &mut body
+            )
+            .is_err()
+        );
+        assert!(
+            follow_redirect(
+                &response(302, Some("http://example.com/a/c")),
+                &mut method,
+                &mut headers,
+                &mut body
+            )
+            .is_err()
+        );
+        assert_eq!(method, http::Method::POST);
+
+        let url = follow_redirect(
+            &response(307, Some("c")),
+            &mut method,
+            &mut headers,
+            &mut body,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(url.as_str(), "https://example.com/a/c");
+        assert_eq!(method, http::Method::POST);
+        assert!(body.is_some());
+        let h = headers.as_ref().unwrap();
+        assert!(h.contains_key(header::AUTHORIZATION));
+        assert!(h.contains_key(header::CONTENT_TYPE));
+
+        let (mut method, mut headers, mut body) = request();
+        let url = follow_redirect(
+            &response(302, Some("https://other.com/x")),
+            &mut method,
+            &mut headers,
+            &mut body,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(url.as_str(), "https://other.com/x");
+        assert_eq!(method, http::Method::GET);
+        assert!(body.is_none());
+        let h = headers.as_ref().unwrap();
+        assert!(!h.contains_key(header::AUTHORIZATION));
+        assert!(!h.contains_key(header::CONTENT_TYPE));
+
+        let url = |v: &str| reqwest::Url::parse(v).unwrap();
+        assert!(redirect_scheme_allowed(
+            &url("https://a.com"),
+            &url("https://b.com")
+        ));
+        assert!(redirect_scheme_allowed(
+            &url("http://a.com"),
+            &url("https://a.com")
+        ));
+        assert!(redirect_scheme_allowed(
+            &url("http://a.com"),
+            &url("http://b.com")
+        ));
+        assert!(!redirect_scheme_allowed(
+            &url("https://a.com"),
+            &url("http://a.com")
+        ));
+        assert!(!redirect_scheme_allowed(
+            &url("http://a.com"),
+            &url("ftp://a.com")
+        ));
+
+        assert_eq!(RedirectPolicy::Default.max_redirects(), 10);
+        assert_eq!(RedirectPolicy::None.max_redirects(), 0);
+        assert_eq!(RedirectPolicy::Limited(3).max_redirects(), 3);
+    }
+
+    #[test]
+    fn test_domain_match() {
+        assert_eq!(normalize_domain(" *.Example.COM. ").unwrap(), "example.com");
+        assert!(normalize_domain("").is_err());
+        assert!(normalize_domain("example.com/path").is_err());
+        assert!(normalize_domain("example.com:443").is_err());
+
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("example.com", "api.example.com"));
+        assert!(!domain_match("example.com", "badexample.com"));
+        assert!(!domain_match("example.com", "example.com.evil.org"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_http_retry() {
+        let response = |status: u16, retry_after: Option<&str>| -> reqwest::Response {
+            let mut res = http::Response::builder().status(status);
+            if let Some(v) = retry_after {
+                res = res.header(header::RETRY_AFTER, v);
+            }
+            res.body("").unwrap().into()
+        };
+
+        let policy =
+            RetryPolicy::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(5));
+        assert_eq!(policy.backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.backoff(2), Duration::from_millis(2));
+        assert_eq!(policy.backoff(10), Duration::from_millis(5));
+        assert!(policy.can_retry(&http::Method::GET));
+        assert!(!policy.can_retry(&http::Method::POST));
+        assert!(
+            policy
+                .clone()
+                .with_idempotent(true)
+                .can_retry(&http::Method::POST)
+        );
+
+        let token = CancellationToken::new();
+        let mut calls = 0;
+        let res = http_retry(&policy, &http::Method::GET, &token, || {
+            calls += 1;
+            let status = if calls < 3 { 503 } else { 200 };
+            async move { Ok(response(status, Some("0"))) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let res = http_retry(&policy, &http::Method::POST, &token, || {
+            calls += 1;
+            async move { Ok(response(503, None)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let res = http_retry(&policy, &http::Method::GET, &token, || {
+            calls += 1;
+            async move { Ok(response(429, None)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 429);
+        assert_eq!(calls, 3);
+
+        // waits on the mock clock
+        let clock = MockClock::new(0);
+        let long_policy =
+            RetryPolicy::new(3).with_backoff(Duration::from_secs(1), Duration::from_secs(60));
+        let res =
+            http_retry_with_clock(&long_policy, &http::Method::GET, &token, &clock, || async {
+                Ok(response(503, None))
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(clock.now_ms(), 7_000);
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert(header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        token.cancel();
+        let policy =
+            RetryPolicy::new(2).with_backoff(Duration::from_secs(10), Duration::from_secs(10));
+        let res = http_retry(&policy, &http::Method::GET, &token, || async {
+            Ok(response(503, None))
+        })
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_rpc_retry() {
+        let err = |status: Option<u16>| -> BoxError {
+            match status {
+                Some(status) => HttpRPCError::ResponseError {
+                    endpoint: "https://remote.example.com".to_string(),
+                    path: "agent_run".to_string(),
+                    status,
+                    error: "".to_string(),
+                },
+                None => HttpRPCError::RequestError {
+                    endpoint: "https://remote.example.com".to_string(),
+                    path: "agent_run".to_string(),
+                    error: "connection refused".to_string(),
+                },
+            }
+            .into()
+        };
+
+        let policy = RpcRetryPolicy::default();
+        assert!(policy.can_retry("information", &err(None)));
+        assert!(policy.can_retry("information", &err(Some(502))));
+        assert!(!policy.can_retry("agent_run", &err(None)));
+        assert!(!policy.can_retry("agent_run", &err(Some(502))));
+        assert!(policy.can_retry("agent_run", &err(Some(503))));
+        assert!(!policy.can_retry("information", &err(Some(400))));
+        assert!(!policy.can_retry("information", &"invalid response".into()));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_millis(5000));
+
+        let token = CancellationToken::new();
+        let clock = MockClock::new(0);
+        let mut calls = 0;
+        let res: u32 = rpc_retry_with_clock(&policy, "information", &token, &clock, || {
+            calls += 1;
+            let res = if calls < 3 { Err(err(None)) } else { Ok(calls) };
+            async move { res }
+        })
+        .await
+        .unwrap();
+        assert_eq!(res, 3);
+        assert_eq!(clock.now_ms(), 600);
+
+        let mut calls = 0;
+        let res: Result<u32, BoxError> =
+            rpc_retry_with_clock(&policy, "agent_run", &token, &clock, || {
+                calls += 1;
+                async { Err(err(None)) }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+}
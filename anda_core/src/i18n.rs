@@ -0,0 +1,141 @@
+//! Locale-aware prompts and output.
+//!
+//! The locale of the caller is a BCP 47 language tag, such as `fr` or `zh-Hant-TW`, given by
+//! [`RequestMeta::locale`](crate::RequestMeta::locale) and read by agents and tools with
+//! [`StateFeatures::locale`](crate::StateFeatures::locale). Agents select their localized system
+//! prompts and templates with [`Localized`], and ask the model to respond in the caller's
+//! language with [`CompletionRequest::with_locale`](crate::CompletionRequest::with_locale):
+//!
+//! ```rust,ignore
+//! let system = Localized::new("You are a helpful assistant.".to_string())
+//!     .with("fr", "Vous êtes un assistant serviable.".to_string());
+//! let req = CompletionRequest {
+//!     system: Some(system.get(ctx.locale()).clone()),
+//!     prompt,
+//!     ..Default::default()
+//! }
+//! .with_locale(ctx.locale());
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A value, such as a prompt or a template, with its translations by locale.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Localized<T> {
+    /// The value for the locales without translation.
+    pub default: T,
+
+    /// The translations by normalized locale, see [`normalize_locale`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub locales: BTreeMap<String, T>,
+}
+
+impl<T> Localized<T> {
+    /// Creates a value without translation.
+    pub fn new(default: T) -> Self {
+        Self {
+            default,
+            locales: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the translation for the locale.
+    pub fn with(mut self, locale: &str, value: T) -> Self {
+        self.insert(locale, value);
+        self
+    }
+
+    /// Inserts the translation for the locale, returning the previous one if any.
+    pub fn insert(&mut self, locale: &str, value: T) -> Option<T> {
+        self.locales.insert(normalize_locale(locale), value)
+    }
+
+    /// Gets the translation for the locale, falling back to its parent locales,
+    /// e.g. `zh-Hant-TW`, `zh-Hant` then `zh`, and finally to the default value.
+    pub fn get(&self, locale: Option<&str>) -> &T {
+        let Some(locale) = locale else {
+            return &self.default;
+        };
+
+        let mut locale = normalize_locale(locale);
+        loop {
+            if let Some(value) = self.locales.get(&locale) {
+                return value;
+            }
+            match locale.rfind('-') {
+                Some(i) => locale.truncate(i),
+                None => return &self.default,
+            }
+        }
+    }
+}
+
+/// Normalizes a locale to a lowercase language tag with `-` separators, e.g. `zh_Hant_TW`
+/// to `zh-hant-tw`.
+pub fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+/// Returns the preferred locale of an `Accept-Language` HTTP header, if any.
+pub fn preferred_locale(accept_language: &str) -> Option<String> {
+    let mut preferred: Option<(&str, f32)> = None;
+    for item in accept_language.split(',') {
+        let mut parts = item.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        if tag.is_empty() || tag == "*" {
+            continue;
+        }
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if q > 0.0 && preferred.is_none_or(|(_, pq)| q > pq) {
+            preferred = Some((tag, q));
+        }
+    }
+    preferred.map(|(tag, _)| tag.to_string())
+}
+
+/// Returns the instruction asking the model to respond in the language of the locale.
+pub fn language_instruction(locale: &str) -> String {
+    format!(
+        "Respond in the language of the user's locale \"{}\", unless the user asks for another language.",
+        locale.trim()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_localized() {
+        let greeting = Localized::new("Hello")
+            .with("fr", "Bonjour")
+            .with("zh_Hant", "你好")
+            .with("zh-CN", "您好");
+        assert_eq!(*greeting.get(None), "Hello");
+        assert_eq!(*greeting.get(Some("en-US")), "Hello");
+        assert_eq!(*greeting.get(Some("fr-CA")), "Bonjour");
+        assert_eq!(*greeting.get(Some("zh-Hant-TW")), "你好");
+        assert_eq!(*greeting.get(Some("zh_CN")), "您好");
+        assert_eq!(*greeting.get(Some("zh")), "Hello");
+
+        let json = serde_json::to_string(&greeting).unwrap();
+        let greeting2: Localized<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(greeting2.get(Some("FR")), "Bonjour");
+    }
+
+    #[test]
+    fn test_preferred_locale() {
+        assert_eq!(
+            preferred_locale("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5").unwrap(),
+            "fr-CH"
+        );
+        assert_eq!(preferred_locale("en;q=0.5, de;q=0.7").unwrap(), "de");
+        assert_eq!(preferred_locale("*"), None);
+        assert_eq!(preferred_locale("ja;q=0"), None);
+        assert_eq!(preferred_locale(""), None);
+    }
+}
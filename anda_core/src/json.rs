@@ -3,6 +3,53 @@ use schemars::{
     r#gen::SchemaSettings,
     schema::{RootSchema, Schema, SchemaObject, SingleOrVec},
 };
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::{BoxError, Error};
+
+pub use anda_macros::ToolArgs;
+
+/// Arguments of a tool, with the JSON schema of its function definition and a validation.
+///
+/// Derive it with `#[derive(ToolArgs)]` alongside `Deserialize` and `JsonSchema`. The doc
+/// comments of the fields become their descriptions, enums are described by their variants,
+/// and the `#[validate(...)]` attributes of the fields, `range(min = .., max = ..)`,
+/// `length(min = .., max = .., equal = ..)` and `required`, are in the schema and checked
+/// by [`ToolArgs::validate`]. Tools check them by returning `args.validate()` in
+/// [`Tool::validate_args`](crate::Tool::validate_args).
+///
+/// ```rust,ignore
+/// #[derive(Debug, Deserialize, JsonSchema, ToolArgs)]
+/// struct SearchArgs {
+///     /// The search query.
+///     #[validate(length(min = 1, max = 256))]
+///     query: String,
+///     /// The number of results.
+///     #[validate(range(min = 1, max = 20))]
+///     limit: u32,
+/// }
+/// ```
+pub trait ToolArgs: JsonSchema + DeserializeOwned {
+    /// Returns the JSON schema of the arguments, for [`FunctionDefinition::parameters`](crate::FunctionDefinition::parameters).
+    fn parameters() -> serde_json::Value {
+        gen_schema_for::<Self>()
+    }
+
+    /// Validates the deserialized arguments.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Deserializes and validates the arguments.
+    fn from_value(value: serde_json::Value) -> Result<Self, BoxError> {
+        let args: Self = serde_json::from_value(value)
+            .map_err(|err| Error::Validation(format!("args: {}", err)))?;
+        args.validate()
+            .map_err(|err| Error::Validation(format!("args: {}", err)))?;
+        Ok(args)
+    }
+}
 
 /// Generate JSON schema for a given type T.
 pub fn root_schema_for<T: JsonSchema>() -> RootSchema {
@@ -44,6 +91,19 @@ fn fix_obj_schema(schema: &mut SchemaObject) {
             }
         }
     }
+    if let Some(sub) = &mut schema.subschemas {
+        // the variants of enums, and optional values
+        for schemas in [&mut sub.all_of, &mut sub.any_of, &mut sub.one_of]
+            .into_iter()
+            .flatten()
+        {
+            for v in schemas {
+                if let Schema::Object(o) = v {
+                    fix_obj_schema(o);
+                }
+            }
+        }
+    }
     if let Some(arr) = &mut schema.array {
         if let Some(v) = &mut arr.items {
             match v {
@@ -63,3 +123,251 @@ fn fix_obj_schema(schema: &mut SchemaObject) {
         }
     }
 }
+
+/// A value with a length, checked by `#[validate(length(...))]`.
+pub trait ValidateLength {
+    /// Returns the length, or None if there is no value.
+    fn validate_len(&self) -> Option<usize>;
+}
+
+impl ValidateLength for str {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.chars().count())
+    }
+}
+
+impl ValidateLength for String {
+    fn validate_len(&self) -> Option<usize> {
+        self.as_str().validate_len()
+    }
+}
+
+impl<T> ValidateLength for [T] {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T> ValidateLength for Vec<T> {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<K, V> ValidateLength for BTreeMap<K, V> {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<K, V, S> ValidateLength for HashMap<K, V, S> {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T> ValidateLength for BTreeSet<T> {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T, S> ValidateLength for HashSet<T, S> {
+    fn validate_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T: ValidateLength> ValidateLength for Option<T> {
+    fn validate_len(&self) -> Option<usize> {
+        self.as_ref().and_then(|v| v.validate_len())
+    }
+}
+
+/// A number, checked by `#[validate(range(...))]`.
+pub trait ValidateRange {
+    /// Returns the number, or None if there is no value.
+    fn validate_num(&self) -> Option<f64>;
+}
+
+macro_rules! impl_validate_range {
+    ($($t:ty),*) => {
+        $(impl ValidateRange for $t {
+            fn validate_num(&self) -> Option<f64> {
+                Some(*self as f64)
+            }
+        })*
+    };
+}
+
+impl_validate_range!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+impl<T: ValidateRange> ValidateRange for Option<T> {
+    fn validate_num(&self) -> Option<f64> {
+        self.as_ref().and_then(|v| v.validate_num())
+    }
+}
+
+/// Checks the length of a field, used by `#[derive(ToolArgs)]`.
+pub fn validate_length<T: ValidateLength + ?Sized>(
+    value: &T,
+    field: &str,
+    min: Option<usize>,
+    max: Option<usize>,
+) -> Result<(), String> {
+    if let Some(len) = value.validate_len() {
+        if let Some(min) = min.filter(|&min| len < min) {
+            return Err(format!(
+                "length of `{}` must be at least {}, got {}",
+                field, min, len
+            ));
+        }
+        if let Some(max) = max.filter(|&max| len > max) {
+            return Err(format!(
+                "length of `{}` must be at most {}, got {}",
+                field, max, len
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the range of a number field, used by `#[derive(ToolArgs)]`.
+pub fn validate_range<T: ValidateRange + ?Sized>(
+    value: &T,
+    field: &str,
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Result<(), String> {
+    if let Some(num) = value.validate_num() {
+        if let Some(min) = min.filter(|&min| num < min) {
+            return Err(format!("`{}` must be at least {}, got {}", field, min, num));
+        }
+        if let Some(max) = max.filter(|&max| num > max) {
+            return Err(format!("`{}` must be at most {}, got {}", field, max, num));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    /// The level of detail.
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Detail {
+        Brief,
+        Full,
+    }
+
+    #[derive(Debug, Deserialize, JsonSchema, ToolArgs)]
+    struct SearchArgs {
+        /// The search query.
+        #[validate(length(min = 1, max = 8))]
+        query: String,
+        /// The number of results.
+        #[validate(range(min = 1, max = 20))]
+        limit: u32,
+        /// The level of detail of the results.
+        detail: Detail,
+        /// The tags to match.
+        #[validate(length(max = 2))]
+        tags: Option<Vec<String>>,
+        /// The minimum score of the results.
+        #[validate(range(min = 0.0, max = 1.0))]
+        min_score: Option<f64>,
+    }
+
+    fn args(value: serde_json::Value) -> SearchArgs {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_tool_args_schema() {
+        let schema = SearchArgs::parameters();
+        let props = &schema["properties"];
+        assert_eq!(props["query"]["description"], "The search query.");
+        assert_eq!(props["limit"]["description"], "The number of results.");
+        assert_eq!(props["query"]["minLength"], 1);
+        assert_eq!(props["query"]["maxLength"], 8);
+        assert_eq!(props["limit"]["minimum"], 1.0);
+        assert_eq!(props["limit"]["maximum"], 20.0);
+        assert_eq!(
+            props["detail"]["description"],
+            "The level of detail of the results."
+        );
+        assert!(props["detail"].to_string().contains(r#"["brief","full"]"#));
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["required"].as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_tool_args_validate() {
+        let valid = args(json!({"query": "anda", "limit": 5, "detail": "full"}));
+        assert_eq!(valid.detail, Detail::Full);
+        valid.validate().unwrap();
+        args(json!({
+            "query": "a", "limit": 20, "detail": "brief",
+            "tags": ["x", "y"], "min_score": 0.5,
+        }))
+        .validate()
+        .unwrap();
+
+        let err = args(json!({"query": "", "limit": 5, "detail": "full"}))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "length of `query` must be at least 1, got 0");
+        let err = args(json!({"query": "too long query", "limit": 5, "detail": "full"}))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "length of `query` must be at most 8, got 14");
+        let err = args(json!({"query": "anda", "limit": 0, "detail": "full"}))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "`limit` must be at least 1, got 0");
+        let err = args(json!({"query": "anda", "limit": 21, "detail": "full"}))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "`limit` must be at most 20, got 21");
+
+        // the optional fields are checked only if set
+        let err = args(json!({
+            "query": "anda", "limit": 5, "detail": "full", "tags": ["x", "y", "z"],
+        }))
+        .validate()
+        .unwrap_err();
+        assert_eq!(err, "length of `tags` must be at most 2, got 3");
+        let err = args(json!({
+            "query": "anda", "limit": 5, "detail": "full", "min_score": 1.5,
+        }))
+        .validate()
+        .unwrap_err();
+        assert_eq!(err, "`min_score` must be at most 1, got 1.5");
+    }
+
+    #[test]
+    fn test_tool_args_from_value() {
+        let args = SearchArgs::from_value(json!({"query": "anda", "limit": 5, "detail": "brief"}))
+            .unwrap();
+        assert_eq!(args.limit, 5);
+
+        let err = SearchArgs::from_value(json!({"query": "anda", "limit": 50, "detail": "brief"}))
+            .unwrap_err();
+        assert!(matches!(
+            crate::anda_error(&err),
+            Some(Error::Validation(msg)) if msg == "args: `limit` must be at most 20, got 50"
+        ));
+        let err = SearchArgs::from_value(json!({"query": "anda", "limit": 5, "detail": "all"}))
+            .unwrap_err();
+        assert!(matches!(
+            crate::anda_error(&err),
+            Some(Error::Validation(_))
+        ));
+    }
+}
@@ -1,19 +1,42 @@
 use object_store::path::DELIMITER;
 use std::{future::Future, pin::Pin};
 
+// lets the derive macros refer to `::anda_core` within the crate
+extern crate self as anda_core;
+
 pub mod agent;
+pub mod canister;
+pub mod clock;
 pub mod context;
+pub mod error;
+pub mod extensions;
+pub mod flags;
 pub mod http;
+pub mod i18n;
 pub mod json;
 pub mod model;
+pub mod multipart;
+pub mod oauth2;
+pub mod sse;
 pub mod tool;
+pub mod websocket;
 
 pub use agent::*;
+pub use canister::*;
+pub use clock::*;
 pub use context::*;
+pub use error::*;
+pub use extensions::*;
+pub use flags::*;
 pub use http::*;
+pub use i18n::*;
 pub use json::*;
 pub use model::*;
+pub use multipart::*;
+pub use oauth2::*;
+pub use sse::*;
 pub use tool::*;
+pub use websocket::*;
 
 /// A type alias for a boxed error that is thread-safe and sendable across threads.
 /// This is commonly used as a return type for functions that can return various error types.
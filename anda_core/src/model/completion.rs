@@ -1,8 +1,15 @@
-use serde::{Deserialize, Serialize};
+use futures::{FutureExt, Stream};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
 use std::{collections::BTreeMap, convert::Infallible, str::FromStr};
 
-use super::{AgentOutput, FunctionDefinition, Knowledge, Resource, Value};
-use crate::BoxError;
+use super::{AgentEvent, AgentOutput, FunctionDefinition, Knowledge, Resource, Usage, Value};
+use crate::{BoxError, Error, language_instruction, root_schema_for};
+
+/// The maximum number of retries of [`CompletionFeatures::completion_structured`]
+/// when the model's output doesn't match the schema.
+pub const MAX_STRUCTURED_RETRIES: usize = 2;
 
 /// Provides LLM completion capabilities for agents.
 pub trait CompletionFeatures: Sized {
@@ -12,11 +19,125 @@ pub trait CompletionFeatures: Sized {
         req: CompletionRequest,
         resources: Option<Vec<Resource>>,
     ) -> impl Future<Output = Result<AgentOutput, BoxError>> + Send;
+
+    /// Generates a completion like [`CompletionFeatures::completion`], streaming its progress:
+    /// the [`AgentEvent::Delta`] of the content as the model generates it, the tool calls,
+    /// and finally an [`AgentEvent::Output`] or an [`AgentEvent::Error`] event.
+    ///
+    /// The default implementation only yields the final event.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> impl Stream<Item = AgentEvent> + Send {
+        futures::stream::once(self.completion(req, resources).map(|res| match res {
+            Ok(output) => AgentEvent::Output(output),
+            Err(err) => AgentEvent::Error {
+                error: err.to_string(),
+            },
+        }))
+    }
+
+    /// Generates a completion whose content is a JSON value of type `T`.
+    ///
+    /// The JSON schema of `T` is sent as the `json_schema` response format, and in the system
+    /// message for the models that only support JSON objects. When the content doesn't parse,
+    /// the model is asked to repair it, up to [`MAX_STRUCTURED_RETRIES`] times.
+    /// Returns the value with the output of the last completion, the usage of all of them.
+    fn completion_structured<T>(
+        &self,
+        mut req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> impl Future<Output = Result<(T, AgentOutput), BoxError>> + Send
+    where
+        Self: Sync,
+        T: JsonSchema + DeserializeOwned + Send,
+    {
+        async move {
+            let schema = root_schema_for::<T>();
+            let name = schema
+                .schema
+                .metadata
+                .as_ref()
+                .and_then(|m| m.title.clone())
+                .unwrap_or_else(|| "output".to_string());
+            let schema = json!(schema);
+            let instruction = format!(
+                "Respond with only a JSON value that conforms to this JSON schema:\n{}",
+                schema
+            );
+            req.system = Some(match req.system {
+                Some(system) if !system.is_empty() => format!("{}\n\n{}", system, instruction),
+                _ => instruction,
+            });
+            req.response_format = Some(json!({
+                "type": "json_schema",
+                "json_schema": {"name": name, "schema": schema, "strict": true},
+            }));
+
+            let mut usage = Usage::default();
+            let mut retries = 0;
+            loop {
+                let mut output = self.completion(req.clone(), resources.clone()).await?;
+                usage.accumulate(&output.usage);
+                if let Some(reason) = &output.failed_reason {
+                    return Err(format!("completion failed: {}", reason).into());
+                }
+
+                match parse_json_content::<T>(&output.content) {
+                    Ok(value) => {
+                        output.usage = usage;
+                        return Ok((value, output));
+                    }
+                    Err(err) if retries < MAX_STRUCTURED_RETRIES => {
+                        retries += 1;
+                        // the model repairs its output in the next round
+                        req.system = None;
+                        req.documents.clear();
+                        req.content_parts.clear();
+                        req.chat_history = output.full_history.take().unwrap_or_default();
+                        req.prompt = format!(
+                            "Your response is not a valid JSON value of the schema: {}. \
+                             Respond again with only the corrected JSON value.",
+                            err
+                        );
+                    }
+                    Err(err) => {
+                        return Err(Error::Validation(format!("structured output: {}", err)).into());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses the content of a completion as JSON, without the Markdown code fence
+/// that some models wrap it in.
+fn parse_json_content<T: DeserializeOwned>(content: &str) -> Result<T, serde_json::Error> {
+    let content = content.trim();
+    let content = match content.strip_prefix("```") {
+        Some(fenced) => fenced
+            .trim_start_matches("json")
+            .trim_end()
+            .trim_end_matches("```")
+            .trim(),
+        None => content,
+    };
+    serde_json::from_str(content)
 }
 
+/// The default maximum number of tool call rounds of a completion, see
+/// [`CompletionRequest::max_tool_rounds`].
+pub const DEFAULT_MAX_TOOL_ROUNDS: usize = 10;
+
 /// Represents a general completion request that can be sent to a completion model provider.
 #[derive(Debug, Clone, Default)]
 pub struct CompletionRequest {
+    /// The name of a model registered with the engine to complete the request,
+    /// e.g. a cheap model for routing and an expensive one for final answers.
+    /// The default model of the engine if not set.
+    pub model: Option<String>,
+
     /// The system message to be sent to the completion model provider, as the "system" role.
     pub system: Option<String>,
 
@@ -46,6 +167,15 @@ pub struct CompletionRequest {
     /// Whether the tool choice is required.
     pub tool_choice_required: bool,
 
+    /// Whether the tool calls of a completion round are executed concurrently
+    /// instead of one by one. Their results are still added to the chat history in order.
+    pub parallel_tool_calls: bool,
+
+    /// The maximum number of completion rounds with tool calls,
+    /// [`DEFAULT_MAX_TOOL_ROUNDS`] if not set.
+    /// The completion fails when the model keeps calling tools beyond it.
+    pub max_tool_rounds: Option<usize>,
+
     /// The temperature to be sent to the completion model provider.
     pub temperature: Option<f64>,
 
@@ -86,6 +216,19 @@ impl CompletionRequest {
         self
     }
 
+    /// Asks the model to respond in the language of the locale, if any,
+    /// by appending the [`language_instruction`] to the system message.
+    pub fn with_locale(mut self, locale: Option<&str>) -> Self {
+        if let Some(locale) = locale.filter(|l| !l.trim().is_empty()) {
+            let instruction = language_instruction(locale);
+            self.system = Some(match self.system {
+                Some(system) if !system.is_empty() => format!("{}\n\n{}", system, instruction),
+                _ => instruction,
+            });
+        }
+        self
+    }
+
     /// Returns the prompt with context if available.
     pub fn prompt_with_context(&self) -> Option<String> {
         if self.documents.0.is_empty() && self.prompt.is_empty() {
@@ -229,6 +372,57 @@ impl std::fmt::Display for Documents {
     }
 }
 
+/// A source of an agent answer, from a document provided to the completion.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Citation {
+    /// The ID of the source document, the `doc_id` metadata of a chunk if present.
+    pub doc_id: String,
+
+    /// The ID of the provided document, the chunk ID for retrieved chunks.
+    pub chunk_id: String,
+
+    /// The byte range of the chunk in the source document, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<(usize, usize)>,
+
+    /// The retrieval or rerank score of the chunk, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+
+    /// The source of the document, such as a URI, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl Document {
+    /// Gets a metadata value as a string, JSON string values are unquoted.
+    pub fn meta_str(&self, key: &str) -> Option<String> {
+        self.metadata
+            .get(key)
+            .map(|v| serde_json::from_str::<String>(v).unwrap_or_else(|_| v.clone()))
+    }
+}
+
+impl From<&Document> for Citation {
+    fn from(doc: &Document) -> Self {
+        let offset = doc.meta_str("offset").and_then(|v| v.parse::<usize>().ok());
+        Citation {
+            doc_id: doc.meta_str("doc_id").unwrap_or_else(|| doc.id.clone()),
+            chunk_id: doc.id.clone(),
+            range: offset.map(|start| (start, start + doc.text.len())),
+            score: doc.meta_str("score").and_then(|v| v.parse::<f32>().ok()),
+            source: doc.meta_str("source"),
+        }
+    }
+}
+
+impl Documents {
+    /// Returns the citations of the documents, in order.
+    pub fn citations(&self) -> Vec<Citation> {
+        self.0.iter().map(Citation::from).collect()
+    }
+}
+
 /// OpenAI style content part for the completion request.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
@@ -282,6 +476,110 @@ impl FromStr for ContentPart {
 mod tests {
     use super::*;
     use serde_json::{json, to_string};
+    use std::sync::Mutex;
+
+    /// Responds with the given contents in order, recording the prompts.
+    struct MockCompleter {
+        contents: Mutex<Vec<&'static str>>,
+        prompts: Mutex<Vec<String>>,
+    }
+
+    impl CompletionFeatures for MockCompleter {
+        async fn completion(
+            &self,
+            req: CompletionRequest,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            self.prompts.lock().unwrap().push(req.prompt);
+            Ok(AgentOutput {
+                content: self.contents.lock().unwrap().remove(0).to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Contact {
+        name: String,
+        phone: String,
+    }
+
+    #[tokio::test]
+    async fn test_completion_structured() {
+        let completer = MockCompleter {
+            contents: Mutex::new(vec![
+                "John Doe, 123",
+                "```json\n{\"name\": \"John Doe\", \"phone\": \"123\"}\n```",
+            ]),
+            prompts: Mutex::new(Vec::new()),
+        };
+        let req = CompletionRequest {
+            prompt: "John Doe, phone: 123".to_string(),
+            ..Default::default()
+        };
+        let (contact, _) = completer
+            .completion_structured::<Contact>(req.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            contact,
+            Contact {
+                name: "John Doe".to_string(),
+                phone: "123".to_string(),
+            }
+        );
+        let prompts = completer.prompts.lock().unwrap().clone();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].starts_with("Your response is not a valid JSON value"));
+
+        let completer = MockCompleter {
+            contents: Mutex::new(vec!["a", "b", "c"]),
+            prompts: Mutex::new(Vec::new()),
+        };
+        let res = completer.completion_structured::<Contact>(req, None).await;
+        assert!(res.is_err());
+        assert_eq!(
+            completer.prompts.lock().unwrap().len(),
+            1 + MAX_STRUCTURED_RETRIES
+        );
+    }
+
+    #[test]
+    fn test_citations() {
+        let docs = Documents(vec![
+            Document {
+                id: "file:///guide.md#1".to_string(),
+                text: "Install with cargo.".to_string(),
+                metadata: BTreeMap::from([
+                    ("doc_id".to_string(), "file:///guide.md".to_string()),
+                    ("offset".to_string(), "15".to_string()),
+                    ("score".to_string(), "0.8125".to_string()),
+                    ("source".to_string(), "\"guide.md\"".to_string()),
+                ]),
+            },
+            Document {
+                id: "doc_1".to_string(),
+                text: "Test document.".to_string(),
+                metadata: BTreeMap::new(),
+            },
+        ]);
+        let citations = docs.citations();
+        assert_eq!(
+            citations[0],
+            Citation {
+                doc_id: "file:///guide.md".to_string(),
+                chunk_id: "file:///guide.md#1".to_string(),
+                range: Some((15, 34)),
+                score: Some(0.8125),
+                source: Some("guide.md".to_string()),
+            }
+        );
+        assert_eq!(citations[1].doc_id, "doc_1");
+        assert_eq!(
+            serde_json::to_string(&citations[1]).unwrap(),
+            r#"{"doc_id":"doc_1","chunk_id":"doc_1"}"#
+        );
+    }
 
     #[test]
     fn test_prompt() {
@@ -324,6 +622,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_with_locale() {
+        let req = CompletionRequest::default().with_locale(None);
+        assert!(req.system.is_none());
+
+        let req = CompletionRequest {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..Default::default()
+        }
+        .with_locale(Some("fr-FR"));
+        assert_eq!(
+            req.system.unwrap(),
+            format!(
+                "You are a helpful assistant.\n\n{}",
+                language_instruction("fr-FR")
+            )
+        );
+    }
+
     #[test]
     fn test_content_part() {
         let content = ContentPart::Text {
@@ -2,15 +2,17 @@
 //!
 //! This module defines the fundamental data structures and interfaces used throughout the AI agent system.
 //! It includes:
-//! - Core message and conversation structures ([`AgentOutput`], [`Message`], [`ToolCall`]).
+//! - Core message and conversation structures ([`AgentOutput`], [`AgentEvent`], [`Message`], [`ToolCall`]).
 //! - Function definition and tooling support ([`FunctionDefinition`]).
-//! - Knowledge and document handling ([`Document`], [`Documents`]).
+//! - Knowledge and document handling ([`Document`], [`Documents`], [`Citation`]).
 //! - Completion request and response structures ([`CompletionRequest`], [`Embedding`]).
-//! - Core AI capabilities traits ([`CompletionFeatures`], [`EmbeddingFeatures`]).
+//! - Usage statistics and their estimated cost ([`Usage`], [`Pricing`]).
+//! - Core AI capabilities traits ([`CompletionFeatures`], [`EmbeddingFeatures`], [`VectorStoreFeatures`]).
 
 use candid::Principal;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::{collections::BTreeMap, ops::Bound};
 
 pub use ic_auth_types::{ByteArrayB64, ByteBufB64, Xid};
 
@@ -19,12 +21,14 @@ mod embedding;
 mod knowledge;
 mod resource;
 mod thread;
+mod vector;
 
 pub use completion::*;
 pub use embedding::*;
 pub use knowledge::*;
 pub use resource::*;
 pub use thread::*;
+pub use vector::*;
 
 pub const ANONYMOUS: Principal = Principal::anonymous();
 
@@ -67,6 +71,10 @@ pub struct AgentOutput {
     /// The usage statistics for the agent execution.
     pub usage: Usage,
 
+    /// The model that produced the output, a fallback model if the primary one failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
     /// The unique identifier for the thread.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thread: Option<Xid>,
@@ -88,6 +96,111 @@ pub struct AgentOutput {
     /// The resources generated by the agent execution.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resources: Option<Vec<Resource>>,
+
+    /// The sources of the answer, from the documents provided to the completion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub citations: Option<Vec<Citation>>,
+
+    /// The ordered steps of the execution: the completion rounds and the tool and agent calls,
+    /// for debugging what the agent did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<TraceStep>>,
+}
+
+/// A step of an agent execution, see [`AgentOutput::trace`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceStep {
+    /// A completion round of the LLM.
+    Completion {
+        round: usize,
+        duration_ms: u64,
+        usage: Usage,
+        /// The number of tool calls requested by the LLM in the round.
+        tool_calls: usize,
+    },
+
+    /// A tool call requested by the LLM.
+    ToolCall(CallTrace),
+
+    /// An agent run requested by the LLM.
+    AgentRun(CallTrace),
+}
+
+/// The trace of a tool or agent call.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CallTrace {
+    /// The tool call ID.
+    pub id: String,
+
+    /// The tool or agent name.
+    pub name: String,
+
+    /// The SHA3-256 hash of the arguments.
+    pub args_hash: ByteArrayB64<32>,
+
+    pub duration_ms: u64,
+
+    /// The size of the JSON result in bytes, 0 if the call failed.
+    pub result_bytes: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The trace of the called agent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<TraceStep>,
+}
+
+/// Represents a progress event of an agent execution, for streaming to clients.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// A delta of the content generated by the LLM, as it is streamed by the model.
+    /// The whole content of the round is still sent as a [`AgentEvent::Content`] event.
+    Delta { agent: String, content: String },
+
+    /// Content generated by the LLM in a completion round.
+    Content { agent: String, content: String },
+
+    /// A tool or agent call requested by the LLM is starting.
+    ToolCallStart {
+        agent: String,
+        id: String,
+        name: String,
+        args: String,
+    },
+
+    /// A tool or agent call finished, with its result or error.
+    ToolCallEnd {
+        agent: String,
+        id: String,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// The final output of the agent execution.
+    Output(AgentOutput),
+
+    /// The agent execution failed.
+    Error { error: String },
+}
+
+impl AgentEvent {
+    /// Returns the event name, as used by the `event` field of server-sent events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgentEvent::Delta { .. } => "delta",
+            AgentEvent::Content { .. } => "content",
+            AgentEvent::ToolCallStart { .. } => "tool_call_start",
+            AgentEvent::ToolCallEnd { .. } => "tool_call_end",
+            AgentEvent::Output(_) => "output",
+            AgentEvent::Error { .. } => "error",
+        }
+    }
 }
 
 /// Represents a request to a tool for processing.
@@ -145,6 +258,34 @@ impl<T> ToolOutput<T> {
     }
 }
 
+impl ToolOutput<Value> {
+    /// Returns the content of the tool output to send back to the LLM.
+    ///
+    /// A string output is sent as is, other outputs as JSON text. If the tool generated
+    /// resources, the output is sent with them as `artifacts`, without their binary data,
+    /// so that the LLM knows about them.
+    pub fn to_content(&self) -> Result<Value, serde_json::Error> {
+        match &self.resources {
+            Some(resources) if !resources.is_empty() => {
+                let artifacts: Vec<Resource> = resources
+                    .iter()
+                    .map(|r| Resource {
+                        blob: None,
+                        ..r.clone()
+                    })
+                    .collect();
+                let content = serde_json::json!({
+                    "output": self.output,
+                    "artifacts": artifacts,
+                });
+                Ok(serde_json::to_string(&content)?.into())
+            }
+            _ if self.output.is_string() => Ok(self.output.clone()),
+            _ => Ok(serde_json::to_string(&self.output)?.into()),
+        }
+    }
+}
+
 /// Represents the metadata for an agent or tool request.
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct RequestMeta {
@@ -161,10 +302,44 @@ pub struct RequestMeta {
     /// of the user interacting with the bot.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+
+    /// The priority of the request, interactive by default.
+    #[serde(default, skip_serializing_if = "Priority::is_interactive")]
+    pub priority: Priority,
+
+    /// The time budget of the request in milliseconds, including its nested agent runs,
+    /// tool calls and remote calls, which fail once it is exceeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// The locale of the user as a BCP 47 language tag, such as `fr` or `zh-Hant-TW`,
+    /// so that agents may localize their prompts and respond in the user's language.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+}
+
+/// The quality of service class of a request.
+///
+/// Batch requests, such as background jobs, yield to interactive ones in the run queues
+/// and may be served by a dedicated model, so that they never starve user-facing conversations.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// A user waits for the response.
+    #[default]
+    Interactive,
+    /// Nobody waits for the response.
+    Batch,
+}
+
+impl Priority {
+    pub fn is_interactive(&self) -> bool {
+        *self == Priority::Interactive
+    }
 }
 
 /// Represents the usage statistics for the agent or tool execution.
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Usage {
     /// input tokens sent to the LLM
     pub input_tokens: u64,
@@ -174,14 +349,164 @@ pub struct Usage {
 
     /// number of requests made to agents and tools
     pub requests: u64,
+
+    /// The estimated cost in USD, computed with the [`Pricing`] of the models.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cost_usd: f64,
+
+    /// The usage broken down by model, nested agent, tool and completion round, for cost
+    /// attribution. The totals above include all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<Box<UsageBreakdown>>,
+}
+
+/// The breakdown of a [`Usage`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct UsageBreakdown {
+    /// The usage of each model, by model name, including the models used by nested agents
+    /// and tools.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, Usage>,
+
+    /// The usage of each nested agent, by agent name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub agents: BTreeMap<String, Usage>,
+
+    /// The usage of each called tool, by tool name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, Usage>,
+
+    /// The usage of each completion round of the tool loop.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rounds: Vec<Usage>,
 }
 
 impl Usage {
-    /// Accumulates the usage statistics from another usage object.
+    /// Accumulates the usage statistics from another usage object,
+    /// with the usage of its models.
     pub fn accumulate(&mut self, other: &Usage) {
         self.input_tokens = self.input_tokens.saturating_add(other.input_tokens);
         self.output_tokens = self.output_tokens.saturating_add(other.output_tokens);
         self.requests = self.requests.saturating_add(other.requests);
+        self.cost_usd += other.cost_usd;
+        if let Some(breakdown) = &other.breakdown {
+            for (model, usage) in &breakdown.models {
+                self.breakdown_mut()
+                    .models
+                    .entry(model.clone())
+                    .or_default()
+                    .accumulate(usage);
+            }
+        }
+    }
+
+    /// Attributes the usage to the given model, used by model providers.
+    pub fn with_model(mut self, model: &str) -> Self {
+        let totals = self.totals();
+        self.breakdown_mut()
+            .models
+            .insert(model.to_string(), totals);
+        self
+    }
+
+    /// Accumulates the usage of a nested agent run.
+    pub fn accumulate_agent(&mut self, agent: &str, other: &Usage) {
+        self.accumulate(other);
+        self.breakdown_mut()
+            .agents
+            .entry(agent.to_string())
+            .or_default()
+            .accumulate(other);
+    }
+
+    /// Accumulates the usage of a tool call.
+    pub fn accumulate_tool(&mut self, tool: &str, other: &Usage) {
+        self.accumulate(other);
+        self.breakdown_mut()
+            .tools
+            .entry(tool.to_string())
+            .or_default()
+            .accumulate(other);
+    }
+
+    /// Accumulates the usage of a completion round.
+    pub fn accumulate_round(&mut self, other: &Usage) {
+        self.accumulate(other);
+        self.breakdown_mut().rounds.push(other.clone());
+    }
+
+    /// Returns the totals, without the breakdown.
+    pub fn totals(&self) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens,
+            output_tokens: self.output_tokens,
+            requests: self.requests,
+            cost_usd: self.cost_usd,
+            breakdown: None,
+        }
+    }
+
+    fn breakdown_mut(&mut self) -> &mut UsageBreakdown {
+        self.breakdown.get_or_insert_with(Default::default)
+    }
+}
+
+fn is_zero(v: &f64) -> bool {
+    *v == 0.0
+}
+
+/// The price of a model, in USD per million tokens.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    /// USD per million input tokens.
+    pub input: f64,
+    /// USD per million output tokens.
+    pub output: f64,
+}
+
+impl ModelPrice {
+    /// Returns the cost of the tokens of the usage, in USD.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// The prices of the models by name, to estimate the cost of their usage.
+///
+/// A model is priced by its name as reported by the provider, or by the longest name
+/// that it starts with, so that `gpt-4o` prices `gpt-4o-2024-08-06` too.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Pricing(pub BTreeMap<String, ModelPrice>);
+
+impl Pricing {
+    /// Returns the price of the model, if any.
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.0.get(model).or_else(|| {
+            self.0
+                .range::<str, _>((Bound::Unbounded, Bound::Excluded(model)))
+                .rev()
+                .find(|(name, _)| model.starts_with(name.as_str()))
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Sets the cost of the usage of a model completion from the usage of its models,
+    /// see [`Usage::with_model`]. The models without price cost nothing.
+    pub fn apply(&self, usage: &mut Usage) {
+        let Some(breakdown) = usage.breakdown.as_mut() else {
+            return;
+        };
+        let mut cost = 0.0;
+        for (model, u) in breakdown.models.iter_mut() {
+            if let Some(price) = self.price(model) {
+                u.cost_usd = price.cost(u);
+                cost += u.cost_usd;
+            }
+        }
+        usage.cost_usd = cost;
     }
 }
 
@@ -241,3 +566,126 @@ impl FunctionDefinition {
 pub fn evaluate_tokens(content: &str) -> usize {
     content.len() / 3
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_usage_breakdown() {
+        let round = |input_tokens, model: &str| {
+            Usage {
+                input_tokens,
+                output_tokens: 10,
+                requests: 1,
+                ..Default::default()
+            }
+            .with_model(model)
+        };
+
+        let mut nested = Usage::default();
+        nested.accumulate_round(&round(100, "gpt-4o-mini"));
+        nested.accumulate_tool(
+            "weather",
+            &Usage {
+                requests: 1,
+                ..Default::default()
+            },
+        );
+
+        let mut usage = Usage::default();
+        usage.accumulate_round(&round(200, "gpt-4o"));
+        usage.accumulate_agent("forecaster", &nested);
+        usage.accumulate_round(&round(300, "gpt-4o"));
+        assert_eq!(usage.input_tokens, 600);
+        assert_eq!(usage.output_tokens, 30);
+        assert_eq!(usage.requests, 4);
+
+        let breakdown = usage.breakdown.as_ref().unwrap();
+        assert_eq!(breakdown.models["gpt-4o"].input_tokens, 500);
+        assert_eq!(breakdown.models["gpt-4o-mini"].input_tokens, 100);
+        assert_eq!(breakdown.agents["forecaster"].input_tokens, 100);
+        assert_eq!(breakdown.agents["forecaster"].requests, 2);
+        assert!(breakdown.tools.is_empty());
+        assert_eq!(breakdown.rounds.len(), 2);
+        assert_eq!(breakdown.rounds[1].input_tokens, 300);
+        assert!(usage.totals().breakdown.is_none());
+    }
+
+    #[test]
+    fn test_pricing() {
+        let pricing = Pricing(BTreeMap::from([
+            (
+                "gpt-4o".to_string(),
+                ModelPrice {
+                    input: 2.5,
+                    output: 10.0,
+                },
+            ),
+            (
+                "gpt-4o-mini".to_string(),
+                ModelPrice {
+                    input: 0.15,
+                    output: 0.6,
+                },
+            ),
+        ]));
+        assert_eq!(pricing.price("gpt-4o-2024-08-06").unwrap().input, 2.5);
+        assert_eq!(pricing.price("gpt-4o-mini-2024-07-18").unwrap().input, 0.15);
+        assert!(pricing.price("gpt-4").is_none());
+
+        let round = |model: &str| {
+            let mut usage = Usage {
+                input_tokens: 1_000_000,
+                output_tokens: 100_000,
+                requests: 1,
+                ..Default::default()
+            }
+            .with_model(model);
+            pricing.apply(&mut usage);
+            usage
+        };
+        let mut usage = Usage::default();
+        usage.accumulate_round(&round("gpt-4o-2024-08-06"));
+        usage.accumulate_round(&round("gpt-4o-mini"));
+        usage.accumulate_round(&round("unknown"));
+        let breakdown = usage.breakdown.as_ref().unwrap();
+        assert!((breakdown.models["gpt-4o-2024-08-06"].cost_usd - 3.5).abs() < 1e-9);
+        assert!((breakdown.models["gpt-4o-mini"].cost_usd - 0.21).abs() < 1e-9);
+        assert_eq!(breakdown.models["unknown"].cost_usd, 0.0);
+        assert!((usage.cost_usd - 3.71).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tool_output_content() {
+        let output = ToolOutput::new(json!("sunny"));
+        assert_eq!(output.to_content().unwrap(), json!("sunny"));
+
+        let mut output = ToolOutput::new(json!({"temperature": 21}));
+        assert_eq!(output.to_content().unwrap(), json!(r#"{"temperature":21}"#));
+
+        output.resources = Some(vec![Resource {
+            tag: "image".to_string(),
+            name: Some("chart.png".to_string()),
+            mime_type: Some("image/png".to_string()),
+            blob: Some(ByteBufB64(vec![1, 2, 3])),
+            size: Some(3),
+            ..Default::default()
+        }]);
+        let content = output.to_content().unwrap();
+        let content: Value = serde_json::from_str(content.as_str().unwrap()).unwrap();
+        assert_eq!(
+            content,
+            json!({
+                "output": {"temperature": 21},
+                "artifacts": [{
+                    "tag": "image",
+                    "name": "chart.png",
+                    "mime_type": "image/png",
+                    "size": 3,
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::Value;
+use crate::BoxError;
+
+/// The metadata key of the access labels of a document, see [`VectorFilter::acl`].
+pub static VECTOR_ACL_KEY: &str = "acl";
+
+/// The access label of the documents visible to all callers.
+pub static VECTOR_ACL_PUBLIC: &str = "public";
+
+/// A document in a vector store, with its embedding vector.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct VectorDocument {
+    /// The unique ID of the document in the store.
+    pub id: String,
+    /// The embedding vector of the document.
+    pub vec: Vec<f32>,
+    /// The text of the document.
+    #[serde(default)]
+    pub text: String,
+    /// The metadata of the document, used by the search filters.
+    #[serde(default)]
+    pub meta: BTreeMap<String, Value>,
+}
+
+/// A document found by a vector search, with its similarity score to the query.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct VectorMatch {
+    pub id: String,
+    /// The similarity to the query, higher is more similar, 1.0 is identical for cosine.
+    pub score: f32,
+    pub text: String,
+    pub meta: BTreeMap<String, Value>,
+}
+
+/// A filter on the metadata of the documents in a vector search.
+///
+/// The equality filters also match an array value, such as tags, when one of its elements
+/// matches. Range filters only match numbers, dates are compared as timestamps in milliseconds.
+///
+/// # Example
+/// ```rust,ignore
+/// let filter = VectorFilter::And(vec![
+///     VectorFilter::Eq { key: "lang".into(), value: "en".into() },
+///     VectorFilter::In { key: "tags".into(), values: vec!["rust".into(), "wasm".into()] },
+///     VectorFilter::Gte { key: "ingested_at".into(), value: 1735689600000.0 },
+/// ]);
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorFilter {
+    Eq {
+        key: String,
+        value: Value,
+    },
+    Ne {
+        key: String,
+        value: Value,
+    },
+    /// The value is one of the values.
+    In {
+        key: String,
+        values: Vec<Value>,
+    },
+    Gt {
+        key: String,
+        value: f64,
+    },
+    Gte {
+        key: String,
+        value: f64,
+    },
+    Lt {
+        key: String,
+        value: f64,
+    },
+    Lte {
+        key: String,
+        value: f64,
+    },
+    /// The key is present.
+    Exists {
+        key: String,
+    },
+    And(Vec<VectorFilter>),
+    Or(Vec<VectorFilter>),
+    Not(Box<VectorFilter>),
+}
+
+impl VectorFilter {
+    /// Matches the documents visible to a caller with the access labels, e.g.
+    /// `user:{principal}` and `tenant:{id}`: the ones with one of the labels or
+    /// [`VECTOR_ACL_PUBLIC`] in the [`VECTOR_ACL_KEY`] metadata.
+    /// The documents without access labels are not visible.
+    pub fn acl(labels: Vec<String>) -> Self {
+        VectorFilter::In {
+            key: VECTOR_ACL_KEY.to_string(),
+            values: labels
+                .into_iter()
+                .chain([VECTOR_ACL_PUBLIC.to_string()])
+                .map(Value::from)
+                .collect(),
+        }
+    }
+
+    /// Combines the filter with another one, both must match.
+    pub fn and(self, other: VectorFilter) -> Self {
+        match self {
+            VectorFilter::And(mut filters) => {
+                filters.push(other);
+                VectorFilter::And(filters)
+            }
+            filter => VectorFilter::And(vec![filter, other]),
+        }
+    }
+
+    /// Returns true if the metadata matches the filter.
+    /// Range filters only match numbers, other filters compare the JSON values,
+    /// or the elements of an array value.
+    pub fn matches(&self, meta: &BTreeMap<String, Value>) -> bool {
+        let number = |key: &str| meta.get(key).and_then(|v| v.as_f64());
+        let any = |key: &str, f: &dyn Fn(&Value) -> bool| match meta.get(key) {
+            Some(Value::Array(values)) => values.iter().any(f),
+            Some(v) => f(v),
+            None => false,
+        };
+        match self {
+            VectorFilter::Eq { key, value } => any(key, &|v| v == value),
+            VectorFilter::Ne { key, value } => !any(key, &|v| v == value),
+            VectorFilter::In { key, values } => any(key, &|v| values.contains(v)),
+            VectorFilter::Gt { key, value } => number(key).is_some_and(|v| v > *value),
+            VectorFilter::Gte { key, value } => number(key).is_some_and(|v| v >= *value),
+            VectorFilter::Lt { key, value } => number(key).is_some_and(|v| v < *value),
+            VectorFilter::Lte { key, value } => number(key).is_some_and(|v| v <= *value),
+            VectorFilter::Exists { key } => meta.contains_key(key),
+            VectorFilter::And(filters) => filters.iter().all(|f| f.matches(meta)),
+            VectorFilter::Or(filters) => filters.iter().any(|f| f.matches(meta)),
+            VectorFilter::Not(filter) => !filter.matches(meta),
+        }
+    }
+}
+
+/// Provides the storage and similarity search of embedding vectors, for retrieval-augmented generation.
+pub trait VectorStoreFeatures: Sized {
+    /// Inserts the documents, or replaces the documents with the same IDs.
+    fn vector_upsert(
+        &self,
+        docs: Vec<VectorDocument>,
+    ) -> impl Future<Output = Result<(), BoxError>> + Send;
+
+    /// Gets the documents by IDs with their vectors, the missing ones are skipped.
+    fn vector_get(
+        &self,
+        ids: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<VectorDocument>, BoxError>> + Send;
+
+    /// Deletes the documents by IDs, returns the number of deleted documents.
+    fn vector_delete(
+        &self,
+        ids: Vec<String>,
+    ) -> impl Future<Output = Result<usize, BoxError>> + Send;
+
+    /// Finds the top k documents most similar to the query vector and matching the filter,
+    /// ordered by descending score.
+    fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> impl Future<Output = Result<Vec<VectorMatch>, BoxError>> + Send;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_vector_filter() {
+        let meta: BTreeMap<String, Value> =
+            serde_json::from_value(json!({"lang": "en", "year": 2024, "tags": "rust"})).unwrap();
+        let filter: VectorFilter = serde_json::from_value(json!({
+            "and": [
+                {"eq": {"key": "lang", "value": "en"}},
+                {"gte": {"key": "year", "value": 2024}},
+                {"not": {"in": {"key": "tags", "values": ["go", "java"]}}},
+            ]
+        }))
+        .unwrap();
+        assert!(filter.matches(&meta));
+        assert!(
+            !VectorFilter::Lt {
+                key: "year".into(),
+                value: 2024.0
+            }
+            .matches(&meta)
+        );
+        assert!(
+            !VectorFilter::Gt {
+                key: "lang".into(),
+                value: 0.0
+            }
+            .matches(&meta)
+        );
+        assert!(
+            !VectorFilter::Exists {
+                key: "author".into()
+            }
+            .matches(&meta)
+        );
+        assert!(
+            VectorFilter::Ne {
+                key: "author".into(),
+                value: json!("x")
+            }
+            .matches(&meta)
+        );
+        assert!(
+            VectorFilter::Or(vec![
+                VectorFilter::Exists {
+                    key: "author".into()
+                },
+                VectorFilter::Eq {
+                    key: "tags".into(),
+                    value: json!("rust")
+                },
+            ])
+            .matches(&meta)
+        );
+    }
+
+    #[test]
+    fn test_vector_filter_acl() {
+        let doc: BTreeMap<String, Value> = serde_json::from_value(json!({
+            "tags": ["rust", "wasm"],
+            "acl": ["tenant:acme", "user:aaaaa-aa"],
+        }))
+        .unwrap();
+        let public: BTreeMap<String, Value> =
+            serde_json::from_value(json!({"tags": "go", "acl": "public"})).unwrap();
+        let unlabeled: BTreeMap<String, Value> =
+            serde_json::from_value(json!({"tags": "go"})).unwrap();
+
+        let tags = VectorFilter::In {
+            key: "tags".into(),
+            values: vec![json!("wasm"), json!("go")],
+        };
+        assert!(tags.matches(&doc));
+        assert!(tags.matches(&public));
+        let not_rust = VectorFilter::Ne {
+            key: "tags".into(),
+            value: json!("rust"),
+        };
+        assert!(!not_rust.matches(&doc));
+        assert!(not_rust.matches(&public));
+
+        let acme = VectorFilter::acl(vec!["user:2vxsx-fae".into(), "tenant:acme".into()]);
+        assert!(acme.matches(&doc));
+        assert!(acme.matches(&public));
+        assert!(!acme.matches(&unlabeled));
+        let other = VectorFilter::acl(vec!["user:2vxsx-fae".into(), "tenant:other".into()]);
+        assert!(!other.matches(&doc));
+        assert!(other.clone().and(tags.clone()).matches(&public));
+        assert!(!other.and(tags).matches(&doc));
+    }
+}
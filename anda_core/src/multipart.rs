@@ -0,0 +1,211 @@
+//! Multipart/form-data body builder for `https_call`.
+//!
+//! Many third-party APIs (transcription, OCR, file upload) require `multipart/form-data`.
+//! [`MultipartForm`] builds the body from text fields and files from memory or the store.
+//!
+//! # Example
+//! ```rust,ignore
+//! use anda_core::MultipartForm;
+//!
+//! let form = MultipartForm::new()
+//!     .text("model", "whisper-1")
+//!     .store_file(&ctx, "file", &Path::from("audio/1.mp3"), "1.mp3", "audio/mpeg")
+//!     .await?;
+//! let (headers, body) = form.into_request(None)?;
+//! let res = ctx
+//!     .https_call(url, http::Method::POST, Some(headers), Some(body))
+//!     .await?;
+//! ```
+
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{BoxError, Path, StoreFeatures};
+
+/// A part of a multipart form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartPart {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// A `multipart/form-data` body builder.
+#[derive(Debug, Clone)]
+pub struct MultipartForm {
+    boundary: String,
+    parts: Vec<MultipartPart>,
+}
+
+impl Default for MultipartForm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MultipartForm {
+    /// Creates an empty form with a generated boundary.
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Self {
+            boundary: format!("----AndaFormBoundary{:032x}", nanos),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Sets the boundary, it should not occur in any part.
+    pub fn with_boundary(mut self, boundary: &str) -> Self {
+        self.boundary = boundary.to_string();
+        self
+    }
+
+    /// Returns the boundary.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Returns the parts.
+    pub fn parts(&self) -> &[MultipartPart] {
+        &self.parts
+    }
+
+    /// Adds a text field.
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.parts.push(MultipartPart {
+            name: name.to_string(),
+            filename: None,
+            content_type: None,
+            data: Bytes::copy_from_slice(value.as_bytes()),
+        });
+        self
+    }
+
+    /// Adds a file from memory.
+    pub fn file(
+        mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        data: impl Into<Bytes>,
+    ) -> Self {
+        self.parts.push(MultipartPart {
+            name: name.to_string(),
+            filename: Some(filename.to_string()),
+            content_type: Some(content_type.to_string()),
+            data: data.into(),
+        });
+        self
+    }
+
+    /// Adds a file read from the store at `path`.
+    pub async fn store_file<C: StoreFeatures>(
+        self,
+        ctx: &C,
+        name: &str,
+        path: &Path,
+        filename: &str,
+        content_type: &str,
+    ) -> Result<Self, BoxError> {
+        let (data, _) = ctx.store_get(path).await?;
+        Ok(self.file(name, filename, content_type, data))
+    }
+
+    /// Returns the `Content-Type` header value.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Encodes the form body.
+    pub fn to_body(&self) -> Result<Vec<u8>, BoxError> {
+        let delimiter = format!("--{}", self.boundary);
+        let size: usize = self
+            .parts
+            .iter()
+            .map(|p| p.data.len() + p.name.len() + delimiter.len() + 128)
+            .sum();
+        let mut body = Vec::with_capacity(size + delimiter.len() + 4);
+        for part in &self.parts {
+            if part
+                .data
+                .windows(delimiter.len())
+                .any(|w| w == delimiter.as_bytes())
+            {
+                return Err(format!("multipart part {} contains the boundary", part.name).into());
+            }
+
+            body.extend_from_slice(delimiter.as_bytes());
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"",
+                    escape_quoted(&part.name)
+                )
+                .as_bytes(),
+            );
+            if let Some(filename) = &part.filename {
+                body.extend_from_slice(
+                    format!("; filename=\"{}\"", escape_quoted(filename)).as_bytes(),
+                );
+            }
+            body.extend_from_slice(b"\r\n");
+            if let Some(content_type) = &part.content_type {
+                body.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+            }
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(&part.data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(delimiter.as_bytes());
+        body.extend_from_slice(b"--\r\n");
+        Ok(body)
+    }
+
+    /// Returns the headers (with `Content-Type` set) and the body for `https_call`.
+    pub fn into_request(
+        self,
+        headers: Option<http::HeaderMap>,
+    ) -> Result<(http::HeaderMap, Vec<u8>), BoxError> {
+        let mut headers = headers.unwrap_or_default();
+        headers.insert(http::header::CONTENT_TYPE, self.content_type().parse()?);
+        let body = self.to_body()?;
+        Ok((headers, body))
+    }
+}
+
+/// Escapes a quoted parameter value as browsers do for form data.
+fn escape_quoted(s: &str) -> String {
+    s.replace('\r', "%0D")
+        .replace('\n', "%0A")
+        .replace('"', "%22")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_form() {
+        let form = MultipartForm::new()
+            .with_boundary("XyZ")
+            .text("model", "whisper-1")
+            .file("file", "a \"b\".txt", "text/plain", b"hello".to_vec());
+        let (headers, body) = form.into_request(None).unwrap();
+        assert_eq!(
+            headers.get(http::header::CONTENT_TYPE).unwrap(),
+            "multipart/form-data; boundary=XyZ"
+        );
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--XyZ\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a %22b%22.txt\"\r\nContent-Type: text/plain\r\n\r\nhello\r\n--XyZ--\r\n"
+        );
+
+        let form = MultipartForm::new()
+            .with_boundary("XyZ")
+            .text("field", "--XyZ");
+        assert!(form.to_body().is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! OAuth2 client-credentials helper.
+//!
+//! Tools calling OAuth-protected APIs share the token lifecycle implemented here:
+//! - [`OAuth2Config`]: Client credentials, token endpoint and scopes;
+//! - [`OAuth2Features`]: An extension of [`HttpFeatures`] and [`CacheFeatures`] that acquires,
+//!   caches and refreshes access tokens, and injects bearer tokens into `https_call`.
+//!
+//! # Example
+//! ```rust,ignore
+//! use anda_core::{OAuth2Config, OAuth2Features};
+//!
+//! let cfg = OAuth2Config::new("https://auth.example.com/oauth/token", "client_id", "secret")
+//!     .with_scopes(vec!["read".to_string()]);
+//! let res = ctx
+//!     .oauth2_https_call(&cfg, "https://api.example.com/v1/items", http::Method::GET, None, None)
+//!     .await?;
+//! ```
+
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{BoxError, CacheExpiry, CacheFeatures, HttpFeatures};
+
+/// Tokens are refreshed this long before they expire.
+const EXPIRY_SKEW_SECS: u64 = 60;
+/// Default lifetime of tokens without `expires_in`.
+const DEFAULT_EXPIRES_IN_SECS: u64 = 3600;
+/// Cached tokens with a refresh token are kept this long after they expire.
+const REFRESH_WINDOW_SECS: u64 = 3600 * 24;
+
+/// How the client authenticates to the token endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OAuth2ClientAuth {
+    /// HTTP Basic authentication (`client_secret_basic`), the default.
+    #[default]
+    Basic,
+    /// Credentials in the request body (`client_secret_post`).
+    Post,
+}
+
+/// OAuth2 client-credentials configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OAuth2Config {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+    /// Optional `audience` parameter required by some providers.
+    pub audience: Option<String>,
+    pub client_auth: OAuth2ClientAuth,
+}
+
+impl OAuth2Config {
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scopes: Vec::new(),
+            audience: None,
+            client_auth: OAuth2ClientAuth::default(),
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    pub fn with_client_auth(mut self, client_auth: OAuth2ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Returns the cache key of the token, a hash of all the fields that shape
+    /// the token request, so the client secret is not part of the key.
+    pub fn cache_key(&self) -> String {
+        let fields = to_cbor_bytes(&(
+            &self.token_url,
+            &self.client_id,
+            &self.client_secret,
+            &self.scopes,
+            &self.audience,
+            self.client_auth,
+        ));
+        format!(
+            "oauth2:{}",
+            BASE64_URL_SAFE_NO_PAD.encode(sha3_256(&fields))
+        )
+    }
+
+    /// Builds the form-urlencoded body of a token request.
+    fn token_request(&self, refresh_token: Option<&str>) -> (http::HeaderMap, Vec<u8>) {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        match refresh_token {
+            Some(token) => {
+                form.append_pair("grant_type", "refresh_token");
+                form.append_pair("refresh_token", token);
+            }
+            None => {
+                form.append_pair("grant_type", "client_credentials");
+            }
+        }
+        if !self.scopes.is_empty() {
+            form.append_pair("scope", &self.scopes.join(" "));
+        }
+        if let Some(audience) = &self.audience {
+            form.append_pair("audience", audience);
+        }
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(
+            http::header::ACCEPT,
+            http::HeaderValue::from_static("application/json"),
+        );
+        match self.client_auth {
+            OAuth2ClientAuth::Basic => {
+                let encode = |s: &str| {
+                    url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>()
+                };
+                let credentials = format!(
+                    "{}:{}",
+                    encode(&self.client_id),
+                    encode(&self.client_secret)
+                );
+                let value = format!("Basic {}", BASE64_STANDARD.encode(credentials));
+                if let Ok(value) = value.parse() {
+                    headers.insert(http::header::AUTHORIZATION, value);
+                }
+            }
+            OAuth2ClientAuth::Post => {
+                form.append_pair("client_id", &self.client_id);
+                form.append_pair("client_secret", &self.client_secret);
+            }
+        }
+        (headers, form.finish().into_bytes())
+    }
+}
+
+/// OAuth2 token response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OAuth2Token {
+    pub access_token: String,
+    #[serde(default)]
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CachedOAuth2Token {
+    token: OAuth2Token,
+    /// Expiration time in seconds since the Unix epoch.
+    expires_at: u64,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// OAuth2Features manages OAuth2 access tokens for tools, caching them with [`CacheFeatures`].
+/// It is implemented for all cloneable contexts with [`HttpFeatures`] and [`CacheFeatures`].
+pub trait OAuth2Features: HttpFeatures + CacheFeatures + Clone + Send + Sync + 'static {
+    /// Returns a valid access token, from the cache or by requesting the token endpoint.
+    /// Expired tokens are refreshed with the refresh token if there is one.
+    fn oauth2_token(
+        &self,
+        cfg: &OAuth2Config,
+    ) -> impl Future<Output = Result<OAuth2Token, BoxError>> + Send {
+        async move {
+            let key = cfg.cache_key();
+            let now = unix_secs();
+            let mut refresh_token = None;
+            if let Ok(cached) = self.cache_get::<CachedOAuth2Token>(&key).await {
+                if cached.expires_at > now + EXPIRY_SKEW_SECS {
+                    return Ok(cached.token);
+                }
+                refresh_token = cached.token.refresh_token;
+            }
+
+            let token = match refresh_token {
+                Some(rt) => match self.oauth2_request(cfg, Some(&rt)).await {
+                    Ok(token) => token,
+                    Err(_) => self.oauth2_request(cfg, None).await?,
+                },
+                None => self.oauth2_request(cfg, None).await?,
+            };
+
+            let expires_in = token.expires_in.unwrap_or(DEFAULT_EXPIRES_IN_SECS);
+            let ttl = if token.refresh_token.is_some() {
+                expires_in + REFRESH_WINDOW_SECS
+            } else {
+                expires_in
+            };
+            self.cache_set(
+                &key,
+                (
+                    CachedOAuth2Token {
+                        token: token.clone(),
+                        expires_at: now + expires_in,
+                    },
+                    Some(CacheExpiry::TTL(Duration::from_secs(ttl))),
+                ),
+            )
+            .await;
+            Ok(token)
+        }
+    }
+
+    /// Requests a token from the token endpoint, with the refresh token grant if provided,
+    /// otherwise the client credentials grant. The result is not cached.
+    fn oauth2_request(
+        &self,
+        cfg: &OAuth2Config,
+        refresh_token: Option<&str>,
+    ) -> impl Future<Output = Result<OAuth2Token, BoxError>> + Send {
+        let (headers, body) = cfg.token_request(refresh_token);
+        async move {
+            let res = self
+                .https_call(
+                    &cfg.token_url,
+                    http::Method::POST,
+                    Some(headers),
+                    Some(body),
+                )
+                .await?;
+            let status = res.status();
+            if !status.is_success() {
+                let msg = res.text().await.unwrap_or_default();
+                return Err(format!(
+                    "OAuth2 token request to {} failed, status: {}, body: {}",
+                    cfg.token_url, status, msg
+                )
+                .into());
+            }
+            let token: OAuth2Token = res.json().await?;
+            if token.access_token.is_empty() {
+                return Err(format!("OAuth2 token from {} is empty", cfg.token_url).into());
+            }
+            Ok(token)
+        }
+    }
+
+    /// Removes the cached token, e.g. after it was rejected.
+    fn oauth2_invalidate(&self, cfg: &OAuth2Config) -> impl Future<Output = bool> + Send {
+        let key = cfg.cache_key();
+        async move { self.cache_delete(&key).await }
+    }
+
+    /// Makes an HTTPs request with the bearer token of `cfg`.
+    /// If the response is 401 Unauthorized, the token is invalidated and the request is retried once.
+    fn oauth2_https_call(
+        &self,
+        cfg: &OAuth2Config,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+    ) -> impl Future<Output = Result<reqwest::Response, BoxError>> + Send {
+        async move {
+            let headers = headers.unwrap_or_default();
+            let mut retried = false;
+            loop {
+                let token = self.oauth2_token(cfg).await?;
+                let mut headers = headers.clone();
+                headers.insert(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {}", token.access_token).parse()?,
+                );
+                let res = self
+                    .https_call(url, method.clone(), Some(headers), body.clone())
+                    .await?;
+                if res.status() == http::StatusCode::UNAUTHORIZED && !retried {
+                    retried = true;
+                    self.oauth2_invalidate(cfg).await;
+                    continue;
+                }
+                return Ok(res);
+            }
+        }
+    }
+}
+
+impl<T> OAuth2Features for T where T: HttpFeatures + CacheFeatures + Clone + Send + Sync + 'static {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_request() {
+        let cfg = OAuth2Config::new("https://auth.example.com/token", "my id", "s&cret")
+            .with_scopes(vec!["read".to_string(), "write".to_string()]);
+        let (headers, body) = cfg.token_request(None);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "grant_type=client_credentials&scope=read+write"
+        );
+        assert_eq!(
+            headers.get(http::header::AUTHORIZATION).unwrap(),
+            &format!("Basic {}", BASE64_STANDARD.encode("my+id:s%26cret"))
+        );
+
+        let cfg = cfg
+            .with_audience("https://api.example.com")
+            .with_client_auth(OAuth2ClientAuth::Post);
+        let (headers, body) = cfg.token_request(Some("rt"));
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "grant_type=refresh_token&refresh_token=rt&scope=read+write&audience=https%3A%2F%2Fapi.example.com&client_id=my+id&client_secret=s%26cret"
+        );
+        assert!(headers.get(http::header::AUTHORIZATION).is_none());
+
+        let cfg = OAuth2Config::new("https://auth.example.com/token", "id", "secret");
+        let key = cfg.cache_key();
+        assert!(key.starts_with("oauth2:"));
+        assert!(!key.contains("secret"));
+        assert_eq!(cfg.clone().cache_key(), key);
+        for other in [
+            cfg.clone().with_scopes(vec!["read".to_string()]),
+            cfg.clone().with_audience("https://api.example.com"),
+            cfg.clone().with_client_auth(OAuth2ClientAuth::Post),
+            OAuth2Config::new("https://auth.example.com/token", "id", "secret2"),
+            OAuth2Config::new("https://auth.example.com/token2", "id", "secret"),
+        ] {
+            assert_ne!(other.cache_key(), key);
+        }
+
+        let token: OAuth2Token = serde_json::from_str(
+            r#"{"access_token":"abc","token_type":"Bearer","expires_in":3600}"#,
+        )
+        .unwrap();
+        assert_eq!(token.access_token, "abc");
+        assert_eq!(token.expires_in, Some(3600));
+        assert!(token.refresh_token.is_none());
+    }
+}
@@ -0,0 +1,340 @@
+//! Server-Sent Events client helper.
+//!
+//! Many model providers and external agent APIs stream responses via
+//! [Server-Sent Events](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+//! This module provides:
+//! - [`SseParser`]: An incremental parser for `text/event-stream` bodies;
+//! - [`SseFeatures`]: An extension of [`HttpFeatures`] that opens an event stream over
+//!   `https_call`, with automatic reconnection and `Last-Event-ID` resumption.
+//!
+//! # Example
+//! ```rust,ignore
+//! use anda_core::{SseFeatures, SseOptions};
+//! use futures::StreamExt;
+//!
+//! let mut stream = ctx.https_sse("https://example.com/events", None, SseOptions::default());
+//! while let Some(event) = stream.next().await {
+//!     let event = event?;
+//!     println!("{}: {}", event.event, event.data);
+//! }
+//! ```
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{BoxError, HttpFeatures};
+
+pub static CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+/// A Server-Sent Event.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The event id, if any.
+    pub id: Option<String>,
+    /// The event type, "message" by default.
+    pub event: String,
+    /// The event data, multiple data lines are joined by "\n".
+    pub data: String,
+    /// The reconnection time requested by the server, in milliseconds.
+    pub retry: Option<u64>,
+}
+
+/// Incremental parser for `text/event-stream` bodies.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buf: Vec<u8>,
+    id: Option<String>,
+    event: String,
+    data: String,
+    retry: Option<u64>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk of the body, returns the events completed by it.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.buf.drain(..=pos).collect();
+            line.pop(); // '\n'
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let line = String::from_utf8_lossy(&line);
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            // comment
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse::<u64>() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = std::mem::take(&mut self.event);
+        let retry = self.retry.take();
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.pop(); // trailing '\n'
+        Some(SseEvent {
+            // the last event id persists across events
+            id: self.id.clone(),
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            retry,
+        })
+    }
+}
+
+/// Options for [`SseFeatures::https_sse`].
+#[derive(Debug, Clone)]
+pub struct SseOptions {
+    /// Reconnect when the connection is closed or broken, default is true.
+    pub reconnect: bool,
+    /// Maximum consecutive reconnection attempts without receiving an event, default is 5.
+    pub max_retries: u32,
+    /// Initial reconnection delay, the server can override it with the `retry` field.
+    pub retry_delay: Duration,
+    /// The last event id to resume from.
+    pub last_event_id: Option<String>,
+}
+
+impl Default for SseOptions {
+    fn default() -> Self {
+        Self {
+            reconnect: true,
+            max_retries: 5,
+            retry_delay: Duration::from_secs(3),
+            last_event_id: None,
+        }
+    }
+}
+
+struct SseState<C> {
+    ctx: C,
+    url: String,
+    headers: http::HeaderMap,
+    opts: SseOptions,
+    body: Option<BoxStream<'static, Result<Bytes, reqwest::Error>>>,
+    parser: SseParser,
+    pending: VecDeque<SseEvent>,
+    attempts: u32,
+    done: bool,
+}
+
+impl<C: HttpFeatures> SseState<C> {
+    async fn connect(&mut self) -> Result<(), BoxError> {
+        let mut headers = self.headers.clone();
+        headers.insert(
+            http::header::ACCEPT,
+            http::HeaderValue::from_static(CONTENT_TYPE_EVENT_STREAM),
+        );
+        if let Some(id) = &self.opts.last_event_id {
+            headers.insert("last-event-id", id.parse()?);
+        }
+
+        let res = self
+            .ctx
+            .https_call(&self.url, http::Method::GET, Some(headers), None)
+            .await?;
+        let status = res.status();
+        if status == http::StatusCode::NO_CONTENT {
+            // the server asks the client to stop reconnecting
+            self.done = true;
+            return Ok(());
+        }
+        if !status.is_success() {
+            return Err(format!("SSE {} failed, status: {}", self.url, status).into());
+        }
+
+        self.parser = SseParser::new();
+        self.body = Some(res.bytes_stream().boxed());
+        Ok(())
+    }
+}
+
+/// SseFeatures extends [`HttpFeatures`] with a Server-Sent Events client.
+/// It is implemented for all cloneable [`HttpFeatures`] implementations.
+pub trait SseFeatures: HttpFeatures + Clone + Send + Sync + 'static {
+    /// Opens an SSE stream with a GET request over `https_call`.
+    /// The stream reconnects automatically with the `Last-Event-ID` header according to `opts`,
+    /// and ends when the server responds with 204 No Content, or retries are exhausted.
+    fn https_sse(
+        &self,
+        url: &str,
+        headers: Option<http::HeaderMap>,
+        opts: SseOptions,
+    ) -> BoxStream<'static, Result<SseEvent, BoxError>> {
+        let state = SseState {
+            ctx: self.clone(),
+            url: url.to_string(),
+            headers: headers.unwrap_or_default(),
+            opts,
+            body: None,
+            parser: SseParser::new(),
+            pending: VecDeque::new(),
+            attempts: 0,
+            done: false,
+        };
+
+        sse_stream(state).boxed()
+    }
+}
+
+impl<T> SseFeatures for T where T: HttpFeatures + Clone + Send + Sync + 'static {}
+
+fn sse_stream<C>(state: SseState<C>) -> impl Stream<Item = Result<SseEvent, BoxError>> + Send
+where
+    C: HttpFeatures + Send + Sync + 'static,
+{
+    futures::stream::unfold(state, |mut st| async move {
+        loop {
+            if let Some(event) = st.pending.pop_front() {
+                return Some((Ok(event), st));
+            }
+            if st.done {
+                return None;
+            }
+
+            match st.body.as_mut() {
+                None => {
+                    if st.attempts > 0 {
+                        if !st.opts.reconnect || st.attempts > st.opts.max_retries {
+                            st.done = true;
+                            return Some((
+                                Err(format!(
+                                    "SSE {} disconnected after {} attempts",
+                                    st.url, st.attempts
+                                )
+                                .into()),
+                                st,
+                            ));
+                        }
+                        tokio::time::sleep(st.opts.retry_delay).await;
+                    }
+
+                    st.attempts += 1;
+                    if let Err(err) = st.connect().await
+                        && (!st.opts.reconnect || st.attempts > st.opts.max_retries)
+                    {
+                        st.done = true;
+                        return Some((Err(err), st));
+                    }
+                }
+                Some(body) => match body.next().await {
+                    Some(Ok(chunk)) => {
+                        for event in st.parser.feed(&chunk) {
+                            if event.id.is_some() {
+                                st.opts.last_event_id = event.id.clone();
+                            }
+                            if let Some(retry) = event.retry {
+                                st.opts.retry_delay = Duration::from_millis(retry);
+                            }
+                            st.pending.push_back(event);
+                        }
+                        if !st.pending.is_empty() {
+                            st.attempts = 0;
+                        }
+                    }
+                    Some(Err(_)) | None => {
+                        // connection closed or broken
+                        st.body = None;
+                        if !st.opts.reconnect {
+                            st.done = true;
+                        } else if st.attempts == 0 {
+                            st.attempts = 1;
+                        }
+                    }
+                },
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b": comment\n").is_empty());
+        assert!(parser.feed(b"data: hello\r\n").is_empty());
+        let events = parser.feed(b"data:world\n\nid: 1\nevent: delta\ndata: {\"a\":1}");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                id: None,
+                event: "message".to_string(),
+                data: "hello\nworld".to_string(),
+                retry: None,
+            }]
+        );
+
+        let events = parser.feed(b"\n\nretry: 1000\ndata\n\ndata: [DONE]\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    id: Some("1".to_string()),
+                    event: "delta".to_string(),
+                    data: "{\"a\":1}".to_string(),
+                    retry: None,
+                },
+                SseEvent {
+                    id: Some("1".to_string()),
+                    event: "message".to_string(),
+                    data: "".to_string(),
+                    retry: Some(1000),
+                },
+                SseEvent {
+                    id: Some("1".to_string()),
+                    event: "message".to_string(),
+                    data: "[DONE]".to_string(),
+                    retry: None,
+                },
+            ]
+        );
+    }
+}
@@ -28,11 +28,11 @@
 //! required for LLMs Function Calling.
 
 use serde::{Serialize, de::DeserializeOwned};
-use std::{collections::BTreeMap, future::Future, marker::PhantomData, sync::Arc};
+use std::{collections::BTreeMap, future::Future, marker::PhantomData, sync::Arc, time::Duration};
 
 use crate::{
-    BoxError, BoxPinFut, Function, Resource, ToolOutput, Value, context::BaseContext,
-    model::FunctionDefinition, select_resources, validate_function_name,
+    BoxError, BoxPinFut, Error, Function, Resource, ToolOutput, Value, anda_error,
+    context::BaseContext, model::FunctionDefinition, select_resources, validate_function_name,
 };
 
 /// Core trait for implementing tools that can be used by the AI Agent system.
@@ -78,6 +78,12 @@ where
         Vec::new()
     }
 
+    /// Returns the timeout of each call of the tool, overriding the engine's tool timeout.
+    /// By default, it returns `None`.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
     /// Initializes the tool with the given context.
     /// It will be called once when building the Anda engine.
     fn init(&self, _ctx: C) -> impl Future<Output = Result<(), BoxError>> + Send {
@@ -100,21 +106,35 @@ where
         resources: Option<Vec<Resource>>,
     ) -> impl Future<Output = Result<ToolOutput<Self::Output>, BoxError>> + Send;
 
-    /// Executes the tool with given context and arguments using raw JSON string
-    /// Returns the output as a JSON object.
+    /// Validates the arguments deserialized by [`Tool::call_raw`], before calling the tool.
+    /// Tools whose arguments derive [`ToolArgs`](crate::ToolArgs) return `args.validate()`.
+    fn validate_args(&self, _args: &Self::Args) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Executes the tool with given context and JSON arguments.
+    /// Returns the output as a JSON value, so that callers keep its structure.
     fn call_raw(
         &self,
         ctx: C,
-        args: String,
+        args: Value,
         resources: Option<Vec<Resource>>,
     ) -> impl Future<Output = Result<ToolOutput<Value>, BoxError>> + Send {
         async move {
-            let args: Self::Args = serde_json::from_str(&args)
-                .map_err(|err| format!("tool {}, invalid args: {}", self.name(), err))?;
-            let mut result = self
-                .call(ctx, args, resources)
-                .await
-                .map_err(|err| format!("tool {}, call failed: {}", self.name(), err))?;
+            let args: Self::Args = serde_json::from_value(args).map_err(|err| {
+                Error::Validation(format!("args of tool {}: {}", self.name(), err))
+            })?;
+            self.validate_args(&args).map_err(|err| {
+                Error::Validation(format!("args of tool {}: {}", self.name(), err))
+            })?;
+            let mut result =
+                self.call(ctx, args, resources)
+                    .await
+                    .map_err(|err| match anda_error(&err) {
+                        // keeps the kind of structured errors
+                        Some(_) => err,
+                        None => format!("tool {}, call failed: {}", self.name(), err).into(),
+                    })?;
             let output = serde_json::to_value(&result.output)?;
             if result.usage.requests == 0 {
                 result.usage.requests = 1;
@@ -143,12 +163,14 @@ where
 
     fn supported_resource_tags(&self) -> Vec<String>;
 
+    fn timeout(&self) -> Option<Duration>;
+
     fn init(&self, ctx: C) -> BoxPinFut<Result<(), BoxError>>;
 
     fn call(
         &self,
         ctx: C,
-        args: String,
+        args: Value,
         resources: Option<Vec<Resource>>,
     ) -> BoxPinFut<Result<ToolOutput<Value>, BoxError>>;
 }
@@ -176,6 +198,10 @@ where
         self.0.supported_resource_tags()
     }
 
+    fn timeout(&self) -> Option<Duration> {
+        self.0.timeout()
+    }
+
     fn init(&self, ctx: C) -> BoxPinFut<Result<(), BoxError>> {
         let tool = self.0.clone();
         Box::pin(async move { tool.init(ctx).await })
@@ -184,7 +210,7 @@ where
     fn call(
         &self,
         ctx: C,
-        args: String,
+        args: Value,
         resources: Option<Vec<Resource>>,
     ) -> BoxPinFut<Result<ToolOutput<Value>, BoxError>> {
         let tool = self.0.clone();
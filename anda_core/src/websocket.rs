@@ -0,0 +1,119 @@
+//! WebSocket client types for `HttpFeatures::websocket_connect`.
+//!
+//! The connection is managed by the engine, Agents and Tools exchange [`WsMessage`]s
+//! with it through bounded channels, which provide backpressure in both directions:
+//! - [`WebSocket::send`] waits when the outgoing buffer is full;
+//! - the engine stops reading from the socket when the incoming buffer is full.
+//!
+//! The connection is closed when the [`WebSocket`] is dropped, or when the context is cancelled.
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::BoxError;
+
+/// A WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    /// Close frame with an optional status code and reason.
+    Close(Option<(u16, String)>),
+}
+
+/// Options for `HttpFeatures::websocket_connect`.
+#[derive(Debug, Clone)]
+pub struct WsOptions {
+    /// Extra HTTP headers of the handshake request.
+    pub headers: Option<http::HeaderMap>,
+    /// Interval of keepalive pings, `None` to disable. Default is 30 seconds.
+    pub ping_interval: Option<Duration>,
+    /// Capacity of the incoming and outgoing message buffers. Default is 32.
+    pub buffer: usize,
+    /// Maximum size of an incoming message in bytes. Default is 16 MiB.
+    pub max_message_size: usize,
+    /// Timeout of the connection and handshake. Default is 10 seconds.
+    pub connect_timeout: Duration,
+}
+
+impl Default for WsOptions {
+    fn default() -> Self {
+        Self {
+            headers: None,
+            ping_interval: Some(Duration::from_secs(30)),
+            buffer: 32,
+            max_message_size: 16 * 1024 * 1024,
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A WebSocket connection managed by the engine.
+#[derive(Debug)]
+pub struct WebSocket {
+    sender: WsSender,
+    receiver: mpsc::Receiver<Result<WsMessage, BoxError>>,
+}
+
+/// The sending half of a [`WebSocket`].
+#[derive(Debug, Clone)]
+pub struct WsSender {
+    tx: mpsc::Sender<WsMessage>,
+}
+
+impl WsSender {
+    /// Sends a message, waits if the outgoing buffer is full.
+    pub async fn send(&self, msg: WsMessage) -> Result<(), BoxError> {
+        self.tx
+            .send(msg)
+            .await
+            .map_err(|_| "WebSocket connection closed".into())
+    }
+
+    /// Sends a text message.
+    pub async fn send_text(&self, text: String) -> Result<(), BoxError> {
+        self.send(WsMessage::Text(text)).await
+    }
+
+    /// Returns true if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl WebSocket {
+    /// Creates a WebSocket from the channels bridged to the connection by its implementation.
+    pub fn new(
+        tx: mpsc::Sender<WsMessage>,
+        receiver: mpsc::Receiver<Result<WsMessage, BoxError>>,
+    ) -> Self {
+        Self {
+            sender: WsSender { tx },
+            receiver,
+        }
+    }
+
+    /// Sends a message, waits if the outgoing buffer is full.
+    pub async fn send(&self, msg: WsMessage) -> Result<(), BoxError> {
+        self.sender.send(msg).await
+    }
+
+    /// Receives the next message, returns `None` when the connection is closed.
+    /// Pings are answered by the engine automatically.
+    pub async fn recv(&mut self) -> Option<Result<WsMessage, BoxError>> {
+        self.receiver.recv().await
+    }
+
+    /// Sends a close frame, the connection is closed after the server acknowledges it.
+    pub async fn close(&self) -> Result<(), BoxError> {
+        self.sender.send(WsMessage::Close(None)).await
+    }
+
+    /// Returns a sender that can be used concurrently with [`Self::recv`].
+    pub fn sender(&self) -> WsSender {
+        self.sender.clone()
+    }
+}
@@ -0,0 +1,827 @@
+//! Agent2Agent (A2A) protocol interoperability.
+//!
+//! [A2A](https://github.com/google/A2A) is a JSON-RPC 2.0 protocol over HTTP between agents
+//! of different frameworks: a client sends a [`Task`] with a [`A2aMessage`] to a remote agent
+//! described by its [`A2aAgentCard`], then gets its status and artifacts, streamed with
+//! server-sent events or polled, and may cancel it.
+//!
+//! This module provides:
+//! - The protocol types, shared with the A2A server of `anda_engine_server`;
+//! - Conversions between A2A messages and tasks and Anda agent inputs and outputs;
+//! - [`A2aAgent`]: wraps a remote A2A agent as a local agent, so that Anda engines
+//!   can delegate to agents of other frameworks.
+//!
+//! # Example
+//! ```rust,ignore
+//! let agent = A2aAgent::connect(&web3, "https://agent.example.com", None).await?;
+//! let engine = Engine::builder().register_agent(agent)?.build(default_agent).await?;
+//! ```
+
+use anda_core::{
+    Agent, AgentOutput, BoxError, ByteBufB64, FunctionDefinition, HttpFeatures, Resource,
+    StateFeatures, Xid,
+};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{collections::BTreeSet, time::Duration};
+
+use crate::context::AgentCtx;
+
+/// Path of the agent card, relative to the base URL of the agent.
+pub const A2A_CARD_PATH: &str = "/.well-known/agent.json";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const TASK_NOT_FOUND: i64 = -32001;
+pub const TASK_NOT_CANCELABLE: i64 = -32002;
+
+/// Interval between the polls of a task by a client without streaming.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A JSON-RPC 2.0 request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl JsonRpcRequest {
+    pub fn new(id: Value, method: &str, params: impl Serialize) -> Result<Self, BoxError> {
+        Ok(Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            method: method.to_string(),
+            params: serde_json::to_value(params)?,
+        })
+    }
+}
+
+/// A JSON-RPC 2.0 error.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// A JSON-RPC 2.0 response, with either a result or an error.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+impl JsonRpcResponse {
+    pub fn result(id: Value, result: impl Serialize) -> Self {
+        match serde_json::to_value(result) {
+            Ok(result) => Self {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(err) => Self::error(id, INTERNAL_ERROR, err.to_string()),
+        }
+    }
+
+    pub fn error(id: Value, code: i64, message: String) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message,
+                data: None,
+            }),
+        }
+    }
+
+    /// Returns the result, or the error as an error.
+    pub fn into_result<T: DeserializeOwned>(self) -> Result<T, BoxError> {
+        if let Some(err) = self.error {
+            return Err(format!("A2A error {}: {}", err.code, err.message).into());
+        }
+        let result = self.result.unwrap_or_default();
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+/// The agent card of an A2A agent, served at [`A2A_CARD_PATH`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct A2aAgentCard {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The URL of the JSON-RPC endpoint of the agent.
+    pub url: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub capabilities: A2aCapabilities,
+    /// Supported input MIME types, or "text", "file" and "data".
+    #[serde(default)]
+    pub default_input_modes: Vec<String>,
+    /// Supported output MIME types, or "text", "file" and "data".
+    #[serde(default)]
+    pub default_output_modes: Vec<String>,
+    #[serde(default)]
+    pub skills: Vec<A2aSkill>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct A2aCapabilities {
+    /// The agent supports `tasks/sendSubscribe`.
+    #[serde(default)]
+    pub streaming: bool,
+    #[serde(default)]
+    pub push_notifications: bool,
+    #[serde(default)]
+    pub state_transition_history: bool,
+}
+
+/// A skill of an A2A agent, an Anda agent in the cards served by Anda engines.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct A2aSkill {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// The state of a task.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskState {
+    Submitted,
+    Working,
+    InputRequired,
+    Completed,
+    Canceled,
+    Failed,
+    #[serde(other)]
+    Unknown,
+}
+
+impl TaskState {
+    /// Returns true if the task will not change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Completed | TaskState::Canceled | TaskState::Failed | TaskState::Unknown
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskStatus {
+    pub state: TaskState,
+    /// A message of the agent about the status, e.g. its answer or the input it requires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<A2aMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+impl TaskStatus {
+    pub fn new(state: TaskState) -> Self {
+        Self {
+            state,
+            message: None,
+            timestamp: None,
+        }
+    }
+}
+
+/// A unit of work of an agent, identified by the client.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    pub id: String,
+    /// The session of the task, the thread of the conversation in Anda engines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub status: TaskStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+/// A message between a user and an agent.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct A2aMessage {
+    /// "user" or "agent".
+    pub role: String,
+    pub parts: Vec<Part>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+impl A2aMessage {
+    /// Creates a message with the text and the resources as file parts.
+    pub fn new(role: &str, text: String, resources: Option<Vec<Resource>>) -> Self {
+        let mut parts = vec![Part::Text { text }];
+        parts.extend(resources.unwrap_or_default().iter().map(Part::from));
+        Self {
+            role: role.to_string(),
+            parts,
+            metadata: None,
+        }
+    }
+
+    /// Returns the text parts joined by new lines, with the data parts as JSON.
+    pub fn text(&self) -> String {
+        parts_text(&self.parts)
+    }
+
+    /// Returns the file parts as resources.
+    pub fn resources(&self) -> Option<Vec<Resource>> {
+        parts_resources(&self.parts)
+    }
+}
+
+/// A part of a message or an artifact.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Part {
+    Text { text: String },
+    File { file: FileContent },
+    Data { data: Value },
+}
+
+/// The content of a file, as base64 `bytes` or as an `uri`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+impl From<&Resource> for Part {
+    fn from(resource: &Resource) -> Self {
+        Part::File {
+            file: FileContent {
+                name: resource.name.clone(),
+                mime_type: resource.mime_type.clone(),
+                bytes: resource.blob.as_ref().map(|b| BASE64_STANDARD.encode(&b.0)),
+                uri: resource.uri.clone(),
+            },
+        }
+    }
+}
+
+impl From<&FileContent> for Resource {
+    fn from(file: &FileContent) -> Self {
+        let blob = file
+            .bytes
+            .as_ref()
+            .and_then(|b| BASE64_STANDARD.decode(b).ok());
+        Resource {
+            // tagged by the type of the MIME type, e.g. "image"
+            tag: file
+                .mime_type
+                .as_deref()
+                .and_then(|m| m.split('/').next())
+                .unwrap_or("file")
+                .to_string(),
+            uri: file.uri.clone(),
+            name: file.name.clone(),
+            mime_type: file.mime_type.clone(),
+            size: blob.as_ref().map(|b| b.len()),
+            blob: blob.map(ByteBufB64),
+            ..Default::default()
+        }
+    }
+}
+
+/// An output of a task.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parts: Vec<Part>,
+    #[serde(default)]
+    pub index: u32,
+    /// The parts are appended to the artifact with the same index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_chunk: Option<bool>,
+}
+
+impl From<&AgentOutput> for Artifact {
+    fn from(output: &AgentOutput) -> Self {
+        let mut parts = vec![Part::Text {
+            text: output.content.clone(),
+        }];
+        if let Some(resources) = &output.resources {
+            parts.extend(resources.iter().map(Part::from));
+        }
+        Artifact {
+            parts,
+            ..Default::default()
+        }
+    }
+}
+
+/// Parameters of `tasks/send` and `tasks/sendSubscribe`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSendParams {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub message: A2aMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_length: Option<u32>,
+    /// Anda engines take the name of the agent to run in `{"agent": "..."}`,
+    /// the default agent if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+impl TaskSendParams {
+    /// Returns the agent name from the metadata of the task or the message.
+    pub fn agent(&self) -> Option<&str> {
+        self.metadata
+            .as_ref()
+            .or(self.message.metadata.as_ref())
+            .and_then(|m| m.get("agent"))
+            .and_then(|v| v.as_str())
+    }
+}
+
+/// Parameters of `tasks/get` and `tasks/cancel`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskQueryParams {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_length: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskStatusUpdateEvent {
+    pub id: String,
+    pub status: TaskStatus,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskArtifactUpdateEvent {
+    pub id: String,
+    pub artifact: Artifact,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+/// An update of a task streamed by `tasks/sendSubscribe`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TaskUpdateEvent {
+    Status(TaskStatusUpdateEvent),
+    Artifact(TaskArtifactUpdateEvent),
+}
+
+impl Task {
+    /// Applies a streamed update to the task.
+    pub fn apply(&mut self, event: TaskUpdateEvent) {
+        match event {
+            TaskUpdateEvent::Status(ev) => self.status = ev.status,
+            TaskUpdateEvent::Artifact(ev) => {
+                let artifacts = self.artifacts.get_or_insert_with(Vec::new);
+                let existing = artifacts.iter_mut().find(|a| a.index == ev.artifact.index);
+                match existing {
+                    Some(a) if ev.artifact.append == Some(true) => {
+                        a.parts.extend(ev.artifact.parts)
+                    }
+                    Some(a) => *a = ev.artifact,
+                    None => artifacts.push(ev.artifact),
+                }
+            }
+        }
+    }
+
+    /// Converts the task of a remote agent to an agent output: the text of the artifacts
+    /// or of the status message, failed if the task failed or was canceled.
+    pub fn into_output(self) -> AgentOutput {
+        let parts: Vec<Part> = self
+            .artifacts
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|a| a.parts)
+            .collect();
+        let message = self.status.message.as_ref().map(|m| m.text());
+        let content = if parts.is_empty() {
+            message.clone().unwrap_or_default()
+        } else {
+            parts_text(&parts)
+        };
+        let failed_reason = match self.status.state {
+            TaskState::Failed | TaskState::Canceled | TaskState::Unknown => Some(
+                message
+                    .filter(|m| !m.is_empty())
+                    .unwrap_or_else(|| format!("task {} {:?}", self.id, self.status.state)),
+            ),
+            _ => None,
+        };
+        AgentOutput {
+            content,
+            failed_reason,
+            resources: parts_resources(&parts),
+            ..Default::default()
+        }
+    }
+}
+
+fn parts_text(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::Text { text } => Some(text.clone()),
+            Part::Data { data } => Some(data.to_string()),
+            Part::File { .. } => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parts_resources(parts: &[Part]) -> Option<Vec<Resource>> {
+    let resources: Vec<Resource> = parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::File { file } => Some(Resource::from(file)),
+            _ => None,
+        })
+        .collect();
+    if resources.is_empty() {
+        None
+    } else {
+        Some(resources)
+    }
+}
+
+/// Wraps a remote A2A agent as a local agent.
+///
+/// The prompt and resources are sent as a task in the session of the thread of the run,
+/// with `tasks/sendSubscribe` if the agent supports streaming, otherwise with `tasks/send`
+/// and polled with `tasks/get`. The task is canceled if the run is cancelled.
+/// Requests go through the HTTP policy of the engine, which must allow the agent's URL.
+#[derive(Debug, Clone)]
+pub struct A2aAgent {
+    name: String,
+    card: A2aAgentCard,
+    headers: http::HeaderMap,
+}
+
+impl A2aAgent {
+    /// Creates an agent from the card of the remote agent, named after the card if not set.
+    pub fn new(card: A2aAgentCard, name: Option<String>) -> Result<Self, BoxError> {
+        let name = name
+            .unwrap_or_else(|| {
+                card.name
+                    .to_ascii_lowercase()
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect()
+            })
+            .to_ascii_lowercase();
+        anda_core::validate_function_name(&name)
+            .map_err(|err| format!("invalid A2A agent name {:?}: {}", name, err))?;
+        Ok(Self {
+            name,
+            card,
+            headers: http::HeaderMap::new(),
+        })
+    }
+
+    /// Fetches the card of the remote agent at `{base_url}/.well-known/agent.json`.
+    pub async fn connect(
+        ctx: &impl HttpFeatures,
+        base_url: &str,
+        name: Option<String>,
+    ) -> Result<Self, BoxError> {
+        let url = format!("{}{}", base_url.trim_end_matches('/'), A2A_CARD_PATH);
+        let res = ctx.https_call(&url, http::Method::GET, None, None).await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(
+                format!("failed to fetch A2A agent card {}, status: {}", url, status).into(),
+            );
+        }
+        let card: A2aAgentCard = res.json().await?;
+        Self::new(card, name)
+    }
+
+    /// Adds a header to the requests, e.g. the credentials required by the remote agent.
+    pub fn with_header(mut self, name: http::HeaderName, value: http::HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn card(&self) -> &A2aAgentCard {
+        &self.card
+    }
+
+    async fn call(
+        &self,
+        ctx: &impl HttpFeatures,
+        method: &str,
+        params: impl Serialize,
+        accept: &'static str,
+    ) -> Result<reqwest::Response, BoxError> {
+        let req = JsonRpcRequest::new(Value::String(Xid::new().to_string()), method, params)?;
+        let mut headers = self.headers.clone();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/json"),
+        );
+        headers.insert(http::header::ACCEPT, http::HeaderValue::from_static(accept));
+        let res = ctx
+            .https_call(
+                &self.card.url,
+                http::Method::POST,
+                Some(headers),
+                Some(serde_json::to_vec(&req)?),
+            )
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(
+                format!("A2A {} failed, status: {}, body: {}", method, status, body).into(),
+            );
+        }
+        Ok(res)
+    }
+
+    async fn rpc<T: DeserializeOwned>(
+        &self,
+        ctx: &impl HttpFeatures,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<T, BoxError> {
+        let res = self.call(ctx, method, params, "application/json").await?;
+        let res: JsonRpcResponse = res.json().await?;
+        res.into_result()
+    }
+
+    /// Sends a task with `tasks/send`.
+    pub async fn send_task(
+        &self,
+        ctx: &impl HttpFeatures,
+        params: &TaskSendParams,
+    ) -> Result<Task, BoxError> {
+        self.rpc(ctx, "tasks/send", params).await
+    }
+
+    /// Gets a task with `tasks/get`.
+    pub async fn get_task(&self, ctx: &impl HttpFeatures, id: &str) -> Result<Task, BoxError> {
+        self.rpc(
+            ctx,
+            "tasks/get",
+            TaskQueryParams {
+                id: id.to_string(),
+                history_length: None,
+            },
+        )
+        .await
+    }
+
+    /// Cancels a task with `tasks/cancel`.
+    pub async fn cancel_task(&self, ctx: &impl HttpFeatures, id: &str) -> Result<Task, BoxError> {
+        self.rpc(
+            ctx,
+            "tasks/cancel",
+            TaskQueryParams {
+                id: id.to_string(),
+                history_length: None,
+            },
+        )
+        .await
+    }
+
+    /// Sends a task with `tasks/sendSubscribe`, calls `on_update` with each streamed update,
+    /// and returns the task when its final status is received.
+    pub async fn send_task_subscribe(
+        &self,
+        ctx: &impl HttpFeatures,
+        params: &TaskSendParams,
+        mut on_update: impl FnMut(&TaskUpdateEvent) + Send,
+    ) -> Result<Task, BoxError> {
+        let mut res = self
+            .call(ctx, "tasks/sendSubscribe", params, "text/event-stream")
+            .await?;
+        let mut task = Task {
+            id: params.id.clone(),
+            session_id: params.session_id.clone(),
+            status: TaskStatus::new(TaskState::Submitted),
+            artifacts: None,
+            metadata: None,
+        };
+        let mut parser = anda_core::SseParser::new();
+        while let Some(chunk) = res.chunk().await? {
+            for event in parser.feed(&chunk) {
+                let res: JsonRpcResponse = serde_json::from_str(&event.data)?;
+                let update: TaskUpdateEvent = res.into_result()?;
+                on_update(&update);
+                let is_final = matches!(&update, TaskUpdateEvent::Status(ev) if ev.is_final);
+                task.apply(update);
+                if is_final {
+                    return Ok(task);
+                }
+            }
+        }
+        Err(format!("A2A task {} stream ended before the final status", task.id).into())
+    }
+
+    /// Runs the task to its final status, or until it requires input.
+    async fn run_task(&self, ctx: &AgentCtx, params: &TaskSendParams) -> Result<Task, BoxError> {
+        if self.card.capabilities.streaming {
+            return self
+                .send_task_subscribe(ctx, params, |update| {
+                    let message = match update {
+                        TaskUpdateEvent::Status(ev) => ev.status.message.as_ref(),
+                        TaskUpdateEvent::Artifact(_) => None,
+                    };
+                    if let Some(message) = message {
+                        ctx.base.emit(anda_core::AgentEvent::Content {
+                            agent: self.name.clone(),
+                            content: message.text(),
+                        });
+                    }
+                })
+                .await;
+        }
+
+        let mut task = self.send_task(ctx, params).await?;
+        while !task.status.state.is_final() && task.status.state != TaskState::InputRequired {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            task = self.get_task(ctx, &params.id).await?;
+        }
+        Ok(task)
+    }
+}
+
+impl Agent<AgentCtx> for A2aAgent {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.card.description.clone()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name.clone(),
+            description: self.description(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "prompt": {
+                        "type": "string",
+                        "description": "The task for the agent.",
+                    },
+                },
+                "required": ["prompt"],
+            }),
+            strict: None,
+        }
+    }
+
+    /// The types of the MIME types accepted by the remote agent, e.g. "image".
+    fn supported_resource_tags(&self) -> Vec<String> {
+        let tags: BTreeSet<String> = self
+            .card
+            .default_input_modes
+            .iter()
+            .filter_map(|mode| mode.split_once('/').map(|(t, _)| t.to_string()))
+            .collect();
+        tags.into_iter().collect()
+    }
+
+    async fn run(
+        &self,
+        ctx: AgentCtx,
+        prompt: String,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        let params = TaskSendParams {
+            id: Xid::new().to_string(),
+            session_id: ctx.meta().thread.as_ref().map(|t| t.to_string()),
+            message: A2aMessage::new("user", prompt, resources),
+            history_length: None,
+            metadata: None,
+        };
+
+        let token = ctx.base.cancellation_token.clone();
+        let res = tokio::select! {
+            res = self.run_task(&ctx, &params) => res,
+            _ = token.cancelled() => {
+                // best effort, the remote agent may have finished the task already
+                let _ = self.cancel_task(&ctx, &params.id).await;
+                Err(format!("A2A task {} cancelled", params.id).into())
+            }
+        };
+        Ok(res?.into_output())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a2a_task() {
+        let params: TaskSendParams = serde_json::from_value(serde_json::json!({
+            "id": "task1",
+            "sessionId": "session1",
+            "message": {
+                "role": "user",
+                "parts": [
+                    {"type": "text", "text": "hello"},
+                    {"type": "file", "file": {"name": "a.txt", "mimeType": "text/plain", "bytes": "aGk="}},
+                ],
+                "metadata": {"agent": "assistant"},
+            },
+        }))
+        .unwrap();
+        assert_eq!(params.agent(), Some("assistant"));
+        assert_eq!(params.message.text(), "hello");
+        let resources = params.message.resources().unwrap();
+        assert_eq!(resources[0].tag, "text");
+        assert_eq!(resources[0].blob.as_ref().unwrap().0, b"hi".to_vec());
+
+        let mut task = Task {
+            id: params.id.clone(),
+            session_id: params.session_id.clone(),
+            status: TaskStatus::new(TaskState::Working),
+            artifacts: None,
+            metadata: None,
+        };
+        let update: TaskUpdateEvent = serde_json::from_value(serde_json::json!({
+            "id": "task1",
+            "artifact": {"parts": [{"type": "text", "text": "hi"}], "index": 0},
+        }))
+        .unwrap();
+        task.apply(update);
+        let update: TaskUpdateEvent = serde_json::from_value(serde_json::json!({
+            "id": "task1",
+            "artifact": {"parts": [{"type": "text", "text": "there"}], "index": 0, "append": true},
+        }))
+        .unwrap();
+        task.apply(update);
+        let update: TaskUpdateEvent = serde_json::from_value(serde_json::json!({
+            "id": "task1",
+            "status": {"state": "completed"},
+            "final": true,
+        }))
+        .unwrap();
+        assert!(matches!(&update, TaskUpdateEvent::Status(ev) if ev.is_final));
+        task.apply(update);
+        assert!(task.status.state.is_final());
+
+        let output = task.into_output();
+        assert_eq!(output.content, "hi\nthere");
+        assert!(output.failed_reason.is_none());
+
+        let task: Task = serde_json::from_value(serde_json::json!({
+            "id": "task2",
+            "status": {"state": "failed", "message": {"role": "agent", "parts": [{"type": "text", "text": "boom"}]}},
+        }))
+        .unwrap();
+        assert_eq!(task.into_output().failed_reason.as_deref(), Some("boom"));
+    }
+}
@@ -0,0 +1,213 @@
+//! API keys for callers that can't sign requests with an ICP identity,
+//! such as simple web backends and scripts.
+//!
+//! Each key is mapped to a pseudo-principal derived from its ID, so the callers holding
+//! a key go through the same authorization, rate limits and quotas as signed callers,
+//! and keep their identity in the audit log after the key is revoked.
+//! Only the SHA3-256 hash of a key is kept, persisted to the engine's [`Store`].
+
+use anda_core::{BoxError, ByteArrayB64, Path, PutMode, Xid};
+use candid::Principal;
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::RwLock};
+use structured_logger::unix_ms;
+use tokio::sync::Mutex;
+
+use crate::store::{Store, is_not_found};
+
+/// The store namespace of the API keys.
+pub static API_KEYS_PATH: &str = "_api_keys";
+
+/// The prefix of API keys, to tell them from signed envelopes.
+pub const API_KEY_PREFIX: &str = "anda_";
+
+/// Information about an API key, without the key.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    /// A label given by the issuer, e.g. the name of the backend.
+    pub name: String,
+    /// The pseudo-principal of the callers with the key.
+    pub principal: Principal,
+    pub created_by: Principal,
+    pub created_at_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ApiKeyRecord {
+    hash: ByteArrayB64<32>,
+    info: ApiKeyInfo,
+}
+
+/// Returns the pseudo-principal of an API key ID.
+pub fn api_key_principal(id: &str) -> Principal {
+    Principal::self_authenticating(format!("{API_KEY_PREFIX}{id}"))
+}
+
+fn keys_path() -> Path {
+    Path::from("keys")
+}
+
+/// The API keys of an engine.
+pub struct ApiKeys {
+    store: Store,
+    namespace: Path,
+    keys: RwLock<BTreeMap<[u8; 32], ApiKeyInfo>>,
+    // serializes the updates and their persistence
+    update: Mutex<()>,
+}
+
+impl ApiKeys {
+    /// Opens the API keys persisted in the store.
+    pub async fn open(store: Store) -> Result<Self, BoxError> {
+        let namespace = Path::from(API_KEYS_PATH);
+        let records: Vec<ApiKeyRecord> = match store.store_get(&namespace, &keys_path()).await {
+            Ok((data, _)) => ciborium::from_reader(&data[..])?,
+            Err(err) if is_not_found(&err) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            store,
+            namespace,
+            keys: RwLock::new(records.into_iter().map(|r| (r.hash.0, r.info)).collect()),
+            update: Mutex::new(()),
+        })
+    }
+
+    /// Issues a new API key, the key is only returned here.
+    pub async fn issue(
+        &self,
+        name: String,
+        created_by: Principal,
+    ) -> Result<(String, ApiKeyInfo), BoxError> {
+        let secret: [u8; 32] = rand::random();
+        let key = format!(
+            "{API_KEY_PREFIX}{}",
+            secret
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+        let id = Xid::new().to_string();
+        let info = ApiKeyInfo {
+            principal: api_key_principal(&id),
+            id,
+            name,
+            created_by,
+            created_at_ms: unix_ms(),
+            revoked_at_ms: None,
+        };
+
+        let _guard = self.update.lock().await;
+        let mut keys = self.keys.read().expect("api keys lock poisoned").clone();
+        keys.insert(sha3_256(key.as_bytes()), info.clone());
+        self.save(keys).await?;
+        Ok((key, info))
+    }
+
+    /// Revokes an API key by its ID, returns false if it is not found or already revoked.
+    pub async fn revoke(&self, id: &str) -> Result<bool, BoxError> {
+        let _guard = self.update.lock().await;
+        let mut keys = self.keys.read().expect("api keys lock poisoned").clone();
+        match keys
+            .values_mut()
+            .find(|info| info.id == id && info.revoked_at_ms.is_none())
+        {
+            Some(info) => info.revoked_at_ms = Some(unix_ms()),
+            None => return Ok(false),
+        }
+        self.save(keys).await?;
+        Ok(true)
+    }
+
+    /// Lists the API keys, including the revoked ones.
+    pub fn list(&self) -> Vec<ApiKeyInfo> {
+        let mut keys: Vec<ApiKeyInfo> = self
+            .keys
+            .read()
+            .expect("api keys lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_at_ms);
+        keys
+    }
+
+    /// Verifies an API key, returns its pseudo-principal if it is valid and not revoked.
+    pub fn verify(&self, key: &str) -> Option<Principal> {
+        if !key.starts_with(API_KEY_PREFIX) {
+            return None;
+        }
+        let keys = self.keys.read().expect("api keys lock poisoned");
+        match keys.get(&sha3_256(key.as_bytes())) {
+            Some(info) if info.revoked_at_ms.is_none() => Some(info.principal),
+            _ => None,
+        }
+    }
+
+    async fn save(&self, keys: BTreeMap<[u8; 32], ApiKeyInfo>) -> Result<(), BoxError> {
+        let records: Vec<ApiKeyRecord> = keys
+            .iter()
+            .map(|(hash, info)| ApiKeyRecord {
+                hash: ByteArrayB64(*hash),
+                info: info.clone(),
+            })
+            .collect();
+        self.store
+            .store_put(
+                &self.namespace,
+                &keys_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(&records).into(),
+            )
+            .await?;
+        *self.keys.write().expect("api keys lock poisoned") = keys;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_api_keys() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let manager = Principal::from_slice(&[1]);
+        let keys = ApiKeys::open(store.clone()).await.unwrap();
+        assert!(keys.list().is_empty());
+
+        let (key, info) = keys.issue("backend".to_string(), manager).await.unwrap();
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(info.principal, api_key_principal(&info.id));
+        assert_eq!(info.created_by, manager);
+        assert_eq!(keys.verify(&key), Some(info.principal));
+        assert_eq!(keys.verify("anda_invalid"), None);
+        assert_eq!(keys.verify(&key[API_KEY_PREFIX.len()..]), None);
+
+        let (key2, info2) = keys.issue("script".to_string(), manager).await.unwrap();
+        assert_ne!(info.principal, info2.principal);
+
+        // reopened from the store
+        let keys = ApiKeys::open(store).await.unwrap();
+        assert_eq!(keys.list().len(), 2);
+        assert_eq!(keys.verify(&key), Some(info.principal));
+
+        assert!(keys.revoke(&info.id).await.unwrap());
+        assert!(!keys.revoke(&info.id).await.unwrap());
+        assert!(!keys.revoke("unknown").await.unwrap());
+        assert_eq!(keys.verify(&key), None);
+        assert_eq!(keys.verify(&key2), Some(info2.principal));
+        let list = keys.list();
+        assert!(
+            list.iter()
+                .any(|k| k.id == info.id && k.revoked_at_ms.is_some())
+        );
+    }
+}
@@ -0,0 +1,415 @@
+//! Scanning of the attachments of agent runs.
+//!
+//! Callers may attach files to an agent run, which the agent may pass to tools and models.
+//! Engines built `with_attachment_scanning` check the attachments before the agent runs:
+//! the [`AttachmentPolicy`] bounds their number, size and MIME types, and checks that the
+//! declared MIME type matches the content, then each [`AttachmentScanner`], such as a
+//! malware scanner or an image safety classifier, may flag them.
+//!
+//! A flagged attachment is handled by the [`ViolationAction`] of the policy: the run is
+//! rejected with [`Error::Validation`], or the attachment is quarantined, that is removed from
+//! the run and saved to the engine's store under [`QUARANTINE_PATH`] for review.
+//!
+//! ```rust,ignore
+//! struct ClamAv { /* ... */ }
+//!
+//! #[async_trait]
+//! impl AttachmentScanner for ClamAv {
+//!     fn name(&self) -> &str {
+//!         "clamav"
+//!     }
+//!
+//!     async fn scan(&self, _caller: &Principal, attachment: &Resource) -> Result<ScanVerdict, BoxError> {
+//!         match self.scan_bytes(attachment.blob.as_ref().map_or(&[][..], |b| &b.0)).await? {
+//!             Some(virus) => Ok(ScanVerdict::Flagged(format!("malware {}", virus))),
+//!             None => Ok(ScanVerdict::Clean),
+//!         }
+//!     }
+//! }
+//!
+//! let engine = EngineBuilder::new().with_attachment_scanning(
+//!     AttachmentScanning::new(AttachmentPolicy {
+//!         max_size: Some(10 * 1024 * 1024),
+//!         allowed_mime_types: Some(vec!["image/*".to_string(), "application/pdf".to_string()]),
+//!         action: ViolationAction::Quarantine,
+//!         ..Default::default()
+//!     })
+//!     .with_scanner(Arc::new(ClamAv::new())),
+//! );
+//! ```
+
+use anda_core::{BoxError, Error, Path, PutMode, Resource, Xid};
+use async_trait::async_trait;
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use structured_logger::unix_ms;
+
+use crate::{context::wildcard_match, store::Store};
+
+/// The store namespace of the quarantined attachments.
+pub static QUARANTINE_PATH: &str = "_quarantine";
+
+/// What to do with an attachment that violates the policy or is flagged by a scanner.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationAction {
+    /// Rejects the run.
+    #[default]
+    Reject,
+    /// Removes the attachment from the run and saves it for review.
+    Quarantine,
+}
+
+/// The verdict of an [`AttachmentScanner`].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanVerdict {
+    Clean,
+    /// The attachment is unsafe, with the reason.
+    Flagged(String),
+}
+
+/// Scans an attachment, e.g. for malware or unsafe images.
+#[async_trait]
+pub trait AttachmentScanner: Send + Sync {
+    /// The name of the scanner, recorded with the quarantined attachments.
+    fn name(&self) -> &str;
+
+    /// Scans the attachment. The run is rejected if the scan fails.
+    async fn scan(
+        &self,
+        caller: &Principal,
+        attachment: &Resource,
+    ) -> Result<ScanVerdict, BoxError>;
+}
+
+/// Limits on the attachments of an agent run.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AttachmentPolicy {
+    /// The maximum number of attachments of a run, which is rejected otherwise.
+    pub max_count: Option<usize>,
+
+    /// The maximum size of an attachment in bytes.
+    pub max_size: Option<usize>,
+
+    /// The maximum total size of the attachments of a run, which is rejected otherwise.
+    pub max_total_size: Option<usize>,
+
+    /// The allowed MIME type patterns, e.g. `image/*`. An attachment without MIME type is
+    /// not allowed. None means all are allowed.
+    pub allowed_mime_types: Option<Vec<String>>,
+
+    /// Checks that the declared MIME type of the attachments matches the content,
+    /// for the formats recognized by [`sniff_mime_type`].
+    #[serde(default)]
+    pub check_content_type: bool,
+
+    /// What to do with the attachments that violate the policy or are flagged by a scanner.
+    #[serde(default)]
+    pub action: ViolationAction,
+}
+
+impl AttachmentPolicy {
+    /// Checks an attachment, returns the violation if any.
+    pub fn check(&self, attachment: &Resource) -> Option<String> {
+        let size = attachment_size(attachment);
+        if let Some(max) = self.max_size.filter(|max| size > *max) {
+            return Some(format!("size {} exceeds the limit of {} bytes", size, max));
+        }
+
+        if let Some(allowed) = &self.allowed_mime_types {
+            match &attachment.mime_type {
+                Some(mime) if allowed.iter().any(|p| wildcard_match(p, mime)) => {}
+                Some(mime) => return Some(format!("MIME type {} is not allowed", mime)),
+                None => return Some("MIME type is required".to_string()),
+            }
+        }
+
+        if self.check_content_type {
+            let sniffed = attachment.blob.as_ref().and_then(|b| sniff_mime_type(&b.0));
+            match (&attachment.mime_type, sniffed) {
+                (Some(mime), Some(sniffed)) if !mime.eq_ignore_ascii_case(sniffed) => {
+                    return Some(format!(
+                        "MIME type {} does not match the content {}",
+                        mime, sniffed
+                    ));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// A quarantined attachment, saved to the store for review.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuarantinedAttachment {
+    pub id: Xid,
+    pub caller: Principal,
+    pub agent: String,
+    /// The scanner that flagged the attachment, or "policy".
+    pub scanner: String,
+    pub reason: String,
+    pub attachment: Resource,
+    pub quarantined_at_ms: u64,
+}
+
+/// The attachment scanning of an engine: a policy and scanners.
+#[derive(Clone, Default)]
+pub struct AttachmentScanning {
+    policy: AttachmentPolicy,
+    scanners: Vec<Arc<dyn AttachmentScanner>>,
+}
+
+impl AttachmentScanning {
+    /// Creates the attachment scanning with the policy, without scanner.
+    pub fn new(policy: AttachmentPolicy) -> Self {
+        Self {
+            policy,
+            scanners: Vec::new(),
+        }
+    }
+
+    /// Adds a scanner, the scanners run in order.
+    pub fn with_scanner(mut self, scanner: Arc<dyn AttachmentScanner>) -> Self {
+        self.scanners.push(scanner);
+        self
+    }
+
+    /// Returns the policy.
+    pub fn policy(&self) -> &AttachmentPolicy {
+        &self.policy
+    }
+
+    /// Checks the attachments of a run to the agent, returns the attachments to pass to the
+    /// agent, without the quarantined ones, or an error if the run is rejected.
+    pub async fn check(
+        &self,
+        store: &Store,
+        caller: &Principal,
+        agent: &str,
+        attachments: Vec<Resource>,
+    ) -> Result<Vec<Resource>, BoxError> {
+        if let Some(max) = self.policy.max_count.filter(|max| attachments.len() > *max) {
+            return Err(Error::Validation(format!(
+                "attachments: {} attachments exceed the limit of {}",
+                attachments.len(),
+                max
+            ))
+            .into());
+        }
+        let total: usize = attachments.iter().map(attachment_size).sum();
+        if let Some(max) = self.policy.max_total_size.filter(|max| total > *max) {
+            return Err(Error::Validation(format!(
+                "attachments: total size {} exceeds the limit of {} bytes",
+                total, max
+            ))
+            .into());
+        }
+
+        let mut passed = Vec::with_capacity(attachments.len());
+        for attachment in attachments {
+            let mut flagged = self
+                .policy
+                .check(&attachment)
+                .map(|reason| ("policy".to_string(), reason));
+            if flagged.is_none() {
+                for scanner in &self.scanners {
+                    if let ScanVerdict::Flagged(reason) = scanner.scan(caller, &attachment).await? {
+                        flagged = Some((scanner.name().to_string(), reason));
+                        break;
+                    }
+                }
+            }
+
+            match flagged {
+                None => passed.push(attachment),
+                Some((_, reason)) if self.policy.action == ViolationAction::Reject => {
+                    return Err(Error::Validation(format!(
+                        "attachment {}: {}",
+                        attachment_name(&attachment),
+                        reason
+                    ))
+                    .into());
+                }
+                Some((scanner, reason)) => {
+                    let record = QuarantinedAttachment {
+                        id: Xid::new(),
+                        caller: *caller,
+                        agent: agent.to_string(),
+                        scanner,
+                        reason,
+                        attachment,
+                        quarantined_at_ms: unix_ms(),
+                    };
+                    log::warn!(
+                        caller = record.caller.to_text(),
+                        agent = agent,
+                        scanner = record.scanner.as_str();
+                        "attachment {} quarantined: {}",
+                        attachment_name(&record.attachment),
+                        record.reason
+                    );
+                    store
+                        .store_put(
+                            &Path::from(QUARANTINE_PATH),
+                            &Path::from(record.id.to_string()),
+                            PutMode::Create,
+                            to_cbor_bytes(&record).into(),
+                        )
+                        .await?;
+                }
+            }
+        }
+        Ok(passed)
+    }
+}
+
+fn attachment_size(attachment: &Resource) -> usize {
+    attachment
+        .blob
+        .as_ref()
+        .map(|b| b.0.len())
+        .or(attachment.size)
+        .unwrap_or(0)
+}
+
+fn attachment_name(attachment: &Resource) -> &str {
+    attachment
+        .name
+        .as_deref()
+        .or(attachment.uri.as_deref())
+        .unwrap_or(&attachment.tag)
+}
+
+/// Recognizes the MIME type of common image, document and archive formats
+/// from their magic bytes.
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"MZ", "application/x-msdownload"),
+        (b"\x7fELF", "application/x-executable"),
+    ];
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::ByteBufB64;
+    use object_store::memory::InMemory;
+
+    struct Eicar;
+
+    #[async_trait]
+    impl AttachmentScanner for Eicar {
+        fn name(&self) -> &str {
+            "eicar"
+        }
+
+        async fn scan(
+            &self,
+            _caller: &Principal,
+            attachment: &Resource,
+        ) -> Result<ScanVerdict, BoxError> {
+            match &attachment.blob {
+                Some(blob) if blob.0.starts_with(b"X5O!") => {
+                    Ok(ScanVerdict::Flagged("EICAR test file".to_string()))
+                }
+                _ => Ok(ScanVerdict::Clean),
+            }
+        }
+    }
+
+    fn attachment(name: &str, mime: &str, blob: &[u8]) -> Resource {
+        Resource {
+            tag: "file".to_string(),
+            name: Some(name.to_string()),
+            mime_type: Some(mime.to_string()),
+            blob: Some(ByteBufB64(blob.to_vec())),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_attachment_scanning() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let caller = Principal::anonymous();
+        let png = attachment("a.png", "image/png", b"\x89PNG\r\n\x1a\n....");
+        let fake = attachment("b.png", "image/png", b"MZ....");
+        let virus = attachment("c.png", "image/png", b"X5O!P%@AP");
+        let text = attachment("d.txt", "text/plain", b"hello");
+
+        let policy = AttachmentPolicy {
+            max_count: Some(3),
+            max_size: Some(16),
+            allowed_mime_types: Some(vec!["image/*".to_string()]),
+            check_content_type: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&png), None);
+        assert_eq!(
+            policy.check(&fake).unwrap(),
+            "MIME type image/png does not match the content application/x-msdownload"
+        );
+        assert_eq!(
+            policy.check(&text).unwrap(),
+            "MIME type text/plain is not allowed"
+        );
+        assert_eq!(
+            policy
+                .check(&attachment("e.png", "image/png", &[0u8; 17]))
+                .unwrap(),
+            "size 17 exceeds the limit of 16 bytes"
+        );
+
+        let scanning = AttachmentScanning::new(policy.clone()).with_scanner(Arc::new(Eicar));
+        let res = scanning
+            .check(&store, &caller, "a", vec![png.clone(), virus.clone()])
+            .await;
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "invalid attachment c.png: EICAR test file"
+        );
+        let res = scanning
+            .check(&store, &caller, "a", vec![png.clone(); 4])
+            .await;
+        assert!(
+            res.unwrap_err()
+                .to_string()
+                .contains("exceed the limit of 3")
+        );
+
+        let scanning = AttachmentScanning::new(AttachmentPolicy {
+            action: ViolationAction::Quarantine,
+            ..policy
+        })
+        .with_scanner(Arc::new(Eicar));
+        let passed = scanning
+            .check(&store, &caller, "a", vec![png.clone(), virus, fake])
+            .await
+            .unwrap();
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].name, png.name);
+
+        let quarantined = store
+            .store_list(&Path::from(QUARANTINE_PATH), None, &Path::from(""))
+            .await
+            .unwrap();
+        assert_eq!(quarantined.len(), 2);
+    }
+}
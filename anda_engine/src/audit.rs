@@ -0,0 +1,788 @@
+//! Append-only audit log.
+//!
+//! Sensitive operations are recorded with the caller identity and a timestamp:
+//! - Signing operations of the contexts;
+//! - Calls of audited tools, such as token transfers;
+//! - Store mutations;
+//! - Admin actions;
+//! - Every tool call and agent run if [`AuditConfig::calls`] is enabled, with the digests of
+//!   their arguments and results, their duration and usage, see [`CallRecord`].
+//!
+//! Each [`AuditEntry`] carries the hash of the previous one, so the log forms a hash chain
+//! persisted to the [`Store`], and [`AuditLog::verify`] detects altered or missing entries.
+//! Entries are written with [`PutMode::Create`], so the chain can never be forked.
+//! With an [`AuditSigner`], the hash of each entry is also signed by a key of the engine,
+//! so that the log can't be rewritten by whoever has access to the store.
+
+use anda_core::{BoxError, ByteArrayB64, Path, PutMode, Usage};
+use async_trait::async_trait;
+use candid::Principal;
+use futures::future::join_all;
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+use structured_logger::unix_ms;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    context::{Web3Client, Web3SDK},
+    store::{Store, is_not_found},
+};
+
+/// The store namespace of the audit log.
+pub static AUDIT_PATH: &str = "_audit";
+
+/// Maximum number of entries returned by [`AuditLog::export`].
+pub const MAX_EXPORT_ENTRIES: u64 = 1000;
+
+/// The kind of an audited operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Sign,
+    ToolCall,
+    StorePut,
+    StoreRename,
+    StoreDelete,
+    Admin,
+    /// Payments pulled from callers and their refunds.
+    Payment,
+    /// Completed tool calls, with a [`CallRecord`].
+    ToolCallEnd,
+    /// Completed agent runs, with a [`CallRecord`].
+    AgentRunEnd,
+}
+
+/// The detail of a completed tool call or agent run.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CallRecord {
+    /// SHA3-256 hash of the JSON arguments, or of the prompt and resources of an agent run.
+    pub args_sha3: ByteArrayB64<32>,
+    /// SHA3-256 hash of the JSON output, if it succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_sha3: Option<ByteArrayB64<32>>,
+    /// The redacted error, if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub usage: Usage,
+}
+
+/// Returns the SHA3-256 hash of the JSON encoded value.
+pub fn json_digest<T: Serialize>(value: &T) -> ByteArrayB64<32> {
+    ByteArrayB64(sha3_256(&serde_json::to_vec(value).unwrap_or_default()))
+}
+
+/// An entry of the audit log.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AuditEntry {
+    /// Sequence number, starting from 0.
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub caller: Principal,
+    /// The path of the context performing the operation, e.g. "T:icp_ledger_transfer".
+    pub path: String,
+    pub action: AuditAction,
+    /// The target of the operation, e.g. the tool name or the store path.
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+    /// Hash of the previous entry, zero for the first entry.
+    pub prev_hash: ByteArrayB64<32>,
+    /// SHA3-256 hash of the CBOR-encoded entry with a zero `hash` and no `signature`.
+    pub hash: ByteArrayB64<32>,
+    /// Signature of the `hash` by the [`AuditSigner`] of the log, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ByteArrayB64<64>>,
+}
+
+impl AuditEntry {
+    /// Computes the hash of the entry.
+    pub fn compute_hash(&self) -> [u8; 32] {
+        let mut entry = self.clone();
+        entry.hash = ByteArrayB64([0u8; 32]);
+        entry.signature = None;
+        sha3_256(&to_cbor_bytes(&entry))
+    }
+}
+
+/// Signs the hashes of the audit entries.
+#[async_trait]
+pub trait AuditSigner: Send + Sync {
+    /// Returns the public key verifying the signatures.
+    async fn public_key(&self) -> Result<[u8; 32], BoxError>;
+
+    /// Signs the hash of an entry.
+    async fn sign(&self, hash: &[u8; 32]) -> Result<[u8; 64], BoxError>;
+
+    /// Verifies the signature of the hash of an entry.
+    async fn verify(&self, hash: &[u8; 32], signature: &[u8; 64]) -> Result<(), BoxError>;
+}
+
+/// Signs the audit entries with an Ed25519 key of the engine, derived from [`AUDIT_PATH`].
+pub struct Web3AuditSigner {
+    web3: Arc<Web3SDK>,
+}
+
+impl Web3AuditSigner {
+    pub fn new(web3: Arc<Web3SDK>) -> Self {
+        Self { web3 }
+    }
+
+    fn derivation_path() -> Vec<Vec<u8>> {
+        vec![AUDIT_PATH.as_bytes().to_vec()]
+    }
+}
+
+#[async_trait]
+impl AuditSigner for Web3AuditSigner {
+    async fn public_key(&self) -> Result<[u8; 32], BoxError> {
+        match self.web3.as_ref() {
+            Web3SDK::Tee(cli) => cli.ed25519_public_key(Self::derivation_path()).await,
+            Web3SDK::Web3(Web3Client { client: cli }) => {
+                cli.ed25519_public_key(Self::derivation_path()).await
+            }
+        }
+    }
+
+    async fn sign(&self, hash: &[u8; 32]) -> Result<[u8; 64], BoxError> {
+        match self.web3.as_ref() {
+            Web3SDK::Tee(cli) => {
+                cli.ed25519_sign_message(Self::derivation_path(), hash)
+                    .await
+            }
+            Web3SDK::Web3(Web3Client { client: cli }) => {
+                cli.ed25519_sign_message(Self::derivation_path(), hash)
+                    .await
+            }
+        }
+    }
+
+    async fn verify(&self, hash: &[u8; 32], signature: &[u8; 64]) -> Result<(), BoxError> {
+        match self.web3.as_ref() {
+            Web3SDK::Tee(cli) => {
+                cli.ed25519_verify(Self::derivation_path(), hash, signature)
+                    .await
+            }
+            Web3SDK::Web3(Web3Client { client: cli }) => {
+                cli.ed25519_verify(Self::derivation_path(), hash, signature)
+                    .await
+            }
+        }
+    }
+}
+
+/// Result of [`AuditLog::verify`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditVerification {
+    /// Number of verified entries.
+    pub entries: u64,
+    /// Hash of the last verified entry, zero if there is none.
+    pub last_hash: ByteArrayB64<32>,
+    /// Number of entries with a verified signature.
+    #[serde(default)]
+    pub signed: u64,
+    /// The public key of the signer of the log, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<ByteArrayB64<32>>,
+}
+
+/// Configuration of the audit log.
+#[derive(Clone, Debug)]
+pub struct AuditConfig {
+    /// Names of the tools whose calls are audited, in addition to the `sys_` admin tools.
+    pub tools: BTreeSet<String>,
+    /// Records every tool call and agent run when they complete.
+    pub calls: bool,
+    /// Signs the entries with a key of the engine, see [`Web3AuditSigner`].
+    pub sign: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            tools: BTreeSet::from([
+                "icp_ledger_transfer".to_string(),
+                "bnb_ledger_transfer".to_string(),
+            ]),
+            calls: false,
+            sign: false,
+        }
+    }
+}
+
+impl AuditConfig {
+    /// Adds tools whose calls are audited.
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = String>) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    /// Records every tool call and agent run when they complete.
+    pub fn with_calls(mut self, calls: bool) -> Self {
+        self.calls = calls;
+        self
+    }
+
+    /// Signs the entries with a key of the engine.
+    pub fn with_signing(mut self, sign: bool) -> Self {
+        self.sign = sign;
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct AuditHead {
+    next_seq: u64,
+    last_hash: [u8; 32],
+}
+
+/// Capacity of the queue of the audit log writer.
+const WRITER_QUEUE: usize = 1024;
+/// Maximum number of entries appended in a batch.
+const MAX_BATCH: usize = 256;
+
+/// An operation of the audit log writer.
+enum AuditOp {
+    Record(
+        Box<AuditEntry>,
+        oneshot::Sender<Result<AuditEntry, BoxError>>,
+    ),
+    Flush(oneshot::Sender<Result<(), BoxError>>),
+}
+
+/// The hash-chained audit log persisted to the store.
+///
+/// Entries are appended by a writer task in batches: the callers queue their entries without
+/// contending on the head of the chain, the entries of a batch are signed concurrently and
+/// the head is saved once per batch.
+pub struct AuditLog {
+    writer: Arc<AuditWriter>,
+    tools: BTreeSet<String>,
+    calls: bool,
+    ops: mpsc::Sender<AuditOp>,
+}
+
+/// The state of the log shared with its writer task.
+struct AuditWriter {
+    store: Store,
+    namespace: Path,
+    signer: RwLock<Option<Arc<dyn AuditSigner>>>,
+    head: RwLock<AuditHead>,
+}
+
+fn entry_path(seq: u64) -> Path {
+    Path::from(format!("entries/{:020}", seq))
+}
+
+fn head_path() -> Path {
+    Path::from("head")
+}
+
+impl AuditLog {
+    /// Opens the audit log in the store, recovering entries appended after the last saved head.
+    pub async fn open(store: Store, cfg: AuditConfig) -> Result<Self, BoxError> {
+        let namespace = Path::from(AUDIT_PATH);
+        let mut head: AuditHead = match store.store_get(&namespace, &head_path()).await {
+            Ok((data, _)) => ciborium::from_reader(&data[..])?,
+            Err(err) if is_not_found(&err) => AuditHead::default(),
+            Err(err) => return Err(err),
+        };
+
+        // entries appended before the head was saved
+        loop {
+            let data = match store
+                .store_get(&namespace, &entry_path(head.next_seq))
+                .await
+            {
+                Ok((data, _)) => data,
+                Err(err) if is_not_found(&err) => break,
+                Err(err) => return Err(err),
+            };
+            let entry: AuditEntry = ciborium::from_reader(&data[..])?;
+            if entry.prev_hash.0 != head.last_hash || entry.compute_hash() != entry.hash.0 {
+                return Err(format!("audit entry {} is corrupted", entry.seq).into());
+            }
+            head.next_seq += 1;
+            head.last_hash = entry.hash.0;
+        }
+
+        let writer = Arc::new(AuditWriter {
+            store,
+            namespace,
+            signer: RwLock::new(None),
+            head: RwLock::new(head),
+        });
+        let (ops, rx) = mpsc::channel(WRITER_QUEUE);
+        // stops when the log is dropped
+        tokio::spawn(writer.clone().run(rx));
+
+        Ok(Self {
+            writer,
+            tools: cfg.tools,
+            calls: cfg.calls,
+            ops,
+        })
+    }
+
+    /// Signs the entries appended from now on with the signer.
+    pub fn with_signer(self, signer: Arc<dyn AuditSigner>) -> Self {
+        *self
+            .writer
+            .signer
+            .write()
+            .expect("audit signer lock poisoned") = Some(signer);
+        self
+    }
+
+    /// Returns true if calls of the tool are audited.
+    pub fn is_audited_tool(&self, name: &str) -> bool {
+        self.tools.contains(name) || name.starts_with("sys_")
+    }
+
+    /// Returns true if every tool call and agent run is recorded.
+    pub fn records_calls(&self) -> bool {
+        self.calls
+    }
+
+    /// Returns the number of entries.
+    pub async fn len(&self) -> u64 {
+        self.writer.head().next_seq
+    }
+
+    /// Returns true if there is no entry.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Appends an entry to the log.
+    pub async fn record(
+        &self,
+        caller: Principal,
+        path: &Path,
+        action: AuditAction,
+        target: String,
+        detail: Option<Value>,
+    ) -> Result<AuditEntry, BoxError> {
+        let entry = AuditEntry {
+            seq: 0,
+            timestamp_ms: 0,
+            caller,
+            path: path.to_string(),
+            action,
+            target,
+            detail,
+            prev_hash: ByteArrayB64([0u8; 32]),
+            hash: ByteArrayB64([0u8; 32]),
+            signature: None,
+        };
+        let (tx, rx) = oneshot::channel();
+        self.ops
+            .send(AuditOp::Record(Box::new(entry), tx))
+            .await
+            .map_err(|_| "audit log writer stopped")?;
+        rx.await.map_err(|_| "audit log writer stopped")?
+    }
+
+    /// Saves the head of the log, called on shutdown, after the entries queued before.
+    /// Entries are written as they are recorded, so only the head may lag behind.
+    pub async fn flush(&self) -> Result<(), BoxError> {
+        let (tx, rx) = oneshot::channel();
+        self.ops
+            .send(AuditOp::Flush(tx))
+            .await
+            .map_err(|_| "audit log writer stopped")?;
+        rx.await.map_err(|_| "audit log writer stopped")?
+    }
+
+    /// Gets an entry by its sequence number.
+    pub async fn get(&self, seq: u64) -> Result<AuditEntry, BoxError> {
+        let (data, _) = self
+            .writer
+            .store
+            .store_get(&self.writer.namespace, &entry_path(seq))
+            .await
+            .map_err(|err| format!("audit entry {} not found: {}", seq, err))?;
+        let entry: AuditEntry = ciborium::from_reader(&data[..])?;
+        Ok(entry)
+    }
+
+    /// Exports up to `limit` entries (at most [`MAX_EXPORT_ENTRIES`]) starting from `from_seq`.
+    pub async fn export(&self, from_seq: u64, limit: u64) -> Result<Vec<AuditEntry>, BoxError> {
+        let end = self
+            .len()
+            .await
+            .min(from_seq.saturating_add(limit.min(MAX_EXPORT_ENTRIES)));
+        let mut entries = Vec::new();
+        for seq in from_seq..end {
+            entries.push(self.get(seq).await?);
+        }
+        Ok(entries)
+    }
+
+    /// Verifies the hash chain of all entries, and their signatures if the log has a signer.
+    /// Returns an error describing the first altered or missing entry.
+    pub async fn verify(&self) -> Result<AuditVerification, BoxError> {
+        let len = self.len().await;
+        let signer = self.writer.signer();
+        let mut last_hash = [0u8; 32];
+        let mut signed = 0;
+        for seq in 0..len {
+            let entry = self.get(seq).await?;
+            if entry.seq != seq {
+                return Err(format!("audit entry {} has sequence {}", seq, entry.seq).into());
+            }
+            if entry.prev_hash.0 != last_hash {
+                return Err(
+                    format!("audit entry {} does not link to the previous entry", seq).into(),
+                );
+            }
+            if entry.compute_hash() != entry.hash.0 {
+                return Err(format!("audit entry {} has an invalid hash", seq).into());
+            }
+            // the entries appended before signing was enabled are not signed
+            if let (Some(signer), Some(signature)) = (&signer, &entry.signature) {
+                signer
+                    .verify(&entry.hash.0, &signature.0)
+                    .await
+                    .map_err(|err| {
+                        format!("audit entry {} has an invalid signature: {}", seq, err)
+                    })?;
+                signed += 1;
+            }
+            last_hash = entry.hash.0;
+        }
+        let public_key = match &signer {
+            Some(signer) => Some(ByteArrayB64(signer.public_key().await?)),
+            None => None,
+        };
+        Ok(AuditVerification {
+            entries: len,
+            last_hash: ByteArrayB64(last_hash),
+            signed,
+            public_key,
+        })
+    }
+}
+
+impl AuditWriter {
+    fn head(&self) -> AuditHead {
+        self.head.read().expect("audit head lock poisoned").clone()
+    }
+
+    fn signer(&self) -> Option<Arc<dyn AuditSigner>> {
+        self.signer
+            .read()
+            .expect("audit signer lock poisoned")
+            .clone()
+    }
+
+    async fn run(self: Arc<Self>, mut rx: mpsc::Receiver<AuditOp>) {
+        while let Some(op) = rx.recv().await {
+            let mut ops = vec![op];
+            while ops.len() < MAX_BATCH {
+                match rx.try_recv() {
+                    Ok(op) => ops.push(op),
+                    Err(_) => break,
+                }
+            }
+            self.process(ops).await;
+        }
+    }
+
+    /// Appends the entries of a batch in order. The batch is committed up to the first entry
+    /// that fails to be signed or written, the following entries fail too, so that the chain
+    /// never has a gap.
+    async fn process(&self, ops: Vec<AuditOp>) {
+        let mut records = Vec::with_capacity(ops.len());
+        let mut flushes = Vec::new();
+        for op in ops {
+            match op {
+                AuditOp::Record(entry, tx) => records.push((entry, tx)),
+                AuditOp::Flush(tx) => flushes.push(tx),
+            }
+        }
+
+        let mut head = self.head();
+        let now_ms = unix_ms();
+        for (entry, _) in records.iter_mut() {
+            entry.seq = head.next_seq;
+            entry.timestamp_ms = now_ms;
+            entry.prev_hash = ByteArrayB64(head.last_hash);
+            entry.hash = ByteArrayB64(entry.compute_hash());
+            head.next_seq += 1;
+            head.last_hash = entry.hash.0;
+        }
+
+        let signatures = match self.signer() {
+            Some(signer) => {
+                join_all(records.iter().map(|(entry, _)| signer.sign(&entry.hash.0))).await
+            }
+            None => Vec::new(),
+        };
+        let mut committed: Option<AuditHead> = None;
+        let mut failure: Option<String> = None;
+        for (i, (entry, tx)) in records.into_iter().enumerate() {
+            let mut entry = *entry;
+            if let Some(err) = &failure {
+                let _ = tx.send(Err(format!(
+                    "audit entry {} not appended after a failure: {}",
+                    entry.seq, err
+                )
+                .into()));
+                continue;
+            }
+
+            let res = match signatures.get(i) {
+                Some(Ok(signature)) => {
+                    entry.signature = Some(ByteArrayB64(*signature));
+                    Ok(())
+                }
+                Some(Err(err)) => Err(format!("failed to sign audit entry: {}", err).into()),
+                None => Ok(()),
+            };
+            let res = match res {
+                Ok(()) => self
+                    .store
+                    .store_put(
+                        &self.namespace,
+                        &entry_path(entry.seq),
+                        PutMode::Create,
+                        to_cbor_bytes(&entry).into(),
+                    )
+                    .await
+                    .map(|_| ()),
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(()) => {
+                    committed = Some(AuditHead {
+                        next_seq: entry.seq + 1,
+                        last_hash: entry.hash.0,
+                    });
+                    let _ = tx.send(Ok(entry));
+                }
+                Err(err) => {
+                    failure = Some(err.to_string());
+                    let _ = tx.send(Err(err));
+                }
+            }
+        }
+
+        if let Some(head) = committed {
+            *self.head.write().expect("audit head lock poisoned") = head;
+            // the entries are written, a head that lags behind is recovered when opening the log
+            if let Err(err) = self.save_head().await {
+                log::warn!("failed to save the audit log head: {}", err);
+            }
+        }
+        for tx in flushes {
+            let _ = tx.send(self.save_head().await);
+        }
+    }
+
+    async fn save_head(&self) -> Result<(), BoxError> {
+        let head = self.head();
+        self.store
+            .store_put(
+                &self.namespace,
+                &head_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(&head).into(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_audit_log() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let log = AuditLog::open(store.clone(), AuditConfig::default())
+            .await
+            .unwrap();
+        assert!(log.is_audited_tool("icp_ledger_transfer"));
+        assert!(log.is_audited_tool("sys_user_state"));
+        assert!(!log.is_audited_tool("google_web_search"));
+        assert!(log.is_empty().await);
+
+        let path = Path::from("T:icp_ledger_transfer");
+        let e0 = log
+            .record(
+                Principal::anonymous(),
+                &path,
+                AuditAction::ToolCall,
+                "icp_ledger_transfer".to_string(),
+                Some(json!({"amount": 1})),
+            )
+            .await
+            .unwrap();
+        let e1 = log
+            .record(
+                Principal::anonymous(),
+                &path,
+                AuditAction::Sign,
+                "ed25519".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(e0.seq, 0);
+        assert_eq!(e1.seq, 1);
+        assert_eq!(e1.prev_hash, e0.hash);
+
+        let res = log.verify().await.unwrap();
+        assert_eq!(res.entries, 2);
+        assert_eq!(res.last_hash, e1.hash);
+        assert_eq!(log.export(1, 10).await.unwrap(), vec![e1.clone()]);
+
+        // reopen and continue the chain
+        let log = AuditLog::open(store.clone(), AuditConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(log.len().await, 2);
+
+        // tamper an entry
+        let mut bad = e0.clone();
+        bad.target = "other".to_string();
+        store
+            .store_put(
+                &Path::from(AUDIT_PATH),
+                &entry_path(0),
+                PutMode::Overwrite,
+                to_cbor_bytes(&bad).into(),
+            )
+            .await
+            .unwrap();
+        assert!(
+            log.verify()
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("invalid hash")
+        );
+    }
+
+    struct MockSigner;
+
+    #[async_trait]
+    impl AuditSigner for MockSigner {
+        async fn public_key(&self) -> Result<[u8; 32], BoxError> {
+            Ok([7u8; 32])
+        }
+
+        async fn sign(&self, hash: &[u8; 32]) -> Result<[u8; 64], BoxError> {
+            let mut sig = [0u8; 64];
+            sig[..32].copy_from_slice(hash);
+            sig[32..].copy_from_slice(hash);
+            Ok(sig)
+        }
+
+        async fn verify(&self, hash: &[u8; 32], signature: &[u8; 64]) -> Result<(), BoxError> {
+            if signature == &self.sign(hash).await? {
+                Ok(())
+            } else {
+                Err("signature mismatch".into())
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_signed_calls() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let log = AuditLog::open(
+            store.clone(),
+            AuditConfig::default().with_calls(true).with_signing(true),
+        )
+        .await
+        .unwrap()
+        .with_signer(Arc::new(MockSigner));
+        assert!(log.records_calls());
+
+        let record = CallRecord {
+            args_sha3: json_digest(&json!({"query": "anda"})),
+            result_sha3: Some(json_digest(&json!("ok"))),
+            error: None,
+            duration_ms: 12,
+            usage: Usage::default(),
+        };
+        let entry = log
+            .record(
+                Principal::anonymous(),
+                &Path::from("T:web_search"),
+                AuditAction::ToolCallEnd,
+                "web_search".to_string(),
+                Some(json!(record)),
+            )
+            .await
+            .unwrap();
+        assert!(entry.signature.is_some());
+        let res = log.verify().await.unwrap();
+        assert_eq!(res.signed, 1);
+        assert_eq!(res.public_key, Some(ByteArrayB64([7u8; 32])));
+
+        // a forged signature is detected even though the hash chain is intact
+        let mut bad = entry.clone();
+        bad.signature = Some(ByteArrayB64([0u8; 64]));
+        assert_eq!(bad.compute_hash(), entry.hash.0);
+        store
+            .store_put(
+                &Path::from(AUDIT_PATH),
+                &entry_path(0),
+                PutMode::Overwrite,
+                to_cbor_bytes(&bad).into(),
+            )
+            .await
+            .unwrap();
+        assert!(
+            log.verify()
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("invalid signature")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_records() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let log = AuditLog::open(store.clone(), AuditConfig::default())
+            .await
+            .unwrap()
+            .with_signer(Arc::new(MockSigner));
+
+        let path = Path::from("T:web_search");
+        let entries = join_all((0..50).map(|i| {
+            log.record(
+                Principal::anonymous(),
+                &path,
+                AuditAction::ToolCall,
+                format!("call_{}", i),
+                None,
+            )
+        }))
+        .await;
+        let mut seqs: Vec<u64> = entries.into_iter().map(|e| e.unwrap().seq).collect();
+        seqs.sort_unstable();
+        assert_eq!(seqs, (0..50).collect::<Vec<_>>());
+
+        log.flush().await.unwrap();
+        let res = log.verify().await.unwrap();
+        assert_eq!(res.entries, 50);
+        assert_eq!(res.signed, 50);
+
+        let log = AuditLog::open(store, AuditConfig::default()).await.unwrap();
+        assert_eq!(log.len().await, 50);
+    }
+}
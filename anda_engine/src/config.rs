@@ -0,0 +1,1238 @@
+//! Engine configuration from TOML or YAML files.
+//!
+//! [`EngineConfig`] describes what can be changed without recompiling: engine identity,
+//! models and their prices, reranker, enabled tools, remote engines with their registry, health checks and RPC retries, cache limits, policies, audit log, API keys, tenants, rate limits, background jobs, usage metering, user quotas, conversation memory, history compaction, object store and its encryption, vector store, knowledge collections, feature flags and tracing.
+//! String values may reference environment variables as `${NAME}`, and API keys may be
+//! read from files with `api_key_file`, so that they are kept out of the file. Errors point at the offending key, e.g. `model.provider`.
+//!
+//! Tools and agents are still registered in code; the config only selects which ones are enabled.
+//!
+//! Policies, remote engines, export lists and feature flags can be changed at runtime with [`Engine::reload`],
+//! or by watching the file with [`watch_config_file`].
+//!
+//! # Example
+//! ```toml
+//! name = "Anda"
+//! visibility = "protected"
+//! export_tools = ["google_web_search"]
+//!
+//! [model]
+//! provider = "deepseek"
+//! api_key = "${DEEPSEEK_API_KEY}"
+//!
+//! [embedding]
+//! provider = "cohere"
+//! api_key = "${COHERE_API_KEY}"
+//! model = "embed-multilingual-v3.0"
+//!
+//! [rerank]
+//! provider = "cohere"
+//! api_key = "${COHERE_API_KEY}"
+//!
+//! [models.cheap]
+//! provider = "openai"
+//! api_key = "${OPENAI_API_KEY}"
+//! model = "gpt-4o-mini"
+//!
+//! [pricing]
+//! "deepseek-chat" = { input = 0.27, output = 1.1 }
+//! "gpt-4o-mini" = { input = 0.15, output = 0.6 }
+//!
+//! [tools]
+//! disabled = ["icp_ledger_transfer"]
+//! timeout_ms = 30000
+//!
+//! [tools.capabilities.web_reader]
+//! http = true
+//! sign = false
+//! store_write = false
+//! canister_update = false
+//! vector_write = false
+//!
+//! [[remote_engines]]
+//! endpoint = "https://remote.example.com/default"
+//!
+//! [registry]
+//! canister = "be2us-64aaa-aaaaa-qaabq-cai"
+//! endpoint = "https://anda.example.com/default"
+//!
+//! [canister_policy.allow]
+//! "ryjl3-tyaaa-aaaaa-aaaba-cai" = ["icrc1_transfer"]
+//!
+//! [http_policy]
+//! allow = ["api.example.com"]
+//!
+//! [store]
+//! backend = "s3"
+//! bucket = "anda"
+//! region = "us-east-1"
+//! secret_access_key_file = "/run/secrets/s3"
+//!
+//! [vector_store]
+//! provider = "qdrant"
+//! url = "http://localhost:6333"
+//! collection = "anda"
+//! ```
+//!
+//! ```rust,ignore
+//! let cfg = EngineConfig::from_file("engine.toml")?;
+//! let engine = Engine::builder()
+//!     .register_tools(tools)?
+//!     .register_agents(agents)?
+//!     .with_config(cfg)?
+//!     .build(default_agent)
+//!     .await?;
+//! ```
+
+use anda_core::{BoxError, Pricing, RpcRetryPolicy};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    audit::AuditConfig,
+    context::{
+        AccessList, AccessPolicy, CacheConfig, CanisterPolicy, Capabilities, DomainPolicy,
+        HttpPolicy, PolicyCanisterConfig, RemoteEngineArgs, RemoteHealthConfig, Tenant,
+        TenantQuota,
+    },
+    encryption::StoreEncryptionConfig,
+    engine::Engine,
+    flags::{FeatureFlag, validate_feature_flag},
+    history::HistoryConfig,
+    ingest::{ChunkStrategy, Ingestor},
+    jobs::JobsConfig,
+    management::Visibility,
+    memory::MemoryConfig,
+    metering::MeteringConfig,
+    model::{CompletionFeaturesDyn, Model, azure, cohere, deepseek, gemini, openai, xai},
+    payment::PaymentPolicy,
+    quota::QuotaConfig,
+    rate_limit::{RateLimitConfig, RateLimiter},
+    registry::RegistryConfig,
+    secrets::{REDACTED, SecretSource, redact, register_redaction},
+    store::{Store, StoreConfig},
+    telemetry::OtlpConfig,
+    vector::{QdrantConfig, VectorIndex, VectorStoreConfig},
+};
+
+/// Engine configuration, see the [module documentation](self) for an example.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngineConfig {
+    /// The engine ID, usually it comes from the TEE.
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// "private" (default), "protected" or "public".
+    pub visibility: Option<String>,
+    pub controller: Option<String>,
+    #[serde(default)]
+    pub managers: Vec<String>,
+    pub export_agents: Option<Vec<String>>,
+    pub export_tools: Option<Vec<String>>,
+    pub model: Option<CompletionConfig>,
+    /// Completion model of batch requests, such as background jobs.
+    pub batch_model: Option<CompletionConfig>,
+    /// Completion models by name, that requests may select with `CompletionRequest::model`.
+    pub models: Option<BTreeMap<String, CompletionConfig>>,
+    /// Prices of the models in USD per million tokens, by model name.
+    pub pricing: Option<Pricing>,
+    pub embedding: Option<EmbeddingConfig>,
+    /// Reranker of the retrieved documents.
+    pub rerank: Option<RerankConfig>,
+    pub tools: Option<ToolsConfig>,
+    pub remote_engines: Option<Vec<RemoteEngineConfig>>,
+    /// Registry canister of the engines, to discover remote engines and publish the engine.
+    pub registry: Option<RegistryConfig>,
+    /// Periodic health checks of the remote engines.
+    pub remote_health: Option<RemoteHealthConfig>,
+    /// Retries of the signed RPC calls to remote engines.
+    pub rpc_retry: Option<RpcRetryPolicy>,
+    /// Capacity, eviction policy and background sweeping of the caches of the agents and tools.
+    pub cache: Option<CacheConfig>,
+    pub canister_policy: Option<CanisterPolicyConfig>,
+    pub http_policy: Option<HttpPolicyConfig>,
+    pub access_policy: Option<AccessPolicyConfig>,
+    pub audit: Option<AuditLogConfig>,
+    /// OTLP exporter of the tracing spans, initialized by the application with
+    /// `telemetry::init_otlp_tracing` (`otlp` feature) before building the engine.
+    /// The builder warns if it is set and no tracing subscriber is installed.
+    pub otlp: Option<OtlpTracingConfig>,
+    /// Enables API keys for callers that can't sign requests.
+    #[serde(default)]
+    pub api_keys: bool,
+    pub tenants: Option<Vec<TenantConfig>>,
+    /// Limits the rate of the agent runs and tool calls of each caller.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Enables the background jobs of agents and tools.
+    pub jobs: Option<JobsConfig>,
+    /// Enables the usage metering of the callers.
+    pub metering: Option<MeteringConfig>,
+    /// Enables the daily and monthly usage quotas of the users.
+    pub quotas: Option<QuotaConfig>,
+    /// Enables the conversation memory of the agents.
+    pub memory: Option<MemoryConfig>,
+    /// Enables the compaction of the long chat histories.
+    pub history: Option<HistoryConfig>,
+    /// Prices of the agents and tools.
+    pub payments: Option<PaymentPolicy>,
+    /// Object store of the engine, in memory if absent.
+    pub store: Option<StoreConfig>,
+    /// Encryption at rest of the object store, enabled by default in a TEE.
+    pub store_encryption: Option<StoreEncryptionConfig>,
+    /// Vector store of the engine, an in-memory HNSW store if absent.
+    pub vector_store: Option<VectorStoreConfig>,
+    /// Enables the knowledge collections managed at runtime.
+    pub knowledge: Option<KnowledgeConfig>,
+    /// Feature flags by name, see [`crate::flags`].
+    pub feature_flags: Option<BTreeMap<String, FeatureFlag>>,
+}
+
+/// Completion model: "openai", "azure", "deepseek", "xai" or "gemini".
+///
+/// For "azure", `endpoint` is the resource endpoint and `model` the deployment name.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompletionConfig {
+    pub provider: String,
+    /// The API key, usually an environment reference such as `${OPENAI_API_KEY}`.
+    #[serde(default)]
+    pub api_key: String,
+    /// A file containing the API key, e.g. a mounted secret, instead of `api_key`.
+    pub api_key_file: Option<String>,
+    pub endpoint: Option<String>,
+    /// The provider's default model if empty.
+    #[serde(default)]
+    pub model: String,
+    /// The API version of "azure", [`azure::DEFAULT_API_VERSION`] if not set.
+    pub api_version: Option<String>,
+}
+
+impl fmt::Debug for CompletionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompletionConfig")
+            .field("provider", &self.provider)
+            .field("api_key", &REDACTED)
+            .field("api_key_file", &self.api_key_file)
+            .field("endpoint", &self.endpoint)
+            .field("model", &self.model)
+            .field("api_version", &self.api_version)
+            .finish()
+    }
+}
+
+/// Embedding model: "openai" or "cohere".
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddingConfig {
+    pub provider: String,
+    #[serde(default)]
+    pub api_key: String,
+    pub api_key_file: Option<String>,
+    pub endpoint: Option<String>,
+    pub model: String,
+}
+
+impl fmt::Debug for EmbeddingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbeddingConfig")
+            .field("provider", &self.provider)
+            .field("api_key", &REDACTED)
+            .field("api_key_file", &self.api_key_file)
+            .field("endpoint", &self.endpoint)
+            .field("model", &self.model)
+            .finish()
+    }
+}
+
+/// Rerank model: "cohere".
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RerankConfig {
+    pub provider: String,
+    #[serde(default)]
+    pub api_key: String,
+    pub api_key_file: Option<String>,
+    /// The provider's default model if empty.
+    #[serde(default)]
+    pub model: String,
+}
+
+impl fmt::Debug for RerankConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RerankConfig")
+            .field("provider", &self.provider)
+            .field("api_key", &REDACTED)
+            .field("api_key_file", &self.api_key_file)
+            .field("model", &self.model)
+            .finish()
+    }
+}
+
+/// Tools enablement, timeout and capabilities, applied to the registered tools.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// Only these tools are enabled if present.
+    pub enabled: Option<Vec<String>>,
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Timeout of each tool call in milliseconds, unless the tool has its own.
+    pub timeout_ms: Option<u64>,
+    /// Capabilities of the calls of the tools, all are granted to the tools not listed.
+    #[serde(default)]
+    pub capabilities: BTreeMap<String, Capabilities>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteEngineConfig {
+    pub endpoint: String,
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub tools: Vec<String>,
+    pub name: Option<String>,
+}
+
+/// Allowed methods by canister (or `*`), all update calls are allowed if absent.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanisterPolicyConfig {
+    #[serde(default)]
+    pub allow: BTreeMap<String, Vec<String>>,
+}
+
+/// Allowed tools and agents by caller and by end user, all are allowed if absent.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicyConfig {
+    /// The list of the callers without their own list.
+    pub default: Option<AccessList>,
+    /// Lists by caller principal text.
+    #[serde(default)]
+    pub callers: BTreeMap<String, AccessList>,
+    /// Lists by end user, restricting the lists of their callers.
+    #[serde(default)]
+    pub users: BTreeMap<String, AccessList>,
+    /// A policy canister serving the policy instead of the lists, see [`PolicyCanisterConfig`].
+    pub canister: Option<String>,
+    /// Interval between two refreshes of the policy of the canister, in seconds.
+    pub refresh_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DomainPolicyConfig {
+    /// All domains not denied are allowed if absent.
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpPolicyConfig {
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Policies by tool name, applied in addition to the engine policy.
+    #[serde(default)]
+    pub tools: BTreeMap<String, DomainPolicyConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogConfig {
+    /// Audited tools in addition to the default ones.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Records every tool call and agent run when they complete.
+    #[serde(default)]
+    pub calls: bool,
+    /// Signs the entries with an Ed25519 key of the engine.
+    #[serde(default)]
+    pub sign: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnowledgeConfig {
+    /// How the documents are split into chunks, sentences of 1000 characters by default.
+    #[serde(default)]
+    pub chunking: ChunkStrategy,
+    /// The number of chunks per embedding request.
+    pub batch_size: Option<usize>,
+}
+
+/// A tenant with its members, its own model sections and quotas.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub id: String,
+    /// Principals of the tenant's callers, including API key pseudo-principals.
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// The engine's model if not set.
+    pub model: Option<CompletionConfig>,
+    /// The engine's embedding model if not set.
+    pub embedding: Option<EmbeddingConfig>,
+    pub quota: Option<TenantQuota>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpTracingConfig {
+    pub service_name: String,
+    pub endpoint: Option<String>,
+    pub sample_ratio: Option<f64>,
+}
+
+/// Formats an error of the config key.
+fn key_err(key: &str, err: impl std::fmt::Display) -> BoxError {
+    format!("invalid config `{}`: {}", key, err).into()
+}
+
+/// Expands `${NAME}` references to environment variables in all string values.
+fn expand_env(value: &mut Value, key: &str) -> Result<(), BoxError> {
+    match value {
+        Value::String(s) => {
+            let mut out = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${") {
+                out.push_str(&rest[..start]);
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| key_err(key, "unclosed `${`"))?;
+                let name = &rest[start + 2..start + end];
+                let val = std::env::var(name).map_err(|_| {
+                    key_err(key, format!("environment variable {} is not set", name))
+                })?;
+                out.push_str(&val);
+                rest = &rest[start + end + 1..];
+            }
+            out.push_str(rest);
+            *s = out;
+        }
+        Value::Array(arr) => {
+            for (i, v) in arr.iter_mut().enumerate() {
+                expand_env(v, &format!("{}[{}]", key, i))?;
+            }
+        }
+        Value::Object(obj) => {
+            for (k, v) in obj.iter_mut() {
+                let key = if key.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", key, k)
+                };
+                expand_env(v, &key)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Resolves the API key of the model section from `api_key` or `api_key_file`,
+/// registering it for redaction.
+fn resolve_api_key(
+    section: &str,
+    api_key: &str,
+    api_key_file: &Option<String>,
+) -> Result<String, BoxError> {
+    let key = match api_key_file {
+        Some(_) if !api_key.is_empty() => {
+            return Err(key_err(
+                &format!("{}.api_key_file", section),
+                "api_key and api_key_file are mutually exclusive",
+            ));
+        }
+        Some(path) => SecretSource::File(path.clone())
+            .read_local()
+            .map_err(|err| key_err(&format!("{}.api_key_file", section), err))?,
+        None => api_key.to_string(),
+    };
+    if key.is_empty() {
+        return Err(key_err(&format!("{}.api_key", section), "missing API key"));
+    }
+    register_redaction(&key);
+    Ok(key)
+}
+
+fn parse_principal(key: &str, text: &str) -> Result<Principal, BoxError> {
+    Principal::from_text(text).map_err(|err| key_err(key, err))
+}
+
+/// Builds the completer of the model section at the key.
+fn build_completer(
+    key: &str,
+    cfg: &CompletionConfig,
+) -> Result<Arc<dyn CompletionFeaturesDyn>, BoxError> {
+    let api_key = resolve_api_key(key, &cfg.api_key, &cfg.api_key_file)?;
+    Ok(match cfg.provider.as_str() {
+        "openai" => Arc::new(
+            openai::Client::new(&api_key, cfg.endpoint.clone()).completion_model(&cfg.model),
+        ),
+        "azure" => {
+            let endpoint = cfg
+                .endpoint
+                .as_deref()
+                .ok_or_else(|| key_err(&format!("{}.endpoint", key), "missing endpoint"))?;
+            if cfg.model.is_empty() {
+                return Err(key_err(
+                    &format!("{}.model", key),
+                    "missing deployment name",
+                ));
+            }
+            let client = azure::Client::new(endpoint, azure::Auth::ApiKey(api_key));
+            let client = match &cfg.api_version {
+                Some(v) => client.with_api_version(v),
+                None => client,
+            };
+            Arc::new(client.completion_model(&cfg.model))
+        }
+        "deepseek" => Arc::new(
+            deepseek::Client::new(&api_key, cfg.endpoint.clone()).completion_model(&cfg.model),
+        ),
+        "xai" => {
+            Arc::new(xai::Client::new(&api_key, cfg.endpoint.clone()).completion_model(&cfg.model))
+        }
+        "gemini" => Arc::new(
+            gemini::Client::new(&api_key, cfg.endpoint.clone()).completion_model(&cfg.model),
+        ),
+        p => {
+            return Err(key_err(
+                &format!("{}.provider", key),
+                format!(
+                    "expected openai, azure, deepseek, xai or gemini, got {:?}",
+                    p
+                ),
+            ));
+        }
+    })
+}
+
+/// Builds a model from the `model` and `embedding` sections under the key prefix,
+/// the sections that are not set are kept from the base model.
+fn build_model(
+    prefix: &str,
+    mut model: Model,
+    completion: &Option<CompletionConfig>,
+    embedding: &Option<EmbeddingConfig>,
+) -> Result<Model, BoxError> {
+    if let Some(cfg) = completion {
+        model.completer = build_completer(&format!("{}model", prefix), cfg)?;
+    }
+    if let Some(cfg) = embedding {
+        let api_key = resolve_api_key(
+            &format!("{}embedding", prefix),
+            &cfg.api_key,
+            &cfg.api_key_file,
+        )?;
+        model.embedder = match cfg.provider.as_str() {
+            "openai" => Arc::new(
+                openai::Client::new(&api_key, cfg.endpoint.clone()).embedding_model(&cfg.model),
+            ),
+            "cohere" => Arc::new(cohere::Client::new(&api_key).embedding_model(&cfg.model)),
+            p => {
+                return Err(key_err(
+                    &format!("{}embedding.provider", prefix),
+                    format!("expected openai or cohere, got {:?}", p),
+                ));
+            }
+        };
+    }
+    Ok(model)
+}
+
+fn domain_policy(
+    key: &str,
+    allow: &Option<Vec<String>>,
+    deny: &[String],
+) -> Result<DomainPolicy, BoxError> {
+    let mut policy = match allow {
+        Some(_) => DomainPolicy::deny_all(),
+        None => DomainPolicy::allow_all(),
+    };
+    for (i, domain) in allow.iter().flatten().enumerate() {
+        policy = policy
+            .allow(domain)
+            .map_err(|err| key_err(&format!("{}.allow[{}]", key, i), err))?;
+    }
+    for (i, domain) in deny.iter().enumerate() {
+        policy = policy
+            .deny(domain)
+            .map_err(|err| key_err(&format!("{}.deny[{}]", key, i), err))?;
+    }
+    Ok(policy)
+}
+
+impl EngineConfig {
+    /// Parses a config from a JSON value, expanding environment variables.
+    pub fn from_value(mut value: Value) -> Result<Self, BoxError> {
+        expand_env(&mut value, "")?;
+        let cfg: Self = serde_path_to_error::deserialize(value).map_err(|err| {
+            let key = err.path().to_string();
+            key_err(&key, err.into_inner())
+        })?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Parses a config from TOML content.
+    pub fn from_toml(content: &str) -> Result<Self, BoxError> {
+        let value: Value = toml::from_str(content)?;
+        Self::from_value(value)
+    }
+
+    /// Parses a config from YAML content.
+    pub fn from_yaml(content: &str) -> Result<Self, BoxError> {
+        let value: Value = serde_yaml::from_str(content)?;
+        Self::from_value(value)
+    }
+
+    /// Loads a config from a `.toml`, `.yaml` or `.yml` file.
+    pub fn from_file(path: &str) -> Result<Self, BoxError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read config file {}: {}", path, err))?;
+        let res = if path.ends_with(".yaml") || path.ends_with(".yml") {
+            Self::from_yaml(&content)
+        } else {
+            Self::from_toml(&content)
+        };
+        res.map_err(|err| format!("{}: {}", path, err).into())
+    }
+
+    /// Validates the values, the typed accessors below can not fail afterwards.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        self.id()?;
+        self.visibility()?;
+        self.controller()?;
+        self.managers()?;
+        self.model()?;
+        self.batch_model(&Model::not_implemented())?;
+        self.models(&Model::not_implemented())?;
+        self.tenants(&Model::not_implemented())?;
+        if let Some(rate_limit) = &self.rate_limit {
+            RateLimiter::new(rate_limit.clone())
+                .map_err(|err| format!("invalid config `rate_limit`: {}", err))?;
+        }
+        self.canister_policy()?;
+        self.http_policy()?;
+        self.access_policy()?;
+        self.policy_canister()?;
+        self.vector_store()?;
+        Ok(())
+    }
+
+    pub fn id(&self) -> Result<Option<Principal>, BoxError> {
+        self.id
+            .as_deref()
+            .map(|id| parse_principal("id", id))
+            .transpose()
+    }
+
+    pub fn visibility(&self) -> Result<Option<Visibility>, BoxError> {
+        match self.visibility.as_deref() {
+            None => Ok(None),
+            Some("private") => Ok(Some(Visibility::Private)),
+            Some("protected") => Ok(Some(Visibility::Protected)),
+            Some("public") => Ok(Some(Visibility::Public)),
+            Some(v) => Err(key_err(
+                "visibility",
+                format!("expected private, protected or public, got {:?}", v),
+            )),
+        }
+    }
+
+    pub fn controller(&self) -> Result<Option<Principal>, BoxError> {
+        self.controller
+            .as_deref()
+            .map(|id| parse_principal("controller", id))
+            .transpose()
+    }
+
+    pub fn managers(&self) -> Result<BTreeSet<Principal>, BoxError> {
+        self.managers
+            .iter()
+            .enumerate()
+            .map(|(i, id)| parse_principal(&format!("managers[{}]", i), id))
+            .collect()
+    }
+
+    /// Builds the model from the `model`, `embedding` and `rerank` sections.
+    pub fn model(&self) -> Result<Option<Model>, BoxError> {
+        if self.model.is_none() && self.embedding.is_none() && self.rerank.is_none() {
+            return Ok(None);
+        }
+        let mut model = build_model("", Model::not_implemented(), &self.model, &self.embedding)?;
+        if let Some(cfg) = &self.rerank {
+            let api_key = resolve_api_key("rerank", &cfg.api_key, &cfg.api_key_file)?;
+            model = match cfg.provider.as_str() {
+                "cohere" => model.with_reranker(Arc::new(
+                    cohere::Client::new(&api_key).rerank_model(&cfg.model),
+                )),
+                p => {
+                    return Err(key_err(
+                        "rerank.provider",
+                        format!("expected cohere, got {:?}", p),
+                    ));
+                }
+            };
+        }
+        Ok(Some(model))
+    }
+
+    /// Builds the model of batch requests from the `batch_model` section,
+    /// the embeddings are kept from the given engine model.
+    pub fn batch_model(&self, model: &Model) -> Result<Option<Model>, BoxError> {
+        if self.batch_model.is_none() {
+            return Ok(None);
+        }
+        build_model("batch_", model.clone(), &self.batch_model, &None).map(Some)
+    }
+
+    /// Builds the named models from the `models` section,
+    /// the embeddings are kept from the given engine model.
+    pub fn models(&self, model: &Model) -> Result<BTreeMap<String, Model>, BoxError> {
+        let mut models = BTreeMap::new();
+        for (name, cfg) in self.models.iter().flatten() {
+            let mut named = model.clone();
+            named.completer = build_completer(&format!("models.{}", name), cfg)?;
+            named.fallbacks = Vec::new();
+            models.insert(name.clone(), named);
+        }
+        Ok(models)
+    }
+
+    /// Builds the tenants, their models default to the given engine model.
+    pub fn tenants(&self, model: &Model) -> Result<Vec<Tenant>, BoxError> {
+        let mut tenants = Vec::new();
+        for (i, cfg) in self.tenants.iter().flatten().enumerate() {
+            let key = format!("tenants[{}]", i);
+            let mut tenant = Tenant::new(cfg.id.clone()).map_err(|err| key_err(&key, err))?;
+            let mut members = Vec::with_capacity(cfg.members.len());
+            for (j, id) in cfg.members.iter().enumerate() {
+                members.push(parse_principal(&format!("{}.members[{}]", key, j), id)?);
+            }
+            tenant = tenant.with_members(members);
+            if cfg.model.is_some() || cfg.embedding.is_some() {
+                tenant = tenant.with_model(build_model(
+                    &format!("{}.", key),
+                    model.clone(),
+                    &cfg.model,
+                    &cfg.embedding,
+                )?);
+            }
+            if let Some(quota) = cfg.quota {
+                tenant = tenant.with_quota(quota);
+            }
+            tenants.push(tenant);
+        }
+        Ok(tenants)
+    }
+
+    pub fn remote_engines(&self) -> Vec<RemoteEngineArgs> {
+        self.remote_engines
+            .iter()
+            .flatten()
+            .map(|r| RemoteEngineArgs {
+                endpoint: r.endpoint.clone(),
+                agents: r.agents.clone(),
+                tools: r.tools.clone(),
+                name: r.name.clone(),
+            })
+            .collect()
+    }
+
+    pub fn canister_policy(&self) -> Result<Option<CanisterPolicy>, BoxError> {
+        let cfg = match &self.canister_policy {
+            Some(cfg) => cfg,
+            None => return Ok(None),
+        };
+        let mut policy = CanisterPolicy::deny_all();
+        for (canister, methods) in &cfg.allow {
+            for (i, method) in methods.iter().enumerate() {
+                policy = policy.allow(canister, method).map_err(|err| {
+                    key_err(&format!("canister_policy.allow.{}[{}]", canister, i), err)
+                })?;
+            }
+        }
+        Ok(Some(policy))
+    }
+
+    pub fn http_policy(&self) -> Result<Option<HttpPolicy>, BoxError> {
+        let cfg = match &self.http_policy {
+            Some(cfg) => cfg,
+            None => return Ok(None),
+        };
+        let mut policy = HttpPolicy::new(domain_policy("http_policy", &cfg.allow, &cfg.deny)?);
+        for (tool, p) in &cfg.tools {
+            policy = policy.with_tool(
+                tool,
+                domain_policy(&format!("http_policy.tools.{}", tool), &p.allow, &p.deny)?,
+            );
+        }
+        Ok(Some(policy))
+    }
+
+    pub fn access_policy(&self) -> Result<Option<AccessPolicy>, BoxError> {
+        let cfg = match &self.access_policy {
+            Some(cfg) => cfg,
+            None => return Ok(None),
+        };
+        let mut policy = AccessPolicy::allow_all();
+        if let Some(list) = &cfg.default {
+            policy = policy.with_default(list.clone());
+        }
+        for (caller, list) in &cfg.callers {
+            let caller = parse_principal(&format!("access_policy.callers.{}", caller), caller)?;
+            policy = policy.with_caller(caller, list.clone());
+        }
+        for (user, list) in &cfg.users {
+            policy = policy.with_user(user, list.clone());
+        }
+        Ok(Some(policy))
+    }
+
+    pub fn policy_canister(&self) -> Result<Option<PolicyCanisterConfig>, BoxError> {
+        let cfg = match &self.access_policy {
+            Some(cfg) => cfg,
+            None => return Ok(None),
+        };
+        let Some(canister) = &cfg.canister else {
+            return Ok(None);
+        };
+        let mut policy =
+            PolicyCanisterConfig::new(parse_principal("access_policy.canister", canister)?);
+        if let Some(secs) = cfg.refresh_secs {
+            policy = policy.with_refresh_secs(secs);
+        }
+        Ok(Some(policy))
+    }
+
+    /// Builds the object store from the `store` section.
+    pub fn store(&self) -> Result<Option<Store>, BoxError> {
+        match &self.store {
+            Some(cfg) => cfg
+                .clone()
+                .build()
+                .map(Some)
+                .map_err(|err| key_err("store", err)),
+            None => Ok(None),
+        }
+    }
+
+    /// Builds the vector store from the `vector_store` section.
+    pub fn vector_store(&self) -> Result<Option<VectorIndex>, BoxError> {
+        let cfg = match &self.vector_store {
+            Some(cfg) => cfg.clone(),
+            None => return Ok(None),
+        };
+        if let VectorStoreConfig::Qdrant(QdrantConfig {
+            api_key: Some(api_key),
+            ..
+        }) = &cfg
+        {
+            register_redaction(api_key);
+        }
+        cfg.build()
+            .map(Some)
+            .map_err(|err| key_err("vector_store", err))
+    }
+
+    pub fn audit(&self) -> Option<AuditConfig> {
+        self.audit.as_ref().map(|cfg| {
+            AuditConfig::default()
+                .with_tools(cfg.tools.clone())
+                .with_calls(cfg.calls)
+                .with_signing(cfg.sign)
+        })
+    }
+
+    pub fn knowledge(&self) -> Option<Ingestor> {
+        self.knowledge.as_ref().map(|cfg| {
+            let ingestor = Ingestor::new(cfg.chunking);
+            match cfg.batch_size {
+                Some(batch_size) => ingestor.with_batch_size(batch_size),
+                None => ingestor,
+            }
+        })
+    }
+
+    pub fn feature_flags(&self) -> Result<Option<BTreeMap<String, FeatureFlag>>, BoxError> {
+        let flags = match &self.feature_flags {
+            Some(flags) => flags,
+            None => return Ok(None),
+        };
+        for (name, flag) in flags {
+            validate_feature_flag(name, flag)
+                .map_err(|err| key_err(&format!("feature_flags.{}", name), err))?;
+        }
+        Ok(Some(flags.clone()))
+    }
+
+    pub fn otlp(&self) -> Option<OtlpConfig> {
+        self.otlp.as_ref().map(|cfg| {
+            let mut otlp = OtlpConfig::new(&cfg.service_name);
+            if let Some(endpoint) = &cfg.endpoint {
+                otlp = otlp.with_endpoint(endpoint);
+            }
+            if let Some(ratio) = cfg.sample_ratio {
+                otlp = otlp.with_sample_ratio(ratio);
+            }
+            otlp
+        })
+    }
+
+    /// Returns true if the tool is enabled by the `tools` section.
+    pub fn is_tool_enabled(&self, name: &str) -> bool {
+        match &self.tools {
+            None => true,
+            Some(cfg) => {
+                cfg.enabled
+                    .as_ref()
+                    .is_none_or(|enabled| enabled.iter().any(|t| t == name))
+                    && !cfg.disabled.iter().any(|t| t == name)
+            }
+        }
+    }
+}
+
+/// Watches the config file, polling its modification time at the interval,
+/// and reloads the engine when it changes, see [`Engine::reload`].
+/// An invalid config is logged and ignored, the engine keeps its current settings.
+pub fn watch_config_file(
+    engine: Engine,
+    path: String,
+    interval: Duration,
+    cancellation_token: CancellationToken,
+) -> tokio::task::JoinHandle<()> {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    tokio::spawn(async move {
+        let mut last = modified(&path);
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            let current = modified(&path);
+            if current.is_none() || current == last {
+                continue;
+            }
+            last = current;
+            let res = match EngineConfig::from_file(&path) {
+                Ok(cfg) => engine.reload(&cfg).await,
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(applied) => log::warn!(
+                    "engine {} reloaded {} from {}",
+                    engine.name(),
+                    applied.join(", "),
+                    path
+                ),
+                Err(err) => log::error!(
+                    "engine {} failed to reload config: {}",
+                    engine.name(),
+                    redact(&err.to_string())
+                ),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::CachePolicy;
+
+    #[test]
+    fn test_engine_config() {
+        unsafe {
+            std::env::set_var("ANDA_TEST_API_KEY", "sk-test");
+        }
+        let cfg = EngineConfig::from_toml(
+            r#"
+            name = "Anda"
+            visibility = "protected"
+            managers = ["aaaaa-aa"]
+            api_keys = true
+
+            [model]
+            provider = "deepseek"
+            api_key = "key-${ANDA_TEST_API_KEY}"
+
+            [batch_model]
+            provider = "openai"
+            api_key = "${ANDA_TEST_API_KEY}"
+            model = "gpt-4o-mini"
+
+            [pricing]
+            "gpt-4o-mini" = { input = 0.15, output = 0.6 }
+
+            [tools]
+            disabled = ["icp_ledger_transfer"]
+            timeout_ms = 30000
+
+            [tools.capabilities.web_reader]
+            sign = false
+            store_write = false
+
+            [audit]
+            tools = ["web_reader"]
+            calls = true
+            sign = true
+
+            [canister_policy.allow]
+            "ryjl3-tyaaa-aaaaa-aaaba-cai" = ["icrc1_*"]
+
+            [http_policy]
+            allow = ["example.com"]
+
+            [http_policy.tools.google_web_search]
+            allow = ["googleapis.com"]
+
+            [access_policy]
+            canister = "ryjl3-tyaaa-aaaaa-aaaba-cai"
+            refresh_secs = 30
+
+            [access_policy.default]
+            tools = ["google_*"]
+            agents = ["assistant"]
+
+            [access_policy.callers."aaaaa-aa"]
+            tools = ["*"]
+            agents = ["*"]
+
+            [[tenants]]
+            id = "acme"
+            members = ["aaaaa-aa"]
+            quota = { max_requests_per_day = 100 }
+
+            [rate_limit]
+            default = { burst = 10, per_minute = 60 }
+            anonymous = { burst = 2, per_minute = 6 }
+
+            [jobs]
+            max_concurrency = 2
+
+            [metering]
+            period_secs = 86400
+
+            [quotas]
+            daily = { max_tokens = 100000, max_tool_calls = 500 }
+            monthly = { max_cost_usd = 10.0 }
+
+            [memory]
+            max_messages = 20
+
+            [history]
+            max_tokens = 8000
+
+            [registry]
+            canister = "be2us-64aaa-aaaaa-qaabq-cai"
+
+            [remote_health]
+            interval_secs = 10
+
+            [rpc_retry]
+            max_retries = 3
+            idempotent_methods = ["information", "tool_call"]
+
+            [cache]
+            max_bytes = 268435456
+            policy = "lru"
+            sweep_interval_secs = 60
+            persist = true
+            persist_paths = ["T:web_search"]
+
+            [payments.agents.assistant]
+            ledger = "ryjl3-tyaaa-aaaaa-aaaba-cai"
+            amount = 100000
+
+            [store]
+            backend = "s3"
+            bucket = "anda"
+            endpoint = "http://localhost:9000"
+            secret_access_key = "s3-${ANDA_TEST_API_KEY}"
+
+            [store_encryption]
+            enabled = true
+            allow_plaintext = false
+
+            [vector_store]
+            provider = "qdrant"
+            url = "http://localhost:6333"
+            api_key = "qdrant-${ANDA_TEST_API_KEY}"
+            collection = "anda"
+
+            [knowledge.chunking.fixed]
+            size = 500
+            overlap = 50
+
+            [feature_flags.new_search]
+            enabled = true
+            tenants = ["acme"]
+            percentage = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.model.as_ref().unwrap().api_key, "key-sk-test");
+        assert!(!format!("{:?}", cfg).contains("key-sk-test"));
+        assert!(cfg.api_keys);
+        assert!(
+            cfg.batch_model(&Model::not_implemented())
+                .unwrap()
+                .is_some()
+        );
+        let pricing = cfg.pricing.as_ref().unwrap();
+        assert_eq!(pricing.price("gpt-4o-mini-2024-07-18").unwrap().output, 0.6);
+        let jobs = cfg.jobs.as_ref().unwrap();
+        assert_eq!(jobs.max_concurrency, 2);
+        assert_eq!(jobs.retention, 1000);
+        assert_eq!(cfg.metering.as_ref().unwrap().period_secs, 86400);
+        let quotas = cfg.quotas.as_ref().unwrap();
+        assert_eq!(quotas.daily.max_tool_calls, Some(500));
+        assert_eq!(quotas.monthly.max_cost_usd, Some(10.0));
+        assert_eq!(quotas.flush_secs, 60);
+        let rate_limit = cfg.rate_limit.as_ref().unwrap();
+        assert_eq!(rate_limit.default.unwrap().burst, 10);
+        assert_eq!(rate_limit.anonymous.unwrap().per_minute, 6);
+        assert_eq!(cfg.memory.unwrap().max_messages, 20);
+        assert_eq!(cfg.history.unwrap().max_tokens, 8000);
+        assert_eq!(cfg.history.unwrap().keep_recent, 6);
+        assert_eq!(cfg.registry.as_ref().unwrap().refresh_secs, 300);
+        let remote_health = cfg.remote_health.as_ref().unwrap();
+        assert_eq!(remote_health.interval_secs, 10);
+        assert_eq!(remote_health.failure_threshold, 2);
+        let rpc_retry = cfg.rpc_retry.as_ref().unwrap();
+        assert_eq!(rpc_retry.max_retries, 3);
+        assert!(rpc_retry.idempotent_methods.contains("tool_call"));
+        let cache = cfg.cache.as_ref().unwrap();
+        assert_eq!(cache.max_bytes, Some(268435456));
+        assert_eq!(cache.policy, CachePolicy::Lru);
+        assert_eq!(cache.sweep_interval(), Some(Duration::from_secs(60)));
+        assert!(cache.persists("T:web_search"));
+        assert!(!cache.persists("A:assistant"));
+        let payments = cfg.payments.as_ref().unwrap();
+        assert_eq!(payments.agent_price("assistant").unwrap().amount, 100000);
+        assert!(cfg.vector_store().unwrap().is_some());
+        assert!(matches!(
+            &cfg.store,
+            Some(StoreConfig::S3(s3)) if s3.secret_access_key.as_deref() == Some("s3-sk-test")
+        ));
+        assert!(!format!("{:?}", cfg.store).contains("s3-sk-test"));
+        let encryption = cfg.store_encryption.as_ref().unwrap();
+        assert_eq!(encryption.enabled, Some(true));
+        assert!(!encryption.allow_plaintext);
+        assert_eq!(
+            cfg.knowledge.as_ref().unwrap().chunking,
+            ChunkStrategy::Fixed {
+                size: 500,
+                overlap: 50
+            }
+        );
+        assert!(!format!("{:?}", cfg).contains("qdrant-sk-test"));
+        let flags = cfg.feature_flags().unwrap().unwrap();
+        assert_eq!(flags["new_search"].percentage, 10);
+        let tenants = cfg.tenants(&Model::not_implemented()).unwrap();
+        assert_eq!(tenants.len(), 1);
+        assert_eq!(tenants[0].id(), "acme");
+        assert!(tenants[0].is_member(&Principal::management_canister()));
+        assert!(cfg.visibility().unwrap() == Some(Visibility::Protected));
+        assert!(cfg.is_tool_enabled("google_web_search"));
+        assert!(!cfg.is_tool_enabled("icp_ledger_transfer"));
+        assert_eq!(cfg.tools.as_ref().unwrap().timeout_ms, Some(30000));
+        let caps = cfg.tools.as_ref().unwrap().capabilities["web_reader"];
+        assert!(caps.http && !caps.sign && !caps.store_write);
+        let audit = cfg.audit().unwrap();
+        assert!(audit.tools.contains("web_reader") && audit.calls && audit.sign);
+        let policy = cfg.http_policy().unwrap().unwrap();
+        assert!(policy.is_allowed(None, "api.example.com"));
+        assert!(!policy.is_allowed(Some("google_web_search"), "example.com"));
+        let policy = cfg.access_policy().unwrap().unwrap();
+        assert!(policy.is_tool_allowed(
+            &Principal::management_canister(),
+            None,
+            "icp_ledger_transfer"
+        ));
+        assert!(!policy.is_tool_allowed(&Principal::anonymous(), None, "icp_ledger_transfer"));
+        assert!(policy.is_agent_allowed(&Principal::anonymous(), None, "assistant"));
+        let policy = cfg.policy_canister().unwrap().unwrap();
+        assert_eq!(policy.refresh_interval(), Duration::from_secs(30));
+        let policy = cfg.canister_policy().unwrap().unwrap();
+        assert!(policy.is_allowed(
+            &Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap(),
+            "icrc1_transfer"
+        ));
+
+        let yaml = EngineConfig::from_yaml(
+            r#"
+            name: Anda
+            embedding:
+              provider: cohere
+              api_key: ${ANDA_TEST_API_KEY}
+              model: embed-multilingual-v3.0
+            rerank:
+              provider: cohere
+              api_key: ${ANDA_TEST_API_KEY}
+            "#,
+        )
+        .unwrap();
+        assert_eq!(yaml.embedding.as_ref().unwrap().api_key, "sk-test");
+        assert!(yaml.model().unwrap().unwrap().reranker.is_some());
+
+        let err = EngineConfig::from_toml("[model]\nprovider = \"x\"\napi_key = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`model.provider`"), "{}", err);
+        let err = EngineConfig::from_toml("[batch_model]\nprovider = \"x\"\napi_key = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`batch_model.provider`"), "{}", err);
+        let err = EngineConfig::from_toml("[rerank]\nprovider = \"x\"\napi_key = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`rerank.provider`"), "{}", err);
+        let err = EngineConfig::from_toml("[model]\nprovider = \"xai\"\napi_keys = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("model"), "{}", err);
+        let err =
+            EngineConfig::from_toml("[model]\nprovider = \"xai\"\napi_key = \"${ANDA_MISSING}\"")
+                .unwrap_err()
+                .to_string();
+        assert!(err.contains("`model.api_key`"), "{}", err);
+        let err = EngineConfig::from_toml("[model]\nprovider = \"xai\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`model.api_key`"), "{}", err);
+        let err = EngineConfig::from_toml("[models.cheap]\nprovider = \"x\"\napi_key = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`models.cheap.provider`"), "{}", err);
+        let err = EngineConfig::from_toml("[model]\nprovider = \"azure\"\napi_key = \"k\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`model.endpoint`"), "{}", err);
+        EngineConfig::from_toml(
+            "[model]\nprovider = \"azure\"\napi_key = \"k\"\nendpoint = \"https://r.openai.azure.com\"\nmodel = \"gpt-4o\"\napi_version = \"2025-01-01-preview\"",
+        )
+        .unwrap();
+        let err = EngineConfig::from_toml("[http_policy]\ndeny = [\"ok.com\", \"\"]")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`http_policy.deny[1]`"), "{}", err);
+    }
+}
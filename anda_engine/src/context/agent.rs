@@ -17,6 +17,7 @@
 //! - [`EmbeddingFeatures`]: Text embedding generation;
 //! - [`StateFeatures`]: Context state management;
 //! - [`KeysFeatures`]: Cryptographic key operations;
+//! - [`LogFeatures`]: Structured logging tagged with the context;
 //! - [`StoreFeatures`]: Persistent storage operations;
 //! - [`CacheFeatures`]: Caching mechanisms;
 //! - [`CanisterCaller`]: Canister interaction capabilities;
@@ -26,20 +27,47 @@
 //! agents or tools while maintaining access to the core functionality.
 
 use anda_core::{
-    AgentArgs, AgentContext, AgentInput, AgentOutput, AgentSet, BaseContext, BoxError, CacheExpiry,
-    CacheFeatures, CacheStoreFeatures, CancellationToken, CanisterCaller, CompletionFeatures,
-    CompletionRequest, Embedding, EmbeddingFeatures, FunctionDefinition, HttpFeatures,
-    KeysFeatures, Message, ObjectMeta, Path, PutMode, PutResult, RequestMeta, Resource,
-    StateFeatures, StoreFeatures, ToolCall, ToolInput, ToolOutput, ToolSet, Usage, Value,
+    AgentArgs, AgentContext, AgentEvent, AgentInput, AgentOutput, AgentSet, BaseContext, BoxError,
+    ByteArrayB64, CacheExpiry, CacheFeatures, CacheStoreFeatures, CallTrace, CancellationToken,
+    CanisterCaller, CompletionFeatures, CompletionRequest, DEFAULT_MAX_TOOL_ROUNDS, Embedding,
+    EmbeddingFeatures, Error, Extensions, FunctionDefinition, HttpFeatures, HttpOptions,
+    KeysFeatures, LogFeatures, LogLevel, Message, ObjectMeta, Path, Pricing, PutMode, PutResult,
+    RequestMeta, Resource, StateFeatures, StoreFeatures, StoreListOptions, StoreListPage, ToolCall,
+    ToolInput, ToolOutput, ToolSet, TraceStep, Usage, Value, VectorDocument, VectorFilter,
+    VectorMatch, VectorStoreFeatures, WebSocket, WsOptions, anda_error,
 };
 use bytes::Bytes;
 use candid::{CandidType, Principal, utils::ArgumentEncoder};
+use futures::{FutureExt, Stream, StreamExt, future::BoxFuture, stream::BoxStream};
+use ic_cose_types::cose::sha3_256;
 use serde::{Serialize, de::DeserializeOwned};
 use serde_json::json;
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    ops::Range,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::Instrument;
 
-use super::{base::BaseCtx, engine::RemoteEngines};
-use crate::{management::Management, model::Model};
+use super::{
+    base::{BaseCtx, remote_error},
+    engine::RemoteEngines,
+};
+use crate::{
+    audit::AuditAction,
+    history::{HistoryConfig, summary_message, summary_request},
+    knowledge::{KnowledgeCollection, KnowledgeScope},
+    management::Management,
+    memory::{Memory, MemoryConfig},
+    middleware::AgentHooks,
+    model::{Model, is_failover_error},
+    retrieval::Retriever,
+    secrets::redact,
+    telemetry::record_usage,
+};
 
 pub static DYNAMIC_REMOTE_ENGINES: &str = "_engines";
 
@@ -50,10 +78,22 @@ pub struct AgentCtx {
     pub base: BaseCtx,
     /// AI model used for completions and embeddings.
     pub(crate) model: Model,
+    /// AI model used for the completions of batch requests, the main model if not set.
+    pub(crate) batch_model: Option<Model>,
+    /// AI models by name, selected by [`CompletionRequest::model`].
+    pub(crate) models: Arc<BTreeMap<String, Model>>,
+    /// Prices of the models, to estimate the cost of the completions.
+    pub(crate) pricing: Arc<Pricing>,
     /// Set of available tools that can be called.
     pub(crate) tools: Arc<ToolSet<BaseCtx>>,
     /// Set of available agents that can be invoked.
     pub(crate) agents: Arc<AgentSet<AgentCtx>>,
+    /// Conversation memory of the threads, disabled if not set.
+    pub(crate) memory: Option<MemoryConfig>,
+    /// Compaction of the long chat histories, disabled if not set.
+    pub(crate) history: Option<HistoryConfig>,
+    /// Hooks around the runs of the local agents.
+    pub(crate) agent_hooks: Arc<AgentHooks>,
 
     management: Arc<Management>,
 }
@@ -76,8 +116,14 @@ impl AgentCtx {
         Self {
             base,
             model,
+            batch_model: None,
+            models: Arc::new(BTreeMap::new()),
+            pricing: Arc::new(Pricing::default()),
             tools,
             agents,
+            memory: None,
+            history: None,
+            agent_hooks: Arc::new(AgentHooks::default()),
             management,
         }
     }
@@ -90,8 +136,14 @@ impl AgentCtx {
         Ok(Self {
             base: self.base.child(format!("A:{}", agent_name))?,
             model: self.model.clone(),
+            batch_model: self.batch_model.clone(),
+            models: self.models.clone(),
+            pricing: self.pricing.clone(),
             tools: self.tools.clone(),
             agents: self.agents.clone(),
+            memory: self.memory,
+            history: self.history,
+            agent_hooks: self.agent_hooks.clone(),
             management: self.management.clone(),
         })
     }
@@ -116,13 +168,31 @@ impl AgentCtx {
         agent_name: &str,
         meta: RequestMeta,
     ) -> Result<Self, BoxError> {
+        let base = self
+            .base
+            .child_with(caller, format!("A:{}", agent_name), meta)?;
+        // the caller's tenant may bring its own model, batch requests may have theirs
+        let model = base
+            .tenant
+            .as_ref()
+            .and_then(|tenant| tenant.model.clone())
+            .or_else(|| {
+                self.batch_model
+                    .clone()
+                    .filter(|_| !base.meta.priority.is_interactive())
+            })
+            .unwrap_or_else(|| self.model.clone());
         Ok(Self {
-            base: self
-                .base
-                .child_with(caller, format!("A:{}", agent_name), meta)?,
-            model: self.model.clone(),
+            base,
+            model,
+            batch_model: self.batch_model.clone(),
+            models: self.models.clone(),
+            pricing: self.pricing.clone(),
             tools: self.tools.clone(),
             agents: self.agents.clone(),
+            memory: self.memory,
+            history: self.history,
+            agent_hooks: self.agent_hooks.clone(),
             management: self.management.clone(),
         })
     }
@@ -142,6 +212,253 @@ impl AgentCtx {
         self.base
             .child_with(caller, format!("T:{}", tool_name), meta)
     }
+
+    /// Gets the request-scoped extensions to modify them, see [`StateFeatures::extensions`].
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.base.extensions
+    }
+
+    /// Returns the memory of the agent for the thread of the request and the caller,
+    /// None if the memory is disabled or the request has no thread.
+    pub fn memory(&self) -> Option<Memory> {
+        let config = self.memory?;
+        let thread = self.base.meta.thread.clone()?;
+        Some(Memory::new(
+            self.base.clone(),
+            &self.base.caller,
+            thread,
+            config,
+        ))
+    }
+
+    /// Creates a retriever returning the top K documents, with the model's reranker if any.
+    /// Use it with this context as the embedder and the vector store.
+    pub fn retriever(&self, top_k: usize) -> Retriever {
+        let retriever = Retriever::new(top_k);
+        match &self.model.reranker {
+            Some(reranker) => retriever.with_reranker(reranker.clone()),
+            None => retriever,
+        }
+    }
+
+    /// Returns a knowledge collection visible to the caller, by name, searched in the scopes
+    /// of the caller, of its tenant and of the engine, in that order.
+    /// Use it with [`AgentCtx::retriever`] as the vector store.
+    pub fn knowledge(&self, name: &str) -> Option<KnowledgeCollection> {
+        let kb = self.base.knowledge.as_ref()?;
+        let mut scopes = vec![KnowledgeScope::User(self.base.caller)];
+        if let Some(tenant) = &self.base.tenant {
+            scopes.push(KnowledgeScope::Tenant(tenant.id().to_string()));
+        }
+        scopes.push(KnowledgeScope::Engine);
+        scopes.iter().find_map(|scope| kb.collection(scope, name))
+    }
+
+    /// Runs a retrieval-augmented completion in one call: retrieves the chunks relevant to
+    /// the prompt with the retriever, from the knowledge collection by name if any, otherwise
+    /// from the engine's vector store, adds them to the request documents, and runs the
+    /// [`CompletionFeatures::completion`] loop.
+    /// The output usage includes the embedding of the prompt.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let output = ctx
+    ///     .completion_with_knowledge(req, None, Some("docs"), &ctx.retriever(5))
+    ///     .await?;
+    /// ```
+    pub async fn completion_with_knowledge(
+        &self,
+        mut req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+        knowledge: Option<&str>,
+        retriever: &Retriever,
+    ) -> Result<AgentOutput, BoxError> {
+        let mut usage = Usage::default();
+        if !req.prompt.trim().is_empty() {
+            let (matches, u) = match knowledge {
+                Some(name) => {
+                    let collection = self
+                        .knowledge(name)
+                        .ok_or_else(|| Error::NotFound(format!("knowledge collection {}", name)))?;
+                    retriever.retrieve(self, &collection, &req.prompt).await?
+                }
+                None => retriever.retrieve(self, self, &req.prompt).await?,
+            };
+            usage = u;
+            req.documents.0.extend(Retriever::documents(matches).0);
+        }
+
+        let mut output = self.completion(req, resources).await?;
+        output.usage.accumulate(&usage);
+        Ok(output)
+    }
+
+    /// Calls the model of the request, failing over to its fallback models in order when it is
+    /// rate limited, unavailable or times out, and records the model that produced the output
+    /// and the estimated cost of its usage.
+    async fn model_completion(
+        &self,
+        agent: &str,
+        req: CompletionRequest,
+    ) -> Result<AgentOutput, BoxError> {
+        let mut model = match &req.model {
+            Some(name) => self
+                .models
+                .get(name)
+                .ok_or_else(|| Error::NotFound(format!("model {}", name)))?,
+            None => &self.model,
+        };
+        let mut fallbacks = model.fallbacks.iter();
+        loop {
+            let (res, streamed) = self.model_attempt(model, agent, req.clone()).await;
+            match res {
+                // the deltas of a partially streamed output can't be taken back
+                Err(err) if !streamed && is_failover_error(&err) => match fallbacks.next() {
+                    Some(next) => {
+                        tracing::warn!(error = %err, "model completion failed, failing over");
+                        model = next;
+                    }
+                    None => return Err(err),
+                },
+                res => {
+                    return res.map(|mut output| {
+                        self.pricing.apply(&mut output.usage);
+                        if output.model.is_none() {
+                            output.model = output
+                                .usage
+                                .breakdown
+                                .as_ref()
+                                .and_then(|b| b.models.keys().next().cloned());
+                        }
+                        output
+                    });
+                }
+            }
+        }
+    }
+
+    /// Summarizes the older messages of the chat history when it exceeds the token threshold,
+    /// keeping the latest ones verbatim. The older messages are dropped if the summarization fails.
+    async fn compact_history(
+        &self,
+        cfg: &HistoryConfig,
+        req: &mut CompletionRequest,
+        usage: &mut Usage,
+    ) -> Result<(), BoxError> {
+        let Some(split) = cfg.split_point(&req.chat_history) else {
+            return Ok(());
+        };
+        let model = match &req.model {
+            Some(name) => self
+                .models
+                .get(name)
+                .ok_or_else(|| Error::NotFound(format!("model {}", name)))?,
+            None => &self.model,
+        };
+        let older: Vec<Value> = req.chat_history.drain(..split).collect();
+        let summary_req = summary_request(req.model.clone(), &older);
+        match self.base.guard(model.completion(summary_req)).await {
+            Ok(mut output) if !output.content.is_empty() => {
+                self.pricing.apply(&mut output.usage);
+                usage.accumulate(&output.usage);
+                req.chat_history.insert(0, summary_message(&output.content));
+            }
+            Ok(_) => {
+                tracing::warn!(messages = older.len(), "empty history summary, dropping");
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error = %err,
+                    messages = older.len(),
+                    "failed to summarize the history, dropping",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the model, emitting the deltas of its content when the progress is streamed.
+    /// Returns whether any delta was emitted.
+    async fn model_attempt(
+        &self,
+        model: &Model,
+        agent: &str,
+        req: CompletionRequest,
+    ) -> (Result<AgentOutput, BoxError>, bool) {
+        if self.base.events.is_none() {
+            return (model.completion(req).await, false);
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let deltas = async {
+            let mut streamed = false;
+            while let Some(content) = rx.recv().await {
+                streamed = true;
+                self.base.emit(AgentEvent::Delta {
+                    agent: agent.to_string(),
+                    content,
+                });
+            }
+            streamed
+        };
+        futures::join!(model.stream_completion(req, tx), deltas)
+    }
+
+    /// Returns true if a tool call timed out before the deadline of the request,
+    /// so that the completion can go on without its result.
+    fn is_tool_timeout(&self, err: &BoxError) -> bool {
+        matches!(anda_error(err), Some(Error::Timeout(_)))
+            && self.base.time_remaining().is_none_or(|d| !d.is_zero())
+    }
+
+    fn emit_tool_call_start(&self, agent: &str, tool: &ToolCall) {
+        self.base.emit(AgentEvent::ToolCallStart {
+            agent: agent.to_string(),
+            id: tool.id.clone(),
+            name: tool.name.clone(),
+            args: tool.args.clone(),
+        });
+    }
+
+    fn emit_tool_call_end(&self, agent: &str, tool: &ToolCall, error: Option<String>) {
+        self.base.emit(AgentEvent::ToolCallEnd {
+            agent: agent.to_string(),
+            id: tool.id.clone(),
+            name: tool.name.clone(),
+            result: tool.result.clone(),
+            error,
+        });
+    }
+}
+
+/// Parses the arguments of a tool or agent call generated by the model.
+fn parse_args<T: DeserializeOwned>(name: &str, args: &str) -> Result<T, BoxError> {
+    serde_json::from_str(args)
+        .map_err(|err| Error::Validation(format!("args of {}: {}", name, err)).into())
+}
+
+/// The result of a tool or agent call requested by the model.
+enum CallResult {
+    Tool(Result<ToolOutput<Value>, BoxError>),
+    Agent(Result<AgentOutput, BoxError>),
+}
+
+/// Records a tool or agent call in the trace of a completion.
+fn call_trace(tool: &ToolCall, elapsed: Duration, error: Option<&String>) -> CallTrace {
+    CallTrace {
+        id: tool.id.clone(),
+        name: tool.name.clone(),
+        args_hash: ByteArrayB64(sha3_256(tool.args.as_bytes())),
+        duration_ms: elapsed.as_millis() as u64,
+        result_bytes: tool.result.as_ref().map_or(0, |r| r.to_string().len()),
+        error: error.cloned(),
+        trace: Vec::new(),
+    }
+}
+
+/// Returns true if the call failed because of invalid arguments.
+fn is_invalid_args(err: &BoxError) -> bool {
+    matches!(anda_error(err), Some(Error::Validation(_)))
 }
 
 impl CacheStoreFeatures for AgentCtx {}
@@ -155,7 +472,11 @@ impl AgentContext for AgentCtx {
     /// # Returns
     /// Vector of function definitions for the requested tools.
     fn tool_definitions(&self, names: Option<&[&str]>) -> Vec<FunctionDefinition> {
-        self.tools.definitions(names)
+        self.tools
+            .definitions(names)
+            .into_iter()
+            .filter(|d| self.base.is_tool_allowed(&d.name))
+            .collect()
     }
 
     /// Retrieves definitions for available tools in the remote engines.
@@ -171,7 +492,7 @@ impl AgentContext for AgentCtx {
         endpoint: Option<&str>,
         names: Option<&[&str]>,
     ) -> Result<Vec<FunctionDefinition>, BoxError> {
-        let mut defs = self.base.remote.tool_definitions(endpoint, names);
+        let mut defs = self.base.remote.load().tool_definitions(endpoint, names);
         if let Ok((engines, _)) = self
             .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
             .await
@@ -182,11 +503,9 @@ impl AgentContext for AgentCtx {
                     defs.push(def);
                 }
             }
-
-            Ok(defs)
-        } else {
-            Ok(defs)
         }
+        defs.retain(|d| self.base.is_tool_allowed(&d.name));
+        Ok(defs)
     }
 
     /// Extracts resources from the provided list based on the tool's supported tags.
@@ -199,7 +518,12 @@ impl AgentContext for AgentCtx {
             return self.tools.select_resources(name, resources);
         }
 
-        if let Some(res) = self.base.remote.select_tool_resources(name, resources) {
+        if let Some(res) = self
+            .base
+            .remote
+            .load()
+            .select_tool_resources(name, resources)
+        {
             return Some(res);
         }
 
@@ -226,16 +550,19 @@ impl AgentContext for AgentCtx {
         names: Option<&[&str]>,
         with_prefix: bool,
     ) -> Vec<FunctionDefinition> {
-        let res = self.agents.definitions(names);
+        let res = self
+            .agents
+            .definitions(names)
+            .into_iter()
+            .filter(|d| self.base.is_agent_allowed(&d.name));
         if with_prefix {
-            res.into_iter()
-                .map(|mut d| {
-                    d.name = format!("LA_{}", d.name);
-                    d
-                })
-                .collect()
+            res.map(|mut d| {
+                d.name = format!("LA_{}", d.name);
+                d
+            })
+            .collect()
         } else {
-            res
+            res.collect()
         }
     }
 
@@ -252,7 +579,7 @@ impl AgentContext for AgentCtx {
         endpoint: Option<&str>,
         names: Option<&[&str]>,
     ) -> Result<Vec<FunctionDefinition>, BoxError> {
-        let mut defs = self.base.remote.agent_definitions(endpoint, names);
+        let mut defs = self.base.remote.load().agent_definitions(endpoint, names);
         if let Ok((engines, _)) = self
             .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
             .await
@@ -263,11 +590,9 @@ impl AgentContext for AgentCtx {
                     defs.push(def);
                 }
             }
-
-            Ok(defs)
-        } else {
-            Ok(defs)
         }
+        defs.retain(|d| self.base.is_agent_allowed(&d.name));
+        Ok(defs)
     }
 
     /// Extracts resources from the provided list based on the agent's supported tags.
@@ -283,7 +608,12 @@ impl AgentContext for AgentCtx {
                 .select_resources(&name.to_ascii_lowercase(), resources);
         }
 
-        if let Some(res) = self.base.remote.select_agent_resources(name, resources) {
+        if let Some(res) = self
+            .base
+            .remote
+            .load()
+            .select_agent_resources(name, resources)
+        {
             return Some(res);
         }
 
@@ -305,32 +635,15 @@ impl AgentContext for AgentCtx {
     ///
     /// # Returns
     /// Tuple containing the result string and a boolean indicating if further processing is needed
-    async fn tool_call(&self, mut input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
-        if !input.name.starts_with("RT_") {
-            let ctx = self.child_base(&input.name)?;
-            let tool = self.tools.get(&input.name).expect("tool not found");
-            let args = serde_json::to_string(&input.args)?;
-            return tool.call(ctx, args, input.resources).await;
-        }
-
-        // find registered remote tool and call it
-        if let Some((endpoint, tool_name)) = self.base.remote.get_tool_endpoint(&input.name) {
-            input.name = tool_name;
-            return self.base.remote_tool_call(&endpoint, input).await;
-        }
-
-        // find dynamic remote tool and call it
-        if let Ok((engines, _)) = self
-            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
-            .await
-        {
-            if let Some((endpoint, tool_name)) = engines.get_tool_endpoint(&input.name) {
-                input.name = tool_name;
-                return self.base.remote_tool_call(&endpoint, input).await;
-            }
-        }
-
-        Err(format!("tool {} not found", &input.name).into())
+    #[tracing::instrument(name = "ctx.tool_call", skip_all, fields(
+        tool = %input.name,
+        input_tokens = tracing::field::Empty,
+        output_tokens = tracing::field::Empty,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
+    async fn tool_call(&self, input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
+        record_usage(self.call_tool(input).await, |output| &output.usage)
     }
 
     /// Runs a local agent.
@@ -340,33 +653,15 @@ impl AgentContext for AgentCtx {
     ///
     /// # Returns
     /// [`AgentOutput`] containing the result of the agent execution.
-    async fn agent_run(&self, mut input: AgentInput) -> Result<AgentOutput, BoxError> {
-        if !input.name.starts_with("RA_") {
-            let name = input.name.strip_prefix("LA_").unwrap_or(&input.name);
-            let name = name.to_ascii_lowercase();
-            let ctx = self.child(&name)?;
-            let agent = self.agents.get(&name).expect("agent not found");
-            return agent.run(ctx, input.prompt, input.resources).await;
-        }
-
-        // find registered remote agent and run it
-        if let Some((endpoint, agent_name)) = self.base.remote.get_agent_endpoint(&input.name) {
-            input.name = agent_name;
-            return self.remote_agent_run(&endpoint, input).await;
-        }
-
-        // find dynamic remote agent and run it
-        if let Ok((engines, _)) = self
-            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
-            .await
-        {
-            if let Some((endpoint, agent_name)) = engines.get_agent_endpoint(&input.name) {
-                input.name = agent_name;
-                return self.remote_agent_run(&endpoint, input).await;
-            }
-        }
-
-        Err(format!("agent {} not found", input.name).into())
+    #[tracing::instrument(name = "ctx.agent_run", skip_all, fields(
+        agent = %input.name,
+        input_tokens = tracing::field::Empty,
+        output_tokens = tracing::field::Empty,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
+    async fn agent_run(&self, input: AgentInput) -> Result<AgentOutput, BoxError> {
+        record_usage(self.run_agent(input).await, |output| &output.usage)
     }
 
     /// Runs a remote agent via HTTP RPC.
@@ -385,8 +680,10 @@ impl AgentContext for AgentCtx {
         let target = self
             .base
             .remote
+            .load()
             .get_id_by_endpoint(endpoint)
             .ok_or_else(|| format!("remote engine endpoint {} not found", endpoint))?;
+        self.base.remote_health.check(endpoint)?;
         let mut meta = self.base.self_meta(target);
         if let Some(thread_id) = &meta.thread {
             let thread = self.management.get_thread_meta(thread_id).await?;
@@ -396,9 +693,11 @@ impl AgentContext for AgentCtx {
         }
 
         args.meta = Some(meta.clone());
+        self.base.meter(|u| u.remote_calls += 1);
         let output: AgentOutput = self
             .https_signed_rpc(endpoint, "agent_run", &(&args,))
-            .await?;
+            .await
+            .map_err(|err| remote_error(endpoint, err))?;
 
         if let Some(child) = &output.thread {
             let mut update_my_threads = true;
@@ -426,6 +725,122 @@ impl AgentContext for AgentCtx {
     }
 }
 
+impl AgentCtx {
+    /// Calls a tool, see [`AgentContext::tool_call`].
+    async fn call_tool(&self, input: ToolInput<Value>) -> Result<ToolOutput<Value>, BoxError> {
+        self.base.check_tool_access(&input.name)?;
+        if !input.name.starts_with("RT_") {
+            let ctx = self.child_base(&input.name)?;
+            let tool = self.tools.get(&input.name).expect("tool not found");
+            let mut args = input.args;
+            ctx.tool_middlewares
+                .before(&ctx, &input.name, &mut args)
+                .await?;
+            ctx.audit_tool_call(&input.name, &args).await?;
+            ctx.meter(|u| u.tool_calls += 1);
+            let args_sha3 = ctx.audit_call_digest(&args);
+            let started = ctx.clock.instant();
+            let base = ctx.clone();
+            let res = base
+                .guard_tool(
+                    &input.name,
+                    tool.timeout(),
+                    tool.call(ctx, args, input.resources),
+                )
+                .await;
+            let res = base.tool_middlewares.after(&base, &input.name, res).await;
+            base.audit_call(
+                AuditAction::ToolCallEnd,
+                &input.name,
+                args_sha3,
+                res.as_ref().map(|output| (output, &output.usage)),
+                started,
+            )
+            .await;
+            return res;
+        }
+
+        // find registered remote tool and call it
+        let remote = self.base.remote.load().get_tool_endpoint(&input.name);
+        if let Some((endpoint, tool_name)) = remote {
+            let ctx = self.child_base(&input.name)?;
+            return ctx.remote_tool_call_with(&endpoint, tool_name, input).await;
+        }
+
+        // find dynamic remote tool and call it
+        if let Ok((engines, _)) = self
+            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
+            .await
+        {
+            if let Some((endpoint, tool_name)) = engines.get_tool_endpoint(&input.name) {
+                let ctx = self.child_base(&input.name)?;
+                return ctx.remote_tool_call_with(&endpoint, tool_name, input).await;
+            }
+        }
+
+        Err(Error::NotFound(format!("tool {}", input.name)).into())
+    }
+
+    /// Runs an agent, see [`AgentContext::agent_run`].
+    async fn run_agent(&self, mut input: AgentInput) -> Result<AgentOutput, BoxError> {
+        self.base
+            .check_agent_access(input.name.strip_prefix("LA_").unwrap_or(&input.name))?;
+        if !input.name.starts_with("RA_") {
+            let name = input.name.strip_prefix("LA_").unwrap_or(&input.name);
+            let name = name.to_ascii_lowercase();
+            let ctx = self.child(&name)?;
+            let agent = self.agents.get(&name).expect("agent not found");
+            let (mut prompt, mut resources) = (input.prompt, input.resources);
+            ctx.agent_hooks
+                .before_run(&ctx, &name, &mut prompt, &mut resources)
+                .await?;
+            let args_sha3 = ctx.base.audit_call_digest(&(&prompt, &resources));
+            let started = ctx.base.clock.instant();
+            let base = ctx.base.clone();
+            let res = match base
+                .guard(agent.run(ctx.clone(), prompt.clone(), resources))
+                .await
+            {
+                Ok(output) => {
+                    ctx.agent_hooks
+                        .after_run(&ctx, &name, &prompt, output)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            base.audit_call(
+                AuditAction::AgentRunEnd,
+                &name,
+                args_sha3,
+                res.as_ref().map(|output| (output, &output.usage)),
+                started,
+            )
+            .await;
+            return res;
+        }
+
+        // find registered remote agent and run it
+        let remote = self.base.remote.load().get_agent_endpoint(&input.name);
+        if let Some((endpoint, agent_name)) = remote {
+            input.name = agent_name;
+            return self.remote_agent_run(&endpoint, input).await;
+        }
+
+        // find dynamic remote agent and run it
+        if let Ok((engines, _)) = self
+            .cache_store_get::<RemoteEngines>(DYNAMIC_REMOTE_ENGINES)
+            .await
+        {
+            if let Some((endpoint, agent_name)) = engines.get_agent_endpoint(&input.name) {
+                input.name = agent_name;
+                return self.remote_agent_run(&endpoint, input).await;
+            }
+        }
+
+        Err(Error::NotFound(format!("agent {}", input.name)).into())
+    }
+}
+
 impl CompletionFeatures for AgentCtx {
     /// Executes a completion request with automatic tool call handling.
     ///
@@ -443,11 +858,84 @@ impl CompletionFeatures for AgentCtx {
     /// # Process Flow
     /// 1. Makes initial completion request to the model;
     /// 2. If tool calls are returned:
-    ///    - Executes each tool call;
+    ///    - Executes each tool call, concurrently if [`CompletionRequest::parallel_tool_calls`]
+    ///      is set, a timed out tool call returns its error to the model;
     ///    - Adds tool results to the chat history;
     ///    - Repeats the completion with updated history;
-    /// 3. Returns final result when no more tool calls need processing.
+    /// 3. Fails when the model requests tool calls for more than
+    ///    [`CompletionRequest::max_tool_rounds`] rounds, or repeats a tool call of a previous round;
+    /// 4. Returns final result when no more tool calls need processing,
+    ///    with the citations of the request documents and of the called agents,
+    ///    and the trace of the completion rounds and calls.
+    ///
+    /// With the memory enabled, the history of the request's thread is loaded before the chat
+    /// history, and the prompt and the final answer are appended to it, see [`AgentCtx::memory`].
+    /// With the history compaction enabled, the older messages of a long chat history are
+    /// summarized before the first round, see [`crate::history`].
+    #[tracing::instrument(name = "completion", skip_all, fields(
+        agent = self.base.agent_name().unwrap_or_default(),
+        input_tokens = tracing::field::Empty,
+        output_tokens = tracing::field::Empty,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
     async fn completion(
+        &self,
+        req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        record_usage(self.run_completion(req, resources).await, |output| {
+            &output.usage
+        })
+    }
+
+    /// Executes a completion request like [`CompletionFeatures::completion`] in a background
+    /// task, streaming the deltas of the model's content, the tool and agent calls, including
+    /// those of the called agents, and finally the output or the error.
+    ///
+    /// The events are also sent to the progress stream of the agent run, if any, except the
+    /// final one. Dropping the stream aborts the completion.
+    fn completion_stream(
+        &self,
+        req: CompletionRequest,
+        resources: Option<Vec<Resource>>,
+    ) -> impl Stream<Item = AgentEvent> + Send {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let run_events = self.base.events.clone();
+        let mut ctx = self.clone();
+        ctx.base.events = Some(tx.clone());
+        tokio::spawn(
+            async move {
+                let event = tokio::select! {
+                    res = ctx.completion(req, resources) => match res {
+                        Ok(output) => AgentEvent::Output(output),
+                        Err(err) => AgentEvent::Error {
+                            error: redact(&err.to_string()),
+                        },
+                    },
+                    // the stream was dropped
+                    _ = tx.closed() => return,
+                };
+                let _ = tx.send(event);
+            }
+            .in_current_span(),
+        );
+
+        futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })
+        .inspect(move |event| {
+            let is_final = matches!(event, AgentEvent::Output(_) | AgentEvent::Error { .. });
+            if let Some(events) = run_events.as_ref().filter(|_| !is_final) {
+                let _ = events.send(event.clone());
+            }
+        })
+    }
+}
+
+impl AgentCtx {
+    /// Runs the completion loop of [`CompletionFeatures::completion`].
+    async fn run_completion(
         &self,
         mut req: CompletionRequest,
         resources: Option<Vec<Resource>>,
@@ -455,41 +943,196 @@ impl CompletionFeatures for AgentCtx {
         let mut tool_calls_result: Vec<ToolCall> = Vec::new();
         let mut usage = Usage::default();
         let mut resources = resources.unwrap_or_default();
+        let agent = self.base.agent_name().unwrap_or_default().to_string();
+        // the documents are only sent in the first round
+        let mut citations = req.documents.citations();
+        // the tools called with invalid arguments, which the model may call again once
+        let mut retried: BTreeSet<String> = BTreeSet::new();
+        let mut trace: Vec<TraceStep> = Vec::new();
+        let mut round: usize = 0;
+        let max_tool_rounds = req.max_tool_rounds.unwrap_or(DEFAULT_MAX_TOOL_ROUNDS);
+        // the tool calls of the previous rounds, by name and arguments
+        let mut called: BTreeSet<(String, String)> = BTreeSet::new();
+        let memory = self.memory();
+        let mut remembered: Vec<Message> = Vec::new();
+        if let Some(memory) = &memory {
+            let history = memory.load().await?;
+            req.chat_history
+                .splice(0..0, history.iter().map(|msg| json!(msg)));
+            if !req.content_parts.is_empty() || !req.prompt.is_empty() {
+                remembered.push(Message {
+                    role: "user".to_string(),
+                    content: if req.content_parts.is_empty() {
+                        req.prompt.clone().into()
+                    } else {
+                        json!(req.content_parts)
+                    },
+                    name: req.prompter_name.clone(),
+                    tool_call_id: None,
+                });
+            }
+        }
+        if let Some(cfg) = &self.history {
+            self.compact_history(cfg, &mut req, &mut usage).await?;
+        }
         loop {
+            round += 1;
             let mut resources_out: Vec<Resource> = Vec::new();
-            let mut output = self.model.completion(req.clone()).await?;
-            usage.accumulate(&output.usage);
+            let started = self.base.clock.instant();
+            // the model request is aborted when the context is cancelled or its deadline exceeded
+            let mut output = self
+                .base
+                .guard(
+                    async {
+                        let res = self.model_completion(&agent, req.clone()).await;
+                        record_usage(res, |output| &output.usage)
+                    }
+                    .instrument(tracing::info_span!(
+                        "model.completion",
+                        round,
+                        model = req.model.as_deref().unwrap_or_default(),
+                        input_tokens = tracing::field::Empty,
+                        output_tokens = tracing::field::Empty,
+                        error = tracing::field::Empty,
+                        otel.status_code = tracing::field::Empty,
+                    )),
+                )
+                .await?;
+            usage.accumulate_round(&output.usage);
+            trace.push(TraceStep::Completion {
+                round,
+                duration_ms: self.base.elapsed_since(started).as_millis() as u64,
+                usage: output.usage.clone(),
+                tool_calls: output.tool_calls.as_ref().map_or(0, |t| t.len()),
+            });
+            if !output.content.is_empty() {
+                self.base.emit(AgentEvent::Content {
+                    agent: agent.clone(),
+                    content: output.content.clone(),
+                });
+            }
+            // guards against a model that keeps calling tools
+            if let Some(tool_calls) = output.tool_calls.as_ref().filter(|t| !t.is_empty()) {
+                let failed_reason = if round > max_tool_rounds {
+                    Some(format!(
+                        "exceeded the limit of {} tool call rounds",
+                        max_tool_rounds
+                    ))
+                } else {
+                    tool_calls
+                        .iter()
+                        .find(|tool| called.contains(&(tool.name.clone(), tool.args.clone())))
+                        .map(|tool| {
+                            format!("repeated tool call {} with the same arguments", tool.name)
+                        })
+                };
+                if failed_reason.is_some() {
+                    output.failed_reason = failed_reason;
+                    output.usage = usage;
+                    output.trace = Some(trace);
+                    return Ok(output);
+                }
+                called.extend(
+                    tool_calls
+                        .iter()
+                        .map(|tool| (tool.name.clone(), tool.args.clone())),
+                );
+            }
+
             // automatically executes tools calls
             let mut tool_calls_continue: Vec<Value> = Vec::new();
             if let Some(tool_calls) = &mut output.tool_calls {
-                for tool in tool_calls.iter_mut() {
-                    if !req.tools.iter().any(|t| t.name == tool.name) {
+                // the calls to execute, by index of the tool call, and their pending results
+                let mut calls: Vec<(usize, FunctionDefinition)> = Vec::new();
+                let mut pending: Vec<BoxFuture<'_, (CallResult, Duration)>> = Vec::new();
+                for (i, tool) in tool_calls.iter().enumerate() {
+                    let definition = match req.tools.iter().find(|t| t.name == tool.name) {
+                        Some(definition) => definition.clone(),
                         // tool already called, skip
-                        continue;
-                    }
+                        None => continue,
+                    };
 
                     // remove called tool from req.tools
                     req.tools.retain(|t| t.name != tool.name);
+                    let tool = tool.clone();
+                    let agent = &agent;
                     if self.tools.contains(&tool.name) || tool.name.starts_with("RT_") {
-                        match self
-                            .tool_call(ToolInput {
-                                name: tool.name.clone(),
-                                args: serde_json::from_str(&tool.args)?,
-                                resources: self
-                                    .select_tool_resources(&tool.name, &mut resources)
-                                    .await,
-                                meta: Some(self.meta().clone()),
-                            })
-                            .await
-                        {
-                            Ok(mut res) => {
-                                usage.accumulate(&res.usage);
-                                let content: Value = if res.output.is_string() {
-                                    res.output.clone()
-                                } else {
-                                    serde_json::to_string(&res.output)?.into()
-                                };
+                        let args = parse_args(&tool.name, &tool.args);
+                        let resources = if args.is_ok() {
+                            self.select_tool_resources(&tool.name, &mut resources).await
+                        } else {
+                            None
+                        };
+                        calls.push((i, definition));
+                        pending.push(Box::pin(async move {
+                            self.emit_tool_call_start(agent, &tool);
+                            let started = self.base.clock.instant();
+                            let res = match args {
+                                Ok(args) => {
+                                    self.tool_call(ToolInput {
+                                        name: tool.name.clone(),
+                                        args,
+                                        resources,
+                                        meta: Some(self.meta().clone()),
+                                    })
+                                    .await
+                                }
+                                Err(err) => Err(err),
+                            };
+                            (CallResult::Tool(res), self.base.elapsed_since(started))
+                        }));
+                    } else if self.agents.contains(&tool.name)
+                        || tool.name.starts_with("LA_")
+                        || tool.name.starts_with("RA_")
+                    {
+                        let args = parse_args::<AgentArgs>(&tool.name, &tool.args);
+                        let resources = if args.is_ok() {
+                            self.agents.select_resources(&tool.name, &mut resources)
+                        } else {
+                            None
+                        };
+                        calls.push((i, definition));
+                        pending.push(Box::pin(async move {
+                            self.emit_tool_call_start(agent, &tool);
+                            let started = self.base.clock.instant();
+                            let res = match args {
+                                Ok(args) => {
+                                    self.agent_run(AgentInput {
+                                        name: tool.name.clone(),
+                                        prompt: args.prompt,
+                                        resources,
+                                        meta: Some(self.meta().clone()),
+                                    })
+                                    .await
+                                }
+                                Err(err) => Err(err),
+                            };
+                            (CallResult::Agent(res), self.base.elapsed_since(started))
+                        }));
+                    }
+                    // ignore unknown tool
+                }
 
+                // the calls run one by one unless they are requested to run concurrently,
+                // their results are handled in order either way
+                let pending = if req.parallel_tool_calls && pending.len() > 1 {
+                    futures::future::join_all(pending)
+                        .await
+                        .into_iter()
+                        .map(|res| futures::future::ready(res).boxed())
+                        .collect()
+                } else {
+                    pending
+                };
+
+                for ((i, definition), call) in calls.into_iter().zip(pending) {
+                    let (res, elapsed) = call.await;
+                    let tool = &mut tool_calls[i];
+                    match res {
+                        CallResult::Tool(res) => match res {
+                            Ok(mut res) => {
+                                usage.accumulate_tool(&tool.name, &res.usage);
+                                let content = res.to_content()?;
                                 tool_calls_continue.push(json!(Message {
                                     role: "tool".to_string(),
                                     content,
@@ -503,34 +1146,69 @@ impl CompletionFeatures for AgentCtx {
                                 }
 
                                 tool.result = Some(serde_json::to_value(&res)?);
+                                trace.push(TraceStep::ToolCall(call_trace(tool, elapsed, None)));
+                                self.emit_tool_call_end(&agent, tool, None);
+                            }
+                            Err(err)
+                                if self.is_tool_timeout(&err)
+                                    || (is_invalid_args(&err)
+                                        && retried.insert(tool.name.clone())) =>
+                            {
+                                // lets the model fix the arguments in the next round,
+                                // or go on without the result of a timed out call
+                                if is_invalid_args(&err) {
+                                    req.tools.push(definition);
+                                }
+                                let err = redact(&err.to_string());
+                                tool_calls_continue.push(json!(Message {
+                                    role: "tool".to_string(),
+                                    content: format!("Error: {}", err).into(),
+                                    name: None,
+                                    tool_call_id: Some(tool.id.clone()),
+                                }));
+                                trace.push(TraceStep::ToolCall(call_trace(
+                                    tool,
+                                    elapsed,
+                                    Some(&err),
+                                )));
+                                self.emit_tool_call_end(&agent, tool, Some(err));
                             }
                             Err(err) => {
-                                output.failed_reason = Some(err.to_string());
+                                let err = redact(&err.to_string());
+                                output.failed_reason = Some(err.clone());
                                 output.usage = usage;
+                                trace.push(TraceStep::ToolCall(call_trace(
+                                    tool,
+                                    elapsed,
+                                    Some(&err),
+                                )));
+                                output.trace = Some(trace);
+                                self.emit_tool_call_end(&agent, tool, Some(err));
                                 return Ok(output);
                             }
-                        }
-                    } else if self.agents.contains(&tool.name)
-                        || tool.name.starts_with("LA_")
-                        || tool.name.starts_with("RA_")
-                    {
-                        let args: AgentArgs = serde_json::from_str(&tool.args)?;
-                        match self
-                            .agent_run(AgentInput {
-                                name: tool.name.clone(),
-                                prompt: args.prompt,
-                                resources: self.agents.select_resources(&tool.name, &mut resources),
-                                meta: Some(self.meta().clone()),
-                            })
-                            .await
-                        {
+                        },
+                        CallResult::Agent(res) => match res {
                             Ok(mut res) => {
-                                usage.accumulate(&res.usage);
+                                usage.accumulate_agent(&tool.name, &res.usage);
                                 if res.failed_reason.is_some() {
+                                    let mut step =
+                                        call_trace(tool, elapsed, res.failed_reason.as_ref());
+                                    step.trace = res.trace.take().unwrap_or_default();
+                                    trace.push(TraceStep::AgentRun(step));
+                                    output.trace = Some(trace);
+                                    self.emit_tool_call_end(
+                                        &agent,
+                                        tool,
+                                        res.failed_reason.clone(),
+                                    );
                                     output.failed_reason = res.failed_reason;
                                     return Ok(output);
                                 }
 
+                                if let Some(cited) = res.citations.take() {
+                                    citations.extend(cited);
+                                }
+                                let nested = res.trace.take().unwrap_or_default();
                                 tool_calls_continue.push(json!(Message {
                                     role: "tool".to_string(),
                                     content: res.content.clone().into(),
@@ -544,15 +1222,45 @@ impl CompletionFeatures for AgentCtx {
                                 }
 
                                 tool.result = Some(serde_json::to_value(&res)?);
+                                let mut step = call_trace(tool, elapsed, None);
+                                step.trace = nested;
+                                trace.push(TraceStep::AgentRun(step));
+                                self.emit_tool_call_end(&agent, tool, None);
+                            }
+                            Err(err)
+                                if is_invalid_args(&err) && retried.insert(tool.name.clone()) =>
+                            {
+                                // lets the model fix the arguments in the next round
+                                let err = redact(&err.to_string());
+                                req.tools.push(definition);
+                                tool_calls_continue.push(json!(Message {
+                                    role: "tool".to_string(),
+                                    content: format!("Error: {}", err).into(),
+                                    name: None,
+                                    tool_call_id: Some(tool.id.clone()),
+                                }));
+                                trace.push(TraceStep::AgentRun(call_trace(
+                                    tool,
+                                    elapsed,
+                                    Some(&err),
+                                )));
+                                self.emit_tool_call_end(&agent, tool, Some(err));
                             }
                             Err(err) => {
-                                output.failed_reason = Some(err.to_string());
+                                let err = redact(&err.to_string());
+                                output.failed_reason = Some(err.clone());
                                 output.usage = usage;
+                                trace.push(TraceStep::AgentRun(call_trace(
+                                    tool,
+                                    elapsed,
+                                    Some(&err),
+                                )));
+                                output.trace = Some(trace);
+                                self.emit_tool_call_end(&agent, tool, Some(err));
                                 return Ok(output);
                             }
-                        }
+                        },
                     }
-                    // ignore unknown tool
                 }
 
                 tool_calls_result.append(tool_calls);
@@ -569,8 +1277,26 @@ impl CompletionFeatures for AgentCtx {
                 } else {
                     Some(resources_out)
                 };
+                output.citations = if citations.is_empty() {
+                    None
+                } else {
+                    Some(citations)
+                };
 
                 output.usage = usage;
+                output.trace = Some(trace);
+                if let Some(memory) = memory.as_ref().filter(|_| !output.content.is_empty()) {
+                    remembered.push(Message {
+                        role: "assistant".to_string(),
+                        content: output.content.clone().into(),
+                        name: None,
+                        tool_call_id: None,
+                    });
+                    // the answer is returned even if the history can't be saved
+                    if let Err(err) = memory.append(remembered).await {
+                        tracing::warn!(error = %err, "failed to save the thread memory");
+                    }
+                }
                 return Ok(output);
             }
 
@@ -603,7 +1329,7 @@ impl EmbeddingFeatures for AgentCtx {
         &self,
         texts: impl IntoIterator<Item = String> + Send,
     ) -> Result<(Vec<Embedding>, Usage), BoxError> {
-        self.model.embed(texts).await
+        self.base.guard(self.model.embed(texts)).await
     }
 
     /// Generates an embedding for a single query text.
@@ -614,7 +1340,7 @@ impl EmbeddingFeatures for AgentCtx {
     /// # Returns
     /// Embedding vector for the input text.
     async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
-        self.model.embed_query(text).await
+        self.base.guard(self.model.embed_query(text)).await
     }
 }
 
@@ -660,6 +1386,28 @@ impl StateFeatures for AgentCtx {
     fn time_elapsed(&self) -> Duration {
         self.base.time_elapsed()
     }
+
+    fn time_remaining(&self) -> Option<Duration> {
+        self.base.time_remaining()
+    }
+
+    fn extensions(&self) -> &Extensions {
+        &self.base.extensions
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.base.now_ms()
+    }
+
+    fn fill_random(&self, buf: &mut [u8]) {
+        self.base.fill_random(buf)
+    }
+}
+
+impl LogFeatures for AgentCtx {
+    fn log(&self, level: LogLevel, message: &str, fields: &[(&str, Value)]) {
+        self.base.log(level, message, fields)
+    }
 }
 
 impl KeysFeatures for AgentCtx {
@@ -772,6 +1520,19 @@ impl StoreFeatures for AgentCtx {
         self.base.store_get(path).await
     }
 
+    /// Retrieves a byte range of the object at the specified path.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the object;
+    /// * `range` - Byte range to read, its end is clamped to the size of the object.
+    async fn store_get_range(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+    ) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
+        self.base.store_get_range(path, range).await
+    }
+
     /// Lists objects in storage with optional prefix and offset filters.
     ///
     /// # Arguments
@@ -785,6 +1546,14 @@ impl StoreFeatures for AgentCtx {
         self.base.store_list(prefix, offset).await
     }
 
+    /// Lists a page of objects in storage, ordered by path and filtered by the options.
+    ///
+    /// # Arguments
+    /// * `options` - Prefix, page size, continuation token and filters of the listing.
+    async fn store_list_page(&self, options: &StoreListOptions) -> Result<StoreListPage, BoxError> {
+        self.base.store_list_page(options).await
+    }
+
     /// Stores data at the specified path with a given write mode.
     ///
     /// # Arguments
@@ -800,6 +1569,38 @@ impl StoreFeatures for AgentCtx {
         self.base.store_put(path, mode, value).await
     }
 
+    /// Stores data at the specified path with user-defined metadata.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `mode` - Write mode (Create, Overwrite, etc.);
+    /// * `metadata` - User-defined metadata of the object;
+    /// * `value` - Data to store as bytes.
+    async fn store_put_with_metadata(
+        &self,
+        path: &Path,
+        mode: PutMode,
+        metadata: BTreeMap<String, String>,
+        value: bytes::Bytes,
+    ) -> Result<PutResult, BoxError> {
+        self.base
+            .store_put_with_metadata(path, mode, metadata, value)
+            .await
+    }
+
+    /// Stores the chunks of a stream at the specified path with a multipart upload.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `chunks` - Stream of the chunks of the object.
+    async fn store_put_stream(
+        &self,
+        path: &Path,
+        chunks: BoxStream<'static, Result<bytes::Bytes, BoxError>>,
+    ) -> Result<PutResult, BoxError> {
+        self.base.store_put_stream(path, chunks).await
+    }
+
     /// Renames a storage object if the target path doesn't exist.
     ///
     /// # Arguments
@@ -818,6 +1619,33 @@ impl StoreFeatures for AgentCtx {
     }
 }
 
+impl VectorStoreFeatures for AgentCtx {
+    /// Inserts or replaces documents in the vector store of the engine.
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.base.vector_upsert(docs).await
+    }
+
+    /// Gets documents by IDs from the vector store of the engine.
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        self.base.vector_get(ids).await
+    }
+
+    /// Deletes documents from the vector store of the engine.
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.base.vector_delete(ids).await
+    }
+
+    /// Finds the top k documents most similar to the query vector and matching the filter.
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.base.vector_search(query, top_k, filter).await
+    }
+}
+
 impl CacheFeatures for AgentCtx {
     /// Checks if a key exists in the cache.
     fn cache_contains(&self, key: &str) -> bool {
@@ -928,6 +1756,27 @@ impl HttpFeatures for AgentCtx {
         self.base.https_call(url, method, headers, body).await
     }
 
+    /// Makes an HTTPs request with per-request options.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`;
+    /// * `method` - HTTP method (GET, POST, etc.);
+    /// * `headers` - Optional HTTP headers;
+    /// * `body` - Optional request body (default empty);
+    /// * `opts` - Timeouts, redirect policy and response size limit.
+    async fn https_call_with_options(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        self.base
+            .https_call_with_options(url, method, headers, body, opts)
+            .await
+    }
+
     /// Makes a signed HTTPs request with message authentication.
     ///
     /// # Arguments
@@ -966,6 +1815,16 @@ impl HttpFeatures for AgentCtx {
     {
         self.base.https_signed_rpc(endpoint, method, args).await
     }
+
+    /// Connects to a WebSocket server.
+    /// The connection is closed when the context is cancelled.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `wss://`;
+    /// * `opts` - Handshake headers, keepalive and buffer options.
+    async fn websocket_connect(&self, url: &str, opts: WsOptions) -> Result<WebSocket, BoxError> {
+        self.base.websocket_connect(url, opts).await
+    }
 }
 
 #[cfg(test)]
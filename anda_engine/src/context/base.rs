@@ -8,7 +8,8 @@
 //! - [`StoreFeatures`]: Persistent storage operations;
 //! - [`CacheFeatures`]: Caching mechanisms;
 //! - [`CanisterCaller`]: Canister interaction capabilities;
-//! - [`HttpFeatures`]: HTTPs communication features.
+//! - [`HttpFeatures`]: HTTPs communication features;
+//! - [`LogFeatures`]: Structured logging tagged with the context.
 //!
 //! The context is designed to be:
 //! - Thread-safe through Arc-based sharing of resources;
@@ -24,30 +25,58 @@
 //! - Time tracking for operation duration.
 
 use anda_core::{
-    ANONYMOUS, BaseContext, BoxError, CacheExpiry, CacheFeatures, CacheStoreFeatures,
-    CancellationToken, CanisterCaller, HttpFeatures, KeysFeatures, ObjectMeta, Path, PutMode,
-    PutResult, RequestMeta, StateFeatures, StoreFeatures, ToolInput, ToolOutput, Value,
-    derivation_path_with,
+    ANONYMOUS, AgentEvent, BaseContext, BoxError, ByteArrayB64, ByteBufB64, CacheExpiry,
+    CacheFeatures, CacheStoreFeatures, CancellationToken, CanisterCaller, Clock, Error, Extensions,
+    HttpFeatures, HttpOptions, KeysFeatures, LogFeatures, LogLevel, ObjectMeta, Path, PutMode,
+    PutResult, RandomSource, RedirectPolicy, RequestId, RequestMeta, RetryPolicy, RpcRetryPolicy,
+    StateFeatures, StoreFeatures, StoreListOptions, StoreListPage, SystemClock, SystemRandom,
+    ToolInput, ToolOutput, Usage, VECTOR_ACL_KEY, VECTOR_ACL_PUBLIC, Value, VectorDocument,
+    VectorFilter, VectorMatch, VectorStoreFeatures, WebSocket, WsOptions, anda_error,
+    derivation_path_with, follow_redirect, http_retry_with_clock, rpc_retry_with_clock,
+    with_cancellation,
 };
+use arc_swap::ArcSwap;
 use bytes::Bytes;
 use candid::{CandidType, Principal, utils::ArgumentEncoder};
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use ic_cose_types::cose::sha3_256;
 use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     future::Future,
-    sync::Arc,
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
+use tokio::sync::mpsc;
 
 const CONTEXT_MAX_DEPTH: u8 = 42;
-const CACHE_MAX_CAPACITY: u64 = 1000000;
 
 use super::{
-    RemoteEngines,
-    cache::CacheService,
+    RemoteEngines, RemoteHealth,
+    cache::{CacheService, CacheStats},
+    policy::{AccessPolicy, CanisterPolicy, Capabilities, HttpPolicy},
+    tenant::{Tenant, Tenants},
     web3::{Web3Client, Web3SDK},
+    websocket::websocket_connect,
+};
+use crate::{
+    audit::{AuditAction, AuditLog, CallRecord, json_digest},
+    jobs::{JobInfo, JobSpec, Jobs},
+    knowledge::{KnowledgeBase, KnowledgeScope},
+    metering::{Metering, UsageCounters},
+    middleware::ToolMiddlewares,
+    quota::{Quotas, quota_user},
+    secrets::redact,
+    snapshot::CacheEntrySnapshot,
+    store::Store,
+    telemetry::{record_error, url_host},
+    vector::VectorIndex,
 };
-use crate::store::Store;
 
 #[derive(Clone)]
 pub struct BaseCtx {
@@ -57,11 +86,58 @@ pub struct BaseCtx {
     pub(crate) path: Path,
     pub(crate) cancellation_token: CancellationToken,
     pub(crate) start_at: Instant,
+    /// Deadline of the request, inherited by the child contexts.
+    pub(crate) deadline: Option<Instant>,
     pub(crate) depth: u8,
     pub(crate) web3: Arc<Web3SDK>,
-    /// Registered remote engines for tool and agent execution.
-    pub(crate) remote: Arc<RemoteEngines>,
+    /// Registered remote engines for tool and agent execution, swapped on config reload.
+    pub(crate) remote: Arc<ArcSwap<RemoteEngines>>,
+    /// Health of the remote engines, calls to the unhealthy ones fail fast.
+    pub(crate) remote_health: Arc<RemoteHealth>,
     pub(crate) meta: RequestMeta,
+    /// Policy restricting the targets of `canister_update`.
+    pub(crate) canister_policy: Arc<ArcSwap<CanisterPolicy>>,
+    /// Policy bounding the domains HTTP requests may reach.
+    pub(crate) http_policy: Arc<ArcSwap<HttpPolicy>>,
+    /// Policy restricting the tools and agents each caller may invoke.
+    pub(crate) access_policy: Arc<ArcSwap<AccessPolicy>>,
+    /// Sink of progress events for streaming agent runs.
+    pub(crate) events: Option<mpsc::UnboundedSender<AgentEvent>>,
+    /// Audit log of signing operations, audited tool calls and store mutations.
+    pub(crate) audit: Option<Arc<AuditLog>>,
+    /// Tenants of the engine, the tenant of a request is resolved from its caller.
+    pub(crate) tenants: Arc<Tenants>,
+    /// The controller and managers of the engine, who may label vector documents public.
+    pub(crate) managers: Arc<BTreeSet<Principal>>,
+    /// Tenant of the caller, scoping the store, cache and key derivation.
+    pub(crate) tenant: Option<Arc<Tenant>>,
+    /// Background jobs of the engine, if enabled.
+    pub(crate) jobs: Option<Arc<Jobs>>,
+    /// Usage meter of the engine, if enabled.
+    pub(crate) metering: Option<Arc<Metering>>,
+    pub(crate) quotas: Option<Arc<Quotas>>,
+    /// Vector store of the engine, shared by all agents and tools.
+    pub(crate) vectors: VectorIndex,
+    /// Knowledge collections of the engine, if enabled.
+    pub(crate) knowledge: Option<Arc<KnowledgeBase>>,
+    /// Request-scoped values, inherited by the child contexts.
+    pub(crate) extensions: Extensions,
+    /// Source of time, mocked in tests.
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Source of random bytes, seeded in tests.
+    pub(crate) random: Arc<dyn RandomSource>,
+    /// Timeout of each tool call, unless the tool has its own.
+    pub(crate) tool_timeout: Option<Duration>,
+    /// Retry policy of the signed RPC calls to remote engines, no retries if not set.
+    pub(crate) rpc_retry: Option<Arc<RpcRetryPolicy>>,
+    /// Retry policy of the HTTPs calls without per-request options, no retries if not set.
+    pub(crate) http_retry: Option<Arc<RetryPolicy>>,
+    /// Middlewares around the tool calls.
+    pub(crate) tool_middlewares: Arc<ToolMiddlewares>,
+    /// Capabilities of the context, inherited and possibly narrowed by the child contexts.
+    pub(crate) capabilities: Capabilities,
+    /// Capabilities of the tools, the contexts of their calls are narrowed to them.
+    pub(crate) tool_capabilities: Arc<BTreeMap<String, Capabilities>>,
 
     cache: Arc<CacheService>,
     store: Store,
@@ -86,10 +162,10 @@ impl BaseCtx {
         id: Principal,
         name: String,
         cancellation_token: CancellationToken,
-        names: BTreeSet<Path>,
+        cache: Arc<CacheService>,
         web3: Arc<Web3SDK>,
         store: Store,
-        remote: Arc<RemoteEngines>,
+        remote: Arc<ArcSwap<RemoteEngines>>,
     ) -> Self {
         Self {
             id,
@@ -98,12 +174,36 @@ impl BaseCtx {
             path: Path::default(),
             cancellation_token,
             start_at: Instant::now(),
-            cache: Arc::new(CacheService::new(CACHE_MAX_CAPACITY, names)),
+            deadline: None,
+            cache,
             store,
             web3,
             depth: 0,
             remote,
+            remote_health: Arc::new(RemoteHealth::default()),
             meta: RequestMeta::default(),
+            canister_policy: Arc::new(ArcSwap::from_pointee(CanisterPolicy::default())),
+            http_policy: Arc::new(ArcSwap::from_pointee(HttpPolicy::default())),
+            access_policy: Arc::new(ArcSwap::from_pointee(AccessPolicy::default())),
+            events: None,
+            audit: None,
+            tenants: Arc::new(Tenants::default()),
+            managers: Arc::new(BTreeSet::new()),
+            tenant: None,
+            jobs: None,
+            metering: None,
+            quotas: None,
+            vectors: VectorIndex::in_memory(),
+            knowledge: None,
+            extensions: Extensions::default(),
+            clock: Arc::new(SystemClock),
+            random: Arc::new(SystemRandom),
+            tool_timeout: None,
+            rpc_retry: None,
+            http_retry: None,
+            tool_middlewares: Arc::new(ToolMiddlewares::default()),
+            capabilities: Capabilities::default(),
+            tool_capabilities: Arc::new(BTreeMap::new()),
         }
     }
 
@@ -113,7 +213,8 @@ impl BaseCtx {
     /// The child context inherits all properties from the parent but with:
     /// - A new path;
     /// - A child cancellation token;
-    /// - Incremented depth.
+    /// - Incremented depth;
+    /// - The capabilities narrowed to the tool's, if it is a tool context.
     ///
     /// # Arguments
     /// * `path` - New path for the child context.
@@ -122,6 +223,7 @@ impl BaseCtx {
     /// Returns an error if the context depth exceeds CONTEXT_MAX_DEPTH.
     pub(crate) fn child(&self, path: String) -> Result<Self, BoxError> {
         let path = Path::parse(path)?;
+        let capabilities = self.child_capabilities(&path);
         let child = Self {
             id: self.id,
             name: self.name.clone(),
@@ -129,12 +231,36 @@ impl BaseCtx {
             path,
             cancellation_token: self.cancellation_token.child_token(),
             start_at: self.start_at,
+            deadline: self.deadline,
             cache: self.cache.clone(),
             store: self.store.clone(),
             web3: self.web3.clone(),
             depth: self.depth + 1,
             remote: self.remote.clone(),
+            remote_health: self.remote_health.clone(),
             meta: self.meta.clone(),
+            canister_policy: self.canister_policy.clone(),
+            http_policy: self.http_policy.clone(),
+            access_policy: self.access_policy.clone(),
+            events: self.events.clone(),
+            audit: self.audit.clone(),
+            tenants: self.tenants.clone(),
+            managers: self.managers.clone(),
+            tenant: self.tenant.clone(),
+            jobs: self.jobs.clone(),
+            metering: self.metering.clone(),
+            quotas: self.quotas.clone(),
+            vectors: self.vectors.clone(),
+            knowledge: self.knowledge.clone(),
+            extensions: self.extensions.clone(),
+            clock: self.clock.clone(),
+            random: self.random.clone(),
+            tool_timeout: self.tool_timeout,
+            rpc_retry: self.rpc_retry.clone(),
+            http_retry: self.http_retry.clone(),
+            tool_middlewares: self.tool_middlewares.clone(),
+            capabilities,
+            tool_capabilities: self.tool_capabilities.clone(),
         };
 
         if child.depth >= CONTEXT_MAX_DEPTH {
@@ -146,7 +272,8 @@ impl BaseCtx {
     /// Creates a child context with additional user and caller information.
     ///
     /// Similar to `child()`, but allows specifying user and caller information
-    /// for the new context.
+    /// for the new context. Its deadline is the earlier of the parent's deadline
+    /// and the request's [`RequestMeta::timeout_ms`].
     ///
     /// # Arguments
     /// * `path` - New path for the child context;
@@ -162,19 +289,51 @@ impl BaseCtx {
         meta: RequestMeta,
     ) -> Result<Self, BoxError> {
         let path = Path::parse(path)?;
+        let capabilities = self.child_capabilities(&path);
+        let start_at = self.clock.instant();
+        let deadline = meta
+            .timeout_ms
+            .map(|ms| start_at + Duration::from_millis(ms))
+            .into_iter()
+            .chain(self.deadline)
+            .min();
         let child = Self {
             id: self.id,
             name: self.name.clone(),
             caller,
             path,
             cancellation_token: self.cancellation_token.child_token(),
-            start_at: Instant::now(),
+            start_at,
+            deadline,
             cache: self.cache.clone(),
             store: self.store.clone(),
             web3: self.web3.clone(),
             depth: self.depth + 1,
             remote: self.remote.clone(),
+            remote_health: self.remote_health.clone(),
             meta,
+            canister_policy: self.canister_policy.clone(),
+            http_policy: self.http_policy.clone(),
+            access_policy: self.access_policy.clone(),
+            events: self.events.clone(),
+            audit: self.audit.clone(),
+            tenants: self.tenants.clone(),
+            managers: self.managers.clone(),
+            tenant: self.tenants.of(&caller),
+            jobs: self.jobs.clone(),
+            metering: self.metering.clone(),
+            quotas: self.quotas.clone(),
+            vectors: self.vectors.clone(),
+            knowledge: self.knowledge.clone(),
+            extensions: self.extensions.clone(),
+            clock: self.clock.clone(),
+            random: self.random.clone(),
+            tool_timeout: self.tool_timeout,
+            rpc_retry: self.rpc_retry.clone(),
+            http_retry: self.http_retry.clone(),
+            tool_middlewares: self.tool_middlewares.clone(),
+            capabilities,
+            tool_capabilities: self.tool_capabilities.clone(),
         };
 
         if child.depth >= CONTEXT_MAX_DEPTH {
@@ -183,11 +342,533 @@ impl BaseCtx {
         Ok(child)
     }
 
+    /// Returns the capabilities of a child context at the path, the tool contexts are
+    /// narrowed to the capabilities of their tool.
+    fn child_capabilities(&self, path: &Path) -> Capabilities {
+        match path
+            .as_ref()
+            .strip_prefix("T:")
+            .and_then(|tool| self.tool_capabilities.get(tool))
+        {
+            Some(caps) => self.capabilities.intersect(caps),
+            None => self.capabilities,
+        }
+    }
+
+    /// Returns the capabilities of the context.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Narrows the capabilities of the context and of its children, they can't be widened.
+    pub fn restrict_capabilities(&mut self, caps: &Capabilities) {
+        self.capabilities = self.capabilities.intersect(caps);
+    }
+
+    /// Returns the backend storage.
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Evicts a key from the cache of the agent or tool at `path`, e.g. "T:{name}".
+    pub(crate) async fn cache_evict(&self, path: &Path, key: &str) -> Result<bool, BoxError> {
+        if !self.cache.has_path(path) {
+            return Err(format!("cache {} not found", path).into());
+        }
+        Ok(self.cache.delete(path, key).await)
+    }
+
+    /// Returns the statistics of the caches of all agents and tools.
+    pub(crate) fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Returns the entries of the caches of all agents and tools.
+    pub(crate) fn cache_snapshot(&self) -> Vec<CacheEntrySnapshot> {
+        self.cache
+            .entries()
+            .into_iter()
+            .map(|(path, key, val)| CacheEntrySnapshot::new(path, &key, &val.0, &val.1))
+            .collect()
+    }
+
+    /// Restores cache entries, skipping those of agents and tools no longer registered and
+    /// the keys already cached.
+    /// Returns the number of restored entries.
+    pub(crate) async fn cache_restore(&self, entries: &[CacheEntrySnapshot]) -> usize {
+        let mut restored = 0;
+        for entry in entries {
+            if self
+                .cache
+                .insert_raw(
+                    &Path::from(entry.path.as_str()),
+                    entry.key.clone(),
+                    Bytes::from(entry.value.0.clone()),
+                    entry.expiry(),
+                )
+                .await
+            {
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    /// Spawns a background job on behalf of the caller, see [`crate::jobs`].
+    pub async fn spawn_job(&self, spec: JobSpec) -> Result<JobInfo, BoxError> {
+        let jobs = self.jobs.as_ref().ok_or("background jobs not enabled")?;
+        jobs.spawn(self.caller, self.path.to_string(), spec).await
+    }
+
+    /// Lists the background jobs of the caller.
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .as_ref()
+            .map(|jobs| jobs.list(Some(&self.caller)))
+            .unwrap_or_default()
+    }
+
+    /// Gets a background job of the caller.
+    pub fn job(&self, id: &str) -> Option<JobInfo> {
+        self.jobs
+            .as_ref()?
+            .get(id)
+            .filter(|job| job.caller == self.caller)
+    }
+
+    /// Cancels a background job of the caller, returns false if it is not found or finished.
+    pub async fn cancel_job(&self, id: &str) -> Result<bool, BoxError> {
+        match (&self.jobs, self.job(id)) {
+            (Some(jobs), Some(_)) => jobs.cancel(id).await,
+            _ => Ok(false),
+        }
+    }
+
+    /// Gets the request-scoped extensions to modify them, see [`StateFeatures::extensions`].
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns true if the caller, or its end user, may call the tool.
+    pub(crate) fn is_tool_allowed(&self, tool: &str) -> bool {
+        self.access_policy
+            .load()
+            .is_tool_allowed(&self.caller, self.meta.user.as_deref(), tool)
+    }
+
+    /// Returns true if the caller, or its end user, may run the agent.
+    pub(crate) fn is_agent_allowed(&self, agent: &str) -> bool {
+        self.access_policy
+            .load()
+            .is_agent_allowed(&self.caller, self.meta.user.as_deref(), agent)
+    }
+
+    /// Checks the tool call against the access policy.
+    pub(crate) fn check_tool_access(&self, tool: &str) -> Result<(), BoxError> {
+        self.access_policy
+            .load()
+            .check_tool(&self.caller, self.meta.user.as_deref(), tool)
+    }
+
+    /// Checks the agent run against the access policy.
+    pub(crate) fn check_agent_access(&self, agent: &str) -> Result<(), BoxError> {
+        self.access_policy
+            .load()
+            .check_agent(&self.caller, self.meta.user.as_deref(), agent)
+    }
+
+    /// Records usage of the caller if metering is enabled, and of the user if the user quotas
+    /// are enabled.
+    pub(crate) fn meter(&self, f: impl FnOnce(&mut UsageCounters)) {
+        if self.metering.is_none() && self.quotas.is_none() {
+            return;
+        }
+        let mut usage = UsageCounters::default();
+        f(&mut usage);
+        if let Some(metering) = &self.metering {
+            metering.record(self.caller, |u| u.accumulate(&usage));
+        }
+        if let Some(quotas) = &self.quotas {
+            quotas.record(
+                &quota_user(&self.caller, &self.meta),
+                self.clock.now_ms(),
+                |u| u.accumulate(&usage),
+            );
+        }
+    }
+
+    /// Records an operation in the audit log if it is enabled.
+    pub(crate) async fn audit(
+        &self,
+        action: AuditAction,
+        target: String,
+        detail: Option<Value>,
+    ) -> Result<(), BoxError> {
+        if let Some(audit) = &self.audit {
+            audit
+                .record(self.caller, &self.path, action, target, detail)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Records a call of an audited tool in the audit log if it is enabled.
+    /// The `sys_` tools are recorded as admin actions.
+    pub(crate) async fn audit_tool_call(&self, name: &str, args: &Value) -> Result<(), BoxError> {
+        match &self.audit {
+            Some(audit) if audit.is_audited_tool(name) => {
+                let action = if name.starts_with("sys_") {
+                    AuditAction::Admin
+                } else {
+                    AuditAction::ToolCall
+                };
+                audit
+                    .record(
+                        self.caller,
+                        &self.path,
+                        action,
+                        name.to_string(),
+                        Some(args.clone()),
+                    )
+                    .await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the digest of the arguments of a call if the calls are audited.
+    pub(crate) fn audit_call_digest<T: Serialize>(&self, args: &T) -> Option<ByteArrayB64<32>> {
+        match &self.audit {
+            Some(audit) if audit.records_calls() => Some(json_digest(args)),
+            _ => None,
+        }
+    }
+
+    /// Records a completed tool call or agent run in the audit log if the calls are audited,
+    /// `args_sha3` is given by [`BaseCtx::audit_call_digest`].
+    /// The call has completed, so a failure to record it is logged instead of returned.
+    pub(crate) async fn audit_call<T: Serialize>(
+        &self,
+        action: AuditAction,
+        name: &str,
+        args_sha3: Option<ByteArrayB64<32>>,
+        res: Result<(&T, &Usage), &BoxError>,
+        started: Instant,
+    ) {
+        let (Some(audit), Some(args_sha3)) = (&self.audit, args_sha3) else {
+            return;
+        };
+        let mut record = CallRecord {
+            args_sha3,
+            duration_ms: self.elapsed_since(started).as_millis() as u64,
+            ..Default::default()
+        };
+        match res {
+            Ok((output, usage)) => {
+                record.result_sha3 = Some(json_digest(output));
+                record.usage = usage.clone();
+            }
+            Err(err) => record.error = Some(redact(&err.to_string())),
+        }
+        let res = audit
+            .record(
+                self.caller,
+                &self.path,
+                action,
+                name.to_string(),
+                Some(json!(record)),
+            )
+            .await;
+        if let Err(err) = res {
+            log::error!("failed to audit the call of {}: {}", name, err);
+        }
+    }
+
+    /// Records a signing operation in the audit log if it is enabled.
+    async fn audit_sign(
+        &self,
+        scheme: &str,
+        derivation_path: &[Vec<u8>],
+        message: &[u8],
+    ) -> Result<(), BoxError> {
+        if self.audit.is_none() {
+            return Ok(());
+        }
+        let detail = json!({
+            "derivation_path": derivation_path
+                .iter()
+                .map(|p| ByteBufB64(p.clone()))
+                .collect::<Vec<_>>(),
+            "message_sha3": ByteArrayB64(sha3_256(message)),
+        });
+        self.audit(AuditAction::Sign, scheme.to_string(), Some(detail))
+            .await
+    }
+
+    /// Returns the store namespace and key derivation root of the context,
+    /// within the namespace of the caller's tenant if any.
+    fn scope(&self) -> Path {
+        match &self.tenant {
+            Some(tenant) => tenant.scoped(&self.path),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Returns the cache key within the namespace of the caller's tenant, or of the callers
+    /// without a tenant.
+    fn cache_key(&self, key: &str) -> String {
+        Tenant::cache_key(self.tenant.as_deref(), key)
+    }
+
+    /// Returns the access labels of the caller in the vector store,
+    /// `user:{principal}` and `tenant:{id}` if the caller is a member of a tenant.
+    pub(crate) fn access_labels(&self) -> Vec<String> {
+        let mut labels = vec![KnowledgeScope::User(self.caller).to_string()];
+        if let Some(tenant) = &self.tenant {
+            labels.push(KnowledgeScope::Tenant(tenant.id().to_string()).to_string());
+        }
+        labels
+    }
+
+    /// Returns the access labels that the caller may write in the vector store, its access
+    /// labels and [`VECTOR_ACL_PUBLIC`] for the controller and managers of the engine.
+    fn writable_labels(&self) -> Vec<String> {
+        let mut labels = self.access_labels();
+        if self.managers.contains(&self.caller) {
+            labels.push(VECTOR_ACL_PUBLIC.to_string());
+        }
+        labels
+    }
+
+    /// Checks that the documents, existing or new, are writable by the caller.
+    /// The documents without access labels are labeled with the caller, `user:{principal}`.
+    async fn check_vector_access(&self, docs: &mut [VectorDocument]) -> Result<(), BoxError> {
+        let labels = self.writable_labels();
+        for doc in docs.iter_mut() {
+            match doc.meta.get(VECTOR_ACL_KEY) {
+                None => {
+                    let label = KnowledgeScope::User(self.caller).to_string();
+                    doc.meta
+                        .insert(VECTOR_ACL_KEY.to_string(), vec![label].into());
+                }
+                Some(acl) => {
+                    let valid = match acl {
+                        Value::Array(values) => {
+                            !values.is_empty()
+                                && values.iter().all(|v| {
+                                    v.as_str().is_some_and(|s| labels.iter().any(|l| l == s))
+                                })
+                        }
+                        Value::String(s) => labels.contains(s),
+                        _ => false,
+                    };
+                    if !valid {
+                        return Err(format!(
+                            "invalid access labels of document {}: {}",
+                            doc.id, acl
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+
+        // the documents of other callers, and the public ones, can't be replaced
+        let filter = VectorFilter::In {
+            key: VECTOR_ACL_KEY.to_string(),
+            values: labels.into_iter().map(Value::from).collect(),
+        };
+        let existing = self
+            .vectors
+            .vector_get(docs.iter().map(|d| d.id.clone()).collect())
+            .await?;
+        if let Some(doc) = existing.iter().find(|d| !filter.matches(&d.meta)) {
+            return Err(format!("document {} is not accessible", doc.id).into());
+        }
+        Ok(())
+    }
+
+    /// Returns the tool name if this is a tool context.
+    fn tool_name(&self) -> Option<&str> {
+        self.path.as_ref().strip_prefix("T:")
+    }
+
+    /// Returns the agent name if this is an agent context.
+    pub(crate) fn agent_name(&self) -> Option<&str> {
+        self.path.as_ref().strip_prefix("A:")
+    }
+
+    /// Emits a progress event if the execution is streamed.
+    pub(crate) fn emit(&self, event: AgentEvent) {
+        if let Some(events) = &self.events {
+            // the receiver may have been dropped by a disconnected client
+            let _ = events.send(event);
+        }
+    }
+
+    /// Checks the request URL against the engine's [`HttpPolicy`].
+    fn check_http(&self, url: &str) -> Result<(), BoxError> {
+        Capabilities::check(self.capabilities.http, "http")?;
+        self.http_policy.load().check_url(self.tool_name(), url)
+    }
+
+    /// Makes an HTTPs request and follows its redirects up to `opts.redirect`.
+    /// The client doesn't follow redirects itself, so every hop is checked against the
+    /// [`HttpPolicy`], and the final URL too for clients that follow them anyway.
+    async fn https_follow(
+        &self,
+        url: &str,
+        mut method: http::Method,
+        mut headers: Option<http::HeaderMap>,
+        mut body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        let max_redirects = opts.redirect.max_redirects();
+        let opts = opts.with_redirect(RedirectPolicy::None);
+        let mut url = url.to_string();
+        let mut redirects = 0;
+        loop {
+            self.check_http(&url)?;
+            let res = self
+                .https_send(&url, method.clone(), headers.clone(), body.clone(), &opts)
+                .await?;
+            self.check_http(res.url().as_str())?;
+            if max_redirects == 0 {
+                return Ok(res);
+            }
+            let next = match follow_redirect(&res, &mut method, &mut headers, &mut body)? {
+                Some(next) => next,
+                None => return Ok(res),
+            };
+            if redirects >= max_redirects {
+                return Err(format!("too many redirects from {}", url_host(&url)).into());
+            }
+            redirects += 1;
+            url = next.into();
+        }
+    }
+
+    /// Makes an HTTPs request, retrying transient failures if `opts.retry` is set.
+    async fn https_send(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: &HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        match opts.retry.clone() {
+            Some(policy) => {
+                http_retry_with_clock(
+                    &policy,
+                    &method,
+                    &self.cancellation_token,
+                    self.clock.as_ref(),
+                    || {
+                        let (method, headers, body, opts) =
+                            (method.clone(), headers.clone(), body.clone(), opts.clone());
+                        async move {
+                            self.web3
+                                .as_ref()
+                                .https_call_with_options(url, method, headers, body, opts)
+                                .await
+                        }
+                    },
+                )
+                .await
+            }
+            None => {
+                self.web3
+                    .as_ref()
+                    .https_call_with_options(url, method, headers, body, opts.clone())
+                    .await
+            }
+        }
+    }
+
     pub(crate) fn self_meta(&self, target: Principal) -> RequestMeta {
         RequestMeta {
             engine: Some(target),
             thread: self.meta.thread.clone(),
             user: Some(self.name.clone()),
+            priority: self.meta.priority,
+            timeout_ms: self.time_remaining().map(|d| d.as_millis() as u64),
+            locale: self.meta.locale.clone(),
+        }
+    }
+
+    /// Returns the time elapsed since the instant of the context's clock.
+    pub(crate) fn elapsed_since(&self, started: Instant) -> Duration {
+        self.clock.instant().saturating_duration_since(started)
+    }
+
+    /// Sets the clock of the context and of its children, restarting the elapsed time.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.start_at = clock.instant();
+        self.clock = clock;
+    }
+
+    /// Runs the future until the context is cancelled or its deadline is exceeded.
+    pub(crate) async fn guard<T>(
+        &self,
+        fut: impl Future<Output = Result<T, BoxError>>,
+    ) -> Result<T, BoxError> {
+        match self.time_remaining() {
+            Some(remaining) => {
+                with_cancellation(&self.cancellation_token, async {
+                    tokio::time::timeout(remaining, fut)
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Error::Timeout("request deadline exceeded".to_string()).into())
+                        })
+                })
+                .await
+            }
+            None => with_cancellation(&self.cancellation_token, fut).await,
+        }
+    }
+
+    /// Calls the remote tool named `tool_name` on the engine at the endpoint, through the tool
+    /// middlewares with the local name of the tool in `input`.
+    pub(crate) async fn remote_tool_call_with(
+        &self,
+        endpoint: &str,
+        tool_name: String,
+        mut input: ToolInput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        let name = std::mem::replace(&mut input.name, tool_name);
+        self.tool_middlewares
+            .before(self, &name, &mut input.args)
+            .await?;
+        let res = self
+            .guard_tool(&name, None, self.remote_tool_call(endpoint, input))
+            .await;
+        self.tool_middlewares.after(self, &name, res).await
+    }
+
+    /// Runs a tool call like [`BaseCtx::guard`], until the timeout of the tool or the tool
+    /// timeout of the engine, if any. The context of the call is cancelled when it times out,
+    /// so is the work it spawned.
+    pub(crate) async fn guard_tool<T>(
+        &self,
+        name: &str,
+        timeout: Option<Duration>,
+        fut: impl Future<Output = Result<T, BoxError>>,
+    ) -> Result<T, BoxError> {
+        match timeout.or(self.tool_timeout) {
+            Some(timeout) => tokio::time::timeout(timeout, self.guard(fut))
+                .await
+                .unwrap_or_else(|_| {
+                    self.cancellation_token.cancel();
+                    Err(Error::Timeout(format!(
+                        "tool {} timed out after {} ms",
+                        name,
+                        timeout.as_millis()
+                    ))
+                    .into())
+                }),
+            None => self.guard(fut).await,
         }
     }
 }
@@ -208,12 +889,29 @@ impl BaseContext for BaseCtx {
     ) -> Result<ToolOutput<Value>, BoxError> {
         let target = self
             .remote
+            .load()
             .get_id_by_endpoint(endpoint)
             .ok_or_else(|| format!("remote engine endpoint {} not found", endpoint))?;
+        self.remote_health.check(endpoint)?;
         args.meta = Some(self.self_meta(target));
+        self.meter(|u| u.remote_calls += 1);
         self.https_signed_rpc(endpoint, "tool_call", &(&args,))
             .await
+            .map_err(|err| remote_error(endpoint, err))
+    }
+}
+
+/// Wraps the error of a remote call in an [`Error::Remote`],
+/// unless it is already structured, e.g. a cancellation.
+pub(crate) fn remote_error(endpoint: &str, err: BoxError) -> BoxError {
+    if anda_error(&err).is_some() {
+        return err;
     }
+    Error::Remote {
+        endpoint: endpoint.to_string(),
+        message: err.to_string(),
+    }
+    .into()
 }
 
 impl CacheStoreFeatures for BaseCtx {}
@@ -240,7 +938,71 @@ impl StateFeatures for BaseCtx {
     }
 
     fn time_elapsed(&self) -> Duration {
-        self.start_at.elapsed()
+        self.clock
+            .instant()
+            .saturating_duration_since(self.start_at)
+    }
+
+    fn time_remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(self.clock.instant()))
+    }
+
+    fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    fn fill_random(&self, buf: &mut [u8]) {
+        self.random.fill_bytes(buf)
+    }
+}
+
+impl LogFeatures for BaseCtx {
+    /// Writes a log entry tagged with the engine, the path, the caller, the user and the
+    /// request ID of the context. String fields are written as is, the others as JSON.
+    fn log(&self, level: LogLevel, message: &str, fields: &[(&str, Value)]) {
+        let level = match level {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+        };
+        if level > log::max_level() {
+            return;
+        }
+
+        let path = self.path.to_string();
+        let caller = self.caller.to_text();
+        let mut kvs: Vec<(&str, log::kv::Value)> = vec![
+            ("engine", log::kv::Value::from(self.name.as_str())),
+            ("path", log::kv::Value::from(path.as_str())),
+            ("caller", log::kv::Value::from(caller.as_str())),
+        ];
+        if let Some(user) = &self.meta.user {
+            kvs.push(("user", log::kv::Value::from(user.as_str())));
+        }
+        if let Some(id) = self.extensions.get::<RequestId>() {
+            kvs.push(("request_id", log::kv::Value::from(id.0)));
+        }
+        for (key, value) in fields {
+            let value = match value {
+                Value::String(v) => log::kv::Value::from(v.as_str()),
+                v => log::kv::Value::from_display(v),
+            };
+            kvs.push((*key, value));
+        }
+        log::logger().log(
+            &log::Record::builder()
+                .level(level)
+                .target(module_path!())
+                .args(format_args!("{}", message))
+                .key_values(&kvs.as_slice())
+                .build(),
+        );
     }
 }
 
@@ -249,11 +1011,11 @@ impl KeysFeatures for BaseCtx {
     async fn a256gcm_key(&self, derivation_path: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError> {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
-                cli.a256gcm_key(derivation_path_with(&self.path, derivation_path))
+                cli.a256gcm_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
-                cli.a256gcm_key(derivation_path_with(&self.path, derivation_path))
+                cli.a256gcm_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
         }
@@ -265,14 +1027,23 @@ impl KeysFeatures for BaseCtx {
         derivation_path: Vec<Vec<u8>>,
         message: &[u8],
     ) -> Result<[u8; 64], BoxError> {
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        self.audit_sign("ed25519", &derivation_path, message)
+            .await?;
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
-                cli.ed25519_sign_message(derivation_path_with(&self.path, derivation_path), message)
-                    .await
+                cli.ed25519_sign_message(
+                    derivation_path_with(&self.scope(), derivation_path),
+                    message,
+                )
+                .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
-                cli.ed25519_sign_message(derivation_path_with(&self.path, derivation_path), message)
-                    .await
+                cli.ed25519_sign_message(
+                    derivation_path_with(&self.scope(), derivation_path),
+                    message,
+                )
+                .await
             }
         }
     }
@@ -287,7 +1058,7 @@ impl KeysFeatures for BaseCtx {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.ed25519_verify(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                     signature,
                 )
@@ -295,7 +1066,7 @@ impl KeysFeatures for BaseCtx {
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.ed25519_verify(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                     signature,
                 )
@@ -311,11 +1082,11 @@ impl KeysFeatures for BaseCtx {
     ) -> Result<[u8; 32], BoxError> {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
-                cli.ed25519_public_key(derivation_path_with(&self.path, derivation_path))
+                cli.ed25519_public_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
-                cli.ed25519_public_key(derivation_path_with(&self.path, derivation_path))
+                cli.ed25519_public_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
         }
@@ -327,17 +1098,20 @@ impl KeysFeatures for BaseCtx {
         derivation_path: Vec<Vec<u8>>,
         message: &[u8],
     ) -> Result<[u8; 64], BoxError> {
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        self.audit_sign("secp256k1_bip340", &derivation_path, message)
+            .await?;
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.secp256k1_sign_message_bip340(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                 )
                 .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.secp256k1_sign_message_bip340(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                 )
                 .await
@@ -355,7 +1129,7 @@ impl KeysFeatures for BaseCtx {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.secp256k1_verify_bip340(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                     signature,
                 )
@@ -363,7 +1137,7 @@ impl KeysFeatures for BaseCtx {
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.secp256k1_verify_bip340(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                     signature,
                 )
@@ -379,17 +1153,20 @@ impl KeysFeatures for BaseCtx {
         derivation_path: Vec<Vec<u8>>,
         message: &[u8],
     ) -> Result<[u8; 64], BoxError> {
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        self.audit_sign("secp256k1_ecdsa", &derivation_path, message)
+            .await?;
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.secp256k1_sign_message_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                 )
                 .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.secp256k1_sign_message_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message,
                 )
                 .await
@@ -403,17 +1180,20 @@ impl KeysFeatures for BaseCtx {
         derivation_path: Vec<Vec<u8>>,
         message_hash: &[u8],
     ) -> Result<[u8; 64], BoxError> {
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        self.audit_sign("secp256k1_ecdsa_digest", &derivation_path, message_hash)
+            .await?;
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.secp256k1_sign_digest_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message_hash,
                 )
                 .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.secp256k1_sign_digest_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message_hash,
                 )
                 .await
@@ -431,7 +1211,7 @@ impl KeysFeatures for BaseCtx {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
                 cli.secp256k1_verify_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message_hash,
                     signature,
                 )
@@ -439,7 +1219,7 @@ impl KeysFeatures for BaseCtx {
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
                 cli.secp256k1_verify_ecdsa(
-                    derivation_path_with(&self.path, derivation_path),
+                    derivation_path_with(&self.scope(), derivation_path),
                     message_hash,
                     signature,
                 )
@@ -455,11 +1235,11 @@ impl KeysFeatures for BaseCtx {
     ) -> Result<[u8; 33], BoxError> {
         match self.web3.as_ref() {
             Web3SDK::Tee(cli) => {
-                cli.secp256k1_public_key(derivation_path_with(&self.path, derivation_path))
+                cli.secp256k1_public_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
             Web3SDK::Web3(Web3Client { client: cli }) => {
-                cli.secp256k1_public_key(derivation_path_with(&self.path, derivation_path))
+                cli.secp256k1_public_key(derivation_path_with(&self.scope(), derivation_path))
                     .await
             }
         }
@@ -469,7 +1249,20 @@ impl KeysFeatures for BaseCtx {
 impl StoreFeatures for BaseCtx {
     /// Retrieves data from storage at the specified path.
     async fn store_get(&self, path: &Path) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
-        self.store.store_get(&self.path, path).await
+        self.store.store_get(&self.scope(), path).await
+    }
+
+    /// Retrieves a byte range of the object at the specified path.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the object;
+    /// * `range` - Byte range to read, its end is clamped to the size of the object.
+    async fn store_get_range(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+    ) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
+        self.store.store_get_range(&self.scope(), path, range).await
     }
 
     /// Lists objects in storage with optional prefix and offset filters.
@@ -482,7 +1275,15 @@ impl StoreFeatures for BaseCtx {
         prefix: Option<&Path>,
         offset: &Path,
     ) -> Result<Vec<ObjectMeta>, BoxError> {
-        self.store.store_list(&self.path, prefix, offset).await
+        self.store.store_list(&self.scope(), prefix, offset).await
+    }
+
+    /// Lists a page of objects in storage, ordered by path and filtered by the options.
+    ///
+    /// # Arguments
+    /// * `options` - Prefix, page size, continuation token and filters of the listing.
+    async fn store_list_page(&self, options: &StoreListOptions) -> Result<StoreListPage, BoxError> {
+        self.store.store_list_page(&self.scope(), options).await
     }
 
     /// Stores data at the specified path with a given write mode.
@@ -497,7 +1298,62 @@ impl StoreFeatures for BaseCtx {
         mode: PutMode,
         value: bytes::Bytes,
     ) -> Result<PutResult, BoxError> {
-        self.store.store_put(&self.path, path, mode, value).await
+        Capabilities::check(self.capabilities.store_write, "store_write")?;
+        self.audit(AuditAction::StorePut, path.to_string(), None)
+            .await?;
+        self.meter(|u| u.storage_bytes += value.len() as u64);
+        self.store.store_put(&self.scope(), path, mode, value).await
+    }
+
+    /// Stores data at the specified path with user-defined metadata.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `mode` - Write mode (Create, Overwrite, etc.);
+    /// * `metadata` - User-defined metadata of the object;
+    /// * `value` - Data to store as bytes.
+    async fn store_put_with_metadata(
+        &self,
+        path: &Path,
+        mode: PutMode,
+        metadata: BTreeMap<String, String>,
+        value: bytes::Bytes,
+    ) -> Result<PutResult, BoxError> {
+        Capabilities::check(self.capabilities.store_write, "store_write")?;
+        self.audit(AuditAction::StorePut, path.to_string(), None)
+            .await?;
+        self.meter(|u| u.storage_bytes += value.len() as u64);
+        self.store
+            .store_put_with_metadata(&self.scope(), path, mode, metadata, value)
+            .await
+    }
+
+    /// Stores the chunks of a stream at the specified path with a multipart upload.
+    ///
+    /// # Arguments
+    /// * `path` - Target storage path;
+    /// * `chunks` - Stream of the chunks of the object.
+    async fn store_put_stream(
+        &self,
+        path: &Path,
+        chunks: BoxStream<'static, Result<bytes::Bytes, BoxError>>,
+    ) -> Result<PutResult, BoxError> {
+        Capabilities::check(self.capabilities.store_write, "store_write")?;
+        self.audit(AuditAction::StorePut, path.to_string(), None)
+            .await?;
+        let size = Arc::new(AtomicU64::new(0));
+        let counter = size.clone();
+        let chunks = chunks
+            .inspect_ok(move |chunk| {
+                counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            })
+            .boxed();
+        let res = self
+            .store
+            .store_put_stream(&self.scope(), path, chunks)
+            .await;
+        self.meter(|u| u.storage_bytes += size.load(Ordering::Relaxed));
+        res
     }
 
     /// Renames a storage object if the target path doesn't exist.
@@ -506,8 +1362,15 @@ impl StoreFeatures for BaseCtx {
     /// * `from` - Source path;
     /// * `to` - Destination path.
     async fn store_rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<(), BoxError> {
+        Capabilities::check(self.capabilities.store_write, "store_write")?;
+        self.audit(
+            AuditAction::StoreRename,
+            from.to_string(),
+            Some(json!({ "to": to.to_string() })),
+        )
+        .await?;
         self.store
-            .store_rename_if_not_exists(&self.path, from, to)
+            .store_rename_if_not_exists(&self.scope(), from, to)
             .await
     }
 
@@ -516,14 +1379,81 @@ impl StoreFeatures for BaseCtx {
     /// # Arguments
     /// * `path` - Path of the object to delete.
     async fn store_delete(&self, path: &Path) -> Result<(), BoxError> {
-        self.store.store_delete(&self.path, path).await
+        Capabilities::check(self.capabilities.store_write, "store_write")?;
+        self.audit(AuditAction::StoreDelete, path.to_string(), None)
+            .await?;
+        self.store.store_delete(&self.scope(), path).await
+    }
+}
+
+/// The vector store of the engine is shared by all callers, the documents are scoped by their
+/// [`VECTOR_ACL_KEY`] metadata: a caller reads the documents with one of its labels (see
+/// [`BaseCtx::access_labels`]) or [`VECTOR_ACL_PUBLIC`], and only replaces and deletes the
+/// ones with its labels; the controller and managers of the engine also write public ones.
+impl VectorStoreFeatures for BaseCtx {
+    /// Inserts or replaces documents in the vector store of the engine.
+    /// The access labels of the documents must be the caller's, the documents without
+    /// labels are only visible to the caller by default.
+    async fn vector_upsert(&self, mut docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        Capabilities::check(self.capabilities.vector_write, "vector_write")?;
+        self.check_vector_access(&mut docs).await?;
+        self.vectors.vector_upsert(docs).await
+    }
+
+    /// Gets the documents visible to the caller by IDs from the vector store of the engine.
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        let filter = VectorFilter::acl(self.access_labels());
+        let mut docs = self.vectors.vector_get(ids).await?;
+        docs.retain(|d| filter.matches(&d.meta));
+        Ok(docs)
+    }
+
+    /// Deletes the documents writable by the caller from the vector store of the engine.
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        Capabilities::check(self.capabilities.vector_write, "vector_write")?;
+        let filter = VectorFilter::In {
+            key: VECTOR_ACL_KEY.to_string(),
+            values: self
+                .writable_labels()
+                .into_iter()
+                .map(Value::from)
+                .collect(),
+        };
+        let ids: Vec<String> = self
+            .vectors
+            .vector_get(ids)
+            .await?
+            .into_iter()
+            .filter(|d| filter.matches(&d.meta))
+            .map(|d| d.id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        self.vectors.vector_delete(ids).await
+    }
+
+    /// Finds the top k documents visible to the caller most similar to the query vector
+    /// and matching the filter.
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        let acl = VectorFilter::acl(self.access_labels());
+        let filter = match filter {
+            Some(filter) => acl.and(filter),
+            None => acl,
+        };
+        self.vectors.vector_search(query, top_k, Some(filter)).await
     }
 }
 
 impl CacheFeatures for BaseCtx {
     /// Checks if a key exists in the cache.
     fn cache_contains(&self, key: &str) -> bool {
-        self.cache.contains(&self.path, key)
+        self.cache.contains(&self.path, &self.cache_key(key))
     }
 
     /// Gets a cached value by key, returns error if not found or deserialization fails.
@@ -531,7 +1461,7 @@ impl CacheFeatures for BaseCtx {
     where
         T: DeserializeOwned,
     {
-        self.cache.get(&self.path, key).await
+        self.cache.get(&self.path, &self.cache_key(key)).await
     }
 
     /// Gets a cached value or initializes it if missing.
@@ -542,7 +1472,9 @@ impl CacheFeatures for BaseCtx {
         T: Sized + DeserializeOwned + Serialize + Send,
         F: Future<Output = Result<(T, Option<CacheExpiry>), BoxError>> + Send + 'static,
     {
-        self.cache.get_with(&self.path, key, init).await
+        self.cache
+            .get_with(&self.path, &self.cache_key(key), init)
+            .await
     }
 
     /// Sets a value in cache with optional expiration policy.
@@ -550,7 +1482,7 @@ impl CacheFeatures for BaseCtx {
     where
         T: Sized + Serialize + Send,
     {
-        self.cache.set(&self.path, key, val).await
+        self.cache.set(&self.path, &self.cache_key(key), val).await
     }
 
     /// Sets a value in cache if key doesn't exist, returns true if set.
@@ -558,19 +1490,26 @@ impl CacheFeatures for BaseCtx {
     where
         T: Sized + Serialize + Send,
     {
-        self.cache.set_if_not_exists(&self.path, key, val).await
+        self.cache
+            .set_if_not_exists(&self.path, &self.cache_key(key), val)
+            .await
     }
 
     /// Deletes a cached value by key, returns true if key existed.
     async fn cache_delete(&self, key: &str) -> bool {
-        self.cache.delete(&self.path, key).await
+        self.cache.delete(&self.path, &self.cache_key(key)).await
     }
 
-    /// Returns an iterator over all cached items with raw value.
+    /// Returns an iterator over the cached items with raw value in the namespace of the
+    /// caller's tenant, or of the callers without a tenant.
     fn cache_raw_iter(
         &self,
     ) -> impl Iterator<Item = (Arc<String>, Arc<(Bytes, Option<CacheExpiry>)>)> {
-        self.cache.iter(&self.path)
+        let prefix = self.cache_key("");
+        self.cache.iter(&self.path).filter_map(move |(key, val)| {
+            key.strip_prefix(prefix.as_str())
+                .map(|key| (Arc::new(key.to_string()), val))
+        })
     }
 }
 
@@ -581,6 +1520,12 @@ impl CanisterCaller for BaseCtx {
     /// * `canister` - Target canister principal;
     /// * `method` - Method name to call;
     /// * `args` - Input arguments encoded in Candid format.
+    #[tracing::instrument(skip_all, fields(
+        canister = %canister,
+        method = method,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
     async fn canister_query<
         In: ArgumentEncoder + Send,
         Out: CandidType + for<'a> candid::Deserialize<'a>,
@@ -590,18 +1535,26 @@ impl CanisterCaller for BaseCtx {
         method: &str,
         args: In,
     ) -> Result<Out, BoxError> {
-        self.web3
-            .as_ref()
-            .canister_query(canister, method, args)
-            .await
+        record_error(
+            self.guard(self.web3.as_ref().canister_query(canister, method, args))
+                .await,
+        )
     }
 
     /// Performs an update call to a canister (may modify state).
+    /// The call is checked against the capabilities of the context and the engine's
+    /// [`CanisterPolicy`].
     ///
     /// # Arguments
     /// * `canister` - Target canister principal;
     /// * `method` - Method name to call;
     /// * `args` - Input arguments encoded in Candid format.
+    #[tracing::instrument(skip_all, fields(
+        canister = %canister,
+        method = method,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
     async fn canister_update<
         In: ArgumentEncoder + Send,
         Out: CandidType + for<'a> candid::Deserialize<'a>,
@@ -611,21 +1564,26 @@ impl CanisterCaller for BaseCtx {
         method: &str,
         args: In,
     ) -> Result<Out, BoxError> {
-        self.web3
-            .as_ref()
-            .canister_update(canister, method, args)
-            .await
+        Capabilities::check(self.capabilities.canister_update, "canister_update")?;
+        self.canister_policy.load().check_update(canister, method)?;
+        record_error(
+            self.guard(self.web3.as_ref().canister_update(canister, method, args))
+                .await,
+        )
     }
 }
 
+/// The HTTPs requests are aborted when the context is cancelled or its deadline is exceeded.
 impl HttpFeatures for BaseCtx {
     /// Makes an HTTPs request.
+    /// Transient failures are retried with the engine's HTTP retry policy if set.
     ///
     /// # Arguments
     /// * `url` - Target URL, should start with `https://`;
     /// * `method` - HTTP method (GET, POST, etc.);
     /// * `headers` - Optional HTTP headers;
     /// * `body` - Optional request body (default empty).
+    #[tracing::instrument(skip_all, fields(http.method = %method, http.host = url_host(url)))]
     async fn https_call(
         &self,
         url: &str,
@@ -633,9 +1591,33 @@ impl HttpFeatures for BaseCtx {
         headers: Option<http::HeaderMap>,
         body: Option<Vec<u8>>,
     ) -> Result<reqwest::Response, BoxError> {
-        self.web3
-            .as_ref()
-            .https_call(url, method, headers, body)
+        let opts = HttpOptions {
+            retry: self.http_retry.as_deref().cloned(),
+            ..Default::default()
+        };
+        self.guard(self.https_follow(url, method, headers, body, opts))
+            .await
+    }
+
+    /// Makes an HTTPs request with per-request options.
+    /// Transient failures are retried if `opts.retry` is set, until the context is cancelled.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`;
+    /// * `method` - HTTP method (GET, POST, etc.);
+    /// * `headers` - Optional HTTP headers;
+    /// * `body` - Optional request body (default empty);
+    /// * `opts` - Timeouts, redirect policy and response size limit.
+    #[tracing::instrument(skip_all, fields(http.method = %method, http.host = url_host(url)))]
+    async fn https_call_with_options(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        self.guard(self.https_follow(url, method, headers, body, opts))
             .await
     }
 
@@ -647,6 +1629,7 @@ impl HttpFeatures for BaseCtx {
     /// * `message_digest` - 32-byte message digest for signing;
     /// * `headers` - Optional HTTP headers;
     /// * `body` - Optional request body (default empty).
+    #[tracing::instrument(skip_all, fields(http.method = %method, http.host = url_host(url)))]
     async fn https_signed_call(
         &self,
         url: &str,
@@ -655,18 +1638,44 @@ impl HttpFeatures for BaseCtx {
         headers: Option<http::HeaderMap>,
         body: Option<Vec<u8>>, // default is empty
     ) -> Result<reqwest::Response, BoxError> {
-        self.web3
-            .as_ref()
-            .https_signed_call(url, method, message_digest, headers, body)
-            .await
+        self.check_http(url)?;
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        self.audit(
+            AuditAction::Sign,
+            "https_signed_call".to_string(),
+            Some(json!({
+                "method": method.as_str(),
+                "host": url_host(url),
+                "message_digest": ByteArrayB64(message_digest),
+            })),
+        )
+        .await?;
+        let res = self
+            .guard(
+                self.web3
+                    .as_ref()
+                    .https_signed_call(url, method, message_digest, headers, body),
+            )
+            .await?;
+        // signed requests are not redirected by the engine's client, but may be by others
+        self.check_http(res.url().as_str())?;
+        Ok(res)
     }
 
     /// Makes a signed CBOR-encoded RPC call.
+    /// Transient failures are retried with the engine's [`RpcRetryPolicy`] if set,
+    /// until the context is cancelled.
     ///
     /// # Arguments
     /// * `endpoint` - URL endpoint to send the request to;
     /// * `method` - RPC method name to call;
     /// * `args` - Arguments to serialize as CBOR and send with the request.
+    #[tracing::instrument(skip_all, fields(
+        rpc.method = method,
+        http.host = url_host(endpoint),
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
     async fn https_signed_rpc<T>(
         &self,
         endpoint: &str,
@@ -676,9 +1685,46 @@ impl HttpFeatures for BaseCtx {
     where
         T: DeserializeOwned,
     {
-        self.web3
-            .as_ref()
-            .https_signed_rpc(endpoint, method, args)
-            .await
+        self.check_http(endpoint)?;
+        Capabilities::check(self.capabilities.sign, "sign")?;
+        // boxed, the signed requests are large futures that would bloat the callers' ones
+        let res = self
+            .guard(Box::pin(async {
+                match &self.rpc_retry {
+                    Some(policy) => {
+                        // encoded once as a CBOR value, to be sent again by the retries
+                        let args: ciborium::Value =
+                            ciborium::from_reader(&ic_cose_types::to_cbor_bytes(&args)[..])?;
+                        let web3 = self.web3.as_ref();
+                        rpc_retry_with_clock(
+                            policy,
+                            method,
+                            &self.cancellation_token,
+                            self.clock.as_ref(),
+                            || web3.https_signed_rpc(endpoint, method, &args),
+                        )
+                        .await
+                    }
+                    None => {
+                        self.web3
+                            .as_ref()
+                            .https_signed_rpc(endpoint, method, args)
+                            .await
+                    }
+                }
+            }))
+            .await;
+        record_error(res)
+    }
+
+    /// Connects to a WebSocket server.
+    /// The connection is closed when the context is cancelled.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `wss://`;
+    /// * `opts` - Handshake headers, keepalive and buffer options.
+    async fn websocket_connect(&self, url: &str, opts: WsOptions) -> Result<WebSocket, BoxError> {
+        self.check_http(url)?;
+        websocket_connect(url, opts, self.cancellation_token.clone(), false).await
     }
 }
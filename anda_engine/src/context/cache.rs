@@ -1,13 +1,16 @@
 //! In-memory caching system for AI Agent components.
 //!
-//! This module provides a thread-safe, in-memory LRU cache implementation with expiration policies
+//! This module provides a thread-safe, in-memory cache implementation with expiration policies
 //! for storing serialized data. The cache is primarily used by AI Agents and Tools to store
 //! frequently accessed data with configurable expiration policies.
 //!
 //! # Key Features
-//! - LRU (Least Recently Used) eviction policy;
-//! - Configurable maximum capacity;
+//! - TinyLFU (Least Frequently Used admission, the default) or LRU (Least Recently Used) eviction policy;
+//! - Configurable maximum capacity, in entries or in bytes, see [`CacheConfig`];
 //! - Time-to-Idle (TTI) and Time-to-Live (TTL) expiration policies;
+//! - Optional background sweeping of the expired entries;
+//! - Optional persistence of the entries across restarts, see [`CacheConfig::persist`];
+//! - Hit, miss and eviction statistics, see [`CacheStats`];
 //! - Thread-safe operations;
 //! - Automatic serialization/deserialization using CBOR format.
 //!
@@ -21,7 +24,7 @@
 //! - Automatic eviction of expired items.
 //!
 //! # Limitations
-//! - Data is not persisted across system restarts;
+//! - Data is not persisted across system restarts, unless persistence is enabled;
 //! - Maximum cache size is limited by available memory;
 //! - Serialization/deserialization overhead for large objects.
 
@@ -30,21 +33,156 @@ use anda_core::context::CacheExpiry;
 use bytes::Bytes;
 use ciborium::from_reader;
 use ic_cose_types::to_cbor_bytes;
-use moka::{future::Cache, policy::Expiry};
+use moka::{
+    future::Cache,
+    notification::RemovalCause,
+    policy::{EvictionPolicy, Expiry},
+};
 use object_store::path::Path;
-use serde::{Serialize, de::DeserializeOwned};
-use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::{BTreeMap, BTreeSet};
 use std::{
     collections::HashMap,
     future::Future,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
+use tokio_util::sync::CancellationToken;
+
+/// Eviction policy of the cache when it is full.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CachePolicy {
+    /// Admits new entries by their estimated frequency of access (TinyLFU), and evicts the
+    /// least recently used ones. Suits most workloads.
+    #[default]
+    Lfu,
+    /// Evicts the least recently used entries, admitting every new entry. Suits workloads
+    /// where recent entries are the most likely to be accessed.
+    Lru,
+}
+
+/// Configuration of the cache of the agents and tools.
+///
+/// Each agent or tool has its own cache with these limits.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Maximum number of entries of each cache, 1,000,000 by default.
+    pub max_entries: u64,
+    /// Maximum size in bytes of each cache, keys and values, instead of the number of entries.
+    pub max_bytes: Option<u64>,
+    /// Eviction policy when a cache is full.
+    pub policy: CachePolicy,
+    /// Maximum time-to-idle of the entries in seconds, 7 days by default.
+    pub max_idle_secs: u64,
+    /// Interval in seconds of the background sweeping of the expired entries. Without it,
+    /// expired entries are not returned but are only removed by later cache operations.
+    pub sweep_interval_secs: Option<u64>,
+    /// Saves the entries to the store on graceful shutdown, and reloads them in the background
+    /// when the engine is built, so that warm caches survive restarts and upgrades.
+    /// The TTL of a reloaded entry restarts from the reload.
+    pub persist: bool,
+    /// Paths of the persisted caches, e.g. "T:{tool}" for an expensive tool, all if empty.
+    pub persist_paths: BTreeSet<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1_000_000,
+            max_bytes: None,
+            policy: CachePolicy::default(),
+            max_idle_secs: 3600 * 24 * 7,
+            sweep_interval_secs: None,
+            persist: false,
+            persist_paths: BTreeSet::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Sets the maximum number of entries of each cache.
+    pub fn with_max_entries(mut self, max_entries: u64) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the maximum size in bytes of each cache.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets the eviction policy.
+    pub fn with_policy(mut self, policy: CachePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sweeps the expired entries in the background at the interval.
+    pub fn with_sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval_secs = Some(interval.as_secs().max(1));
+        self
+    }
+
+    /// Persists the caches at the paths across restarts, all of them if empty.
+    pub fn with_persistence(mut self, paths: BTreeSet<String>) -> Self {
+        self.persist = true;
+        self.persist_paths = paths;
+        self
+    }
+
+    /// Checks if the cache at the path is persisted.
+    pub fn persists(&self, path: &str) -> bool {
+        self.persist && (self.persist_paths.is_empty() || self.persist_paths.contains(path))
+    }
+
+    /// Returns the interval of the background sweeping, if enabled.
+    pub fn sweep_interval(&self) -> Option<Duration> {
+        self.sweep_interval_secs
+            .map(|secs| Duration::from_secs(secs.max(1)))
+    }
+}
+
+/// Statistics of the cache of the agents and tools, since the engine started.
+///
+/// The entry counts and sizes are approximate until the pending maintenance of the cache ran,
+/// e.g. by the background sweeping.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of entries of all caches.
+    pub entries: u64,
+    /// Size of all caches: in bytes if `max_bytes` is configured, else in entries.
+    pub weighted_size: u64,
+    /// Number of lookups that found their key.
+    pub hits: u64,
+    /// Number of lookups that missed their key.
+    pub misses: u64,
+    /// Number of entries evicted because a cache was full.
+    pub evictions: u64,
+    /// Number of entries removed because they expired.
+    pub expirations: u64,
+    /// Number of entries of each agent's or tool's cache, e.g. "T:{name}".
+    pub paths: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
 
 #[derive(Debug)]
 pub(crate) struct CacheService {
     #[allow(clippy::type_complexity)]
     cache_store: HashMap<Path, Cache<String, Arc<(Bytes, Option<CacheExpiry>)>>>,
+    counters: Arc<CacheCounters>,
 }
 
 /// CacheService provides an in-memory LRU cache with expiration for AI Agent system's agents and tools.
@@ -55,36 +193,62 @@ pub(crate) struct CacheService {
 /// Note: Data is cached only in memory and will be lost upon system restart.
 /// For persistent storage, use `StoreFeatures`.
 impl CacheService {
-    /// Creates a new CacheService instance with specified maximum capacity.
+    /// Creates a new CacheService instance with the specified configuration.
     ///
     /// # Arguments
-    /// * `max_capacity` - Maximum number of items the cache can hold (u64);
+    /// * `cfg` - Capacity, eviction policy and maximum time-to-idle of each cache;
     /// * `names` - Set of base paths for cache namespacing.
     ///
     /// # Default Behavior
+    /// - Maximum number of items: 1,000,000;
     /// - Maximum time-to-idle (TTI): 7 days;
     /// - Uses custom expiration policy based on CacheExpiry.
-    pub fn new(max_capacity: u64, names: BTreeSet<Path>) -> Self {
+    pub fn new(cfg: &CacheConfig, names: BTreeSet<Path>) -> Self {
+        let counters = Arc::new(CacheCounters::default());
         Self {
             cache_store: names
                 .into_iter()
                 .map(|k| {
-                    (
-                        k,
-                        Cache::builder()
-                            .max_capacity(max_capacity)
-                            // max TTI is 7 days
-                            .time_to_idle(Duration::from_secs(3600 * 24 * 7))
-                            .expire_after(CacheServiceExpiry)
+                    let counters = counters.clone();
+                    let builder = Cache::builder()
+                        .time_to_idle(Duration::from_secs(cfg.max_idle_secs))
+                        .expire_after(CacheServiceExpiry)
+                        .eviction_policy(match cfg.policy {
+                            CachePolicy::Lfu => EvictionPolicy::tiny_lfu(),
+                            CachePolicy::Lru => EvictionPolicy::lru(),
+                        })
+                        .eviction_listener(move |_key, _value, cause| match cause {
+                            RemovalCause::Size => {
+                                counters.evictions.fetch_add(1, Ordering::Relaxed);
+                            }
+                            RemovalCause::Expired => {
+                                counters.expirations.fetch_add(1, Ordering::Relaxed);
+                            }
+                            _ => {}
+                        });
+                    let cache = match cfg.max_bytes {
+                        Some(max_bytes) => builder
+                            .max_capacity(max_bytes)
+                            .weigher(|key: &String, val: &Arc<(Bytes, Option<CacheExpiry>)>| {
+                                u32::try_from(key.len() + val.0.len()).unwrap_or(u32::MAX)
+                            })
                             .build(),
-                    )
+                        None => builder.max_capacity(cfg.max_entries).build(),
+                    };
+                    (k, cache)
                 })
                 .collect(),
+            counters,
         }
     }
 }
 
 impl CacheService {
+    /// Checks if the cache is created for the given path.
+    pub fn has_path(&self, path: &Path) -> bool {
+        self.cache_store.contains_key(path)
+    }
+
     /// Checks if a key exists in the cache.
     ///
     /// # Arguments
@@ -120,8 +284,10 @@ impl CacheService {
             .get(key)
             .await
         {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
             from_reader(&val.0[..]).map_err(|err| err.into())
         } else {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
             Err(format!("key {} not found", key).into())
         }
     }
@@ -143,11 +309,16 @@ impl CacheService {
         F: Future<Output = Result<(T, Option<CacheExpiry>), BoxError>> + Send + 'static,
     {
         futures_util::pin_mut!(init);
+        let counters = self.counters.clone();
+        // counted as a hit unless the init function runs
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
         match self
             .cache_store
             .get(path)
             .expect("CacheService: cache not found")
             .try_get_with_by_ref(key, async move {
+                counters.hits.fetch_sub(1, Ordering::Relaxed);
+                counters.misses.fetch_add(1, Ordering::Relaxed);
                 match init.await {
                     Ok((val, expiry)) => {
                         let data = to_cbor_bytes(&val);
@@ -236,6 +407,72 @@ impl CacheService {
             .expect("CacheService: cache not found")
             .iter()
     }
+
+    /// Returns the entries of all paths.
+    #[allow(clippy::type_complexity)]
+    pub fn entries(&self) -> Vec<(&Path, Arc<String>, Arc<(Bytes, Option<CacheExpiry>)>)> {
+        self.cache_store
+            .iter()
+            .flat_map(|(path, cache)| cache.iter().map(move |(k, v)| (path, k, v)))
+            .collect()
+    }
+
+    /// Inserts an encoded value if the key is not cached, so that a restored value doesn't
+    /// replace a fresher one. Returns false if the cache is not created for the path or the key
+    /// is cached.
+    pub async fn insert_raw(
+        &self,
+        path: &Path,
+        key: String,
+        value: Bytes,
+        expiry: Option<CacheExpiry>,
+    ) -> bool {
+        match self.cache_store.get(path) {
+            Some(cache) => cache
+                .entry(key)
+                .or_insert_with(async { Arc::new((value, expiry)) })
+                .await
+                .is_fresh(),
+            None => false,
+        }
+    }
+
+    /// Returns the statistics of all caches.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            expirations: self.counters.expirations.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for (path, cache) in &self.cache_store {
+            let entries = cache.entry_count();
+            stats.entries += entries;
+            stats.weighted_size += cache.weighted_size();
+            stats.paths.insert(path.to_string(), entries);
+        }
+        stats
+    }
+
+    /// Runs the pending maintenance of all caches, removing the expired entries and
+    /// evicting the entries over capacity.
+    pub async fn sweep(&self) {
+        for cache in self.cache_store.values() {
+            cache.run_pending_tasks().await;
+        }
+    }
+
+    /// Sweeps the caches at the interval until the token is cancelled.
+    pub(crate) async fn run_sweeps(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {},
+            }
+            self.sweep().await;
+        }
+    }
 }
 
 struct CacheServiceExpiry;
@@ -299,7 +536,10 @@ mod tests {
     async fn test_cache_service() {
         let path1 = Path::from("path1");
         let path2 = Path::from("path2");
-        let cache = CacheService::new(100, BTreeSet::from([path1.clone(), path2.clone()]));
+        let cache = CacheService::new(
+            &CacheConfig::default().with_max_entries(100),
+            BTreeSet::from([path1.clone(), path2.clone()]),
+        );
         assert!(!cache.contains(&path1, "key"));
         assert!(cache.get::<Profile>(&path2, "key").await.is_err());
 
@@ -339,5 +579,102 @@ mod tests {
 
         cache.delete(&path1, "key").await;
         assert!(cache.get::<Profile>(&path1, "key").await.is_err());
+
+        cache.set(&path2, "key", (profile.clone(), None)).await;
+        let entries = cache.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, &path2);
+        let (key, value) = (entries[0].1.to_string(), entries[0].2.0.clone());
+        assert!(
+            cache
+                .insert_raw(&path1, key.clone(), value.clone(), None)
+                .await
+        );
+        assert!(
+            !cache
+                .insert_raw(&path1, key.clone(), Bytes::new(), None)
+                .await
+        );
+        assert!(
+            !cache
+                .insert_raw(&Path::from("path3"), key, value, None)
+                .await
+        );
+        let res = cache.get::<Profile>(&path1, "key").await.unwrap();
+        assert_eq!(res, profile);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cache_limits_and_stats() {
+        let path = Path::from("path");
+        let cfg: CacheConfig = serde_json::from_value(serde_json::json!({
+            "max_entries": 10,
+            "policy": "lru",
+            "sweep_interval_secs": 0,
+        }))
+        .unwrap();
+        assert_eq!(cfg.policy, CachePolicy::Lru);
+        assert_eq!(cfg.max_idle_secs, 3600 * 24 * 7);
+        assert_eq!(cfg.sweep_interval(), Some(Duration::from_secs(1)));
+        assert!(!cfg.persists("path"));
+        let persisted = cfg
+            .clone()
+            .with_persistence(BTreeSet::from(["T:search".to_string()]));
+        assert!(persisted.persists("T:search"));
+        assert!(!persisted.persists("path"));
+        assert!(
+            cfg.clone()
+                .with_persistence(BTreeSet::new())
+                .persists("path")
+        );
+
+        let cache = CacheService::new(&cfg, BTreeSet::from([path.clone()]));
+        for i in 0..20 {
+            cache.set(&path, &format!("key{}", i), (i, None)).await;
+        }
+        cache.sweep().await;
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 10);
+        assert_eq!(stats.weighted_size, 10);
+        assert_eq!(stats.evictions, 10);
+        assert_eq!(stats.paths.get("path"), Some(&10));
+        // LRU keeps the most recent entries
+        assert_eq!(cache.get::<i32>(&path, "key19").await.unwrap(), 19);
+        assert!(cache.get::<i32>(&path, "key0").await.is_err());
+        let res = cache
+            .get_with(&path, "key19", async { Ok((0, None)) })
+            .await
+            .unwrap();
+        assert_eq!(res, 19);
+        let res = cache
+            .get_with(&path, "new", async { Ok((1, None)) })
+            .await
+            .unwrap();
+        assert_eq!(res, 1);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+
+        let cache = CacheService::new(
+            &CacheConfig::default().with_max_bytes(100),
+            BTreeSet::from([path.clone()]),
+        );
+        cache.set(&path, "big", ("x".repeat(60), None)).await;
+        cache.set(&path, "bigger", ("y".repeat(60), None)).await;
+        cache.sweep().await;
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert!(stats.weighted_size <= 100);
+
+        cache
+            .set(
+                &path,
+                "short",
+                (1, Some(CacheExpiry::TTL(Duration::from_millis(1)))),
+            )
+            .await;
+        // expired entries are removed by the timer wheel of moka, with a 1s granularity
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        cache.sweep().await;
+        assert!(cache.stats().expirations >= 1);
     }
 }
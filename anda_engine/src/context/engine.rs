@@ -1,13 +1,16 @@
 use anda_core::{
-    Agent, AgentContext, AgentInput, AgentOutput, BaseContext, BoxError, Function,
-    FunctionDefinition, HttpFeatures, Resource, Tool, ToolInput, ToolOutput, Value,
+    Agent, AgentContext, AgentInput, AgentOutput, BaseContext, BoxError, Error, Function,
+    FunctionDefinition, HttpFeatures, Resource, Tool, ToolInput, ToolOutput, Usage, Value,
     select_resources, validate_function_name,
 };
 use candid::Principal;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, sync::RwLock, time::Duration};
 
-use crate::context::{AgentCtx, BaseCtx};
+use crate::{
+    context::{AgentCtx, BaseCtx},
+    payment::Price,
+};
 
 /// Information about the engine, including agent and tool definitions.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -26,6 +29,117 @@ pub struct Information {
     pub endpoint: String,
 }
 
+/// Descriptor of an engine for discovery by registries and peer engines,
+/// served at `/.well-known/agent.json`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentCard {
+    /// The principal ID of the engine, the identity signing its requests and responses.
+    pub id: Principal,
+    /// The name of the engine.
+    pub name: String,
+    /// Description of the engine.
+    pub description: String,
+    /// The agent running requests without an agent name.
+    pub default_agent: String,
+    /// The exported agents.
+    pub agents: Vec<AgentSkill>,
+    /// The exported tools.
+    pub tools: Vec<AgentSkill>,
+    /// The optional features of the engine.
+    pub capabilities: AgentCapabilities,
+    /// Endpoints of the engine by protocol, e.g. "rpc", "stream", set by the server.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub endpoints: BTreeMap<String, String>,
+    /// URL of the TEE attestation of the engine, if it runs in a TEE.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<String>,
+}
+
+/// An agent or tool in an [`AgentCard`], with its price if it is not free.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentSkill {
+    #[serde(flatten)]
+    pub function: Function,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Price>,
+}
+
+/// The optional features of an engine in an [`AgentCard`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AgentCapabilities {
+    /// Agent runs can stream their progress.
+    pub streaming: bool,
+    /// Agent runs and tool calls can run as background jobs.
+    pub jobs: bool,
+    /// Finished jobs can be notified with webhooks.
+    pub webhooks: bool,
+    /// Some agents or tools require a payment.
+    pub payments: bool,
+    /// Callers can authenticate with API keys.
+    pub api_keys: bool,
+}
+
+/// Result of a readiness check.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HealthCheck {
+    /// Whether the check passed.
+    pub ok: bool,
+    /// Time taken by the check in milliseconds.
+    pub elapsed_ms: u64,
+    /// The error if the check failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Readiness of the engine, with the detail of each check.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Readiness {
+    /// The principal ID of the engine.
+    pub id: Principal,
+    /// The name of the engine.
+    pub name: String,
+    /// Whether all checks passed.
+    pub ready: bool,
+    /// Checks by name: "model", "store" and "remote:{name}" for each remote engine.
+    pub checks: BTreeMap<String, HealthCheck>,
+}
+
+/// Result of [`crate::engine::Engine::shutdown`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ShutdownReport {
+    /// Number of runs in flight when the shutdown started.
+    pub in_flight: usize,
+    /// Number of runs cancelled after the drain timeout.
+    pub cancelled: usize,
+    /// Time taken by the shutdown in milliseconds.
+    pub elapsed_ms: u64,
+}
+
+/// An agent run or tool call in flight.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunInfo {
+    /// The run ID, unique in the engine.
+    pub id: u64,
+    /// "agent" or "tool".
+    pub kind: String,
+    /// The name of the agent or tool.
+    pub name: String,
+    pub caller: Principal,
+    pub started_at_ms: u64,
+}
+
+/// Usage statistics of the engine since it was built.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EngineStats {
+    pub agent_runs: u64,
+    pub tool_calls: u64,
+    /// Runs and calls that failed or were rejected or cancelled.
+    pub failed: u64,
+    pub in_flight: usize,
+    /// Usage accumulated by the succeeded runs and calls.
+    pub usage: Usage,
+}
+
 /// Collection of remote engines.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RemoteEngines {
@@ -307,6 +421,123 @@ impl RemoteEngines {
     }
 }
 
+/// Configuration of the periodic health checks of the remote engines.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteHealthConfig {
+    /// Interval between two checks, in seconds.
+    pub interval_secs: u64,
+    /// Timeout of a check, in milliseconds.
+    pub timeout_ms: u64,
+    /// Number of consecutive failed checks after which an engine is unhealthy.
+    pub failure_threshold: u32,
+}
+
+impl Default for RemoteHealthConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            timeout_ms: 5000,
+            failure_threshold: 2,
+        }
+    }
+}
+
+impl RemoteHealthConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// Health of a remote engine, from its latest checks.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RemoteEngineHealth {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    /// The timestamp in milliseconds of the latest check.
+    pub checked_at_ms: u64,
+    /// The error of the latest failed check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Health of the remote engines by endpoint. The calls to an unhealthy engine fail fast
+/// with an [`Error::Remote`] until a check succeeds again.
+/// Engines that have not been checked are considered healthy.
+#[derive(Debug, Default)]
+pub struct RemoteHealth {
+    failure_threshold: u32,
+    engines: RwLock<BTreeMap<String, RemoteEngineHealth>>,
+}
+
+impl RemoteHealth {
+    pub fn new(failure_threshold: u32) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            engines: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns an error if the engine of the endpoint is unhealthy.
+    pub fn check(&self, endpoint: &str) -> Result<(), BoxError> {
+        match self.engines.read().unwrap().get(endpoint) {
+            Some(health) if !health.healthy => Err(Error::Remote {
+                endpoint: endpoint.to_string(),
+                message: format!(
+                    "engine unhealthy after {} failed checks: {}",
+                    health.consecutive_failures,
+                    health.last_error.as_deref().unwrap_or_default()
+                ),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the result of a check of the engine of the endpoint.
+    pub fn record(&self, endpoint: &str, result: Result<(), String>, now_ms: u64) {
+        let mut engines = self.engines.write().unwrap();
+        let health = engines
+            .entry(endpoint.to_string())
+            .or_insert(RemoteEngineHealth {
+                healthy: true,
+                consecutive_failures: 0,
+                checked_at_ms: now_ms,
+                last_error: None,
+            });
+        health.checked_at_ms = now_ms;
+        match result {
+            Ok(()) => {
+                health.healthy = true;
+                health.consecutive_failures = 0;
+                health.last_error = None;
+            }
+            Err(err) => {
+                health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+                health.healthy = health.consecutive_failures < self.failure_threshold;
+                health.last_error = Some(err);
+            }
+        }
+    }
+
+    /// Removes the engines whose endpoint is no longer registered.
+    pub fn retain(&self, remote: &RemoteEngines) {
+        self.engines
+            .write()
+            .unwrap()
+            .retain(|endpoint, _| remote.get_id_by_endpoint(endpoint).is_some());
+    }
+
+    /// Returns the health of the checked engines by endpoint.
+    pub fn snapshot(&self) -> BTreeMap<String, RemoteEngineHealth> {
+        self.engines.read().unwrap().clone()
+    }
+}
+
 /// Wraps a remote tool as a local tool.
 #[derive(Debug, Clone)]
 pub struct RemoteTool {
@@ -449,3 +680,32 @@ impl Agent<AgentCtx> for RemoteAgent {
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_health() {
+        let endpoint = "https://remote.example.com/default";
+        let health = RemoteHealth::new(2);
+        assert!(health.check(endpoint).is_ok());
+
+        health.record(endpoint, Err("connection refused".to_string()), 1);
+        assert!(health.check(endpoint).is_ok());
+        health.record(endpoint, Err("connection refused".to_string()), 2);
+        let err = health.check(endpoint).unwrap_err();
+        assert!(matches!(
+            anda_core::anda_error(&err),
+            Some(Error::Remote { .. })
+        ));
+        assert_eq!(health.snapshot()[endpoint].consecutive_failures, 2);
+
+        health.record(endpoint, Ok(()), 3);
+        assert!(health.check(endpoint).is_ok());
+        assert_eq!(health.snapshot()[endpoint].checked_at_ms, 3);
+
+        health.retain(&RemoteEngines::new());
+        assert!(health.snapshot().is_empty());
+    }
+}
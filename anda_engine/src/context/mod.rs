@@ -6,12 +6,24 @@ mod agent;
 mod base;
 mod cache;
 mod engine;
+#[cfg(feature = "pocket-ic")]
+mod pocketic;
+mod policy;
+mod tenant;
 mod web3;
+mod websocket;
 
 pub use agent::*;
 pub use base::*;
+pub(crate) use cache::CacheService;
+pub use cache::{CacheConfig, CachePolicy, CacheStats};
 pub use engine::*;
+#[cfg(feature = "pocket-ic")]
+pub use pocketic::*;
+pub use policy::*;
+pub use tenant::*;
 pub use web3::*;
+pub use websocket::*;
 
 /// Mock implementations for testing purposes.
 ///
@@ -0,0 +1,282 @@
+//! PocketIC integration for testing canister-calling tools and agents.
+//!
+//! Enabled with the `pocket-ic` feature. [`PocketIcClient`] routes all canister calls to a
+//! [PocketIC](https://github.com/dfinity/pocketic) instance, so tools and agents can be
+//! integration-tested deterministically against real canister wasm without a replica.
+//!
+//! Other capabilities (keys, HTTP, RPC) are delegated to an inner [`Web3ClientFeatures`],
+//! which is not implemented by default.
+//!
+//! # Example
+//! ```rust,ignore
+//! let pic = Arc::new(PocketIcBuilder::new().with_application_subnet().build_async().await);
+//! let ledger = deploy_ledger(&pic).await;
+//! let client = PocketIcClient::new(pic.clone(), Principal::anonymous());
+//! let engine = EngineBuilder::new()
+//!     .with_web3_client(Arc::new(client.into_web3_sdk()))
+//!     .register_tool(my_tool)?
+//!     ...
+//! ```
+
+use anda_core::{BoxError, BoxPinFut, CanisterCallError, CanisterCaller, HttpOptions};
+use candid::{
+    CandidType, Decode, Principal,
+    utils::{ArgumentEncoder, encode_args},
+};
+use std::sync::Arc;
+
+pub use pocket_ic::nonblocking::PocketIc;
+
+use super::web3::{Web3Client, Web3ClientFeatures, Web3SDK};
+
+fn reject_error(
+    canister: Principal,
+    method: String,
+    update: bool,
+    err: pocket_ic::RejectResponse,
+) -> BoxError {
+    CanisterCallError::from_reject(
+        canister,
+        method,
+        update,
+        err.reject_code as u64,
+        err.reject_message,
+        Some(format!("{:?}", err.error_code)),
+    )
+    .into()
+}
+
+/// A web3 client backed by a PocketIC instance.
+#[derive(Clone)]
+pub struct PocketIcClient {
+    pic: Arc<PocketIc>,
+    sender: Principal,
+    inner: Arc<dyn Web3ClientFeatures>,
+}
+
+impl PocketIcClient {
+    /// Creates a new client that sends canister calls to PocketIC as `sender`.
+    pub fn new(pic: Arc<PocketIc>, sender: Principal) -> Self {
+        Self {
+            pic,
+            sender,
+            inner: Web3Client::not_implemented().client,
+        }
+    }
+
+    /// Sets the inner client used for non-canister operations.
+    pub fn with_inner(mut self, inner: Arc<dyn Web3ClientFeatures>) -> Self {
+        self.inner = inner;
+        self
+    }
+
+    /// Sets the sender principal of canister calls.
+    pub fn with_sender(mut self, sender: Principal) -> Self {
+        self.sender = sender;
+        self
+    }
+
+    /// Returns the PocketIC instance.
+    pub fn pic(&self) -> &Arc<PocketIc> {
+        &self.pic
+    }
+
+    /// Converts the client into a [`Web3SDK`] to be used with `EngineBuilder::with_web3_client`.
+    pub fn into_web3_sdk(self) -> Web3SDK {
+        Web3SDK::from_web3(Arc::new(self))
+    }
+}
+
+impl Web3ClientFeatures for PocketIcClient {
+    fn a256gcm_key(&self, derivation_path: Vec<Vec<u8>>) -> BoxPinFut<Result<[u8; 32], BoxError>> {
+        self.inner.a256gcm_key(derivation_path)
+    }
+
+    fn ed25519_sign_message(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message: &[u8],
+    ) -> BoxPinFut<Result<[u8; 64], BoxError>> {
+        self.inner.ed25519_sign_message(derivation_path, message)
+    }
+
+    fn ed25519_verify(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message: &[u8],
+        signature: &[u8],
+    ) -> BoxPinFut<Result<(), BoxError>> {
+        self.inner
+            .ed25519_verify(derivation_path, message, signature)
+    }
+
+    fn ed25519_public_key(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> BoxPinFut<Result<[u8; 32], BoxError>> {
+        self.inner.ed25519_public_key(derivation_path)
+    }
+
+    fn secp256k1_sign_message_bip340(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message: &[u8],
+    ) -> BoxPinFut<Result<[u8; 64], BoxError>> {
+        self.inner
+            .secp256k1_sign_message_bip340(derivation_path, message)
+    }
+
+    fn secp256k1_verify_bip340(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message: &[u8],
+        signature: &[u8],
+    ) -> BoxPinFut<Result<(), BoxError>> {
+        self.inner
+            .secp256k1_verify_bip340(derivation_path, message, signature)
+    }
+
+    fn secp256k1_sign_message_ecdsa(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message: &[u8],
+    ) -> BoxPinFut<Result<[u8; 64], BoxError>> {
+        self.inner
+            .secp256k1_sign_message_ecdsa(derivation_path, message)
+    }
+
+    fn secp256k1_sign_digest_ecdsa(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message_hash: &[u8],
+    ) -> BoxPinFut<Result<[u8; 64], BoxError>> {
+        self.inner
+            .secp256k1_sign_digest_ecdsa(derivation_path, message_hash)
+    }
+
+    fn secp256k1_verify_ecdsa(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+        message_hash: &[u8],
+        signature: &[u8],
+    ) -> BoxPinFut<Result<(), BoxError>> {
+        self.inner
+            .secp256k1_verify_ecdsa(derivation_path, message_hash, signature)
+    }
+
+    fn secp256k1_public_key(
+        &self,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> BoxPinFut<Result<[u8; 33], BoxError>> {
+        self.inner.secp256k1_public_key(derivation_path)
+    }
+
+    fn canister_query_raw(
+        &self,
+        canister: Principal,
+        method: String,
+        args: Vec<u8>,
+    ) -> BoxPinFut<Result<Vec<u8>, BoxError>> {
+        let pic = self.pic.clone();
+        let sender = self.sender;
+        Box::pin(async move {
+            pic.query_call(canister, sender, &method, args)
+                .await
+                .map_err(|err| reject_error(canister, method, false, err))
+        })
+    }
+
+    fn canister_update_raw(
+        &self,
+        canister: Principal,
+        method: String,
+        args: Vec<u8>,
+    ) -> BoxPinFut<Result<Vec<u8>, BoxError>> {
+        let pic = self.pic.clone();
+        let sender = self.sender;
+        Box::pin(async move {
+            pic.update_call(canister, sender, &method, args)
+                .await
+                .map_err(|err| reject_error(canister, method, true, err))
+        })
+    }
+
+    fn https_call(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+    ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
+        self.inner.https_call(url, method, headers, body)
+    }
+
+    fn https_call_with_options(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
+        self.inner
+            .https_call_with_options(url, method, headers, body, opts)
+    }
+
+    fn https_signed_call(
+        &self,
+        url: String,
+        method: http::Method,
+        message_digest: [u8; 32],
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+    ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
+        self.inner
+            .https_signed_call(url, method, message_digest, headers, body)
+    }
+
+    fn https_signed_rpc_raw(
+        &self,
+        endpoint: String,
+        method: String,
+        args: Vec<u8>,
+    ) -> BoxPinFut<Result<Vec<u8>, BoxError>> {
+        self.inner.https_signed_rpc_raw(endpoint, method, args)
+    }
+}
+
+impl CanisterCaller for PocketIcClient {
+    async fn canister_query<
+        In: ArgumentEncoder + Send,
+        Out: CandidType + for<'a> candid::Deserialize<'a>,
+    >(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: In,
+    ) -> Result<Out, BoxError> {
+        let input = encode_args(args)?;
+        let res = self
+            .canister_query_raw(*canister, method.to_string(), input)
+            .await?;
+        let output = Decode!(res.as_slice(), Out)?;
+        Ok(output)
+    }
+
+    async fn canister_update<
+        In: ArgumentEncoder + Send,
+        Out: CandidType + for<'a> candid::Deserialize<'a>,
+    >(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: In,
+    ) -> Result<Out, BoxError> {
+        let input = encode_args(args)?;
+        let res = self
+            .canister_update_raw(*canister, method.to_string(), input)
+            .await?;
+        let output = Decode!(res.as_slice(), Out)?;
+        Ok(output)
+    }
+}
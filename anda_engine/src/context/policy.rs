@@ -0,0 +1,662 @@
+//! Call policies enforced by the context.
+//!
+//! Agents act with the engine's identity, so a prompt-injected agent could otherwise
+//! call any canister update method. [`CanisterPolicy`] restricts which canisters and
+//! methods `canister_update` may target, and [`HttpPolicy`] bounds which domains
+//! HTTP requests may reach.
+//!
+//! [`AccessPolicy`] restricts which tools and agents each caller, or end user of a caller,
+//! may invoke, and which of them the agents' models are offered. It is set by the engine
+//! builder, or served by a policy canister, see [`PolicyCanisterConfig`].
+//!
+//! [`Capabilities`] restrict what a context may do at all, e.g. an untrusted tool that only
+//! needs to read the store gets no HTTP requests, no signing and no writes. Child contexts
+//! inherit the capabilities of their parent and may only narrow them.
+
+use anda_core::{
+    BoxError, CanisterCallError, CanisterCaller, Error, domain_match, normalize_domain,
+};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Wildcard that matches any canister or method.
+pub static WILDCARD: &str = "*";
+
+/// An allowlist of canisters and methods for `canister_update`.
+///
+/// Canister keys are principal texts or `*` for any canister.
+/// Method patterns support `*` wildcards, e.g. `icrc1_*` or `*`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CanisterPolicy {
+    /// `None` means all calls are allowed.
+    allowlist: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl CanisterPolicy {
+    /// Creates a policy that allows all update calls, this is the default.
+    pub fn allow_all() -> Self {
+        Self { allowlist: None }
+    }
+
+    /// Creates a policy that denies all update calls until rules are added with [`Self::allow`].
+    pub fn deny_all() -> Self {
+        Self {
+            allowlist: Some(BTreeMap::new()),
+        }
+    }
+
+    /// Allows the method pattern on the canister (or `*` for any canister).
+    pub fn allow(mut self, canister: &str, method: &str) -> Result<Self, BoxError> {
+        if canister != WILDCARD {
+            Principal::from_text(canister)
+                .map_err(|err| format!("invalid canister {}: {}", canister, err))?;
+        }
+        if method.is_empty() {
+            return Err("method pattern should not be empty".into());
+        }
+
+        self.allowlist
+            .get_or_insert_with(BTreeMap::new)
+            .entry(canister.to_string())
+            .or_default()
+            .push(method.to_string());
+        Ok(self)
+    }
+
+    /// Returns true if the update call is allowed.
+    pub fn is_allowed(&self, canister: &Principal, method: &str) -> bool {
+        let allowlist = match &self.allowlist {
+            None => return true,
+            Some(list) => list,
+        };
+
+        let id = canister.to_text();
+        [id.as_str(), WILDCARD].iter().any(|key| {
+            allowlist
+                .get(*key)
+                .map(|patterns| patterns.iter().any(|p| wildcard_match(p, method)))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Checks the update call, returns an error if it is not allowed.
+    pub fn check_update(&self, canister: &Principal, method: &str) -> Result<(), BoxError> {
+        if self.is_allowed(canister, method) {
+            Ok(())
+        } else {
+            Err(CanisterCallError::new(
+                *canister,
+                method.to_string(),
+                true,
+                "not allowed by canister policy".to_string(),
+            )
+            .with_retryable(false)
+            .into())
+        }
+    }
+}
+
+/// An allowlist and denylist of domains for outbound HTTP requests.
+///
+/// A domain matches the host itself and all its subdomains,
+/// e.g. `example.com` matches `example.com` and `api.example.com`.
+/// The denylist takes precedence over the allowlist.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DomainPolicy {
+    /// `None` means all domains not in the denylist are allowed.
+    allowlist: Option<Vec<String>>,
+    denylist: Vec<String>,
+}
+
+impl DomainPolicy {
+    /// Creates a policy that allows all domains, this is the default.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Creates a policy that denies all domains until domains are added with [`Self::allow`].
+    pub fn deny_all() -> Self {
+        Self {
+            allowlist: Some(Vec::new()),
+            denylist: Vec::new(),
+        }
+    }
+
+    /// Allows the domain and its subdomains.
+    pub fn allow(mut self, domain: &str) -> Result<Self, BoxError> {
+        let domain = normalize_domain(domain)?;
+        self.allowlist.get_or_insert_with(Vec::new).push(domain);
+        Ok(self)
+    }
+
+    /// Denies the domain and its subdomains.
+    pub fn deny(mut self, domain: &str) -> Result<Self, BoxError> {
+        self.denylist.push(normalize_domain(domain)?);
+        Ok(self)
+    }
+
+    /// Returns true if requests to the host are allowed.
+    pub fn is_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if self.denylist.iter().any(|d| domain_match(d, &host)) {
+            return false;
+        }
+        match &self.allowlist {
+            None => true,
+            Some(list) => list.iter().any(|d| domain_match(d, &host)),
+        }
+    }
+}
+
+/// Outbound domain policy for `https_call` and `https_signed_call`,
+/// configured for the engine and optionally per tool.
+///
+/// A request from a tool must be allowed by both the engine policy and the tool policy.
+/// Redirects are followed by the context rather than the HTTP client, so every hop is
+/// checked, and signed requests are not redirected.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HttpPolicy {
+    engine: DomainPolicy,
+    tools: BTreeMap<String, DomainPolicy>,
+}
+
+impl HttpPolicy {
+    /// Creates a policy with the engine-wide domain policy.
+    pub fn new(engine: DomainPolicy) -> Self {
+        Self {
+            engine,
+            tools: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the domain policy for a tool.
+    pub fn with_tool(mut self, tool: &str, policy: DomainPolicy) -> Self {
+        self.tools.insert(tool.to_string(), policy);
+        self
+    }
+
+    /// Returns true if the tool (or the engine when `None`) may request the host.
+    pub fn is_allowed(&self, tool: Option<&str>, host: &str) -> bool {
+        self.engine.is_allowed(host)
+            && tool
+                .and_then(|name| self.tools.get(name))
+                .map(|policy| policy.is_allowed(host))
+                .unwrap_or(true)
+    }
+
+    /// Checks the request URL, returns an error if it is not allowed.
+    pub fn check_url(&self, tool: Option<&str>, url: &str) -> Result<(), BoxError> {
+        let parsed =
+            reqwest::Url::parse(url).map_err(|err| format!("invalid url {}: {}", url, err))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| format!("invalid url {}: missing host", url))?;
+        if self.is_allowed(tool, host) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized(format!(
+                "HTTP request to {} is not allowed by domain policy",
+                host
+            ))
+            .into())
+        }
+    }
+}
+
+/// Tool and agent name patterns that a caller may invoke, with `*` wildcards.
+///
+/// Tool names are those seen by the agents, e.g. `RT_` prefixed for remote tools, and agent
+/// names are those of the local agents, or `RA_` prefixed for remote agents.
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AccessList {
+    #[serde(default)]
+    pub tools: Vec<String>,
+    #[serde(default)]
+    pub agents: Vec<String>,
+}
+
+impl AccessList {
+    /// Creates a list that allows all tools and agents.
+    pub fn allow_all() -> Self {
+        Self {
+            tools: vec![WILDCARD.to_string()],
+            agents: vec![WILDCARD.to_string()],
+        }
+    }
+
+    /// Creates a list of tool and agent name patterns.
+    pub fn new(tools: &[&str], agents: &[&str]) -> Self {
+        Self {
+            tools: tools.iter().map(|s| s.to_string()).collect(),
+            agents: agents.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    pub fn allows_tool(&self, tool: &str) -> bool {
+        self.tools.iter().any(|p| wildcard_match(p, tool))
+    }
+
+    pub fn allows_agent(&self, agent: &str) -> bool {
+        let agent = agent.to_ascii_lowercase();
+        self.agents
+            .iter()
+            .any(|p| wildcard_match(&p.to_ascii_lowercase(), &agent))
+    }
+}
+
+/// Per-caller allowlists of tools and agents, enforced on the calls of the engine's callers
+/// and of the agents' models.
+///
+/// A caller is allowed by its own list, or by the default list if it has none, and all
+/// callers are allowed if there is no default list. A list of an end user, named by
+/// [`anda_core::RequestMeta::user`], further restricts its caller's list, since end users are
+/// declared by the callers.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct AccessPolicy {
+    /// `None` means the callers without a list are allowed all tools and agents.
+    default: Option<AccessList>,
+    callers: BTreeMap<Principal, AccessList>,
+    users: BTreeMap<String, AccessList>,
+}
+
+impl AccessPolicy {
+    /// Creates a policy that allows all callers, this is the default.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Sets the list of the callers without their own list.
+    pub fn with_default(mut self, list: AccessList) -> Self {
+        self.default = Some(list);
+        self
+    }
+
+    pub fn with_caller(mut self, caller: Principal, list: AccessList) -> Self {
+        self.callers.insert(caller, list);
+        self
+    }
+
+    pub fn with_user(mut self, user: &str, list: AccessList) -> Self {
+        self.users.insert(user.to_string(), list);
+        self
+    }
+
+    fn allows(
+        &self,
+        caller: &Principal,
+        user: Option<&str>,
+        f: impl Fn(&AccessList) -> bool,
+    ) -> bool {
+        let allowed = match self.callers.get(caller).or(self.default.as_ref()) {
+            Some(list) => f(list),
+            None => true,
+        };
+        allowed && user.and_then(|u| self.users.get(u)).is_none_or(f)
+    }
+
+    /// Returns true if the caller, or its end user, may call the tool.
+    pub fn is_tool_allowed(&self, caller: &Principal, user: Option<&str>, tool: &str) -> bool {
+        self.allows(caller, user, |list| list.allows_tool(tool))
+    }
+
+    /// Returns true if the caller, or its end user, may run the agent.
+    pub fn is_agent_allowed(&self, caller: &Principal, user: Option<&str>, agent: &str) -> bool {
+        self.allows(caller, user, |list| list.allows_agent(agent))
+    }
+
+    /// Checks the tool call, returns an error if it is not allowed.
+    pub fn check_tool(
+        &self,
+        caller: &Principal,
+        user: Option<&str>,
+        tool: &str,
+    ) -> Result<(), BoxError> {
+        if self.is_tool_allowed(caller, user, tool) {
+            Ok(())
+        } else {
+            Err(
+                Error::Unauthorized(format!("tool {} is not allowed by access policy", tool))
+                    .into(),
+            )
+        }
+    }
+
+    /// Checks the agent run, returns an error if it is not allowed.
+    pub fn check_agent(
+        &self,
+        caller: &Principal,
+        user: Option<&str>,
+        agent: &str,
+    ) -> Result<(), BoxError> {
+        if self.is_agent_allowed(caller, user, agent) {
+            Ok(())
+        } else {
+            Err(
+                Error::Unauthorized(format!("agent {} is not allowed by access policy", agent))
+                    .into(),
+            )
+        }
+    }
+}
+
+/// Capabilities of a context, enforced by its implementation of the context traits.
+/// All are granted by default.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Capabilities {
+    /// HTTPs requests, WebSocket connections and calls to remote engines.
+    pub http: bool,
+    /// Signing with the engine's keys, and signed HTTPs requests and RPCs.
+    pub sign: bool,
+    /// Writes to the store: put, rename and delete.
+    pub store_write: bool,
+    /// Update calls to canisters, queries are always allowed.
+    pub canister_update: bool,
+    /// Writes to the vector store: upsert and delete.
+    pub vector_write: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Capabilities {
+    /// Grants all capabilities.
+    pub fn all() -> Self {
+        Self {
+            http: true,
+            sign: true,
+            store_write: true,
+            canister_update: true,
+            vector_write: true,
+        }
+    }
+
+    /// Grants none of the capabilities, the context can only read the store, the vector
+    /// store and the cache, and query canisters.
+    pub fn read_only() -> Self {
+        Self {
+            http: false,
+            sign: false,
+            store_write: false,
+            canister_update: false,
+            vector_write: false,
+        }
+    }
+
+    /// Returns the capabilities granted by both.
+    pub fn intersect(&self, other: &Self) -> Self {
+        Self {
+            http: self.http && other.http,
+            sign: self.sign && other.sign,
+            store_write: self.store_write && other.store_write,
+            canister_update: self.canister_update && other.canister_update,
+            vector_write: self.vector_write && other.vector_write,
+        }
+    }
+
+    /// Returns an error if the capability is not granted.
+    pub(crate) fn check(granted: bool, capability: &str) -> Result<(), BoxError> {
+        if granted {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized(format!(
+                "the context does not have the {} capability",
+                capability
+            ))
+            .into())
+        }
+    }
+}
+
+/// A policy canister serving the [`AccessPolicy`] of the engine, fetched when the engine is
+/// built and refreshed periodically. The engine fails to build if the policy can't be fetched,
+/// and keeps the last fetched policy if a refresh fails.
+///
+/// The policy canister implements:
+/// ```candid
+/// type AccessList = record { tools : vec text; agents : vec text };
+/// type AccessPolicy = record {
+///   default : opt AccessList;
+///   callers : vec record { principal; AccessList };
+///   users : vec record { text; AccessList };
+/// };
+/// service : {
+///   get_access_policy : () -> (AccessPolicy) query;
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyCanisterConfig {
+    /// The policy canister.
+    pub canister: Principal,
+    /// Interval between two refreshes of the policy, in seconds.
+    #[serde(default = "default_policy_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+fn default_policy_refresh_secs() -> u64 {
+    60
+}
+
+impl PolicyCanisterConfig {
+    pub fn new(canister: Principal) -> Self {
+        Self {
+            canister,
+            refresh_secs: default_policy_refresh_secs(),
+        }
+    }
+
+    pub fn with_refresh_secs(mut self, refresh_secs: u64) -> Self {
+        self.refresh_secs = refresh_secs;
+        self
+    }
+
+    /// Returns the interval between two refreshes.
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_secs.max(1))
+    }
+
+    /// Fetches the policy from the canister.
+    pub async fn fetch(&self, caller: &impl CanisterCaller) -> Result<AccessPolicy, BoxError> {
+        let record: AccessPolicyRecord = caller
+            .canister_query(&self.canister, "get_access_policy", ())
+            .await
+            .map_err(|err| format!("failed to fetch the access policy: {}", err))?;
+        Ok(record.into())
+    }
+}
+
+/// The [`AccessPolicy`] served by a policy canister.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AccessPolicyRecord {
+    pub default: Option<AccessList>,
+    pub callers: Vec<(Principal, AccessList)>,
+    pub users: Vec<(String, AccessList)>,
+}
+
+impl From<AccessPolicyRecord> for AccessPolicy {
+    fn from(record: AccessPolicyRecord) -> Self {
+        Self {
+            default: record.default,
+            callers: record.callers.into_iter().collect(),
+            users: record.users.into_iter().collect(),
+        }
+    }
+}
+
+/// Matches a string against a pattern with `*` wildcards.
+pub fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == s;
+    }
+
+    let mut rest = s;
+    for (i, part) in parts.iter().enumerate() {
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "icrc1_transfer"));
+        assert!(wildcard_match("icrc1_*", "icrc1_transfer"));
+        assert!(!wildcard_match("icrc1_*", "icrc2_approve"));
+        assert!(wildcard_match("*_transfer", "icrc1_transfer"));
+        assert!(wildcard_match("icrc*transfer", "icrc1_transfer"));
+        assert!(!wildcard_match("icrc*transfer", "icrc1_transfer_from"));
+        assert!(wildcard_match("transfer", "transfer"));
+        assert!(!wildcard_match("transfer", "transfer_from"));
+    }
+
+    #[test]
+    fn test_canister_policy() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let other = Principal::from_text("mxzaz-hqaaa-aaaar-qaada-cai").unwrap();
+
+        let policy = CanisterPolicy::default();
+        assert!(policy.is_allowed(&ledger, "icrc1_transfer"));
+
+        let policy = CanisterPolicy::deny_all();
+        assert!(policy.check_update(&ledger, "icrc1_transfer").is_err());
+
+        let policy = CanisterPolicy::deny_all()
+            .allow(&ledger.to_text(), "icrc1_*")
+            .unwrap()
+            .allow("*", "get_*")
+            .unwrap();
+        assert!(policy.is_allowed(&ledger, "icrc1_transfer"));
+        assert!(!policy.is_allowed(&ledger, "icrc2_approve"));
+        assert!(!policy.is_allowed(&other, "icrc1_transfer"));
+        assert!(policy.is_allowed(&other, "get_blocks"));
+
+        assert!(CanisterPolicy::deny_all().allow("invalid", "*").is_err());
+    }
+
+    #[test]
+    fn test_http_policy() {
+        let policy = HttpPolicy::default();
+        assert!(policy.check_url(None, "https://example.com/a").is_ok());
+        assert!(policy.check_url(None, "not a url").is_err());
+
+        let policy = HttpPolicy::new(
+            DomainPolicy::allow_all()
+                .deny("internal")
+                .unwrap()
+                .deny("169.254.169.254")
+                .unwrap(),
+        )
+        .with_tool(
+            "google_web_search",
+            DomainPolicy::deny_all().allow("googleapis.com").unwrap(),
+        );
+        assert!(policy.is_allowed(None, "example.com"));
+        assert!(!policy.is_allowed(None, "svc.internal"));
+        assert!(!policy.is_allowed(Some("other_tool"), "169.254.169.254"));
+        assert!(policy.is_allowed(Some("other_tool"), "example.com"));
+        assert!(policy.is_allowed(Some("google_web_search"), "www.googleapis.com"));
+        assert!(!policy.is_allowed(Some("google_web_search"), "example.com"));
+        assert!(!policy.is_allowed(Some("google_web_search"), "notgoogleapis.com"));
+        assert!(
+            policy
+                .check_url(Some("google_web_search"), "https://example.com")
+                .is_err()
+        );
+
+        assert!(DomainPolicy::deny_all().allow("").is_err());
+        assert!(
+            DomainPolicy::deny_all()
+                .allow("https://example.com")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_access_policy() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+
+        let policy = AccessPolicy::allow_all();
+        assert!(policy.is_tool_allowed(&alice, None, "icp_ledger_transfer"));
+
+        let policy = AccessPolicy::allow_all()
+            .with_default(AccessList::new(&["google_*"], &["assistant"]))
+            .with_caller(alice, AccessList::allow_all())
+            .with_user("mallory", AccessList::new(&[], &["assistant"]));
+        assert!(policy.is_tool_allowed(&alice, None, "icp_ledger_transfer"));
+        assert!(policy.is_agent_allowed(&alice, Some("mallory"), "assistant"));
+        assert!(!policy.is_agent_allowed(&alice, Some("mallory"), "RA_remote"));
+        assert!(!policy.is_tool_allowed(&alice, Some("mallory"), "google_web_search"));
+        assert!(policy.is_tool_allowed(&bob, None, "google_web_search"));
+        assert!(!policy.is_tool_allowed(&bob, None, "icp_ledger_transfer"));
+        assert!(policy.is_agent_allowed(&bob, None, "Assistant"));
+        assert!(policy.check_agent(&bob, Some("mallory"), "other").is_err());
+        assert!(policy.check_tool(&bob, None, "RT_remote").is_err());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::default();
+        assert_eq!(caps, Capabilities::all());
+        let caps = caps.intersect(&Capabilities {
+            http: false,
+            store_write: false,
+            ..Default::default()
+        });
+        assert!(!caps.http && !caps.store_write && caps.sign);
+        assert_eq!(
+            caps.intersect(&Capabilities::all())
+                .intersect(&Capabilities::read_only()),
+            Capabilities::read_only()
+        );
+        assert!(Capabilities::check(caps.sign, "sign").is_ok());
+        assert!(Capabilities::check(caps.http, "http").is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_policy_canister() {
+        use crate::context::mock::MockCanisterCaller;
+
+        let canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let alice = Principal::from_slice(&[1]);
+        let caller = MockCanisterCaller::new(move |_canister, method, _args| {
+            assert_eq!(method, "get_access_policy");
+            candid::encode_args((AccessPolicyRecord {
+                default: Some(AccessList::new(&["google_*"], &[])),
+                callers: vec![(alice, AccessList::allow_all())],
+                users: vec![],
+            },))
+            .unwrap()
+        });
+
+        let cfg = PolicyCanisterConfig::new(canister);
+        assert_eq!(cfg.refresh_interval(), Duration::from_secs(60));
+        let policy = cfg.fetch(&caller).await.unwrap();
+        assert!(policy.is_tool_allowed(&alice, None, "icp_ledger_transfer"));
+        assert!(policy.is_tool_allowed(&Principal::anonymous(), None, "google_web_search"));
+        assert!(!policy.is_agent_allowed(&Principal::anonymous(), None, "assistant"));
+    }
+}
@@ -0,0 +1,335 @@
+//! Tenants let a single engine deployment serve multiple customers.
+//!
+//! A [`Tenant`] groups callers, signed principals or API key pseudo-principals, and isolates them:
+//! - their own model, e.g. with the customer's provider credentials;
+//! - a store prefix and a cache namespace, so agents and tools only see the tenant's data;
+//! - a key derivation root, so the derived keys differ between tenants;
+//! - daily quotas of requests and tokens.
+//!
+//! Callers that don't belong to any tenant get the engine's defaults.
+
+use anda_core::{BoxError, Error, Path, Usage};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::model::Model;
+
+/// The store namespace and key derivation root prefix of the tenants.
+pub static TENANTS_PATH: &str = "_tenants";
+
+/// The cache namespace of the callers without a tenant, reserved as a tenant ID.
+pub(crate) static NO_TENANT: &str = "_";
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// Daily quotas of a tenant, reset at 00:00 UTC.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TenantQuota {
+    /// Maximum number of agent runs and tool calls per day.
+    pub max_requests_per_day: Option<u64>,
+    /// Maximum number of LLM tokens, input and output, per day.
+    /// A request is rejected once the quota is reached, a running one is not interrupted.
+    pub max_tokens_per_day: Option<u64>,
+}
+
+/// Usage of a tenant in the current day.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TenantUsage {
+    /// Days since the unix epoch.
+    pub day: u64,
+    pub requests: u64,
+    pub tokens: u64,
+}
+
+/// Information about a tenant.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TenantInfo {
+    pub id: String,
+    pub members: Vec<Principal>,
+    pub quota: TenantQuota,
+    pub usage: TenantUsage,
+    /// True if the tenant has its own model.
+    pub own_model: bool,
+}
+
+/// A tenant of the engine.
+pub struct Tenant {
+    id: String,
+    members: BTreeSet<Principal>,
+    pub(crate) model: Option<Model>,
+    quota: TenantQuota,
+    usage: Mutex<TenantUsage>,
+}
+
+impl Tenant {
+    /// Creates a tenant, the ID is made of lowercase letters, digits, `-` and `_`,
+    /// and can't be `_`.
+    pub fn new(id: String) -> Result<Self, BoxError> {
+        if id.is_empty()
+            || id == NO_TENANT
+            || id.len() > 64
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+        {
+            return Err(format!("invalid tenant id {id:?}").into());
+        }
+        Ok(Self {
+            id,
+            members: BTreeSet::new(),
+            model: None,
+            quota: TenantQuota::default(),
+            usage: Mutex::new(TenantUsage::default()),
+        })
+    }
+
+    /// Adds callers to the tenant.
+    pub fn with_members(mut self, members: impl IntoIterator<Item = Principal>) -> Self {
+        self.members.extend(members);
+        self
+    }
+
+    /// Sets the model used by the agents for the tenant's callers.
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn with_quota(mut self, quota: TenantQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_member(&self, caller: &Principal) -> bool {
+        self.members.contains(caller)
+    }
+
+    /// Returns the usage of the current day.
+    pub fn usage(&self, now_ms: u64) -> TenantUsage {
+        let mut usage = self.usage.lock().expect("tenant usage lock poisoned");
+        Self::roll(&mut usage, now_ms);
+        *usage
+    }
+
+    pub fn info(&self, now_ms: u64) -> TenantInfo {
+        TenantInfo {
+            id: self.id.clone(),
+            members: self.members.iter().cloned().collect(),
+            quota: self.quota,
+            usage: self.usage(now_ms),
+            own_model: self.model.is_some(),
+        }
+    }
+
+    /// Restores the usage from a snapshot, it is reset if the day has changed.
+    pub(crate) fn restore_usage(&self, usage: TenantUsage) {
+        *self.usage.lock().expect("tenant usage lock poisoned") = usage;
+    }
+
+    /// Prefixes a store path or a key derivation root with the tenant's namespace.
+    pub(crate) fn scoped(&self, path: &Path) -> Path {
+        Path::from(format!("{}/{}/{}", TENANTS_PATH, self.id, path))
+    }
+
+    /// Prefixes a cache key with the namespace of the tenant, or of the callers without a
+    /// tenant if None.
+    pub(crate) fn cache_key(tenant: Option<&Self>, key: &str) -> String {
+        format!("{}/{}", tenant.map_or(NO_TENANT, |t| t.id.as_str()), key)
+    }
+
+    /// Counts a request, returns an error if a quota of the day is exhausted.
+    pub(crate) fn try_request(&self, now_ms: u64) -> Result<(), BoxError> {
+        let mut usage = self.usage.lock().expect("tenant usage lock poisoned");
+        Self::roll(&mut usage, now_ms);
+        let exceeded = |quota: &str| {
+            Error::RateLimited {
+                message: format!("tenant {} exceeded its daily {} quota", self.id, quota),
+                // the quotas are reset at the start of the next day
+                retry_after_ms: Some(DAY_MS - now_ms % DAY_MS),
+            }
+            .into()
+        };
+        if self
+            .quota
+            .max_requests_per_day
+            .is_some_and(|max| usage.requests >= max)
+        {
+            return Err(exceeded("request"));
+        }
+        if self
+            .quota
+            .max_tokens_per_day
+            .is_some_and(|max| usage.tokens >= max)
+        {
+            return Err(exceeded("token"));
+        }
+        usage.requests += 1;
+        Ok(())
+    }
+
+    /// Records the tokens used by a request.
+    pub(crate) fn record_usage(&self, used: &Usage, now_ms: u64) {
+        let mut usage = self.usage.lock().expect("tenant usage lock poisoned");
+        Self::roll(&mut usage, now_ms);
+        usage.tokens += used.input_tokens + used.output_tokens;
+    }
+
+    fn roll(usage: &mut TenantUsage, now_ms: u64) {
+        let day = now_ms / DAY_MS;
+        if usage.day != day {
+            *usage = TenantUsage {
+                day,
+                ..Default::default()
+            };
+        }
+    }
+}
+
+/// The tenants of an engine, with their members.
+#[derive(Default)]
+pub struct Tenants {
+    tenants: BTreeMap<String, Arc<Tenant>>,
+    members: BTreeMap<Principal, Arc<Tenant>>,
+}
+
+impl Tenants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tenant, a caller can only belong to one tenant.
+    pub fn add(&mut self, tenant: Tenant) -> Result<(), BoxError> {
+        if self.tenants.contains_key(&tenant.id) {
+            return Err(format!("tenant {} already exists", tenant.id).into());
+        }
+        if let Some(member) = tenant
+            .members
+            .iter()
+            .find(|m| self.members.contains_key(*m))
+        {
+            return Err(format!(
+                "caller {} of tenant {} already belongs to tenant {}",
+                member.to_text(),
+                tenant.id,
+                self.members[member].id
+            )
+            .into());
+        }
+
+        let tenant = Arc::new(tenant);
+        for member in &tenant.members {
+            self.members.insert(*member, tenant.clone());
+        }
+        self.tenants.insert(tenant.id.clone(), tenant);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Tenant>> {
+        self.tenants.get(id).cloned()
+    }
+
+    /// Returns the tenant of the caller.
+    pub fn of(&self, caller: &Principal) -> Option<Arc<Tenant>> {
+        self.members.get(caller).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    /// Returns the usage of the day by tenant ID.
+    pub fn usages(&self, now_ms: u64) -> BTreeMap<String, TenantUsage> {
+        self.tenants
+            .iter()
+            .map(|(id, t)| (id.clone(), t.usage(now_ms)))
+            .collect()
+    }
+
+    pub fn list(&self, now_ms: u64) -> Vec<TenantInfo> {
+        self.tenants.values().map(|t| t.info(now_ms)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenants() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        assert!(Tenant::new("Acme".to_string()).is_err());
+        assert!(Tenant::new("acme/x".to_string()).is_err());
+        assert!(Tenant::new("_".to_string()).is_err());
+
+        let mut tenants = Tenants::new();
+        tenants
+            .add(
+                Tenant::new("acme".to_string())
+                    .unwrap()
+                    .with_members([alice])
+                    .with_quota(TenantQuota {
+                        max_requests_per_day: Some(2),
+                        max_tokens_per_day: Some(100),
+                    }),
+            )
+            .unwrap();
+        assert!(
+            tenants
+                .add(Tenant::new("acme".to_string()).unwrap())
+                .is_err()
+        );
+        assert!(
+            tenants
+                .add(
+                    Tenant::new("other".to_string())
+                        .unwrap()
+                        .with_members([alice])
+                )
+                .is_err()
+        );
+        assert!(tenants.of(&bob).is_none());
+
+        let acme = tenants.of(&alice).unwrap();
+        assert_eq!(acme.id(), "acme");
+        assert_eq!(
+            acme.scoped(&Path::from("T:memory")).as_ref(),
+            "_tenants/acme/T:memory"
+        );
+        assert_eq!(Tenant::cache_key(Some(&acme), "k"), "acme/k");
+        assert_eq!(Tenant::cache_key(None, "k"), "_/k");
+
+        let now = 10 * DAY_MS + 1;
+        acme.try_request(now).unwrap();
+        acme.record_usage(
+            &Usage {
+                input_tokens: 80,
+                output_tokens: 30,
+                requests: 1,
+                ..Default::default()
+            },
+            now,
+        );
+        // the token quota is exhausted
+        assert!(acme.try_request(now).is_err());
+        let usage = acme.usage(now);
+        assert_eq!(usage.requests, 1);
+        assert_eq!(usage.tokens, 110);
+
+        // reset on the next day
+        let now = now + DAY_MS;
+        acme.try_request(now).unwrap();
+        acme.try_request(now).unwrap();
+        assert!(acme.try_request(now).is_err());
+        assert_eq!(tenants.list(now)[0].usage.requests, 2);
+    }
+}
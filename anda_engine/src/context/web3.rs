@@ -1,4 +1,7 @@
-use anda_core::{BoxError, BoxPinFut, CanisterCaller, HttpFeatures};
+use anda_core::{
+    BoxError, BoxPinFut, CancellationToken, CanisterCaller, HttpFeatures, HttpOptions, WebSocket,
+    WsOptions, apply_http_options,
+};
 use candid::{
     CandidType, Decode, Principal,
     utils::{ArgumentEncoder, encode_args},
@@ -8,6 +11,8 @@ use ic_cose_types::to_cbor_bytes;
 use serde::{Serialize, de::DeserializeOwned};
 use std::sync::Arc;
 
+use super::websocket::websocket_connect;
+
 pub use ic_tee_gateway_sdk::client::Client as TEEClient;
 
 /// Represents a Web3 client for interacting with the Internet Computer and other services.
@@ -136,6 +141,29 @@ pub trait Web3ClientFeatures: Send + Sync + 'static {
         body: Option<Vec<u8>>, // default is empty
     ) -> BoxPinFut<Result<reqwest::Response, BoxError>>;
 
+    /// Makes an HTTPs request with per-request options
+    ///
+    /// The default implementation only applies the total timeout and response size limit,
+    /// clients should override it to apply connect timeout, read timeout and redirect policy.
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`
+    /// * `method` - HTTP method (GET, POST, etc.)
+    /// * `headers` - Optional HTTP headers
+    /// * `body` - Optional request body (default empty)
+    /// * `opts` - Per-request options
+    fn https_call_with_options(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
+        let fut = self.https_call(url, method, headers, body);
+        Box::pin(async move { apply_http_options(fut, &opts).await })
+    }
+
     /// Makes a signed HTTPs request with message authentication
     ///
     /// # Arguments
@@ -389,6 +417,33 @@ impl HttpFeatures for &Web3SDK {
         }
     }
 
+    /// Makes an HTTPs request with per-request options
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`
+    /// * `method` - HTTP method (GET, POST, etc.)
+    /// * `headers` - Optional HTTP headers
+    /// * `body` - Optional request body (default empty)
+    /// * `opts` - Per-request options
+    async fn https_call_with_options(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        match self {
+            Web3SDK::Tee(cli) => {
+                apply_http_options(cli.https_call(url, method, headers, body), &opts).await
+            }
+            Web3SDK::Web3(Web3Client { client: cli }) => {
+                cli.https_call_with_options(url.to_string(), method, headers, body, opts)
+                    .await
+            }
+        }
+    }
+
     /// Makes a signed HTTPs request with message authentication
     ///
     /// # Arguments
@@ -444,4 +499,13 @@ impl HttpFeatures for &Web3SDK {
             }
         }
     }
+
+    /// Connects to a WebSocket server
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `wss://`
+    /// * `opts` - Handshake headers, keepalive and buffer options
+    async fn websocket_connect(&self, url: &str, opts: WsOptions) -> Result<WebSocket, BoxError> {
+        websocket_connect(url, opts, CancellationToken::new(), false).await
+    }
 }
@@ -0,0 +1,170 @@
+//! WebSocket client implementation for `HttpFeatures::websocket_connect`.
+//!
+//! The connection runs in a background task bridged to the [`WebSocket`] channels.
+//! It sends keepalive pings, answers pings from the server, and closes the connection
+//! when the cancellation token is cancelled or the [`WebSocket`] is dropped.
+
+use anda_core::{BoxError, CancellationToken, WebSocket, WsMessage, WsOptions};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    connect_async_with_config,
+    tungstenite::{
+        Message,
+        client::IntoClientRequest,
+        protocol::{CloseFrame, WebSocketConfig, frame::coding::CloseCode},
+    },
+};
+
+fn to_message(msg: WsMessage) -> Message {
+    match msg {
+        WsMessage::Text(text) => Message::Text(text.into()),
+        WsMessage::Binary(data) => Message::Binary(data),
+        WsMessage::Ping(data) => Message::Ping(data),
+        WsMessage::Pong(data) => Message::Pong(data),
+        WsMessage::Close(frame) => Message::Close(frame.map(|(code, reason)| CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.into(),
+        })),
+    }
+}
+
+fn from_message(msg: Message) -> Option<WsMessage> {
+    match msg {
+        Message::Text(text) => Some(WsMessage::Text(text.as_str().to_string())),
+        Message::Binary(data) => Some(WsMessage::Binary(data)),
+        Message::Ping(data) => Some(WsMessage::Ping(data)),
+        Message::Pong(data) => Some(WsMessage::Pong(data)),
+        Message::Close(frame) => Some(WsMessage::Close(
+            frame.map(|f| (u16::from(f.code), f.reason.as_str().to_string())),
+        )),
+        Message::Frame(_) => None,
+    }
+}
+
+/// Connects to a WebSocket server and bridges the connection to a [`WebSocket`].
+///
+/// # Arguments
+/// * `url` - Target URL, should start with `wss://` unless `allow_ws` is true;
+/// * `opts` - Connection options;
+/// * `cancellation_token` - The connection is closed when it is cancelled;
+/// * `allow_ws` - Allows insecure `ws://` connections.
+pub async fn websocket_connect(
+    url: &str,
+    opts: WsOptions,
+    cancellation_token: CancellationToken,
+    allow_ws: bool,
+) -> Result<WebSocket, BoxError> {
+    if !(url.starts_with("wss://") || (allow_ws && url.starts_with("ws://"))) {
+        return Err("Invalid url, must start with wss://".into());
+    }
+
+    let mut req = url.into_client_request()?;
+    if let Some(headers) = opts.headers {
+        req.headers_mut().extend(headers);
+    }
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(opts.max_message_size))
+        .max_frame_size(Some(opts.max_message_size));
+    let (stream, _) = tokio::time::timeout(
+        opts.connect_timeout,
+        connect_async_with_config(req, Some(config), false),
+    )
+    .await
+    .map_err(|_| format!("WebSocket connect to {} timed out", url))??;
+
+    let buffer = opts.buffer.max(1);
+    let (out_tx, mut out_rx) = mpsc::channel::<WsMessage>(buffer);
+    let (in_tx, in_rx) = mpsc::channel::<Result<WsMessage, BoxError>>(buffer);
+    let ping_interval = opts.ping_interval;
+
+    tokio::spawn(async move {
+        let (mut sink, mut stream) = stream.split();
+        let mut ping =
+            ping_interval.map(|d| tokio::time::interval_at(tokio::time::Instant::now() + d, d));
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    let _ = sink.send(Message::Close(None)).await;
+                    break;
+                }
+                _ = async {
+                    match ping.as_mut() {
+                        Some(ping) => ping.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if sink.send(Message::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                }
+                msg = out_rx.recv() => match msg {
+                    Some(msg) => {
+                        if let Err(err) = sink.send(to_message(msg)).await {
+                            let _ = in_tx.send(Err(err.into())).await;
+                            break;
+                        }
+                    }
+                    None => {
+                        // the WebSocket was dropped
+                        let _ = sink.send(Message::Close(None)).await;
+                        break;
+                    }
+                },
+                msg = stream.next() => match msg {
+                    Some(Ok(msg)) => {
+                        if let Some(msg) = from_message(msg) {
+                            // waits for the receiver when the buffer is full (backpressure)
+                            if in_tx.send(Ok(msg)).await.is_err() {
+                                let _ = sink.send(Message::Close(None)).await;
+                                break;
+                            }
+                        }
+                    }
+                    Some(Err(err)) => {
+                        let _ = in_tx.send(Err(err.into())).await;
+                        break;
+                    }
+                    None => break,
+                },
+            }
+        }
+    });
+
+    Ok(WebSocket::new(out_tx, in_rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_conversion() {
+        let msgs = vec![
+            WsMessage::Text("hello".to_string()),
+            WsMessage::Binary(vec![1, 2, 3].into()),
+            WsMessage::Ping(vec![1].into()),
+            WsMessage::Pong(vec![2].into()),
+            WsMessage::Close(None),
+            WsMessage::Close(Some((1000, "bye".to_string()))),
+        ];
+        for msg in msgs {
+            assert_eq!(from_message(to_message(msg.clone())), Some(msg));
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_websocket_connect_invalid_url() {
+        let token = CancellationToken::new();
+        let res = websocket_connect(
+            "ws://127.0.0.1:1",
+            WsOptions::default(),
+            token.clone(),
+            false,
+        )
+        .await;
+        assert!(res.unwrap_err().to_string().contains("wss://"));
+        let res = websocket_connect("https://example.com", WsOptions::default(), token, true).await;
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,561 @@
+//! Encryption at rest of the store objects.
+//!
+//! Objects persisted outside the TEE, e.g. in an S3 bucket, are readable by whoever has access
+//! to the backend. A [`Store`] with a [`StoreCipher`] encrypts the object bodies with AES-256-GCM
+//! before they are written, and decrypts them transparently in `store_get`. The key of each
+//! namespace (an agent, a tool or a system namespace such as the audit log) is derived with
+//! `a256gcm_key` from its path by [`StoreKeys`], and the path of each object is authenticated,
+//! so that an object moved to another path fails to decrypt.
+//!
+//! An encrypted object is `magic (4 bytes) || nonce (12 bytes) || ciphertext || tag (16 bytes)`,
+//! so the sizes listed by `store_list` include these 32 bytes. Objects written from a stream
+//! are encrypted in segments of 64 KiB instead, each with its own tag, so that they are
+//! encrypted while they are uploaded and that a range is read by decrypting only the segments
+//! covering it. Objects without the magic prefix
+//! were written before the encryption was enabled, they are read as plaintext if
+//! [`StoreEncryptionConfig::allow_plaintext`] is set, and encrypted when rewritten.
+//!
+//! Engines running in a TEE encrypt their store by default, see
+//! [`crate::engine::EngineBuilder::with_store_encryption`].
+//!
+//! [`Store`]: crate::store::Store
+
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use anda_core::{BoxError, ObjectMeta, Path};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    context::{Web3Client, Web3SDK},
+    store::get_range_raw,
+};
+
+/// The first derivation path component of the store keys.
+pub static STORE_KEY_PATH: &str = "_store";
+
+/// The prefix of the encrypted objects.
+const MAGIC: &[u8; 4] = b"AGCM";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The prefix of the objects encrypted in segments, written from streams.
+const SEGMENTED_MAGIC: &[u8; 4] = b"AGCS";
+/// The plaintext size of the segments.
+const SEGMENT_SIZE: usize = 64 * 1024;
+/// `magic (4 bytes) || segment size (u32, big-endian) || nonce prefix (8 bytes)`.
+const SEGMENTED_HEADER_LEN: usize = 16;
+
+/// Configuration of the encryption at rest of the store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreEncryptionConfig {
+    /// Encrypts the objects, by default if the engine runs in a TEE.
+    pub enabled: Option<bool>,
+    /// Reads the objects written before the encryption was enabled as plaintext, true by
+    /// default. Disable it once all objects are encrypted, so that unencrypted objects put
+    /// in the backend are rejected.
+    pub allow_plaintext: bool,
+}
+
+impl Default for StoreEncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: None,
+            allow_plaintext: true,
+        }
+    }
+}
+
+/// Derives the keys of the store namespaces.
+#[async_trait]
+pub trait StoreKeys: Send + Sync {
+    /// Derives a 256-bit AES-GCM key from the derivation path.
+    async fn a256gcm_key(&self, derivation_path: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError>;
+}
+
+/// Derives the store keys from the keys of the engine, in the TEE.
+pub struct Web3StoreKeys {
+    web3: Arc<Web3SDK>,
+}
+
+impl Web3StoreKeys {
+    pub fn new(web3: Arc<Web3SDK>) -> Self {
+        Self { web3 }
+    }
+}
+
+#[async_trait]
+impl StoreKeys for Web3StoreKeys {
+    async fn a256gcm_key(&self, derivation_path: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError> {
+        match self.web3.as_ref() {
+            Web3SDK::Tee(cli) => cli.a256gcm_key(derivation_path).await,
+            Web3SDK::Web3(Web3Client { client: cli }) => cli.a256gcm_key(derivation_path).await,
+        }
+    }
+}
+
+/// Encrypts and decrypts the store objects with the keys of their namespaces.
+pub struct StoreCipher {
+    keys: Arc<dyn StoreKeys>,
+    allow_plaintext: bool,
+    /// Derived keys by namespace.
+    ciphers: RwLock<HashMap<String, Arc<Aes256Gcm>>>,
+}
+
+impl StoreCipher {
+    pub fn new(keys: Arc<dyn StoreKeys>) -> Self {
+        Self {
+            keys,
+            allow_plaintext: false,
+            ciphers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the objects without the magic prefix as plaintext.
+    pub fn with_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    async fn cipher(&self, namespace: &Path) -> Result<Arc<Aes256Gcm>, BoxError> {
+        if let Some(cipher) = self
+            .ciphers
+            .read()
+            .expect("ciphers lock poisoned")
+            .get(namespace.as_ref())
+        {
+            return Ok(cipher.clone());
+        }
+
+        let key = self
+            .keys
+            .a256gcm_key(vec![
+                STORE_KEY_PATH.as_bytes().to_vec(),
+                namespace.as_ref().as_bytes().to_vec(),
+            ])
+            .await?;
+        let cipher = Arc::new(Aes256Gcm::new(&Key::<Aes256Gcm>::from(key)));
+        self.ciphers
+            .write()
+            .expect("ciphers lock poisoned")
+            .insert(namespace.to_string(), cipher.clone());
+        Ok(cipher)
+    }
+
+    /// Encrypts the body of the object at `path` in `namespace`.
+    pub async fn encrypt(
+        &self,
+        namespace: &Path,
+        path: &Path,
+        data: Bytes,
+    ) -> Result<Bytes, BoxError> {
+        let cipher = self.cipher(namespace).await?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &data,
+                    aad: path.as_ref().as_bytes(),
+                },
+            )
+            .map_err(|_| format!("failed to encrypt object {}", path))?;
+        let mut buf = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+        Ok(buf.into())
+    }
+
+    /// Returns an encryptor of the chunks of a stream, for the object at `path` in `namespace`.
+    pub async fn encryptor(
+        &self,
+        namespace: &Path,
+        path: &Path,
+    ) -> Result<SegmentEncryptor, BoxError> {
+        Ok(SegmentEncryptor {
+            cipher: self.cipher(namespace).await?,
+            path: path.clone(),
+            prefix: rand::random(),
+            index: 0,
+            buf: BytesMut::new(),
+        })
+    }
+
+    /// Decrypts the body of the object at `path` in `namespace`.
+    pub async fn decrypt(
+        &self,
+        namespace: &Path,
+        path: &Path,
+        data: Bytes,
+    ) -> Result<Bytes, BoxError> {
+        if data.starts_with(SEGMENTED_MAGIC) {
+            let segments = Segments::parse(path, &data, data.len() as u64)?;
+            let cipher = self.cipher(namespace).await?;
+            let plaintext = segments.open(&cipher, path, &data[SEGMENTED_HEADER_LEN..], 0)?;
+            return Ok(plaintext.into());
+        }
+        if !data.starts_with(MAGIC) {
+            if self.allow_plaintext {
+                return Ok(data);
+            }
+            return Err(format!("object {} is not encrypted", path).into());
+        }
+        if data.len() < MAGIC.len() + NONCE_LEN + TAG_LEN {
+            return Err(format!("object {} is truncated", path).into());
+        }
+
+        let cipher = self.cipher(namespace).await?;
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(
+                &Nonce::from(<[u8; NONCE_LEN]>::try_from(nonce)?),
+                Payload {
+                    msg: ciphertext,
+                    aad: path.as_ref().as_bytes(),
+                },
+            )
+            .map_err(|_| format!("failed to decrypt object {}", path))?;
+        Ok(plaintext.into())
+    }
+
+    /// Retrieves a byte range of the object at `path` in `namespace` from the backend.
+    /// Only the segments covering the range are fetched and decrypted if the object was
+    /// written from a stream, the other objects are decrypted as a whole.
+    pub async fn get_range(
+        &self,
+        store: &dyn ObjectStore,
+        namespace: &Path,
+        path: &Path,
+        range: Range<u64>,
+    ) -> Result<(Bytes, ObjectMeta), BoxError> {
+        let (head, mut meta) = get_range_raw(store, path, 0..SEGMENTED_HEADER_LEN as u64).await?;
+        if head.starts_with(SEGMENTED_MAGIC) {
+            let segments = Segments::parse(path, &head, meta.size)?;
+            let end = range.end.min(segments.size);
+            if range.start >= end {
+                return Err(format!("range {:?} is out of object {}", range, path).into());
+            }
+            let first = range.start / segments.segment_size;
+            let last = (end - 1) / segments.segment_size;
+            let (sealed, _) = get_range_raw(
+                store,
+                path,
+                segments.offset(first)..segments.offset(last + 1).min(meta.size),
+            )
+            .await?;
+            let cipher = self.cipher(namespace).await?;
+            let data = Bytes::from(segments.open(&cipher, path, &sealed, first)?);
+            let skip = (range.start - first * segments.segment_size) as usize;
+            meta.size = segments.size;
+            return Ok((data.slice(skip..skip + (end - range.start) as usize), meta));
+        }
+        if !head.starts_with(MAGIC) && self.allow_plaintext {
+            return get_range_raw(store, path, range).await;
+        }
+
+        let data = store.get(path).await?.bytes().await?;
+        let data = self.decrypt(namespace, path, data).await?;
+        meta.size = data.len() as u64;
+        let end = range.end.min(meta.size);
+        if range.start >= end {
+            return Err(format!("range {:?} is out of object {}", range, path).into());
+        }
+        Ok((data.slice(range.start as usize..end as usize), meta))
+    }
+}
+
+/// Encrypts the chunks of a stream in segments, see [`StoreCipher::encryptor`].
+pub struct SegmentEncryptor {
+    cipher: Arc<Aes256Gcm>,
+    path: Path,
+    prefix: [u8; 8],
+    index: u64,
+    buf: BytesMut,
+}
+
+impl SegmentEncryptor {
+    /// Returns the header of the object, written before the segments.
+    pub fn header(&self) -> Bytes {
+        let mut header = Vec::with_capacity(SEGMENTED_HEADER_LEN);
+        header.extend_from_slice(SEGMENTED_MAGIC);
+        header.extend_from_slice(&(SEGMENT_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&self.prefix);
+        header.into()
+    }
+
+    /// Buffers a chunk, returns the segments that it completed.
+    pub fn update(&mut self, chunk: &[u8]) -> Result<Vec<Bytes>, BoxError> {
+        self.buf.extend_from_slice(chunk);
+        let mut sealed = Vec::new();
+        // the last segment is only sealed by finish
+        while self.buf.len() > SEGMENT_SIZE {
+            let segment = self.buf.split_to(SEGMENT_SIZE);
+            sealed.push(self.seal(&segment, false)?);
+        }
+        Ok(sealed)
+    }
+
+    /// Seals the last segment.
+    pub fn finish(mut self) -> Result<Bytes, BoxError> {
+        let segment = self.buf.split();
+        self.seal(&segment, true)
+    }
+
+    fn seal(&mut self, segment: &[u8], last: bool) -> Result<Bytes, BoxError> {
+        let nonce = segment_nonce(&self.prefix, self.index)?;
+        let sealed = self
+            .cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: segment,
+                    aad: &segment_aad(&self.path, self.index, last),
+                },
+            )
+            .map_err(|_| format!("failed to encrypt object {}", self.path))?;
+        self.index += 1;
+        Ok(sealed.into())
+    }
+}
+
+fn segment_nonce(prefix: &[u8; 8], index: u64) -> Result<[u8; NONCE_LEN], BoxError> {
+    let index = u32::try_from(index).map_err(|_| "too many segments")?;
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..8].copy_from_slice(prefix);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    Ok(nonce)
+}
+
+/// The path, the index of the segment and whether it is the last one are authenticated,
+/// so that the segments can't be reordered and the object can't be truncated.
+fn segment_aad(path: &Path, index: u64, last: bool) -> Vec<u8> {
+    let mut aad = path.as_ref().as_bytes().to_vec();
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(last as u8);
+    aad
+}
+
+/// The layout of an object encrypted in segments.
+struct Segments {
+    segment_size: u64,
+    prefix: [u8; 8],
+    count: u64,
+    /// The size of the plaintext.
+    size: u64,
+}
+
+impl Segments {
+    fn parse(path: &Path, header: &[u8], object_size: u64) -> Result<Self, BoxError> {
+        let truncated = || format!("object {} is truncated", path);
+        if header.len() < SEGMENTED_HEADER_LEN || object_size < SEGMENTED_HEADER_LEN as u64 {
+            return Err(truncated().into());
+        }
+        let segment_size = u32::from_be_bytes(header[4..8].try_into()?) as u64;
+        if segment_size == 0 {
+            return Err(format!("object {} has invalid segments", path).into());
+        }
+        let body = object_size - SEGMENTED_HEADER_LEN as u64;
+        let count = body.div_ceil(segment_size + TAG_LEN as u64).max(1);
+        if body < count * TAG_LEN as u64 {
+            return Err(truncated().into());
+        }
+        Ok(Self {
+            segment_size,
+            prefix: header[8..16].try_into()?,
+            count,
+            size: body - count * TAG_LEN as u64,
+        })
+    }
+
+    /// Returns the offset of the segment in the object.
+    fn offset(&self, index: u64) -> u64 {
+        SEGMENTED_HEADER_LEN as u64 + index * (self.segment_size + TAG_LEN as u64)
+    }
+
+    /// Decrypts the consecutive sealed segments from the `first` one.
+    fn open(
+        &self,
+        cipher: &Aes256Gcm,
+        path: &Path,
+        sealed: &[u8],
+        first: u64,
+    ) -> Result<Vec<u8>, BoxError> {
+        let mut plaintext = Vec::with_capacity(sealed.len());
+        for (i, segment) in sealed
+            .chunks((self.segment_size as usize) + TAG_LEN)
+            .enumerate()
+        {
+            let index = first + i as u64;
+            let nonce = segment_nonce(&self.prefix, index)?;
+            let data = cipher
+                .decrypt(
+                    &Nonce::from(nonce),
+                    Payload {
+                        msg: segment,
+                        aad: &segment_aad(path, index, index + 1 == self.count),
+                    },
+                )
+                .map_err(|_| format!("failed to decrypt object {}", path))?;
+            plaintext.extend_from_slice(&data);
+        }
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{InMemory, ObjectStore, Store};
+    use anda_core::PutMode;
+    use ic_cose_types::cose::sha3_256;
+
+    struct TestKeys;
+
+    #[async_trait]
+    impl StoreKeys for TestKeys {
+        async fn a256gcm_key(&self, derivation_path: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError> {
+            Ok(sha3_256(&derivation_path.concat()))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_encrypted_store() {
+        let backend = Arc::new(InMemory::new());
+        let store = Store::new(backend.clone()).with_cipher(StoreCipher::new(Arc::new(TestKeys)));
+        let namespace = Path::from("T:memory");
+        let path = Path::from("notes");
+
+        store
+            .store_put(&namespace, &path, PutMode::Create, "secret notes".into())
+            .await
+            .unwrap();
+        let (data, meta) = store.store_get(&namespace, &path).await.unwrap();
+        assert_eq!(&data[..], b"secret notes");
+        assert_eq!(meta.size as usize, data.len());
+
+        // the backend only sees the ciphertext
+        let raw = backend
+            .get(&Path::from("t:memory/notes"))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert!(raw.starts_with(MAGIC));
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+
+        // moved to another path, it fails to decrypt
+        backend
+            .put(&Path::from("t:memory/moved"), raw.into())
+            .await
+            .unwrap();
+        assert!(
+            store
+                .store_get(&namespace, &Path::from("moved"))
+                .await
+                .is_err()
+        );
+
+        // renamed by the store, it is encrypted again
+        store
+            .store_rename_if_not_exists(&namespace, &path, &Path::from("renamed"))
+            .await
+            .unwrap();
+        let (data, _) = store
+            .store_get(&namespace, &Path::from("renamed"))
+            .await
+            .unwrap();
+        assert_eq!(&data[..], b"secret notes");
+        assert!(store.store_get(&namespace, &path).await.is_err());
+
+        // plaintext objects are rejected unless allowed
+        backend
+            .put(&Path::from("t:memory/legacy"), Bytes::from("legacy").into())
+            .await
+            .unwrap();
+        assert!(
+            store
+                .store_get(&namespace, &Path::from("legacy"))
+                .await
+                .is_err()
+        );
+        let store = Store::new(backend)
+            .with_cipher(StoreCipher::new(Arc::new(TestKeys)).with_plaintext(true));
+        let (data, _) = store
+            .store_get(&namespace, &Path::from("legacy"))
+            .await
+            .unwrap();
+        assert_eq!(&data[..], b"legacy");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_encrypted_stream() {
+        let backend = Arc::new(InMemory::new());
+        let store = Store::new(backend.clone()).with_cipher(StoreCipher::new(Arc::new(TestKeys)));
+        let namespace = Path::from("T:memory");
+        let path = Path::from("stream");
+        let data: Vec<u8> = (0..SEGMENT_SIZE * 3 + 7).map(|i| (i % 251) as u8).collect();
+        let chunks: Vec<Result<Bytes, BoxError>> = data
+            .chunks(10_000)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        store
+            .store_put_stream(&namespace, &path, Box::pin(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+
+        let (all, meta) = store.store_get(&namespace, &path).await.unwrap();
+        assert_eq!(&all[..], &data[..]);
+        assert_eq!(meta.size as usize, data.len());
+
+        // ranges within a segment, across segments and past the end
+        let start = SEGMENT_SIZE as u64 - 5;
+        for range in [10..20, start..start + SEGMENT_SIZE as u64 + 10, 0..u64::MAX] {
+            let (part, meta) = store
+                .store_get_range(&namespace, &path, range.clone())
+                .await
+                .unwrap();
+            let end = (range.end as usize).min(data.len());
+            assert_eq!(&part[..], &data[range.start as usize..end]);
+            assert_eq!(meta.size as usize, data.len());
+        }
+
+        // an empty stream
+        let empty = Path::from("empty");
+        store
+            .store_put_stream(
+                &namespace,
+                &empty,
+                Box::pin(futures::stream::empty::<Result<Bytes, BoxError>>()),
+            )
+            .await
+            .unwrap();
+        let (all, _) = store.store_get(&namespace, &empty).await.unwrap();
+        assert!(all.is_empty());
+
+        // a truncated object fails to decrypt, the last segment is authenticated as such
+        let raw_path = Path::from("t:memory/stream");
+        let raw = backend.get(&raw_path).await.unwrap().bytes().await.unwrap();
+        assert!(raw.starts_with(SEGMENTED_MAGIC));
+        let truncated = raw.slice(..SEGMENTED_HEADER_LEN + 2 * (SEGMENT_SIZE + TAG_LEN));
+        backend.put(&raw_path, truncated.into()).await.unwrap();
+        assert!(store.store_get(&namespace, &path).await.is_err());
+        assert!(
+            store
+                .store_get_range(&namespace, &path, SEGMENT_SIZE as u64..u64::MAX)
+                .await
+                .is_err()
+        );
+    }
+}
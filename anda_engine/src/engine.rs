@@ -30,31 +30,89 @@
 //! ```
 
 use anda_core::{
-    ANONYMOUS, Agent, AgentInput, AgentOutput, AgentSet, BoxError, Function, Path, RequestMeta,
-    ThreadMeta, Tool, ToolInput, ToolOutput, ToolSet, Value, validate_function_name,
+    ANONYMOUS, Agent, AgentEvent, AgentInput, AgentOutput, AgentSet, BoxError, Clock, Error,
+    Extensions, FeatureFlags, Function, HttpFeatures, Path, Pricing, RandomSource, RequestId,
+    RequestMeta, RetryPolicy as HttpRetryPolicy, RpcRetryPolicy, SystemClock, SystemRandom,
+    ThreadMeta, Tool, ToolInput, ToolOutput, ToolSet, Usage, Value, validate_function_name,
 };
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use candid::Principal;
+use futures::{FutureExt, future::join_all};
 use object_store::memory::InMemory;
+use serde_json::json;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    sync::Arc,
+    future::Future,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 use structured_logger::unix_ms;
+use tokio::sync::{Notify, mpsc};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    context::{AgentCtx, BaseCtx, Web3Client, Web3SDK},
+    api_key::ApiKeys,
+    attachment::AttachmentScanning,
+    audit::AuditAction,
+    context::{
+        AccessPolicy, AgentCtx, BaseCtx, CacheService, CanisterPolicy, Capabilities, HttpPolicy,
+        PolicyCanisterConfig, RemoteHealth, Tenants, Web3Client, Web3SDK,
+    },
+    encryption::{StoreCipher, Web3StoreKeys},
+    flags::{evaluate_feature_flags, validate_feature_flag},
+    ingest::Ingestor,
+    jobs::Jobs,
+    knowledge::KnowledgeBase,
     management::{Management, SYSTEM_PATH, ThreadMetaTool, UserStateTool, UserStateWrapper},
+    metering::Metering,
     model::Model,
+    payment::{self, Payment},
+    quota::{Quotas, quota_user},
+    rate_limit::RateLimiter,
+    registry::{self, Registry},
+    secrets::redact,
+    snapshot::{CacheSnapshot, EngineSnapshot},
     store::Store,
+    telemetry::record_usage,
+    vector::VectorIndex,
+    webhook::{self, Webhooks},
 };
 
 pub use crate::{
-    context::{Information, RemoteEngineArgs, RemoteEngines},
+    api_key::ApiKeyInfo,
+    audit::{AuditConfig, AuditLog, Web3AuditSigner},
+    config::EngineConfig,
+    context::{
+        AgentCapabilities, AgentCard, AgentSkill, CacheConfig, CachePolicy, CacheStats,
+        EngineStats, HealthCheck, Information, Readiness, RemoteEngineArgs, RemoteEngineHealth,
+        RemoteEngines, RemoteHealthConfig, RunInfo, ShutdownReport, Tenant, TenantInfo,
+        TenantQuota,
+    },
+    encryption::StoreEncryptionConfig,
+    flags::FeatureFlag,
+    history::HistoryConfig,
+    ingest::{IngestDocument, IngestReport},
+    jobs::{JobInfo, JobSpec, JobStatus, JobTarget, JobsConfig, RetryPolicy},
+    knowledge::{CollectionInfo, KnowledgeDocumentInfo, KnowledgeScope},
     management::{ManagementBuilder, Visibility},
+    memory::MemoryConfig,
+    metering::{BillingHook, BillingRecord, MeteringConfig, UsageCounters},
+    middleware::{AgentHook, AgentHooks, ToolMiddleware, ToolMiddlewares},
+    payment::{PaymentPolicy, Price},
+    quota::{QuotaConfig, QuotaLimits, UserUsage},
+    rate_limit::{RateLimit, RateLimitConfig},
+    registry::{EngineRecord, RegistryConfig},
+    snapshot::SnapshotReport,
+    webhook::WebhookEvent,
 };
 
+/// Capacity of the event stream of [`Engine::agent_run_events`].
+pub const RUN_EVENTS_CAPACITY: usize = 256;
+
 /// Engine is the core component that manages agents, tools, and execution context.
 /// It provides methods to interact with agents, call tools, and manage execution.
 #[derive(Clone)]
@@ -64,16 +122,41 @@ pub struct Engine {
     name: String,
     description: String,
     default_agent: String,
-    export_agents: BTreeSet<String>,
-    export_tools: BTreeSet<String>,
+    export_agents: Arc<ArcSwap<BTreeSet<String>>>,
+    export_tools: Arc<ArcSwap<BTreeSet<String>>>,
     hooks: Arc<Hooks>,
     management: Arc<Management>,
+    runs: Arc<RunTracker>,
+    api_keys: Option<Arc<ApiKeys>>,
+    webhooks: Option<Arc<Webhooks>>,
+    payments: Arc<PaymentPolicy>,
+    attachments: Option<Arc<AttachmentScanning>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Feature flags, swapped on config reload or by the managers.
+    flags: Arc<ArcSwap<BTreeMap<String, FeatureFlag>>>,
+    snapshots: bool,
+    cache: Arc<CacheConfig>,
+    registry: Option<Arc<Registry>>,
+    policy_canister: Option<Arc<PolicyCanisterConfig>>,
 }
 
 /// Hook trait for customizing engine behavior.
 /// Hooks can be used to intercept and modify agent and tool execution.
 #[async_trait]
 pub trait Hook: Send + Sync {
+    /// Called when the context of an agent run or a tool call is created, before the other
+    /// hooks, to set request-scoped values such as a request ID, the locale of the user or an
+    /// experiment variant. The agents and tools read them with
+    /// [`anda_core::StateFeatures::extensions`].
+    async fn on_request(
+        &self,
+        _caller: &Principal,
+        _meta: &RequestMeta,
+        _extensions: &mut Extensions,
+    ) -> Result<(), BoxError> {
+        Ok(())
+    }
+
     /// Called before an agent is executed.
     async fn on_agent_start(
         &self,
@@ -140,6 +223,18 @@ impl Hooks {
 
 #[async_trait]
 impl Hook for Hooks {
+    async fn on_request(
+        &self,
+        caller: &Principal,
+        meta: &RequestMeta,
+        extensions: &mut Extensions,
+    ) -> Result<(), BoxError> {
+        for hook in &self.hooks {
+            hook.on_request(caller, meta, extensions).await?;
+        }
+        Ok(())
+    }
+
     async fn on_agent_start(
         &self,
         ctx: &AgentCtx,
@@ -235,8 +330,8 @@ impl Engine {
         meta: RequestMeta,
     ) -> Result<AgentCtx, BoxError> {
         let name = agent_name.to_ascii_lowercase();
-        if !self.export_agents.contains(&name) || !self.ctx.agents.contains(&name) {
-            return Err(format!("agent {} not found", name).into());
+        if !self.export_agents.load().contains(&name) || !self.ctx.agents.contains(&name) {
+            return Err(Error::NotFound(format!("agent {}", name)).into());
         }
 
         self.ctx.child_with(caller, &name, meta)
@@ -246,9 +341,109 @@ impl Engine {
     /// If no agent name is provided, uses the default agent.
     /// Returns the agent's output or an error if the agent is not found.
     pub async fn agent_run(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+    ) -> Result<AgentOutput, BoxError> {
+        self.agent_run_with(caller, input, None, self.cancellation_token())
+            .await
+    }
+
+    /// Executes an agent and sends its progress events, without the final output event.
+    /// Unlike [`Engine::agent_run_events`], the run is cancelled if the future is dropped.
+    pub async fn agent_run_with_events(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+        events: mpsc::UnboundedSender<AgentEvent>,
+    ) -> Result<AgentOutput, BoxError> {
+        self.agent_run_with(caller, input, Some(events), self.cancellation_token())
+            .await
+    }
+
+    /// Executes an agent in a background task and streams its progress events.
+    /// The stream ends with an [`AgentEvent::Output`] or an [`AgentEvent::Error`] event.
+    ///
+    /// The stream holds up to [`RUN_EVENTS_CAPACITY`] events, the run waits for a slow
+    /// receiver, and it is cancelled when the receiver is dropped.
+    pub fn agent_run_events(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+    ) -> mpsc::Receiver<AgentEvent> {
+        let (tx, rx) = mpsc::channel(RUN_EVENTS_CAPACITY);
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let token = engine.cancellation_token();
+            let (events, mut progress) = mpsc::unbounded_channel();
+            let run = engine.agent_run_with(caller, input, Some(events), token.clone());
+            tokio::pin!(run);
+
+            // the run is not polled while an event waits for room in the stream
+            let mut closed = false;
+            let res = loop {
+                tokio::select! {
+                    res = &mut run => break res,
+                    Some(event) = progress.recv() => {
+                        if !closed && tx.send(event).await.is_err() {
+                            closed = true;
+                            token.cancel();
+                        }
+                    }
+                    _ = tx.closed(), if !closed => {
+                        closed = true;
+                        token.cancel();
+                    }
+                }
+            };
+            if closed {
+                return;
+            }
+
+            while let Ok(event) = progress.try_recv() {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            let event = match res {
+                Ok(output) => AgentEvent::Output(output),
+                Err(err) => AgentEvent::Error {
+                    error: redact(&err.to_string()),
+                },
+            };
+            let _ = tx.send(event).await;
+        });
+        rx
+    }
+
+    #[tracing::instrument(name = "agent_run", skip_all, fields(
+        engine = %self.id,
+        agent = %input.name,
+        caller = %caller,
+        input_tokens = tracing::field::Empty,
+        output_tokens = tracing::field::Empty,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
+    async fn agent_run_with(
+        &self,
+        caller: Principal,
+        input: AgentInput,
+        events: Option<mpsc::UnboundedSender<AgentEvent>>,
+        token: CancellationToken,
+    ) -> Result<AgentOutput, BoxError> {
+        record_usage(
+            self.run_agent(caller, input, events, token).await,
+            |output| &output.usage,
+        )
+    }
+
+    async fn run_agent(
         &self,
         caller: Principal,
         mut input: AgentInput,
+        events: Option<mpsc::UnboundedSender<AgentEvent>>,
+        token: CancellationToken,
     ) -> Result<AgentOutput, BoxError> {
         let mut meta = input.meta.unwrap_or_default();
         if meta.engine.is_some() && meta.engine != Some(self.id) {
@@ -269,7 +464,16 @@ impl Engine {
             .ctx
             .agents
             .get(&input.name)
-            .ok_or_else(|| format!("agent {} not found", input.name))?;
+            .ok_or_else(|| Error::NotFound(format!("agent {}", input.name)))?;
+        self.ctx.base.access_policy.load().check_agent(
+            &caller,
+            meta.user.as_deref(),
+            &input.name,
+        )?;
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.try_acquire(&caller, &input.name)?;
+        }
+        let mut run = self.runs.enter("agent", &input.name, caller, token)?;
 
         let visibility = self.management.try_get_visibility(&caller)?;
         let mut sw = if visibility == Visibility::Public {
@@ -278,10 +482,25 @@ impl Engine {
         } else {
             let sw = self.management.load_user_state(&caller).await?;
             if !sw.has_permission(&caller, unix_ms()) {
-                return Err("caller does not have permission".into());
+                return Err(
+                    Error::Unauthorized("caller does not have permission".to_string()).into(),
+                );
             }
             sw
         };
+        let tenant = self.ctx.base.tenants.of(&caller);
+        if let Some(tenant) = &tenant {
+            tenant.try_request(unix_ms())?;
+        }
+        if let Some(quotas) = &self.ctx.base.quotas {
+            quotas
+                .check(
+                    &quota_user(&caller, &meta),
+                    true,
+                    self.ctx.base.clock.now_ms(),
+                )
+                .await?;
+        }
 
         let thread = self
             .management
@@ -289,7 +508,26 @@ impl Engine {
             .await?;
 
         meta.thread = Some(thread.id.clone());
-        let ctx = self.ctx_with(caller, &input.name, meta.clone())?;
+        let mut ctx = self.ctx_with(caller, &input.name, meta.clone())?;
+        ctx.base.events = events;
+        ctx.base.cancellation_token = run.token.clone();
+        ctx.base.extensions.insert(RequestId(run.id));
+        ctx.base
+            .extensions
+            .insert(self.evaluate_flags(&caller, &meta, tenant.as_deref()));
+        self.hooks
+            .on_request(&caller, &ctx.base.meta, &mut ctx.base.extensions)
+            .await?;
+        if let (Some(scanning), Some(resources)) = (&self.attachments, input.resources.as_mut()) {
+            *resources = scanning
+                .check(
+                    ctx.base.store(),
+                    &caller,
+                    &input.name,
+                    std::mem::take(resources),
+                )
+                .await?;
+        }
         self.hooks
             .on_agent_start(&ctx, &input.name, &thread, &mut sw)
             .await?;
@@ -299,23 +537,91 @@ impl Engine {
         // should save the thread meta before running the agent
         self.management.save_thread_meta(thread).await?;
 
-        let output = agent
-            .run(ctx.clone(), input.prompt, input.resources)
+        ctx.agent_hooks
+            .before_run(&ctx, &input.name, &mut input.prompt, &mut input.resources)
             .await?;
-        let mut output = self.hooks.on_agent_end(&ctx, &input.name, output).await?;
+        let target = format!("A:{}", input.name);
+        let payment = self
+            .charge(caller, &target, self.payments.agent_price(&input.name))
+            .await?;
+        let args_sha3 = ctx
+            .base
+            .audit_call_digest(&(&input.prompt, &input.resources));
+        let started = ctx.base.clock.instant();
+        let res = tokio::select! {
+            res = ctx.base.guard(agent.run(ctx.clone(), input.prompt.clone(), input.resources)) => res,
+            _ = ctx.base.cancellation_token.cancelled() => {
+                Err(Error::Cancelled(format!("agent {} run cancelled", input.name)).into())
+            }
+        };
+        let res = match res {
+            Ok(output) => {
+                ctx.agent_hooks
+                    .after_run(&ctx, &input.name, &input.prompt, output)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let res = match res {
+            Ok(output) => self.hooks.on_agent_end(&ctx, &input.name, output).await,
+            Err(err) => Err(err),
+        };
+        ctx.base
+            .audit_call(
+                AuditAction::AgentRunEnd,
+                &input.name,
+                args_sha3,
+                res.as_ref().map(|output| (output, &output.usage)),
+                started,
+            )
+            .await;
+        let mut output = match res {
+            Ok(output) => output,
+            Err(err) => {
+                self.refund(&target, payment).await;
+                return Err(err);
+            }
+        };
+        if output.failed_reason.is_some() {
+            self.refund(&target, payment).await;
+        }
         output.thread = meta.thread;
         output.full_history = None; // clear full history
+        run.finish(&output.usage);
+        if let Some(tenant) = &tenant {
+            tenant.record_usage(&output.usage, unix_ms());
+        }
+        ctx.base.meter(|u| {
+            u.agent_runs += 1;
+            u.add_tokens(&output.usage);
+        });
         Ok(output)
     }
 
     /// Calls a tool by name with the specified arguments.
     /// Returns tuple containing the result string and a boolean indicating if further processing is needed.
+    #[tracing::instrument(name = "tool_call", skip_all, fields(
+        engine = %self.id,
+        tool = %input.name,
+        caller = %caller,
+        input_tokens = tracing::field::Empty,
+        output_tokens = tracing::field::Empty,
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    ))]
     pub async fn tool_call(
         &self,
         caller: Principal,
         input: ToolInput<Value>,
     ) -> Result<ToolOutput<Value>, BoxError> {
-        let args = serde_json::to_string(&input.args)?;
+        record_usage(self.call_tool(caller, input).await, |output| &output.usage)
+    }
+
+    async fn call_tool(
+        &self,
+        caller: Principal,
+        input: ToolInput<Value>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
         let meta = input.meta.unwrap_or_default();
         if meta.engine.is_some() && meta.engine != Some(self.id) {
             return Err(format!(
@@ -326,14 +632,29 @@ impl Engine {
             .into());
         }
 
-        if !self.export_tools.contains(&input.name) || !self.ctx.tools.contains(&input.name) {
-            return Err(format!("tool {} not found", &input.name).into());
+        if !self.export_tools.load().contains(&input.name) || !self.ctx.tools.contains(&input.name)
+        {
+            return Err(Error::NotFound(format!("tool {}", input.name)).into());
         }
         let tool = self
             .ctx
             .tools
             .get(&input.name)
-            .ok_or_else(|| format!("tool {} not found", &input.name))?;
+            .ok_or_else(|| Error::NotFound(format!("tool {}", input.name)))?;
+        self.ctx.base.access_policy.load().check_tool(
+            &caller,
+            meta.user.as_deref(),
+            &input.name,
+        )?;
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.try_acquire(&caller, &input.name)?;
+        }
+        let mut run = self.runs.enter(
+            "tool",
+            &input.name,
+            caller,
+            self.ctx.base.cancellation_token.child_token(),
+        )?;
 
         let visibility = self.management.try_get_visibility(&caller)?;
         let mut sw = if visibility == Visibility::Public {
@@ -342,19 +663,89 @@ impl Engine {
         } else {
             let sw = self.management.load_user_state(&caller).await?;
             if !sw.has_permission(&caller, unix_ms()) {
-                return Err("caller does not have permission".into());
+                return Err(
+                    Error::Unauthorized("caller does not have permission".to_string()).into(),
+                );
             }
             sw
         };
+        let tenant = self.ctx.base.tenants.of(&caller);
+        if let Some(tenant) = &tenant {
+            tenant.try_request(unix_ms())?;
+        }
+        if let Some(quotas) = &self.ctx.base.quotas {
+            quotas
+                .check(
+                    &quota_user(&caller, &meta),
+                    false,
+                    self.ctx.base.clock.now_ms(),
+                )
+                .await?;
+        }
 
-        let ctx = self.ctx.child_base_with(caller, &input.name, meta)?;
+        let mut ctx = self.ctx.child_base_with(caller, &input.name, meta)?;
+        ctx.cancellation_token = run.token.clone();
+        ctx.extensions.insert(RequestId(run.id));
+        ctx.extensions
+            .insert(self.evaluate_flags(&caller, &ctx.meta, tenant.as_deref()));
+        self.hooks
+            .on_request(&caller, &ctx.meta, &mut ctx.extensions)
+            .await?;
         self.hooks.on_tool_start(&ctx, &input.name, &mut sw).await?;
+        let mut args = input.args;
+        ctx.tool_middlewares
+            .before(&ctx, &input.name, &mut args)
+            .await?;
+        ctx.audit_tool_call(&input.name, &args).await?;
 
         sw.increment_tool_requests(unix_ms());
         self.management.save_user_state(sw.state).await?;
 
-        let output = tool.call(ctx.clone(), args, input.resources).await?;
-        self.hooks.on_tool_end(&ctx, &input.name, output).await
+        let target = format!("T:{}", input.name);
+        let payment = self
+            .charge(caller, &target, self.payments.tool_price(&input.name))
+            .await?;
+        let args_sha3 = ctx.audit_call_digest(&args);
+        let started = ctx.clock.instant();
+        let res = tokio::select! {
+            res = ctx.guard_tool(
+                &input.name,
+                tool.timeout(),
+                tool.call(ctx.clone(), args, input.resources),
+            ) => res,
+            _ = ctx.cancellation_token.cancelled() => {
+                Err(Error::Cancelled(format!("tool {} call cancelled", input.name)).into())
+            }
+        };
+        let res = ctx.tool_middlewares.after(&ctx, &input.name, res).await;
+        let res = match res {
+            Ok(output) => self.hooks.on_tool_end(&ctx, &input.name, output).await,
+            Err(err) => Err(err),
+        };
+        ctx.audit_call(
+            AuditAction::ToolCallEnd,
+            &input.name,
+            args_sha3,
+            res.as_ref().map(|output| (output, &output.usage)),
+            started,
+        )
+        .await;
+        let output = match res {
+            Ok(output) => output,
+            Err(err) => {
+                self.refund(&target, payment).await;
+                return Err(err);
+            }
+        };
+        run.finish(&output.usage);
+        if let Some(tenant) = &tenant {
+            tenant.record_usage(&output.usage, unix_ms());
+        }
+        ctx.meter(|u| {
+            u.tool_calls += 1;
+            u.add_tokens(&output.usage);
+        });
+        Ok(output)
     }
 
     /// Returns function definitions for the specified agents.
@@ -369,190 +760,1499 @@ impl Engine {
         self.ctx.tools.functions(names)
     }
 
-    /// Returns information about the engine, including agent and tool definitions.
-    pub fn information(&self) -> Information {
-        Information {
+    /// Checks that the model, the store and the remote engines are reachable.
+    /// The model is not checked if none is configured.
+    /// Remote engines are expected to still serve the same engine ID as when they were registered.
+    pub async fn readiness(&self) -> Readiness {
+        let remote = self.ctx.base.remote.load_full();
+        let mut checks = vec![(
+            "store".to_string(),
+            health_check(self.ctx.base.store().health()).boxed(),
+        )];
+        if self.ctx.model.is_implemented() {
+            checks.push((
+                "model".to_string(),
+                health_check(self.ctx.model.health()).boxed(),
+            ));
+        }
+        if let Some(model) = self.ctx.batch_model.as_ref().filter(|m| m.is_implemented()) {
+            checks.push((
+                "batch_model".to_string(),
+                health_check(model.health()).boxed(),
+            ));
+        }
+        for (name, info) in remote.engines.iter() {
+            let check = ping_remote_engine(&self.ctx.base, info);
+            checks.push((format!("remote:{}", name), health_check(check).boxed()));
+        }
+
+        let (names, futs): (Vec<_>, Vec<_>) = checks.into_iter().unzip();
+        let mut checks: BTreeMap<String, HealthCheck> =
+            names.into_iter().zip(join_all(futs).await).collect();
+        if self.is_shutting_down() {
+            checks.insert(
+                "shutdown".to_string(),
+                HealthCheck {
+                    ok: false,
+                    elapsed_ms: 0,
+                    error: Some("engine is shutting down".to_string()),
+                },
+            );
+        }
+        Readiness {
             id: self.id,
             name: self.name.clone(),
-            description: self.description.clone(),
-            endpoint: "".to_string(),
-            agents: self.agents(Some(
-                self.export_agents
-                    .iter()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            )),
-            tools: self.tools(Some(
-                self.export_tools
-                    .iter()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            )),
+            ready: checks.values().all(|c| c.ok),
+            checks,
         }
     }
-}
-
-/// Builder pattern implementation for constructing an Engine.
-/// Allows for step-by-step configuration of the engine's components.
-pub struct EngineBuilder {
-    id: Principal,
-    name: String,
-    description: String,
-    tools: ToolSet<BaseCtx>,
-    agents: AgentSet<AgentCtx>,
-    remote: BTreeMap<String, RemoteEngineArgs>,
-    model: Model,
-    store: Store,
-    web3: Arc<Web3SDK>,
-    hooks: Arc<Hooks>,
-    cancellation_token: CancellationToken,
-    export_agents: BTreeSet<String>,
-    export_tools: BTreeSet<String>,
-    management: ManagementBuilder,
-}
 
-impl Default for EngineBuilder {
-    fn default() -> Self {
-        Self::new()
+    /// Checks the remote engines concurrently and records their health, so that the calls to
+    /// the unhealthy ones fail fast instead of timing out.
+    pub async fn check_remote_engines(&self, timeout: Duration) {
+        let remote = self.ctx.base.remote.load_full();
+        let health = &self.ctx.base.remote_health;
+        health.retain(&remote);
+        let checks = remote.engines.values().map(|info| async move {
+            let res = match tokio::time::timeout(timeout, ping_remote_engine(&self.ctx.base, info))
+                .await
+            {
+                Ok(res) => res.map_err(|err| err.to_string()),
+                Err(_) => Err(format!("check timed out after {:?}", timeout)),
+            };
+            if let Err(err) = &res {
+                log::warn!(endpoint = info.endpoint; "remote engine check failed: {}", err);
+            }
+            health.record(&info.endpoint, res, self.ctx.base.clock.now_ms());
+        });
+        join_all(checks).await;
     }
-}
 
-impl EngineBuilder {
-    /// Creates a new EngineBuilder with default values.
-    pub fn new() -> Self {
-        let mstore = Arc::new(InMemory::new());
-        EngineBuilder {
-            id: Principal::anonymous(),
-            name: "Anda".to_string(),
-            description: "".to_string(),
-            tools: ToolSet::new(),
-            agents: AgentSet::new(),
-            remote: BTreeMap::new(),
-            model: Model::not_implemented(),
-            store: Store::new(mstore),
-            web3: Arc::new(Web3SDK::Web3(Web3Client::not_implemented())),
-            hooks: Arc::new(Hooks { hooks: Vec::new() }),
-            cancellation_token: CancellationToken::new(),
-            export_agents: BTreeSet::new(),
-            export_tools: BTreeSet::new(),
-            management: ManagementBuilder::new(Visibility::Private, Principal::anonymous()),
-        }
+    /// Returns the health of the remote engines by endpoint, empty if they are not checked.
+    pub fn remote_health(&self) -> BTreeMap<String, RemoteEngineHealth> {
+        self.ctx.base.remote_health.snapshot()
     }
 
-    /// Sets the engine ID, which comes from the TEE.
-    pub fn with_id(mut self, id: Principal) -> Self {
-        self.id = id;
-        self
+    /// Returns true once [`Engine::shutdown`] has been called, new runs are rejected.
+    pub fn is_shutting_down(&self) -> bool {
+        self.runs.draining.load(Ordering::SeqCst)
     }
 
-    /// Sets the engine name.
-    pub fn with_name(mut self, name: String) -> Result<Self, BoxError> {
-        validate_function_name(&name.to_ascii_lowercase())?;
-        self.name = name;
-        Ok(self)
+    /// Returns the number of agent runs and tool calls in flight.
+    pub fn in_flight(&self) -> usize {
+        self.runs.in_flight.load(Ordering::SeqCst)
     }
 
-    pub fn with_description(mut self, description: String) -> Self {
-        self.description = description;
-        self
+    /// Returns the agent runs and tool calls in flight.
+    pub fn active_runs(&self) -> Vec<RunInfo> {
+        self.runs.active_runs()
     }
 
-    /// Sets the cancellation token.
-    pub fn with_cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
-        self.cancellation_token = cancellation_token;
-        self
+    /// Cancels an agent run or a tool call in flight by its ID, on behalf of a manager.
+    /// Returns false if the run is not found, e.g. it has completed.
+    pub async fn cancel_run(&self, caller: Principal, id: u64) -> Result<bool, BoxError> {
+        self.audit_admin(caller, "cancel_run", json!({"id": id}))
+            .await?;
+        Ok(self.runs.cancel(id))
     }
 
-    /// Sets the TEE (Trusted Execution Environment) client.
-    pub fn with_web3_client(mut self, web3: Arc<Web3SDK>) -> Self {
-        self.web3 = web3;
-        self
+    /// Returns the usage statistics of the engine since it was built.
+    pub fn stats(&self) -> EngineStats {
+        let mut stats = self.runs.stats.read().expect("stats lock poisoned").clone();
+        stats.in_flight = self.in_flight();
+        stats
     }
 
-    /// Sets the model to be used by the engine.
-    pub fn with_model(mut self, model: Model) -> Self {
-        self.model = model;
-        self
+    /// Returns the statistics of the caches of the agents and tools.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.ctx.base.cache_stats()
     }
 
-    /// Sets the storage backend for the engine.
-    pub fn with_store(mut self, store: Store) -> Self {
-        self.store = store;
-        self
+    /// Saves the persisted caches to the store, see [`CacheConfig::persist`].
+    /// Returns the number of saved entries.
+    pub async fn save_caches(&self) -> Result<usize, BoxError> {
+        let snapshot = CacheSnapshot {
+            engine: self.id,
+            created_at_ms: unix_ms(),
+            entries: self
+                .ctx
+                .base
+                .cache_snapshot()
+                .into_iter()
+                .filter(|entry| self.cache.persists(&entry.path))
+                .collect(),
+        };
+        snapshot.save(self.ctx.base.store()).await?;
+        Ok(snapshot.entries.len())
     }
 
-    /// Sets the management builder for the engine.
-    pub fn with_management(mut self, management: ManagementBuilder) -> Self {
-        self.management = management;
-        self
+    /// Reloads the persisted caches from the store, keeping the keys cached since the engine
+    /// was built. Returns the number of reloaded entries.
+    pub async fn reload_caches(&self) -> Result<usize, BoxError> {
+        let snapshot = match CacheSnapshot::load(self.ctx.base.store()).await? {
+            Some(snapshot) => snapshot,
+            None => return Ok(0),
+        };
+        if snapshot.engine != self.id {
+            return Err(format!(
+                "caches of engine {} can't be reloaded to engine {}",
+                snapshot.engine.to_text(),
+                self.id.to_text()
+            )
+            .into());
+        }
+        let entries: Vec<_> = snapshot
+            .entries
+            .into_iter()
+            .filter(|entry| self.cache.persists(&entry.path))
+            .collect();
+        Ok(self.ctx.base.cache_restore(&entries).await)
     }
 
-    /// Registers a single tool with the engine.
-    /// Returns an error if the tool cannot be added.
-    pub fn register_tool<T>(mut self, tool: T) -> Result<Self, BoxError>
-    where
-        T: Tool<BaseCtx> + Send + Sync + 'static,
-    {
-        self.tools.add(tool)?;
-        Ok(self)
+    /// Saves the runtime state of the engine to the store: caches, tenant usage,
+    /// statistics and runs in flight. See [`crate::snapshot`].
+    pub async fn snapshot(&self) -> Result<SnapshotReport, BoxError> {
+        let now_ms = unix_ms();
+        let snapshot = EngineSnapshot {
+            engine: self.id,
+            created_at_ms: now_ms,
+            stats: self.stats(),
+            tenants: self.ctx.base.tenants.usages(now_ms),
+            runs: self.active_runs(),
+            cache: self.ctx.base.cache_snapshot(),
+        };
+        snapshot.save(self.ctx.base.store()).await?;
+        Ok(snapshot.report())
     }
 
-    /// Registers multiple tools with the engine.
-    /// Returns an error if any tool already exists.
-    pub fn register_tools(mut self, tools: ToolSet<BaseCtx>) -> Result<Self, BoxError> {
-        for (name, tool) in tools.set {
-            if self.tools.set.contains_key(&name) {
-                return Err(format!("tool {} already exists", name).into());
+    /// Restores the runtime state from the latest snapshot in the store, None if there is none.
+    /// The statistics of the snapshot are added to the current ones.
+    pub async fn restore(&self) -> Result<Option<SnapshotReport>, BoxError> {
+        let snapshot = match EngineSnapshot::load(self.ctx.base.store()).await? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        if snapshot.engine != self.id {
+            return Err(format!(
+                "snapshot of engine {} can't be restored to engine {}",
+                snapshot.engine.to_text(),
+                self.id.to_text()
+            )
+            .into());
+        }
+
+        let mut report = snapshot.report();
+        report.cache_entries = self.ctx.base.cache_restore(&snapshot.cache).await;
+        report.tenants = 0;
+        for (id, usage) in snapshot.tenants {
+            if let Some(tenant) = self.ctx.base.tenants.get(&id) {
+                tenant.restore_usage(usage);
+                report.tenants += 1;
             }
-            self.tools.set.insert(name, tool);
         }
+        self.runs.restore_stats(&snapshot.stats);
+        Ok(Some(report))
+    }
 
-        Ok(self)
+    /// Returns the registered remote engines.
+    pub fn remote_engines(&self) -> RemoteEngines {
+        self.ctx.base.remote.load().as_ref().clone()
     }
 
-    /// Registers a single agent with the engine.
-    /// Verifies that all required tools are registered before adding the agent.
-    /// Returns an error if any dependency is missing or if the agent cannot be added.
-    pub fn register_agent<T>(mut self, agent: T) -> Result<Self, BoxError>
-    where
-        T: Agent<AgentCtx> + Send + Sync + 'static,
-    {
-        for tool in agent.tool_dependencies() {
-            if !self.tools.contains(&tool) {
-                return Err(format!("dependent tool {} not found", tool).into());
-            }
+    /// Publishes the engine to the registry canister if it has a public endpoint, and
+    /// refreshes the remote engines discovered from the registry, see [`crate::registry`].
+    /// Returns the number of remote engines.
+    pub async fn refresh_registry(&self) -> Result<usize, BoxError> {
+        let registry = self.registry.as_ref().ok_or("registry not enabled")?;
+        let web3 = self.ctx.base.web3.as_ref();
+        if registry.endpoint().is_some() {
+            registry
+                .publish(&web3, EngineRecord::from(&self.information()))
+                .await?;
         }
+        let current = self.ctx.base.remote.load_full();
+        let remote = registry.discover(web3, self.id, &current).await?;
+        let count = remote.engines.len();
+        self.ctx.base.remote.store(Arc::new(remote));
+        Ok(count)
+    }
 
-        self.agents.add(agent)?;
-        Ok(self)
+    /// Returns true if the caller is the controller or a manager of the engine.
+    pub fn is_manager(&self, caller: &Principal) -> bool {
+        self.management.is_manager(caller)
     }
 
-    /// Registers multiple agents with the engine.
-    /// Verifies that all required tools are registered for each agent.
-    /// Returns an error if any agent already exists or if any dependency is missing.
-    pub fn register_agents(mut self, agents: AgentSet<AgentCtx>) -> Result<Self, BoxError> {
-        for (name, agent) in agents.set {
-            if self.agents.set.contains_key(&name) {
-                return Err(format!("agent {} already exists", name).into());
-            }
+    /// Evicts a key from the cache of an agent ("A:{name}") or a tool ("T:{name}"),
+    /// on behalf of a manager. The key is in the namespace of a tenant, "{tenant}/{key}",
+    /// or of the callers without a tenant, "_/{key}". Returns true if the key existed.
+    pub async fn evict_cache(
+        &self,
+        caller: Principal,
+        path: &str,
+        key: &str,
+    ) -> Result<bool, BoxError> {
+        self.audit_admin(caller, "evict_cache", json!({"path": path, "key": key}))
+            .await?;
+        self.ctx.base.cache_evict(&Path::from(path), key).await
+    }
 
-            for tool in agent.tool_dependencies() {
-                if !self.tools.contains(&tool) {
-                    return Err(format!("dependent tool {} not found", tool).into());
-                }
-            }
-            self.agents.set.insert(name, agent);
+    /// Returns the prices of the agents and tools, see [`crate::payment`].
+    pub fn payment_policy(&self) -> &PaymentPolicy {
+        &self.payments
+    }
+
+    /// Pulls the price of a priced agent ("A:{name}") or tool ("T:{name}") from the caller,
+    /// None if it is free.
+    async fn charge(
+        &self,
+        caller: Principal,
+        target: &str,
+        price: Option<&Price>,
+    ) -> Result<Option<Payment>, BoxError> {
+        let price = match price {
+            Some(price) => price,
+            None => return Ok(None),
+        };
+        if caller == ANONYMOUS {
+            return Err(
+                format!("{} requires a payment, anonymous caller cannot pay", target).into(),
+            );
         }
 
-        Ok(self)
+        let payment = payment::charge(
+            &self.ctx.base.web3,
+            self.payments.account(self.id),
+            caller,
+            price,
+            target,
+        )
+        .await?;
+        self.audit_payment(
+            caller,
+            target,
+            json!({
+                "ledger": payment.ledger.to_text(),
+                "amount": payment.amount,
+                "block_index": payment.block_index.to_string(),
+            }),
+        )
+        .await;
+        Ok(Some(payment))
     }
 
-    /// Registers a remote engine with given endpoint, optional agents, tools, and alias name.
-    pub fn register_remote_engine(mut self, engine: RemoteEngineArgs) -> Result<Self, BoxError> {
-        if self.remote.contains_key(&engine.endpoint) {
-            return Err(format!("remote engine {} already exists", engine.endpoint).into());
+    /// Refunds the payment of a failed run, a failed refund is logged.
+    async fn refund(&self, target: &str, payment: Option<Payment>) {
+        let payment = match payment {
+            Some(payment) => payment,
+            None => return,
+        };
+        let subaccount = self.payments.subaccount.as_ref().map(|s| s.0);
+        match payment::refund(&self.ctx.base.web3, subaccount, &payment).await {
+            Ok(refund) => {
+                self.audit_payment(
+                    payment.payer,
+                    target,
+                    json!({
+                        "ledger": payment.ledger.to_text(),
+                        "refund_of": payment.block_index.to_string(),
+                        "block_index": refund.map(|b| b.to_string()),
+                    }),
+                )
+                .await;
+            }
+            Err(err) => log::error!(
+                payer = payment.payer.to_text(),
+                target = target,
+                block_index = payment.block_index.to_string();
+                "failed to refund payment: {}", err,
+            ),
+        }
+    }
+
+    /// Records a payment or a refund in the audit log if it is enabled.
+    /// The tokens are already transferred, so a failure is only logged.
+    async fn audit_payment(&self, caller: Principal, target: &str, detail: Value) {
+        if let Some(audit) = &self.ctx.base.audit {
+            let res = audit
+                .record(
+                    caller,
+                    &Path::from(SYSTEM_PATH),
+                    AuditAction::Payment,
+                    target.to_string(),
+                    Some(detail),
+                )
+                .await;
+            if let Err(err) = res {
+                log::error!("failed to audit payment of {}: {}", target, err);
+            }
+        }
+    }
+
+    /// Evaluates the feature flags for the caller of a request.
+    fn evaluate_flags(
+        &self,
+        caller: &Principal,
+        meta: &RequestMeta,
+        tenant: Option<&Tenant>,
+    ) -> FeatureFlags {
+        evaluate_feature_flags(
+            &self.flags.load(),
+            caller,
+            meta.user.as_deref(),
+            tenant.map(|t| t.id()),
+        )
+    }
+
+    /// Returns the feature flags, see [`crate::flags`].
+    pub fn feature_flags(&self) -> BTreeMap<String, FeatureFlag> {
+        self.flags.load().as_ref().clone()
+    }
+
+    /// Sets a feature flag on behalf of the caller, a manager,
+    /// until the next config reload or restart.
+    pub async fn set_feature_flag(
+        &self,
+        caller: Principal,
+        name: String,
+        flag: FeatureFlag,
+    ) -> Result<(), BoxError> {
+        validate_feature_flag(&name, &flag)?;
+        self.audit_admin(
+            caller,
+            "set_feature_flag",
+            json!({"name": name, "flag": flag}),
+        )
+        .await?;
+        self.flags.rcu(|flags| {
+            let mut flags = flags.as_ref().clone();
+            flags.insert(name.clone(), flag.clone());
+            flags
+        });
+        Ok(())
+    }
+
+    /// Removes a feature flag on behalf of the caller, a manager.
+    /// Returns false if the flag is not found.
+    pub async fn remove_feature_flag(
+        &self,
+        caller: Principal,
+        name: &str,
+    ) -> Result<bool, BoxError> {
+        if !self.flags.load().contains_key(name) {
+            return Ok(false);
+        }
+        self.audit_admin(caller, "remove_feature_flag", json!({"name": name}))
+            .await?;
+        self.flags.rcu(|flags| {
+            let mut flags = flags.as_ref().clone();
+            flags.remove(name);
+            flags
+        });
+        Ok(true)
+    }
+
+    /// Returns the policy restricting which tools and agents each caller may invoke.
+    pub fn access_policy(&self) -> Arc<AccessPolicy> {
+        self.ctx.base.access_policy.load_full()
+    }
+
+    /// Fetches the access policy from the policy canister and applies it.
+    pub async fn refresh_access_policy(&self) -> Result<(), BoxError> {
+        let cfg = self
+            .policy_canister
+            .as_ref()
+            .ok_or("policy canister not enabled")?;
+        let policy = cfg.fetch(&self.ctx.base.web3.as_ref()).await?;
+        self.ctx.base.access_policy.store(Arc::new(policy));
+        Ok(())
+    }
+
+    /// Sets the access policy on behalf of the caller, a manager, until the next config reload,
+    /// refresh from the policy canister or restart.
+    pub async fn set_access_policy(
+        &self,
+        caller: Principal,
+        policy: AccessPolicy,
+    ) -> Result<(), BoxError> {
+        self.audit_admin(caller, "set_access_policy", json!(policy))
+            .await?;
+        self.ctx.base.access_policy.store(Arc::new(policy));
+        Ok(())
+    }
+
+    /// Records an admin action in the audit log if it is enabled.
+    async fn audit_admin(
+        &self,
+        caller: Principal,
+        action: &str,
+        detail: Value,
+    ) -> Result<(), BoxError> {
+        if let Some(audit) = &self.ctx.base.audit {
+            audit
+                .record(
+                    caller,
+                    &Path::from(SYSTEM_PATH),
+                    AuditAction::Admin,
+                    action.to_string(),
+                    Some(detail),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Shuts down the engine gracefully:
+    /// 1. stops accepting new agent runs and tool calls;
+    /// 2. lets in-flight runs finish up to `drain_timeout`;
+    /// 3. takes a snapshot if enabled with [`EngineBuilder::with_snapshots`], and saves the
+    ///    caches if persisted, see [`CacheConfig::persist`];
+    /// 4. cancels the remaining runs via the engine's [`CancellationToken`];
+    /// 5. flushes the usage metering, the user quotas and the audit log.
+    pub async fn shutdown(&self, drain_timeout: Duration) -> ShutdownReport {
+        let start = Instant::now();
+        self.runs.draining.store(true, Ordering::SeqCst);
+        let in_flight = self.in_flight();
+        let drained = tokio::time::timeout(drain_timeout, self.runs.wait_idle())
+            .await
+            .is_ok();
+        // taken before cancelling, so that the remaining runs are reported as interrupted
+        let snapshot = match self.snapshots {
+            true => self.snapshot().await.map(|_| ()),
+            false => Ok(()),
+        };
+        if let Err(err) = snapshot {
+            log::error!("engine {} failed to take snapshot: {}", self.name, err);
+        }
+        let saved = match self.cache.persist {
+            true => self.save_caches().await.map(|_| ()),
+            false => Ok(()),
+        };
+        if let Err(err) = saved {
+            log::error!("engine {} failed to save caches: {}", self.name, err);
+        }
+
+        let mut cancelled = 0;
+        if !drained {
+            cancelled = self.in_flight();
+            log::warn!(
+                "engine {} cancels {} runs after drain timeout",
+                self.name,
+                cancelled
+            );
+            self.cancel();
+            let _ = tokio::time::timeout(SHUTDOWN_CANCEL_GRACE, self.runs.wait_idle()).await;
+        }
+
+        let metered = match &self.ctx.base.metering {
+            Some(metering) => metering.flush(true).await.map(|_| ()),
+            None => Ok(()),
+        };
+        if let Err(err) = metered {
+            log::error!(
+                "engine {} failed to flush usage metering: {}",
+                self.name,
+                err
+            );
+        }
+        let flushed = match &self.ctx.base.quotas {
+            Some(quotas) => quotas.flush().await.map(|_| ()),
+            None => Ok(()),
+        };
+        if let Err(err) = flushed {
+            log::error!("engine {} failed to flush user quotas: {}", self.name, err);
+        }
+        let flushed = match &self.ctx.base.audit {
+            Some(audit) => audit.flush().await,
+            None => Ok(()),
+        };
+        if let Err(err) = flushed {
+            log::error!("engine {} failed to flush audit log: {}", self.name, err);
+        }
+        ShutdownReport {
+            in_flight,
+            cancelled,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Reloads the hot-reloadable sections of the config without restarting:
+    /// `canister_policy`, `http_policy`, `access_policy`, `remote_engines`, `export_agents`,
+    /// `export_tools` and `feature_flags`.
+    /// Absent sections are left unchanged, other sections require a restart and are ignored.
+    ///
+    /// All sections are validated (and remote engines fetched) before any is applied,
+    /// so a reload either applies entirely or not at all. Returns the applied sections.
+    pub async fn reload(&self, cfg: &EngineConfig) -> Result<Vec<String>, BoxError> {
+        let canister_policy = cfg.canister_policy()?;
+        let http_policy = cfg.http_policy()?;
+        let access_policy = cfg.access_policy()?;
+        let remote = match &cfg.remote_engines {
+            None => None,
+            Some(_) => {
+                let mut remote = RemoteEngines::new();
+                for (i, args) in cfg.remote_engines().into_iter().enumerate() {
+                    remote
+                        .register(self.ctx.base.web3.as_ref(), args)
+                        .await
+                        .map_err(|err| {
+                            format!("invalid config `remote_engines[{}]`: {}", i, err)
+                        })?;
+                }
+                Some(remote)
+            }
+        };
+        let export_agents = match &cfg.export_agents {
+            None => None,
+            Some(agents) => {
+                let mut export = BTreeSet::from([self.default_agent.clone()]);
+                for (i, agent) in agents.iter().enumerate() {
+                    let agent = agent.to_ascii_lowercase();
+                    if !self.ctx.agents.contains(&agent) {
+                        return Err(format!(
+                            "invalid config `export_agents[{}]`: agent {} not found",
+                            i, agent
+                        )
+                        .into());
+                    }
+                    export.insert(agent);
+                }
+                Some(export)
+            }
+        };
+        let export_tools = match &cfg.export_tools {
+            None => None,
+            Some(tools) => {
+                let mut export = BTreeSet::from([
+                    UserStateTool::NAME.to_string(),
+                    ThreadMetaTool::NAME.to_string(),
+                ]);
+                for (i, tool) in tools.iter().enumerate() {
+                    if !self.ctx.tools.contains(tool) {
+                        return Err(format!(
+                            "invalid config `export_tools[{}]`: tool {} not found",
+                            i, tool
+                        )
+                        .into());
+                    }
+                    export.insert(tool.clone());
+                }
+                Some(export)
+            }
+        };
+        let flags = cfg.feature_flags()?;
+
+        let mut applied = Vec::new();
+        if let Some(policy) = canister_policy {
+            self.ctx.base.canister_policy.store(Arc::new(policy));
+            applied.push("canister_policy".to_string());
+        }
+        if let Some(policy) = http_policy {
+            self.ctx.base.http_policy.store(Arc::new(policy));
+            applied.push("http_policy".to_string());
+        }
+        if let Some(policy) = access_policy {
+            self.ctx.base.access_policy.store(Arc::new(policy));
+            applied.push("access_policy".to_string());
+        }
+        if let Some(remote) = remote {
+            self.ctx.base.remote.store(Arc::new(remote));
+            applied.push("remote_engines".to_string());
+        }
+        if let Some(export) = export_agents {
+            self.export_agents.store(Arc::new(export));
+            applied.push("export_agents".to_string());
+        }
+        if let Some(export) = export_tools {
+            self.export_tools.store(Arc::new(export));
+            applied.push("export_tools".to_string());
+        }
+        if let Some(flags) = flags {
+            self.flags.store(Arc::new(flags));
+            applied.push("feature_flags".to_string());
+        }
+        Ok(applied)
+    }
+
+    /// Returns the audit log if it is enabled.
+    pub fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.ctx.base.audit.clone()
+    }
+
+    /// Returns the usage meter if it is enabled, see [`crate::metering`].
+    pub fn metering(&self) -> Option<Arc<Metering>> {
+        self.ctx.base.metering.clone()
+    }
+
+    /// Returns the usage of the day and of the month of the caller, or of its end user,
+    /// if the user quotas are enabled, see [`crate::quota`].
+    pub async fn user_usage(
+        &self,
+        caller: &Principal,
+        user: Option<String>,
+    ) -> Result<Option<UserUsage>, BoxError> {
+        let Some(quotas) = &self.ctx.base.quotas else {
+            return Ok(None);
+        };
+        let meta = RequestMeta {
+            user,
+            ..Default::default()
+        };
+        Ok(Some(quotas.usage(&quota_user(caller, &meta)).await?))
+    }
+
+    /// Lists the tenants of the engine with their usage of the day.
+    pub fn tenants(&self) -> Vec<TenantInfo> {
+        self.ctx.base.tenants.list(unix_ms())
+    }
+
+    /// Lists the background jobs of the engine, from the oldest.
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.ctx
+            .base
+            .jobs
+            .as_ref()
+            .map(|jobs| jobs.list(None))
+            .unwrap_or_default()
+    }
+
+    /// Cancels a background job on behalf of the caller, a manager.
+    /// Returns false if the job is not found or finished.
+    pub async fn cancel_job(&self, caller: Principal, id: &str) -> Result<bool, BoxError> {
+        let jobs = self
+            .ctx
+            .base
+            .jobs
+            .clone()
+            .ok_or("background jobs not enabled")?;
+        self.audit_admin(caller, "cancel_job", json!({"id": id}))
+            .await?;
+        jobs.cancel(id).await
+    }
+
+    /// Spawns a background job on behalf of a client, the caller.
+    /// Its agent or tool must be exported, as for a direct request.
+    pub async fn spawn_job(&self, caller: Principal, spec: JobSpec) -> Result<JobInfo, BoxError> {
+        if caller == Principal::anonymous() {
+            return Err("anonymous caller cannot spawn jobs".into());
+        }
+        let jobs = self
+            .ctx
+            .base
+            .jobs
+            .as_ref()
+            .ok_or("background jobs not enabled")?;
+        jobs.spawn(caller, "client".to_string(), spec).await
+    }
+
+    /// Gets a background job of the caller, or any job for a manager.
+    pub fn job(&self, caller: &Principal, id: &str) -> Option<JobInfo> {
+        self.ctx
+            .base
+            .jobs
+            .as_ref()?
+            .get(id)
+            .filter(|job| &job.caller == caller || self.is_manager(caller))
+    }
+
+    /// Registers the webhook notified of the caller's finished jobs, or removes it if None.
+    pub async fn set_webhook(
+        &self,
+        caller: Principal,
+        url: Option<String>,
+    ) -> Result<(), BoxError> {
+        if caller == Principal::anonymous() {
+            return Err("anonymous caller cannot register a webhook".into());
+        }
+        let webhooks = self
+            .webhooks
+            .as_ref()
+            .ok_or("background jobs not enabled")?;
+        webhooks.set(caller, url).await
+    }
+
+    /// Notifies the webhook of a job that succeeded or failed, in the background.
+    pub(crate) fn notify_job(&self, job: JobInfo) {
+        let url = match job
+            .webhook
+            .clone()
+            .or_else(|| self.webhooks.as_ref()?.get(&job.caller))
+        {
+            Some(url) => url,
+            None => return,
+        };
+        let event = match WebhookEvent::of(self.id, job) {
+            Some(event) => event,
+            None => return,
+        };
+        let ctx = self.ctx.base.clone();
+        tokio::spawn(async move {
+            if let Err(err) = webhook::deliver(&ctx, &url, &event).await {
+                log::warn!(
+                    job = event.job.id,
+                    url = url;
+                    "failed to deliver webhook: {}", err,
+                );
+            }
+        });
+    }
+
+    /// Verifies an API key of the engine, returns the pseudo-principal of its callers.
+    /// Returns None if the key is invalid or revoked, or API keys are not enabled.
+    pub fn verify_api_key(&self, key: &str) -> Option<Principal> {
+        self.api_keys.as_ref()?.verify(key)
+    }
+
+    /// Lists the API keys of the engine, including the revoked ones.
+    pub fn api_keys(&self) -> Vec<ApiKeyInfo> {
+        self.api_keys
+            .as_ref()
+            .map(|keys| keys.list())
+            .unwrap_or_default()
+    }
+
+    /// Issues an API key on behalf of the caller, a manager.
+    /// The key is only returned here, the engine keeps its hash.
+    pub async fn issue_api_key(
+        &self,
+        caller: Principal,
+        name: String,
+    ) -> Result<(String, ApiKeyInfo), BoxError> {
+        let keys = self.api_keys.as_ref().ok_or("API keys not enabled")?;
+        let (key, info) = keys.issue(name, caller).await?;
+        let audited = self
+            .audit_admin(
+                caller,
+                "issue_api_key",
+                json!({"id": info.id, "name": info.name, "principal": info.principal.to_text()}),
+            )
+            .await;
+        if let Err(err) = audited {
+            // the key is not handed out, so it must not stay valid
+            keys.revoke(&info.id).await?;
+            return Err(err);
+        }
+        Ok((key, info))
+    }
+
+    /// Revokes an API key on behalf of the caller, a manager.
+    /// Returns false if the key is not found or already revoked.
+    pub async fn revoke_api_key(&self, caller: Principal, id: &str) -> Result<bool, BoxError> {
+        let keys = self.api_keys.as_ref().ok_or("API keys not enabled")?;
+        self.audit_admin(caller, "revoke_api_key", json!({"id": id}))
+            .await?;
+        keys.revoke(id).await
+    }
+
+    /// Returns the knowledge collections if they are enabled, see [`crate::knowledge`].
+    pub fn knowledge(&self) -> Option<Arc<KnowledgeBase>> {
+        self.ctx.base.knowledge.clone()
+    }
+
+    /// Lists the knowledge collections, of a scope or all of them.
+    pub fn knowledge_collections(&self, scope: Option<&KnowledgeScope>) -> Vec<CollectionInfo> {
+        self.ctx
+            .base
+            .knowledge
+            .as_ref()
+            .map(|kb| kb.list(scope))
+            .unwrap_or_default()
+    }
+
+    /// Lists the documents of a knowledge collection, None if it is not found.
+    pub fn knowledge_documents(
+        &self,
+        scope: &KnowledgeScope,
+        name: &str,
+    ) -> Option<Vec<KnowledgeDocumentInfo>> {
+        self.ctx.base.knowledge.as_ref()?.documents(scope, name)
+    }
+
+    /// Creates a knowledge collection on behalf of the caller, a manager.
+    pub async fn create_knowledge_collection(
+        &self,
+        caller: Principal,
+        scope: KnowledgeScope,
+        name: String,
+        description: String,
+    ) -> Result<CollectionInfo, BoxError> {
+        let kb = self.knowledge_base(&scope)?;
+        self.audit_admin(
+            caller,
+            "create_knowledge_collection",
+            json!({"scope": scope.to_string(), "name": name}),
+        )
+        .await?;
+        kb.create(scope, name, description, caller).await
+    }
+
+    /// Deletes a knowledge collection with its documents on behalf of the caller, a manager.
+    /// Returns false if the collection is not found.
+    pub async fn delete_knowledge_collection(
+        &self,
+        caller: Principal,
+        scope: &KnowledgeScope,
+        name: &str,
+    ) -> Result<bool, BoxError> {
+        let kb = self.knowledge_base(scope)?;
+        self.audit_admin(
+            caller,
+            "delete_knowledge_collection",
+            json!({"scope": scope.to_string(), "name": name}),
+        )
+        .await?;
+        kb.delete(scope, name).await
+    }
+
+    /// Adds documents to a knowledge collection on behalf of the caller, a manager,
+    /// or replaces the documents with the same IDs. The documents are embedded
+    /// with the model the agents use for the callers of the scope.
+    pub async fn add_knowledge_documents(
+        &self,
+        caller: Principal,
+        scope: &KnowledgeScope,
+        name: &str,
+        docs: Vec<IngestDocument>,
+    ) -> Result<IngestReport, BoxError> {
+        let kb = self.knowledge_base(scope)?;
+        self.audit_admin(
+            caller,
+            "add_knowledge_documents",
+            json!({
+                "scope": scope.to_string(),
+                "name": name,
+                "ids": docs.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(),
+            }),
+        )
+        .await?;
+        let tenant = match scope {
+            KnowledgeScope::Engine => None,
+            KnowledgeScope::Tenant(id) => self.ctx.base.tenants.get(id),
+            KnowledgeScope::User(user) => self.ctx.base.tenants.of(user),
+        };
+        let mut ctx = self.ctx.clone();
+        if let Some(model) = tenant.and_then(|t| t.model.clone()) {
+            ctx.model = model;
+        }
+        kb.add_documents(&ctx, scope, name, docs).await
+    }
+
+    /// Removes documents from a knowledge collection on behalf of the caller, a manager.
+    /// Returns the number of removed documents.
+    pub async fn remove_knowledge_documents(
+        &self,
+        caller: Principal,
+        scope: &KnowledgeScope,
+        name: &str,
+        ids: Vec<String>,
+    ) -> Result<usize, BoxError> {
+        let kb = self.knowledge_base(scope)?;
+        self.audit_admin(
+            caller,
+            "remove_knowledge_documents",
+            json!({"scope": scope.to_string(), "name": name, "ids": ids}),
+        )
+        .await?;
+        kb.remove_documents(scope, name, &ids).await
+    }
+
+    /// Returns the knowledge base, checking that the tenant of the scope exists.
+    fn knowledge_base(&self, scope: &KnowledgeScope) -> Result<Arc<KnowledgeBase>, BoxError> {
+        let kb = self
+            .ctx
+            .base
+            .knowledge
+            .clone()
+            .ok_or("knowledge collections not enabled")?;
+        match scope {
+            KnowledgeScope::Tenant(id) if self.ctx.base.tenants.get(id).is_none() => {
+                Err(Error::NotFound(format!("tenant {}", id)).into())
+            }
+            _ => Ok(kb),
+        }
+    }
+
+    /// Returns information about the engine, including agent and tool definitions.
+    pub fn information(&self) -> Information {
+        Information {
+            id: self.id,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            endpoint: "".to_string(),
+            agents: self.agents(Some(
+                self.export_agents
+                    .load()
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )),
+            tools: self.tools(Some(
+                self.export_tools
+                    .load()
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )),
+        }
+    }
+
+    /// Returns the descriptor of the engine for discovery, without endpoints,
+    /// they are set by the server serving the engine.
+    pub fn agent_card(&self) -> AgentCard {
+        let info = self.information();
+        AgentCard {
+            id: self.id,
+            name: info.name,
+            description: info.description,
+            default_agent: self.default_agent.clone(),
+            agents: info
+                .agents
+                .into_iter()
+                .map(|function| AgentSkill {
+                    price: self
+                        .payments
+                        .agent_price(&function.definition.name)
+                        .cloned(),
+                    function,
+                })
+                .collect(),
+            tools: info
+                .tools
+                .into_iter()
+                .map(|function| AgentSkill {
+                    price: self.payments.tool_price(&function.definition.name).cloned(),
+                    function,
+                })
+                .collect(),
+            capabilities: AgentCapabilities {
+                streaming: true,
+                jobs: self.ctx.base.jobs.is_some(),
+                webhooks: self.webhooks.is_some(),
+                payments: !self.payments.is_empty(),
+                api_keys: self.api_keys.is_some(),
+            },
+            endpoints: BTreeMap::new(),
+            attestation: None,
+        }
+    }
+}
+
+/// Time left to cancelled runs to unwind after the drain timeout.
+const SHUTDOWN_CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// Tracks in-flight runs, so that they can be listed, cancelled and drained on shutdown,
+/// and accumulates the usage statistics of the engine.
+#[derive(Default)]
+struct RunTracker {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    next_id: AtomicU64,
+    active: RwLock<BTreeMap<u64, (RunInfo, CancellationToken)>>,
+    stats: RwLock<EngineStats>,
+}
+
+struct RunGuard {
+    runs: Arc<RunTracker>,
+    id: u64,
+    token: CancellationToken,
+    succeeded: bool,
+}
+
+impl RunGuard {
+    /// Marks the run as succeeded and accumulates its usage.
+    fn finish(&mut self, usage: &Usage) {
+        self.succeeded = true;
+        self.runs
+            .stats
+            .write()
+            .expect("stats lock poisoned")
+            .usage
+            .accumulate(usage);
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.runs
+            .active
+            .write()
+            .expect("runs lock poisoned")
+            .remove(&self.id);
+        if !self.succeeded {
+            self.runs.stats.write().expect("stats lock poisoned").failed += 1;
+        }
+        if self.runs.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.runs.idle.notify_waiters();
+        }
+    }
+}
+
+impl RunTracker {
+    /// Registers a run, `kind` is "agent" or "tool".
+    fn enter(
+        self: &Arc<Self>,
+        kind: &str,
+        name: &str,
+        caller: Principal,
+        token: CancellationToken,
+    ) -> Result<RunGuard, BoxError> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let guard = RunGuard {
+            runs: self.clone(),
+            id,
+            token: token.clone(),
+            succeeded: false,
+        };
+        if self.draining.load(Ordering::SeqCst) {
+            return Err("engine is shutting down".into());
+        }
+
+        {
+            let mut stats = self.stats.write().expect("stats lock poisoned");
+            if kind == "agent" {
+                stats.agent_runs += 1;
+            } else {
+                stats.tool_calls += 1;
+            }
+        }
+        let info = RunInfo {
+            id,
+            kind: kind.to_string(),
+            name: name.to_string(),
+            caller,
+            started_at_ms: unix_ms(),
+        };
+        self.active
+            .write()
+            .expect("runs lock poisoned")
+            .insert(id, (info, token));
+        Ok(guard)
+    }
+
+    /// Adds the statistics restored from a snapshot.
+    fn restore_stats(&self, restored: &EngineStats) {
+        let mut stats = self.stats.write().expect("stats lock poisoned");
+        stats.agent_runs += restored.agent_runs;
+        stats.tool_calls += restored.tool_calls;
+        stats.failed += restored.failed;
+        stats.usage.accumulate(&restored.usage);
+    }
+
+    fn active_runs(&self) -> Vec<RunInfo> {
+        self.active
+            .read()
+            .expect("runs lock poisoned")
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect()
+    }
+
+    fn cancel(&self, id: u64) -> bool {
+        match self.active.read().expect("runs lock poisoned").get(&id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// Timeout of each readiness check.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn health_check(fut: impl Future<Output = Result<(), BoxError>>) -> HealthCheck {
+    let start = Instant::now();
+    let res = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, fut).await {
+        Ok(res) => res,
+        Err(_) => Err("timed out".into()),
+    };
+    HealthCheck {
+        ok: res.is_ok(),
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        error: res.err().map(|err| err.to_string()),
+    }
+}
+
+/// Checks that a remote engine is reachable and still serves the engine ID it was registered with.
+async fn ping_remote_engine(ctx: &BaseCtx, info: &Information) -> Result<(), BoxError> {
+    let remote: Information = ctx
+        .https_signed_rpc(&info.endpoint, "information", &(true,))
+        .await?;
+    if remote.id != info.id {
+        return Err(format!(
+            "engine ID changed, expected {}, got {}",
+            info.id.to_text(),
+            remote.id.to_text()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks the remote engines at each interval until the engine is cancelled.
+async fn run_remote_health_checks(engine: Engine, cfg: RemoteHealthConfig) {
+    let token = engine.cancellation_token();
+    loop {
+        engine.check_remote_engines(cfg.timeout()).await;
+        tokio::select! {
+            _ = token.cancelled() => return,
+            _ = tokio::time::sleep(cfg.interval()) => {},
+        }
+    }
+}
+
+/// Refreshes the access policy from the policy canister at each interval until the engine
+/// is cancelled, the last policy is kept if a refresh fails.
+async fn run_access_policy_refresh(engine: Engine, interval: Duration) {
+    let token = engine.cancellation_token();
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {},
+        }
+        if let Err(err) = engine.refresh_access_policy().await {
+            log::error!("failed to refresh the access policy: {}", err);
+        }
+    }
+}
+
+/// Builder pattern implementation for constructing an Engine.
+/// Allows for step-by-step configuration of the engine's components.
+pub struct EngineBuilder {
+    id: Principal,
+    name: String,
+    description: String,
+    tools: ToolSet<BaseCtx>,
+    agents: AgentSet<AgentCtx>,
+    remote: BTreeMap<String, RemoteEngineArgs>,
+    model: Model,
+    batch_model: Option<Model>,
+    models: BTreeMap<String, Model>,
+    pricing: Pricing,
+    store: Store,
+    vectors: VectorIndex,
+    web3: Arc<Web3SDK>,
+    hooks: Arc<Hooks>,
+    cancellation_token: CancellationToken,
+    export_agents: BTreeSet<String>,
+    export_tools: BTreeSet<String>,
+    management: ManagementBuilder,
+    canister_policy: CanisterPolicy,
+    access_policy: AccessPolicy,
+    policy_canister: Option<PolicyCanisterConfig>,
+    http_policy: HttpPolicy,
+    audit: Option<AuditConfig>,
+    api_keys: bool,
+    knowledge: Option<Ingestor>,
+    tenants: Tenants,
+    snapshots: bool,
+    jobs: Option<JobsConfig>,
+    metering: Option<MeteringConfig>,
+    quotas: Option<QuotaConfig>,
+    memory: Option<MemoryConfig>,
+    history: Option<HistoryConfig>,
+    registry: Option<RegistryConfig>,
+    remote_health: Option<RemoteHealthConfig>,
+    billing_hooks: Vec<Arc<dyn BillingHook>>,
+    payments: PaymentPolicy,
+    attachments: Option<AttachmentScanning>,
+    rate_limit: Option<RateLimitConfig>,
+    clock: Arc<dyn Clock>,
+    random: Arc<dyn RandomSource>,
+    flags: BTreeMap<String, FeatureFlag>,
+    tool_timeout: Option<Duration>,
+    rpc_retry: Option<RpcRetryPolicy>,
+    http_retry: Option<HttpRetryPolicy>,
+    tool_middlewares: Vec<Arc<dyn ToolMiddleware>>,
+    agent_hooks: Vec<Arc<dyn AgentHook>>,
+    tool_capabilities: BTreeMap<String, Capabilities>,
+    cache: CacheConfig,
+    store_encryption: StoreEncryptionConfig,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineBuilder {
+    /// Creates a new EngineBuilder with default values.
+    pub fn new() -> Self {
+        let mstore = Arc::new(InMemory::new());
+        EngineBuilder {
+            id: Principal::anonymous(),
+            name: "Anda".to_string(),
+            description: "".to_string(),
+            tools: ToolSet::new(),
+            agents: AgentSet::new(),
+            remote: BTreeMap::new(),
+            model: Model::not_implemented(),
+            batch_model: None,
+            models: BTreeMap::new(),
+            pricing: Pricing::default(),
+            store: Store::new(mstore),
+            vectors: VectorIndex::in_memory(),
+            web3: Arc::new(Web3SDK::Web3(Web3Client::not_implemented())),
+            hooks: Arc::new(Hooks { hooks: Vec::new() }),
+            cancellation_token: CancellationToken::new(),
+            export_agents: BTreeSet::new(),
+            export_tools: BTreeSet::new(),
+            management: ManagementBuilder::new(Visibility::Private, Principal::anonymous()),
+            canister_policy: CanisterPolicy::default(),
+            access_policy: AccessPolicy::default(),
+            policy_canister: None,
+            http_policy: HttpPolicy::default(),
+            audit: None,
+            api_keys: false,
+            knowledge: None,
+            tenants: Tenants::new(),
+            snapshots: false,
+            jobs: None,
+            metering: None,
+            quotas: None,
+            memory: None,
+            history: None,
+            registry: None,
+            remote_health: None,
+            billing_hooks: Vec::new(),
+            payments: PaymentPolicy::default(),
+            attachments: None,
+            rate_limit: None,
+            clock: Arc::new(SystemClock),
+            random: Arc::new(SystemRandom),
+            flags: BTreeMap::new(),
+            tool_timeout: None,
+            rpc_retry: None,
+            http_retry: None,
+            tool_middlewares: Vec::new(),
+            agent_hooks: Vec::new(),
+            tool_capabilities: BTreeMap::new(),
+            cache: CacheConfig::default(),
+            store_encryption: StoreEncryptionConfig::default(),
+        }
+    }
+
+    /// Sets the engine ID, which comes from the TEE.
+    pub fn with_id(mut self, id: Principal) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the engine name.
+    pub fn with_name(mut self, name: String) -> Result<Self, BoxError> {
+        validate_function_name(&name.to_ascii_lowercase())?;
+        self.name = name;
+        Ok(self)
+    }
+
+    pub fn with_description(mut self, description: String) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Sets the cancellation token.
+    pub fn with_cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Sets the TEE (Trusted Execution Environment) client.
+    pub fn with_web3_client(mut self, web3: Arc<Web3SDK>) -> Self {
+        self.web3 = web3;
+        self
+    }
+
+    /// Sets the model to be used by the engine.
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Sets an ordered list of models: the first one is used, the next ones are its fallbacks,
+    /// tried in order when a completion is rate limited, fails with a 5xx response or times out.
+    pub fn with_models(mut self, models: impl IntoIterator<Item = Model>) -> Self {
+        let mut models = models.into_iter();
+        if let Some(model) = models.next() {
+            self.model = model.with_fallbacks(models.collect());
+        }
+        self
+    }
+
+    /// Registers a model by name, that completion requests select with
+    /// [`CompletionRequest::model`](anda_core::CompletionRequest::model).
+    /// Returns an error if the model already exists.
+    pub fn register_model(mut self, name: &str, model: Model) -> Result<Self, BoxError> {
+        if self.models.contains_key(name) {
+            return Err(format!("model {} already exists", name).into());
+        }
+        self.models.insert(name.to_string(), model);
+        Ok(self)
+    }
+
+    /// Sets the prices of the models, to estimate the cost of the completions in their
+    /// [`Usage`], accumulated per agent run in its output and metered per caller.
+    pub fn with_pricing(mut self, pricing: Pricing) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// Sets the model used for the completions of batch requests, such as background jobs,
+    /// e.g. a cheaper model or another provider, so that they don't compete with
+    /// interactive requests for the rate limits of the main model.
+    pub fn with_batch_model(mut self, model: Model) -> Self {
+        self.batch_model = Some(model);
+        self
+    }
+
+    /// Sets the storage backend for the engine, in memory by default. Any [`ObjectStore`]
+    /// can back it, e.g. S3 or the local file system, see [`crate::store::StoreConfig`].
+    ///
+    /// [`ObjectStore`]: crate::store::ObjectStore
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Sets the encryption at rest of the store, enabled by default if the engine runs in a
+    /// TEE, see [`crate::encryption`]. The keys are derived from the keys of the engine.
+    pub fn with_store_encryption(mut self, cfg: StoreEncryptionConfig) -> Self {
+        self.store_encryption = cfg;
+        self
+    }
+
+    /// Sets the vector store for the engine, an in-memory HNSW store by default,
+    /// e.g. a [`crate::vector::QdrantVectorStore`] for production-scale retrieval.
+    pub fn with_vector_store(mut self, vectors: VectorIndex) -> Self {
+        self.vectors = vectors;
+        self
+    }
+
+    /// Sets the management builder for the engine.
+    pub fn with_management(mut self, management: ManagementBuilder) -> Self {
+        self.management = management;
+        self
+    }
+
+    /// Discovers the remote engines published to a registry canister, and publishes the engine
+    /// to it if the config has a public endpoint, see [`crate::registry`].
+    pub fn with_registry(mut self, cfg: RegistryConfig) -> Self {
+        self.registry = Some(cfg);
+        self
+    }
+
+    /// Checks the remote engines periodically, so that the calls to the unhealthy ones
+    /// fail fast instead of timing out, see [`Engine::check_remote_engines`].
+    pub fn with_remote_health_checks(mut self, cfg: RemoteHealthConfig) -> Self {
+        self.remote_health = Some(cfg);
+        self
+    }
+
+    /// Sets the policy restricting which canisters and methods `canister_update` may target.
+    pub fn with_canister_policy(mut self, policy: CanisterPolicy) -> Self {
+        self.canister_policy = policy;
+        self
+    }
+
+    /// Sets the outbound domain policy for HTTP requests of the engine and its tools.
+    pub fn with_http_policy(mut self, policy: HttpPolicy) -> Self {
+        self.http_policy = policy;
+        self
+    }
+
+    /// Sets the policy restricting which tools and agents each caller may invoke.
+    pub fn with_access_policy(mut self, policy: AccessPolicy) -> Self {
+        self.access_policy = policy;
+        self
+    }
+
+    /// Fetches the access policy from a policy canister instead, when the engine is built and
+    /// periodically, see [`PolicyCanisterConfig`].
+    pub fn with_policy_canister(mut self, cfg: PolicyCanisterConfig) -> Self {
+        self.policy_canister = Some(cfg);
+        self
+    }
+
+    /// Registers a single tool with the engine.
+    /// Returns an error if the tool cannot be added.
+    pub fn register_tool<T>(mut self, tool: T) -> Result<Self, BoxError>
+    where
+        T: Tool<BaseCtx> + Send + Sync + 'static,
+    {
+        self.tools.add(tool)?;
+        Ok(self)
+    }
+
+    /// Registers multiple tools with the engine.
+    /// Returns an error if any tool already exists.
+    pub fn register_tools(mut self, tools: ToolSet<BaseCtx>) -> Result<Self, BoxError> {
+        for (name, tool) in tools.set {
+            if self.tools.set.contains_key(&name) {
+                return Err(format!("tool {} already exists", name).into());
+            }
+            self.tools.set.insert(name, tool);
+        }
+
+        Ok(self)
+    }
+
+    /// Registers a single agent with the engine.
+    /// Verifies that all required tools are registered before adding the agent.
+    /// Returns an error if any dependency is missing or if the agent cannot be added.
+    pub fn register_agent<T>(mut self, agent: T) -> Result<Self, BoxError>
+    where
+        T: Agent<AgentCtx> + Send + Sync + 'static,
+    {
+        for tool in agent.tool_dependencies() {
+            if !self.tools.contains(&tool) {
+                return Err(format!("dependent tool {} not found", tool).into());
+            }
+        }
+
+        self.agents.add(agent)?;
+        Ok(self)
+    }
+
+    /// Registers multiple agents with the engine.
+    /// Verifies that all required tools are registered for each agent.
+    /// Returns an error if any agent already exists or if any dependency is missing.
+    pub fn register_agents(mut self, agents: AgentSet<AgentCtx>) -> Result<Self, BoxError> {
+        for (name, agent) in agents.set {
+            if self.agents.set.contains_key(&name) {
+                return Err(format!("agent {} already exists", name).into());
+            }
+
+            for tool in agent.tool_dependencies() {
+                if !self.tools.contains(&tool) {
+                    return Err(format!("dependent tool {} not found", tool).into());
+                }
+            }
+            self.agents.set.insert(name, agent);
+        }
+
+        Ok(self)
+    }
+
+    /// Registers a remote engine with given endpoint, optional agents, tools, and alias name.
+    pub fn register_remote_engine(mut self, engine: RemoteEngineArgs) -> Result<Self, BoxError> {
+        if self.remote.contains_key(&engine.endpoint) {
+            return Err(format!("remote engine {} already exists", engine.endpoint).into());
         }
         if let Some(name) = &engine.name {
             validate_function_name(name).map_err(|err| format!("invalid name: {}", err))?;
@@ -585,6 +2285,322 @@ impl EngineBuilder {
         self
     }
 
+    /// Adds a middleware around the tool calls, see [`ToolMiddleware`].
+    /// Middlewares run in the order they are added before the calls, in reverse order after.
+    pub fn with_tool_middleware(mut self, middleware: Arc<dyn ToolMiddleware>) -> Self {
+        self.tool_middlewares.push(middleware);
+        self
+    }
+
+    /// Adds a hook around the runs of the local agents, see [`AgentHook`].
+    /// Hooks run in the order they are added before the runs, in reverse order after.
+    pub fn with_agent_hook(mut self, hook: Arc<dyn AgentHook>) -> Self {
+        self.agent_hooks.push(hook);
+        self
+    }
+
+    /// Enables the hash-chained audit log, persisted to the engine's store.
+    /// It may record every tool call and agent run, and sign its entries, see [`AuditConfig`].
+    pub fn with_audit_log(mut self, cfg: AuditConfig) -> Self {
+        self.audit = Some(cfg);
+        self
+    }
+
+    /// Enables API keys for callers that can't sign requests, persisted to the engine's store.
+    pub fn with_api_keys(mut self) -> Self {
+        self.api_keys = true;
+        self
+    }
+
+    /// Enables the background jobs of agents and tools, persisted to the engine's store.
+    /// Enables the knowledge collections managed at runtime, see [`crate::knowledge`].
+    /// Their documents are chunked by the ingestor.
+    pub fn with_knowledge(mut self, ingestor: Ingestor) -> Self {
+        self.knowledge = Some(ingestor);
+        self
+    }
+
+    pub fn with_jobs(mut self, cfg: JobsConfig) -> Self {
+        self.jobs = Some(cfg);
+        self
+    }
+
+    /// Enables the usage metering of the callers, with billing records persisted to the
+    /// engine's store, see [`crate::metering`].
+    pub fn with_metering(mut self, cfg: MeteringConfig) -> Self {
+        self.metering = Some(cfg);
+        self
+    }
+
+    /// Enables the daily and monthly usage quotas of the users, with their usage persisted to
+    /// the engine's store, see [`crate::quota`].
+    pub fn with_user_quotas(mut self, cfg: QuotaConfig) -> Self {
+        self.quotas = Some(cfg);
+        self
+    }
+
+    /// Enables the conversation memory of the agents, with the chat history of the threads
+    /// persisted to the engine's store, see [`crate::memory`].
+    pub fn with_memory(mut self, cfg: MemoryConfig) -> Self {
+        self.memory = Some(cfg);
+        self
+    }
+
+    /// Enables the compaction of the long chat histories, whose older messages are summarized
+    /// by the model, see [`crate::history`].
+    pub fn with_history_compaction(mut self, cfg: HistoryConfig) -> Self {
+        self.history = Some(cfg);
+        self
+    }
+
+    /// Sets the prices of agents and tools, paid by the callers with ICRC-2 approvals,
+    /// see [`crate::payment`].
+    pub fn with_payment_policy(mut self, policy: PaymentPolicy) -> Self {
+        self.payments = policy;
+        self
+    }
+
+    /// Checks the attachments of agent runs before the agents get them,
+    /// see [`crate::attachment`].
+    pub fn with_attachment_scanning(mut self, scanning: AttachmentScanning) -> Self {
+        self.attachments = Some(scanning);
+        self
+    }
+
+    /// Limits the rate of the agent runs and tool calls of each caller, see
+    /// [`crate::rate_limit`].
+    pub fn with_rate_limit(mut self, cfg: RateLimitConfig) -> Self {
+        self.rate_limit = Some(cfg);
+        self
+    }
+
+    /// Sets the clock of the contexts, e.g. a [`anda_core::MockClock`] in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the random source of the contexts, e.g. a [`anda_core::SeededRandom`] in tests.
+    pub fn with_random(mut self, random: Arc<dyn RandomSource>) -> Self {
+        self.random = random;
+        self
+    }
+
+    /// Sets the timeout of each tool call, unless the tool has its own [`Tool::timeout`].
+    /// A timed out call in a completion returns its error to the model instead of failing the run.
+    pub fn with_tool_timeout(mut self, timeout: Duration) -> Self {
+        self.tool_timeout = Some(timeout);
+        self
+    }
+
+    /// Restricts the capabilities of the calls of the tool, e.g. an untrusted tool may get
+    /// [`Capabilities::read_only`]. The contexts of its calls, and their children, are denied
+    /// the operations it lacks.
+    pub fn with_tool_capabilities(mut self, tool: &str, caps: Capabilities) -> Self {
+        self.tool_capabilities.insert(tool.to_string(), caps);
+        self
+    }
+
+    /// Sets the capacity, eviction policy and background sweeping of the caches of the agents
+    /// and tools, see [`CacheConfig`].
+    pub fn with_cache(mut self, cfg: CacheConfig) -> Self {
+        self.cache = cfg;
+        self
+    }
+
+    /// Retries the transient failures of the signed RPC calls to remote engines, see
+    /// [`RpcRetryPolicy`].
+    pub fn with_rpc_retry(mut self, policy: RpcRetryPolicy) -> Self {
+        self.rpc_retry = Some(policy);
+        self
+    }
+
+    /// Retries the transient failures of the HTTPs calls made without per-request options,
+    /// see [`HttpRetryPolicy`]. The calls with options use their own retry policy.
+    pub fn with_http_retry(mut self, policy: HttpRetryPolicy) -> Self {
+        self.http_retry = Some(policy);
+        self
+    }
+
+    /// Adds a feature flag, see [`crate::flags`].
+    pub fn with_feature_flag(mut self, name: String, flag: FeatureFlag) -> Result<Self, BoxError> {
+        validate_feature_flag(&name, &flag)?;
+        self.flags.insert(name, flag);
+        Ok(self)
+    }
+
+    /// Adds a hook called with the billing records as they are flushed.
+    pub fn with_billing_hook(mut self, hook: Arc<dyn BillingHook>) -> Self {
+        self.billing_hooks.push(hook);
+        self
+    }
+
+    /// Restores the runtime state from the latest snapshot in the store when the engine
+    /// is built, and takes a snapshot on shutdown, see [`crate::snapshot`].
+    pub fn with_snapshots(mut self) -> Self {
+        self.snapshots = true;
+        self
+    }
+
+    /// Adds a tenant with isolated store, cache, keys and quotas, and optionally its own model.
+    pub fn with_tenant(mut self, tenant: Tenant) -> Result<Self, BoxError> {
+        self.tenants.add(tenant)?;
+        Ok(self)
+    }
+
+    /// Applies an [`EngineConfig`] loaded from a TOML or YAML file.
+    /// It should be called after the tools are registered, so that the `tools` section
+    /// can be checked against them. Values absent from the config are left unchanged.
+    pub fn with_config(mut self, cfg: EngineConfig) -> Result<Self, BoxError> {
+        if let Some(id) = cfg.id()? {
+            self.id = id;
+        }
+        if let Some(name) = &cfg.name {
+            self = self
+                .with_name(name.clone())
+                .map_err(|err| format!("invalid config `name`: {}", err))?;
+        }
+        if let Some(description) = &cfg.description {
+            self.description = description.clone();
+        }
+        if let Some(model) = cfg.model()? {
+            self.model = model;
+        }
+        if let Some(model) = cfg.batch_model(&self.model)? {
+            self.batch_model = Some(model);
+        }
+        self.models.extend(cfg.models(&self.model)?);
+        if let Some(pricing) = &cfg.pricing {
+            self.pricing = pricing.clone();
+        }
+        for tenant in cfg.tenants(&self.model)? {
+            self = self
+                .with_tenant(tenant)
+                .map_err(|err| format!("invalid config `tenants`: {}", err))?;
+        }
+        if let Some(visibility) = cfg.visibility()? {
+            self.management.visibility = visibility;
+        }
+        if let Some(controller) = cfg.controller()? {
+            self.management.controller = controller;
+        }
+        if !cfg.managers.is_empty() {
+            self.management.managers = cfg.managers()?;
+        }
+
+        if let Some(tools) = &cfg.tools {
+            for (i, name) in tools.enabled.iter().flatten().enumerate() {
+                if !self.tools.contains(name) {
+                    return Err(format!(
+                        "invalid config `tools.enabled[{}]`: tool {} not found",
+                        i, name
+                    )
+                    .into());
+                }
+            }
+            for (name, agent) in &self.agents.set {
+                for tool in agent.tool_dependencies() {
+                    if !cfg.is_tool_enabled(&tool) {
+                        return Err(format!(
+                            "invalid config `tools`: tool {} is required by agent {}",
+                            tool, name
+                        )
+                        .into());
+                    }
+                }
+            }
+            self.tools.set.retain(|name, _| cfg.is_tool_enabled(name));
+            if let Some(timeout_ms) = tools.timeout_ms {
+                self.tool_timeout = Some(Duration::from_millis(timeout_ms));
+            }
+            for (tool, caps) in &tools.capabilities {
+                self.tool_capabilities.insert(tool.clone(), *caps);
+            }
+        }
+
+        for (i, remote) in cfg.remote_engines().into_iter().enumerate() {
+            self = self
+                .register_remote_engine(remote)
+                .map_err(|err| format!("invalid config `remote_engines[{}]`: {}", i, err))?;
+        }
+        if let Some(registry) = &cfg.registry {
+            self.registry = Some(registry.clone());
+        }
+        if let Some(remote_health) = &cfg.remote_health {
+            self.remote_health = Some(remote_health.clone());
+        }
+        if let Some(rpc_retry) = &cfg.rpc_retry {
+            self.rpc_retry = Some(rpc_retry.clone());
+        }
+        if let Some(cache) = &cfg.cache {
+            self.cache = cache.clone();
+        }
+        self = self
+            .export_agents(cfg.export_agents.clone().unwrap_or_default())
+            .export_tools(cfg.export_tools.clone().unwrap_or_default());
+        if let Some(policy) = cfg.canister_policy()? {
+            self.canister_policy = policy;
+        }
+        if let Some(policy) = cfg.http_policy()? {
+            self.http_policy = policy;
+        }
+        if let Some(policy) = cfg.access_policy()? {
+            self.access_policy = policy;
+        }
+        if let Some(policy) = cfg.policy_canister()? {
+            self.policy_canister = Some(policy);
+        }
+        if let Some(audit) = cfg.audit() {
+            self.audit = Some(audit);
+        }
+        if cfg.otlp.is_some() && !tracing::dispatcher::has_been_set() {
+            // the application installs the subscriber, the builder doesn't
+            log::warn!(
+                "config `otlp` is ignored without a tracing subscriber, the application installs it with `telemetry::init_otlp_tracing`"
+            );
+        }
+        if cfg.api_keys {
+            self.api_keys = true;
+        }
+        if let Some(jobs) = &cfg.jobs {
+            self.jobs = Some(jobs.clone());
+        }
+        if let Some(metering) = &cfg.metering {
+            self.metering = Some(metering.clone());
+        }
+        if let Some(quotas) = &cfg.quotas {
+            self.quotas = Some(quotas.clone());
+        }
+        if let Some(memory) = cfg.memory {
+            self.memory = Some(memory);
+        }
+        if let Some(history) = cfg.history {
+            self.history = Some(history);
+        }
+        if let Some(payments) = &cfg.payments {
+            self.payments = payments.clone();
+        }
+        if let Some(rate_limit) = &cfg.rate_limit {
+            self.rate_limit = Some(rate_limit.clone());
+        }
+        if let Some(store) = cfg.store()? {
+            self.store = store;
+        }
+        if let Some(encryption) = &cfg.store_encryption {
+            self.store_encryption = encryption.clone();
+        }
+        if let Some(vectors) = cfg.vector_store()? {
+            self.vectors = vectors;
+        }
+        if let Some(knowledge) = cfg.knowledge() {
+            self.knowledge = Some(knowledge);
+        }
+        if let Some(flags) = cfg.feature_flags()? {
+            self.flags = flags;
+        }
+        Ok(self)
+    }
+
     /// Finalizes the builder and creates an Engine instance.
     /// Requires a default agent name to be specified.
     /// Returns an error if the default agent is not found.
@@ -596,6 +2612,16 @@ impl EngineBuilder {
 
         self.export_agents.insert(default_agent.clone());
 
+        let encrypted = self
+            .store_encryption
+            .enabled
+            .unwrap_or(matches!(self.web3.as_ref(), Web3SDK::Tee(_)));
+        if encrypted && !self.store.is_encrypted() {
+            let cipher = StoreCipher::new(Arc::new(Web3StoreKeys::new(self.web3.clone())))
+                .with_plaintext(self.store_encryption.allow_plaintext);
+            self.store = self.store.with_cipher(cipher);
+        }
+
         let mut names: BTreeSet<Path> = self
             .tools
             .set
@@ -615,19 +2641,93 @@ impl EngineBuilder {
             remote.register(self.web3.as_ref(), engine).await?;
         }
 
-        let ctx = BaseCtx::new(
+        let audit = match self.audit {
+            Some(cfg) => {
+                let sign = cfg.sign;
+                let mut log = AuditLog::open(self.store.clone(), cfg).await?;
+                if sign {
+                    log = log.with_signer(Arc::new(Web3AuditSigner::new(self.web3.clone())));
+                }
+                Some(Arc::new(log))
+            }
+            None => None,
+        };
+        let api_keys = if self.api_keys {
+            Some(Arc::new(ApiKeys::open(self.store.clone()).await?))
+        } else {
+            None
+        };
+        let knowledge = match self.knowledge {
+            Some(ingestor) => Some(Arc::new(
+                KnowledgeBase::open(self.store.clone(), self.vectors.clone(), ingestor).await?,
+            )),
+            None => None,
+        };
+        let jobs = match self.jobs {
+            Some(cfg) => Some(Arc::new(Jobs::open(self.store.clone(), cfg).await?)),
+            None => None,
+        };
+        let webhooks = match jobs {
+            Some(_) => Some(Arc::new(Webhooks::open(self.store.clone()).await?)),
+            None => None,
+        };
+        let metering = self
+            .metering
+            .map(|cfg| Arc::new(Metering::new(self.store.clone(), cfg, self.billing_hooks)));
+        let quotas = self.quotas.map(|cfg| {
+            Arc::new(Quotas::new(self.store.clone(), cfg).with_clock(self.clock.clone()))
+        });
+        let rate_limiter = self
+            .rate_limit
+            .map(RateLimiter::new)
+            .transpose()?
+            .map(Arc::new);
+        if let Some(cfg) = &self.policy_canister {
+            // no caller is allowed more than the policy of the canister
+            self.access_policy = cfg.fetch(&self.web3.as_ref()).await?;
+        }
+        let cache = Arc::new(CacheService::new(&self.cache, names));
+        let mut ctx = BaseCtx::new(
             self.id,
             self.name.clone(),
             self.cancellation_token,
-            names,
+            cache.clone(),
             self.web3,
             self.store,
-            Arc::new(remote),
+            Arc::new(ArcSwap::from_pointee(remote)),
         );
+        if let Some(cfg) = &self.remote_health {
+            ctx.remote_health = Arc::new(RemoteHealth::new(cfg.failure_threshold));
+        }
+        ctx.canister_policy = Arc::new(ArcSwap::from_pointee(self.canister_policy));
+        ctx.http_policy = Arc::new(ArcSwap::from_pointee(self.http_policy));
+        ctx.access_policy = Arc::new(ArcSwap::from_pointee(self.access_policy));
+        ctx.audit = audit;
+        ctx.tenants = Arc::new(self.tenants);
+        ctx.jobs = jobs.clone();
+        ctx.metering = metering.clone();
+        ctx.quotas = quotas.clone();
+        ctx.vectors = self.vectors;
+        ctx.knowledge = knowledge;
+        ctx.set_clock(self.clock);
+        ctx.random = self.random;
+        ctx.tool_timeout = self.tool_timeout;
+        ctx.rpc_retry = self.rpc_retry.map(Arc::new);
+        ctx.http_retry = self.http_retry.map(Arc::new);
+        ctx.tool_middlewares = Arc::new(ToolMiddlewares::new(self.tool_middlewares));
+        ctx.tool_capabilities = Arc::new(self.tool_capabilities);
 
         if self.management.controller == Principal::anonymous() {
             self.management.controller = self.id;
         }
+        ctx.managers = Arc::new(
+            self.management
+                .managers
+                .iter()
+                .copied()
+                .chain([self.management.controller])
+                .collect(),
+        );
 
         let management = self.management.build(&ctx);
         let management = Arc::new(management);
@@ -640,13 +2740,19 @@ impl EngineBuilder {
 
         let tools = Arc::new(self.tools);
         let agents = Arc::new(self.agents);
-        let ctx = AgentCtx::new(
+        let mut ctx = AgentCtx::new(
             ctx,
             self.model,
             tools.clone(),
             agents.clone(),
             management.clone(),
         );
+        ctx.batch_model = self.batch_model;
+        ctx.models = Arc::new(self.models);
+        ctx.pricing = Arc::new(self.pricing);
+        ctx.memory = self.memory;
+        ctx.history = self.history;
+        ctx.agent_hooks = Arc::new(AgentHooks::new(self.agent_hooks));
 
         let meta = RequestMeta::default();
         for (name, tool) in &tools.set {
@@ -659,17 +2765,86 @@ impl EngineBuilder {
             agent.init(ct).await?;
         }
 
-        Ok(Engine {
+        let engine = Engine {
             id: self.id,
             ctx,
             name: self.name,
             description: self.description,
             default_agent,
-            export_agents: self.export_agents,
-            export_tools: self.export_tools,
+            export_agents: Arc::new(ArcSwap::from_pointee(self.export_agents)),
+            export_tools: Arc::new(ArcSwap::from_pointee(self.export_tools)),
             hooks: self.hooks,
             management,
-        })
+            runs: Arc::new(RunTracker::default()),
+            api_keys,
+            webhooks,
+            payments: Arc::new(self.payments),
+            attachments: self.attachments.map(Arc::new),
+            rate_limiter,
+            flags: Arc::new(ArcSwap::from_pointee(self.flags)),
+            snapshots: self.snapshots,
+            cache: Arc::new(self.cache.clone()),
+            registry: self.registry.map(|cfg| Arc::new(Registry::new(cfg))),
+            policy_canister: self.policy_canister.map(Arc::new),
+        };
+
+        let restored = if engine.snapshots {
+            engine.restore().await?
+        } else {
+            None
+        };
+        if let Some(report) = restored {
+            log::warn!(
+                engine = engine.id.to_text(),
+                created_at_ms = report.created_at_ms,
+                cache_entries = report.cache_entries,
+                interrupted = report.runs.len();
+                "snapshot restored",
+            );
+        }
+        if let Some(jobs) = jobs {
+            tokio::spawn(jobs.run(engine.clone()));
+        }
+        if let Some(metering) = metering {
+            tokio::spawn(metering.run(engine.cancellation_token()));
+        }
+        if let Some(quotas) = quotas {
+            tokio::spawn(quotas.run(engine.cancellation_token()));
+        }
+        if let Some(interval) = self.cache.sweep_interval() {
+            tokio::spawn(cache.run_sweeps(interval, engine.cancellation_token()));
+        }
+        if self.cache.persist {
+            // reloaded in the background, so that a large cache doesn't delay the start
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                match engine.reload_caches().await {
+                    Ok(entries) => log::info!(
+                        engine = engine.id.to_text(),
+                        entries = entries;
+                        "caches reloaded",
+                    ),
+                    Err(err) => log::error!("failed to reload caches: {}", err),
+                }
+            });
+        }
+        if let Some(registry) = &engine.registry {
+            // the engine still starts with its own remote engines if the registry is unavailable
+            if let Err(err) = engine.refresh_registry().await {
+                log::warn!("failed to refresh the registry: {}", err);
+            }
+            tokio::spawn(registry::run(engine.clone(), registry.refresh_interval()));
+        }
+        if let Some(cfg) = self.remote_health {
+            tokio::spawn(run_remote_health_checks(engine.clone(), cfg));
+        }
+        if let Some(cfg) = &engine.policy_canister {
+            tokio::spawn(run_access_policy_refresh(
+                engine.clone(),
+                cfg.refresh_interval(),
+            ));
+        }
+        Ok(engine)
     }
 
     /// Creates a mock context for testing purposes.
@@ -683,23 +2858,682 @@ impl EngineBuilder {
             .map(|s| Path::from(s.as_str()))
             .collect();
         names.insert(Path::from(SYSTEM_PATH));
-        let ctx = BaseCtx::new(
+        let quotas = self.quotas.map(|cfg| {
+            Arc::new(Quotas::new(self.store.clone(), cfg).with_clock(self.clock.clone()))
+        });
+        let mut ctx = BaseCtx::new(
             anda_core::ANONYMOUS,
             "Mocker".to_string(),
             self.cancellation_token,
-            names,
+            Arc::new(CacheService::new(&self.cache, names)),
             self.web3,
             self.store,
-            Arc::new(RemoteEngines::new()),
+            Arc::new(ArcSwap::from_pointee(RemoteEngines::new())),
         );
+        ctx.canister_policy = Arc::new(ArcSwap::from_pointee(self.canister_policy));
+        ctx.http_policy = Arc::new(ArcSwap::from_pointee(self.http_policy));
+        ctx.access_policy = Arc::new(ArcSwap::from_pointee(self.access_policy));
+        ctx.vectors = self.vectors;
+        ctx.quotas = quotas;
+        ctx.set_clock(self.clock);
+        ctx.random = self.random;
+        ctx.tool_timeout = self.tool_timeout;
+        ctx.rpc_retry = self.rpc_retry.map(Arc::new);
+        ctx.http_retry = self.http_retry.map(Arc::new);
+        ctx.tool_middlewares = Arc::new(ToolMiddlewares::new(self.tool_middlewares));
+        ctx.tool_capabilities = Arc::new(self.tool_capabilities);
         let management = self.management.build(&ctx);
         let management = Arc::new(management);
-        AgentCtx::new(
+        let mut ctx = AgentCtx::new(
             ctx,
             self.model,
             Arc::new(self.tools),
             Arc::new(self.agents),
             management,
-        )
+        );
+        ctx.models = Arc::new(self.models);
+        ctx.pricing = Arc::new(self.pricing);
+        ctx.memory = self.memory;
+        ctx.history = self.history;
+        ctx.agent_hooks = Arc::new(AgentHooks::new(self.agent_hooks));
+        ctx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{
+        AgentContext, BoxPinFut, CompletionFeatures, CompletionRequest, FunctionDefinition,
+        Resource, StateFeatures, TraceStep,
+    };
+    use futures::StreamExt;
+
+    use crate::model::CompletionFeaturesDyn;
+
+    /// Sleeps for the given milliseconds, then returns its name.
+    struct SleepTool(&'static str);
+
+    impl Tool<BaseCtx> for SleepTool {
+        type Args = u64;
+        type Output = String;
+
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn description(&self) -> String {
+            "Sleeps for the given milliseconds.".to_string()
+        }
+
+        fn definition(&self) -> FunctionDefinition {
+            FunctionDefinition {
+                name: self.name(),
+                description: self.description(),
+                parameters: json!({"type": "integer"}),
+                strict: None,
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: BaseCtx,
+            args: Self::Args,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<ToolOutput<Self::Output>, BoxError> {
+            tokio::time::sleep(Duration::from_millis(args)).await;
+            Ok(ToolOutput::new(self.name()))
+        }
+    }
+
+    /// Answers with the prompt.
+    struct EchoAgent;
+
+    impl Agent<AgentCtx> for EchoAgent {
+        fn name(&self) -> String {
+            "echo".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Echoes the prompt.".to_string()
+        }
+
+        async fn run(
+            &self,
+            _ctx: AgentCtx,
+            prompt: String,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            Ok(AgentOutput {
+                content: prompt,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Fails with the HTTP status if any, or answers as the named model.
+    struct StatusCompleter(Option<u16>, &'static str);
+
+    impl CompletionFeaturesDyn for StatusCompleter {
+        fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+            let res = match self.0 {
+                Some(status) => Err(Error::Provider {
+                    provider: self.1.to_string(),
+                    status: Some(status),
+                    message: "error".to_string(),
+                }
+                .into()),
+                None => Ok(AgentOutput {
+                    content: req.prompt,
+                    usage: Usage {
+                        input_tokens: 1_000_000,
+                        requests: 1,
+                        ..Default::default()
+                    }
+                    .with_model(self.1),
+                    ..Default::default()
+                }),
+            };
+            Box::pin(futures::future::ready(res))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_run_tracker() {
+        let runs = Arc::new(RunTracker::default());
+        let caller = Principal::anonymous();
+        let mut r1 = runs
+            .enter("agent", "a", caller, CancellationToken::new())
+            .unwrap();
+        let r2 = runs
+            .enter("tool", "t", caller, CancellationToken::new())
+            .unwrap();
+        assert_eq!(runs.in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(runs.active_runs().len(), 2);
+        assert!(runs.cancel(r2.id));
+        assert!(r2.token.is_cancelled());
+        assert!(!runs.cancel(42));
+
+        runs.draining.store(true, Ordering::SeqCst);
+        assert!(
+            runs.enter("agent", "a", caller, CancellationToken::new())
+                .is_err()
+        );
+        assert_eq!(runs.in_flight.load(Ordering::SeqCst), 2);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), runs.wait_idle())
+                .await
+                .is_err()
+        );
+
+        r1.finish(&Usage {
+            input_tokens: 10,
+            output_tokens: 5,
+            requests: 1,
+            ..Default::default()
+        });
+        drop(r1);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(r2);
+        });
+        tokio::time::timeout(Duration::from_secs(1), runs.wait_idle())
+            .await
+            .unwrap();
+        assert_eq!(runs.in_flight.load(Ordering::SeqCst), 0);
+        assert!(runs.active_runs().is_empty());
+
+        let stats = runs.stats.read().unwrap().clone();
+        assert_eq!(stats.agent_runs, 1);
+        assert_eq!(stats.tool_calls, 1);
+        // r2 and the rejected run
+        assert_eq!(stats.failed, 2);
+        assert_eq!(stats.usage.input_tokens, 10);
+
+        runs.restore_stats(&stats);
+        let stats = runs.stats.read().unwrap().clone();
+        assert_eq!(stats.agent_runs, 2);
+        assert_eq!(stats.usage.input_tokens, 20);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_readiness() {
+        // no model is configured
+        let engine = EngineBuilder::new()
+            .register_agent(EchoAgent)
+            .unwrap()
+            .build("echo".to_string())
+            .await
+            .unwrap();
+        let readiness = engine.readiness().await;
+        assert!(readiness.ready, "{:?}", readiness.checks);
+        assert!(readiness.checks["store"].ok);
+        assert!(!readiness.checks.contains_key("model"));
+        assert!(!Model::not_implemented().is_implemented());
+
+        let engine = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .register_agent(EchoAgent)
+            .unwrap()
+            .build("echo".to_string())
+            .await
+            .unwrap();
+        let readiness = engine.readiness().await;
+        assert!(readiness.ready, "{:?}", readiness.checks);
+        assert!(readiness.checks["model"].ok);
+    }
+
+    /// Runs until it is cancelled.
+    struct PendingAgent;
+
+    impl Agent<AgentCtx> for PendingAgent {
+        fn name(&self) -> String {
+            "pending".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Never completes.".to_string()
+        }
+
+        async fn run(
+            &self,
+            _ctx: AgentCtx,
+            _prompt: String,
+            _resources: Option<Vec<Resource>>,
+        ) -> Result<AgentOutput, BoxError> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_agent_run_events() {
+        let caller = Principal::management_canister();
+        let engine = EngineBuilder::new()
+            .with_management(ManagementBuilder::new(Visibility::Public, caller))
+            .register_agent(EchoAgent)
+            .unwrap()
+            .register_agent(PendingAgent)
+            .unwrap()
+            .export_agents(vec!["pending".to_string()])
+            .build("echo".to_string())
+            .await
+            .unwrap();
+        let mut rx = engine.agent_run_events(
+            caller,
+            AgentInput::new("echo".to_string(), "hi".to_string()),
+        );
+        match rx.recv().await {
+            Some(AgentEvent::Output(output)) => assert_eq!(output.content, "hi"),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(rx.recv().await.is_none());
+
+        // the run is cancelled when the receiver is dropped
+        let rx = engine.agent_run_events(
+            caller,
+            AgentInput::new("pending".to_string(), "hi".to_string()),
+        );
+        let engine = &engine;
+        let wait_in_flight = |n: usize| {
+            tokio::time::timeout(Duration::from_secs(5), async move {
+                while engine.in_flight() != n {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+        wait_in_flight(1).await.unwrap();
+        drop(rx);
+        wait_in_flight(0).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_clock_and_random() {
+        let clock = anda_core::MockClock::new(1_000);
+        let ctx = EngineBuilder::new()
+            .with_clock(Arc::new(clock.clone()))
+            .with_random(Arc::new(anda_core::SeededRandom::new(42)))
+            .mock_ctx();
+        let req = ctx
+            .child_with(
+                Principal::anonymous(),
+                "a",
+                RequestMeta {
+                    timeout_ms: Some(10_000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(req.time_elapsed(), Duration::ZERO);
+        assert_eq!(req.time_remaining(), Some(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(ctx.now_ms(), 5_000);
+        assert_eq!(ctx.time_elapsed(), Duration::from_secs(4));
+        assert_eq!(req.time_remaining(), Some(Duration::from_secs(6)));
+        let tool = req.child_base("t").unwrap();
+        assert_eq!(tool.time_remaining(), Some(Duration::from_secs(6)));
+
+        let nonce: [u8; 16] = tool.random_bytes();
+        let ctx2 = EngineBuilder::new()
+            .with_random(Arc::new(anda_core::SeededRandom::new(42)))
+            .mock_ctx();
+        assert_eq!(ctx2.random_bytes::<16>(), nonce);
+        assert_ne!(ctx.random_bytes::<16>(), nonce);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_quota_window_clock() {
+        let clock = anda_core::MockClock::new(1_000);
+        let ctx = EngineBuilder::new()
+            .with_clock(Arc::new(clock.clone()))
+            .with_user_quotas(QuotaConfig {
+                daily: QuotaLimits {
+                    max_tool_calls: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .mock_ctx();
+        let quotas = ctx.base.quotas.clone().unwrap();
+        let user = quota_user(&ctx.base.caller, &ctx.base.meta);
+
+        ctx.base.meter(|u| u.tool_calls += 1);
+        let err = quotas
+            .check(&user, false, clock.now_ms())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("daily tool call quota"));
+
+        // the next day of the test clock opens a new daily window
+        clock.advance(Duration::from_secs(60 * 60 * 24));
+        quotas.check(&user, false, clock.now_ms()).await.unwrap();
+        assert_eq!(quotas.usage(&user).await.unwrap().daily.tool_calls, 0);
+        ctx.base.meter(|u| u.tool_calls += 1);
+        assert!(quotas.check(&user, false, clock.now_ms()).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_completion_stream() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .mock_ctx();
+        let req = CompletionRequest {
+            prompt: "Hello".to_string(),
+            ..Default::default()
+        };
+        let events: Vec<AgentEvent> = ctx.completion_stream(req, None).collect().await;
+        let names: Vec<&str> = events.iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["delta", "content", "output"]);
+        match &events[2] {
+            AgentEvent::Output(output) => assert_eq!(output.content, "Hello"),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_parallel_tool_calls() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .register_tool(SleepTool("sleep_a"))
+            .unwrap()
+            .register_tool(SleepTool("sleep_b"))
+            .unwrap()
+            .mock_ctx();
+        for parallel in [false, true] {
+            // the mock model calls every tool with the prompt as arguments
+            let req = CompletionRequest {
+                prompt: "200".to_string(),
+                tools: ctx.tool_definitions(None),
+                parallel_tool_calls: parallel,
+                ..Default::default()
+            };
+            let started = Instant::now();
+            let output = ctx.completion(req, None).await.unwrap();
+            let elapsed = started.elapsed();
+            assert!(output.failed_reason.is_none());
+            if parallel {
+                assert!(elapsed < Duration::from_millis(400));
+            } else {
+                assert!(elapsed >= Duration::from_millis(400));
+            }
+
+            let tool_calls = output.tool_calls.unwrap();
+            let names: Vec<&str> = tool_calls.iter().map(|t| t.name.as_str()).collect();
+            assert_eq!(names, ["sleep_a", "sleep_b"]);
+            assert_eq!(tool_calls[0].result.as_ref().unwrap()["output"], "sleep_a");
+            assert_eq!(tool_calls[1].result.as_ref().unwrap()["output"], "sleep_b");
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_timeout() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .with_tool_timeout(Duration::from_millis(100))
+            .register_tool(SleepTool("sleep_a"))
+            .unwrap()
+            .mock_ctx();
+        let req = CompletionRequest {
+            prompt: "1000".to_string(),
+            tools: ctx.tool_definitions(None),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let output = ctx.completion(req, None).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(1000));
+        // the run goes on without the result of the tool
+        assert!(output.failed_reason.is_none());
+        let tool_calls = output.tool_calls.unwrap();
+        assert!(tool_calls[0].result.is_none());
+        match &output.trace.unwrap()[1] {
+            TraceStep::ToolCall(call) => {
+                assert_eq!(
+                    call.error.as_deref(),
+                    Some("tool sleep_a timed out after 100 ms")
+                );
+            }
+            step => panic!("unexpected step {:?}", step),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_capabilities() {
+        use anda_core::{PutMode, StoreFeatures, anda_error};
+
+        let ctx = EngineBuilder::new()
+            .with_tool_capabilities("untrusted", Capabilities::read_only())
+            .mock_ctx();
+        let path = Path::from("notes/a");
+        let base = ctx.child_base("untrusted").unwrap();
+        assert_eq!(base.capabilities(), &Capabilities::read_only());
+        let err = base
+            .store_put(&path, PutMode::Overwrite, "a".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(anda_error(&err), Some(Error::Unauthorized(_))));
+        let err = base
+            .https_call("https://example.com", http::Method::GET, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(anda_error(&err), Some(Error::Unauthorized(_))));
+        // the children of the tool context inherit its capabilities
+        let child = base.child("T:trusted".to_string()).unwrap();
+        assert!(child.store_delete(&path).await.is_err());
+
+        let mut base = ctx.child_base("trusted").unwrap();
+        assert_eq!(base.capabilities(), &Capabilities::all());
+        base.store_put(&path, PutMode::Overwrite, "a".into())
+            .await
+            .unwrap();
+        base.restrict_capabilities(&Capabilities {
+            store_write: false,
+            ..Default::default()
+        });
+        assert!(base.store_delete(&path).await.is_err());
+        assert!(base.store_get(&path).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_rounds_guard() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::mock_implemented())
+            .register_tool(SleepTool("sleep_a"))
+            .unwrap()
+            .mock_ctx();
+        let req = CompletionRequest {
+            prompt: "10".to_string(),
+            tools: ctx.tool_definitions(None),
+            max_tool_rounds: Some(0),
+            ..Default::default()
+        };
+        let output = ctx.completion(req, None).await.unwrap();
+        assert_eq!(
+            output.failed_reason.as_deref(),
+            Some("exceeded the limit of 0 tool call rounds")
+        );
+
+        // the mock model calls the tool again with the same empty arguments, which are invalid
+        let req = CompletionRequest {
+            tools: ctx.tool_definitions(None),
+            ..Default::default()
+        };
+        let output = ctx.completion(req, None).await.unwrap();
+        assert_eq!(
+            output.failed_reason.as_deref(),
+            Some("repeated tool call sleep_a with the same arguments")
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_model_fallbacks() {
+        let model = |status, name| Model::with_completer(Arc::new(StatusCompleter(status, name)));
+        let req = CompletionRequest {
+            prompt: "Hello".to_string(),
+            ..Default::default()
+        };
+
+        let ctx = EngineBuilder::new()
+            .with_models([
+                model(Some(429), "primary"),
+                model(Some(503), "secondary"),
+                model(None, "tertiary"),
+            ])
+            .mock_ctx();
+        let output = ctx.completion(req.clone(), None).await.unwrap();
+        assert_eq!(output.content, "Hello");
+        assert_eq!(output.model.as_deref(), Some("tertiary"));
+
+        // a bad request is not retried on the fallback models
+        let ctx = EngineBuilder::new()
+            .with_models([model(Some(400), "primary"), model(None, "secondary")])
+            .mock_ctx();
+        let err = ctx.completion(req.clone(), None).await.unwrap_err();
+        assert!(err.to_string().starts_with("primary error"), "{}", err);
+
+        let ctx = EngineBuilder::new()
+            .with_models([model(Some(500), "primary"), model(Some(502), "secondary")])
+            .mock_ctx();
+        let err = ctx.completion(req, None).await.unwrap_err();
+        assert!(err.to_string().starts_with("secondary error"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_model_override() {
+        let model = |name| Model::with_completer(Arc::new(StatusCompleter(None, name)));
+        let ctx = EngineBuilder::new()
+            .with_model(model("default"))
+            .register_model("cheap", model("cheap"))
+            .unwrap()
+            .mock_ctx();
+        let output = ctx
+            .completion(CompletionRequest::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(output.model.as_deref(), Some("default"));
+
+        let req = CompletionRequest {
+            model: Some("cheap".to_string()),
+            ..Default::default()
+        };
+        let output = ctx.completion(req, None).await.unwrap();
+        assert_eq!(output.model.as_deref(), Some("cheap"));
+
+        let req = CompletionRequest {
+            model: Some("unknown".to_string()),
+            ..Default::default()
+        };
+        let err = ctx.completion(req, None).await.unwrap_err();
+        assert_eq!(
+            anda_core::anda_error(&err),
+            Some(&Error::NotFound("model unknown".to_string()))
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pricing() {
+        let ctx = EngineBuilder::new()
+            .with_model(Model::with_completer(Arc::new(StatusCompleter(
+                None, "default",
+            ))))
+            .with_pricing(Pricing(BTreeMap::from([(
+                "default".to_string(),
+                anda_core::ModelPrice {
+                    input: 1.0,
+                    output: 2.0,
+                },
+            )])))
+            .mock_ctx();
+        let req = CompletionRequest {
+            prompt: "Hello".to_string(),
+            ..Default::default()
+        };
+        let output = ctx.completion(req, None).await.unwrap();
+        // one million input tokens
+        assert!((output.usage.cost_usd - 1.0).abs() < 1e-9);
+        assert_eq!(
+            output.usage.breakdown.unwrap().models["default"].cost_usd,
+            output.usage.cost_usd
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_deadline() {
+        let ctx = EngineBuilder::new().mock_ctx();
+        assert!(ctx.time_remaining().is_none());
+
+        let meta = RequestMeta {
+            timeout_ms: Some(50),
+            ..Default::default()
+        };
+        let req = ctx
+            .base
+            .child_with(Principal::anonymous(), "A:a".to_string(), meta.clone())
+            .unwrap();
+        assert!(req.time_remaining().unwrap() <= Duration::from_millis(50));
+        // a nested request can't extend the deadline
+        let nested = req
+            .child_with(
+                Principal::anonymous(),
+                "T:t".to_string(),
+                RequestMeta {
+                    timeout_ms: Some(60_000),
+                    ..meta
+                },
+            )
+            .unwrap();
+        assert!(nested.time_remaining().unwrap() <= Duration::from_millis(50));
+        let remote = nested.self_meta(Principal::anonymous());
+        assert!(remote.timeout_ms.unwrap() <= 50);
+
+        let res: Result<(), BoxError> = nested
+            .child("T:u".to_string())
+            .unwrap()
+            .guard(async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(())
+            })
+            .await;
+        assert!(res.unwrap_err().to_string().contains("deadline exceeded"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_extensions() {
+        struct Locale(String);
+        struct LocaleHook;
+
+        #[async_trait]
+        impl Hook for LocaleHook {
+            async fn on_request(
+                &self,
+                _caller: &Principal,
+                meta: &RequestMeta,
+                extensions: &mut Extensions,
+            ) -> Result<(), BoxError> {
+                if let Some(user) = &meta.user {
+                    extensions.insert(Locale(format!("locale of {}", user)));
+                }
+                Ok(())
+            }
+        }
+
+        let mut hooks = Hooks::new();
+        hooks.add(Box::new(LocaleHook));
+        let ctx = EngineBuilder::new().mock_ctx();
+        let meta = RequestMeta {
+            user: Some("alice".to_string()),
+            ..Default::default()
+        };
+        let mut req = ctx.child_with(Principal::anonymous(), "a", meta).unwrap();
+        hooks
+            .on_request(&req.base.caller, &req.base.meta, &mut req.base.extensions)
+            .await
+            .unwrap();
+        assert!(ctx.extensions().get::<Locale>().is_none());
+
+        let tool = req.child_base("t").unwrap();
+        assert_eq!(
+            tool.extensions().get::<Locale>().unwrap().0,
+            "locale of alice"
+        );
     }
 }
@@ -0,0 +1,1164 @@
+//! Bitcoin Extension for Anda Engine
+//!
+//! This module gives agents a Bitcoin wallet whose key never leaves the engine: the addresses
+//! are derived from the Secp256k1 public key of a derivation path, and the transactions are
+//! signed with the [`KeysFeatures`] of the context.
+//!
+//! # Features
+//! - P2WPKH (native SegWit) addresses, see [`AddressType`];
+//! - PSBT construction with largest-first coin selection and change, and PSBT signing;
+//! - UTXOs and fee rates through a [`BitcoinBackend`]: the Bitcoin canister of the Internet
+//!   Computer (queries only), or an Esplora HTTP endpoint, which also broadcasts transactions;
+//! - `bitcoin_wallet`: a tool to get the caller's address and balance, and to send bitcoins.
+//!
+//! The keys are derived within the scope of the context, that is the name of the tool or agent,
+//! and the `bitcoin_wallet` tool derives a wallet for each caller from its principal, so that
+//! a caller only spends its own bitcoins. Anonymous callers can't send bitcoins.
+//! The transfers are limited by [`BitcoinConfig::with_limit`], without which they are
+//! disabled, and the recipients can be restricted with [`BitcoinConfig::with_recipients`].
+//!
+//! Taproot addresses are not supported: a P2TR output key must be tweaked as BIP341 requires,
+//! and the context only signs with the untweaked key of a derivation path.
+//!
+//! The module is enabled by the `bitcoin` feature.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let wallet = BitcoinWalletTool::new(
+//!     BitcoinConfig::new(Network::Bitcoin)
+//!         .with_backend(BitcoinBackend::Esplora("https://blockstream.info/api".to_string()))
+//!         .with_limit(BitcoinLimit {
+//!             per_call: Amount::from_sat(100_000),
+//!             per_caller_daily: Amount::from_sat(1_000_000),
+//!         }),
+//! );
+//! let engine = Engine::builder()
+//!     .register_tool(wallet)?
+//!     .register_agent(my_agent)?
+//!     .build(default_agent)
+//!     .await?;
+//! ```
+
+use anda_core::{
+    BoxError, CanisterCaller, FunctionDefinition, HttpFeatures, KeysFeatures, Resource,
+    StateFeatures, Tool, ToolArgs, ToolOutput,
+};
+use bitcoin::{
+    Address, Amount, CompressedPublicKey, Denomination, EcdsaSighashType, Network, OutPoint, Psbt,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness, absolute::LockTime,
+    consensus::encode::serialize_hex, hashes::Hash, secp256k1, sighash::SighashCache,
+    transaction::Version,
+};
+use candid::{CandidType, Principal};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use structured_logger::unix_ms;
+
+use crate::context::BaseCtx;
+
+/// The default derivation path of the wallet, within the scope of the context.
+pub const DEFAULT_DERIVATION_PATH: &[u8] = b"bitcoin";
+
+/// The weight of the version, locktime, counts and SegWit marker of a transaction.
+const TX_BASE_WEIGHT: u64 = 42;
+
+/// The weight of a P2WPKH input: outpoint, sequence, empty script and the witness with a
+/// DER signature of up to 72 bytes and a compressed public key.
+const P2WPKH_INPUT_WEIGHT: u64 = 272;
+
+/// The confirmation target of the estimated fee rates, in blocks.
+const FEE_TARGET_BLOCKS: &str = "6";
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// The type of the wallet's address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressType {
+    /// Native SegWit v0 address, spent with an ECDSA signature.
+    #[default]
+    P2wpkh,
+}
+
+impl AddressType {
+    fn input_weight(&self) -> u64 {
+        match self {
+            AddressType::P2wpkh => P2WPKH_INPUT_WEIGHT,
+        }
+    }
+}
+
+/// Derives the address of a compressed Secp256k1 public key.
+pub fn derive_address(
+    public_key: &CompressedPublicKey,
+    address_type: AddressType,
+    network: Network,
+) -> Address {
+    match address_type {
+        AddressType::P2wpkh => Address::p2wpkh(public_key, network),
+    }
+}
+
+/// An unspent transaction output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    /// Value in satoshis.
+    pub value: u64,
+    /// Height of the block that includes the transaction, `None` if unconfirmed.
+    pub height: Option<u32>,
+}
+
+/// A Bitcoin wallet derived from a derivation path of the context's keys.
+#[derive(Debug, Clone)]
+pub struct BitcoinWallet {
+    pub network: Network,
+    pub address_type: AddressType,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub public_key: CompressedPublicKey,
+    pub address: Address,
+}
+
+impl BitcoinWallet {
+    /// Loads the wallet of the derivation path from the context's keys.
+    pub async fn load(
+        ctx: &impl KeysFeatures,
+        network: Network,
+        address_type: AddressType,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> Result<Self, BoxError> {
+        let public_key = ctx.secp256k1_public_key(derivation_path.clone()).await?;
+        let public_key = CompressedPublicKey::from_slice(&public_key)?;
+        Ok(Self {
+            network,
+            address_type,
+            derivation_path,
+            address: derive_address(&public_key, address_type, network),
+            public_key,
+        })
+    }
+
+    /// Builds an unsigned PSBT that pays the outputs from the UTXOs of the wallet.
+    ///
+    /// The UTXOs are selected from the largest, and the change above the dust limit is sent
+    /// back to the wallet's address, otherwise it is left to the fee.
+    ///
+    /// # Arguments
+    /// * `utxos` - UTXOs of the wallet's address;
+    /// * `outputs` - Recipients and amounts;
+    /// * `fee_rate` - Fee rate in satoshis per virtual byte.
+    pub fn build_psbt(
+        &self,
+        utxos: &[Utxo],
+        outputs: &[(Address, Amount)],
+        fee_rate: u64,
+    ) -> Result<Psbt, BoxError> {
+        if outputs.is_empty() {
+            return Err("no outputs to pay".into());
+        }
+
+        let mut output = Vec::with_capacity(outputs.len() + 1);
+        let mut target = 0u64;
+        for (address, amount) in outputs {
+            if !address.as_unchecked().is_valid_for_network(self.network) {
+                return Err(
+                    format!("address {} is not valid for {}", address, self.network).into(),
+                );
+            }
+            let txout = TxOut {
+                value: *amount,
+                script_pubkey: address.script_pubkey(),
+            };
+            if *amount < txout.script_pubkey.minimal_non_dust() {
+                return Err(
+                    format!("amount {} to {} is below the dust limit", amount, address).into(),
+                );
+            }
+            target = target
+                .checked_add(amount.to_sat())
+                .ok_or("total amount overflow")?;
+            output.push(txout);
+        }
+
+        let change = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: self.address.script_pubkey(),
+        };
+        let fee = |weight: u64| weight.div_ceil(4).saturating_mul(fee_rate);
+        let mut weight = TX_BASE_WEIGHT + output.iter().map(|o| o.weight().to_wu()).sum::<u64>();
+
+        let mut candidates = utxos.to_vec();
+        candidates.sort_by(|a, b| b.value.cmp(&a.value));
+        let mut selected = Vec::new();
+        let mut total = 0u64;
+        for utxo in candidates {
+            if total >= target.saturating_add(fee(weight)) {
+                break;
+            }
+            weight += self.address_type.input_weight();
+            total += utxo.value;
+            selected.push(utxo);
+        }
+
+        let required = target.saturating_add(fee(weight));
+        if total < required {
+            return Err(format!(
+                "insufficient funds: {} available, {} required",
+                Amount::from_sat(total),
+                Amount::from_sat(required)
+            )
+            .into());
+        }
+
+        let change_value = total
+            .saturating_sub(target)
+            .saturating_sub(fee(weight + change.weight().to_wu()));
+        if Amount::from_sat(change_value) >= change.script_pubkey.minimal_non_dust() {
+            output.push(TxOut {
+                value: Amount::from_sat(change_value),
+                ..change
+            });
+        }
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: selected
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        for (input, utxo) in psbt.inputs.iter_mut().zip(selected.iter()) {
+            input.witness_utxo = Some(TxOut {
+                value: Amount::from_sat(utxo.value),
+                script_pubkey: self.address.script_pubkey(),
+            });
+        }
+        Ok(psbt)
+    }
+
+    /// Signs and finalizes the inputs of the PSBT that spend the wallet's address.
+    ///
+    /// All the inputs must have their witness UTXO, which is needed by the signature hashes.
+    /// The other inputs are left as they are.
+    pub async fn sign_psbt(
+        &self,
+        ctx: &impl KeysFeatures,
+        psbt: &mut Psbt,
+    ) -> Result<(), BoxError> {
+        let script_pubkey = self.address.script_pubkey();
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                input
+                    .witness_utxo
+                    .clone()
+                    .ok_or_else(|| format!("input {} has no witness UTXO", i))
+            })
+            .collect::<Result<Vec<TxOut>, _>>()?;
+
+        let mut cache = SighashCache::new(psbt.unsigned_tx.clone());
+        for (i, prevout) in prevouts.iter().enumerate() {
+            if prevout.script_pubkey != script_pubkey {
+                continue;
+            }
+
+            let witness = match self.address_type {
+                AddressType::P2wpkh => {
+                    let sighash = cache.p2wpkh_signature_hash(
+                        i,
+                        &script_pubkey,
+                        prevout.value,
+                        EcdsaSighashType::All,
+                    )?;
+                    let sig = ctx
+                        .secp256k1_sign_digest_ecdsa(
+                            self.derivation_path.clone(),
+                            sighash.as_byte_array(),
+                        )
+                        .await?;
+                    let mut signature = secp256k1::ecdsa::Signature::from_compact(&sig)?;
+                    signature.normalize_s();
+                    let signature = bitcoin::ecdsa::Signature {
+                        signature,
+                        sighash_type: EcdsaSighashType::All,
+                    };
+                    Witness::p2wpkh(&signature, &self.public_key.0)
+                }
+            };
+            psbt.inputs[i].final_script_witness = Some(witness);
+        }
+        Ok(())
+    }
+}
+
+/// The service to query UTXOs and fee rates, and to broadcast transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitcoinBackend {
+    /// The Bitcoin canister of the Internet Computer, e.g. "ghsi2-tqaaa-aaaan-aaaca-cai" for
+    /// mainnet. It only serves queries to callers outside the Internet Computer, so the fee
+    /// rates must be given and the transactions can't be broadcast.
+    Canister(Principal),
+    /// An Esplora HTTP API, e.g. "https://blockstream.info/api".
+    Esplora(String),
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize)]
+enum IcNetwork {
+    #[serde(rename = "mainnet")]
+    Mainnet,
+    #[serde(rename = "testnet")]
+    Testnet,
+    #[serde(rename = "regtest")]
+    Regtest,
+}
+
+impl From<Network> for IcNetwork {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Bitcoin => IcNetwork::Mainnet,
+            Network::Regtest => IcNetwork::Regtest,
+            _ => IcNetwork::Testnet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+enum IcUtxosFilter {
+    #[serde(rename = "page")]
+    Page(Vec<u8>),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct IcGetUtxosRequest {
+    network: IcNetwork,
+    address: String,
+    filter: Option<IcUtxosFilter>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct IcOutpoint {
+    txid: Vec<u8>,
+    vout: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct IcUtxo {
+    outpoint: IcOutpoint,
+    value: u64,
+    height: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct IcGetUtxosResponse {
+    utxos: Vec<IcUtxo>,
+    next_page: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraUtxo {
+    txid: String,
+    vout: u32,
+    value: u64,
+    status: EsploraStatus,
+}
+
+impl BitcoinBackend {
+    /// Returns true if the backend broadcasts transactions.
+    pub fn can_broadcast(&self) -> bool {
+        matches!(self, BitcoinBackend::Esplora(_))
+    }
+
+    /// Gets the UTXOs of an address.
+    pub async fn get_utxos(
+        &self,
+        ctx: &(impl CanisterCaller + HttpFeatures),
+        network: Network,
+        address: &Address,
+    ) -> Result<Vec<Utxo>, BoxError> {
+        match self {
+            BitcoinBackend::Canister(canister) => {
+                let mut res = Vec::new();
+                let mut filter = None;
+                loop {
+                    let req = IcGetUtxosRequest {
+                        network: network.into(),
+                        address: address.to_string(),
+                        filter,
+                    };
+                    let page: IcGetUtxosResponse = ctx
+                        .canister_query(canister, "bitcoin_get_utxos_query", (req,))
+                        .await?;
+                    for utxo in page.utxos {
+                        let txid = Txid::from_slice(&utxo.outpoint.txid)?;
+                        res.push(Utxo {
+                            outpoint: OutPoint::new(txid, utxo.outpoint.vout),
+                            value: utxo.value,
+                            height: Some(utxo.height),
+                        });
+                    }
+                    match page.next_page {
+                        Some(next) => filter = Some(IcUtxosFilter::Page(next)),
+                        None => return Ok(res),
+                    }
+                }
+            }
+            BitcoinBackend::Esplora(url) => {
+                let url = format!("{}/address/{}/utxo", url.trim_end_matches('/'), address);
+                let utxos: Vec<EsploraUtxo> = esplora_get(ctx, &url).await?.json().await?;
+                utxos
+                    .into_iter()
+                    .map(|utxo| {
+                        Ok(Utxo {
+                            outpoint: OutPoint::new(Txid::from_str(&utxo.txid)?, utxo.vout),
+                            value: utxo.value,
+                            height: if utxo.status.confirmed {
+                                utxo.status.block_height
+                            } else {
+                                None
+                            },
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Estimates the fee rate to confirm within 6 blocks, in satoshis per virtual byte.
+    pub async fn fee_rate(&self, ctx: &impl HttpFeatures) -> Result<u64, BoxError> {
+        match self {
+            BitcoinBackend::Canister(_) => {
+                Err("fee rates are not available from the Bitcoin canister".into())
+            }
+            BitcoinBackend::Esplora(url) => {
+                let url = format!("{}/fee-estimates", url.trim_end_matches('/'));
+                let estimates: BTreeMap<String, f64> = esplora_get(ctx, &url).await?.json().await?;
+                let rate = estimates
+                    .get(FEE_TARGET_BLOCKS)
+                    .ok_or("no fee estimate for 6 blocks")?;
+                Ok((rate.ceil() as u64).max(1))
+            }
+        }
+    }
+
+    /// Broadcasts a signed transaction and returns its ID.
+    pub async fn send_transaction(
+        &self,
+        ctx: &impl HttpFeatures,
+        tx: &Transaction,
+    ) -> Result<Txid, BoxError> {
+        match self {
+            BitcoinBackend::Canister(_) => {
+                Err("transactions can't be broadcast through the Bitcoin canister from outside the Internet Computer".into())
+            }
+            BitcoinBackend::Esplora(url) => {
+                let url = format!("{}/tx", url.trim_end_matches('/'));
+                let response = ctx
+                    .https_call(
+                        &url,
+                        http::Method::POST,
+                        None,
+                        Some(serialize_hex(tx).into_bytes()),
+                    )
+                    .await?;
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let msg = response.text().await.unwrap_or_default();
+                    return Err(format!("Esplora API returned status: {}, {}", status, msg).into());
+                }
+                let txid = response.text().await?;
+                Ok(Txid::from_str(txid.trim())?)
+            }
+        }
+    }
+}
+
+async fn esplora_get(ctx: &impl HttpFeatures, url: &str) -> Result<reqwest::Response, BoxError> {
+    let response = ctx.https_call(url, http::Method::GET, None, None).await?;
+    if !response.status().is_success() {
+        return Err(format!("Esplora API returned status: {}", response.status()).into());
+    }
+    Ok(response)
+}
+
+/// The limit of the transfers of the [`BitcoinWalletTool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitcoinLimit {
+    /// The maximum amount of a transfer, without the fee.
+    pub per_call: Amount,
+    /// The maximum total amount of the transfers of a caller in a UTC day.
+    pub per_caller_daily: Amount,
+}
+
+/// Configuration of the [`BitcoinWalletTool`].
+#[derive(Debug, Clone)]
+pub struct BitcoinConfig {
+    pub network: Network,
+    pub address_type: AddressType,
+    pub backend: BitcoinBackend,
+    /// The derivation path of the wallets, extended with the principal of each caller.
+    pub derivation_path: Vec<Vec<u8>>,
+    /// The limit of the transfers, which are disabled if None.
+    pub limit: Option<BitcoinLimit>,
+    /// The addresses that may receive the transfers, all if empty.
+    pub recipients: BTreeSet<String>,
+}
+
+impl BitcoinConfig {
+    /// Creates a configuration of a P2WPKH wallet with the Bitcoin canister of the network,
+    /// which can't broadcast transactions: set an Esplora backend to send bitcoins.
+    pub fn new(network: Network) -> Self {
+        let canister = match network {
+            Network::Bitcoin => "ghsi2-tqaaa-aaaan-aaaca-cai",
+            _ => "g4xu7-jiaaa-aaaan-aaaaq-cai",
+        };
+        Self {
+            network,
+            address_type: AddressType::default(),
+            backend: BitcoinBackend::Canister(Principal::from_text(canister).unwrap()),
+            derivation_path: vec![DEFAULT_DERIVATION_PATH.to_vec()],
+            limit: None,
+            recipients: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_address_type(mut self, address_type: AddressType) -> Self {
+        self.address_type = address_type;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: BitcoinBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_derivation_path(mut self, derivation_path: Vec<Vec<u8>>) -> Self {
+        self.derivation_path = derivation_path;
+        self
+    }
+
+    /// Enables the transfers within the limit.
+    pub fn with_limit(mut self, limit: BitcoinLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Restricts the recipients of the transfers to the addresses.
+    pub fn with_recipients(mut self, recipients: BTreeSet<String>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+}
+
+/// Actions of the [`BitcoinWalletTool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletAction {
+    /// Gets the user's Bitcoin address of the agent
+    #[default]
+    Address,
+    /// Gets the balance of the user's Bitcoin address
+    Balance,
+    /// Sends bitcoins from the user's address
+    Transfer,
+}
+
+/// Arguments of the Bitcoin wallet tool
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct WalletArgs {
+    /// Action to perform: "address", "balance" or "transfer"
+    pub action: WalletAction,
+    /// Recipient Bitcoin address, required by "transfer"
+    #[validate(length(max = 128))]
+    pub to: Option<String>,
+    /// Amount to send in BTC as a decimal string, e.g. "0.0015", required by "transfer"
+    #[validate(length(max = 32))]
+    pub amount: Option<String>,
+    /// Fee rate in satoshis per virtual byte for "transfer", estimated if not set
+    #[validate(range(min = 1, max = 10000))]
+    pub fee_rate: Option<u64>,
+}
+
+/// Output of the Bitcoin wallet tool.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WalletOutput {
+    /// The caller's Bitcoin address.
+    pub address: String,
+    /// Confirmed balance in BTC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<String>,
+    /// Unconfirmed balance in BTC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<String>,
+    /// ID of the sent transaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    /// Fee of the sent transaction in BTC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<String>,
+}
+
+/// Bitcoin wallet tool of an agent, with a wallet for each caller.
+#[derive(Debug, Clone)]
+pub struct BitcoinWalletTool {
+    config: BitcoinConfig,
+    schema: Value,
+    /// Amounts sent by caller, in satoshis, with their day.
+    spent: Arc<Mutex<BTreeMap<Principal, (u64, u64)>>>,
+}
+
+impl BitcoinWalletTool {
+    pub const NAME: &'static str = "bitcoin_wallet";
+
+    pub fn new(config: BitcoinConfig) -> Self {
+        Self {
+            config,
+            schema: WalletArgs::parameters(),
+            spent: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Loads the wallet of the caller from the context.
+    pub async fn wallet(
+        &self,
+        ctx: &impl KeysFeatures,
+        caller: &Principal,
+    ) -> Result<BitcoinWallet, BoxError> {
+        let mut derivation_path = self.config.derivation_path.clone();
+        derivation_path.push(caller.as_slice().to_vec());
+        BitcoinWallet::load(
+            ctx,
+            self.config.network,
+            self.config.address_type,
+            derivation_path,
+        )
+        .await
+    }
+
+    /// Signs and broadcasts a transaction that sends the amount from the caller's wallet to
+    /// the address.
+    pub async fn transfer(
+        &self,
+        ctx: &BaseCtx,
+        to: &str,
+        amount: Amount,
+        fee_rate: Option<u64>,
+    ) -> Result<WalletOutput, BoxError> {
+        if !self.config.backend.can_broadcast() {
+            return Err("the Bitcoin canister can't broadcast transactions, transfers require an Esplora backend".into());
+        }
+        let caller = *ctx.caller();
+        let to = Address::from_str(to)?.require_network(self.config.network)?;
+        self.reserve(&caller, &to, amount, unix_ms())?;
+        let (wallet, tx, fee) = match self
+            .sign_transfer(ctx, &caller, &to, amount, fee_rate)
+            .await
+        {
+            Ok(res) => res,
+            Err(err) => {
+                self.release(&caller, amount);
+                return Err(err);
+            }
+        };
+        // the amount stays reserved if the broadcast fails, the transaction may have been sent
+        let txid = self.config.backend.send_transaction(ctx, &tx).await?;
+        log::info!(
+            caller = caller.to_text(),
+            to = to.to_string(),
+            amount = amount.to_sat(),
+            txid = txid.to_string();
+            "bitcoin_transfer",
+        );
+        Ok(WalletOutput {
+            address: wallet.address.to_string(),
+            txid: Some(txid.to_string()),
+            fee: Some(fee.to_string_in(Denomination::Bitcoin)),
+            ..Default::default()
+        })
+    }
+
+    /// Builds and signs a transaction that sends the amount from the caller's wallet.
+    async fn sign_transfer(
+        &self,
+        ctx: &BaseCtx,
+        caller: &Principal,
+        to: &Address,
+        amount: Amount,
+        fee_rate: Option<u64>,
+    ) -> Result<(BitcoinWallet, Transaction, Amount), BoxError> {
+        let wallet = self.wallet(ctx, caller).await?;
+        let fee_rate = match fee_rate {
+            Some(rate) => rate,
+            None => self.config.backend.fee_rate(ctx).await?,
+        };
+        let utxos: Vec<Utxo> = self
+            .config
+            .backend
+            .get_utxos(ctx, self.config.network, &wallet.address)
+            .await?
+            .into_iter()
+            .filter(|utxo| utxo.height.is_some())
+            .collect();
+        let mut psbt = wallet.build_psbt(&utxos, &[(to.clone(), amount)], fee_rate)?;
+        wallet.sign_psbt(ctx, &mut psbt).await?;
+        let fee = psbt.fee()?;
+        let tx = psbt.extract_tx()?;
+        Ok((wallet, tx, fee))
+    }
+
+    /// Checks the caller and the recipient of a transfer, and reserves the amount in the
+    /// daily limit of the caller.
+    fn reserve(
+        &self,
+        caller: &Principal,
+        to: &Address,
+        amount: Amount,
+        now_ms: u64,
+    ) -> Result<(), BoxError> {
+        if caller == &Principal::anonymous() {
+            return Err("anonymous caller can't send bitcoins".into());
+        }
+        if !self.config.recipients.is_empty() && !self.config.recipients.contains(&to.to_string()) {
+            return Err(format!("recipient {} is not allowed", to).into());
+        }
+        let limit = self
+            .config
+            .limit
+            .ok_or("transfers are disabled, no limit is set")?;
+        if amount > limit.per_call {
+            return Err(format!(
+                "amount exceeds the limit of {} per transfer",
+                limit
+                    .per_call
+                    .display_in(Denomination::Bitcoin)
+                    .show_denomination()
+            )
+            .into());
+        }
+
+        let day = now_ms / DAY_MS;
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, (d, _)| *d == day);
+        let total = spent
+            .get(caller)
+            .map_or(0, |(_, sats)| *sats)
+            .saturating_add(amount.to_sat());
+        if total > limit.per_caller_daily.to_sat() {
+            return Err(format!(
+                "amount exceeds the daily limit of {} per caller",
+                limit
+                    .per_caller_daily
+                    .display_in(Denomination::Bitcoin)
+                    .show_denomination()
+            )
+            .into());
+        }
+        spent.insert(*caller, (day, total));
+        Ok(())
+    }
+
+    /// Releases the amount reserved for a transfer that failed before the broadcast.
+    fn release(&self, caller: &Principal, amount: Amount) {
+        if let Some((_, sats)) = self.spent.lock().unwrap().get_mut(caller) {
+            *sats = sats.saturating_sub(amount.to_sat());
+        }
+    }
+}
+
+impl Tool<BaseCtx> for BitcoinWalletTool {
+    type Args = WalletArgs;
+    type Output = WalletOutput;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Manages the user's Bitcoin wallet of the agent on {}: gets its address and balance, and sends bitcoins to an address.",
+            self.config.network
+        )
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = match args.action {
+            WalletAction::Address => WalletOutput {
+                address: self.wallet(&ctx, ctx.caller()).await?.address.to_string(),
+                ..Default::default()
+            },
+            WalletAction::Balance => {
+                let wallet = self.wallet(&ctx, ctx.caller()).await?;
+                let utxos = self
+                    .config
+                    .backend
+                    .get_utxos(&ctx, self.config.network, &wallet.address)
+                    .await?;
+                let (confirmed, pending) =
+                    utxos
+                        .iter()
+                        .fold((0u64, 0u64), |(c, p), utxo| match utxo.height {
+                            Some(_) => (c + utxo.value, p),
+                            None => (c, p + utxo.value),
+                        });
+                WalletOutput {
+                    address: wallet.address.to_string(),
+                    balance: Some(Amount::from_sat(confirmed).to_string_in(Denomination::Bitcoin)),
+                    pending: Some(Amount::from_sat(pending).to_string_in(Denomination::Bitcoin)),
+                    ..Default::default()
+                }
+            }
+            WalletAction::Transfer => {
+                let to = args.to.as_deref().ok_or("\"to\" is required to transfer")?;
+                let amount = args
+                    .amount
+                    .as_deref()
+                    .ok_or("\"amount\" is required to transfer")?;
+                let amount = Amount::from_str_in(amount.trim(), Denomination::Bitcoin)?;
+                self.transfer(&ctx, to, amount, args.fee_rate).await?
+            }
+        };
+        Ok(ToolOutput::new(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        hashes::sha256,
+        secp256k1::{Message, Secp256k1, SecretKey},
+    };
+    use serde_json::json;
+
+    struct TestKeys(SecretKey);
+
+    impl TestKeys {
+        /// Derives the key of the derivation path.
+        fn key(&self, derivation_path: &[Vec<u8>]) -> SecretKey {
+            let mut data = self.0.secret_bytes().to_vec();
+            data.extend(derivation_path.concat());
+            SecretKey::from_slice(sha256::Hash::hash(&data).as_byte_array()).unwrap()
+        }
+    }
+
+    impl KeysFeatures for TestKeys {
+        async fn a256gcm_key(&self, _: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError> {
+            unimplemented!()
+        }
+
+        async fn ed25519_sign_message(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+        ) -> Result<[u8; 64], BoxError> {
+            unimplemented!()
+        }
+
+        async fn ed25519_verify(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+            _: &[u8],
+        ) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+
+        async fn ed25519_public_key(&self, _: Vec<Vec<u8>>) -> Result<[u8; 32], BoxError> {
+            unimplemented!()
+        }
+
+        async fn secp256k1_sign_message_bip340(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+        ) -> Result<[u8; 64], BoxError> {
+            unimplemented!()
+        }
+
+        async fn secp256k1_verify_bip340(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+            _: &[u8],
+        ) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+
+        async fn secp256k1_sign_message_ecdsa(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+        ) -> Result<[u8; 64], BoxError> {
+            unimplemented!()
+        }
+
+        async fn secp256k1_sign_digest_ecdsa(
+            &self,
+            derivation_path: Vec<Vec<u8>>,
+            message_hash: &[u8],
+        ) -> Result<[u8; 64], BoxError> {
+            let secp = Secp256k1::new();
+            let msg = Message::from_digest_slice(message_hash)?;
+            Ok(secp
+                .sign_ecdsa(&msg, &self.key(&derivation_path))
+                .serialize_compact())
+        }
+
+        async fn secp256k1_verify_ecdsa(
+            &self,
+            _: Vec<Vec<u8>>,
+            _: &[u8],
+            _: &[u8],
+        ) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+
+        async fn secp256k1_public_key(
+            &self,
+            derivation_path: Vec<Vec<u8>>,
+        ) -> Result<[u8; 33], BoxError> {
+            let secp = Secp256k1::new();
+            Ok(self.key(&derivation_path).public_key(&secp).serialize())
+        }
+    }
+
+    fn utxo(n: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([n; 32]), n as u32),
+            value,
+            height: Some(100),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_wallet() {
+        let keys = TestKeys(SecretKey::from_slice(&[7u8; 32]).unwrap());
+        let secp = Secp256k1::verification_only();
+        let to = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080")
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+        let utxos = vec![utxo(1, 10_000), utxo(2, 50_000), utxo(3, 20_000)];
+
+        for address_type in [AddressType::P2wpkh] {
+            let wallet = BitcoinWallet::load(
+                &keys,
+                Network::Regtest,
+                address_type,
+                vec![DEFAULT_DERIVATION_PATH.to_vec()],
+            )
+            .await
+            .unwrap();
+            assert!(
+                wallet
+                    .address
+                    .as_unchecked()
+                    .is_valid_for_network(Network::Regtest)
+            );
+
+            // largest-first: 50_000 covers the amount and the fee, with change
+            let amount = Amount::from_sat(40_000);
+            let mut psbt = wallet
+                .build_psbt(&utxos, &[(to.clone(), amount)], 2)
+                .unwrap();
+            assert_eq!(psbt.unsigned_tx.input.len(), 1);
+            assert_eq!(psbt.unsigned_tx.input[0].previous_output, utxos[1].outpoint);
+            assert_eq!(psbt.unsigned_tx.output.len(), 2);
+            assert_eq!(
+                psbt.unsigned_tx.output[1].script_pubkey,
+                wallet.address.script_pubkey()
+            );
+
+            wallet.sign_psbt(&keys, &mut psbt).await.unwrap();
+            let fee = psbt.fee().unwrap();
+            let tx = psbt.clone().extract_tx().unwrap();
+            // the estimated weight is an upper bound of the signed transaction's
+            assert!(fee.to_sat() >= tx.weight().to_vbytes_ceil() * 2);
+            assert!(fee.to_sat() <= (tx.weight().to_vbytes_ceil() + 2) * 2);
+
+            let prevouts = [psbt.inputs[0].witness_utxo.clone().unwrap()];
+            let mut cache = SighashCache::new(&tx);
+            let witness = &tx.input[0].witness;
+            match address_type {
+                AddressType::P2wpkh => {
+                    assert_eq!(witness.len(), 2);
+                    let sighash = cache
+                        .p2wpkh_signature_hash(
+                            0,
+                            &prevouts[0].script_pubkey,
+                            prevouts[0].value,
+                            EcdsaSighashType::All,
+                        )
+                        .unwrap();
+                    let sig = bitcoin::ecdsa::Signature::from_slice(&witness[0]).unwrap();
+                    let msg = Message::from_digest(sighash.to_byte_array());
+                    secp.verify_ecdsa(&msg, &sig.signature, &wallet.public_key.0)
+                        .unwrap();
+                }
+            }
+
+            // all the UTXOs are needed, the change below the dust limit goes to the fee
+            let psbt = wallet
+                .build_psbt(&utxos, &[(to.clone(), Amount::from_sat(79_500))], 1)
+                .unwrap();
+            assert_eq!(psbt.unsigned_tx.input.len(), 3);
+            assert_eq!(psbt.unsigned_tx.output.len(), 1);
+
+            assert!(
+                wallet
+                    .build_psbt(&utxos, &[(to.clone(), Amount::from_sat(80_000))], 1)
+                    .unwrap_err()
+                    .to_string()
+                    .contains("insufficient funds")
+            );
+            assert!(
+                wallet
+                    .build_psbt(&utxos, &[(to.clone(), Amount::from_sat(100))], 1)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_transfer_limits() {
+        let to = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080")
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+        let user = Principal::management_canister();
+        let config = BitcoinConfig::new(Network::Regtest);
+        let tool = BitcoinWalletTool::new(config.clone());
+        assert_eq!(
+            tool.reserve(&user, &to, Amount::from_sat(1000), 0)
+                .unwrap_err()
+                .to_string(),
+            "transfers are disabled, no limit is set"
+        );
+
+        let tool = BitcoinWalletTool::new(config.with_limit(BitcoinLimit {
+            per_call: Amount::from_sat(50_000),
+            per_caller_daily: Amount::from_sat(80_000),
+        }));
+        assert_eq!(
+            tool.reserve(&Principal::anonymous(), &to, Amount::from_sat(1000), 0)
+                .unwrap_err()
+                .to_string(),
+            "anonymous caller can't send bitcoins"
+        );
+        assert!(
+            tool.reserve(&user, &to, Amount::from_sat(60_000), 0)
+                .unwrap_err()
+                .to_string()
+                .contains("per transfer")
+        );
+        tool.reserve(&user, &to, Amount::from_sat(50_000), 0)
+            .unwrap();
+        assert!(
+            tool.reserve(&user, &to, Amount::from_sat(40_000), 0)
+                .unwrap_err()
+                .to_string()
+                .contains("daily limit")
+        );
+        // released after a failed transfer, and reset the next day
+        tool.release(&user, Amount::from_sat(50_000));
+        tool.reserve(&user, &to, Amount::from_sat(40_000), 0)
+            .unwrap();
+        tool.reserve(&user, &to, Amount::from_sat(50_000), DAY_MS)
+            .unwrap();
+
+        let tool = BitcoinWalletTool::new(
+            BitcoinConfig::new(Network::Regtest)
+                .with_limit(BitcoinLimit {
+                    per_call: Amount::from_sat(50_000),
+                    per_caller_daily: Amount::from_sat(80_000),
+                })
+                .with_recipients(BTreeSet::from([
+                    "bcrt1q6rhpng9evdsfnn833a4f4vej0asu6dk5srld6x".to_string(),
+                ])),
+        );
+        assert_eq!(
+            tool.reserve(&user, &to, Amount::from_sat(1000), 0)
+                .unwrap_err()
+                .to_string(),
+            format!("recipient {} is not allowed", to)
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_caller_wallets() {
+        let keys = TestKeys(SecretKey::from_slice(&[7u8; 32]).unwrap());
+        let tool = BitcoinWalletTool::new(BitcoinConfig::new(Network::Regtest));
+        let alice = tool
+            .wallet(&keys, &Principal::management_canister())
+            .await
+            .unwrap();
+        let bob = tool.wallet(&keys, &Principal::anonymous()).await.unwrap();
+        assert_ne!(alice.address, bob.address);
+        assert_eq!(
+            alice.derivation_path,
+            vec![
+                DEFAULT_DERIVATION_PATH.to_vec(),
+                Principal::management_canister().as_slice().to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        let args = WalletArgs::from_value(json!({
+            "action": "transfer",
+            "to": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            "amount": "0.0015",
+        }))
+        .unwrap();
+        assert_eq!(args.action, WalletAction::Transfer);
+        assert_eq!(
+            Amount::from_str_in(args.amount.as_deref().unwrap(), Denomination::Bitcoin).unwrap(),
+            Amount::from_sat(150_000)
+        );
+
+        let args = WalletArgs::from_value(json!({"action": "address"})).unwrap();
+        assert_eq!(args.action, WalletAction::Address);
+        assert!(WalletArgs::from_value(json!({"action": "mint"})).is_err());
+    }
+}
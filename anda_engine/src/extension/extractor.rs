@@ -255,7 +255,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use anda_core::{AgentContext, AgentInput, ToolInput};
+    use anda_core::{AgentContext, AgentInput, ToolInput, TraceStep};
 
     use super::*;
     use crate::{engine::EngineBuilder, model::Model};
@@ -333,6 +333,29 @@ mod tests {
             .await
             .unwrap();
         println!("test_with_ctx: {:?}", res);
+        let trace = res.trace.unwrap();
+        assert_eq!(trace.len(), 3);
+        assert!(matches!(
+            &trace[0],
+            TraceStep::Completion {
+                round: 1,
+                tool_calls: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &trace[1],
+            TraceStep::ToolCall(call)
+                if call.name == tool_name && call.error.is_none() && call.result_bytes > 0
+        ));
+        assert!(matches!(
+            &trace[2],
+            TraceStep::Completion {
+                round: 2,
+                tool_calls: 0,
+                ..
+            }
+        ));
         // assert_eq!(
         //     res.tool_calls.as_ref().unwrap()[0].result.unwrap().as_str(),
         //     Some(r#"{"name":"Anda","age":null}"#)
@@ -27,9 +27,7 @@
 //!     .build("default_agent".to_string())?;
 //! ```
 
-use anda_core::{
-    BoxError, FunctionDefinition, HttpFeatures, Resource, Tool, ToolOutput, gen_schema_for,
-};
+use anda_core::{BoxError, FunctionDefinition, HttpFeatures, Resource, Tool, ToolArgs, ToolOutput};
 use http::header;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -39,9 +37,10 @@ use url::Url;
 use crate::context::BaseCtx;
 
 /// Arguments for Google search query
-#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
 pub struct SearchArgs {
     /// The search query string
+    #[validate(length(min = 1, max = 2048))]
     pub query: String,
 }
 
@@ -87,7 +86,7 @@ impl GoogleSearchTool {
     /// * `search_engine_id` - Custom Search Engine ID
     /// * `result_number` - Optional number of results to return (defaults to 5)
     pub fn new(api_key: String, search_engine_id: String, result_number: Option<u8>) -> Self {
-        let schema = gen_schema_for::<SearchArgs>();
+        let schema = SearchArgs::parameters();
 
         GoogleSearchTool {
             api_key,
@@ -182,6 +181,10 @@ impl Tool<BaseCtx> for GoogleSearchTool {
         }
     }
 
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
     /// Executes the search operation
     ///
     /// # Arguments
@@ -205,6 +208,30 @@ impl Tool<BaseCtx> for GoogleSearchTool {
 mod tests {
     use super::*;
     use crate::{engine::EngineBuilder, model::Model};
+    use serde_json::json;
+
+    #[test]
+    fn test_search_args() {
+        let schema = SearchArgs::parameters();
+        assert_eq!(
+            schema["properties"]["query"],
+            json!({
+                "description": "The search query string",
+                "type": "string",
+                "minLength": 1,
+                "maxLength": 2048,
+            })
+        );
+
+        let args = SearchArgs::from_value(json!({"query": "anda"})).unwrap();
+        assert_eq!(args.query, "anda");
+        let err = SearchArgs::from_value(json!({"query": ""})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid args: length of `query` must be at least 1, got 0"
+        );
+        assert!(SearchArgs::from_value(json!({"query": 1})).is_err());
+    }
 
     #[tokio::test]
     #[ignore]
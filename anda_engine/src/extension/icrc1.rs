@@ -0,0 +1,1085 @@
+//! ICRC-1 / ICRC-2 Ledger Extension for Anda Engine
+//!
+//! This module provides tools to query balances and to transfer, approve and transfer from
+//! the tokens of any ICRC ledger on the Internet Computer, through the
+//! [`CanisterCaller`] of the context.
+//!
+//! # Features
+//! - `icrc1_balance_of`: queries the balance of an account;
+//! - `icrc1_transfer`: transfers tokens from the caller's subaccount of the engine;
+//! - `icrc2_approve`: approves a spender to transfer tokens from the caller's subaccount;
+//! - `icrc2_transfer_from`: transfers tokens that the caller approved the engine to spend.
+//!
+//! The amounts are decimal strings in whole tokens, e.g. "1.5", converted with the decimals of
+//! the ledger, so that the models don't handle the smallest units of the tokens. The accounts
+//! are principals, or accounts with a subaccount in the ICRC-1 textual encoding.
+//! The metadata of the ledgers (symbol, decimals and fee) are queried once and cached.
+//!
+//! Each caller has its own subaccount of the engine's account, derived from its principal, so
+//! that a caller can only transfer and approve its own tokens; a subaccount shared by all
+//! callers can be set with [`IcrcLedgers::with_subaccount`] for trusted callers. Transfers from
+//! approvals are restricted to the caller's accounts and its subaccount of the engine.
+//! Anonymous callers can't call the update tools.
+//!
+//! The update calls are checked against the capabilities of the context and the engine's
+//! canister policy, and are limited by [`IcrcLedgers::with_limit`]: a ledger without a limit
+//! can't be updated. The ledgers can be restricted with [`IcrcLedgers::with_ledgers`], and the
+//! recipients and spenders with [`IcrcLedgers::with_recipients`]. The transactions carry their
+//! creation time, so that the ledger deduplicates a transaction submitted twice.
+//!
+//! # Usage
+//! ```rust,ignore
+//! let ledgers = IcrcLedgers::new()
+//!     .with_ledgers(BTreeSet::from([ICP_LEDGER]))
+//!     .with_limit(
+//!         ICP_LEDGER,
+//!         TransferLimit {
+//!             per_call: Nat::from(100_000_000u64),
+//!             per_caller_daily: Nat::from(1_000_000_000u64),
+//!         },
+//!     );
+//! let engine = Engine::builder()
+//!     .register_tools(ledgers.tools()?)?
+//!     .register_agent(my_agent)?
+//!     .build(default_agent)
+//!     .await?;
+//! ```
+
+use anda_core::{
+    BoxError, CanisterCaller, FunctionDefinition, Resource, StateFeatures, Tool, ToolArgs,
+    ToolOutput, ToolSet,
+};
+use candid::{Nat, Principal};
+use icrc_ledger_types::{
+    icrc1::{
+        account::{Account, principal_to_subaccount},
+        transfer::{Memo, TransferArg, TransferError},
+    },
+    icrc2::{
+        approve::{ApproveArgs as LedgerApproveArgs, ApproveError},
+        transfer_from::{TransferFromArgs as LedgerTransferFromArgs, TransferFromError},
+    },
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
+use structured_logger::unix_ms;
+
+use crate::context::BaseCtx;
+
+/// The maximum size of a memo in bytes.
+const MAX_MEMO_LEN: usize = 32;
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// Arguments to query the balance of an account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct BalanceOfArgs {
+    /// Ledger canister ID of the token, e.g. "ryjl3-tyaaa-aaaaa-aaaba-cai" for ICP
+    #[validate(length(min = 1, max = 64))]
+    pub ledger: String,
+    /// Account to query: a principal, e.g. "77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe", or an account with a subaccount in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub account: String,
+}
+
+/// Arguments to transfer tokens from the caller's subaccount of the engine
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct TransferArgs {
+    /// Ledger canister ID of the token, e.g. "ryjl3-tyaaa-aaaaa-aaaba-cai" for ICP
+    #[validate(length(min = 1, max = 64))]
+    pub ledger: String,
+    /// Account to receive the tokens: a principal or an account in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub to: String,
+    /// Amount in whole tokens as a decimal string, e.g. "1.5"; the fee is paid on top of it
+    #[validate(length(min = 1, max = 64))]
+    pub amount: String,
+    /// Optional memo of the transaction, at most 32 bytes
+    #[validate(length(max = 32))]
+    pub memo: Option<String>,
+}
+
+/// Arguments to approve a spender to transfer tokens from the caller's subaccount of the engine
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct ApproveArgs {
+    /// Ledger canister ID of the token, e.g. "ryjl3-tyaaa-aaaaa-aaaba-cai" for ICP
+    #[validate(length(min = 1, max = 64))]
+    pub ledger: String,
+    /// Account allowed to spend the tokens: a principal or an account in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub spender: String,
+    /// Allowance in whole tokens as a decimal string, e.g. "10", replacing the previous one
+    #[validate(length(min = 1, max = 64))]
+    pub amount: String,
+    /// Optional lifetime of the allowance in seconds, it never expires if not set
+    pub expires_in_secs: Option<u64>,
+    /// Optional memo of the transaction, at most 32 bytes
+    #[validate(length(max = 32))]
+    pub memo: Option<String>,
+}
+
+/// Arguments to transfer tokens that the caller approved the engine to spend
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct TransferFromArgs {
+    /// Ledger canister ID of the token, e.g. "ryjl3-tyaaa-aaaaa-aaaba-cai" for ICP
+    #[validate(length(min = 1, max = 64))]
+    pub ledger: String,
+    /// Account of the caller to transfer the tokens from, that approved the engine as spender
+    #[validate(length(min = 1, max = 256))]
+    pub from: String,
+    /// Account to receive the tokens: a principal or an account in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub to: String,
+    /// Amount in whole tokens as a decimal string, e.g. "1.5"; the fee is paid on top of it
+    #[validate(length(min = 1, max = 64))]
+    pub amount: String,
+    /// Optional memo of the transaction, at most 32 bytes
+    #[validate(length(max = 32))]
+    pub memo: Option<String>,
+}
+
+/// The balance of an account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct Balance {
+    /// Ledger canister ID of the token
+    pub ledger: String,
+    /// Symbol of the token
+    pub symbol: String,
+    /// Balance in whole tokens
+    pub balance: String,
+}
+
+/// The receipt of a transfer or an approval
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LedgerReceipt {
+    /// Ledger canister ID of the token
+    pub ledger: String,
+    /// Symbol of the token
+    pub symbol: String,
+    /// Amount transferred or approved in whole tokens
+    pub amount: String,
+    /// Fee paid in whole tokens
+    pub fee: String,
+    /// Index of the block of the transaction in the ledger
+    pub block_index: String,
+}
+
+/// The metadata of a ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerInfo {
+    pub symbol: String,
+    pub decimals: u8,
+    pub fee: Nat,
+}
+
+impl LedgerInfo {
+    fn receipt(&self, ledger: Principal, amount: &Nat, block_index: Nat) -> LedgerReceipt {
+        LedgerReceipt {
+            ledger: ledger.to_text(),
+            symbol: self.symbol.clone(),
+            amount: format_amount(amount, self.decimals),
+            fee: format_amount(&self.fee, self.decimals),
+            block_index: block_index.0.to_string(),
+        }
+    }
+}
+
+/// The limit of the transfers and approvals on a ledger, in the smallest unit of the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferLimit {
+    /// The maximum amount of a transfer or an approval.
+    pub per_call: Nat,
+    /// The maximum total amount of the transfers and approvals of a caller in a UTC day.
+    pub per_caller_daily: Nat,
+}
+
+/// ICRC ledgers that the tools call, with the metadata of the ledgers called so far.
+#[derive(Debug, Default)]
+pub struct IcrcLedgers {
+    /// Ledgers that the tools may call, all if empty.
+    ledgers: BTreeSet<Principal>,
+    /// Subaccount of the engine's account shared by all callers, instead of their own.
+    subaccount: Option<[u8; 32]>,
+    /// Limits of the update calls by ledger, a ledger without a limit can't be updated.
+    limits: BTreeMap<Principal, TransferLimit>,
+    /// Owners of the accounts that may receive or spend the tokens, besides the caller,
+    /// all if empty.
+    recipients: BTreeSet<Principal>,
+    /// Amounts transferred and approved by ledger and caller, with their day.
+    spent: Mutex<BTreeMap<(Principal, Principal), (u64, Nat)>>,
+    info: RwLock<BTreeMap<Principal, LedgerInfo>>,
+}
+
+impl IcrcLedgers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the tools to the ledgers.
+    pub fn with_ledgers(mut self, ledgers: BTreeSet<Principal>) -> Self {
+        self.ledgers = ledgers;
+        self
+    }
+
+    /// Transfers and approves the tokens of all callers from the subaccount of the engine's
+    /// account, instead of their own subaccounts. Only for engines whose callers are trusted.
+    pub fn with_subaccount(mut self, subaccount: [u8; 32]) -> Self {
+        self.subaccount = Some(subaccount);
+        self
+    }
+
+    /// Sets the limit of the transfers and approvals on the ledger.
+    pub fn with_limit(mut self, ledger: Principal, limit: TransferLimit) -> Self {
+        self.limits.insert(ledger, limit);
+        self
+    }
+
+    /// Restricts the recipients of the transfers and the spenders of the approvals to the
+    /// accounts of the owners, besides the caller's own accounts.
+    pub fn with_recipients(mut self, recipients: BTreeSet<Principal>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    /// Returns the ledger tools, sharing the ledgers.
+    pub fn tools(self) -> Result<ToolSet<BaseCtx>, BoxError> {
+        let ledgers = Arc::new(self);
+        let mut tools = ToolSet::new();
+        tools.add(BalanceOfTool::new(ledgers.clone()))?;
+        tools.add(TransferTool::new(ledgers.clone()))?;
+        tools.add(ApproveTool::new(ledgers.clone()))?;
+        tools.add(TransferFromTool::new(ledgers))?;
+        Ok(tools)
+    }
+
+    /// Returns the metadata of the ledger, queried once.
+    pub async fn info(
+        &self,
+        ctx: &impl CanisterCaller,
+        ledger: &Principal,
+    ) -> Result<LedgerInfo, BoxError> {
+        let cached = self.info.read().unwrap().get(ledger).cloned();
+        if let Some(info) = cached {
+            return Ok(info);
+        }
+
+        let (symbol, decimals, fee) = futures::try_join!(
+            ctx.canister_query::<_, String>(ledger, "icrc1_symbol", ()),
+            ctx.canister_query::<_, u8>(ledger, "icrc1_decimals", ()),
+            ctx.canister_query::<_, Nat>(ledger, "icrc1_fee", ()),
+        )?;
+        let info = LedgerInfo {
+            symbol,
+            decimals,
+            fee,
+        };
+        self.info.write().unwrap().insert(*ledger, info.clone());
+        Ok(info)
+    }
+
+    /// Queries the balance of an account.
+    pub async fn balance_of(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: BalanceOfArgs,
+    ) -> Result<Balance, BoxError> {
+        let ledger = self.ledger(&args.ledger)?;
+        let account = parse_account(&args.account)?;
+        let (info, balance) = futures::try_join!(
+            self.info(ctx, &ledger),
+            ctx.canister_query::<_, Nat>(&ledger, "icrc1_balance_of", (account,)),
+        )?;
+        Ok(Balance {
+            ledger: ledger.to_text(),
+            symbol: info.symbol,
+            balance: format_amount(&balance, info.decimals),
+        })
+    }
+
+    /// Transfers tokens from the caller's subaccount of the engine.
+    pub async fn transfer(
+        &self,
+        ctx: &impl CanisterCaller,
+        caller: &Principal,
+        args: TransferArgs,
+    ) -> Result<LedgerReceipt, BoxError> {
+        let ledger = self.ledger(&args.ledger)?;
+        let to = parse_account(&args.to)?;
+        let memo = parse_memo(args.memo)?;
+        let info = self.info(ctx, &ledger).await?;
+        let amount = parse_amount(&args.amount, info.decimals)?;
+        self.reserve(&ledger, &info, caller, &to, &amount)?;
+        let res: Result<Nat, TransferError> = ctx
+            .canister_update(
+                &ledger,
+                "icrc1_transfer",
+                (TransferArg {
+                    from_subaccount: self.source_subaccount(caller),
+                    to,
+                    fee: None,
+                    created_at_time: Some(created_at_time()),
+                    memo,
+                    amount: amount.clone(),
+                },),
+            )
+            .await?;
+        let block_index = res.map_err(|err| {
+            self.release(&ledger, caller, &amount);
+            format!("icrc1_transfer failed: {:?}", err)
+        })?;
+        log::info!(
+            ledger = args.ledger,
+            caller = caller.to_text(),
+            to = args.to,
+            amount = args.amount;
+            "icrc1_transfer",
+        );
+        Ok(info.receipt(ledger, &amount, block_index))
+    }
+
+    /// Approves a spender to transfer tokens from the caller's subaccount of the engine.
+    pub async fn approve(
+        &self,
+        ctx: &impl CanisterCaller,
+        caller: &Principal,
+        args: ApproveArgs,
+    ) -> Result<LedgerReceipt, BoxError> {
+        let ledger = self.ledger(&args.ledger)?;
+        let spender = parse_account(&args.spender)?;
+        let memo = parse_memo(args.memo)?;
+        let info = self.info(ctx, &ledger).await?;
+        let amount = parse_amount(&args.amount, info.decimals)?;
+        self.reserve(&ledger, &info, caller, &spender, &amount)?;
+        let expires_at = args
+            .expires_in_secs
+            .map(|secs| unix_ms().saturating_add(secs.saturating_mul(1000)) * 1_000_000);
+        let res: Result<Nat, ApproveError> = ctx
+            .canister_update(
+                &ledger,
+                "icrc2_approve",
+                (LedgerApproveArgs {
+                    from_subaccount: self.source_subaccount(caller),
+                    spender,
+                    amount: amount.clone(),
+                    expected_allowance: None,
+                    expires_at,
+                    fee: None,
+                    memo,
+                    created_at_time: Some(created_at_time()),
+                },),
+            )
+            .await?;
+        let block_index = res.map_err(|err| {
+            self.release(&ledger, caller, &amount);
+            format!("icrc2_approve failed: {:?}", err)
+        })?;
+        log::info!(
+            ledger = args.ledger,
+            caller = caller.to_text(),
+            spender = args.spender,
+            amount = args.amount;
+            "icrc2_approve",
+        );
+        Ok(info.receipt(ledger, &amount, block_index))
+    }
+
+    /// Transfers tokens that the caller approved the engine to spend, from the caller's
+    /// account or its subaccount of the engine.
+    pub async fn transfer_from(
+        &self,
+        ctx: &impl CanisterCaller,
+        engine: &Principal,
+        caller: &Principal,
+        args: TransferFromArgs,
+    ) -> Result<LedgerReceipt, BoxError> {
+        let ledger = self.ledger(&args.ledger)?;
+        let from = parse_account(&args.from)?;
+        let to = parse_account(&args.to)?;
+        let memo = parse_memo(args.memo)?;
+        let caller_subaccount = Account {
+            owner: *engine,
+            subaccount: Some(principal_to_subaccount(*caller)),
+        };
+        if from.owner != *caller && from != caller_subaccount {
+            return Err(format!(
+                "account {} is not an account of the caller {}",
+                args.from,
+                caller.to_text()
+            )
+            .into());
+        }
+        let info = self.info(ctx, &ledger).await?;
+        let amount = parse_amount(&args.amount, info.decimals)?;
+        self.reserve(&ledger, &info, caller, &to, &amount)?;
+        let res: Result<Nat, TransferFromError> = ctx
+            .canister_update(
+                &ledger,
+                "icrc2_transfer_from",
+                (LedgerTransferFromArgs {
+                    spender_subaccount: self.subaccount,
+                    from,
+                    to,
+                    amount: amount.clone(),
+                    fee: None,
+                    memo,
+                    created_at_time: Some(created_at_time()),
+                },),
+            )
+            .await?;
+        let block_index = res.map_err(|err| {
+            self.release(&ledger, caller, &amount);
+            format!("icrc2_transfer_from failed: {:?}", err)
+        })?;
+        log::info!(
+            ledger = args.ledger,
+            caller = caller.to_text(),
+            from = args.from,
+            to = args.to,
+            amount = args.amount;
+            "icrc2_transfer_from",
+        );
+        Ok(info.receipt(ledger, &amount, block_index))
+    }
+
+    /// Returns the subaccount of the engine's account that the caller's tokens are in.
+    fn source_subaccount(&self, caller: &Principal) -> Option<[u8; 32]> {
+        Some(
+            self.subaccount
+                .unwrap_or_else(|| principal_to_subaccount(*caller)),
+        )
+    }
+
+    /// Checks the caller and the recipient of an update call, and reserves the amount in the
+    /// daily limit of the caller.
+    fn reserve(
+        &self,
+        ledger: &Principal,
+        info: &LedgerInfo,
+        caller: &Principal,
+        recipient: &Account,
+        amount: &Nat,
+    ) -> Result<(), BoxError> {
+        if caller == &Principal::anonymous() {
+            return Err("anonymous caller can't transfer or approve tokens".into());
+        }
+        if !self.recipients.is_empty()
+            && recipient.owner != *caller
+            && !self.recipients.contains(&recipient.owner)
+        {
+            return Err(format!("recipient {} is not allowed", recipient.owner.to_text()).into());
+        }
+        let limit = self.limits.get(ledger).ok_or_else(|| {
+            format!(
+                "ledger {} has no transfer limit, it can't be updated",
+                ledger.to_text()
+            )
+        })?;
+        if amount > &limit.per_call {
+            return Err(format!(
+                "amount exceeds the limit of {} {} per call",
+                format_amount(&limit.per_call, info.decimals),
+                info.symbol
+            )
+            .into());
+        }
+
+        let day = unix_ms() / DAY_MS;
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, (d, _)| *d == day);
+        let total = spent
+            .get(&(*ledger, *caller))
+            .map(|(_, spent)| spent.clone() + amount.clone())
+            .unwrap_or_else(|| amount.clone());
+        if total > limit.per_caller_daily {
+            return Err(format!(
+                "amount exceeds the daily limit of {} {} per caller",
+                format_amount(&limit.per_caller_daily, info.decimals),
+                info.symbol
+            )
+            .into());
+        }
+        spent.insert((*ledger, *caller), (day, total));
+        Ok(())
+    }
+
+    /// Releases the amount reserved for an update call that the ledger rejected.
+    fn release(&self, ledger: &Principal, caller: &Principal, amount: &Nat) {
+        if let Some((_, spent)) = self.spent.lock().unwrap().get_mut(&(*ledger, *caller))
+            && *spent >= *amount
+        {
+            *spent -= amount.clone();
+        }
+    }
+
+    fn ledger(&self, ledger: &str) -> Result<Principal, BoxError> {
+        let ledger =
+            Principal::from_text(ledger).map_err(|_| format!("invalid ledger {:?}", ledger))?;
+        if !self.ledgers.is_empty() && !self.ledgers.contains(&ledger) {
+            return Err(format!("ledger {} is not allowed", ledger.to_text()).into());
+        }
+        Ok(ledger)
+    }
+}
+
+/// Returns the creation time of a transaction, in nanoseconds.
+fn created_at_time() -> u64 {
+    unix_ms() * 1_000_000
+}
+
+/// Parses an account, a principal or an account in the ICRC-1 textual encoding.
+pub fn parse_account(account: &str) -> Result<Account, BoxError> {
+    Account::from_str(account.trim())
+        .map_err(|err| format!("invalid account {:?}: {:?}", account, err).into())
+}
+
+fn parse_memo(memo: Option<String>) -> Result<Option<Memo>, BoxError> {
+    match memo {
+        Some(memo) if memo.len() > MAX_MEMO_LEN => {
+            Err(format!("memo must be at most {} bytes", MAX_MEMO_LEN).into())
+        }
+        Some(memo) => Ok(Some(Memo::from(memo.into_bytes()))),
+        None => Ok(None),
+    }
+}
+
+/// Converts an amount in whole tokens, e.g. "1.5", to the smallest unit of the token.
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<Nat, BoxError> {
+    let digits = amount.trim().replace('_', "");
+    let (int, frac) = digits.split_once('.').unwrap_or((&digits, ""));
+    if (int.is_empty() && frac.is_empty())
+        || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return Err(format!("invalid amount {:?}", amount).into());
+    }
+    let frac = frac.trim_end_matches('0');
+    let decimals = decimals as usize;
+    if frac.len() > decimals {
+        return Err(format!("amount {:?} has more than {} decimals", amount, decimals).into());
+    }
+    let units = format!("{}{}{}", int, frac, "0".repeat(decimals - frac.len()));
+    match units.trim_start_matches('0') {
+        "" => Ok(Nat::from(0u64)),
+        units => Ok(units.parse::<Nat>()?),
+    }
+}
+
+/// Converts an amount in the smallest unit of the token to whole tokens, e.g. "1.5".
+pub fn format_amount(amount: &Nat, decimals: u8) -> String {
+    let units = amount.0.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return units;
+    }
+    let units = format!("{:0>width$}", units, width = decimals + 1);
+    let (int, frac) = units.split_at(units.len() - decimals);
+    match frac.trim_end_matches('0') {
+        "" => int.to_string(),
+        frac => format!("{}.{}", int, frac),
+    }
+}
+
+/// ICRC-1 balance tool.
+#[derive(Debug, Clone)]
+pub struct BalanceOfTool {
+    ledgers: Arc<IcrcLedgers>,
+    schema: Value,
+}
+
+impl BalanceOfTool {
+    pub const NAME: &'static str = "icrc1_balance_of";
+
+    pub fn new(ledgers: Arc<IcrcLedgers>) -> Self {
+        Self {
+            ledgers,
+            schema: BalanceOfArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for BalanceOfTool {
+    type Args = BalanceOfArgs;
+    type Output = Balance;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Queries the balance of an account on an ICRC-1 token ledger of the Internet Computer."
+            .to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.ledgers.balance_of(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-1 transfer tool.
+#[derive(Debug, Clone)]
+pub struct TransferTool {
+    ledgers: Arc<IcrcLedgers>,
+    schema: Value,
+}
+
+impl TransferTool {
+    pub const NAME: &'static str = "icrc1_transfer";
+
+    pub fn new(ledgers: Arc<IcrcLedgers>) -> Self {
+        Self {
+            ledgers,
+            schema: TransferArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for TransferTool {
+    type Args = TransferArgs;
+    type Output = LedgerReceipt;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Transfers tokens from the user's account of the agent to another account on an ICRC-1 token ledger of the Internet Computer.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.ledgers.transfer(&ctx, ctx.caller(), args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-2 approve tool.
+#[derive(Debug, Clone)]
+pub struct ApproveTool {
+    ledgers: Arc<IcrcLedgers>,
+    schema: Value,
+}
+
+impl ApproveTool {
+    pub const NAME: &'static str = "icrc2_approve";
+
+    pub fn new(ledgers: Arc<IcrcLedgers>) -> Self {
+        Self {
+            ledgers,
+            schema: ApproveArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for ApproveTool {
+    type Args = ApproveArgs;
+    type Output = LedgerReceipt;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Approves an account to spend tokens from the user's account of the agent on an ICRC-2 token ledger of the Internet Computer.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.ledgers.approve(&ctx, ctx.caller(), args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-2 transfer from tool.
+#[derive(Debug, Clone)]
+pub struct TransferFromTool {
+    ledgers: Arc<IcrcLedgers>,
+    schema: Value,
+}
+
+impl TransferFromTool {
+    pub const NAME: &'static str = "icrc2_transfer_from";
+
+    pub fn new(ledgers: Arc<IcrcLedgers>) -> Self {
+        Self {
+            ledgers,
+            schema: TransferFromArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for TransferFromTool {
+    type Args = TransferFromArgs;
+    type Output = LedgerReceipt;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Transfers tokens that the user approved the agent to spend on an ICRC-2 token ledger of the Internet Computer.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self
+            .ledgers
+            .transfer_from(&ctx, ctx.engine_id(), ctx.caller(), args)
+            .await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::mock::MockCanisterCaller;
+    use candid::{decode_args, encode_args, encode_one};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_amounts() {
+        assert_eq!(parse_amount("1.5", 8).unwrap(), Nat::from(150_000_000u64));
+        assert_eq!(parse_amount("0.00000001", 8).unwrap(), Nat::from(1u64));
+        assert_eq!(parse_amount("1_000", 0).unwrap(), Nat::from(1000u64));
+        assert_eq!(parse_amount(".50", 2).unwrap(), Nat::from(50u64));
+        assert_eq!(parse_amount("0", 8).unwrap(), Nat::from(0u64));
+        assert!(parse_amount("0.000000001", 8).is_err());
+        assert!(parse_amount("-1", 8).is_err());
+        assert!(parse_amount("1e8", 8).is_err());
+        assert!(parse_amount(".", 8).is_err());
+
+        assert_eq!(format_amount(&Nat::from(150_000_000u64), 8), "1.5");
+        assert_eq!(format_amount(&Nat::from(1u64), 8), "0.00000001");
+        assert_eq!(format_amount(&Nat::from(100_000_000u64), 8), "1");
+        assert_eq!(format_amount(&Nat::from(1000u64), 0), "1000");
+        assert_eq!(format_amount(&Nat::from(0u64), 8), "0");
+    }
+
+    #[test]
+    fn test_args() {
+        let args = TransferArgs::from_value(json!({
+            "ledger": "ryjl3-tyaaa-aaaaa-aaaba-cai",
+            "to": "77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe",
+            "amount": "1.5",
+        }))
+        .unwrap();
+        assert_eq!(args.memo, None);
+        assert!(parse_account(&args.to).is_ok());
+        assert!(parse_account("not an account").is_err());
+
+        let err = TransferArgs::from_value(json!({
+            "ledger": "ryjl3-tyaaa-aaaaa-aaaba-cai",
+            "to": "77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe",
+            "amount": "",
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid args: length of `amount` must be at least 1, got 0"
+        );
+
+        let tools = IcrcLedgers::new().tools().unwrap();
+        for name in [
+            BalanceOfTool::NAME,
+            TransferTool::NAME,
+            ApproveTool::NAME,
+            TransferFromTool::NAME,
+        ] {
+            assert!(tools.contains(name));
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icrc_ledgers() {
+        let ledger = Principal::from_text("druyg-tyaaa-aaaaq-aactq-cai").unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        let caller = MockCanisterCaller::new(move |canister, method, args| {
+            assert_eq!(canister, &ledger);
+            counter.fetch_add(1, Ordering::SeqCst);
+            match method {
+                "icrc1_symbol" => encode_one("PANDA").unwrap(),
+                "icrc1_decimals" => encode_one(8u8).unwrap(),
+                "icrc1_fee" => encode_one(Nat::from(10_000u64)).unwrap(),
+                "icrc1_balance_of" => encode_one(Nat::from(250_000_000u64)).unwrap(),
+                "icrc1_transfer" => {
+                    let (arg,): (TransferArg,) = decode_args(&args).unwrap();
+                    assert_eq!(arg.amount, Nat::from(150_000_000u64));
+                    assert_eq!(arg.from_subaccount, Some([1u8; 32]));
+                    assert_eq!(arg.memo, Some(Memo::from(b"thanks".to_vec())));
+                    assert!(arg.created_at_time.is_some());
+                    let res: Result<Nat, TransferError> = Ok(Nat::from(42u64));
+                    encode_args((res,)).unwrap()
+                }
+                "icrc2_approve" => {
+                    let (arg,): (LedgerApproveArgs,) = decode_args(&args).unwrap();
+                    assert_eq!(arg.amount, Nat::from(900_000_000u64));
+                    assert!(arg.expires_at.is_some());
+                    let res: Result<Nat, ApproveError> = Err(ApproveError::TemporarilyUnavailable);
+                    encode_args((res,)).unwrap()
+                }
+                _ => panic!("unexpected method {}", method),
+            }
+        });
+
+        let engine = Principal::from_text("aaaaa-aa").unwrap();
+        let user =
+            Principal::from_text("77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe")
+                .unwrap();
+        let ledgers = IcrcLedgers::new()
+            .with_ledgers(BTreeSet::from([ledger]))
+            .with_subaccount([1u8; 32])
+            .with_limit(
+                ledger,
+                TransferLimit {
+                    per_call: Nat::from(1_000_000_000u64),
+                    per_caller_daily: Nat::from(1_100_000_000u64),
+                },
+            );
+        let balance = ledgers
+            .balance_of(
+                &caller,
+                BalanceOfArgs {
+                    ledger: ledger.to_text(),
+                    account: Principal::anonymous().to_text(),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(balance.symbol, "PANDA");
+        assert_eq!(balance.balance, "2.5");
+        assert_eq!(queries.load(Ordering::SeqCst), 4);
+
+        let receipt = ledgers
+            .transfer(
+                &caller,
+                &user,
+                TransferArgs {
+                    ledger: ledger.to_text(),
+                    to: Principal::anonymous().to_text(),
+                    amount: "1.5".to_string(),
+                    memo: Some("thanks".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            receipt,
+            LedgerReceipt {
+                ledger: ledger.to_text(),
+                symbol: "PANDA".to_string(),
+                amount: "1.5".to_string(),
+                fee: "0.0001".to_string(),
+                block_index: "42".to_string(),
+            }
+        );
+        // the metadata are cached
+        assert_eq!(queries.load(Ordering::SeqCst), 5);
+
+        let err = ledgers
+            .transfer(
+                &caller,
+                &Principal::anonymous(),
+                TransferArgs {
+                    ledger: ledger.to_text(),
+                    to: user.to_text(),
+                    amount: "1".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "anonymous caller can't transfer or approve tokens"
+        );
+
+        // the rejected approval is released from the daily limit
+        let err = ledgers
+            .approve(
+                &caller,
+                &user,
+                ApproveArgs {
+                    ledger: ledger.to_text(),
+                    spender: Principal::anonymous().to_text(),
+                    amount: "9".to_string(),
+                    expires_in_secs: Some(3600),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("TemporarilyUnavailable"));
+
+        let err = ledgers
+            .transfer(
+                &caller,
+                &user,
+                TransferArgs {
+                    ledger: ledger.to_text(),
+                    to: Principal::anonymous().to_text(),
+                    amount: "10.5".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "amount exceeds the limit of 10 PANDA per call"
+        );
+
+        let err = ledgers
+            .transfer(
+                &caller,
+                &user,
+                TransferArgs {
+                    ledger: ledger.to_text(),
+                    to: Principal::anonymous().to_text(),
+                    amount: "10".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "amount exceeds the daily limit of 11 PANDA per caller"
+        );
+
+        let ledgers = ledgers.with_recipients(BTreeSet::from([engine]));
+        let err = ledgers
+            .transfer(
+                &caller,
+                &user,
+                TransferArgs {
+                    ledger: ledger.to_text(),
+                    to: Principal::anonymous().to_text(),
+                    amount: "1".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "recipient 2vxsx-fae is not allowed");
+
+        let err = ledgers
+            .transfer_from(
+                &caller,
+                &engine,
+                &user,
+                TransferFromArgs {
+                    ledger: ledger.to_text(),
+                    from: Principal::anonymous().to_text(),
+                    to: user.to_text(),
+                    amount: "1".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "account 2vxsx-fae is not an account of the caller {}",
+                user.to_text()
+            )
+        );
+
+        let err = ledgers
+            .transfer_from(
+                &caller,
+                &engine,
+                &user,
+                TransferFromArgs {
+                    ledger: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+                    from: user.to_text(),
+                    to: user.to_text(),
+                    amount: "1".to_string(),
+                    memo: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ledger ryjl3-tyaaa-aaaaa-aaaba-cai is not allowed"
+        );
+    }
+}
@@ -0,0 +1,710 @@
+//! ICRC-7 NFT Extension for Anda Engine
+//!
+//! This module provides tools to inspect and transfer the NFTs of any ICRC-7 collection on
+//! the Internet Computer, through the [`CanisterCaller`] of the context.
+//!
+//! # Features
+//! - `icrc7_collection_metadata`: queries the metadata of a collection;
+//! - `icrc7_tokens_of`: lists the tokens owned by an account, page by page;
+//! - `icrc7_token_info`: queries the owners and the metadata of tokens;
+//! - `icrc7_transfer`: transfers tokens from the engine's account.
+//!
+//! The token IDs are decimal strings, since they can exceed the integers of JSON, and the
+//! metadata are converted to JSON by [`icrc3_value_to_json`]. The accounts are principals, or
+//! accounts with a subaccount in the ICRC-1 textual encoding.
+//!
+//! The tokens are transferred from the engine's account, or its subaccount set with
+//! [`Icrc7Collections::with_subaccount`]. The update calls are checked against the
+//! capabilities of the context and the engine's canister policy, and the collections can be
+//! restricted with [`Icrc7Collections::with_collections`].
+//!
+//! # Usage
+//! ```rust,ignore
+//! let collections = Icrc7Collections::new().with_collections(BTreeSet::from([collection]));
+//! let engine = Engine::builder()
+//!     .register_tools(collections.tools()?)?
+//!     .register_agent(my_agent)?
+//!     .build(default_agent)
+//!     .await?;
+//! ```
+
+use anda_core::{
+    BoxError, CanisterCaller, FunctionDefinition, Resource, Tool, ToolArgs, ToolOutput, ToolSet,
+};
+use candid::{CandidType, Nat, Principal};
+use icrc_ledger_types::{icrc::generic_value::ICRC3Value, icrc1::account::Account};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use super::icrc1::parse_account;
+use crate::{context::BaseCtx, watcher::icrc3_value_to_json};
+
+/// The maximum size of a memo in bytes.
+const MAX_MEMO_LEN: usize = 32;
+
+/// The argument of a transfer of `icrc7_transfer`.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Icrc7TransferArg {
+    pub from_subaccount: Option<[u8; 32]>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+/// The error of a transfer of `icrc7_transfer`.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Icrc7TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+/// Arguments to query the metadata of a collection
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct CollectionArgs {
+    /// Canister ID of the ICRC-7 collection
+    #[validate(length(min = 1, max = 64))]
+    pub collection: String,
+}
+
+/// Arguments to list the tokens owned by an account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct TokensOfArgs {
+    /// Canister ID of the ICRC-7 collection
+    #[validate(length(min = 1, max = 64))]
+    pub collection: String,
+    /// Owner of the tokens: a principal, e.g. "77ibd-jp5kr-moeco-kgoar-rro5v-5tng4-krif5-5h2i6-osf2f-2sjtv-kqe", or an account with a subaccount in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub account: String,
+    /// Optional last token ID of the previous page, to list the next one
+    pub prev: Option<String>,
+    /// Optional maximum number of tokens to list, at most 100
+    #[validate(range(min = 1, max = 100))]
+    pub take: Option<u64>,
+}
+
+/// Arguments to query the owners and the metadata of tokens
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct TokenInfoArgs {
+    /// Canister ID of the ICRC-7 collection
+    #[validate(length(min = 1, max = 64))]
+    pub collection: String,
+    /// IDs of the tokens as decimal strings, e.g. ["1", "2"]
+    #[validate(length(min = 1, max = 100))]
+    pub token_ids: Vec<String>,
+}
+
+/// Arguments to transfer tokens from the engine's account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, ToolArgs)]
+pub struct TransferArgs {
+    /// Canister ID of the ICRC-7 collection
+    #[validate(length(min = 1, max = 64))]
+    pub collection: String,
+    /// Account to receive the tokens: a principal or an account in the ICRC-1 textual encoding
+    #[validate(length(min = 1, max = 256))]
+    pub to: String,
+    /// IDs of the tokens to transfer as decimal strings, e.g. ["1", "2"]
+    #[validate(length(min = 1, max = 100))]
+    pub token_ids: Vec<String>,
+    /// Optional memo of the transfers, at most 32 bytes
+    #[validate(length(max = 32))]
+    pub memo: Option<String>,
+}
+
+/// The tokens owned by an account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TokensOf {
+    /// IDs of the tokens
+    pub token_ids: Vec<String>,
+    /// The last token ID of the page, to list the next one, none if the page is not full
+    pub next: Option<String>,
+}
+
+/// The owner and the metadata of a token
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct TokenInfo {
+    /// ID of the token
+    pub token_id: String,
+    /// Owner of the token, none if the token doesn't exist
+    pub owner: Option<String>,
+    /// Metadata of the token
+    pub metadata: Option<Value>,
+}
+
+/// The result of the transfer of a token
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TransferResult {
+    /// ID of the token
+    pub token_id: String,
+    /// Index of the block of the transaction, if the transfer succeeded
+    pub block_index: Option<String>,
+    /// Error of the transfer, if it failed or was not processed
+    pub error: Option<String>,
+}
+
+/// ICRC-7 collections that the tools call.
+#[derive(Debug, Clone, Default)]
+pub struct Icrc7Collections {
+    /// Collections that the tools may call, all if empty.
+    collections: BTreeSet<Principal>,
+    /// Subaccount of the engine's account that the tokens are transferred from.
+    subaccount: Option<[u8; 32]>,
+}
+
+impl Icrc7Collections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the tools to the collections.
+    pub fn with_collections(mut self, collections: BTreeSet<Principal>) -> Self {
+        self.collections = collections;
+        self
+    }
+
+    /// Transfers the tokens from the subaccount of the engine's account.
+    pub fn with_subaccount(mut self, subaccount: [u8; 32]) -> Self {
+        self.subaccount = Some(subaccount);
+        self
+    }
+
+    /// Returns the NFT tools, sharing the collections.
+    pub fn tools(self) -> Result<ToolSet<BaseCtx>, BoxError> {
+        let collections = Arc::new(self);
+        let mut tools = ToolSet::new();
+        tools.add(CollectionMetadataTool::new(collections.clone()))?;
+        tools.add(TokensOfTool::new(collections.clone()))?;
+        tools.add(TokenInfoTool::new(collections.clone()))?;
+        tools.add(TransferTool::new(collections))?;
+        Ok(tools)
+    }
+
+    /// Queries the metadata of a collection.
+    pub async fn collection_metadata(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: CollectionArgs,
+    ) -> Result<Value, BoxError> {
+        let collection = self.collection(&args.collection)?;
+        let metadata: BTreeMap<String, ICRC3Value> = ctx
+            .canister_query(&collection, "icrc7_collection_metadata", ())
+            .await?;
+        Ok(icrc3_value_to_json(ICRC3Value::Map(metadata)))
+    }
+
+    /// Lists the tokens owned by an account.
+    pub async fn tokens_of(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: TokensOfArgs,
+    ) -> Result<TokensOf, BoxError> {
+        let collection = self.collection(&args.collection)?;
+        let account = parse_account(&args.account)?;
+        let prev = args.prev.as_deref().map(parse_token_id).transpose()?;
+        let take = args.take.map(Nat::from);
+        let token_ids: Vec<Nat> = ctx
+            .canister_query(
+                &collection,
+                "icrc7_tokens_of",
+                (account, prev, take.clone()),
+            )
+            .await?;
+        // the collection may take less than requested, up to its own limit
+        let count = Nat::from(token_ids.len());
+        let next = match take {
+            Some(take) if count < take => None,
+            _ if token_ids.is_empty() => None,
+            _ => token_ids.last().map(|id| id.0.to_string()),
+        };
+        Ok(TokensOf {
+            token_ids: token_ids.into_iter().map(|id| id.0.to_string()).collect(),
+            next,
+        })
+    }
+
+    /// Queries the owners and the metadata of tokens.
+    pub async fn token_info(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: TokenInfoArgs,
+    ) -> Result<Vec<TokenInfo>, BoxError> {
+        let collection = self.collection(&args.collection)?;
+        let token_ids = args
+            .token_ids
+            .iter()
+            .map(|id| parse_token_id(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (owners, metadata) = futures::try_join!(
+            ctx.canister_query::<_, Vec<Option<Account>>>(
+                &collection,
+                "icrc7_owner_of",
+                (token_ids.clone(),)
+            ),
+            ctx.canister_query::<_, Vec<Option<BTreeMap<String, ICRC3Value>>>>(
+                &collection,
+                "icrc7_token_metadata",
+                (token_ids.clone(),)
+            ),
+        )?;
+        let mut owners = owners.into_iter();
+        let mut metadata = metadata.into_iter();
+        Ok(token_ids
+            .into_iter()
+            .map(|id| TokenInfo {
+                token_id: id.0.to_string(),
+                owner: owners.next().flatten().map(|owner| owner.to_string()),
+                metadata: metadata
+                    .next()
+                    .flatten()
+                    .map(|m| icrc3_value_to_json(ICRC3Value::Map(m))),
+            })
+            .collect())
+    }
+
+    /// Transfers tokens from the engine's account, with a result for each token.
+    pub async fn transfer(
+        &self,
+        ctx: &impl CanisterCaller,
+        args: TransferArgs,
+    ) -> Result<Vec<TransferResult>, BoxError> {
+        let collection = self.collection(&args.collection)?;
+        let to = parse_account(&args.to)?;
+        let memo = match args.memo {
+            Some(memo) if memo.len() > MAX_MEMO_LEN => {
+                return Err(format!("memo must be at most {} bytes", MAX_MEMO_LEN).into());
+            }
+            memo => memo.map(String::into_bytes),
+        };
+        let transfers = args
+            .token_ids
+            .iter()
+            .map(|id| {
+                Ok(Icrc7TransferArg {
+                    from_subaccount: self.subaccount,
+                    to,
+                    token_id: parse_token_id(id)?,
+                    memo: memo.clone(),
+                    created_at_time: None,
+                })
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        let res: Vec<Option<Result<Nat, Icrc7TransferError>>> = ctx
+            .canister_update(&collection, "icrc7_transfer", (transfers,))
+            .await?;
+        log::info!(
+            collection = args.collection,
+            to = args.to,
+            tokens = args.token_ids.len();
+            "icrc7_transfer",
+        );
+
+        let mut res = res.into_iter();
+        Ok(args
+            .token_ids
+            .into_iter()
+            .map(|token_id| match res.next().flatten() {
+                Some(Ok(block_index)) => TransferResult {
+                    token_id,
+                    block_index: Some(block_index.0.to_string()),
+                    error: None,
+                },
+                Some(Err(err)) => TransferResult {
+                    token_id,
+                    block_index: None,
+                    error: Some(format!("{:?}", err)),
+                },
+                None => TransferResult {
+                    token_id,
+                    block_index: None,
+                    error: Some("not processed".to_string()),
+                },
+            })
+            .collect())
+    }
+
+    fn collection(&self, collection: &str) -> Result<Principal, BoxError> {
+        let collection = Principal::from_text(collection)
+            .map_err(|_| format!("invalid collection {:?}", collection))?;
+        if !self.collections.is_empty() && !self.collections.contains(&collection) {
+            return Err(format!("collection {} is not allowed", collection.to_text()).into());
+        }
+        Ok(collection)
+    }
+}
+
+fn parse_token_id(token_id: &str) -> Result<Nat, BoxError> {
+    let id = token_id.trim();
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid token ID {:?}", token_id).into());
+    }
+    Ok(id.parse::<Nat>()?)
+}
+
+/// ICRC-7 collection metadata tool.
+#[derive(Debug, Clone)]
+pub struct CollectionMetadataTool {
+    collections: Arc<Icrc7Collections>,
+    schema: Value,
+}
+
+impl CollectionMetadataTool {
+    pub const NAME: &'static str = "icrc7_collection_metadata";
+
+    pub fn new(collections: Arc<Icrc7Collections>) -> Self {
+        Self {
+            collections,
+            schema: CollectionArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for CollectionMetadataTool {
+    type Args = CollectionArgs;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Queries the metadata of an ICRC-7 NFT collection on the Internet Computer, such as its name, symbol, description and total supply.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.collections.collection_metadata(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-7 tokens of an account tool.
+#[derive(Debug, Clone)]
+pub struct TokensOfTool {
+    collections: Arc<Icrc7Collections>,
+    schema: Value,
+}
+
+impl TokensOfTool {
+    pub const NAME: &'static str = "icrc7_tokens_of";
+
+    pub fn new(collections: Arc<Icrc7Collections>) -> Self {
+        Self {
+            collections,
+            schema: TokensOfArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for TokensOfTool {
+    type Args = TokensOfArgs;
+    type Output = TokensOf;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Lists the IDs of the NFTs owned by an account in an ICRC-7 collection on the Internet Computer, page by page.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.collections.tokens_of(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-7 token owners and metadata tool.
+#[derive(Debug, Clone)]
+pub struct TokenInfoTool {
+    collections: Arc<Icrc7Collections>,
+    schema: Value,
+}
+
+impl TokenInfoTool {
+    pub const NAME: &'static str = "icrc7_token_info";
+
+    pub fn new(collections: Arc<Icrc7Collections>) -> Self {
+        Self {
+            collections,
+            schema: TokenInfoArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for TokenInfoTool {
+    type Args = TokenInfoArgs;
+    type Output = Vec<TokenInfo>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Queries the owners and the metadata of NFTs in an ICRC-7 collection on the Internet Computer.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.collections.token_info(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+/// ICRC-7 transfer tool.
+#[derive(Debug, Clone)]
+pub struct TransferTool {
+    collections: Arc<Icrc7Collections>,
+    schema: Value,
+}
+
+impl TransferTool {
+    pub const NAME: &'static str = "icrc7_transfer";
+
+    pub fn new(collections: Arc<Icrc7Collections>) -> Self {
+        Self {
+            collections,
+            schema: TransferArgs::parameters(),
+        }
+    }
+}
+
+impl Tool<BaseCtx> for TransferTool {
+    type Args = TransferArgs;
+    type Output = Vec<TransferResult>;
+
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn description(&self) -> String {
+        "Transfers NFTs of an ICRC-7 collection on the Internet Computer from the agent's account to another account.".to_string()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: self.name(),
+            description: self.description(),
+            parameters: self.schema.clone(),
+            strict: Some(true),
+        }
+    }
+
+    fn validate_args(&self, args: &Self::Args) -> Result<(), String> {
+        args.validate()
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.collections.transfer(&ctx, args).await?;
+        Ok(ToolOutput::new(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::mock::MockCanisterCaller;
+    use candid::{decode_args, encode_one};
+    use serde_json::json;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icrc7_collections() {
+        let collection = Principal::from_text("druyg-tyaaa-aaaaq-aactq-cai").unwrap();
+        let owner = Principal::anonymous();
+        let caller = MockCanisterCaller::new(move |canister, method, args| {
+            assert_eq!(canister, &collection);
+            match method {
+                "icrc7_collection_metadata" => encode_one(BTreeMap::from([
+                    (
+                        "icrc7:symbol".to_string(),
+                        ICRC3Value::Text("PND".to_string()),
+                    ),
+                    (
+                        "icrc7:total_supply".to_string(),
+                        ICRC3Value::Nat(Nat::from(3u64)),
+                    ),
+                ]))
+                .unwrap(),
+                "icrc7_tokens_of" => {
+                    let (account, prev, take): (Account, Option<Nat>, Option<Nat>) =
+                        decode_args(&args).unwrap();
+                    assert_eq!(account.owner, owner);
+                    assert_eq!(take, Some(Nat::from(2u64)));
+                    let ids: Vec<Nat> = match prev {
+                        None => vec![Nat::from(1u64), Nat::from(2u64)],
+                        Some(_) => vec![Nat::from(3u64)],
+                    };
+                    encode_one(ids).unwrap()
+                }
+                "icrc7_owner_of" => encode_one(vec![
+                    Some(Account {
+                        owner,
+                        subaccount: None,
+                    }),
+                    None,
+                ])
+                .unwrap(),
+                "icrc7_token_metadata" => encode_one(vec![
+                    Some(BTreeMap::from([(
+                        "icrc7:name".to_string(),
+                        ICRC3Value::Text("Panda #1".to_string()),
+                    )])),
+                    None,
+                ])
+                .unwrap(),
+                "icrc7_transfer" => {
+                    let (transfers,): (Vec<Icrc7TransferArg>,) = decode_args(&args).unwrap();
+                    assert_eq!(transfers.len(), 2);
+                    assert_eq!(transfers[0].to.owner, owner);
+                    assert_eq!(transfers[0].memo, Some(b"gift".to_vec()));
+                    let res: Vec<Option<Result<Nat, Icrc7TransferError>>> = vec![
+                        Some(Ok(Nat::from(7u64))),
+                        Some(Err(Icrc7TransferError::Unauthorized)),
+                    ];
+                    encode_one(res).unwrap()
+                }
+                _ => panic!("unexpected method {}", method),
+            }
+        });
+
+        let collections = Icrc7Collections::new().with_collections(BTreeSet::from([collection]));
+        let metadata = collections
+            .collection_metadata(
+                &caller,
+                CollectionArgs {
+                    collection: collection.to_text(),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            metadata,
+            json!({"icrc7:symbol": "PND", "icrc7:total_supply": "3"})
+        );
+
+        let mut args = TokensOfArgs {
+            collection: collection.to_text(),
+            account: owner.to_text(),
+            prev: None,
+            take: Some(2),
+        };
+        let page = collections.tokens_of(&caller, args.clone()).await.unwrap();
+        assert_eq!(page.token_ids, vec!["1", "2"]);
+        assert_eq!(page.next, Some("2".to_string()));
+        args.prev = page.next;
+        let page = collections.tokens_of(&caller, args).await.unwrap();
+        assert_eq!(page.token_ids, vec!["3"]);
+        assert_eq!(page.next, None);
+
+        let info = collections
+            .token_info(
+                &caller,
+                TokenInfoArgs {
+                    collection: collection.to_text(),
+                    token_ids: vec!["1".to_string(), "9".to_string()],
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(info[0].owner, Some(owner.to_text()));
+        assert_eq!(info[0].metadata, Some(json!({"icrc7:name": "Panda #1"})));
+        assert_eq!(info[1].owner, None);
+
+        let res = collections
+            .transfer(
+                &caller,
+                TransferArgs {
+                    collection: collection.to_text(),
+                    to: owner.to_text(),
+                    token_ids: vec!["1".to_string(), "2".to_string()],
+                    memo: Some("gift".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(res[0].block_index, Some("7".to_string()));
+        assert_eq!(res[1].error, Some("Unauthorized".to_string()));
+
+        assert!(parse_token_id("1.5").is_err());
+        let err = collections
+            .collection_metadata(
+                &caller,
+                CollectionArgs {
+                    collection: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "collection ryjl3-tyaaa-aaaaa-aaaba-cai is not allowed"
+        );
+    }
+}
@@ -10,6 +10,9 @@
 //! - **Character System**: Defines agent personalities and communication styles
 //! - **Extraction Tools**: Enables structured data extraction from unstructured text
 //! - **Google Web Search Tool**: Enables web searches and retrieve results.
+//! - **ICRC Ledger Tools**: Query balances, transfer and approve tokens on ICRC-1/ICRC-2 ledgers
+//! - **ICRC-7 NFT Tools**: Inspect NFT collections and ownership, and transfer NFTs
+//! - **Bitcoin Wallet Tool**: Derives Bitcoin addresses, signs PSBTs and sends bitcoins (`bitcoin` feature)
 //! - **Document Segmentation**: Breaks down large documents into manageable chunks
 //!
 //! # Usage
@@ -22,7 +25,11 @@
 //!
 
 pub mod attention;
+#[cfg(feature = "bitcoin")]
+pub mod bitcoin;
 pub mod character;
 pub mod extractor;
 pub mod google;
+pub mod icrc1;
+pub mod icrc7;
 pub mod segmenter;
@@ -0,0 +1,202 @@
+//! Runtime feature flags evaluated per caller.
+//!
+//! A [`FeatureFlag`] rolls out a risky new tool, prompt or model to a subset of the callers:
+//! the listed callers, users and tenants, and a stable percentage of the others, chosen by a
+//! hash of the flag name and the caller, so that a caller keeps the same variant across
+//! requests. The flags are defined in the `feature_flags` section of the
+//! [`EngineConfig`](crate::config::EngineConfig), or set at runtime by the managers with the
+//! admin API, until the next config reload or restart.
+//!
+//! The engine evaluates the flags when it creates the context of an agent run or a tool call,
+//! and agents and tools read them with [`anda_core::StateFeatures::is_feature_enabled`].
+//!
+//! ```toml
+//! [feature_flags.new_search]
+//! enabled = true
+//! tenants = ["acme"]
+//! percentage = 10
+//!
+//! [feature_flags.summary_model]
+//! enabled = true
+//! users = ["alice"]
+//! value = "gpt-4o"
+//! ```
+
+use anda_core::{BoxError, FeatureFlags, Value, validate_function_name};
+use candid::Principal;
+use ic_cose_types::cose::sha3_256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The rollout rules of a feature flag.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeatureFlag {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+
+    /// The switch of the flag, a disabled flag is off for everyone.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The principals of the callers for whom the flag is on.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub callers: BTreeSet<String>,
+
+    /// The users for whom the flag is on, see [`anda_core::RequestMeta::user`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub users: BTreeSet<String>,
+
+    /// The tenants for whose callers the flag is on.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tenants: BTreeSet<String>,
+
+    /// The percentage of the other callers for whom the flag is on, from 0 to 100.
+    #[serde(default)]
+    pub percentage: u8,
+
+    /// The value of the flag when it is on, e.g. the name of a model, `true` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+impl FeatureFlag {
+    /// Validates the flag.
+    pub fn validate(&self) -> Result<(), BoxError> {
+        if self.percentage > 100 {
+            return Err(format!("percentage {} is greater than 100", self.percentage).into());
+        }
+        for caller in &self.callers {
+            Principal::from_text(caller)
+                .map_err(|err| format!("invalid caller {}: {}", caller, err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the flag named `name` is on for the caller.
+    pub fn is_on(
+        &self,
+        name: &str,
+        caller: &Principal,
+        user: Option<&str>,
+        tenant: Option<&str>,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.callers.contains(&caller.to_text())
+            || user.is_some_and(|u| self.users.contains(u))
+            || tenant.is_some_and(|t| self.tenants.contains(t))
+        {
+            return true;
+        }
+        match self.percentage {
+            0 => false,
+            100.. => true,
+            percentage => {
+                // the anonymous callers are told apart by their user name
+                let key = match user {
+                    Some(user) if *caller == Principal::anonymous() => user.to_string(),
+                    _ => caller.to_text(),
+                };
+                rollout_bucket(name, &key) < percentage
+            }
+        }
+    }
+}
+
+/// Returns the rollout bucket of the key for the flag, from 0 to 99.
+pub fn rollout_bucket(name: &str, key: &str) -> u8 {
+    let hash = sha3_256(format!("{}:{}", name, key).as_bytes());
+    (u16::from_be_bytes([hash[0], hash[1]]) % 100) as u8
+}
+
+/// Validates the name and the rules of a flag.
+pub fn validate_feature_flag(name: &str, flag: &FeatureFlag) -> Result<(), BoxError> {
+    validate_function_name(name).map_err(|err| format!("invalid flag name {}: {}", name, err))?;
+    flag.validate()
+        .map_err(|err| format!("invalid flag {}: {}", name, err).into())
+}
+
+/// Evaluates the flags for the caller, returns the enabled ones with their values.
+pub fn evaluate_feature_flags(
+    flags: &BTreeMap<String, FeatureFlag>,
+    caller: &Principal,
+    user: Option<&str>,
+    tenant: Option<&str>,
+) -> FeatureFlags {
+    FeatureFlags(
+        flags
+            .iter()
+            .filter(|(name, flag)| flag.is_on(name, caller, user, tenant))
+            .map(|(name, flag)| {
+                (
+                    name.clone(),
+                    flag.value.clone().unwrap_or(Value::Bool(true)),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_feature_flags() {
+        let alice = Principal::from_slice(&[1]);
+        let flags: BTreeMap<String, FeatureFlag> = serde_json::from_value(json!({
+            "new_search": {"enabled": true, "tenants": ["acme"], "percentage": 30},
+            "summary_model": {"enabled": true, "users": ["alice"], "value": "gpt-4o"},
+            "beta": {"enabled": false, "percentage": 100},
+            "canary": {"enabled": true, "callers": [alice.to_text()]},
+        }))
+        .unwrap();
+        for (name, flag) in &flags {
+            validate_feature_flag(name, flag).unwrap();
+        }
+
+        let res = evaluate_feature_flags(&flags, &alice, Some("alice"), Some("acme"));
+        assert!(res.is_enabled("new_search"));
+        assert!(res.is_enabled("canary"));
+        assert!(!res.is_enabled("beta"));
+        assert_eq!(res.value("summary_model"), Some(&json!("gpt-4o")));
+        assert_eq!(res.value("canary"), Some(&json!(true)));
+
+        // only the rollout can turn a flag on for the other callers
+        let anonymous = Principal::anonymous();
+        let res = evaluate_feature_flags(&flags, &anonymous, None, None);
+        assert!(!res.is_enabled("canary"));
+        assert!(!res.is_enabled("summary_model"));
+        assert!(!res.is_enabled("beta"));
+        assert_eq!(
+            res.is_enabled("new_search"),
+            rollout_bucket("new_search", &anonymous.to_text()) < 30
+        );
+
+        // about 30% of the callers, always the same ones
+        let on: Vec<bool> = (0..1000u32)
+            .map(|i| {
+                let caller = Principal::from_slice(&i.to_be_bytes());
+                flags["new_search"].is_on("new_search", &caller, None, None)
+            })
+            .collect();
+        let count = on.iter().filter(|on| **on).count();
+        assert!((250..350).contains(&count), "{count}");
+        assert!((0..1000u32).all(|i| flags["new_search"].is_on(
+            "new_search",
+            &Principal::from_slice(&i.to_be_bytes()),
+            None,
+            None
+        ) == on[i as usize]));
+
+        let invalid = FeatureFlag {
+            percentage: 101,
+            ..Default::default()
+        };
+        assert!(validate_feature_flag("beta", &invalid).is_err());
+        assert!(validate_feature_flag("Beta", &FeatureFlag::default()).is_err());
+    }
+}
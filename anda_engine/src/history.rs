@@ -0,0 +1,155 @@
+//! Compaction of long chat histories.
+//!
+//! With compaction enabled by [`crate::engine::EngineBuilder::with_history_compaction`],
+//! [`AgentCtx::completion`] checks the estimated tokens of the request's chat history, memory
+//! included, before the first completion round. Above [`HistoryConfig::max_tokens`], the older
+//! messages are summarized by the model of the request and replaced with a single summary
+//! message, and only the latest [`HistoryConfig::keep_recent`] messages are kept verbatim.
+//! If the summarization fails, the older messages are dropped, as a sliding window.
+//!
+//! Tokens are estimated from the length of the serialized messages, about 4 bytes per token,
+//! so the threshold should keep a margin below the context window of the model.
+//!
+//! [`AgentCtx::completion`]: anda_core::CompletionFeatures::completion
+
+use anda_core::{CompletionRequest, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// The average number of bytes of a token, to estimate the tokens of the messages.
+const BYTES_PER_TOKEN: usize = 4;
+
+static SUMMARY_SYSTEM: &str = "You summarize conversations. Write a concise summary of the \
+conversation below, keeping the facts, decisions, open questions and user preferences \
+that the next answers may need. Reply with the summary only.";
+
+/// Configuration of the chat history compaction.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Estimated tokens of the chat history above which the older messages are summarized.
+    pub max_tokens: usize,
+    /// Number of the latest messages kept verbatim.
+    pub keep_recent: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 16000,
+            keep_recent: 6,
+        }
+    }
+}
+
+impl HistoryConfig {
+    /// Returns the index of the first message kept verbatim if the history should be
+    /// compacted, None otherwise.
+    pub fn split_point(&self, history: &[Value]) -> Option<usize> {
+        if estimate_tokens(history) <= self.max_tokens {
+            return None;
+        }
+        split_point(history, self.keep_recent)
+    }
+}
+
+/// Estimates the tokens of the messages.
+pub fn estimate_tokens(messages: &[Value]) -> usize {
+    messages
+        .iter()
+        .map(|msg| msg.to_string().len().div_ceil(BYTES_PER_TOKEN))
+        .sum()
+}
+
+/// Returns the index of the first of the latest `keep` messages, moved forward to a user
+/// message so that tool calls are not separated from their results.
+/// None if there are no older messages to compact.
+fn split_point(history: &[Value], keep: usize) -> Option<usize> {
+    let start = history.len().saturating_sub(keep);
+    let start = history[start..]
+        .iter()
+        .position(|msg| msg["role"] == "user")
+        .map_or(history.len(), |i| start + i);
+    if start == 0 { None } else { Some(start) }
+}
+
+/// Builds the request summarizing the messages, with the model of the original request.
+pub(crate) fn summary_request(model: Option<String>, messages: &[Value]) -> CompletionRequest {
+    let transcript = messages
+        .iter()
+        .map(|msg| {
+            let content = match &msg["content"] {
+                Value::String(text) => text.clone(),
+                Value::Null => msg
+                    .get("tool_calls")
+                    .map(|calls| format!("(tool calls) {}", calls))
+                    .unwrap_or_default(),
+                content => content.to_string(),
+            };
+            format!("{}: {}", msg["role"].as_str().unwrap_or("unknown"), content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    CompletionRequest {
+        model,
+        system: Some(SUMMARY_SYSTEM.to_string()),
+        prompt: transcript,
+        ..Default::default()
+    }
+}
+
+/// Returns the message replacing the summarized messages.
+pub(crate) fn summary_message(summary: &str) -> Value {
+    json!({
+        "role": "system",
+        "content": format!("Summary of the earlier conversation:\n{}", summary),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> Value {
+        json!({"role": role, "content": content})
+    }
+
+    #[test]
+    fn test_split_point() {
+        let history = vec![
+            message("user", "1"),
+            message("assistant", "2"),
+            message("tool", "3"),
+            message("assistant", "4"),
+            message("user", "5"),
+            message("assistant", "6"),
+        ];
+        assert_eq!(split_point(&history, 2), Some(4));
+        assert_eq!(split_point(&history, 3), Some(4));
+        assert_eq!(split_point(&history, 1), Some(6));
+        assert_eq!(split_point(&history, 6), None);
+        assert_eq!(split_point(&history, 10), None);
+
+        let cfg = HistoryConfig {
+            max_tokens: estimate_tokens(&history),
+            keep_recent: 2,
+        };
+        assert_eq!(cfg.split_point(&history), None);
+        let cfg = HistoryConfig {
+            max_tokens: 10,
+            keep_recent: 2,
+        };
+        assert_eq!(cfg.split_point(&history), Some(4));
+    }
+
+    #[test]
+    fn test_summary_request() {
+        let req = summary_request(
+            Some("cheap".to_string()),
+            &[message("user", "hello"), message("assistant", "hi")],
+        );
+        assert_eq!(req.model.as_deref(), Some("cheap"));
+        assert_eq!(req.prompt, "user: hello\n\nassistant: hi");
+        assert!(req.tools.is_empty());
+    }
+}
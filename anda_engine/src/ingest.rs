@@ -0,0 +1,722 @@
+//! Document ingestion for retrieval-augmented generation.
+//!
+//! An [`Ingestor`] takes documents, splits them into chunks with a [`ChunkStrategy`],
+//! embeds the chunks with [`EmbeddingFeatures`] and upserts them into a vector store
+//! with [`VectorStoreFeatures`], e.g. the engine's vector store through the agent context.
+//!
+//! Plain text and markdown are supported natively. Other formats, such as PDF, are converted
+//! to text by a [`TextExtractor`] registered for their MIME type.
+//!
+//! Each chunk is stored with the document's metadata and its provenance:
+//! - `doc_id`: the ID of the document;
+//! - `source`: the URI or name of the document, if any;
+//! - `chunk`: the index of the chunk in the document;
+//! - `offset`: the byte offset of the chunk in the document text;
+//! - `section`: the nearest markdown heading before the chunk, if any;
+//! - `ingested_at`: the ingestion time in milliseconds.
+//!
+//! The chunk ID is `{doc_id}#{chunk}`.
+//!
+//! When a document is updated, [`Ingestor::reingest`] replaces its previous chunks and
+//! re-embeds only the chunks whose text changed, the embeddings of the others are reused
+//! from the vector store.
+//!
+//! # Example
+//! ```rust,ignore
+//! let ingestor = Ingestor::new(ChunkStrategy::Sentence { max_chars: 1000 })
+//!     .with_extractor("application/pdf", Arc::new(PdfExtractor));
+//! let doc = ingestor.extract(&resource)?;
+//! let report = ingestor.ingest(&ctx, &ctx, vec![doc]).await?;
+//! ```
+
+use anda_core::{
+    BoxError, EmbeddingFeatures, Resource, Usage, Value, VectorDocument, VectorStoreFeatures,
+};
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use structured_logger::unix_ms;
+
+/// The default number of chunks per embedding request.
+pub const DEFAULT_EMBED_BATCH_SIZE: usize = 64;
+
+/// Converts the content of a document to text, e.g. a PDF extractor.
+pub trait TextExtractor: Send + Sync + 'static {
+    fn extract(&self, data: &[u8]) -> Result<String, BoxError>;
+}
+
+/// The format of a document's text.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    #[default]
+    Text,
+    Markdown,
+}
+
+/// A document to ingest.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct IngestDocument {
+    pub id: String,
+    /// The URI or name of the document.
+    pub source: Option<String>,
+    #[serde(default)]
+    pub format: DocumentFormat,
+    pub text: String,
+    /// The metadata of the document, copied to all its chunks.
+    #[serde(default)]
+    pub meta: BTreeMap<String, Value>,
+}
+
+impl IngestDocument {
+    pub fn text(id: String, text: String) -> Self {
+        Self {
+            id,
+            text,
+            ..Default::default()
+        }
+    }
+
+    pub fn markdown(id: String, text: String) -> Self {
+        Self {
+            id,
+            format: DocumentFormat::Markdown,
+            text,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the hash of the document's content, source, format and metadata,
+    /// to detect whether it changed since its last ingestion.
+    pub fn hash(&self) -> [u8; 32] {
+        sha3_256(&to_cbor_bytes(self))
+    }
+}
+
+/// How documents are split into chunks, sizes are in characters.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    /// Chunks of a fixed size, consecutive chunks share `overlap` characters.
+    Fixed { size: usize, overlap: usize },
+    /// Whole sentences packed into chunks of at most `max_chars`.
+    Sentence { max_chars: usize },
+    /// Consecutive sentences are grouped while the similarity of their embeddings
+    /// stays above `threshold`, in chunks of at most `max_chars`.
+    Semantic { max_chars: usize, threshold: f32 },
+}
+
+impl Default for ChunkStrategy {
+    fn default() -> Self {
+        ChunkStrategy::Sentence { max_chars: 1000 }
+    }
+}
+
+/// A chunk of a document.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Chunk {
+    pub index: usize,
+    /// The byte offset of the chunk in the document text.
+    pub offset: usize,
+    pub text: String,
+}
+
+/// The result of an ingestion.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct IngestReport {
+    pub documents: usize,
+    pub chunks: usize,
+    /// The number of chunks whose previous embeddings were reused.
+    #[serde(default)]
+    pub reused: usize,
+    /// The number of unchanged documents that were not ingested again.
+    #[serde(default)]
+    pub skipped: usize,
+    /// The usage of the embedding model.
+    pub usage: Usage,
+}
+
+/// Chunks, embeds and upserts documents into a vector store.
+#[derive(Clone)]
+pub struct Ingestor {
+    strategy: ChunkStrategy,
+    extractors: BTreeMap<String, Arc<dyn TextExtractor>>,
+    batch_size: usize,
+}
+
+impl Default for Ingestor {
+    fn default() -> Self {
+        Self::new(ChunkStrategy::default())
+    }
+}
+
+impl Ingestor {
+    pub fn new(strategy: ChunkStrategy) -> Self {
+        Self {
+            strategy,
+            extractors: BTreeMap::new(),
+            batch_size: DEFAULT_EMBED_BATCH_SIZE,
+        }
+    }
+
+    /// Registers a text extractor for a MIME type, e.g. "application/pdf".
+    pub fn with_extractor(mut self, mime_type: &str, extractor: Arc<dyn TextExtractor>) -> Self {
+        self.extractors
+            .insert(mime_type.to_ascii_lowercase(), extractor);
+        self
+    }
+
+    /// Sets the number of chunks per embedding request.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Converts a resource with binary data to a document, its ID is the URI or the name.
+    /// Text and markdown are decoded as UTF-8, other MIME types require an extractor.
+    pub fn extract(&self, resource: &Resource) -> Result<IngestDocument, BoxError> {
+        let id = resource
+            .uri
+            .clone()
+            .or_else(|| resource.name.clone())
+            .ok_or("resource has no uri or name")?;
+        let data = resource
+            .blob
+            .as_ref()
+            .ok_or_else(|| format!("resource {} has no data", id))?;
+        let mime_type = resource
+            .mime_type
+            .as_deref()
+            .unwrap_or("text/plain")
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let (format, text) = match self.extractors.get(&mime_type) {
+            Some(extractor) => (DocumentFormat::Text, extractor.extract(&data.0)?),
+            None if mime_type == "text/markdown" => {
+                (DocumentFormat::Markdown, String::from_utf8(data.0.clone())?)
+            }
+            None if mime_type.starts_with("text/") => {
+                (DocumentFormat::Text, String::from_utf8(data.0.clone())?)
+            }
+            None => {
+                return Err(
+                    format!("no text extractor for {} of resource {}", mime_type, id).into(),
+                );
+            }
+        };
+
+        let mut meta = BTreeMap::new();
+        meta.insert("mime_type".to_string(), mime_type.into());
+        Ok(IngestDocument {
+            source: Some(id.clone()),
+            id,
+            format,
+            text,
+            meta,
+        })
+    }
+
+    /// Splits the document into chunks, the embedder is only used by the semantic strategy.
+    pub async fn chunk(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        doc: &IngestDocument,
+    ) -> Result<(Vec<Chunk>, Usage), BoxError> {
+        let mut usage = Usage::default();
+        let sentences = split_sentences(&doc.text, doc.format);
+        // a markdown heading starts a new chunk
+        let heading = |i: usize| {
+            doc.format == DocumentFormat::Markdown && doc.text[sentences[i].0..].starts_with('#')
+        };
+        let spans = match self.strategy {
+            ChunkStrategy::Fixed { size, overlap } => {
+                fixed_spans(&doc.text, 0, doc.text.len(), size, overlap)
+            }
+            ChunkStrategy::Sentence { max_chars } => {
+                pack_sentences(&doc.text, &sentences, max_chars, heading)
+            }
+            ChunkStrategy::Semantic {
+                max_chars,
+                threshold,
+            } => {
+                let mut vecs = Vec::with_capacity(sentences.len());
+                for batch in sentences.chunks(self.batch_size) {
+                    let (embeddings, u) = embedder
+                        .embed(
+                            batch
+                                .iter()
+                                .map(|&(s, e)| doc.text[s..e].to_string())
+                                .collect::<Vec<_>>(),
+                        )
+                        .await?;
+                    usage.accumulate(&u);
+                    vecs.extend(embeddings.into_iter().map(|e| e.vec));
+                }
+                if vecs.len() != sentences.len() {
+                    return Err("embedding count mismatch".into());
+                }
+                pack_sentences(&doc.text, &sentences, max_chars, |i| {
+                    heading(i) || cosine(&vecs[i - 1], &vecs[i]) < threshold
+                })
+            }
+        };
+
+        let chunks = spans
+            .into_iter()
+            .enumerate()
+            .map(|(index, (start, end))| Chunk {
+                index,
+                offset: start,
+                text: doc.text[start..end].to_string(),
+            })
+            .collect();
+        Ok((chunks, usage))
+    }
+
+    /// Chunks and embeds the documents, and upserts the chunks into the vector store.
+    pub async fn ingest(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        store: &impl VectorStoreFeatures,
+        docs: Vec<IngestDocument>,
+    ) -> Result<IngestReport, BoxError> {
+        let mut report = IngestReport::default();
+        for doc in docs {
+            self.ingest_document(embedder, store, doc, 0, &mut report)
+                .await?;
+        }
+        Ok(report)
+    }
+
+    /// Replaces a document previously ingested in `previous_chunks` chunks.
+    /// The embeddings of the unchanged chunks are reused, only the new and changed chunks
+    /// are embedded, and the previous chunks beyond the new ones are deleted.
+    pub async fn reingest(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        store: &impl VectorStoreFeatures,
+        doc: IngestDocument,
+        previous_chunks: usize,
+    ) -> Result<IngestReport, BoxError> {
+        let mut report = IngestReport::default();
+        self.ingest_document(embedder, store, doc, previous_chunks, &mut report)
+            .await?;
+        Ok(report)
+    }
+
+    async fn ingest_document(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        store: &impl VectorStoreFeatures,
+        doc: IngestDocument,
+        previous_chunks: usize,
+        report: &mut IngestReport,
+    ) -> Result<(), BoxError> {
+        if doc.id.is_empty() {
+            return Err("document id is empty".into());
+        }
+        let (chunks, usage) = self.chunk(embedder, &doc).await?;
+        report.usage.accumulate(&usage);
+        let sections = match doc.format {
+            DocumentFormat::Markdown => markdown_headings(&doc.text),
+            DocumentFormat::Text => Vec::new(),
+        };
+        let now_ms = unix_ms();
+
+        // the embeddings of the previous chunks by text
+        let mut previous: HashMap<String, Vec<f32>> = HashMap::new();
+        if previous_chunks > 0 {
+            let ids = (0..previous_chunks)
+                .map(|i| format!("{}#{}", doc.id, i))
+                .collect();
+            for vdoc in store.vector_get(ids).await? {
+                previous.insert(vdoc.text, vdoc.vec);
+            }
+        }
+
+        let mut vecs: Vec<Option<Vec<f32>>> = chunks
+            .iter()
+            .map(|c| previous.get(&c.text).cloned())
+            .collect();
+        report.reused += vecs.iter().filter(|v| v.is_some()).count();
+        let missing: Vec<usize> = (0..chunks.len()).filter(|&i| vecs[i].is_none()).collect();
+        for batch in missing.chunks(self.batch_size) {
+            let (embeddings, usage) = embedder
+                .embed(
+                    batch
+                        .iter()
+                        .map(|&i| chunks[i].text.clone())
+                        .collect::<Vec<_>>(),
+                )
+                .await?;
+            report.usage.accumulate(&usage);
+            if embeddings.len() != batch.len() {
+                return Err(format!("embedding count mismatch for document {}", doc.id).into());
+            }
+            for (&i, embedding) in batch.iter().zip(embeddings) {
+                vecs[i] = Some(embedding.vec);
+            }
+        }
+
+        let count = chunks.len();
+        let vdocs: Vec<VectorDocument> = chunks
+            .into_iter()
+            .zip(vecs)
+            .map(|(chunk, vec)| {
+                let mut meta = doc.meta.clone();
+                meta.insert("doc_id".to_string(), doc.id.clone().into());
+                if let Some(source) = &doc.source {
+                    meta.insert("source".to_string(), source.clone().into());
+                }
+                meta.insert("chunk".to_string(), chunk.index.into());
+                meta.insert("offset".to_string(), chunk.offset.into());
+                if let Some((_, heading)) =
+                    sections.iter().rev().find(|(pos, _)| *pos <= chunk.offset)
+                {
+                    meta.insert("section".to_string(), heading.clone().into());
+                }
+                meta.insert("ingested_at".to_string(), now_ms.into());
+                VectorDocument {
+                    id: format!("{}#{}", doc.id, chunk.index),
+                    vec: vec.unwrap_or_default(),
+                    text: chunk.text,
+                    meta,
+                }
+            })
+            .collect();
+        report.documents += 1;
+        report.chunks += count;
+        if !vdocs.is_empty() {
+            store.vector_upsert(vdocs).await?;
+        }
+        // the previous chunks beyond the new ones are stale
+        if count < previous_chunks {
+            store
+                .vector_delete(
+                    (count..previous_chunks)
+                        .map(|i| format!("{}#{}", doc.id, i))
+                        .collect(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits the text into sentence spans, trimmed of whitespace.
+/// Paragraph breaks end a sentence, and so do line breaks in markdown.
+fn split_sentences(text: &str, format: DocumentFormat) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        let end = match c {
+            '.' | '!' | '?' => next.is_none_or(char::is_whitespace),
+            '。' | '！' | '？' => true,
+            '\n' => format == DocumentFormat::Markdown || next == Some('\n'),
+            _ => false,
+        };
+        if end {
+            push_trimmed(text, start, i + c.len_utf8(), &mut spans);
+            start = i + c.len_utf8();
+        }
+    }
+    push_trimmed(text, start, text.len(), &mut spans);
+    spans
+}
+
+fn push_trimmed(text: &str, start: usize, end: usize, spans: &mut Vec<(usize, usize)>) {
+    let s = &text[start..end];
+    let trimmed = s.trim();
+    if !trimmed.is_empty() {
+        let offset = start + (s.len() - s.trim_start().len());
+        spans.push((offset, offset + trimmed.len()));
+    }
+}
+
+/// Packs consecutive sentences into chunks of at most `max_chars`, starting a new chunk
+/// where `split(i)` is true for the sentence i. Longer sentences are split in fixed chunks.
+fn pack_sentences(
+    text: &str,
+    sentences: &[(usize, usize)],
+    max_chars: usize,
+    split: impl Fn(usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let max_chars = max_chars.max(1);
+    let mut spans = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (i, &(start, end)) in sentences.iter().enumerate() {
+        if text[start..end].chars().count() > max_chars {
+            spans.extend(current.take());
+            spans.extend(fixed_spans(text, start, end, max_chars, 0));
+            continue;
+        }
+        current = match current {
+            Some((s, _)) if !split(i) && text[s..end].chars().count() <= max_chars => {
+                Some((s, end))
+            }
+            Some(span) => {
+                spans.push(span);
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    spans.extend(current);
+    spans
+}
+
+/// Splits the text from `start` to `end` into spans of `size` characters, overlapping by `overlap`.
+fn fixed_spans(
+    text: &str,
+    start: usize,
+    end: usize,
+    size: usize,
+    overlap: usize,
+) -> Vec<(usize, usize)> {
+    let size = size.max(1);
+    let step = size.saturating_sub(overlap).max(1);
+    let bounds: Vec<usize> = text[start..end]
+        .char_indices()
+        .map(|(i, _)| start + i)
+        .chain(std::iter::once(end))
+        .collect();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i + 1 < bounds.len() {
+        let end = (i + size).min(bounds.len() - 1);
+        if !text[bounds[i]..bounds[end]].trim().is_empty() {
+            spans.push((bounds[i], bounds[end]));
+        }
+        if end == bounds.len() - 1 {
+            break;
+        }
+        i += step;
+    }
+    spans
+}
+
+/// Returns the byte offsets and titles of the markdown headings.
+fn markdown_headings(text: &str) -> Vec<(usize, String)> {
+    let mut headings = Vec::new();
+    let mut offset = 0;
+    let mut in_code = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code && trimmed.starts_with('#') {
+            let title = trimmed.trim_start_matches('#');
+            if title.starts_with(' ') && !title.trim().is_empty() {
+                headings.push((offset, title.trim().to_string()));
+            }
+        }
+        offset += line.len();
+    }
+    headings
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let n = norm(a) * norm(b);
+    if n == 0.0 { 0.0 } else { dot / n }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::HnswVectorStore;
+    use anda_core::{ByteBufB64, Embedding};
+
+    /// Embeds texts as letter histograms.
+    struct LetterEmbedder;
+
+    impl EmbeddingFeatures for LetterEmbedder {
+        fn ndims(&self) -> usize {
+            26
+        }
+
+        async fn embed(
+            &self,
+            texts: impl IntoIterator<Item = String> + Send,
+        ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+            let embeddings: Vec<Embedding> = texts
+                .into_iter()
+                .map(|text| {
+                    let mut vec = vec![0.0; 26];
+                    for c in text.to_ascii_lowercase().chars() {
+                        if c.is_ascii_lowercase() {
+                            vec[(c as u8 - b'a') as usize] += 1.0;
+                        }
+                    }
+                    Embedding { text, vec }
+                })
+                .collect();
+            let usage = Usage {
+                input_tokens: embeddings.len() as u64,
+                requests: 1,
+                ..Default::default()
+            };
+            Ok((embeddings, usage))
+        }
+
+        async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
+            let (mut embeddings, usage) = self.embed(vec![text.to_string()]).await?;
+            Ok((embeddings.pop().unwrap(), usage))
+        }
+    }
+
+    #[test]
+    fn test_split_and_pack() {
+        let text = "Hello world. How are you?\n\nFine, 3.5 times! 你好。Bye";
+        let sentences: Vec<&str> = split_sentences(text, DocumentFormat::Text)
+            .into_iter()
+            .map(|(s, e)| &text[s..e])
+            .collect();
+        assert_eq!(
+            sentences,
+            vec![
+                "Hello world.",
+                "How are you?",
+                "Fine, 3.5 times!",
+                "你好。",
+                "Bye"
+            ]
+        );
+
+        let spans = pack_sentences(
+            text,
+            &split_sentences(text, DocumentFormat::Text),
+            30,
+            |_| false,
+        );
+        let chunks: Vec<&str> = spans.iter().map(|&(s, e)| &text[s..e]).collect();
+        assert_eq!(
+            chunks,
+            vec!["Hello world. How are you?", "Fine, 3.5 times! 你好。Bye"]
+        );
+
+        let chunks: Vec<&str> = fixed_spans("abcdefghij", 0, 10, 4, 1)
+            .into_iter()
+            .map(|(s, e)| &"abcdefghij"[s..e])
+            .collect();
+        assert_eq!(chunks, vec!["abcd", "defg", "ghij"]);
+
+        let md = "# Title\nIntro.\n```\n# not a heading\n```\n## Usage\nRun it.";
+        let headings = markdown_headings(md);
+        assert_eq!(
+            headings.iter().map(|h| h.1.as_str()).collect::<Vec<_>>(),
+            vec!["Title", "Usage"]
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ingest() {
+        let store = HnswVectorStore::default();
+        let ingestor = Ingestor::new(ChunkStrategy::Sentence { max_chars: 40 }).with_batch_size(2);
+        let resource = Resource {
+            tag: "text".to_string(),
+            uri: Some("file:///guide.md".to_string()),
+            mime_type: Some("text/markdown; charset=utf-8".to_string()),
+            blob: Some(ByteBufB64(
+                b"# Apples\nApples are red.\n# Bananas\nBananas are yellow.".to_vec(),
+            )),
+            ..Default::default()
+        };
+        let doc = ingestor.extract(&resource).unwrap();
+        assert_eq!(doc.format, DocumentFormat::Markdown);
+        assert!(
+            ingestor
+                .extract(&Resource {
+                    name: Some("a.pdf".to_string()),
+                    mime_type: Some("application/pdf".to_string()),
+                    blob: Some(ByteBufB64(vec![1, 2, 3])),
+                    ..Default::default()
+                })
+                .is_err()
+        );
+
+        let report = ingestor
+            .ingest(&LetterEmbedder, &store, vec![doc])
+            .await
+            .unwrap();
+        assert_eq!(report.documents, 1);
+        assert_eq!(report.chunks, 2);
+        assert_eq!(report.usage.requests, 1);
+
+        let (query, _) = LetterEmbedder.embed_query("bananas yellow").await.unwrap();
+        let res = store.vector_search(query.vec, 1, None).await.unwrap();
+        assert_eq!(res[0].id, "file:///guide.md#1");
+        assert_eq!(res[0].text, "# Bananas\nBananas are yellow.");
+        assert_eq!(res[0].meta["section"], "Bananas");
+        assert_eq!(res[0].meta["doc_id"], "file:///guide.md");
+        assert_eq!(res[0].meta["mime_type"], "text/markdown");
+        assert_eq!(res[0].meta["chunk"], 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reingest() {
+        let store = HnswVectorStore::default();
+        let ingestor = Ingestor::new(ChunkStrategy::Sentence { max_chars: 20 });
+        let doc = IngestDocument::text(
+            "doc".to_string(),
+            "Apples are red. Bananas are yellow. Cherries are dark.".to_string(),
+        );
+        let report = ingestor
+            .ingest(&LetterEmbedder, &store, vec![doc.clone()])
+            .await
+            .unwrap();
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.usage.input_tokens, 3);
+
+        let updated = IngestDocument {
+            text: "Apples are red. Bananas are green.".to_string(),
+            ..doc.clone()
+        };
+        assert_ne!(updated.hash(), doc.hash());
+        let report = ingestor
+            .reingest(&LetterEmbedder, &store, updated, report.chunks)
+            .await
+            .unwrap();
+        assert_eq!(report.chunks, 2);
+        assert_eq!(report.reused, 1);
+        assert_eq!(report.usage.input_tokens, 1);
+
+        let docs = store
+            .vector_get(vec!["doc#0".into(), "doc#1".into(), "doc#2".into()])
+            .await
+            .unwrap();
+        assert_eq!(
+            docs.iter().map(|d| d.text.as_str()).collect::<Vec<_>>(),
+            vec!["Apples are red.", "Bananas are green."]
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_semantic_chunking() {
+        let ingestor = Ingestor::new(ChunkStrategy::Semantic {
+            max_chars: 200,
+            threshold: 0.5,
+        });
+        let doc = IngestDocument::text(
+            "doc".to_string(),
+            "aaa ab. aab a. xyz zz. zyx y.".to_string(),
+        );
+        let (chunks, usage) = ingestor.chunk(&LetterEmbedder, &doc).await.unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            vec!["aaa ab. aab a.", "xyz zz. zyx y."]
+        );
+        assert_eq!(chunks[1].offset, 15);
+        assert_eq!(usage.requests, 1);
+    }
+}
@@ -0,0 +1,655 @@
+//! Background jobs of the engine.
+//!
+//! Agents and tools defer work, such as a large ingestion or a delayed follow-up, by spawning
+//! a [`JobSpec`]: an agent run or a tool call that the engine executes later on behalf of the
+//! same caller. Jobs go through the same checks as any request of the caller, so their targets
+//! must be exported, and they run with the batch [`Priority`]. Failed jobs are retried with
+//! an exponential backoff per their [`RetryPolicy`].
+//!
+//! Each job is persisted as an object of the engine's [`Store`], so pending jobs survive
+//! a restart, and jobs interrupted by a shutdown run again once the engine is built.
+//! Clients are notified of finished jobs by webhooks, see [`crate::webhook`].
+
+use anda_core::{
+    AgentInput, BoxError, CancellationToken, Path, Priority, PutMode, ToolInput, Value, Xid,
+};
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio::sync::{Mutex, Notify};
+
+use crate::{
+    engine::Engine,
+    store::{Store, is_not_found},
+    webhook::validate_webhook_url,
+};
+
+/// The store namespace of the jobs.
+pub static JOBS_PATH: &str = "_jobs";
+
+/// Maximum delay between two attempts of a job.
+const MAX_BACKOFF_MS: u64 = 3600 * 1000;
+
+/// Maximum time the runner sleeps without checking the due jobs.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+/// The work of a job.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", content = "input", rename_all = "snake_case")]
+pub enum JobTarget {
+    AgentRun(AgentInput),
+    ToolCall(ToolInput<Value>),
+}
+
+impl JobTarget {
+    /// Returns the name of the agent or tool.
+    pub fn name(&self) -> &str {
+        match self {
+            JobTarget::AgentRun(input) => &input.name,
+            JobTarget::ToolCall(input) => &input.name,
+        }
+    }
+}
+
+/// How a failed job is retried, attempts are spaced by `backoff_ms` doubled each time.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the next attempt after `attempts` attempts.
+    pub fn backoff(&self, attempts: u32) -> u64 {
+        self.backoff_ms
+            .saturating_mul(1 << attempts.saturating_sub(1).min(20))
+            .min(MAX_BACKOFF_MS)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+/// A job to spawn.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobSpec {
+    pub target: JobTarget,
+    /// Delay before the first attempt.
+    #[serde(default)]
+    pub delay_ms: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// HTTPS URL notified when the job succeeds or fails, instead of the caller's webhook.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+/// Information about a job.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    /// The caller on whose behalf the job runs.
+    pub caller: Principal,
+    /// The path of the context that spawned the job, e.g. "A:assistant".
+    pub spawned_by: String,
+    pub target: JobTarget,
+    pub retry: RetryPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub created_at_ms: u64,
+    /// Time of the next attempt of a pending job.
+    pub run_at_ms: u64,
+    pub updated_at_ms: u64,
+    /// The error of the last attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The [`AgentOutput`](anda_core::AgentOutput) of the agent or the output of the tool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+}
+
+/// Configuration of the background jobs.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Maximum number of jobs running at once.
+    pub max_concurrency: usize,
+    /// Number of finished jobs kept, the oldest are removed.
+    pub retention: usize,
+    /// Maximum number of pending and running jobs of a caller.
+    pub max_jobs_per_caller: usize,
+    /// Maximum number of attempts of a job.
+    pub max_attempts: u32,
+    /// Maximum delay before the first attempt of a job.
+    pub max_delay_ms: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 4,
+            retention: 1000,
+            max_jobs_per_caller: 100,
+            max_attempts: 10,
+            max_delay_ms: 7 * 24 * 3600 * 1000,
+        }
+    }
+}
+
+fn job_path(id: &str) -> Path {
+    Path::from(format!("{}.cbor", id))
+}
+
+/// The background jobs of an engine.
+pub struct Jobs {
+    store: Store,
+    namespace: Path,
+    cfg: JobsConfig,
+    jobs: RwLock<BTreeMap<String, JobInfo>>,
+    running: std::sync::Mutex<HashMap<String, CancellationToken>>,
+    notify: Notify,
+    // serializes the persistence of the jobs
+    update: Mutex<()>,
+}
+
+impl Jobs {
+    /// Opens the jobs persisted in the store, the running ones were interrupted
+    /// and are pending again.
+    pub async fn open(store: Store, cfg: JobsConfig) -> Result<Self, BoxError> {
+        if cfg.max_concurrency == 0 {
+            return Err("invalid jobs config: max_concurrency must be positive".into());
+        }
+        if cfg.max_jobs_per_caller == 0 || cfg.max_attempts == 0 {
+            return Err(
+                "invalid jobs config: max_jobs_per_caller and max_attempts must be positive".into(),
+            );
+        }
+        let namespace = Path::from(JOBS_PATH);
+        let mut jobs = BTreeMap::new();
+        for meta in store.store_list(&namespace, None, &Path::from("")).await? {
+            let Some(id) = meta
+                .location
+                .filename()
+                .and_then(|name| name.strip_suffix(".cbor"))
+            else {
+                continue;
+            };
+            let mut job: JobInfo = match store.store_get(&namespace, &job_path(id)).await {
+                Ok((data, _)) => ciborium::from_reader(&data[..])?,
+                // removed since listed
+                Err(err) if is_not_found(&err) => continue,
+                Err(err) => return Err(err),
+            };
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Pending;
+            }
+            jobs.insert(job.id.clone(), job);
+        }
+
+        Ok(Self {
+            store,
+            namespace,
+            cfg,
+            jobs: RwLock::new(jobs),
+            running: std::sync::Mutex::new(HashMap::new()),
+            notify: Notify::new(),
+            update: Mutex::new(()),
+        })
+    }
+
+    /// Spawns a job on behalf of the caller.
+    pub async fn spawn(
+        &self,
+        caller: Principal,
+        spawned_by: String,
+        spec: JobSpec,
+    ) -> Result<JobInfo, BoxError> {
+        if spec.target.name().is_empty() {
+            return Err("job target name is empty".into());
+        }
+        if spec.retry.max_attempts == 0 || spec.retry.max_attempts > self.cfg.max_attempts {
+            return Err(format!(
+                "job max_attempts must be between 1 and {}",
+                self.cfg.max_attempts
+            )
+            .into());
+        }
+        if spec.delay_ms > self.cfg.max_delay_ms {
+            return Err(format!("job delay_ms must be at most {}", self.cfg.max_delay_ms).into());
+        }
+        if let Some(url) = &spec.webhook {
+            validate_webhook_url(url)?;
+        }
+
+        let now_ms = unix_ms();
+        let job = JobInfo {
+            id: Xid::new().to_string(),
+            caller,
+            spawned_by,
+            target: spec.target,
+            retry: spec.retry,
+            webhook: spec.webhook,
+            status: JobStatus::Pending,
+            attempts: 0,
+            created_at_ms: now_ms,
+            run_at_ms: now_ms.saturating_add(spec.delay_ms),
+            updated_at_ms: now_ms,
+            error: None,
+            output: None,
+        };
+
+        let _guard = self.update.lock().await;
+        let active = self
+            .jobs
+            .read()
+            .expect("jobs lock poisoned")
+            .values()
+            .filter(|j| j.caller == caller && !j.status.is_finished())
+            .count();
+        if active >= self.cfg.max_jobs_per_caller {
+            return Err(format!(
+                "caller has too many jobs, the limit is {}",
+                self.cfg.max_jobs_per_caller
+            )
+            .into());
+        }
+        self.save(&job).await?;
+        self.jobs
+            .write()
+            .expect("jobs lock poisoned")
+            .insert(job.id.clone(), job.clone());
+        self.notify.notify_one();
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.jobs
+            .read()
+            .expect("jobs lock poisoned")
+            .get(id)
+            .cloned()
+    }
+
+    /// Lists the jobs, of the caller if given, from the oldest.
+    pub fn list(&self, caller: Option<&Principal>) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .jobs
+            .read()
+            .expect("jobs lock poisoned")
+            .values()
+            .filter(|job| caller.is_none_or(|c| &job.caller == c))
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|job| job.created_at_ms);
+        jobs
+    }
+
+    /// Cancels a pending or running job, returns false if it is not found or finished.
+    pub async fn cancel(&self, id: &str) -> Result<bool, BoxError> {
+        if let Some(token) = self.running.lock().expect("jobs lock poisoned").get(id) {
+            // the runner records the cancellation when the attempt ends
+            token.cancel();
+            return Ok(true);
+        }
+        let mut cancelled = false;
+        self.update(id, |job| {
+            if job.status == JobStatus::Pending {
+                job.status = JobStatus::Cancelled;
+                cancelled = true;
+            }
+        })
+        .await?;
+        Ok(cancelled)
+    }
+
+    /// Runs the due jobs on the engine until it shuts down.
+    pub(crate) async fn run(self: Arc<Self>, engine: Engine) {
+        let token = engine.cancellation_token();
+        loop {
+            if engine.is_shutting_down() {
+                return;
+            }
+            let now_ms = unix_ms();
+            for job in self.take_due(now_ms).await {
+                let jobs = self.clone();
+                let engine = engine.clone();
+                tokio::spawn(async move { jobs.execute(engine, job).await });
+            }
+
+            let wait = self
+                .next_run_at()
+                .map(|at| Duration::from_millis(at.saturating_sub(now_ms)))
+                .unwrap_or(IDLE_WAIT)
+                .min(IDLE_WAIT);
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = self.notify.notified() => {},
+                _ = tokio::time::sleep(wait) => {},
+            }
+        }
+    }
+
+    /// Marks the due jobs as running, up to the free slots.
+    async fn take_due(&self, now_ms: u64) -> Vec<(JobInfo, CancellationToken)> {
+        let _guard = self.update.lock().await;
+        let due: Vec<(JobInfo, CancellationToken)> = {
+            let mut running = self.running.lock().expect("jobs lock poisoned");
+            let free = self.cfg.max_concurrency.saturating_sub(running.len());
+            let mut jobs = self.jobs.write().expect("jobs lock poisoned");
+            let mut due: Vec<&mut JobInfo> = jobs
+                .values_mut()
+                .filter(|job| job.status == JobStatus::Pending && job.run_at_ms <= now_ms)
+                .collect();
+            due.sort_by_key(|job| job.run_at_ms);
+            due.into_iter()
+                .take(free)
+                .map(|job| {
+                    job.status = JobStatus::Running;
+                    job.attempts += 1;
+                    job.updated_at_ms = now_ms;
+                    let token = CancellationToken::new();
+                    running.insert(job.id.clone(), token.clone());
+                    (job.clone(), token)
+                })
+                .collect()
+        };
+
+        for (job, _) in &due {
+            if let Err(err) = self.save(job).await {
+                log::error!("failed to save job {}: {}", job.id, err);
+            }
+        }
+        due
+    }
+
+    fn next_run_at(&self) -> Option<u64> {
+        self.jobs
+            .read()
+            .expect("jobs lock poisoned")
+            .values()
+            .filter(|job| job.status == JobStatus::Pending)
+            .map(|job| job.run_at_ms)
+            .min()
+    }
+
+    async fn execute(&self, engine: Engine, (job, token): (JobInfo, CancellationToken)) {
+        let caller = job.caller;
+        let run = async {
+            match job.target.clone() {
+                JobTarget::AgentRun(mut input) => {
+                    input.meta.get_or_insert_default().priority = Priority::Batch;
+                    engine
+                        .agent_run(caller, input)
+                        .await
+                        .and_then(|output| Ok(serde_json::to_value(output)?))
+                }
+                JobTarget::ToolCall(mut input) => {
+                    input.meta.get_or_insert_default().priority = Priority::Batch;
+                    engine
+                        .tool_call(caller, input)
+                        .await
+                        .map(|output| output.output)
+                }
+            }
+        };
+        let res = tokio::select! {
+            res = run => res,
+            _ = token.cancelled() => Err("job cancelled".into()),
+        };
+        self.running
+            .lock()
+            .expect("jobs lock poisoned")
+            .remove(&job.id);
+
+        let now_ms = unix_ms();
+        let interrupted = engine.is_shutting_down();
+        let updated = self
+            .update(&job.id, |j| match res {
+                Ok(output) => {
+                    j.status = JobStatus::Succeeded;
+                    j.error = None;
+                    j.output = Some(output);
+                }
+                Err(err) => {
+                    j.error = Some(err.to_string());
+                    if token.is_cancelled() {
+                        j.status = JobStatus::Cancelled;
+                    } else if interrupted {
+                        // run again by the next engine, without counting the attempt
+                        j.status = JobStatus::Pending;
+                        j.attempts -= 1;
+                    } else if j.attempts < j.retry.max_attempts {
+                        j.status = JobStatus::Pending;
+                        j.run_at_ms = now_ms + j.retry.backoff(j.attempts);
+                    } else {
+                        j.status = JobStatus::Failed;
+                    }
+                }
+            })
+            .await;
+        match updated {
+            Ok(Some(job)) => {
+                log::info!(
+                    job = job.id,
+                    target = job.target.name(),
+                    status = format!("{:?}", job.status),
+                    attempts = job.attempts;
+                    "job attempt ended",
+                );
+                engine.notify_job(job);
+            }
+            Ok(None) => {}
+            Err(err) => log::error!("failed to save job {}: {}", job.id, err),
+        }
+        self.notify.notify_one();
+    }
+
+    /// Updates and persists a job, removing the oldest finished jobs beyond the retention.
+    async fn update(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut JobInfo),
+    ) -> Result<Option<JobInfo>, BoxError> {
+        let _guard = self.update.lock().await;
+        let (job, pruned) = {
+            let mut jobs = self.jobs.write().expect("jobs lock poisoned");
+            let job = match jobs.get_mut(id) {
+                Some(job) => {
+                    f(job);
+                    job.updated_at_ms = unix_ms();
+                    job.clone()
+                }
+                None => return Ok(None),
+            };
+
+            let mut finished: Vec<(u64, String)> = jobs
+                .values()
+                .filter(|j| j.status.is_finished())
+                .map(|j| (j.updated_at_ms, j.id.clone()))
+                .collect();
+            let mut pruned = Vec::new();
+            if finished.len() > self.cfg.retention {
+                finished.sort();
+                for (_, id) in finished.drain(..finished.len() - self.cfg.retention) {
+                    jobs.remove(&id);
+                    pruned.push(id);
+                }
+            }
+            (job, pruned)
+        };
+        self.save(&job).await?;
+        for id in pruned {
+            if let Err(err) = self
+                .store
+                .store_delete(&self.namespace, &job_path(&id))
+                .await
+            {
+                log::warn!("failed to delete job {}: {}", id, err);
+            }
+        }
+        Ok(Some(job))
+    }
+
+    async fn save(&self, job: &JobInfo) -> Result<(), BoxError> {
+        self.store
+            .store_put(
+                &self.namespace,
+                &job_path(&job.id),
+                PutMode::Overwrite,
+                to_cbor_bytes(job).into(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_jobs() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let caller = Principal::from_slice(&[1]);
+        let jobs = Jobs::open(store.clone(), JobsConfig::default())
+            .await
+            .unwrap();
+
+        let spec = |name: &str, delay_ms: u64| JobSpec {
+            target: JobTarget::ToolCall(ToolInput::new(name.to_string(), Value::Null)),
+            delay_ms,
+            retry: RetryPolicy {
+                max_attempts: 3,
+                backoff_ms: 100,
+            },
+            webhook: None,
+        };
+        let j1 = jobs
+            .spawn(caller, "T:ingest".to_string(), spec("ingest", 0))
+            .await
+            .unwrap();
+        let j2 = jobs
+            .spawn(caller, "A:assistant".to_string(), spec("follow_up", 60_000))
+            .await
+            .unwrap();
+        assert_eq!(j1.status, JobStatus::Pending);
+        assert!(
+            jobs.spawn(
+                caller,
+                "T:ingest".to_string(),
+                JobSpec {
+                    webhook: Some("http://example.com".to_string()),
+                    ..spec("ingest", 0)
+                },
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(jobs.list(Some(&caller)).len(), 2);
+        assert!(jobs.list(Some(&Principal::anonymous())).is_empty());
+        assert_eq!(jobs.next_run_at(), Some(j1.run_at_ms));
+
+        let due = jobs.take_due(unix_ms()).await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0.id, j1.id);
+        assert_eq!(jobs.get(&j1.id).unwrap().status, JobStatus::Running);
+        assert_eq!(jobs.get(&j1.id).unwrap().attempts, 1);
+
+        assert!(jobs.cancel(&j2.id).await.unwrap());
+        assert!(!jobs.cancel(&j2.id).await.unwrap());
+        assert!(jobs.cancel(&j1.id).await.unwrap());
+        assert!(due[0].1.is_cancelled());
+
+        // reopened from the store, the running job is pending again
+        let jobs = Jobs::open(store, JobsConfig::default()).await.unwrap();
+        assert_eq!(jobs.get(&j1.id).unwrap().status, JobStatus::Pending);
+        assert_eq!(jobs.get(&j2.id).unwrap().status, JobStatus::Cancelled);
+
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            backoff_ms: 1000,
+        };
+        assert_eq!(policy.backoff(1), 1000);
+        assert_eq!(policy.backoff(3), 4000);
+        assert_eq!(policy.backoff(100), MAX_BACKOFF_MS);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_job_limits() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let caller = Principal::from_slice(&[1]);
+        let cfg = JobsConfig {
+            max_jobs_per_caller: 2,
+            max_attempts: 5,
+            max_delay_ms: 60_000,
+            ..Default::default()
+        };
+        let jobs = Jobs::open(store.clone(), cfg.clone()).await.unwrap();
+
+        let spec = |max_attempts: u32, delay_ms: u64| JobSpec {
+            target: JobTarget::ToolCall(ToolInput::new("ingest".to_string(), Value::Null)),
+            delay_ms,
+            retry: RetryPolicy {
+                max_attempts,
+                backoff_ms: 100,
+            },
+            webhook: None,
+        };
+        let spawn = |spec: JobSpec| jobs.spawn(caller, "T:ingest".to_string(), spec);
+        assert!(spawn(spec(6, 0)).await.is_err());
+        assert!(spawn(spec(5, 60_001)).await.is_err());
+        let j1 = spawn(spec(5, 60_000)).await.unwrap();
+        spawn(spec(1, 0)).await.unwrap();
+        assert!(spawn(spec(1, 0)).await.is_err());
+        // the limit is per caller
+        jobs.spawn(Principal::anonymous(), "T:ingest".to_string(), spec(1, 0))
+            .await
+            .unwrap();
+
+        // finished jobs don't count
+        assert!(jobs.cancel(&j1.id).await.unwrap());
+        spawn(spec(1, 0)).await.unwrap();
+
+        // each job is stored on its own
+        let jobs = Jobs::open(store, cfg).await.unwrap();
+        assert_eq!(jobs.list(Some(&caller)).len(), 3);
+        assert_eq!(jobs.list(None).len(), 4);
+        assert_eq!(jobs.get(&j1.id).unwrap().status, JobStatus::Cancelled);
+    }
+}
@@ -0,0 +1,712 @@
+//! Knowledge collections managed at runtime.
+//!
+//! A [`KnowledgeBase`] keeps named collections of documents in the engine's vector store,
+//! each one in a [`KnowledgeScope`]: the engine, a tenant or a user. Documents are chunked
+//! and embedded by an [`Ingestor`], their chunks are tagged with the `collection` metadata
+//! and their IDs are prefixed by the collection, so collections are isolated in a shared
+//! vector store. The chunks are also labeled with their scope in the
+//! [`VECTOR_ACL_KEY`] metadata, the ones of engine collections as public, so other callers
+//! don't find the chunks of tenant and user collections in the vector store. The registry of the collections and their documents is persisted to the
+//! engine's [`Store`], with the content hashes of the documents: adding an unchanged document
+//! again is a no-op, and an updated document only re-embeds its changed chunks.
+//!
+//! Managers manage the collections with the engine's admin API, agents search the
+//! collections visible to their caller with [`AgentCtx::knowledge`](crate::context::AgentCtx::knowledge).
+
+use anda_core::{
+    BoxError, ByteArrayB64, EmbeddingFeatures, Path, PutMode, VECTOR_ACL_KEY, VECTOR_ACL_PUBLIC,
+    VectorDocument, VectorFilter, VectorMatch, VectorStoreFeatures, validate_function_name,
+};
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::RwLock};
+use structured_logger::unix_ms;
+use tokio::sync::Mutex;
+
+use crate::{
+    ingest::{IngestDocument, IngestReport, Ingestor},
+    store::{Store, is_not_found},
+    vector::VectorIndex,
+};
+
+/// The store namespace of the knowledge collections.
+pub static KNOWLEDGE_PATH: &str = "_knowledge";
+
+/// The owner of a knowledge collection, formatted as `engine`, `tenant:{id}` or `user:{principal}`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KnowledgeScope {
+    /// Visible to all callers.
+    Engine,
+    /// Visible to the members of the tenant.
+    Tenant(String),
+    /// Visible to the user only.
+    User(Principal),
+}
+
+impl fmt::Display for KnowledgeScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KnowledgeScope::Engine => write!(f, "engine"),
+            KnowledgeScope::Tenant(id) => write!(f, "tenant:{}", id),
+            KnowledgeScope::User(user) => write!(f, "user:{}", user.to_text()),
+        }
+    }
+}
+
+impl FromStr for KnowledgeScope {
+    type Err = BoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "engine" => Ok(KnowledgeScope::Engine),
+            Some(("tenant", id)) if !id.is_empty() => Ok(KnowledgeScope::Tenant(id.to_string())),
+            Some(("user", user)) => Principal::from_text(user)
+                .map(KnowledgeScope::User)
+                .map_err(|err| format!("invalid knowledge scope {:?}: {}", s, err).into()),
+            _ => Err(format!(
+                "invalid knowledge scope {:?}, expected engine, tenant:{{id}} or user:{{principal}}",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+impl TryFrom<String> for KnowledgeScope {
+    type Error = BoxError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<KnowledgeScope> for String {
+    fn from(scope: KnowledgeScope) -> Self {
+        scope.to_string()
+    }
+}
+
+/// Information about a knowledge collection.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CollectionInfo {
+    pub scope: KnowledgeScope,
+    pub name: String,
+    pub description: String,
+    pub documents: usize,
+    pub chunks: usize,
+    pub created_by: Principal,
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+/// Information about a document of a knowledge collection.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct KnowledgeDocumentInfo {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub chunks: usize,
+    pub ingested_at_ms: u64,
+    /// The hash of the ingested document, see [`IngestDocument::hash`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<ByteArrayB64<32>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CollectionRecord {
+    info: CollectionInfo,
+    docs: BTreeMap<String, KnowledgeDocumentInfo>,
+}
+
+fn collection_key(scope: &KnowledgeScope, name: &str) -> String {
+    format!("{}/{}", scope, name)
+}
+
+fn collections_path() -> Path {
+    Path::from("collections")
+}
+
+/// A knowledge collection in the vector store, searched like a vector store of its own.
+/// Document IDs and search results are relative to the collection.
+#[derive(Clone)]
+pub struct KnowledgeCollection {
+    key: String,
+    // the access label of the chunks, public for the engine collections
+    acl: String,
+    vectors: VectorIndex,
+}
+
+impl KnowledgeCollection {
+    fn new(scope: &KnowledgeScope, name: &str, vectors: VectorIndex) -> Self {
+        Self {
+            key: collection_key(scope, name),
+            acl: match scope {
+                KnowledgeScope::Engine => VECTOR_ACL_PUBLIC.to_string(),
+                scope => scope.to_string(),
+            },
+            vectors,
+        }
+    }
+
+    /// Returns the collection key, `{scope}/{name}`.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn chunk_id(&self, id: &str) -> String {
+        format!("{}/{}", self.key, id)
+    }
+
+    /// Deletes the chunks of a document.
+    async fn delete_chunks(&self, doc_id: &str, chunks: usize) -> Result<(), BoxError> {
+        if chunks > 0 {
+            self.vectors
+                .vector_delete(
+                    (0..chunks)
+                        .map(|i| self.chunk_id(&format!("{}#{}", doc_id, i)))
+                        .collect(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl VectorStoreFeatures for KnowledgeCollection {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let docs = docs
+            .into_iter()
+            .map(|mut doc| {
+                doc.id = self.chunk_id(&doc.id);
+                doc.meta
+                    .insert("collection".to_string(), self.key.clone().into());
+                doc.meta
+                    .insert(VECTOR_ACL_KEY.to_string(), vec![self.acl.clone()].into());
+                doc
+            })
+            .collect();
+        self.vectors.vector_upsert(docs).await
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        let prefix = format!("{}/", self.key);
+        let mut docs = self
+            .vectors
+            .vector_get(ids.iter().map(|id| self.chunk_id(id)).collect())
+            .await?;
+        for doc in docs.iter_mut() {
+            if let Some(id) = doc.id.strip_prefix(&prefix) {
+                doc.id = id.to_string();
+            }
+            doc.meta.remove("collection");
+        }
+        Ok(docs)
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.vectors
+            .vector_delete(ids.iter().map(|id| self.chunk_id(id)).collect())
+            .await
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        let in_collection = VectorFilter::Eq {
+            key: "collection".to_string(),
+            value: self.key.clone().into(),
+        };
+        let filter = match filter {
+            Some(filter) => VectorFilter::And(vec![in_collection, filter]),
+            None => in_collection,
+        };
+        let prefix = format!("{}/", self.key);
+        let mut matches = self
+            .vectors
+            .vector_search(query, top_k, Some(filter))
+            .await?;
+        for m in matches.iter_mut() {
+            if let Some(id) = m.id.strip_prefix(&prefix) {
+                m.id = id.to_string();
+            }
+            m.meta.remove("collection");
+        }
+        Ok(matches)
+    }
+}
+
+/// The knowledge collections of an engine.
+pub struct KnowledgeBase {
+    store: Store,
+    namespace: Path,
+    vectors: VectorIndex,
+    ingestor: Ingestor,
+    collections: RwLock<BTreeMap<String, CollectionRecord>>,
+    // serializes the updates, including the ingestion, and their persistence
+    update: Mutex<()>,
+}
+
+impl KnowledgeBase {
+    /// Opens the knowledge collections persisted in the store.
+    pub async fn open(
+        store: Store,
+        vectors: VectorIndex,
+        ingestor: Ingestor,
+    ) -> Result<Self, BoxError> {
+        let namespace = Path::from(KNOWLEDGE_PATH);
+        let records: Vec<CollectionRecord> =
+            match store.store_get(&namespace, &collections_path()).await {
+                Ok((data, _)) => ciborium::from_reader(&data[..])?,
+                Err(err) if is_not_found(&err) => Vec::new(),
+                Err(err) => return Err(err),
+            };
+
+        Ok(Self {
+            store,
+            namespace,
+            vectors,
+            ingestor,
+            collections: RwLock::new(
+                records
+                    .into_iter()
+                    .map(|r| (collection_key(&r.info.scope, &r.info.name), r))
+                    .collect(),
+            ),
+            update: Mutex::new(()),
+        })
+    }
+
+    /// Lists the collections, of a scope or all of them.
+    pub fn list(&self, scope: Option<&KnowledgeScope>) -> Vec<CollectionInfo> {
+        self.collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .values()
+            .filter(|r| scope.is_none_or(|s| &r.info.scope == s))
+            .map(|r| r.info.clone())
+            .collect()
+    }
+
+    pub fn get(&self, scope: &KnowledgeScope, name: &str) -> Option<CollectionInfo> {
+        self.collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .get(&collection_key(scope, name))
+            .map(|r| r.info.clone())
+    }
+
+    /// Lists the documents of a collection, None if it is not found.
+    pub fn documents(
+        &self,
+        scope: &KnowledgeScope,
+        name: &str,
+    ) -> Option<Vec<KnowledgeDocumentInfo>> {
+        self.collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .get(&collection_key(scope, name))
+            .map(|r| r.docs.values().cloned().collect())
+    }
+
+    /// Returns a collection to search, None if it is not found.
+    pub fn collection(&self, scope: &KnowledgeScope, name: &str) -> Option<KnowledgeCollection> {
+        let key = collection_key(scope, name);
+        if !self
+            .collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .contains_key(&key)
+        {
+            return None;
+        }
+        Some(KnowledgeCollection::new(scope, name, self.vectors.clone()))
+    }
+
+    /// Creates an empty collection, the name must be unique in the scope.
+    pub async fn create(
+        &self,
+        scope: KnowledgeScope,
+        name: String,
+        description: String,
+        created_by: Principal,
+    ) -> Result<CollectionInfo, BoxError> {
+        validate_function_name(&name)
+            .map_err(|err| format!("invalid collection name {:?}: {}", name, err))?;
+        let key = collection_key(&scope, &name);
+        let _guard = self.update.lock().await;
+        let mut collections = self
+            .collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .clone();
+        if collections.contains_key(&key) {
+            return Err(format!("knowledge collection {} already exists", key).into());
+        }
+
+        let now_ms = unix_ms();
+        let info = CollectionInfo {
+            scope,
+            name,
+            description,
+            documents: 0,
+            chunks: 0,
+            created_by,
+            created_at_ms: now_ms,
+            updated_at_ms: now_ms,
+        };
+        collections.insert(
+            key,
+            CollectionRecord {
+                info: info.clone(),
+                docs: BTreeMap::new(),
+            },
+        );
+        self.save(collections).await?;
+        Ok(info)
+    }
+
+    /// Deletes a collection with its documents, returns false if it is not found.
+    pub async fn delete(&self, scope: &KnowledgeScope, name: &str) -> Result<bool, BoxError> {
+        let key = collection_key(scope, name);
+        let _guard = self.update.lock().await;
+        let mut collections = self
+            .collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .clone();
+        let record = match collections.remove(&key) {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+
+        let collection = KnowledgeCollection::new(scope, name, self.vectors.clone());
+        for doc in record.docs.values() {
+            collection.delete_chunks(&doc.id, doc.chunks).await?;
+        }
+        self.save(collections).await?;
+        Ok(true)
+    }
+
+    /// Adds documents to a collection, or replaces the documents with the same IDs.
+    /// Unchanged documents are skipped, and only the changed chunks of the updated ones
+    /// are re-embedded.
+    pub async fn add_documents(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        scope: &KnowledgeScope,
+        name: &str,
+        docs: Vec<IngestDocument>,
+    ) -> Result<IngestReport, BoxError> {
+        let collection = self
+            .collection(scope, name)
+            .ok_or_else(|| format!("knowledge collection {}/{} not found", scope, name))?;
+        let _guard = self.update.lock().await;
+        let mut collections = self
+            .collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .clone();
+        let record = collections
+            .get_mut(&collection.key)
+            .ok_or_else(|| format!("knowledge collection {} not found", collection.key))?;
+
+        let mut report = IngestReport::default();
+        for doc in docs {
+            let id = doc.id.clone();
+            let source = doc.source.clone();
+            let hash = doc.hash();
+            let previous = record.docs.get(&id);
+            if previous.is_some_and(|d| d.hash.as_ref().is_some_and(|h| h.0 == hash)) {
+                report.skipped += 1;
+                continue;
+            }
+
+            let previous = previous.map(|d| d.chunks).unwrap_or(0);
+            let res = self
+                .ingestor
+                .reingest(embedder, &collection, doc, previous)
+                .await?;
+            record.docs.insert(
+                id.clone(),
+                KnowledgeDocumentInfo {
+                    id,
+                    source,
+                    chunks: res.chunks,
+                    ingested_at_ms: unix_ms(),
+                    hash: Some(ByteArrayB64(hash)),
+                },
+            );
+            report.documents += res.documents;
+            report.chunks += res.chunks;
+            report.reused += res.reused;
+            report.usage.accumulate(&res.usage);
+        }
+        if report.documents == 0 {
+            return Ok(report);
+        }
+
+        record.info.documents = record.docs.len();
+        record.info.chunks = record.docs.values().map(|d| d.chunks).sum();
+        record.info.updated_at_ms = unix_ms();
+        self.save(collections).await?;
+        Ok(report)
+    }
+
+    /// Removes documents from a collection, returns the number of removed documents.
+    pub async fn remove_documents(
+        &self,
+        scope: &KnowledgeScope,
+        name: &str,
+        ids: &[String],
+    ) -> Result<usize, BoxError> {
+        let collection = self
+            .collection(scope, name)
+            .ok_or_else(|| format!("knowledge collection {}/{} not found", scope, name))?;
+        let _guard = self.update.lock().await;
+        let mut collections = self
+            .collections
+            .read()
+            .expect("knowledge lock poisoned")
+            .clone();
+        let record = collections
+            .get_mut(&collection.key)
+            .ok_or_else(|| format!("knowledge collection {} not found", collection.key))?;
+
+        let mut removed = 0;
+        for id in ids {
+            if let Some(doc) = record.docs.remove(id) {
+                collection.delete_chunks(&doc.id, doc.chunks).await?;
+                removed += 1;
+            }
+        }
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        record.info.documents = record.docs.len();
+        record.info.chunks = record.docs.values().map(|d| d.chunks).sum();
+        record.info.updated_at_ms = unix_ms();
+        self.save(collections).await?;
+        Ok(removed)
+    }
+
+    async fn save(&self, collections: BTreeMap<String, CollectionRecord>) -> Result<(), BoxError> {
+        let records: Vec<&CollectionRecord> = collections.values().collect();
+        self.store
+            .store_put(
+                &self.namespace,
+                &collections_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(&records).into(),
+            )
+            .await?;
+        *self.collections.write().expect("knowledge lock poisoned") = collections;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::ChunkStrategy;
+    use anda_core::{Embedding, Usage};
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    /// Embeds the texts by their first letter.
+    struct LetterEmbedder;
+
+    impl EmbeddingFeatures for LetterEmbedder {
+        fn ndims(&self) -> usize {
+            2
+        }
+
+        async fn embed(
+            &self,
+            texts: impl IntoIterator<Item = String> + Send,
+        ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+            let mut res = Vec::new();
+            for text in texts.into_iter().collect::<Vec<_>>() {
+                res.push(self.embed_query(&text).await?.0);
+            }
+            Ok((res, Usage::default()))
+        }
+
+        async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
+            let c = text.bytes().next().unwrap_or(b'a') as f32;
+            Ok((
+                Embedding {
+                    text: text.to_string(),
+                    vec: vec![1.0, c - 96.0],
+                },
+                Usage::default(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_knowledge_scope() {
+        let user = Principal::from_slice(&[1]);
+        for scope in [
+            KnowledgeScope::Engine,
+            KnowledgeScope::Tenant("acme".to_string()),
+            KnowledgeScope::User(user),
+        ] {
+            assert_eq!(scope.to_string().parse::<KnowledgeScope>().unwrap(), scope);
+        }
+        assert_eq!(
+            serde_json::to_string(&KnowledgeScope::Tenant("acme".to_string())).unwrap(),
+            r#""tenant:acme""#
+        );
+        assert!("tenant:".parse::<KnowledgeScope>().is_err());
+        assert!("user:xyz".parse::<KnowledgeScope>().is_err());
+        assert!("global".parse::<KnowledgeScope>().is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_knowledge_base() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let vectors = VectorIndex::in_memory();
+        let ingestor = Ingestor::new(ChunkStrategy::Sentence { max_chars: 20 });
+        let manager = Principal::from_slice(&[1]);
+        let acme = KnowledgeScope::Tenant("acme".to_string());
+        let kb = KnowledgeBase::open(store.clone(), vectors.clone(), ingestor.clone())
+            .await
+            .unwrap();
+
+        kb.create(acme.clone(), "docs".to_string(), "".to_string(), manager)
+            .await
+            .unwrap();
+        kb.create(
+            KnowledgeScope::Engine,
+            "docs".to_string(),
+            "".to_string(),
+            manager,
+        )
+        .await
+        .unwrap();
+        assert!(
+            kb.create(acme.clone(), "docs".to_string(), "".to_string(), manager)
+                .await
+                .is_err()
+        );
+        assert!(
+            kb.create(acme.clone(), "Docs".to_string(), "".to_string(), manager)
+                .await
+                .is_err()
+        );
+
+        let report = kb
+            .add_documents(
+                &LetterEmbedder,
+                &acme,
+                "docs",
+                vec![
+                    IngestDocument::text(
+                        "a".to_string(),
+                        "Apples are red. Apples are sweet.".to_string(),
+                    ),
+                    IngestDocument::text("b".to_string(), "Bananas are yellow.".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.documents, 2);
+        assert_eq!(report.chunks, 3);
+        kb.add_documents(
+            &LetterEmbedder,
+            &KnowledgeScope::Engine,
+            "docs",
+            vec![IngestDocument::text(
+                "c".to_string(),
+                "Cherries.".to_string(),
+            )],
+        )
+        .await
+        .unwrap();
+
+        // searches are scoped to the collection
+        let docs = kb.collection(&acme, "docs").unwrap();
+        let res = docs.vector_search(vec![1.0, 3.0], 10, None).await.unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(
+            res.iter()
+                .all(|m| m.id.starts_with("a#") || m.id.starts_with("b#"))
+        );
+        assert!(res.iter().all(|m| !m.meta.contains_key("collection")));
+        // the tenant's chunks are hidden from other callers of the vector store
+        let res = vectors
+            .vector_search(
+                vec![1.0, 3.0],
+                10,
+                Some(VectorFilter::acl(vec!["tenant:other".to_string()])),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, "engine/docs/c#0");
+
+        // a shorter version replaces all chunks of the document
+        let report = kb
+            .add_documents(
+                &LetterEmbedder,
+                &acme,
+                "docs",
+                vec![IngestDocument::text(
+                    "a".to_string(),
+                    "Apples are red.".to_string(),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!((report.chunks, report.reused), (1, 1));
+        let info = kb.get(&acme, "docs").unwrap();
+        assert_eq!((info.documents, info.chunks), (2, 2));
+        let res = docs.vector_search(vec![1.0, 1.0], 10, None).await.unwrap();
+        assert_eq!(res.len(), 2);
+
+        // reopened from the store
+        let kb = KnowledgeBase::open(store, vectors.clone(), ingestor)
+            .await
+            .unwrap();
+        assert_eq!(kb.list(None).len(), 2);
+        assert_eq!(kb.list(Some(&acme)).len(), 1);
+        assert_eq!(kb.documents(&acme, "docs").unwrap().len(), 2);
+
+        // unchanged documents are skipped
+        let report = kb
+            .add_documents(
+                &LetterEmbedder,
+                &acme,
+                "docs",
+                vec![IngestDocument::text(
+                    "b".to_string(),
+                    "Bananas are yellow.".to_string(),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!((report.documents, report.skipped), (0, 1));
+
+        assert_eq!(
+            kb.remove_documents(&acme, "docs", &["b".to_string(), "x".to_string()])
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(kb.delete(&acme, "docs").await.unwrap());
+        assert!(!kb.delete(&acme, "docs").await.unwrap());
+        assert!(kb.collection(&acme, "docs").is_none());
+        let res = vectors
+            .vector_search(vec![1.0, 1.0], 10, None)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, "engine/docs/c#0");
+    }
+}
@@ -1,11 +1,37 @@
 use rand::Rng;
 
+pub mod a2a;
+pub mod api_key;
+pub mod attachment;
+pub mod audit;
+pub mod config;
 pub mod context;
+pub mod encryption;
 pub mod engine;
 pub mod extension;
+pub mod flags;
+pub mod history;
+pub mod ingest;
+pub mod jobs;
+pub mod knowledge;
 pub mod management;
+pub mod mcp;
+pub mod memory;
+pub mod metering;
+pub mod middleware;
 pub mod model;
+pub mod payment;
+pub mod quota;
+pub mod rate_limit;
+pub mod registry;
+pub mod retrieval;
+pub mod secrets;
+pub mod snapshot;
 pub mod store;
+pub mod telemetry;
+pub mod vector;
+pub mod watcher;
+pub mod webhook;
 
 /// Gets current unix timestamp in milliseconds
 pub use structured_logger::unix_ms;
@@ -1,6 +1,6 @@
 use anda_core::{
-    ANONYMOUS, BaseContext, BoxError, CacheStoreFeatures, MyThreads, RequestMeta, ThreadMeta,
-    ToolInput, UpdateVersion, Xid,
+    ANONYMOUS, BaseContext, BoxError, CacheStoreFeatures, MyThreads, Priority, RequestMeta,
+    ThreadMeta, ToolInput, UpdateVersion, Xid,
 };
 use candid::Principal;
 use serde_json::json;
@@ -77,6 +77,9 @@ impl ManagementBuilder {
                         engine: None,
                         thread: None,
                         user: Some(ctx.name.clone()),
+                        priority: Priority::Interactive,
+                        timeout_ms: None,
+                        locale: None,
                     },
                 )
                 .expect("failed to create system context"),
@@ -197,8 +200,12 @@ impl Management {
                     Err(_) => {
                         let threads = self.load_my_threads().await?;
                         if let Some(agent) = threads.get_agent_by(id) {
-                            let endpoint =
-                                self.ctx.remote.get_endpoint_by_id(agent).ok_or_else(|| {
+                            let endpoint = self
+                                .ctx
+                                .remote
+                                .load()
+                                .get_endpoint_by_id(agent)
+                                .ok_or_else(|| {
                                     format!(
                                         "failed to get the engine endpoint: {}",
                                         agent.to_text()
@@ -0,0 +1,740 @@
+//! Model Context Protocol (MCP) interoperability.
+//!
+//! [MCP](https://modelcontextprotocol.io) is a JSON-RPC 2.0 protocol between AI applications
+//! and the servers providing them tools, over the standard input and output of a local process
+//! or over HTTP. The protocol types are shared with the MCP server of `anda_engine_server`.
+//! This module provides [`McpClient`], which connects to an MCP server with either
+//! transport, lists its tools and registers them as [`McpTool`]s into a [`ToolSet`], so that the
+//! agents of the engine call them as any other tool.
+//!
+//! The HTTP transport is the "Streamable HTTP" transport: each request is POSTed to the server,
+//! which answers with either a JSON response or a stream of server-sent events, and the session
+//! ID returned by the server is sent back with the following requests. HTTP requests go through
+//! the HTTP policy of the engine, which must allow the server's URL.
+//!
+//! # Example
+//! ```rust,ignore
+//! let transport = McpTransport::Stdio {
+//!     command: "npx".to_string(),
+//!     args: vec!["-y".to_string(), "@modelcontextprotocol/server-everything".to_string()],
+//!     env: BTreeMap::new(),
+//! };
+//! let client = McpClient::connect(&web3, transport).await?;
+//! let tools = client.tool_set(&web3, Some("everything_")).await?;
+//! let engine = Engine::builder().register_tools(tools)?.build(default_agent).await?;
+//! ```
+
+use anda_core::{
+    BoxError, ByteBufB64, FunctionDefinition, HttpFeatures, Resource, Tool, ToolOutput, ToolSet,
+    Value,
+};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    process::Stdio,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, Command},
+    sync::{Mutex, oneshot},
+};
+
+use crate::{
+    a2a::{JsonRpcRequest, JsonRpcResponse, METHOD_NOT_FOUND},
+    context::BaseCtx,
+};
+
+/// The protocol version requested by the client.
+pub const MCP_PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// Header of the session ID of the HTTP transport.
+pub static MCP_SESSION_ID: &str = "mcp-session-id";
+
+/// The transport to an MCP server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum McpTransport {
+    /// Spawns the server as a local process, with newline-delimited messages on its
+    /// standard input and output.
+    Stdio {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    /// Connects to the server's endpoint with the Streamable HTTP transport.
+    Http {
+        url: String,
+        /// Headers added to the requests, e.g. the credentials required by the server.
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+/// Information of an MCP server, returned by `initialize`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpServerInfo {
+    #[serde(default)]
+    pub protocol_version: String,
+    #[serde(default)]
+    pub capabilities: Value,
+    #[serde(default)]
+    pub server_info: McpImplementation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+/// Name and version of an MCP client or server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct McpImplementation {
+    pub name: String,
+    pub version: String,
+}
+
+/// A tool of an MCP server, returned by `tools/list`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpToolInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The JSON schema of the tool's arguments.
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListToolsResult {
+    #[serde(default)]
+    tools: Vec<McpToolInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+/// A content item of a tool result.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum McpContent {
+    Text {
+        text: String,
+    },
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    Audio {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    Resource {
+        resource: Value,
+    },
+    /// Content types not supported by the client, skipped.
+    #[serde(other)]
+    Other,
+}
+
+/// The result of a tool call, returned by `tools/call`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct McpCallToolResult {
+    #[serde(default)]
+    pub content: Vec<McpContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl McpCallToolResult {
+    /// Returns the text of the text contents, joined by new lines.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                McpContent::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns a failed result with the error message.
+    pub fn error(message: String) -> Self {
+        Self {
+            content: vec![McpContent::Text { text: message }],
+            structured_content: None,
+            is_error: true,
+        }
+    }
+
+    /// Converts a tool output to a result: a text output as is, otherwise serialized as text
+    /// and as structured content if it is an object, with the image and audio resources.
+    pub fn from_output(output: ToolOutput<Value>) -> Self {
+        let mut content = Vec::new();
+        let structured_content = match output.output {
+            Value::String(text) => {
+                content.push(McpContent::Text { text });
+                None
+            }
+            value => {
+                content.push(McpContent::Text {
+                    text: value.to_string(),
+                });
+                if value.is_object() { Some(value) } else { None }
+            }
+        };
+        for resource in output.resources.unwrap_or_default() {
+            let (Some(mime_type), Some(blob)) = (resource.mime_type, resource.blob) else {
+                continue;
+            };
+            let data = BASE64_STANDARD.encode(&blob.0);
+            if mime_type.starts_with("image/") {
+                content.push(McpContent::Image { data, mime_type });
+            } else if mime_type.starts_with("audio/") {
+                content.push(McpContent::Audio { data, mime_type });
+            }
+        }
+        Self {
+            content,
+            structured_content,
+            is_error: false,
+        }
+    }
+
+    /// Converts the result to a tool output: the structured content if any, otherwise the text
+    /// contents, with the images and audios as resources. Errors if the tool failed.
+    pub fn into_output(self) -> Result<ToolOutput<Value>, BoxError> {
+        if self.is_error {
+            return Err(self.text().into());
+        }
+
+        let mut texts: Vec<String> = Vec::new();
+        let mut others: Vec<Value> = Vec::new();
+        let mut resources: Vec<Resource> = Vec::new();
+        for content in self.content {
+            match content {
+                McpContent::Text { text } => texts.push(text),
+                McpContent::Image { data, mime_type } | McpContent::Audio { data, mime_type } => {
+                    let blob = BASE64_STANDARD.decode(&data)?;
+                    resources.push(Resource {
+                        // tagged by the type of the MIME type, e.g. "image"
+                        tag: mime_type.split('/').next().unwrap_or("file").to_string(),
+                        mime_type: Some(mime_type),
+                        size: Some(blob.len()),
+                        blob: Some(ByteBufB64(blob)),
+                        ..Default::default()
+                    });
+                }
+                McpContent::Resource { resource } => others.push(resource),
+                McpContent::Other => {}
+            }
+        }
+
+        let output = match self.structured_content {
+            Some(structured) => structured,
+            None if others.is_empty() => Value::String(texts.join("\n")),
+            None => {
+                others.extend(texts.into_iter().map(Value::String));
+                Value::Array(others)
+            }
+        };
+        let mut output = ToolOutput::new(output);
+        if !resources.is_empty() {
+            output.resources = Some(resources);
+        }
+        Ok(output)
+    }
+}
+
+/// Converts the name of an MCP tool to a valid tool name with the prefix:
+/// lowercase letters, digits and underscores, starting with a letter, at most 64 characters.
+pub fn mcp_tool_name(prefix: &str, name: &str) -> String {
+    let mut name: String = format!("{}{}", prefix, name)
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        name.insert_str(0, "mcp_");
+    }
+    name.truncate(64);
+    name
+}
+
+/// A connection to the standard input and output of a local MCP server.
+struct StdioConn {
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: Arc<std::sync::Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>>,
+    // the process is killed when the connection is dropped
+    _child: Child,
+}
+
+impl StdioConn {
+    fn spawn(
+        command: &str,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Self, BoxError> {
+        let mut child = Command::new(command)
+            .args(args)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("failed to spawn MCP server {}: {}", command, err))?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().ok_or("MCP server stdin")?));
+        let stdout = child.stdout.take().ok_or("MCP server stdout")?;
+        let pending: Arc<std::sync::Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>> =
+            Arc::new(std::sync::Mutex::new(HashMap::new()));
+
+        let reader_stdin = stdin.clone();
+        let reader_pending = pending.clone();
+        let command = command.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let msg: Value = match serde_json::from_str(&line) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        log::warn!(command = command; "invalid MCP message: {}", err);
+                        continue;
+                    }
+                };
+                if let Some(method) = msg.get("method").and_then(|m| m.as_str()) {
+                    // requests of the server, notifications are ignored
+                    if let Some(id) = msg.get("id") {
+                        let res = if method == "ping" {
+                            JsonRpcResponse::result(id.clone(), json!({}))
+                        } else {
+                            JsonRpcResponse::error(
+                                id.clone(),
+                                METHOD_NOT_FOUND,
+                                format!("method {} not supported", method),
+                            )
+                        };
+                        let _ = write_line(&reader_stdin, &res).await;
+                    }
+                    continue;
+                }
+                match serde_json::from_value::<JsonRpcResponse>(msg) {
+                    Ok(res) => {
+                        let tx = reader_pending.lock().unwrap().remove(&res.id.to_string());
+                        if let Some(tx) = tx {
+                            let _ = tx.send(res);
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!(command = command; "invalid MCP response: {}", err);
+                    }
+                }
+            }
+            // the pending requests fail when their senders are dropped
+            reader_pending.lock().unwrap().clear();
+        });
+
+        Ok(Self {
+            stdin,
+            pending,
+            _child: child,
+        })
+    }
+
+    async fn request(&self, req: JsonRpcRequest) -> Result<JsonRpcResponse, BoxError> {
+        let (tx, rx) = oneshot::channel();
+        let key = req.id.to_string();
+        self.pending.lock().unwrap().insert(key.clone(), tx);
+        if let Err(err) = write_line(&self.stdin, &req).await {
+            self.pending.lock().unwrap().remove(&key);
+            return Err(err);
+        }
+        rx.await
+            .map_err(|_| format!("MCP server closed before responding to {}", req.method).into())
+    }
+
+    async fn notify(&self, msg: &Value) -> Result<(), BoxError> {
+        write_line(&self.stdin, msg).await
+    }
+}
+
+async fn write_line(stdin: &Mutex<ChildStdin>, msg: &impl Serialize) -> Result<(), BoxError> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(&line).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// A connection to the endpoint of a remote MCP server.
+struct HttpConn {
+    url: String,
+    headers: http::HeaderMap,
+    session_id: RwLock<Option<http::HeaderValue>>,
+}
+
+impl HttpConn {
+    fn new(url: &str, headers: &BTreeMap<String, String>) -> Result<Self, BoxError> {
+        let mut map = http::HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(
+                http::HeaderName::from_bytes(name.as_bytes())?,
+                http::HeaderValue::from_str(value)?,
+            );
+        }
+        map.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/json"),
+        );
+        map.insert(
+            http::header::ACCEPT,
+            http::HeaderValue::from_static("application/json, text/event-stream"),
+        );
+        Ok(Self {
+            url: url.to_string(),
+            headers: map,
+            session_id: RwLock::new(None),
+        })
+    }
+
+    async fn post(
+        &self,
+        ctx: &impl HttpFeatures,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, BoxError> {
+        let mut headers = self.headers.clone();
+        if let Some(id) = self.session_id.read().unwrap().clone() {
+            headers.insert(MCP_SESSION_ID, id);
+        }
+        let res = ctx
+            .https_call(
+                &self.url,
+                http::Method::POST,
+                Some(headers),
+                Some(serde_json::to_vec(body)?),
+            )
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!(
+                "MCP request to {} failed, status: {}, body: {}",
+                self.url, status, body
+            )
+            .into());
+        }
+        if let Some(id) = res.headers().get(MCP_SESSION_ID) {
+            *self.session_id.write().unwrap() = Some(id.clone());
+        }
+        Ok(res)
+    }
+
+    async fn request(
+        &self,
+        ctx: &impl HttpFeatures,
+        req: JsonRpcRequest,
+    ) -> Result<JsonRpcResponse, BoxError> {
+        let mut res = self.post(ctx, &req).await?;
+        let is_stream = res
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with(anda_core::CONTENT_TYPE_EVENT_STREAM));
+        if !is_stream {
+            return Ok(res.json().await?);
+        }
+
+        // the stream may carry requests and notifications of the server before the response
+        let mut parser = anda_core::SseParser::new();
+        while let Some(chunk) = res.chunk().await? {
+            for event in parser.feed(&chunk) {
+                let msg: Value = match serde_json::from_str(&event.data) {
+                    Ok(msg) => msg,
+                    Err(_) => continue,
+                };
+                if msg.get("method").is_none() && msg.get("id") == Some(&req.id) {
+                    return Ok(serde_json::from_value(msg)?);
+                }
+            }
+        }
+        Err(format!("MCP stream ended before responding to {}", req.method).into())
+    }
+
+    async fn notify(&self, ctx: &impl HttpFeatures, msg: &Value) -> Result<(), BoxError> {
+        self.post(ctx, msg).await?;
+        Ok(())
+    }
+}
+
+enum Conn {
+    Stdio(StdioConn),
+    Http(HttpConn),
+}
+
+/// A client of an MCP server.
+pub struct McpClient {
+    conn: Conn,
+    info: McpServerInfo,
+    next_id: AtomicU64,
+}
+
+impl McpClient {
+    /// Connects to the MCP server and initializes the session.
+    /// `ctx` sends the requests of the HTTP transport, it is unused by the stdio transport.
+    pub async fn connect(
+        ctx: &impl HttpFeatures,
+        transport: McpTransport,
+    ) -> Result<Arc<Self>, BoxError> {
+        let conn = match &transport {
+            McpTransport::Stdio { command, args, env } => {
+                Conn::Stdio(StdioConn::spawn(command, args, env)?)
+            }
+            McpTransport::Http { url, headers } => Conn::Http(HttpConn::new(url, headers)?),
+        };
+        let mut client = Self {
+            conn,
+            info: McpServerInfo::default(),
+            next_id: AtomicU64::new(1),
+        };
+        client.info = client
+            .request(
+                ctx,
+                "initialize",
+                json!({
+                    "protocolVersion": MCP_PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "anda",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        client
+            .notify(
+                ctx,
+                json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            )
+            .await?;
+        Ok(Arc::new(client))
+    }
+
+    /// Returns the information of the server.
+    pub fn info(&self) -> &McpServerInfo {
+        &self.info
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        ctx: &impl HttpFeatures,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<T, BoxError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let req = JsonRpcRequest::new(Value::from(id), method, params)?;
+        let res = match &self.conn {
+            Conn::Stdio(conn) => conn.request(req).await?,
+            Conn::Http(conn) => conn.request(ctx, req).await?,
+        };
+        if let Some(err) = res.error {
+            return Err(format!("MCP {} error {}: {}", method, err.code, err.message).into());
+        }
+        Ok(serde_json::from_value(res.result.unwrap_or_default())?)
+    }
+
+    async fn notify(&self, ctx: &impl HttpFeatures, msg: Value) -> Result<(), BoxError> {
+        match &self.conn {
+            Conn::Stdio(conn) => conn.notify(&msg).await,
+            Conn::Http(conn) => conn.notify(ctx, &msg).await,
+        }
+    }
+
+    /// Lists all the tools of the server with `tools/list`.
+    pub async fn list_tools(&self, ctx: &impl HttpFeatures) -> Result<Vec<McpToolInfo>, BoxError> {
+        let mut tools: Vec<McpToolInfo> = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let page: ListToolsResult = self.request(ctx, "tools/list", params).await?;
+            tools.extend(page.tools);
+            match page.next_cursor {
+                Some(next) if Some(&next) != cursor.as_ref() => cursor = Some(next),
+                _ => return Ok(tools),
+            }
+        }
+    }
+
+    /// Calls a tool of the server with `tools/call`.
+    pub async fn call_tool(
+        &self,
+        ctx: &impl HttpFeatures,
+        name: &str,
+        args: Value,
+    ) -> Result<McpCallToolResult, BoxError> {
+        self.request(ctx, "tools/call", json!({"name": name, "arguments": args}))
+            .await
+    }
+
+    /// Lists the tools of the server and returns them as a tool set to register into the
+    /// engine, named after the MCP tools with the prefix, see [`mcp_tool_name`].
+    pub async fn tool_set(
+        self: &Arc<Self>,
+        ctx: &impl HttpFeatures,
+        prefix: Option<&str>,
+    ) -> Result<ToolSet<BaseCtx>, BoxError> {
+        let mut tools = ToolSet::new();
+        for info in self.list_tools(ctx).await? {
+            tools.add(McpTool::new(self.clone(), info, prefix.unwrap_or_default()))?;
+        }
+        Ok(tools)
+    }
+}
+
+/// A tool of an MCP server.
+pub struct McpTool {
+    client: Arc<McpClient>,
+    name: String,
+    info: McpToolInfo,
+}
+
+impl McpTool {
+    pub fn new(client: Arc<McpClient>, info: McpToolInfo, prefix: &str) -> Self {
+        Self {
+            client,
+            name: mcp_tool_name(prefix, &info.name),
+            info,
+        }
+    }
+}
+
+impl Tool<BaseCtx> for McpTool {
+    type Args = Value;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.info.description.clone().unwrap_or_default()
+    }
+
+    fn definition(&self) -> FunctionDefinition {
+        let parameters = match &self.info.input_schema {
+            Value::Object(_) => self.info.input_schema.clone(),
+            _ => json!({"type": "object", "properties": {}}),
+        };
+        FunctionDefinition {
+            name: self.name.clone(),
+            description: self.description(),
+            parameters,
+            strict: None,
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: BaseCtx,
+        args: Self::Args,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<ToolOutput<Self::Output>, BoxError> {
+        let res = self.client.call_tool(&ctx, &self.info.name, args).await?;
+        res.into_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mcp_tool_name() {
+        assert_eq!(mcp_tool_name("", "get_weather"), "get_weather");
+        assert_eq!(mcp_tool_name("fs_", "read-File"), "fs_read_file");
+        assert_eq!(mcp_tool_name("", "2fa.code"), "mcp_2fa_code");
+        let name = mcp_tool_name("", &"a".repeat(80));
+        assert_eq!(name.len(), 64);
+        assert!(anda_core::validate_function_name(&name).is_ok());
+    }
+
+    #[test]
+    fn test_mcp_call_tool_result() {
+        let res: McpCallToolResult = serde_json::from_value(json!({
+            "content": [
+                {"type": "text", "text": "hello"},
+                {"type": "image", "data": "aGk=", "mimeType": "image/png"},
+                {"type": "text", "text": "world"},
+            ],
+        }))
+        .unwrap();
+        let output = res.into_output().unwrap();
+        assert_eq!(output.output, json!("hello\nworld"));
+        let resources = output.resources.unwrap();
+        assert_eq!(resources[0].tag, "image");
+        assert_eq!(resources[0].blob.as_ref().unwrap().0, b"hi".to_vec());
+
+        let res: McpCallToolResult = serde_json::from_value(json!({
+            "content": [{"type": "text", "text": "{\"temp\":20}"}],
+            "structuredContent": {"temp": 20},
+        }))
+        .unwrap();
+        assert_eq!(res.into_output().unwrap().output, json!({"temp": 20}));
+
+        let res: McpCallToolResult = serde_json::from_value(json!({
+            "content": [{"type": "text", "text": "not found"}],
+            "isError": true,
+        }))
+        .unwrap();
+        assert_eq!(res.into_output().unwrap_err().to_string(), "not found");
+
+        let mut output = ToolOutput::new(json!({"temp": 20}));
+        output.resources = Some(vec![Resource {
+            tag: "image".to_string(),
+            mime_type: Some("image/png".to_string()),
+            blob: Some(ByteBufB64(b"hi".to_vec())),
+            ..Default::default()
+        }]);
+        let res = McpCallToolResult::from_output(output);
+        assert_eq!(res.structured_content, Some(json!({"temp": 20})));
+        assert_eq!(
+            res.content[1],
+            McpContent::Image {
+                data: "aGk=".to_string(),
+                mime_type: "image/png".to_string(),
+            }
+        );
+        let output = res.into_output().unwrap();
+        assert_eq!(output.output, json!({"temp": 20}));
+        assert_eq!(output.resources.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mcp_transport() {
+        let transport: McpTransport = serde_json::from_value(json!({
+            "type": "stdio",
+            "command": "npx",
+            "args": ["-y", "server"],
+        }))
+        .unwrap();
+        assert!(matches!(transport, McpTransport::Stdio { ref env, .. } if env.is_empty()));
+    }
+}
@@ -0,0 +1,179 @@
+//! Conversation memory of the agents.
+//!
+//! With memory enabled by [`crate::engine::EngineBuilder::with_memory`], an agent keeps the
+//! chat history of each thread, see [`anda_core::RequestMeta::thread`], so that it is no longer
+//! stateless between two runs of the same conversation. The history of a thread is scoped by
+//! the agent and the caller, and persisted to the engine's store: [`AgentCtx::completion`]
+//! loads it before the request's own chat history, and appends the prompt and the answer to it.
+//!
+//! Agents may also manage the history explicitly with the [`Memory`] of their context,
+//! see [`AgentCtx::memory`].
+//!
+//! [`AgentCtx::completion`]: anda_core::CompletionFeatures::completion
+//! [`AgentCtx::memory`]: crate::context::AgentCtx::memory
+
+use anda_core::{
+    BoxError, CacheFeatures, CacheStoreFeatures, Message, StateFeatures, UpdateVersion, Xid,
+};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+
+use crate::{context::BaseCtx, store::is_not_found};
+
+/// Maximum number of attempts to append to a history updated concurrently.
+const MAX_APPEND_ATTEMPTS: usize = 3;
+
+/// Configuration of the conversation memory.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    /// Maximum number of messages kept per thread, the oldest are dropped.
+    pub max_messages: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self { max_messages: 40 }
+    }
+}
+
+/// The chat history of a thread, as persisted.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ThreadHistory {
+    /// The messages of the thread, from the oldest.
+    pub messages: Vec<Message>,
+    /// The timestamp in milliseconds of the last update.
+    pub updated_at: u64,
+}
+
+/// The memory of an agent for a thread of a caller.
+#[derive(Clone)]
+pub struct Memory {
+    ctx: BaseCtx,
+    key: String,
+    thread: Xid,
+    config: MemoryConfig,
+}
+
+impl Memory {
+    pub(crate) fn new(ctx: BaseCtx, caller: &Principal, thread: Xid, config: MemoryConfig) -> Self {
+        Self {
+            ctx,
+            key: format!("MEM_{}_{}.cbor", caller.to_text(), thread.xid()),
+            thread,
+            config,
+        }
+    }
+
+    /// Returns the thread of the memory.
+    pub fn thread(&self) -> &Xid {
+        &self.thread
+    }
+
+    /// Loads the messages of the thread, empty for a new thread.
+    pub async fn load(&self) -> Result<Vec<Message>, BoxError> {
+        match self.get().await? {
+            Some((history, _)) => Ok(history.messages),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Appends messages to the thread, keeping the latest [`MemoryConfig::max_messages`].
+    /// A history that starts with an answer is trimmed to its first prompt.
+    pub async fn append(&self, messages: Vec<Message>) -> Result<(), BoxError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (mut history, version) = match self.get().await? {
+                Some((history, version)) => (history, Some(version)),
+                None => (ThreadHistory::default(), None),
+            };
+            history.messages.extend(messages.iter().cloned());
+            truncate(&mut history.messages, self.config.max_messages);
+            history.updated_at = self.ctx.now_ms();
+            match self.ctx.cache_store_set(&self.key, history, version).await {
+                Ok(_) => return Ok(()),
+                Err(err) if attempt < MAX_APPEND_ATTEMPTS && is_conflict(&err) => {
+                    // the cached history is stale, reloads it from the store
+                    self.ctx.cache_delete(&self.key).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Deletes the messages of the thread.
+    pub async fn clear(&self) -> Result<(), BoxError> {
+        match self.ctx.cache_store_delete(&self.key).await {
+            Err(err) if !is_not_found(&err) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get(&self) -> Result<Option<(ThreadHistory, UpdateVersion)>, BoxError> {
+        match self.ctx.cache_store_get::<ThreadHistory>(&self.key).await {
+            Ok(res) => Ok(Some(res)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Keeps the latest `max` messages, starting with a user message if any.
+fn truncate(messages: &mut Vec<Message>, max: usize) {
+    if messages.len() > max {
+        messages.drain(..messages.len() - max);
+    }
+    let start = messages
+        .iter()
+        .position(|m| m.role == "user")
+        .unwrap_or(messages.len());
+    messages.drain(..start);
+}
+
+fn is_conflict(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::Precondition { .. } | object_store::Error::AlreadyExists { .. })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut messages = vec![
+            message("user", "1"),
+            message("assistant", "2"),
+            message("user", "3"),
+            message("assistant", "4"),
+        ];
+        truncate(&mut messages, 3);
+        let contents: Vec<&str> = messages
+            .iter()
+            .map(|m| m.content.as_str().unwrap())
+            .collect();
+        assert_eq!(contents, ["3", "4"]);
+
+        truncate(&mut messages, 10);
+        assert_eq!(messages.len(), 2);
+
+        let mut messages = vec![message("assistant", "1")];
+        truncate(&mut messages, 10);
+        assert!(messages.is_empty());
+    }
+}
@@ -0,0 +1,374 @@
+//! Usage metering and billing records.
+//!
+//! The engine meters the usage of each caller: LLM tokens and their estimated cost, agent runs,
+//! tool calls, bytes written to the store and calls to remote engines. Usage is aggregated in
+//! memory by period, one hour by default, and each closed period is persisted to the store as [`BillingRecord`]s,
+//! one per caller. The current period is persisted too when the engine shuts down.
+//!
+//! Records are exported with [`Metering::records`], as JSON or CSV with
+//! [`BillingRecord::to_csv`], and pushed to external billing systems by [`BillingHook`]s.
+//! A hook receives the usage recorded since the last flush, so a period interrupted by
+//! a restart is reported in more than one batch, to be summed by the receiver.
+
+use anda_core::{BoxError, Path, PutMode, Usage};
+use async_trait::async_trait;
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use structured_logger::unix_ms;
+use tokio_util::sync::CancellationToken;
+
+use crate::store::Store;
+
+/// The store namespace of the billing records.
+pub static METERING_PATH: &str = "_metering";
+
+/// Configuration of the usage metering.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MeteringConfig {
+    /// Length of a billing period, in seconds.
+    pub period_secs: u64,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        Self { period_secs: 3600 }
+    }
+}
+
+/// Usage counters of a caller.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UsageCounters {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub agent_runs: u64,
+    pub tool_calls: u64,
+    /// Bytes written to the store.
+    pub storage_bytes: u64,
+    /// Agent runs and tool calls on remote engines.
+    pub remote_calls: u64,
+    /// The estimated cost of the tokens, in millionths of USD.
+    #[serde(default)]
+    pub cost_micro_usd: u64,
+}
+
+impl UsageCounters {
+    /// Adds the tokens of an agent run or a tool call, and their cost.
+    pub fn add_tokens(&mut self, usage: &Usage) {
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+        self.cost_micro_usd += (usage.cost_usd * 1_000_000.0).round() as u64;
+    }
+
+    pub fn accumulate(&mut self, other: &UsageCounters) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.agent_runs += other.agent_runs;
+        self.tool_calls += other.tool_calls;
+        self.storage_bytes += other.storage_bytes;
+        self.remote_calls += other.remote_calls;
+        self.cost_micro_usd += other.cost_micro_usd;
+    }
+}
+
+/// Usage of a caller in a billing period.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BillingRecord {
+    pub period_start_ms: u64,
+    pub period_end_ms: u64,
+    pub caller: Principal,
+    #[serde(flatten)]
+    pub usage: UsageCounters,
+}
+
+impl BillingRecord {
+    /// Formats the records as CSV, with a header line.
+    pub fn to_csv(records: &[BillingRecord]) -> String {
+        let mut csv = String::from(
+            "period_start_ms,period_end_ms,caller,input_tokens,output_tokens,agent_runs,tool_calls,storage_bytes,remote_calls,cost_micro_usd\n",
+        );
+        for r in records {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                r.period_start_ms,
+                r.period_end_ms,
+                r.caller.to_text(),
+                r.usage.input_tokens,
+                r.usage.output_tokens,
+                r.usage.agent_runs,
+                r.usage.tool_calls,
+                r.usage.storage_bytes,
+                r.usage.remote_calls,
+                r.usage.cost_micro_usd,
+            ));
+        }
+        csv
+    }
+}
+
+/// Hook of an external billing system.
+#[async_trait]
+pub trait BillingHook: Send + Sync {
+    /// Called with the usage recorded since the last flush, once it is persisted.
+    async fn on_records(&self, records: &[BillingRecord]) -> Result<(), BoxError>;
+}
+
+fn index_path() -> Path {
+    Path::from("index")
+}
+
+fn period_path(start_ms: u64) -> Path {
+    Path::from(format!("periods/{:020}", start_ms))
+}
+
+/// The usage meter of an engine.
+pub struct Metering {
+    store: Store,
+    namespace: Path,
+    period_ms: u64,
+    hooks: Vec<Arc<dyn BillingHook>>,
+    // usage not flushed yet, by period start and caller
+    pending: Mutex<BTreeMap<(u64, Principal), UsageCounters>>,
+    // serializes the flushes
+    flush: tokio::sync::Mutex<()>,
+}
+
+impl Metering {
+    pub fn new(store: Store, cfg: MeteringConfig, hooks: Vec<Arc<dyn BillingHook>>) -> Self {
+        Self {
+            store,
+            namespace: Path::from(METERING_PATH),
+            period_ms: cfg.period_secs.max(1) * 1000,
+            hooks,
+            pending: Mutex::new(BTreeMap::new()),
+            flush: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Records usage of the caller in the current period.
+    pub fn record(&self, caller: Principal, f: impl FnOnce(&mut UsageCounters)) {
+        let start = self.period_start(unix_ms());
+        let mut pending = self.pending.lock().expect("metering lock poisoned");
+        f(pending.entry((start, caller)).or_default());
+    }
+
+    fn period_start(&self, now_ms: u64) -> u64 {
+        now_ms - now_ms % self.period_ms
+    }
+
+    /// Persists the usage of the closed periods, or of all periods if `all`,
+    /// then calls the hooks. Returns the number of records flushed.
+    pub async fn flush(&self, all: bool) -> Result<usize, BoxError> {
+        let _guard = self.flush.lock().await;
+        let current = self.period_start(unix_ms());
+        let flushed: BTreeMap<(u64, Principal), UsageCounters> = {
+            let mut pending = self.pending.lock().expect("metering lock poisoned");
+            let (flushed, open) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition(|((start, _), _)| all || *start < current);
+            *pending = open;
+            flushed
+        };
+        if flushed.is_empty() {
+            return Ok(0);
+        }
+
+        let records: Vec<BillingRecord> = flushed
+            .iter()
+            .map(|((start, caller), usage)| BillingRecord {
+                period_start_ms: *start,
+                period_end_ms: start + self.period_ms,
+                caller: *caller,
+                usage: *usage,
+            })
+            .collect();
+        if let Err(err) = self.save(&records).await {
+            // kept for the next flush
+            let mut pending = self.pending.lock().expect("metering lock poisoned");
+            for (key, usage) in flushed {
+                pending.entry(key).or_default().accumulate(&usage);
+            }
+            return Err(err);
+        }
+
+        for hook in &self.hooks {
+            if let Err(err) = hook.on_records(&records).await {
+                log::error!("billing hook failed: {}", err);
+            }
+        }
+        Ok(records.len())
+    }
+
+    /// Merges the records into the persisted periods.
+    async fn save(&self, records: &[BillingRecord]) -> Result<(), BoxError> {
+        let mut index = self.index().await?;
+        let mut periods: BTreeMap<u64, Vec<&BillingRecord>> = BTreeMap::new();
+        for r in records {
+            periods.entry(r.period_start_ms).or_default().push(r);
+        }
+        for (start, added) in periods {
+            let mut saved = self.period(start).await?;
+            for r in added {
+                match saved.iter_mut().find(|s| s.caller == r.caller) {
+                    Some(s) => s.usage.accumulate(&r.usage),
+                    None => saved.push(r.clone()),
+                }
+            }
+            self.store
+                .store_put(
+                    &self.namespace,
+                    &period_path(start),
+                    PutMode::Overwrite,
+                    to_cbor_bytes(&saved).into(),
+                )
+                .await?;
+            if let Err(i) = index.binary_search(&start) {
+                index.insert(i, start);
+            }
+        }
+        self.store
+            .store_put(
+                &self.namespace,
+                &index_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(&index).into(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn index(&self) -> Result<Vec<u64>, BoxError> {
+        match self.store.store_get(&self.namespace, &index_path()).await {
+            Ok((data, _)) => Ok(ciborium::from_reader(&data[..])?),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    async fn period(&self, start_ms: u64) -> Result<Vec<BillingRecord>, BoxError> {
+        match self
+            .store
+            .store_get(&self.namespace, &period_path(start_ms))
+            .await
+        {
+            Ok((data, _)) => Ok(ciborium::from_reader(&data[..])?),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the records of the periods starting in `[from_ms, to_ms)`,
+    /// including the usage not flushed yet, ordered by period and caller.
+    pub async fn records(&self, from_ms: u64, to_ms: u64) -> Result<Vec<BillingRecord>, BoxError> {
+        let mut records: BTreeMap<(u64, Principal), BillingRecord> = BTreeMap::new();
+        for start in self.index().await? {
+            if start < from_ms || start >= to_ms {
+                continue;
+            }
+            for r in self.period(start).await? {
+                records.insert((start, r.caller), r);
+            }
+        }
+
+        let pending = self.pending.lock().expect("metering lock poisoned");
+        for ((start, caller), usage) in pending.iter() {
+            if *start < from_ms || *start >= to_ms {
+                continue;
+            }
+            records
+                .entry((*start, *caller))
+                .or_insert_with(|| BillingRecord {
+                    period_start_ms: *start,
+                    period_end_ms: start + self.period_ms,
+                    caller: *caller,
+                    usage: UsageCounters::default(),
+                })
+                .usage
+                .accumulate(usage);
+        }
+        Ok(records.into_values().collect())
+    }
+
+    /// Flushes the closed periods at the end of each period until cancelled.
+    pub(crate) async fn run(self: Arc<Self>, token: CancellationToken) {
+        loop {
+            let now_ms = unix_ms();
+            let wait = self.period_start(now_ms) + self.period_ms - now_ms;
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = tokio::time::sleep(Duration::from_millis(wait)) => {},
+            }
+            if let Err(err) = self.flush(false).await {
+                log::error!("failed to flush usage metering: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    struct TestHook(Mutex<Vec<BillingRecord>>);
+
+    #[async_trait]
+    impl BillingHook for TestHook {
+        async fn on_records(&self, records: &[BillingRecord]) -> Result<(), BoxError> {
+            self.0.lock().unwrap().extend_from_slice(records);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_metering() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let hook = Arc::new(TestHook(Mutex::new(Vec::new())));
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let metering = Metering::new(store.clone(), MeteringConfig::default(), vec![hook.clone()]);
+
+        metering.record(alice, |u| {
+            u.agent_runs += 1;
+            u.add_tokens(&Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                requests: 1,
+                cost_usd: 0.0025,
+                ..Default::default()
+            });
+        });
+        metering.record(bob, |u| u.storage_bytes += 100);
+        // the current period is still open
+        assert_eq!(metering.flush(false).await.unwrap(), 0);
+        assert_eq!(metering.flush(true).await.unwrap(), 2);
+        assert_eq!(hook.0.lock().unwrap().len(), 2);
+
+        metering.record(alice, |u| u.tool_calls += 1);
+        let records = metering.records(0, u64::MAX).await.unwrap();
+        assert_eq!(records.len(), 2);
+        let usage = records.iter().find(|r| r.caller == alice).unwrap().usage;
+        assert_eq!(usage.agent_runs, 1);
+        assert_eq!(usage.tool_calls, 1);
+        assert_eq!(usage.input_tokens, 10);
+        assert_eq!(usage.cost_micro_usd, 2500);
+
+        // merged into the persisted period
+        assert_eq!(metering.flush(true).await.unwrap(), 1);
+        let metering = Metering::new(store, MeteringConfig::default(), Vec::new());
+        let records = metering.records(0, u64::MAX).await.unwrap();
+        assert_eq!(records.len(), 2);
+        let usage = records.iter().find(|r| r.caller == alice).unwrap().usage;
+        assert_eq!(usage.tool_calls, 1);
+        assert_eq!(usage.output_tokens, 5);
+        assert!(metering.records(0, 1).await.unwrap().is_empty());
+
+        let csv = BillingRecord::to_csv(&records);
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.contains(&alice.to_text()));
+    }
+}
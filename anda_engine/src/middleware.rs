@@ -0,0 +1,333 @@
+//! Middlewares around tool calls and hooks around agent runs.
+//!
+//! A [`ToolMiddleware`] registered with [`crate::engine::EngineBuilder::with_tool_middleware`]
+//! wraps every call of a tool: the calls of the engine's callers, the calls requested by the
+//! agents' models, and the calls of remote tools. It implements cross-cutting concerns, such as
+//! logging, validating or rewriting the arguments, redacting the outputs or collecting metrics,
+//! without wrapping each tool.
+//!
+//! The middlewares run in the order they are registered before a call, and in reverse order
+//! after it, so that the first one registered is the outermost.
+//!
+//! Likewise, an [`AgentHook`] registered with [`crate::engine::EngineBuilder::with_agent_hook`]
+//! is called around every run of a local agent, by the engine's callers or by other agents,
+//! with the child context of the run. It implements auditing, guardrails on the prompts or
+//! post-processing of the outputs centrally.
+//!
+//! # Example
+//! ```rust,ignore
+//! struct Logging;
+//!
+//! #[async_trait]
+//! impl ToolMiddleware for Logging {
+//!     async fn after(
+//!         &self,
+//!         _ctx: &BaseCtx,
+//!         tool: &str,
+//!         result: Result<ToolOutput<Value>, BoxError>,
+//!     ) -> Result<ToolOutput<Value>, BoxError> {
+//!         log::info!(tool = tool, ok = result.is_ok(); "tool called");
+//!         result
+//!     }
+//! }
+//!
+//! let engine = Engine::builder()
+//!     .with_tool_middleware(Arc::new(Logging))
+//!     .build(default_agent)
+//!     .await?;
+//! ```
+
+use anda_core::{AgentOutput, BoxError, Resource, ToolOutput, Value};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::context::{AgentCtx, BaseCtx};
+
+/// A middleware around tool calls.
+#[async_trait]
+pub trait ToolMiddleware: Send + Sync {
+    /// Called before a tool is called, with its arguments that it may rewrite.
+    /// An error rejects the call.
+    async fn before(&self, _ctx: &BaseCtx, _tool: &str, _args: &mut Value) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Called after a tool is called, with its result that it may replace.
+    async fn after(
+        &self,
+        _ctx: &BaseCtx,
+        _tool: &str,
+        result: Result<ToolOutput<Value>, BoxError>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        result
+    }
+}
+
+/// The middlewares of the engine, in the order they are registered.
+#[derive(Clone, Default)]
+pub struct ToolMiddlewares {
+    middlewares: Vec<Arc<dyn ToolMiddleware>>,
+}
+
+impl ToolMiddlewares {
+    pub fn new(middlewares: Vec<Arc<dyn ToolMiddleware>>) -> Self {
+        Self { middlewares }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    /// Runs the middlewares before a call, in order.
+    pub async fn before(
+        &self,
+        ctx: &BaseCtx,
+        tool: &str,
+        args: &mut Value,
+    ) -> Result<(), BoxError> {
+        for middleware in &self.middlewares {
+            middleware.before(ctx, tool, args).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the middlewares after a call, in reverse order.
+    pub async fn after(
+        &self,
+        ctx: &BaseCtx,
+        tool: &str,
+        mut result: Result<ToolOutput<Value>, BoxError>,
+    ) -> Result<ToolOutput<Value>, BoxError> {
+        for middleware in self.middlewares.iter().rev() {
+            result = middleware.after(ctx, tool, result).await;
+        }
+        result
+    }
+}
+
+/// A hook around agent runs.
+#[async_trait]
+pub trait AgentHook: Send + Sync {
+    /// Called before an agent runs, with its prompt and resources that it may rewrite.
+    /// An error rejects the run.
+    async fn before_run(
+        &self,
+        _ctx: &AgentCtx,
+        _agent: &str,
+        _prompt: &mut String,
+        _resources: &mut Option<Vec<Resource>>,
+    ) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Called after an agent ran successfully, with its prompt and its output that it may
+    /// replace. An error fails the run.
+    async fn after_run(
+        &self,
+        _ctx: &AgentCtx,
+        _agent: &str,
+        _prompt: &str,
+        output: AgentOutput,
+    ) -> Result<AgentOutput, BoxError> {
+        Ok(output)
+    }
+}
+
+/// The agent hooks of the engine, in the order they are registered.
+#[derive(Clone, Default)]
+pub struct AgentHooks {
+    hooks: Vec<Arc<dyn AgentHook>>,
+}
+
+impl AgentHooks {
+    pub fn new(hooks: Vec<Arc<dyn AgentHook>>) -> Self {
+        Self { hooks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Runs the hooks before a run, in order.
+    pub async fn before_run(
+        &self,
+        ctx: &AgentCtx,
+        agent: &str,
+        prompt: &mut String,
+        resources: &mut Option<Vec<Resource>>,
+    ) -> Result<(), BoxError> {
+        for hook in &self.hooks {
+            hook.before_run(ctx, agent, prompt, resources).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the hooks after a run, in reverse order.
+    pub async fn after_run(
+        &self,
+        ctx: &AgentCtx,
+        agent: &str,
+        prompt: &str,
+        mut output: AgentOutput,
+    ) -> Result<AgentOutput, BoxError> {
+        for hook in self.hooks.iter().rev() {
+            output = hook.after_run(ctx, agent, prompt, output).await?;
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineBuilder;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl ToolMiddleware for Recorder {
+        async fn before(
+            &self,
+            _ctx: &BaseCtx,
+            tool: &str,
+            args: &mut Value,
+        ) -> Result<(), BoxError> {
+            if args["reject"] == true {
+                return Err(format!("{} rejected {}", self.name, tool).into());
+            }
+            args[self.name] = json!(true);
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+            Ok(())
+        }
+
+        async fn after(
+            &self,
+            _ctx: &BaseCtx,
+            _tool: &str,
+            result: Result<ToolOutput<Value>, BoxError>,
+        ) -> Result<ToolOutput<Value>, BoxError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+            result.map(|mut output| {
+                let output_text = output.output.as_str().unwrap_or_default();
+                output.output = json!(format!("{}|{}", output_text, self.name));
+                output
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tool_middlewares() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let ctx = EngineBuilder::new()
+            .with_tool_middleware(Arc::new(Recorder {
+                name: "a",
+                calls: calls.clone(),
+            }))
+            .with_tool_middleware(Arc::new(Recorder {
+                name: "b",
+                calls: calls.clone(),
+            }))
+            .mock_ctx();
+        let middlewares = ctx.base.tool_middlewares.clone();
+
+        let mut args = json!({});
+        middlewares
+            .before(&ctx.base, "echo", &mut args)
+            .await
+            .unwrap();
+        assert_eq!(args, json!({"a": true, "b": true}));
+        let output = middlewares
+            .after(&ctx.base, "echo", Ok(ToolOutput::new(json!("x"))))
+            .await
+            .unwrap();
+        assert_eq!(output.output, json!("x|b|a"));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before a", "before b", "after b", "after a"]
+        );
+
+        let mut args = json!({"reject": true});
+        let err = middlewares
+            .before(&ctx.base, "echo", &mut args)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "a rejected echo");
+    }
+
+    struct Guardrail;
+
+    #[async_trait]
+    impl AgentHook for Guardrail {
+        async fn before_run(
+            &self,
+            _ctx: &AgentCtx,
+            agent: &str,
+            prompt: &mut String,
+            _resources: &mut Option<Vec<Resource>>,
+        ) -> Result<(), BoxError> {
+            if prompt.contains("forbidden") {
+                return Err(format!("{} rejected the prompt", agent).into());
+            }
+            prompt.insert_str(0, "Be concise. ");
+            Ok(())
+        }
+
+        async fn after_run(
+            &self,
+            _ctx: &AgentCtx,
+            _agent: &str,
+            prompt: &str,
+            mut output: AgentOutput,
+        ) -> Result<AgentOutput, BoxError> {
+            output.content = format!("{} -> {}", prompt, output.content);
+            Ok(output)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_agent_hooks() {
+        let ctx = EngineBuilder::new()
+            .with_agent_hook(Arc::new(Guardrail))
+            .mock_ctx();
+        let hooks = ctx.agent_hooks.clone();
+
+        let mut prompt = "hello".to_string();
+        let mut resources = None;
+        hooks
+            .before_run(&ctx, "assistant", &mut prompt, &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(prompt, "Be concise. hello");
+        let output = hooks
+            .after_run(
+                &ctx,
+                "assistant",
+                &prompt,
+                AgentOutput {
+                    content: "hi".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Be concise. hello -> hi");
+
+        let mut prompt = "forbidden".to_string();
+        let err = hooks
+            .before_run(&ctx, "assistant", &mut prompt, &mut resources)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "assistant rejected the prompt");
+    }
+}
@@ -0,0 +1,180 @@
+//! Azure OpenAI client implementation for Anda Engine
+//!
+//! Azure OpenAI serves the OpenAI API under deployments of a resource, e.g.
+//! `https://{resource}.openai.azure.com/openai/deployments/{deployment}/chat/completions?api-version=2024-10-21`,
+//! authenticated with an API key (`api-key` header) or a Microsoft Entra ID (AAD) token.
+//!
+//! The models of this client are the [`openai`](super::openai) models, with the deployment
+//! name as model name, so that completions and embeddings behave the same on both services.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let client = azure::Client::new("https://my-resource.openai.azure.com", azure::Auth::ApiKey(key));
+//! let model = Model::with_completer(Arc::new(client.completion_model("my-gpt-4o")));
+//! ```
+
+use anda_core::{BoxError, BoxPinFut};
+use std::sync::Arc;
+
+use super::openai;
+
+/// The default API version of the Azure OpenAI data plane.
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// The scope of the Microsoft Entra ID tokens of Azure OpenAI.
+pub const ENTRA_ID_SCOPE: &str = "https://cognitiveservices.azure.com/.default";
+
+/// Provides Microsoft Entra ID (AAD) access tokens for [`ENTRA_ID_SCOPE`],
+/// it is called for each request and should cache the token until it expires.
+pub trait TokenProvider: Send + Sync + 'static {
+    fn token(&self) -> BoxPinFut<Result<String, BoxError>>;
+}
+
+/// A static access token, e.g. from `az account get-access-token`.
+impl TokenProvider for String {
+    fn token(&self) -> BoxPinFut<Result<String, BoxError>> {
+        Box::pin(futures::future::ready(Ok(self.clone())))
+    }
+}
+
+/// Authentication of the Azure OpenAI requests.
+#[derive(Clone)]
+pub enum Auth {
+    /// An API key of the resource, sent as the `api-key` header.
+    ApiKey(String),
+    /// A Microsoft Entra ID (AAD) token provider, the tokens are sent as bearer tokens.
+    EntraId(Arc<dyn TokenProvider>),
+}
+
+/// The deployment-based routing and authentication of an [`openai::Client`] for Azure.
+pub(crate) struct AzureConfig {
+    pub(crate) api_version: String,
+    pub(crate) auth: Auth,
+}
+
+impl AzureConfig {
+    /// Returns the URL of the API path, under the deployment if any.
+    pub(crate) fn url(&self, endpoint: &str, deployment: Option<&str>, path: &str) -> String {
+        match deployment {
+            Some(deployment) => format!(
+                "{}/openai/deployments/{}{}?api-version={}",
+                endpoint, deployment, path, self.api_version
+            ),
+            None => format!(
+                "{}/openai{}?api-version={}",
+                endpoint, path, self.api_version
+            ),
+        }
+    }
+
+    /// Adds the authentication header to the request.
+    pub(crate) async fn authorize(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, BoxError> {
+        match &self.auth {
+            Auth::ApiKey(key) => Ok(req.header("api-key", key)),
+            Auth::EntraId(provider) => Ok(req.bearer_auth(provider.token().await?)),
+        }
+    }
+}
+
+/// Azure OpenAI client of a resource
+#[derive(Clone)]
+pub struct Client {
+    endpoint: String,
+    auth: Auth,
+    api_version: String,
+}
+
+impl Client {
+    /// Creates a new Azure OpenAI client
+    ///
+    /// # Arguments
+    /// * `endpoint` - Endpoint of the resource, e.g. `https://{resource}.openai.azure.com`
+    /// * `auth` - API key or Microsoft Entra ID authentication
+    pub fn new(endpoint: &str, auth: Auth) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            auth,
+            api_version: DEFAULT_API_VERSION.to_string(),
+        }
+    }
+
+    /// Sets the API version of the requests, [`DEFAULT_API_VERSION`] by default
+    pub fn with_api_version(mut self, api_version: &str) -> Self {
+        self.api_version = api_version.to_string();
+        self
+    }
+
+    fn openai_client(&self) -> openai::Client {
+        openai::Client::azure(
+            &self.endpoint,
+            AzureConfig {
+                api_version: self.api_version.clone(),
+                auth: self.auth.clone(),
+            },
+        )
+    }
+
+    /// Creates a completion model of the given deployment
+    pub fn completion_model(&self, deployment: &str) -> openai::CompletionModel {
+        self.openai_client().completion_model(deployment)
+    }
+
+    /// Creates an embedding model of the given deployment
+    ///
+    /// # Arguments
+    /// * `deployment` - Name of the embedding deployment
+    /// * `ndims` - Number of dimensions of the deployed model, e.g. 3072 for `text-embedding-3-large`
+    pub fn embedding_model(&self, deployment: &str, ndims: usize) -> openai::EmbeddingModel {
+        openai::EmbeddingModel::new(self.openai_client(), deployment, ndims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_azure_requests() {
+        let cfg = AzureConfig {
+            api_version: DEFAULT_API_VERSION.to_string(),
+            auth: Auth::ApiKey("key".to_string()),
+        };
+        assert_eq!(
+            cfg.url(
+                "https://r.openai.azure.com",
+                Some("gpt-4o"),
+                "/chat/completions"
+            ),
+            "https://r.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            cfg.url("https://r.openai.azure.com", None, "/models"),
+            "https://r.openai.azure.com/openai/models?api-version=2024-10-21"
+        );
+
+        let http = reqwest::Client::new();
+        let req = cfg
+            .authorize(http.get("https://r.openai.azure.com"))
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.headers()["api-key"], "key");
+
+        let cfg = AzureConfig {
+            api_version: DEFAULT_API_VERSION.to_string(),
+            auth: Auth::EntraId(Arc::new("token".to_string())),
+        };
+        let req = cfg
+            .authorize(http.get("https://r.openai.azure.com"))
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.headers()["authorization"], "Bearer token");
+    }
+}
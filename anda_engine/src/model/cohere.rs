@@ -1,7 +1,7 @@
 //! Cohere API client and Anda integration
 //!
 //! This module provides a client for interacting with Cohere's API, specifically
-//! focused on text embedding functionality. It includes support for various
+//! focused on text embedding and rerank functionality. It includes support for various
 //! Cohere embedding models and handles API communication, error handling,
 //! and response parsing.
 
@@ -10,7 +10,7 @@ use serde::Deserialize;
 use serde_json::json;
 use std::time::Duration;
 
-use super::EmbeddingFeaturesDyn;
+use super::{EmbeddingFeaturesDyn, RerankFeaturesDyn, RerankResult, provider_error};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -29,6 +29,8 @@ pub const EMBED_ENGLISH_LIGHT_V3: &str = "embed-english-light-v3.0";
 pub const EMBED_MULTILINGUAL_V3: &str = "embed-multilingual-v3.0";
 /// `embed-multilingual-light-v3.0` embedding model
 pub const EMBED_MULTILINGUAL_LIGHT_V3: &str = "embed-multilingual-light-v3.0";
+/// `rerank-v3.5` rerank model
+pub const RERANK_V3_5: &str = "rerank-v3.5";
 
 /// Cohere API client configuration and HTTP client
 #[derive(Clone)]
@@ -97,6 +99,12 @@ impl Client {
         };
         EmbeddingModel::new(self.clone(), model, ndims)
     }
+
+    /// Creates a rerank model instance, RERANK_V3_5 if the model is empty
+    pub fn rerank_model(&self, model: &str) -> RerankModel {
+        let model = if model.is_empty() { RERANK_V3_5 } else { model };
+        RerankModel::new(self.clone(), model)
+    }
 }
 
 /// Response structure for Cohere's embedding API
@@ -135,6 +143,7 @@ impl EmbeddingResponse {
                 input_tokens: m.billed_units.input_tokens as u64,
                 output_tokens: m.billed_units.output_tokens as u64,
                 requests: 1,
+                ..Default::default()
             }),
         ))
     }
@@ -229,6 +238,10 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
         self.ndims
     }
 
+    fn max_batch_size(&self) -> usize {
+        MAX_DOCUMENTS
+    }
+
     /// Generates embeddings for a batch of texts
     ///
     /// # Arguments
@@ -261,12 +274,13 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
 
             if response.status().is_success() {
                 match response.json::<EmbeddingResponse>().await {
-                    Ok(res) => res.try_into(texts),
+                    Ok(res) => res
+                        .try_into(texts)
+                        .map(|(embeddings, usage)| (embeddings, usage.with_model(&model))),
                     Err(err) => Err(format!("Cohere embeddings error: {}", err).into()),
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("Cohere embeddings error: {}", msg).into())
+                Err(provider_error("Cohere embeddings", response).await)
             }
         })
     }
@@ -297,18 +311,108 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
                 match response.json::<EmbeddingResponse>().await {
                     Ok(mut res) => {
                         let data = res.embeddings.float.pop().ok_or("no embedding data")?;
-                        let usage = res.meta.as_ref().map_or(Usage::default(), |m| Usage {
-                            input_tokens: m.billed_units.input_tokens as u64,
-                            output_tokens: m.billed_units.output_tokens as u64,
-                            requests: 1,
+                        let usage = res.meta.as_ref().map_or(Usage::default(), |m| {
+                            Usage {
+                                input_tokens: m.billed_units.input_tokens as u64,
+                                output_tokens: m.billed_units.output_tokens as u64,
+                                requests: 1,
+                                ..Default::default()
+                            }
+                            .with_model(&model)
                         });
                         Ok((Embedding { text, vec: data }, usage))
                     }
                     Err(err) => Err(format!("Cohere embeddings error: {}", err).into()),
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("Cohere embeddings error: {}", msg).into())
+                Err(provider_error("Cohere embeddings", response).await)
+            }
+        })
+    }
+}
+
+// ================================================================
+// Cohere Rerank API
+// ================================================================
+/// Response structure for Cohere's rerank API
+#[derive(Debug, Deserialize)]
+pub struct RerankResponse {
+    pub results: Vec<RerankResponseResult>,
+    #[serde(default)]
+    pub meta: Option<Meta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RerankResponseResult {
+    pub index: usize,
+    pub relevance_score: f32,
+}
+
+/// Cohere rerank model wrapper
+#[derive(Clone)]
+pub struct RerankModel {
+    /// Model identifier
+    pub model: String,
+    /// Client instance for API communication
+    client: Client,
+}
+
+impl RerankModel {
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+}
+
+/// Maximum number of documents per rerank call.
+const MAX_RERANK_DOCUMENTS: usize = 1000;
+impl RerankFeaturesDyn for RerankModel {
+    /// Reranks the documents against the query
+    ///
+    /// https://docs.cohere.com/reference/rerank
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> BoxPinFut<Result<Vec<RerankResult>, BoxError>> {
+        let model = self.model.clone();
+        let client = self.client.clone();
+        Box::pin(async move {
+            if documents.len() > MAX_RERANK_DOCUMENTS {
+                return Err(format!("Too many documents, max is {}", MAX_RERANK_DOCUMENTS).into());
+            }
+            if documents.is_empty() || top_n == 0 {
+                return Ok(Vec::new());
+            }
+
+            let response = client
+                .post("/v2/rerank")
+                .json(&json!({
+                    "model": model,
+                    "query": query,
+                    "documents": documents,
+                    "top_n": top_n.min(documents.len()),
+                }))
+                .send()
+                .await?;
+
+            if response.status().is_success() {
+                match response.json::<RerankResponse>().await {
+                    Ok(res) => Ok(res
+                        .results
+                        .into_iter()
+                        .map(|r| RerankResult {
+                            index: r.index,
+                            score: r.relevance_score,
+                        })
+                        .collect()),
+                    Err(err) => Err(format!("Cohere rerank error: {}", err).into()),
+                }
+            } else {
+                Err(provider_error("Cohere rerank", response).await)
             }
         })
     }
@@ -0,0 +1,670 @@
+//! Google Gemini API client implementation for Anda Engine
+//!
+//! This module provides integration with the Gemini API, including:
+//! - Client configuration and management
+//! - Completion model handling, with function calling, system instructions and multimodal parts
+//! - Response parsing and conversion to Anda's internal formats
+//!
+//! The chat history is kept in the OpenAI message format, like the other providers,
+//! and converted to Gemini contents for each request.
+
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CONTENT_TYPE_JSON, CompletionFeatures, CompletionRequest,
+    ContentPart, Message, Resource, ToolCall, Usage as ModelUsage,
+};
+use log::{Level::Debug, log_enabled};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{CompletionFeaturesDyn, models_health, provider_error};
+use crate::APP_USER_AGENT;
+
+// ================================================================
+// Main Gemini Client
+// ================================================================
+const API_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+pub static GEMINI_2_5_FLASH: &str = "gemini-2.5-flash";
+pub static GEMINI_2_5_PRO: &str = "gemini-2.5-pro";
+
+/// Gemini API client configuration and HTTP client
+#[derive(Clone)]
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Creates a new Gemini client instance with the provided API key
+    ///
+    /// # Arguments
+    /// * `api_key` - Gemini API key for authentication
+    /// * `endpoint` - Optional API endpoint, the Gemini API v1beta if not set
+    ///
+    /// # Returns
+    /// Configured Gemini client instance
+    pub fn new(api_key: &str, endpoint: Option<String>) -> Self {
+        let endpoint = endpoint.unwrap_or_else(|| API_BASE_URL.to_string());
+        let endpoint = if endpoint.is_empty() {
+            API_BASE_URL.to_string()
+        } else {
+            endpoint
+        };
+        Self {
+            endpoint,
+            http: reqwest::Client::builder()
+                .use_rustls_tls()
+                .https_only(true)
+                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
+                .http2_keep_alive_timeout(Duration::from_secs(15))
+                .http2_keep_alive_while_idle(true)
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(180))
+                .gzip(true)
+                .user_agent(APP_USER_AGENT)
+                .default_headers({
+                    let mut headers = reqwest::header::HeaderMap::new();
+                    let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
+                    headers.insert(http::header::CONTENT_TYPE, ct.clone());
+                    headers.insert(http::header::ACCEPT, ct);
+                    headers.insert(
+                        "x-goog-api-key",
+                        api_key.parse().expect("API key should parse"),
+                    );
+                    headers
+                })
+                .build()
+                .expect("Gemini reqwest client should build"),
+        }
+    }
+
+    /// Creates a POST request builder for the specified API path
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.endpoint, path);
+        self.http.post(url)
+    }
+
+    /// Creates a GET request builder for the specified API path
+    /// Creates a new completion model instance, the default Gemini model if empty
+    pub fn completion_model(&self, model: &str) -> CompletionModel {
+        CompletionModel::new(
+            self.clone(),
+            if model.is_empty() {
+                GEMINI_2_5_FLASH
+            } else {
+                model
+            },
+        )
+    }
+}
+
+/// Token usage statistics from Gemini API responses
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    /// Number of tokens in the prompt, including the cached ones
+    #[serde(default)]
+    pub prompt_token_count: u64,
+    /// Number of tokens in the generated candidates
+    #[serde(default)]
+    pub candidates_token_count: u64,
+    /// Number of tokens of the thoughts of thinking models
+    #[serde(default)]
+    pub thoughts_token_count: u64,
+    /// Total number of tokens
+    #[serde(default)]
+    pub total_token_count: u64,
+}
+
+impl std::fmt::Display for UsageMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Prompt tokens: {} candidates tokens: {} thoughts tokens: {}",
+            self.prompt_token_count, self.candidates_token_count, self.thoughts_token_count
+        )
+    }
+}
+
+/// Completion response from Gemini API
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionResponse {
+    /// List of completion candidates
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    /// Feedback on the prompt, with the reason if it was blocked
+    pub prompt_feedback: Option<PromptFeedback>,
+    /// Token usage statistics
+    pub usage_metadata: Option<UsageMetadata>,
+    /// Model used for the completion
+    #[serde(default)]
+    pub model_version: String,
+}
+
+impl CompletionResponse {
+    fn try_into(mut self, mut full_history: Vec<Value>) -> Result<AgentOutput, BoxError> {
+        let usage = self
+            .usage_metadata
+            .as_ref()
+            .map(|u| {
+                ModelUsage {
+                    input_tokens: u.prompt_token_count,
+                    output_tokens: u.candidates_token_count + u.thoughts_token_count,
+                    requests: 1,
+                    ..Default::default()
+                }
+                .with_model(&self.model_version)
+            })
+            .unwrap_or_default();
+
+        if self.candidates.is_empty()
+            && let Some(reason) = self.prompt_feedback.and_then(|f| f.block_reason)
+        {
+            return Ok(AgentOutput {
+                failed_reason: Some(format!("prompt blocked: {}", reason)),
+                usage,
+                ..Default::default()
+            });
+        }
+
+        let candidate = self.candidates.pop().ok_or("No completion candidate")?;
+        let mut content = String::new();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+            match part {
+                // the thoughts of thinking models are not part of the answer
+                Part::Text { text, thought } if !thought => content.push_str(&text),
+                Part::FunctionCall { function_call } => tool_calls.push(ToolCall {
+                    id: format!("{}_{}", function_call.name, tool_calls.len()),
+                    name: function_call.name,
+                    args: function_call.args.to_string(),
+                    result: None,
+                }),
+                _ => {}
+            }
+        }
+
+        full_history.push(json!({
+            "role": "assistant",
+            "content": content,
+            "tool_calls": if tool_calls.is_empty() {
+                Value::Null
+            } else {
+                json!(tool_calls.iter().map(|tc| json!({
+                    "id": tc.id,
+                    "type": "function",
+                    "function": {"name": tc.name, "arguments": tc.args},
+                })).collect::<Vec<_>>())
+            },
+        }));
+
+        let mut output = AgentOutput {
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            full_history: Some(full_history),
+            usage,
+            ..Default::default()
+        };
+
+        if let Some(reason) = candidate.finish_reason
+            && reason != "STOP"
+        {
+            output.failed_reason = Some(reason);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Individual completion candidate from Gemini API
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: Option<Content>,
+    pub finish_reason: Option<String>,
+}
+
+/// Feedback on the prompt from Gemini API
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+}
+
+/// Content of a turn of the conversation, "user" or "model"
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Content {
+    #[serde(default)]
+    pub role: String,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+/// A part of a content, other kinds of parts are ignored
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Part {
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        thought: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    FunctionCall {
+        function_call: FunctionCall,
+    },
+    Other(Value),
+}
+
+/// Function call requested by the model
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// Completion model wrapper for Gemini API
+#[derive(Clone)]
+pub struct CompletionModel {
+    /// Gemini client instance
+    client: Client,
+    /// Model identifier
+    pub model: String,
+}
+
+impl CompletionModel {
+    /// Creates a new completion model instance
+    ///
+    /// # Arguments
+    /// * `client` - Gemini client instance
+    /// * `model` - Model identifier string
+    pub fn new(client: Client, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+        }
+    }
+
+    /// Builds the chat history and the body of a generate content request.
+    fn request_body(&self, mut req: CompletionRequest) -> (Vec<Value>, Value) {
+        let mut full_history = std::mem::take(&mut req.chat_history);
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.content_parts),
+                name: req.prompter_name.clone(),
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                name: req.prompter_name.clone(),
+                ..Default::default()
+            }));
+        }
+
+        // the system messages of the history are system instructions too
+        let mut system: Vec<Value> = req
+            .system
+            .iter()
+            .map(|text| json!({"text": text}))
+            .collect();
+        let contents = to_contents(&full_history, &mut system);
+        let mut body = json!({
+            "contents": contents,
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if !system.is_empty() {
+            obj.insert("systemInstruction".to_string(), json!({"parts": system}));
+        }
+
+        let mut config = Map::new();
+        if let Some(temperature) = req.temperature {
+            config.insert("temperature".to_string(), Value::from(temperature));
+        }
+
+        if let Some(max_tokens) = req.max_tokens {
+            config.insert("maxOutputTokens".to_string(), Value::from(max_tokens));
+        }
+
+        if let Some(stop) = req.stop {
+            config.insert("stopSequences".to_string(), Value::from(stop));
+        }
+
+        if let Some(response_format) = req.response_format {
+            config.insert(
+                "responseMimeType".to_string(),
+                Value::from(CONTENT_TYPE_JSON),
+            );
+            if let Some(schema) = response_format.pointer("/json_schema/schema") {
+                config.insert("responseJsonSchema".to_string(), schema.clone());
+            }
+        }
+
+        if !config.is_empty() {
+            obj.insert("generationConfig".to_string(), Value::Object(config));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!([{
+                    "functionDeclarations": req.tools.into_iter().map(|f| json!({
+                        "name": f.name,
+                        "description": f.description,
+                        "parametersJsonSchema": f.parameters,
+                    })).collect::<Vec<_>>(),
+                }]),
+            );
+            obj.insert(
+                "toolConfig".to_string(),
+                json!({"functionCallingConfig": {
+                    "mode": if req.tool_choice_required { "ANY" } else { "AUTO" },
+                }}),
+            );
+        };
+
+        let mut history = req
+            .system
+            .map(|system| {
+                vec![json!(Message {
+                    role: "system".into(),
+                    content: system.into(),
+                    name: req.system_name,
+                    ..Default::default()
+                })]
+            })
+            .unwrap_or_default();
+        history.append(&mut full_history);
+        (history, body)
+    }
+}
+
+/// Converts the chat history in the OpenAI message format to Gemini contents,
+/// appending the text of its system messages to the system instruction parts.
+fn to_contents(history: &[Value], system: &mut Vec<Value>) -> Vec<Value> {
+    // the names of the called functions by tool call ID
+    let mut calls: BTreeMap<String, String> = BTreeMap::new();
+    let mut contents: Vec<Value> = Vec::new();
+    for msg in history {
+        let content = msg.get("content").unwrap_or(&Value::Null);
+        match msg.get("role").and_then(|r| r.as_str()).unwrap_or_default() {
+            "system" | "developer" => {
+                if let Some(text) = content.as_str() {
+                    system.push(json!({"text": text}));
+                }
+            }
+            "assistant" | "model" => {
+                let mut parts = to_parts(content);
+                for tc in msg
+                    .get("tool_calls")
+                    .and_then(|t| t.as_array())
+                    .into_iter()
+                    .flatten()
+                {
+                    let id = tc["id"].as_str().unwrap_or_default();
+                    let name = tc["function"]["name"].as_str().unwrap_or_default();
+                    let args = match &tc["function"]["arguments"] {
+                        Value::String(args) => serde_json::from_str(args).unwrap_or(json!({})),
+                        args => args.clone(),
+                    };
+                    calls.insert(id.to_string(), name.to_string());
+                    parts.push(json!({"functionCall": {"name": name, "args": args}}));
+                }
+                push_content(&mut contents, "model", parts);
+            }
+            "tool" => {
+                let id = msg["tool_call_id"].as_str().unwrap_or_default();
+                let name = calls.get(id).map(|n| n.as_str()).unwrap_or(id);
+                // the response of a function must be an object
+                let response = match content {
+                    Value::Object(_) => content.clone(),
+                    Value::String(text) => match serde_json::from_str::<Value>(text) {
+                        Ok(Value::Object(obj)) => Value::Object(obj),
+                        _ => json!({"content": text}),
+                    },
+                    _ => json!({"content": content}),
+                };
+                let part = json!({"functionResponse": {"name": name, "response": response}});
+                // the responses of parallel function calls are in one turn
+                match contents.last_mut() {
+                    Some(last) if last["parts"][0].get("functionResponse").is_some() => {
+                        if let Some(parts) = last["parts"].as_array_mut() {
+                            parts.push(part);
+                        }
+                    }
+                    _ => contents.push(json!({"role": "user", "parts": [part]})),
+                }
+            }
+            _ => push_content(&mut contents, "user", to_parts(content)),
+        }
+    }
+    contents
+}
+
+/// Appends a content with the parts, if any.
+fn push_content(contents: &mut Vec<Value>, role: &str, parts: Vec<Value>) {
+    if !parts.is_empty() {
+        contents.push(json!({"role": role, "parts": parts}));
+    }
+}
+
+/// Converts the content of a message, a text or [`ContentPart`]s, to Gemini parts.
+fn to_parts(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) if !text.is_empty() => vec![json!({"text": text})],
+        Value::Array(_) => serde_json::from_value::<Vec<ContentPart>>(content.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => json!({"text": text}),
+                ContentPart::Image { image_url } => match parse_data_url(&image_url.url) {
+                    Some((mime_type, data)) => {
+                        json!({"inlineData": {"mimeType": mime_type, "data": data}})
+                    }
+                    None => json!({"fileData": {
+                        "mimeType": image_mime_type(&image_url.url),
+                        "fileUri": image_url.url,
+                    }}),
+                },
+                ContentPart::Audio { input_audio } => json!({"inlineData": {
+                    "mimeType": format!("audio/{}", input_audio.format),
+                    "data": input_audio.data,
+                }}),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Parses a `data:{mime type};base64,{data}` URL.
+fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+    Some((meta.strip_suffix(";base64")?, data))
+}
+
+/// Guesses the MIME type of an image from the extension of its URL.
+fn image_mime_type(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('.').next().map(|ext| ext.to_ascii_lowercase()) {
+        Some(ext) if ext == "png" => "image/png",
+        Some(ext) if ext == "webp" => "image/webp",
+        Some(ext) if ext == "gif" => "image/gif",
+        _ => "image/jpeg",
+    }
+}
+
+impl CompletionFeatures for CompletionModel {
+    async fn completion(
+        &self,
+        req: CompletionRequest,
+        _resources: Option<Vec<Resource>>,
+    ) -> Result<AgentOutput, BoxError> {
+        CompletionFeaturesDyn::completion(self, req).await
+    }
+}
+
+impl CompletionFeaturesDyn for CompletionModel {
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        let req = self
+            .client
+            .http
+            .get(format!("{}/models", self.client.endpoint));
+        Box::pin(models_health(req))
+    }
+
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (full_history, body) = self.request_body(req);
+        let path = format!("/models/{}:generateContent", self.model);
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if log_enabled!(Debug)
+                && let Ok(val) = serde_json::to_string(&body)
+            {
+                log::debug!(request = val; "Gemini completions request");
+            }
+
+            let response = client.post(&path).json(&body).send().await?;
+            if response.status().is_success() {
+                let text = response.text().await?;
+                match serde_json::from_str::<CompletionResponse>(&text) {
+                    Ok(res) => {
+                        if log_enabled!(Debug)
+                            && let Ok(val) = serde_json::to_string(&res)
+                        {
+                            log::debug!(response = val; "Gemini completions response");
+                        }
+                        res.try_into(full_history)
+                    }
+                    Err(err) => {
+                        Err(format!("Gemini completions error: {}, body: {}", err, text).into())
+                    }
+                }
+            } else {
+                Err(provider_error("Gemini completions", response).await)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{FunctionDefinition, ImageDetail};
+
+    #[test]
+    fn test_request_body() {
+        let model = Client::new("key", None).completion_model("");
+        assert_eq!(model.model, GEMINI_2_5_FLASH);
+
+        let req = CompletionRequest {
+            system: Some("You are a helpful assistant.".to_string()),
+            chat_history: vec![
+                json!({"role": "user", "content": "Search for rust"}),
+                json!({"role": "assistant", "content": "", "tool_calls": [
+                    {"id": "search_0", "type": "function", "function": {"name": "search", "arguments": "{\"q\":\"rust\"}"}},
+                ]}),
+                json!({"role": "tool", "content": "[\"rust-lang.org\"]", "tool_call_id": "search_0"}),
+            ],
+            content_parts: vec![
+                ContentPart::Text {
+                    text: "And this image?".to_string(),
+                },
+                ContentPart::Image {
+                    image_url: ImageDetail {
+                        url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                        detail: None,
+                    },
+                },
+            ],
+            tools: vec![FunctionDefinition {
+                name: "search".to_string(),
+                description: "Searches the web.".to_string(),
+                parameters: json!({"type": "object"}),
+                strict: None,
+            }],
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        let (history, body) = model.request_body(req);
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[0]["role"], "system");
+        assert_eq!(
+            body["systemInstruction"]["parts"][0]["text"],
+            "You are a helpful assistant."
+        );
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 4);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"],
+            json!({"name": "search", "args": {"q": "rust"}})
+        );
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"],
+            json!({"name": "search", "response": {"content": "[\"rust-lang.org\"]"}})
+        );
+        assert_eq!(
+            contents[3]["parts"][1]["inlineData"],
+            json!({"mimeType": "image/png", "data": "iVBORw0KGgo="})
+        );
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 100);
+        assert_eq!(
+            body["tools"][0]["functionDeclarations"][0]["name"],
+            "search"
+        );
+        assert_eq!(body["toolConfig"]["functionCallingConfig"]["mode"], "AUTO");
+    }
+
+    #[test]
+    fn test_completion_response() {
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "thinking...", "thought": true},
+                    {"text": "Let me search."},
+                    {"functionCall": {"name": "search", "args": {"q": "rust"}}},
+                ]},
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 10,
+                "candidatesTokenCount": 5,
+                "thoughtsTokenCount": 3,
+                "totalTokenCount": 18,
+            },
+            "modelVersion": "gemini-2.5-flash",
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(output.content, "Let me search.");
+        assert!(output.failed_reason.is_none());
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id, "search_0");
+        assert_eq!(tool_calls[0].name, "search");
+        assert_eq!(tool_calls[0].args, r#"{"q":"rust"}"#);
+        assert_eq!(output.usage.input_tokens, 10);
+        assert_eq!(output.usage.output_tokens, 8);
+        let history = output.full_history.unwrap();
+        assert_eq!(history[0]["tool_calls"][0]["id"], "search_0");
+
+        let res: CompletionResponse = serde_json::from_value(json!({
+            "promptFeedback": {"blockReason": "SAFETY"},
+        }))
+        .unwrap();
+        let output = res.try_into(vec![]).unwrap();
+        assert_eq!(
+            output.failed_reason.as_deref(),
+            Some("prompt blocked: SAFETY")
+        );
+    }
+}
@@ -2,8 +2,11 @@
 //!
 //! This module provides implementations for various AI model providers, including:
 //! - OpenAI (completion and embedding models)
+//! - Azure OpenAI (completion and embedding deployments)
 //! - DeepSeek (completion models)
-//! - Cohere (embedding models)
+//! - xAI (completion models)
+//! - Google Gemini (completion models)
+//! - Cohere (embedding and rerank models)
 //!
 //! Each provider implementation includes:
 //! - Client configuration and management
@@ -14,18 +17,259 @@
 //! while maintaining a consistent interface through the `CompletionFeaturesDyn` and
 //! `EmbeddingFeaturesDyn` traits.
 
-use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, Embedding, ToolCall, Usage};
+use anda_core::{
+    AgentOutput, BoxError, BoxPinFut, CompletionRequest, Embedding, Error, SseParser, ToolCall,
+    Usage, anda_error,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{Value, json};
 use std::sync::Arc;
+use tokio::sync::mpsc;
 
+use crate::secrets::redact_error;
+
+pub mod azure;
 pub mod cohere;
 pub mod deepseek;
+pub mod gemini;
 pub mod openai;
 pub mod xai;
 
+/// The maximum number of embedding batches of a [`Model::embed`] call that run concurrently.
+pub const MAX_EMBED_CONCURRENCY: usize = 4;
+
+/// Converts an unsuccessful response of a model provider to an [`Error::Provider`].
+pub(crate) async fn provider_error(provider: &str, response: reqwest::Response) -> BoxError {
+    let status = response.status().as_u16();
+    let message = match response.text().await {
+        Ok(text) => text,
+        Err(err) => err.to_string(),
+    };
+    Error::Provider {
+        provider: provider.to_string(),
+        status: Some(status),
+        message,
+    }
+    .into()
+}
+
+/// Reads a streamed OpenAI-compatible chat completion (`"stream": true`), sending the deltas
+/// of the content as they arrive, and returns the same output as a non-streamed response.
+pub(crate) async fn read_chat_completion_stream(
+    mut response: reqwest::Response,
+    full_history: Vec<Value>,
+    deltas: &mpsc::UnboundedSender<String>,
+) -> Result<AgentOutput, BoxError> {
+    let mut parser = SseParser::new();
+    let mut stream = ChatCompletionStream::default();
+    while let Some(chunk) = response.chunk().await? {
+        for event in parser.feed(&chunk) {
+            if event.data == "[DONE]" {
+                return stream.finish(full_history);
+            }
+            stream.feed(&event.data, deltas)?;
+        }
+    }
+    stream.finish(full_history)
+}
+
+/// Accumulates the chunks of a streamed chat completion.
+#[derive(Debug, Default)]
+struct ChatCompletionStream {
+    model: String,
+    content: String,
+    refusal: Option<String>,
+    /// The tool calls by index, their arguments are streamed in pieces.
+    tool_calls: Vec<(usize, ToolCall)>,
+    finish_reason: Option<String>,
+    usage: Option<StreamUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    model: String,
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    usage: Option<StreamUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+    refusal: Option<String>,
+    tool_calls: Option<Vec<StreamToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamToolCall {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<StreamFunction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamFunction {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamUsage {
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+impl ChatCompletionStream {
+    fn feed(&mut self, data: &str, deltas: &mpsc::UnboundedSender<String>) -> Result<(), BoxError> {
+        let chunk: StreamChunk = serde_json::from_str(data)
+            .map_err(|err| format!("invalid completion chunk: {}, data: {}", err, data))?;
+        if !chunk.model.is_empty() {
+            self.model = chunk.model;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                self.content.push_str(&content);
+                // the receiver may have been dropped by a disconnected client
+                let _ = deltas.send(content);
+            }
+            if let Some(refusal) = choice.delta.refusal {
+                self.refusal.get_or_insert_default().push_str(&refusal);
+            }
+            for tc in choice.delta.tool_calls.unwrap_or_default() {
+                let i = match self.tool_calls.iter().position(|(i, _)| *i == tc.index) {
+                    Some(i) => i,
+                    None => {
+                        self.tool_calls.push((tc.index, ToolCall::default()));
+                        self.tool_calls.len() - 1
+                    }
+                };
+                let call = &mut self.tool_calls[i].1;
+                if let Some(id) = tc.id {
+                    call.id = id;
+                }
+                if let Some(function) = tc.function {
+                    if let Some(name) = function.name {
+                        call.name.push_str(&name);
+                    }
+                    if let Some(arguments) = function.arguments {
+                        call.args.push_str(&arguments);
+                    }
+                }
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, mut full_history: Vec<Value>) -> Result<AgentOutput, BoxError> {
+        let finish_reason = self.finish_reason.ok_or("No completion choice")?;
+        let tool_calls: Vec<ToolCall> = self.tool_calls.into_iter().map(|(_, tc)| tc).collect();
+        full_history.push(json!({
+            "role": "assistant",
+            "content": self.content,
+            "refusal": self.refusal,
+            "tool_calls": if tool_calls.is_empty() {
+                Value::Null
+            } else {
+                json!(tool_calls.iter().map(|tc| json!({
+                    "id": tc.id,
+                    "type": "function",
+                    "function": {"name": tc.name, "arguments": tc.args},
+                })).collect::<Vec<_>>())
+            },
+        }));
+
+        let mut output = AgentOutput {
+            content: self.content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            full_history: Some(full_history),
+            usage: self
+                .usage
+                .map(|u| {
+                    Usage {
+                        input_tokens: u.prompt_tokens,
+                        output_tokens: u.completion_tokens,
+                        requests: 1,
+                        ..Default::default()
+                    }
+                    .with_model(&self.model)
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        if !matches!(finish_reason.as_str(), "stop" | "tool_calls") {
+            output.failed_reason = Some(finish_reason);
+        }
+        if let Some(refusal) = self.refusal {
+            output.failed_reason = Some(refusal);
+        }
+        Ok(output)
+    }
+}
+
 /// Trait for dynamic completion features that can be used across threads
 pub trait CompletionFeaturesDyn: Send + Sync + 'static {
     /// Performs a completion request and returns a future with the agent's output
     fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>>;
+
+    /// Performs a completion request, sending the deltas of the content as they are generated.
+    /// The default implementation sends the whole content at once.
+    fn stream_completion(
+        &self,
+        req: CompletionRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let completion = self.completion(req);
+        Box::pin(async move {
+            let output = completion.await?;
+            if !output.content.is_empty() {
+                let _ = deltas.send(output.content.clone());
+            }
+            Ok(output)
+        })
+    }
+
+    /// Checks that the model service is reachable, used by readiness probes
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        Box::pin(futures::future::ready(Ok(())))
+    }
+
+    /// Returns false for the placeholder of a model that is not configured
+    fn is_implemented(&self) -> bool {
+        true
+    }
+}
+
+/// Checks that a model service is reachable by listing its models,
+/// the `health` of the HTTP model providers
+pub(crate) async fn models_health(req: reqwest::RequestBuilder) -> Result<(), BoxError> {
+    let response = req.send().await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("model service unhealthy, status: {}", response.status()).into())
+    }
 }
 
 /// Trait for dynamic embedding features that can be used across threads
@@ -33,6 +277,12 @@ pub trait EmbeddingFeaturesDyn: Send + Sync + 'static {
     /// Returns the number of dimensions for the embedding model
     fn ndims(&self) -> usize;
 
+    /// Returns the maximum number of texts of an [`embed`](Self::embed) request,
+    /// [`Model::embed`] splits larger inputs into batches of this size
+    fn max_batch_size(&self) -> usize {
+        usize::MAX
+    }
+
     /// Embeds multiple texts and returns a future with the resulting embeddings
     fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>>;
 
@@ -40,6 +290,27 @@ pub trait EmbeddingFeaturesDyn: Send + Sync + 'static {
     fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>>;
 }
 
+/// The relevance of a document to a query, returned by a reranker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RerankResult {
+    /// Index of the document in the reranked documents
+    pub index: usize,
+    /// Relevance score, higher is more relevant
+    pub score: f32,
+}
+
+/// Trait for dynamic rerank features that can be used across threads,
+/// e.g. a cross-encoder or a provider rerank API
+pub trait RerankFeaturesDyn: Send + Sync + 'static {
+    /// Scores the documents against the query and returns the top N, most relevant first
+    fn rerank(
+        &self,
+        query: String,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> BoxPinFut<Result<Vec<RerankResult>, BoxError>>;
+}
+
 /// A placeholder implementation for unimplemented features
 #[derive(Clone, Debug)]
 pub struct NotImplemented;
@@ -48,6 +319,14 @@ impl CompletionFeaturesDyn for NotImplemented {
     fn completion(&self, _req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
         Box::pin(futures::future::ready(Err("not implemented".into())))
     }
+
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        Box::pin(futures::future::ready(Err("not implemented".into())))
+    }
+
+    fn is_implemented(&self) -> bool {
+        false
+    }
 }
 
 impl EmbeddingFeaturesDyn for NotImplemented {
@@ -128,6 +407,10 @@ pub struct Model {
     pub embedder: Arc<dyn EmbeddingFeaturesDyn>,
     /// Completion feature implementation
     pub completer: Arc<dyn CompletionFeaturesDyn>,
+    /// Optional rerank feature implementation for the retrieval pipeline
+    pub reranker: Option<Arc<dyn RerankFeaturesDyn>>,
+    /// The models that the completions fail over to in order, see [`is_failover_error`]
+    pub fallbacks: Vec<Model>,
 }
 
 impl Model {
@@ -139,6 +422,8 @@ impl Model {
         Self {
             embedder,
             completer,
+            reranker: None,
+            fallbacks: Vec::new(),
         }
     }
 
@@ -147,6 +432,8 @@ impl Model {
         Self {
             completer,
             embedder: Arc::new(NotImplemented),
+            reranker: None,
+            fallbacks: Vec::new(),
         }
     }
 
@@ -155,6 +442,8 @@ impl Model {
         Self {
             completer: Arc::new(NotImplemented),
             embedder: Arc::new(NotImplemented),
+            reranker: None,
+            fallbacks: Vec::new(),
         }
     }
 
@@ -163,25 +452,233 @@ impl Model {
         Self {
             completer: Arc::new(MockImplemented),
             embedder: Arc::new(MockImplemented),
+            reranker: None,
+            fallbacks: Vec::new(),
         }
     }
 
+    /// Sets the reranker of the retrieval pipeline
+    pub fn with_reranker(mut self, reranker: Arc<dyn RerankFeaturesDyn>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    /// Sets the fallback models of the completions, tried in order when the completion of
+    /// this model is rate limited, fails with a 5xx response or times out
+    pub fn with_fallbacks(mut self, fallbacks: Vec<Model>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
     pub async fn completion(&self, req: CompletionRequest) -> Result<AgentOutput, BoxError> {
-        self.completer.completion(req).await
+        self.completer
+            .completion(req)
+            .await
+            .map_err(|err| redact_error(timeout_error(err)))
+    }
+
+    /// Performs a completion request, sending the deltas of the content as they are generated
+    pub async fn stream_completion(
+        &self,
+        req: CompletionRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> Result<AgentOutput, BoxError> {
+        self.completer
+            .stream_completion(req, deltas)
+            .await
+            .map_err(|err| redact_error(timeout_error(err)))
+    }
+
+    /// Checks that the completion model service is reachable
+    pub async fn health(&self) -> Result<(), BoxError> {
+        self.completer.health().await.map_err(redact_error)
+    }
+
+    /// Returns false if no completion model is configured
+    pub fn is_implemented(&self) -> bool {
+        self.completer.is_implemented()
     }
 
     pub fn ndims(&self) -> usize {
         self.embedder.ndims()
     }
 
+    /// Embeds the texts in batches of the embedder's
+    /// [`max_batch_size`](EmbeddingFeaturesDyn::max_batch_size), up to
+    /// [`MAX_EMBED_CONCURRENCY`] batches at once, and returns the embeddings in order
     pub async fn embed(
         &self,
         texts: impl IntoIterator<Item = String> + Send,
     ) -> Result<(Vec<Embedding>, Usage), BoxError> {
-        self.embedder.embed(texts.into_iter().collect()).await
+        let mut texts: Vec<String> = texts.into_iter().collect();
+        let batch_size = self.embedder.max_batch_size().max(1);
+        if texts.len() <= batch_size {
+            return self.embedder.embed(texts).await.map_err(redact_error);
+        }
+
+        let mut batches = Vec::with_capacity(texts.len().div_ceil(batch_size));
+        while !texts.is_empty() {
+            let rest = texts.split_off(texts.len().min(batch_size));
+            batches.push(std::mem::replace(&mut texts, rest));
+        }
+        let results: Vec<_> = futures::stream::iter(batches)
+            .map(|batch| self.embedder.embed(batch))
+            .buffered(MAX_EMBED_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut embeddings = Vec::new();
+        let mut usage = Usage::default();
+        for res in results {
+            let (batch, u) = res.map_err(redact_error)?;
+            embeddings.extend(batch);
+            usage.accumulate(&u);
+        }
+        Ok((embeddings, usage))
     }
 
     pub async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
-        self.embedder.embed_query(text.to_string()).await
+        self.embedder
+            .embed_query(text.to_string())
+            .await
+            .map_err(redact_error)
+    }
+}
+
+/// Converts a timed out model request to an [`Error::Timeout`], so that it keeps its kind
+/// when redacted.
+fn timeout_error(err: BoxError) -> BoxError {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => Error::Timeout("model request timed out".to_string()).into(),
+        _ => err,
+    }
+}
+
+/// Returns true if a completion should fail over to the next model of the fallback chain:
+/// the model is rate limited, fails with a 5xx response or times out.
+pub fn is_failover_error(err: &BoxError) -> bool {
+    match anda_error(err) {
+        Some(Error::Timeout(_)) => true,
+        Some(err) => err.is_retryable(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Embeds numbers as their value, in batches of 3.
+    struct NumberEmbedder;
+
+    impl EmbeddingFeaturesDyn for NumberEmbedder {
+        fn ndims(&self) -> usize {
+            1
+        }
+
+        fn max_batch_size(&self) -> usize {
+            3
+        }
+
+        fn embed(
+            &self,
+            texts: Vec<String>,
+        ) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+            let res = if texts.len() > 3 {
+                Err("Too many documents, max is 3".into())
+            } else {
+                let usage = Usage {
+                    input_tokens: texts.len() as u64,
+                    requests: 1,
+                    ..Default::default()
+                };
+                let embeddings = texts
+                    .into_iter()
+                    .map(|text| Embedding {
+                        vec: vec![text.parse().unwrap()],
+                        text,
+                    })
+                    .collect();
+                Ok((embeddings, usage))
+            };
+            Box::pin(futures::future::ready(res))
+        }
+
+        fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+            let res = self.embed(vec![text]);
+            Box::pin(async move {
+                let (mut embeddings, usage) = res.await?;
+                Ok((embeddings.pop().unwrap(), usage))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_embed_batches() {
+        let model = Model::new(Arc::new(NotImplemented), Arc::new(NumberEmbedder));
+        let (embeddings, usage) = model.embed((0..10).map(|i| i.to_string())).await.unwrap();
+        assert_eq!(embeddings.len(), 10);
+        for (i, embedding) in embeddings.iter().enumerate() {
+            assert_eq!(embedding.text, i.to_string());
+            assert_eq!(embedding.vec, vec![i as f32]);
+        }
+        assert_eq!(usage.input_tokens, 10);
+        assert_eq!(usage.requests, 4);
+
+        let (embeddings, _) = model.embed(Vec::new()).await.unwrap();
+        assert!(embeddings.is_empty());
+    }
+
+    #[test]
+    fn test_is_failover_error() {
+        let err = |status| Error::Provider {
+            provider: "OpenAI completions".to_string(),
+            status: Some(status),
+            message: "error".to_string(),
+        };
+        assert!(is_failover_error(&err(429).into()));
+        assert!(is_failover_error(&err(503).into()));
+        assert!(!is_failover_error(&err(400).into()));
+        assert!(is_failover_error(
+            &Error::Timeout("model request timed out".to_string()).into()
+        ));
+        assert!(!is_failover_error(&"invalid response".into()));
+    }
+
+    #[test]
+    fn test_chat_completion_stream() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut stream = ChatCompletionStream::default();
+        for data in [
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello"}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"content":" world"}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"search","arguments":""}}]}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"q\":"}}]}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"rust\"}"}}]}}]}"#,
+            r#"{"model":"gpt-4o","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            r#"{"model":"gpt-4o","choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5,"total_tokens":15}}"#,
+        ] {
+            stream.feed(data, &tx).unwrap();
+        }
+
+        let output = stream.finish(vec![]).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), "Hello");
+        assert_eq!(rx.try_recv().unwrap(), " world");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(output.content, "Hello world");
+        assert!(output.failed_reason.is_none());
+        let tool_calls = output.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].name, "search");
+        assert_eq!(tool_calls[0].args, r#"{"q":"rust"}"#);
+        assert_eq!(output.usage.input_tokens, 10);
+        assert_eq!(output.usage.output_tokens, 5);
+        let history = output.full_history.unwrap();
+        assert_eq!(history[0]["tool_calls"][0]["function"]["name"], "search");
+
+        let stream = ChatCompletionStream::default();
+        assert!(stream.finish(vec![]).is_err());
     }
 }
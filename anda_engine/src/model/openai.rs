@@ -13,9 +13,13 @@ use anda_core::{
 use log::{Level::Debug, log_enabled};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
 
-use super::{CompletionFeaturesDyn, EmbeddingFeaturesDyn};
+use super::{
+    CompletionFeaturesDyn, EmbeddingFeaturesDyn, azure::AzureConfig, models_health, provider_error,
+    read_chat_completion_stream,
+};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -46,6 +50,7 @@ pub const O3_MINI: &str = "o3-mini";
 pub struct Client {
     endpoint: String,
     http: reqwest::Client,
+    azure: Option<Arc<AzureConfig>>,
 }
 
 impl Client {
@@ -62,38 +67,41 @@ impl Client {
         };
         Self {
             endpoint,
-            http: reqwest::Client::builder()
-                .use_rustls_tls()
-                .https_only(true)
-                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
-                .http2_keep_alive_timeout(Duration::from_secs(15))
-                .http2_keep_alive_while_idle(true)
-                .connect_timeout(Duration::from_secs(10))
-                .timeout(Duration::from_secs(180))
-                .gzip(true)
-                .user_agent(APP_USER_AGENT)
-                .default_headers({
-                    let mut headers = reqwest::header::HeaderMap::new();
-                    let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
-                    headers.insert(http::header::CONTENT_TYPE, ct.clone());
-                    headers.insert(http::header::ACCEPT, ct);
-                    headers.insert(
-                        http::header::AUTHORIZATION,
-                        format!("Bearer {}", api_key)
-                            .parse()
-                            .expect("Bearer token should parse"),
-                    );
-                    headers
-                })
-                .build()
-                .expect("OpenAI reqwest client should build"),
+            http: http_client(Some(api_key)),
+            azure: None,
         }
     }
 
-    /// Creates a POST request builder for the given API path
-    fn post(&self, path: &str) -> reqwest::RequestBuilder {
-        let url = format!("{}{}", self.endpoint, path);
-        self.http.post(url)
+    /// Creates a client for the deployments of an Azure OpenAI resource,
+    /// the authentication is added to each request by the Azure config
+    pub(crate) fn azure(endpoint: &str, azure: AzureConfig) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            http: http_client(None),
+            azure: Some(Arc::new(azure)),
+        }
+    }
+
+    /// Creates a POST request builder for the given API path of the model
+    async fn post(&self, model: &str, path: &str) -> Result<reqwest::RequestBuilder, BoxError> {
+        match &self.azure {
+            Some(azure) => {
+                let url = azure.url(&self.endpoint, Some(model), path);
+                azure.authorize(self.http.post(url)).await
+            }
+            None => Ok(self.http.post(format!("{}{}", self.endpoint, path))),
+        }
+    }
+
+    /// Creates a GET request builder for the given API path
+    async fn get(&self, path: &str) -> Result<reqwest::RequestBuilder, BoxError> {
+        match &self.azure {
+            Some(azure) => {
+                let url = azure.url(&self.endpoint, None, path);
+                azure.authorize(self.http.get(url)).await
+            }
+            None => Ok(self.http.get(format!("{}{}", self.endpoint, path))),
+        }
     }
 
     /// Creates an embedding model with the given name
@@ -121,6 +129,36 @@ impl Client {
     }
 }
 
+fn http_client(api_key: Option<&str>) -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .https_only(true)
+        .http2_keep_alive_interval(Some(Duration::from_secs(25)))
+        .http2_keep_alive_timeout(Duration::from_secs(15))
+        .http2_keep_alive_while_idle(true)
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(180))
+        .gzip(true)
+        .user_agent(APP_USER_AGENT)
+        .default_headers({
+            let mut headers = reqwest::header::HeaderMap::new();
+            let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse().unwrap();
+            headers.insert(http::header::CONTENT_TYPE, ct.clone());
+            headers.insert(http::header::ACCEPT, ct);
+            if let Some(api_key) = api_key {
+                headers.insert(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {}", api_key)
+                        .parse()
+                        .expect("Bearer token should parse"),
+                );
+            }
+            headers
+        })
+        .build()
+        .expect("OpenAI reqwest client should build")
+}
+
 /// Response structure for OpenAI embedding API
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EmbeddingResponse {
@@ -157,7 +195,9 @@ impl EmbeddingResponse {
                     .total_tokens
                     .saturating_sub(self.usage.prompt_tokens) as u64,
                 requests: 1,
-            },
+                ..Default::default()
+            }
+            .with_model(&self.model),
         ))
     }
 }
@@ -221,10 +261,14 @@ impl CompletionResponse {
             usage: self
                 .usage
                 .as_ref()
-                .map(|u| ModelUsage {
-                    input_tokens: u.prompt_tokens as u64,
-                    output_tokens: u.completion_tokens as u64,
-                    requests: 1,
+                .map(|u| {
+                    ModelUsage {
+                        input_tokens: u.prompt_tokens as u64,
+                        output_tokens: u.completion_tokens as u64,
+                        requests: 1,
+                        ..Default::default()
+                    }
+                    .with_model(&self.model)
                 })
                 .unwrap_or_default(),
             ..Default::default()
@@ -300,6 +344,10 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
         self.ndims
     }
 
+    fn max_batch_size(&self) -> usize {
+        MAX_DOCUMENTS
+    }
+
     /// Generates embeddings for multiple texts in a batch
     /// Returns a vector of Embedding structs in the same order as input texts
     fn embed(
@@ -314,7 +362,8 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
             }
 
             let response = client
-                .post("/embeddings")
+                .post(&model, "/embeddings")
+                .await?
                 .json(&json!({
                     "model": model,
                     "input": texts,
@@ -328,8 +377,7 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
                     Err(err) => Err(format!("OpenAI embeddings error: {}", err).into()),
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("OpenAI embeddings error: {}", msg).into())
+                Err(provider_error("OpenAI embeddings", response).await)
             }
         })
     }
@@ -341,7 +389,8 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
         let client = self.client.clone();
         Box::pin(async move {
             let response = client
-                .post("/embeddings")
+                .post(&model, "/embeddings")
+                .await?
                 .json(&json!({
                     "model": model,
                     "input": text,
@@ -366,14 +415,15 @@ impl EmbeddingFeaturesDyn for EmbeddingModel {
                                     .saturating_sub(res.usage.prompt_tokens)
                                     as u64,
                                 requests: 1,
-                            },
+                                ..Default::default()
+                            }
+                            .with_model(&res.model),
                         ))
                     }
                     Err(err) => Err(format!("OpenAI embeddings error: {}", err).into()),
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("OpenAI embeddings error: {}", msg).into())
+                Err(provider_error("OpenAI embeddings", response).await)
             }
         })
     }
@@ -419,6 +469,93 @@ impl CompletionModel {
     fn is_new_model(&self) -> bool {
         self.model.starts_with("o1-")
     }
+
+    /// Builds the chat history and the body of a chat completions request.
+    fn request_body(&self, mut req: CompletionRequest) -> (Vec<Value>, Value) {
+        let is_new = self.is_new_model();
+        // Add preamble to chat history (if available)
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: if is_new {
+                    "developer".into()
+                } else {
+                    "system".into()
+                },
+                content: system.to_owned().into(),
+                name: req.system_name.clone(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
+
+        // Extend existing chat history
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.content_parts),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": full_history.clone(),
+        });
+
+        let obj = body.as_object_mut().unwrap();
+        if let Some(temperature) = req.temperature {
+            obj.insert("temperature".to_string(), Value::from(temperature));
+        }
+
+        if let Some(max_tokens) = req.max_tokens {
+            if is_new {
+                obj.insert("max_completion_tokens".to_string(), Value::from(max_tokens));
+            } else {
+                obj.insert("max_tokens".to_string(), Value::from(max_tokens));
+            }
+        }
+
+        if let Some(response_format) = req.response_format {
+            obj.insert("response_format".to_string(), response_format);
+        }
+
+        if let Some(stop) = req.stop {
+            obj.insert("stop".to_string(), Value::from(stop));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!(
+                    req.tools
+                        .into_iter()
+                        .map(ToolDefinition::from)
+                        .collect::<Vec<_>>()
+                ),
+            );
+            obj.insert(
+                "tool_choice".to_string(),
+                if req.tool_choice_required {
+                    Value::from("required")
+                } else {
+                    Value::from("auto")
+                },
+            );
+        };
+
+        (full_history, body)
+    }
 }
 
 // impl CompletionFeatures for CompletionModel {
@@ -432,100 +569,29 @@ impl CompletionModel {
 // }
 
 impl CompletionFeaturesDyn for CompletionModel {
-    fn completion(&self, mut req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
-        let is_new = self.is_new_model();
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        let client = self.client.clone();
+        Box::pin(async move { models_health(client.get("/models").await?).await })
+    }
+
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (full_history, body) = self.request_body(req);
         let model = self.model.clone();
         let client = self.client.clone();
 
         Box::pin(async move {
-            // Add preamble to chat history (if available)
-            let mut full_history = if let Some(system) = &req.system {
-                vec![json!(Message {
-                    role: if is_new {
-                        "developer".into()
-                    } else {
-                        "system".into()
-                    },
-                    content: system.to_owned().into(),
-                    name: req.system_name.clone(),
-                    ..Default::default()
-                })]
-            } else {
-                vec![]
-            };
-
-            // Extend existing chat history
-            full_history.append(&mut req.chat_history);
-
-            if !req.content_parts.is_empty() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: json!(req.content_parts),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            } else if let Some(prompt) = req.prompt_with_context() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: prompt.into(),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            }
-
-            let mut body = json!({
-                "model": model,
-                "messages": full_history.clone(),
-            });
-
-            let body = body.as_object_mut().unwrap();
-            if let Some(temperature) = req.temperature {
-                body.insert("temperature".to_string(), Value::from(temperature));
-            }
-
-            if let Some(max_tokens) = req.max_tokens {
-                if is_new {
-                    body.insert("max_completion_tokens".to_string(), Value::from(max_tokens));
-                } else {
-                    body.insert("max_tokens".to_string(), Value::from(max_tokens));
-                }
-            }
-
-            if let Some(response_format) = req.response_format {
-                body.insert("response_format".to_string(), response_format);
-            }
-
-            if let Some(stop) = req.stop {
-                body.insert("stop".to_string(), Value::from(stop));
-            }
-
-            if !req.tools.is_empty() {
-                body.insert(
-                    "tools".to_string(),
-                    json!(
-                        req.tools
-                            .into_iter()
-                            .map(ToolDefinition::from)
-                            .collect::<Vec<_>>()
-                    ),
-                );
-                body.insert(
-                    "tool_choice".to_string(),
-                    if req.tool_choice_required {
-                        Value::from("required")
-                    } else {
-                        Value::from("auto")
-                    },
-                );
-            };
-
             if log_enabled!(Debug) {
                 if let Ok(val) = serde_json::to_string(&body) {
                     log::debug!(request = val; "OpenAI completions request");
                 }
             }
 
-            let response = client.post("/chat/completions").json(body).send().await?;
+            let response = client
+                .post(&model, "/chat/completions")
+                .await?
+                .json(&body)
+                .send()
+                .await?;
             if response.status().is_success() {
                 let text = response.text().await?;
                 match serde_json::from_str::<CompletionResponse>(&text) {
@@ -542,8 +608,35 @@ impl CompletionFeaturesDyn for CompletionModel {
                     }
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("OpenAI completions error: {}", msg).into())
+                Err(provider_error("OpenAI completions", response).await)
+            }
+        })
+    }
+
+    fn stream_completion(
+        &self,
+        req: CompletionRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (full_history, mut body) = self.request_body(req);
+        body["stream"] = Value::from(true);
+        body["stream_options"] = json!({"include_usage": true});
+        let model = self.model.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let response = client
+                .post(&model, "/chat/completions")
+                .await?
+                .json(&body)
+                .send()
+                .await?;
+            if response.status().is_success() {
+                read_chat_completion_stream(response, full_history, &deltas)
+                    .await
+                    .map_err(|err| format!("OpenAI completions error: {}", err).into())
+            } else {
+                Err(provider_error("OpenAI completions", response).await)
             }
         })
     }
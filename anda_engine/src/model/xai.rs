@@ -13,8 +13,9 @@ use log::{Level::Debug, log_enabled};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::time::Duration;
+use tokio::sync::mpsc;
 
-use super::CompletionFeaturesDyn;
+use super::{CompletionFeaturesDyn, models_health, provider_error, read_chat_completion_stream};
 use crate::APP_USER_AGENT;
 
 // ================================================================
@@ -81,6 +82,7 @@ impl Client {
         self.http.post(url)
     }
 
+    /// Creates a GET request builder for the specified API path
     /// Creates a new completion model instance using the default Grok model
     pub fn completion_model(&self, model: &str) -> CompletionModel {
         CompletionModel::new(
@@ -227,94 +229,107 @@ impl CompletionModel {
             model: model.to_string(),
         }
     }
-}
 
-impl CompletionFeaturesDyn for CompletionModel {
-    fn completion(&self, mut req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
-        let model = self.model.clone();
-        let client = self.client.clone();
+    /// Builds the chat history and the body of a chat completions request.
+    fn request_body(&self, mut req: CompletionRequest) -> (Vec<Value>, Value) {
+        // Add system to chat history (if available)
+        let mut full_history = if let Some(system) = &req.system {
+            vec![json!(Message {
+                role: "system".into(),
+                content: system.to_owned().into(),
+                name: req.system_name.clone(),
+                ..Default::default()
+            })]
+        } else {
+            vec![]
+        };
 
-        Box::pin(async move {
-            // Add system to chat history (if available)
-            let mut full_history = if let Some(system) = &req.system {
-                vec![json!(Message {
-                    role: "system".into(),
-                    content: system.to_owned().into(),
-                    name: req.system_name.clone(),
-                    ..Default::default()
-                })]
-            } else {
-                vec![]
-            };
-
-            // Extend existing chat history
-            full_history.append(&mut req.chat_history);
-
-            if !req.content_parts.is_empty() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: json!(req.content_parts),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            } else if let Some(prompt) = req.prompt_with_context() {
-                full_history.push(json!(Message {
-                    role: "user".into(),
-                    content: prompt.into(),
-                    name: req.prompter_name,
-                    ..Default::default()
-                }));
-            }
+        // Extend existing chat history
+        full_history.append(&mut req.chat_history);
+
+        if !req.content_parts.is_empty() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: json!(req.content_parts),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        } else if let Some(prompt) = req.prompt_with_context() {
+            full_history.push(json!(Message {
+                role: "user".into(),
+                content: prompt.into(),
+                name: req.prompter_name,
+                ..Default::default()
+            }));
+        }
 
-            let mut body = json!({
-                "model": model,
-                "messages": full_history.clone(),
-            });
+        let mut body = json!({
+            "model": self.model,
+            "messages": full_history.clone(),
+        });
 
-            let body = body.as_object_mut().unwrap();
-            if let Some(temperature) = req.temperature {
-                body.insert("temperature".to_string(), Value::from(temperature));
-            }
+        let obj = body.as_object_mut().unwrap();
+        if let Some(temperature) = req.temperature {
+            obj.insert("temperature".to_string(), Value::from(temperature));
+        }
 
-            if let Some(max_tokens) = req.max_tokens {
-                body.insert("max_tokens".to_string(), Value::from(max_tokens));
-            }
+        if let Some(max_tokens) = req.max_tokens {
+            obj.insert("max_tokens".to_string(), Value::from(max_tokens));
+        }
 
-            if let Some(response_format) = req.response_format {
-                body.insert("response_format".to_string(), response_format);
-            }
+        if let Some(response_format) = req.response_format {
+            obj.insert("response_format".to_string(), response_format);
+        }
 
-            if let Some(stop) = req.stop {
-                body.insert("stop".to_string(), Value::from(stop));
-            }
+        if let Some(stop) = req.stop {
+            obj.insert("stop".to_string(), Value::from(stop));
+        }
+
+        if !req.tools.is_empty() {
+            obj.insert(
+                "tools".to_string(),
+                json!(
+                    req.tools
+                        .into_iter()
+                        .map(ToolDefinition::from)
+                        .collect::<Vec<_>>()
+                ),
+            );
+            obj.insert(
+                "tool_choice".to_string(),
+                if req.tool_choice_required {
+                    Value::from("required")
+                } else {
+                    Value::from("auto")
+                },
+            );
+        };
 
-            if !req.tools.is_empty() {
-                body.insert(
-                    "tools".to_string(),
-                    json!(
-                        req.tools
-                            .into_iter()
-                            .map(ToolDefinition::from)
-                            .collect::<Vec<_>>()
-                    ),
-                );
-                body.insert(
-                    "tool_choice".to_string(),
-                    if req.tool_choice_required {
-                        Value::from("required")
-                    } else {
-                        Value::from("auto")
-                    },
-                );
-            };
+        (full_history, body)
+    }
+}
 
+impl CompletionFeaturesDyn for CompletionModel {
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        let req = self
+            .client
+            .http
+            .get(format!("{}/models", self.client.endpoint));
+        Box::pin(models_health(req))
+    }
+
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (full_history, body) = self.request_body(req);
+        let client = self.client.clone();
+
+        Box::pin(async move {
             if log_enabled!(Debug) {
                 if let Ok(val) = serde_json::to_string(&body) {
                     log::debug!(request = val; "Grok completions request");
                 }
             }
 
-            let response = client.post("/chat/completions").json(body).send().await?;
+            let response = client.post("/chat/completions").json(&body).send().await?;
             if response.status().is_success() {
                 let text = response.text().await?;
                 match serde_json::from_str::<CompletionResponse>(&text) {
@@ -331,8 +346,29 @@ impl CompletionFeaturesDyn for CompletionModel {
                     }
                 }
             } else {
-                let msg = response.text().await?;
-                Err(format!("Grok completions error: {}", msg).into())
+                Err(provider_error("Grok completions", response).await)
+            }
+        })
+    }
+
+    fn stream_completion(
+        &self,
+        req: CompletionRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        let (full_history, mut body) = self.request_body(req);
+        body["stream"] = Value::from(true);
+        body["stream_options"] = json!({"include_usage": true});
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let response = client.post("/chat/completions").json(&body).send().await?;
+            if response.status().is_success() {
+                read_chat_completion_stream(response, full_history, &deltas)
+                    .await
+                    .map_err(|err| format!("Grok completions error: {}", err).into())
+            } else {
+                Err(provider_error("Grok completions", response).await)
             }
         })
     }
@@ -0,0 +1,212 @@
+//! Payment-gated agents and tools.
+//!
+//! A [`PaymentPolicy`] sets the price of agents and tools in the tokens of ICRC-2 ledgers.
+//! Before running a priced agent or tool, the engine pulls the price from the caller's
+//! account with `icrc2_transfer_from`, so the caller must first approve the engine as
+//! spender for the price plus the ledger fee. If the run fails, the price minus the ledger
+//! fee is refunded with `icrc1_transfer`. Payments and refunds are recorded in the audit log.
+//!
+//! # Example
+//! ```rust,ignore
+//! let policy = PaymentPolicy::default().with_agent(
+//!     "assistant",
+//!     Price { ledger: ICP_LEDGER, amount: 100_000 },
+//! );
+//! let engine = Engine::builder().with_payment_policy(policy).build(default_agent).await?;
+//! ```
+
+use anda_core::{BoxError, ByteArrayB64, CanisterCaller};
+use candid::{Nat, Principal};
+use icrc_ledger_types::{
+    icrc1::{
+        account::Account,
+        transfer::{Memo, TransferArg, TransferError},
+    },
+    icrc2::transfer_from::{TransferFromArgs, TransferFromError},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use structured_logger::unix_ms;
+
+use crate::context::Web3SDK;
+
+/// The price of an agent run or a tool call.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    /// The ICRC-2 ledger of the token.
+    pub ledger: Principal,
+    /// The amount in the smallest unit of the token.
+    pub amount: u64,
+}
+
+/// Prices of the agents and tools, the others are free.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaymentPolicy {
+    /// Subaccount of the engine receiving the payments, the default one if not set.
+    pub subaccount: Option<ByteArrayB64<32>>,
+    pub agents: BTreeMap<String, Price>,
+    pub tools: BTreeMap<String, Price>,
+}
+
+impl PaymentPolicy {
+    pub fn with_agent(mut self, name: &str, price: Price) -> Self {
+        self.agents.insert(name.to_ascii_lowercase(), price);
+        self
+    }
+
+    pub fn with_tool(mut self, name: &str, price: Price) -> Self {
+        self.tools.insert(name.to_string(), price);
+        self
+    }
+
+    pub fn with_subaccount(mut self, subaccount: [u8; 32]) -> Self {
+        self.subaccount = Some(ByteArrayB64(subaccount));
+        self
+    }
+
+    pub fn agent_price(&self, name: &str) -> Option<&Price> {
+        self.agents.get(name)
+    }
+
+    pub fn tool_price(&self, name: &str) -> Option<&Price> {
+        self.tools.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty() && self.tools.is_empty()
+    }
+
+    /// Returns the engine's account receiving the payments.
+    pub fn account(&self, engine: Principal) -> Account {
+        Account {
+            owner: engine,
+            subaccount: self.subaccount.as_ref().map(|s| s.0),
+        }
+    }
+}
+
+/// A payment pulled from a caller.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Payment {
+    pub ledger: Principal,
+    pub payer: Principal,
+    pub amount: u64,
+    /// The block index of the transfer.
+    pub block_index: Nat,
+}
+
+/// Maximum length of a memo accepted by the ledgers.
+const MAX_MEMO_LEN: usize = 32;
+
+/// Pulls the price from the payer's default account to the engine's account.
+pub(crate) async fn charge(
+    web3: &Web3SDK,
+    to: Account,
+    payer: Principal,
+    price: &Price,
+    memo: &str,
+) -> Result<Payment, BoxError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: payer,
+            subaccount: None,
+        },
+        to,
+        amount: Nat::from(price.amount),
+        fee: None,
+        memo: Some(Memo::from(
+            memo.as_bytes()[..memo.len().min(MAX_MEMO_LEN)].to_vec(),
+        )),
+        created_at_time: Some(unix_ms() * 1_000_000),
+    };
+    let res: Result<Nat, TransferFromError> = web3
+        .canister_update(&price.ledger, "icrc2_transfer_from", (args,))
+        .await?;
+    let block_index = res.map_err(|err| {
+        format!(
+            "payment of {} on ledger {} failed: {:?}",
+            price.amount,
+            price.ledger.to_text(),
+            err
+        )
+    })?;
+    Ok(Payment {
+        ledger: price.ledger,
+        payer,
+        amount: price.amount,
+        block_index,
+    })
+}
+
+/// Refunds a payment minus the ledger fee, returns None if the fee exceeds the payment.
+pub(crate) async fn refund(
+    web3: &Web3SDK,
+    from_subaccount: Option<[u8; 32]>,
+    payment: &Payment,
+) -> Result<Option<Nat>, BoxError> {
+    let fee: Nat = web3
+        .canister_query(&payment.ledger, "icrc1_fee", ())
+        .await?;
+    let amount = Nat::from(payment.amount);
+    if amount <= fee {
+        return Ok(None);
+    }
+
+    let args = TransferArg {
+        from_subaccount,
+        to: Account {
+            owner: payment.payer,
+            subaccount: None,
+        },
+        fee: Some(fee.clone()),
+        created_at_time: Some(unix_ms() * 1_000_000),
+        memo: Some(Memo::from(
+            format!("refund:{}", payment.block_index).into_bytes(),
+        )),
+        amount: amount - fee,
+    };
+    let res: Result<Nat, TransferError> = web3
+        .canister_update(&payment.ledger, "icrc1_transfer", (args,))
+        .await?;
+    let block_index = res.map_err(|err| {
+        format!(
+            "refund of payment {} on ledger {} failed: {:?}",
+            payment.block_index,
+            payment.ledger.to_text(),
+            err
+        )
+    })?;
+    Ok(Some(block_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_policy() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let engine = Principal::from_slice(&[1]);
+        let price = Price {
+            ledger,
+            amount: 100_000,
+        };
+        let policy = PaymentPolicy::default()
+            .with_agent("Assistant", price.clone())
+            .with_tool("google_web_search", price.clone());
+        assert!(!policy.is_empty());
+        assert_eq!(policy.agent_price("assistant"), Some(&price));
+        assert!(policy.agent_price("other").is_none());
+        assert_eq!(policy.tool_price("google_web_search"), Some(&price));
+        assert_eq!(policy.account(engine).subaccount, None);
+
+        let policy = policy.with_subaccount([2u8; 32]);
+        assert_eq!(policy.account(engine).subaccount, Some([2u8; 32]));
+        let json = serde_json::to_string(&policy).unwrap();
+        let policy2: PaymentPolicy = serde_json::from_str(&json).unwrap();
+        assert_eq!(policy, policy2);
+    }
+}
@@ -0,0 +1,443 @@
+//! Per-user usage quotas and accounting.
+//!
+//! The engine accounts the usage of each user, LLM tokens and their estimated cost, agent runs
+//! and tool calls, by day and by calendar month (UTC), and rejects the agent runs and tool
+//! calls of the users who exhausted a daily or monthly quota with [`Error::RateLimited`].
+//! A quota is reached once the usage is at the limit, a running request is not interrupted.
+//!
+//! A user is the caller, or the end user named by [`RequestMeta::user`] of a caller that serves
+//! several users, such as a web backend or a bot, with usage accounted per caller and user.
+//! The usage is cached in memory and persisted to the engine's store periodically, and when
+//! the engine shuts down, so that the quotas hold across restarts.
+//!
+//! # Example
+//! ```toml
+//! [quotas]
+//! daily = { max_tokens = 100000, max_tool_calls = 500 }
+//! monthly = { max_cost_usd = 10.0 }
+//! ```
+
+use anda_core::{BoxError, Clock, Error, Path, PutMode, RequestMeta, SystemClock};
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{metering::UsageCounters, store::Store};
+
+/// The store namespace of the users' usage.
+pub static QUOTAS_PATH: &str = "_quotas";
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// Clean entries are evicted from the cache beyond this number.
+const MAX_CACHED: usize = 10_000;
+
+/// Limits of the usage of a user in a day or a month, unlimited if not set.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaLimits {
+    /// Maximum number of LLM tokens, input and output.
+    pub max_tokens: Option<u64>,
+    /// Maximum estimated cost of the tokens, in USD.
+    pub max_cost_usd: Option<f64>,
+    pub max_agent_runs: Option<u64>,
+    /// Maximum number of tool calls, by the user or by the agents it runs.
+    pub max_tool_calls: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Returns the name of the exhausted quota, if any.
+    fn exceeded(&self, usage: &UsageCounters, agent_run: bool) -> Option<&'static str> {
+        if self
+            .max_tokens
+            .is_some_and(|max| usage.input_tokens + usage.output_tokens >= max)
+        {
+            return Some("token");
+        }
+        if self
+            .max_cost_usd
+            .is_some_and(|max| usage.cost_micro_usd as f64 >= max * 1_000_000.0)
+        {
+            return Some("cost");
+        }
+        if agent_run
+            && self
+                .max_agent_runs
+                .is_some_and(|max| usage.agent_runs >= max)
+        {
+            return Some("agent run");
+        }
+        if !agent_run
+            && self
+                .max_tool_calls
+                .is_some_and(|max| usage.tool_calls >= max)
+        {
+            return Some("tool call");
+        }
+        None
+    }
+}
+
+/// Configuration of the user quotas.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    /// Quotas of a day, reset at 00:00 UTC.
+    pub daily: QuotaLimits,
+    /// Quotas of a calendar month, reset on its first day at 00:00 UTC.
+    pub monthly: QuotaLimits,
+    /// Interval of the persistence of the usage, in seconds.
+    pub flush_secs: u64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            daily: QuotaLimits::default(),
+            monthly: QuotaLimits::default(),
+            flush_secs: 60,
+        }
+    }
+}
+
+/// Usage of a user in the current day and month.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UserUsage {
+    /// Days since the unix epoch.
+    pub day: u64,
+    /// Months since the unix epoch.
+    pub month: u64,
+    pub daily: UsageCounters,
+    pub monthly: UsageCounters,
+}
+
+impl UserUsage {
+    /// Resets the counters of a past day or month.
+    fn roll(&mut self, now_ms: u64) {
+        let day = now_ms / DAY_MS;
+        if self.day != day {
+            self.day = day;
+            self.daily = UsageCounters::default();
+        }
+        let month = month_of_day(day);
+        if self.month != month {
+            self.month = month;
+            self.monthly = UsageCounters::default();
+        }
+    }
+}
+
+/// Returns the months since the unix epoch of the day since the unix epoch.
+fn month_of_day(day: u64) -> u64 {
+    // civil_from_days of http://howardhinnant.github.io/date_algorithms.html
+    let z = day + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + u64::from(m <= 2);
+    (y - 1970) * 12 + m - 1
+}
+
+/// Returns the days since the unix epoch of the first day of the month since the unix epoch.
+fn first_day_of_month(month: u64) -> u64 {
+    // days_from_civil of http://howardhinnant.github.io/date_algorithms.html
+    let (y, m) = (1970 + month / 12, month % 12 + 1);
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the user of a request: the caller, or the end user of the caller.
+pub fn quota_user(caller: &Principal, meta: &RequestMeta) -> String {
+    match &meta.user {
+        Some(user) => format!("{}/{}", caller.to_text(), user),
+        None => caller.to_text(),
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    usage: UserUsage,
+    // merged with the persisted usage
+    loaded: bool,
+    dirty: bool,
+}
+
+/// The user quotas of an engine.
+pub struct Quotas {
+    store: Store,
+    namespace: Path,
+    cfg: QuotaConfig,
+    clock: Arc<dyn Clock>,
+    cache: Mutex<HashMap<String, Entry>>,
+    // serializes the flushes
+    flush: tokio::sync::Mutex<()>,
+}
+
+impl Quotas {
+    pub fn new(store: Store, cfg: QuotaConfig) -> Self {
+        Self {
+            store,
+            namespace: Path::from(QUOTAS_PATH),
+            cfg,
+            clock: Arc::new(SystemClock),
+            cache: Mutex::new(HashMap::new()),
+            flush: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Sets the clock that rolls the loaded usage to the current day and month.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn user_path(user: &str) -> Path {
+        // the user is a single segment, whatever its characters
+        Path::from_iter(["users", user])
+    }
+
+    /// Loads the persisted usage of the user into the cache, if not done yet.
+    async fn load(&self, user: &str) -> Result<(), BoxError> {
+        if self
+            .cache
+            .lock()
+            .expect("quotas lock poisoned")
+            .get(user)
+            .is_some_and(|e| e.loaded)
+        {
+            return Ok(());
+        }
+        let stored: UserUsage = match self
+            .store
+            .store_get(&self.namespace, &Self::user_path(user))
+            .await
+        {
+            Ok((data, _)) => ciborium::from_reader(&data[..])?,
+            Err(_) => UserUsage::default(),
+        };
+
+        let now_ms = self.clock.now_ms();
+        let mut cache = self.cache.lock().expect("quotas lock poisoned");
+        let entry = cache.entry(user.to_string()).or_default();
+        if !entry.loaded {
+            // merges the usage recorded before the load
+            let mut usage = stored;
+            usage.roll(now_ms);
+            entry.usage.roll(now_ms);
+            usage.daily.accumulate(&entry.usage.daily);
+            usage.monthly.accumulate(&entry.usage.monthly);
+            entry.usage = usage;
+            entry.loaded = true;
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the user exhausted a daily or monthly quota of agent runs (if
+    /// `agent_run`) or tool calls.
+    pub async fn check(&self, user: &str, agent_run: bool, now_ms: u64) -> Result<(), BoxError> {
+        self.load(user).await?;
+        let mut cache = self.cache.lock().expect("quotas lock poisoned");
+        let entry = cache.entry(user.to_string()).or_default();
+        entry.usage.roll(now_ms);
+        if let Some(quota) = self.cfg.daily.exceeded(&entry.usage.daily, agent_run) {
+            return Err(Error::RateLimited {
+                message: format!("user {} exceeded its daily {} quota", user, quota),
+                retry_after_ms: Some(DAY_MS - now_ms % DAY_MS),
+            }
+            .into());
+        }
+        if let Some(quota) = self.cfg.monthly.exceeded(&entry.usage.monthly, agent_run) {
+            let next_month = first_day_of_month(entry.usage.month + 1) * DAY_MS;
+            return Err(Error::RateLimited {
+                message: format!("user {} exceeded its monthly {} quota", user, quota),
+                retry_after_ms: Some(next_month - now_ms),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Records usage of the user in the current day and month.
+    pub fn record(&self, user: &str, now_ms: u64, f: impl FnOnce(&mut UsageCounters)) {
+        let mut delta = UsageCounters::default();
+        f(&mut delta);
+        let mut cache = self.cache.lock().expect("quotas lock poisoned");
+        let entry = cache.entry(user.to_string()).or_default();
+        entry.usage.roll(now_ms);
+        entry.usage.daily.accumulate(&delta);
+        entry.usage.monthly.accumulate(&delta);
+        entry.dirty = true;
+    }
+
+    /// Returns the usage of the user in the current day and month.
+    pub async fn usage(&self, user: &str) -> Result<UserUsage, BoxError> {
+        self.load(user).await?;
+        let mut cache = self.cache.lock().expect("quotas lock poisoned");
+        let entry = cache.entry(user.to_string()).or_default();
+        entry.usage.roll(self.clock.now_ms());
+        Ok(entry.usage)
+    }
+
+    /// Persists the usage recorded since the last flush, and evicts the clean entries of
+    /// a full cache. Returns the number of users persisted.
+    pub async fn flush(&self) -> Result<usize, BoxError> {
+        let _guard = self.flush.lock().await;
+        let dirty: Vec<String> = {
+            let cache = self.cache.lock().expect("quotas lock poisoned");
+            cache
+                .iter()
+                .filter(|(_, e)| e.dirty)
+                .map(|(user, _)| user.clone())
+                .collect()
+        };
+
+        let mut flushed = 0;
+        for user in dirty {
+            self.load(&user).await?;
+            let usage = {
+                let mut cache = self.cache.lock().expect("quotas lock poisoned");
+                let Some(entry) = cache.get_mut(&user) else {
+                    continue;
+                };
+                entry.dirty = false;
+                entry.usage
+            };
+            if let Err(err) = self
+                .store
+                .store_put(
+                    &self.namespace,
+                    &Self::user_path(&user),
+                    PutMode::Overwrite,
+                    to_cbor_bytes(&usage).into(),
+                )
+                .await
+            {
+                // kept for the next flush
+                if let Some(entry) = self
+                    .cache
+                    .lock()
+                    .expect("quotas lock poisoned")
+                    .get_mut(&user)
+                {
+                    entry.dirty = true;
+                }
+                return Err(err);
+            }
+            flushed += 1;
+        }
+
+        let mut cache = self.cache.lock().expect("quotas lock poisoned");
+        if cache.len() > MAX_CACHED {
+            cache.retain(|_, e| e.dirty);
+        }
+        Ok(flushed)
+    }
+
+    /// Flushes the usage periodically until cancelled.
+    pub(crate) async fn run(self: Arc<Self>, token: CancellationToken) {
+        let interval = Duration::from_secs(self.cfg.flush_secs.max(1));
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {},
+            }
+            if let Err(err) = self.flush().await {
+                log::error!("failed to flush user quotas: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{Usage, anda_error};
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_months() {
+        // 2024-01-01, 2024-02-29, 2024-03-01 and 1970-01-01
+        assert_eq!(month_of_day(19_723), 54 * 12);
+        assert_eq!(month_of_day(19_782), 54 * 12 + 1);
+        assert_eq!(month_of_day(19_783), 54 * 12 + 2);
+        assert_eq!(month_of_day(0), 0);
+        assert_eq!(first_day_of_month(54 * 12), 19_723);
+        assert_eq!(first_day_of_month(54 * 12 + 2), 19_783);
+        assert_eq!(first_day_of_month(0), 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_quotas() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let cfg = QuotaConfig {
+            daily: QuotaLimits {
+                max_tool_calls: Some(2),
+                ..Default::default()
+            },
+            monthly: QuotaLimits {
+                max_tokens: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let alice = Principal::from_slice(&[1]);
+        let user = quota_user(
+            &alice,
+            &RequestMeta {
+                user: Some("bob".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(user, format!("{}/bob", alice.to_text()));
+
+        let quotas = Quotas::new(store.clone(), cfg.clone());
+        let now = structured_logger::unix_ms();
+        quotas.check(&user, false, now).await.unwrap();
+        quotas.record(&user, now, |u| u.tool_calls += 2);
+        let err = quotas.check(&user, false, now).await.unwrap_err();
+        assert!(matches!(
+            anda_error(&err),
+            Some(Error::RateLimited {
+                retry_after_ms: Some(ms),
+                ..
+            }) if *ms <= DAY_MS
+        ));
+        // the agent runs are not limited
+        quotas.check(&user, true, now).await.unwrap();
+        quotas.record(&user, now, |u| {
+            u.agent_runs += 1;
+            u.add_tokens(&Usage {
+                input_tokens: 80,
+                output_tokens: 30,
+                ..Default::default()
+            })
+        });
+        let err = quotas.check(&user, true, now).await.unwrap_err();
+        assert!(err.to_string().contains("monthly token quota"));
+        assert_eq!(quotas.flush().await.unwrap(), 1);
+        assert_eq!(quotas.flush().await.unwrap(), 0);
+
+        // persisted, merged with the usage recorded before the load
+        let quotas = Quotas::new(store, cfg);
+        quotas.record(&user, now, |u| u.tool_calls += 1);
+        let usage = quotas.usage(&user).await.unwrap();
+        assert_eq!(usage.daily.tool_calls, 3);
+        assert_eq!(usage.monthly.input_tokens, 80);
+        assert!(quotas.check(&user, true, now).await.is_err());
+        quotas.check(&alice.to_text(), true, now).await.unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+//! Per-caller rate limiting of agent runs and tool calls.
+//!
+//! Each caller has a token bucket that holds up to `burst` requests and is refilled at
+//! `per_minute` requests per minute. The limit of a caller is given by its tier, or the
+//! default limit for signed callers without a tier, or the anonymous limit for unsigned
+//! callers, which all share a single bucket. With `per_agent`, a caller has a bucket for
+//! each agent (or tool) it calls instead of one for all its requests.
+//!
+//! An engine built with [`crate::engine::EngineBuilder::with_rate_limit`] checks the limit of
+//! the caller of every agent run and tool call, and rejects the exceeding ones with
+//! [`Error::RateLimited`], so that public engines are protected whatever the transport.
+//! The server uses the same limiter in front of the engines.
+
+use anda_core::{BoxError, Error};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Instant,
+};
+
+/// The tier name of callers without limit.
+pub const UNLIMITED_TIER: &str = "unlimited";
+
+/// Buckets are pruned of the full ones beyond this number.
+const MAX_BUCKETS: usize = 100_000;
+
+/// A token bucket limit.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Maximum number of requests at once.
+    pub burst: u32,
+    /// Number of requests refilled per minute.
+    pub per_minute: u32,
+}
+
+/// Rate limiting configuration of the engine or the server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Limit of signed callers without a tier, unlimited if not set.
+    pub default: Option<RateLimit>,
+    /// Limit shared by all anonymous callers, the default limit if not set.
+    pub anonymous: Option<RateLimit>,
+    /// Named tiers of limits.
+    #[serde(default)]
+    pub tiers: BTreeMap<String, RateLimit>,
+    /// Tiers of callers, by principal text. The "unlimited" tier is not limited.
+    #[serde(default)]
+    pub identities: BTreeMap<String, String>,
+    /// Limits each agent or tool of a caller separately.
+    #[serde(default)]
+    pub per_agent: bool,
+}
+
+/// The outcome of a rate limit check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// The burst of the caller's limit.
+    pub limit: u32,
+    /// Requests left in the bucket.
+    pub remaining: u32,
+    /// Seconds until the bucket is full.
+    pub reset_secs: u64,
+    /// Seconds until the next request is allowed, 0 if allowed.
+    pub retry_after_secs: u64,
+}
+
+impl RateLimitDecision {
+    /// Returns the [`Error::RateLimited`] of a rejected request.
+    pub fn to_error(&self) -> Error {
+        Error::RateLimited {
+            message: format!(
+                "rate limit exceeded, retry after {}s",
+                self.retry_after_secs
+            ),
+            retry_after_ms: Some(self.retry_after_secs * 1000),
+        }
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate(&self.limit)).min(self.limit.burst as f64);
+        self.updated_at = now;
+    }
+}
+
+fn rate(limit: &RateLimit) -> f64 {
+    limit.per_minute as f64 / 60.0
+}
+
+/// Token bucket rate limiter keyed by caller, and optionally by agent.
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    anonymous: Option<RateLimit>,
+    identities: BTreeMap<Principal, Option<RateLimit>>,
+    per_agent: bool,
+    buckets: Mutex<HashMap<(Principal, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter, checking that the limits are valid and the tiers defined.
+    pub fn new(cfg: RateLimitConfig) -> Result<Self, BoxError> {
+        let check = |name: &str, limit: &RateLimit| -> Result<(), BoxError> {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                return Err(format!(
+                    "invalid rate limit {name:?}: burst and per_minute must be positive"
+                )
+                .into());
+            }
+            Ok(())
+        };
+        if let Some(limit) = &cfg.default {
+            check("default", limit)?;
+        }
+        if let Some(limit) = &cfg.anonymous {
+            check("anonymous", limit)?;
+        }
+        for (name, limit) in &cfg.tiers {
+            check(name, limit)?;
+        }
+
+        let mut identities = BTreeMap::new();
+        for (id, tier) in cfg.identities {
+            let principal = Principal::from_text(&id)
+                .map_err(|err| format!("invalid rate limit identity {id:?}: {err:?}"))?;
+            let limit = if tier == UNLIMITED_TIER {
+                None
+            } else {
+                Some(
+                    *cfg.tiers
+                        .get(&tier)
+                        .ok_or_else(|| format!("rate limit tier {tier:?} of {id} not found"))?,
+                )
+            };
+            identities.insert(principal, limit);
+        }
+
+        Ok(Self {
+            default: cfg.default,
+            anonymous: cfg.anonymous.or(cfg.default),
+            identities,
+            per_agent: cfg.per_agent,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a request from the caller's bucket, returns None if the caller is not limited.
+    pub fn check(&self, caller: &Principal, agent: &str) -> Option<RateLimitDecision> {
+        self.check_at(caller, agent, Instant::now())
+    }
+
+    /// Takes a request from the caller's bucket, returns [`Error::RateLimited`] if it is empty.
+    pub fn try_acquire(&self, caller: &Principal, agent: &str) -> Result<(), Error> {
+        match self.check(caller, agent) {
+            Some(limit) if !limit.allowed => Err(limit.to_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn limit_of(&self, caller: &Principal) -> Option<RateLimit> {
+        if caller == &Principal::anonymous() {
+            return self.anonymous;
+        }
+        match self.identities.get(caller) {
+            Some(limit) => *limit,
+            None => self.default,
+        }
+    }
+
+    fn check_at(&self, caller: &Principal, agent: &str, now: Instant) -> Option<RateLimitDecision> {
+        let limit = self.limit_of(caller)?;
+        let key = if self.per_agent {
+            (*caller, agent.to_string())
+        } else {
+            (*caller, String::new())
+        };
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("rate limit buckets lock poisoned");
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            buckets.retain(|_, b| {
+                b.refill(now);
+                b.tokens < b.limit.burst as f64
+            });
+        }
+
+        let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated_at: now,
+        });
+        bucket.refill(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let rate = rate(&limit);
+        Some(RateLimitDecision {
+            allowed,
+            limit: limit.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((limit.burst as f64 - bucket.tokens) / rate).ceil() as u64,
+            retry_after_secs: if allowed {
+                0
+            } else {
+                ((1.0 - bucket.tokens) / rate).ceil() as u64
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let carol = Principal::from_slice(&[3]);
+        let cfg = RateLimitConfig {
+            default: Some(RateLimit {
+                burst: 2,
+                per_minute: 60,
+            }),
+            anonymous: Some(RateLimit {
+                burst: 1,
+                per_minute: 6,
+            }),
+            tiers: BTreeMap::from([(
+                "pro".to_string(),
+                RateLimit {
+                    burst: 10,
+                    per_minute: 600,
+                },
+            )]),
+            identities: BTreeMap::from([
+                (bob.to_text(), "pro".to_string()),
+                (carol.to_text(), UNLIMITED_TIER.to_string()),
+            ]),
+            per_agent: false,
+        };
+        let limiter = RateLimiter::new(cfg.clone()).unwrap();
+        let now = Instant::now();
+
+        let d = limiter.check_at(&alice, "a", now).unwrap();
+        assert!(d.allowed);
+        assert_eq!(d.limit, 2);
+        assert_eq!(d.remaining, 1);
+        assert_eq!(d.reset_secs, 1);
+        assert!(limiter.check_at(&alice, "b", now).unwrap().allowed);
+        let d = limiter.check_at(&alice, "a", now).unwrap();
+        assert!(!d.allowed);
+        assert_eq!(d.remaining, 0);
+        assert_eq!(d.retry_after_secs, 1);
+        let d = limiter
+            .check_at(&alice, "a", now + Duration::from_secs(1))
+            .unwrap();
+        assert!(d.allowed);
+
+        let d = limiter.check_at(&bob, "a", now).unwrap();
+        assert!(d.allowed);
+        assert_eq!(d.limit, 10);
+        assert!(limiter.check_at(&carol, "a", now).is_none());
+        assert!(limiter.try_acquire(&carol, "a").is_ok());
+        assert!(limiter.try_acquire(&bob, "a").is_ok());
+
+        let anonymous = Principal::anonymous();
+        assert!(limiter.check_at(&anonymous, "a", now).unwrap().allowed);
+        let d = limiter.check_at(&anonymous, "a", now).unwrap();
+        assert!(!d.allowed);
+        assert_eq!(d.retry_after_secs, 10);
+        assert!(matches!(
+            d.to_error(),
+            Error::RateLimited {
+                retry_after_ms: Some(10_000),
+                ..
+            }
+        ));
+
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_agent: true,
+            ..cfg.clone()
+        })
+        .unwrap();
+        assert!(limiter.check_at(&alice, "a", now).unwrap().allowed);
+        assert!(limiter.check_at(&alice, "a", now).unwrap().allowed);
+        assert!(!limiter.check_at(&alice, "a", now).unwrap().allowed);
+        assert!(limiter.check_at(&alice, "b", now).unwrap().allowed);
+
+        let res = RateLimiter::new(RateLimitConfig {
+            identities: BTreeMap::from([(alice.to_text(), "free".to_string())]),
+            ..cfg.clone()
+        });
+        assert!(res.is_err());
+        let res = RateLimiter::new(RateLimitConfig {
+            default: Some(RateLimit {
+                burst: 0,
+                per_minute: 1,
+            }),
+            ..cfg
+        });
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Discovery of remote engines with a registry canister.
+//!
+//! Instead of hard-coding the endpoints of the remote engines at build time, engines publish
+//! an [`EngineRecord`] of their [`Information`] to a registry canister on the Internet Computer,
+//! and the engines built with [`crate::engine::EngineBuilder::with_registry`] register the
+//! published engines as remote engines. They are discovered when the engine is built and
+//! refreshed periodically, along with the engine's own record if it has a public endpoint.
+//!
+//! A record only locates an engine: its information is fetched from the engine itself with
+//! a signed RPC, and the record is skipped if the engine's ID doesn't match. The remote
+//! engines registered explicitly keep precedence over the discovered ones with the same name.
+//!
+//! The registry canister implements:
+//! ```candid
+//! type EngineRecord = record {
+//!   id : principal;
+//!   name : text;
+//!   description : text;
+//!   endpoint : text;
+//!   agents : vec text;
+//!   tools : vec text;
+//! };
+//! service : {
+//!   // publishes the record of the caller, whose principal must be the record ID.
+//!   register_engine : (EngineRecord) -> (variant { Ok; Err : text });
+//!   // lists the records ordered by ID, after the given ID if any.
+//!   list_engines : (opt principal, nat32) -> (vec EngineRecord) query;
+//! }
+//! ```
+
+use anda_core::{BoxError, CanisterCaller};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, btree_map},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    context::{Information, RemoteEngineArgs, RemoteEngines, Web3SDK},
+    engine::Engine,
+};
+
+/// Maximum number of records listed per query.
+const LIST_LIMIT: u32 = 100;
+
+/// Configuration of the registry of engines.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// The registry canister.
+    pub canister: Principal,
+    /// The public endpoint of the engine, published to the registry if set.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Interval between two refreshes of the remote engines, in seconds.
+    #[serde(default = "default_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+fn default_refresh_secs() -> u64 {
+    300
+}
+
+/// The record of an engine in the registry canister.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EngineRecord {
+    /// The principal ID of the engine.
+    pub id: Principal,
+    pub name: String,
+    pub description: String,
+    /// The endpoint of the engine.
+    pub endpoint: String,
+    /// The names of the exported agents.
+    pub agents: Vec<String>,
+    /// The names of the exported tools.
+    pub tools: Vec<String>,
+}
+
+impl From<&Information> for EngineRecord {
+    fn from(info: &Information) -> Self {
+        Self {
+            id: info.id,
+            name: info.name.clone(),
+            description: info.description.clone(),
+            endpoint: info.endpoint.clone(),
+            agents: info
+                .agents
+                .iter()
+                .map(|f| f.definition.name.clone())
+                .collect(),
+            tools: info
+                .tools
+                .iter()
+                .map(|f| f.definition.name.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Client of a registry canister, keeping track of the discovered remote engines.
+pub struct Registry {
+    cfg: RegistryConfig,
+    /// Names of the remote engines registered from the registry.
+    discovered: Mutex<BTreeSet<String>>,
+}
+
+impl Registry {
+    pub fn new(cfg: RegistryConfig) -> Self {
+        Self {
+            cfg,
+            discovered: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the public endpoint of the engine, if published.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.cfg.endpoint.as_deref()
+    }
+
+    /// Returns the interval between two refreshes.
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.cfg.refresh_secs.max(1))
+    }
+
+    /// Lists all the records of the registry.
+    pub async fn list(&self, caller: &impl CanisterCaller) -> Result<Vec<EngineRecord>, BoxError> {
+        let mut records: Vec<EngineRecord> = Vec::new();
+        loop {
+            let start = records.last().map(|r| r.id);
+            let page: Vec<EngineRecord> = caller
+                .canister_query(&self.cfg.canister, "list_engines", (start, LIST_LIMIT))
+                .await?;
+            let done = page.len() < LIST_LIMIT as usize;
+            records.extend(page);
+            if done {
+                return Ok(records);
+            }
+        }
+    }
+
+    /// Publishes the record of the engine with its public endpoint.
+    pub async fn publish(
+        &self,
+        caller: &impl CanisterCaller,
+        mut record: EngineRecord,
+    ) -> Result<(), BoxError> {
+        let Some(endpoint) = &self.cfg.endpoint else {
+            return Err("registry endpoint not set".into());
+        };
+        record.endpoint = endpoint.clone();
+        let res: Result<(), String> = caller
+            .canister_update(&self.cfg.canister, "register_engine", (record,))
+            .await?;
+        res.map_err(|err| format!("failed to register engine: {}", err).into())
+    }
+
+    /// Returns the remote engines with the ones listed by the registry, replacing the ones
+    /// discovered previously. The engine itself, the engines that can't be reached or whose ID
+    /// doesn't match their record, and the names already registered are skipped.
+    pub(crate) async fn discover(
+        &self,
+        web3: &Web3SDK,
+        self_id: Principal,
+        current: &RemoteEngines,
+    ) -> Result<RemoteEngines, BoxError> {
+        let records = self.list(&web3).await?;
+        let previous = self.discovered.lock().unwrap().clone();
+        let mut remote = current.clone();
+        remote.engines.retain(|name, _| !previous.contains(name));
+
+        let mut discovered: BTreeSet<String> = BTreeSet::new();
+        for record in records {
+            if record.id == self_id || record.endpoint.is_empty() {
+                continue;
+            }
+            if remote.get_id_by_endpoint(&record.endpoint).is_some() {
+                continue;
+            }
+
+            let mut engine = RemoteEngines::new();
+            let args = RemoteEngineArgs {
+                endpoint: record.endpoint.clone(),
+                agents: Vec::new(),
+                tools: Vec::new(),
+                name: None,
+            };
+            if let Err(err) = engine.register(web3, args).await {
+                log::warn!(
+                    engine = record.id.to_text(),
+                    endpoint = record.endpoint;
+                    "failed to register discovered engine: {}", err,
+                );
+                continue;
+            }
+            if let Some((name, info)) = engine.engines.pop_first() {
+                if info.id != record.id {
+                    log::warn!(
+                        engine = record.id.to_text(),
+                        endpoint = record.endpoint;
+                        "discovered engine ID mismatch: {}", info.id.to_text(),
+                    );
+                } else if let btree_map::Entry::Vacant(entry) = remote.engines.entry(name) {
+                    discovered.insert(entry.key().clone());
+                    entry.insert(info);
+                }
+            }
+        }
+
+        *self.discovered.lock().unwrap() = discovered;
+        Ok(remote)
+    }
+}
+
+/// Refreshes the registry of the engine at each interval until the engine is cancelled.
+pub(crate) async fn run(engine: Engine, interval: Duration) {
+    let token = engine.cancellation_token();
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {},
+        }
+        if let Err(err) = engine.refresh_registry().await {
+            log::error!("failed to refresh the registry: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::{Function, FunctionDefinition};
+
+    #[test]
+    fn test_engine_record() {
+        let info = Information {
+            id: Principal::anonymous(),
+            name: "Anda".to_string(),
+            description: "test".to_string(),
+            agents: vec![Function {
+                definition: FunctionDefinition {
+                    name: "assistant".to_string(),
+                    ..Default::default()
+                },
+                supported_resource_tags: Vec::new(),
+            }],
+            tools: Vec::new(),
+            endpoint: "https://anda.example.com/default".to_string(),
+        };
+        let record = EngineRecord::from(&info);
+        assert_eq!(record.agents, vec!["assistant".to_string()]);
+        assert!(record.tools.is_empty());
+        assert_eq!(record.endpoint, info.endpoint);
+
+        let cfg: RegistryConfig =
+            toml::from_str(r#"canister = "ryjl3-tyaaa-aaaaa-aaaba-cai""#).unwrap();
+        assert_eq!(cfg.refresh_secs, 300);
+        assert!(cfg.endpoint.is_none());
+    }
+}
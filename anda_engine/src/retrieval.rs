@@ -0,0 +1,282 @@
+//! Retrieval of documents for retrieval-augmented generation.
+//!
+//! A [`Retriever`] embeds the query, searches the top N candidate chunks in a vector store,
+//! optionally reorders them with a reranker, such as a cross-encoder or a provider rerank API,
+//! and keeps the top K for [`CompletionRequest::documents`](anda_core::CompletionRequest).
+//! Reranking a larger candidate set than the vector search alone would return improves the
+//! answers on large knowledge bases, at the cost of a rerank call.
+//!
+//! The candidates may be restricted by a metadata [`VectorFilter`], e.g. on tags or on the
+//! ingestion time. Through the agent context, the search only finds the documents visible to
+//! the caller, with no access labels or with one of the caller's, so that the documents of other
+//! users and tenants never end up in a prompt.
+//!
+//! The engine's reranker is set on the [`Model`](crate::model::Model), or in the `rerank`
+//! section of the [`EngineConfig`](crate::config::EngineConfig), and used by
+//! [`AgentCtx::retriever`](crate::context::AgentCtx::retriever).
+//!
+//! # Example
+//! ```rust,ignore
+//! let retriever = ctx.retriever(5).with_candidates(50);
+//! let (matches, usage) = retriever.retrieve(&ctx, &ctx, &prompt).await?;
+//! let req = CompletionRequest {
+//!     prompt,
+//!     documents: Retriever::documents(matches),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! Or in one call, with [`AgentCtx::completion_with_knowledge`](crate::context::AgentCtx::completion_with_knowledge):
+//! ```rust,ignore
+//! let output = ctx
+//!     .completion_with_knowledge(req, None, Some("docs"), &ctx.retriever(5))
+//!     .await?;
+//! ```
+
+use anda_core::{
+    BoxError, Document, Documents, EmbeddingFeatures, Usage, VectorFilter, VectorMatch,
+    VectorStoreFeatures,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::model::RerankFeaturesDyn;
+
+/// Retrieves the documents relevant to a query from a vector store.
+#[derive(Clone)]
+pub struct Retriever {
+    top_k: usize,
+    candidates: usize,
+    min_score: Option<f32>,
+    filter: Option<VectorFilter>,
+    reranker: Option<Arc<dyn RerankFeaturesDyn>>,
+}
+
+impl Retriever {
+    /// Creates a retriever returning the top K documents, without reranker.
+    pub fn new(top_k: usize) -> Self {
+        Self {
+            top_k,
+            candidates: top_k,
+            min_score: None,
+            filter: None,
+            reranker: None,
+        }
+    }
+
+    /// Sets the number of candidates searched in the vector store and passed to the reranker,
+    /// at least top K.
+    pub fn with_candidates(mut self, candidates: usize) -> Self {
+        self.candidates = candidates;
+        self
+    }
+
+    /// Drops the documents scored below the minimum, by the reranker if any,
+    /// otherwise by the vector search.
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    /// Sets the metadata filter of the vector search.
+    pub fn with_filter(mut self, filter: VectorFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sets the reranker of the candidates.
+    pub fn with_reranker(mut self, reranker: Arc<dyn RerankFeaturesDyn>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    /// Retrieves the top K documents relevant to the query, most relevant first.
+    /// The scores are the reranker's relevance scores if there is a reranker.
+    pub async fn retrieve(
+        &self,
+        embedder: &impl EmbeddingFeatures,
+        store: &impl VectorStoreFeatures,
+        query: &str,
+    ) -> Result<(Vec<VectorMatch>, Usage), BoxError> {
+        if self.top_k == 0 {
+            return Ok((Vec::new(), Usage::default()));
+        }
+
+        let (embedding, usage) = embedder.embed_query(query).await?;
+        let candidates = match self.reranker {
+            Some(_) => self.candidates.max(self.top_k),
+            None => self.top_k,
+        };
+        let mut matches = store
+            .vector_search(embedding.vec, candidates, self.filter.clone())
+            .await?;
+
+        if let Some(reranker) = self.reranker.as_ref().filter(|_| !matches.is_empty()) {
+            let texts = matches.iter().map(|m| m.text.clone()).collect();
+            let ranked = reranker
+                .rerank(query.to_string(), texts, self.top_k)
+                .await?;
+            let mut slots: Vec<Option<VectorMatch>> = matches.into_iter().map(Some).collect();
+            matches = ranked
+                .into_iter()
+                .filter_map(|r| {
+                    let mut m = slots.get_mut(r.index)?.take()?;
+                    m.score = r.score;
+                    Some(m)
+                })
+                .collect();
+        }
+
+        if let Some(min_score) = self.min_score {
+            matches.retain(|m| m.score >= min_score);
+        }
+        matches.truncate(self.top_k);
+        Ok((matches, usage))
+    }
+
+    /// Converts the retrieved chunks to completion documents,
+    /// with the score and the string metadata of the chunks.
+    /// Their [`Citation`](anda_core::Citation)s are returned in the completion output.
+    pub fn documents(matches: Vec<VectorMatch>) -> Documents {
+        Documents(
+            matches
+                .into_iter()
+                .map(|m| {
+                    let mut metadata: BTreeMap<String, String> = m
+                        .meta
+                        .into_iter()
+                        .map(|(k, v)| match v {
+                            serde_json::Value::String(s) => (k, s),
+                            v => (k, v.to_string()),
+                        })
+                        .collect();
+                    metadata.insert("score".to_string(), format!("{:.4}", m.score));
+                    Document {
+                        id: m.id,
+                        text: m.text,
+                        metadata,
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::RerankResult, vector::HnswVectorStore};
+    use anda_core::{BoxPinFut, Embedding, VectorDocument};
+    use serde_json::json;
+
+    /// Embeds all texts to the same vector, so the vector search can't rank them.
+    struct FlatEmbedder;
+
+    impl EmbeddingFeatures for FlatEmbedder {
+        fn ndims(&self) -> usize {
+            2
+        }
+
+        async fn embed(
+            &self,
+            texts: impl IntoIterator<Item = String> + Send,
+        ) -> Result<(Vec<Embedding>, Usage), BoxError> {
+            Ok((
+                texts
+                    .into_iter()
+                    .map(|text| Embedding {
+                        text,
+                        vec: vec![1.0, 1.0],
+                    })
+                    .collect(),
+                Usage::default(),
+            ))
+        }
+
+        async fn embed_query(&self, text: &str) -> Result<(Embedding, Usage), BoxError> {
+            Ok((
+                Embedding {
+                    text: text.to_string(),
+                    vec: vec![1.0, 1.0],
+                },
+                Usage::default(),
+            ))
+        }
+    }
+
+    /// Scores the documents by the number of query words they contain.
+    struct WordReranker;
+
+    impl RerankFeaturesDyn for WordReranker {
+        fn rerank(
+            &self,
+            query: String,
+            documents: Vec<String>,
+            top_n: usize,
+        ) -> BoxPinFut<Result<Vec<RerankResult>, BoxError>> {
+            let mut results: Vec<RerankResult> = documents
+                .iter()
+                .enumerate()
+                .map(|(index, doc)| RerankResult {
+                    index,
+                    score: query.split(' ').filter(|w| doc.contains(w)).count() as f32,
+                })
+                .collect();
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+            results.truncate(top_n);
+            Box::pin(futures::future::ready(Ok(results)))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_retriever_rerank() {
+        let store = HnswVectorStore::default();
+        let texts = [
+            "red apple",
+            "green apple pie",
+            "yellow banana",
+            "apple pie recipe",
+        ];
+        store
+            .vector_upsert(
+                texts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, text)| VectorDocument {
+                        id: i.to_string(),
+                        vec: vec![1.0, 1.0],
+                        text: text.to_string(),
+                        meta: serde_json::from_value(json!({"source": "fruits.md", "chunk": i}))
+                            .unwrap(),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let retriever = Retriever::new(2)
+            .with_candidates(10)
+            .with_min_score(2.0)
+            .with_reranker(Arc::new(WordReranker));
+        let (matches, _) = retriever
+            .retrieve(&FlatEmbedder, &store, "apple pie recipe")
+            .await
+            .unwrap();
+        assert_eq!(
+            matches.iter().map(|m| m.text.as_str()).collect::<Vec<_>>(),
+            vec!["apple pie recipe", "green apple pie"]
+        );
+        assert_eq!(matches[0].score, 3.0);
+
+        let docs = Retriever::documents(matches);
+        assert_eq!(docs.0[0].id, "3");
+        assert_eq!(docs.0[0].metadata["source"], "fruits.md");
+        assert_eq!(docs.0[0].metadata["chunk"], "3");
+        assert_eq!(docs.0[0].metadata["score"], "3.0000");
+
+        let (matches, _) = Retriever::new(3)
+            .retrieve(&FlatEmbedder, &store, "apple")
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 3);
+    }
+}
@@ -0,0 +1,484 @@
+//! Secrets for provider keys and other credentials.
+//!
+//! A [`Secret`] is loaded from a [`SecretSource`]: an environment variable, a file
+//! (e.g. a mounted Kubernetes or Docker secret) or an external [`SecretsProvider`]
+//! such as a vault. [`Secrets`] keeps the loaded secrets and refreshes them periodically,
+//! and [`SecretCompleter`] / [`SecretEmbedder`] rebuild a model client when its key rotates.
+//!
+//! Every loaded value is registered for redaction: [`redact`] replaces them with
+//! [`REDACTED`] and is applied to model errors, tool errors and server responses,
+//! so that keys do not leak into logs or error strings. `Debug` never prints a secret.
+//!
+//! # Example
+//! ```rust,ignore
+//! let secrets = Arc::new(Secrets::new());
+//! let key = secrets
+//!     .load("deepseek", SecretSource::parse("file:/run/secrets/deepseek")?)
+//!     .await?;
+//! let completer = SecretCompleter::new(key, |key| {
+//!     Arc::new(deepseek::Client::new(key, None).completion_model(""))
+//! });
+//! let model = Model::with_completer(Arc::new(completer));
+//! secrets.clone().spawn_refresh(Duration::from_secs(300), CancellationToken::new());
+//! ```
+
+use anda_core::{AgentOutput, BoxError, BoxPinFut, CompletionRequest, Embedding, Error, Usage};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, LazyLock, RwLock},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::model::{CompletionFeaturesDyn, EmbeddingFeaturesDyn};
+
+/// The replacement of redacted secrets.
+pub static REDACTED: &str = "[REDACTED]";
+
+/// Values shorter than this are not redacted, to avoid mangling unrelated text.
+const MIN_REDACTED_LEN: usize = 8;
+
+static REDACTIONS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// Registers a secret value to be redacted by [`redact`].
+pub fn register_redaction(value: &str) {
+    let value = value.trim();
+    if value.len() < MIN_REDACTED_LEN {
+        return;
+    }
+    let mut list = REDACTIONS.write().expect("redactions lock poisoned");
+    if !list.iter().any(|v| v == value) {
+        list.push(value.to_string());
+        // longer values first, so that a secret containing another is fully redacted
+        list.sort_by_key(|v| Reverse(v.len()));
+    }
+}
+
+/// Replaces all registered secret values in the text with [`REDACTED`].
+pub fn redact(text: &str) -> String {
+    let list = REDACTIONS.read().expect("redactions lock poisoned");
+    let mut text = text.to_string();
+    for value in list.iter() {
+        if text.contains(value.as_str()) {
+            text = text.replace(value.as_str(), REDACTED);
+        }
+    }
+    text
+}
+
+/// Redacts the message of an error, a structured [`Error`] keeps its kind.
+pub fn redact_error(err: BoxError) -> BoxError {
+    match err.downcast::<Error>() {
+        Ok(err) => match *err {
+            Error::Provider {
+                provider,
+                status,
+                message,
+            } => Error::Provider {
+                provider,
+                status,
+                message: redact(&message),
+            }
+            .into(),
+            Error::Remote { endpoint, message } => Error::Remote {
+                endpoint,
+                message: redact(&message),
+            }
+            .into(),
+            err => err.into(),
+        },
+        Err(err) => redact(&err.to_string()).into(),
+    }
+}
+
+/// A secret value that can be rotated, its `Debug` output is redacted.
+#[derive(Clone)]
+pub struct Secret {
+    name: String,
+    // (version, value), the version increases when the value changes
+    value: Arc<RwLock<(u64, String)>>,
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("name", &self.name)
+            .field("value", &REDACTED)
+            .finish()
+    }
+}
+
+impl Secret {
+    /// Creates a secret with the value, registering it for redaction.
+    pub fn new(name: &str, value: String) -> Self {
+        register_redaction(&value);
+        Self {
+            name: name.to_string(),
+            value: Arc::new(RwLock::new((0, value))),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the secret value, it should only be passed to the client using it.
+    pub fn expose(&self) -> String {
+        self.value.read().expect("secret lock poisoned").1.clone()
+    }
+
+    /// Returns the version of the value, increased on each rotation.
+    pub fn version(&self) -> u64 {
+        self.value.read().expect("secret lock poisoned").0
+    }
+
+    /// Sets a new value, returns true if it changed.
+    /// The previous value stays registered for redaction.
+    pub fn set(&self, value: String) -> bool {
+        let mut current = self.value.write().expect("secret lock poisoned");
+        if current.1 == value {
+            return false;
+        }
+        register_redaction(&value);
+        current.0 += 1;
+        current.1 = value;
+        true
+    }
+}
+
+/// An external secrets manager, such as HashiCorp Vault or a cloud secrets manager.
+pub trait SecretsProvider: Send + Sync + 'static {
+    /// Fetches the current value of the secret.
+    fn get_secret(&self, key: String) -> BoxPinFut<Result<String, BoxError>>;
+}
+
+/// Where a secret is loaded from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretSource {
+    /// An environment variable.
+    Env(String),
+    /// A file, trailing whitespace is trimmed.
+    File(String),
+    /// A key of the [`SecretsProvider`] of [`Secrets`].
+    Provider(String),
+}
+
+impl SecretSource {
+    /// Parses a reference such as `env:OPENAI_API_KEY`, `file:/run/secrets/openai`
+    /// or `provider:openai/api_key`.
+    pub fn parse(reference: &str) -> Result<Self, BoxError> {
+        let (kind, value) = reference
+            .split_once(':')
+            .ok_or_else(|| format!("invalid secret reference {:?}", reference))?;
+        if value.is_empty() {
+            return Err(format!("invalid secret reference {:?}", reference).into());
+        }
+        match kind {
+            "env" => Ok(Self::Env(value.to_string())),
+            "file" => Ok(Self::File(value.to_string())),
+            "provider" => Ok(Self::Provider(value.to_string())),
+            _ => Err(format!(
+                "invalid secret reference {:?}, expected env:, file: or provider:",
+                reference
+            )
+            .into()),
+        }
+    }
+
+    /// Reads an environment variable or a file secret.
+    pub fn read_local(&self) -> Result<String, BoxError> {
+        match self {
+            Self::Env(name) => std::env::var(name)
+                .map_err(|_| format!("environment variable {} is not set", name).into()),
+            Self::File(path) => std::fs::read_to_string(path)
+                .map(|s| s.trim_end().to_string())
+                .map_err(|err| format!("failed to read secret file {}: {}", path, err).into()),
+            Self::Provider(key) => {
+                Err(format!("secret {} should be fetched from the provider", key).into())
+            }
+        }
+    }
+}
+
+/// Loaded secrets with their sources, refreshed by [`Secrets::refresh`].
+#[derive(Default)]
+pub struct Secrets {
+    provider: Option<Arc<dyn SecretsProvider>>,
+    entries: RwLock<BTreeMap<String, (SecretSource, Secret)>>,
+}
+
+impl Secrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the external secrets manager for [`SecretSource::Provider`] sources.
+    pub fn with_provider(mut self, provider: Arc<dyn SecretsProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    async fn fetch(&self, source: &SecretSource) -> Result<String, BoxError> {
+        match source {
+            SecretSource::Provider(key) => match &self.provider {
+                Some(provider) => provider.get_secret(key.clone()).await,
+                None => Err(format!("no secrets provider for secret {}", key).into()),
+            },
+            source => source.read_local(),
+        }
+    }
+
+    /// Loads a secret by name, or returns it if already loaded.
+    pub async fn load(&self, name: &str, source: SecretSource) -> Result<Secret, BoxError> {
+        if let Some(secret) = self.get(name) {
+            return Ok(secret);
+        }
+        let value = self
+            .fetch(&source)
+            .await
+            .map_err(|err| format!("failed to load secret {}: {}", name, err))?;
+        let secret = Secret::new(name, value);
+        self.entries
+            .write()
+            .expect("secrets lock poisoned")
+            .entry(name.to_string())
+            .or_insert((source, secret.clone()));
+        Ok(secret)
+    }
+
+    /// Gets a loaded secret by name.
+    pub fn get(&self, name: &str) -> Option<Secret> {
+        self.entries
+            .read()
+            .expect("secrets lock poisoned")
+            .get(name)
+            .map(|(_, secret)| secret.clone())
+    }
+
+    /// Reloads all secrets from their sources, returns the names of rotated secrets.
+    /// A secret failing to reload keeps its current value.
+    pub async fn refresh(&self) -> Vec<String> {
+        let entries: Vec<(SecretSource, Secret)> = self
+            .entries
+            .read()
+            .expect("secrets lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        let mut rotated = Vec::new();
+        for (source, secret) in entries {
+            match self.fetch(&source).await {
+                Ok(value) => {
+                    if secret.set(value) {
+                        rotated.push(secret.name().to_string());
+                    }
+                }
+                Err(err) => log::warn!(
+                    "failed to refresh secret {}: {}",
+                    secret.name(),
+                    redact(&err.to_string())
+                ),
+            }
+        }
+        rotated
+    }
+
+    /// Spawns a task refreshing the secrets at the interval until cancelled.
+    pub fn spawn_refresh(
+        self: Arc<Self>,
+        interval: Duration,
+        cancellation_token: CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => return,
+                    _ = tokio::time::sleep(interval) => {
+                        let rotated = self.refresh().await;
+                        if !rotated.is_empty() {
+                            log::info!("secrets rotated: {:?}", rotated);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+type CompleterBuilder = dyn Fn(&str) -> Arc<dyn CompletionFeaturesDyn> + Send + Sync;
+type EmbedderBuilder = dyn Fn(&str) -> Arc<dyn EmbeddingFeaturesDyn> + Send + Sync;
+
+/// A completion model client rebuilt when its API key rotates.
+pub struct SecretCompleter {
+    secret: Secret,
+    build: Box<CompleterBuilder>,
+    current: RwLock<(u64, Arc<dyn CompletionFeaturesDyn>)>,
+}
+
+impl SecretCompleter {
+    pub fn new<F>(secret: Secret, build: F) -> Self
+    where
+        F: Fn(&str) -> Arc<dyn CompletionFeaturesDyn> + Send + Sync + 'static,
+    {
+        let current = (secret.version(), build(&secret.expose()));
+        Self {
+            secret,
+            build: Box::new(build),
+            current: RwLock::new(current),
+        }
+    }
+
+    fn inner(&self) -> Arc<dyn CompletionFeaturesDyn> {
+        let version = self.secret.version();
+        {
+            let current = self.current.read().expect("model lock poisoned");
+            if current.0 == version {
+                return current.1.clone();
+            }
+        }
+        let model = (self.build)(&self.secret.expose());
+        *self.current.write().expect("model lock poisoned") = (version, model.clone());
+        model
+    }
+}
+
+impl CompletionFeaturesDyn for SecretCompleter {
+    fn completion(&self, req: CompletionRequest) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        self.inner().completion(req)
+    }
+
+    fn stream_completion(
+        &self,
+        req: CompletionRequest,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> BoxPinFut<Result<AgentOutput, BoxError>> {
+        self.inner().stream_completion(req, deltas)
+    }
+
+    fn health(&self) -> BoxPinFut<Result<(), BoxError>> {
+        self.inner().health()
+    }
+}
+
+/// An embedding model client rebuilt when its API key rotates.
+pub struct SecretEmbedder {
+    secret: Secret,
+    build: Box<EmbedderBuilder>,
+    current: RwLock<(u64, Arc<dyn EmbeddingFeaturesDyn>)>,
+}
+
+impl SecretEmbedder {
+    pub fn new<F>(secret: Secret, build: F) -> Self
+    where
+        F: Fn(&str) -> Arc<dyn EmbeddingFeaturesDyn> + Send + Sync + 'static,
+    {
+        let current = (secret.version(), build(&secret.expose()));
+        Self {
+            secret,
+            build: Box::new(build),
+            current: RwLock::new(current),
+        }
+    }
+
+    fn inner(&self) -> Arc<dyn EmbeddingFeaturesDyn> {
+        let version = self.secret.version();
+        {
+            let current = self.current.read().expect("model lock poisoned");
+            if current.0 == version {
+                return current.1.clone();
+            }
+        }
+        let model = (self.build)(&self.secret.expose());
+        *self.current.write().expect("model lock poisoned") = (version, model.clone());
+        model
+    }
+}
+
+impl EmbeddingFeaturesDyn for SecretEmbedder {
+    fn ndims(&self) -> usize {
+        self.inner().ndims()
+    }
+
+    fn embed(&self, texts: Vec<String>) -> BoxPinFut<Result<(Vec<Embedding>, Usage), BoxError>> {
+        self.inner().embed(texts)
+    }
+
+    fn embed_query(&self, text: String) -> BoxPinFut<Result<(Embedding, Usage), BoxError>> {
+        self.inner().embed_query(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MapProvider(RwLock<BTreeMap<String, String>>);
+
+    impl SecretsProvider for MapProvider {
+        fn get_secret(&self, key: String) -> BoxPinFut<Result<String, BoxError>> {
+            let res = self
+                .0
+                .read()
+                .unwrap()
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| format!("secret {} not found", key).into());
+            Box::pin(futures::future::ready(res))
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_secrets() {
+        assert_eq!(
+            SecretSource::parse("env:OPENAI_API_KEY").unwrap(),
+            SecretSource::Env("OPENAI_API_KEY".to_string())
+        );
+        assert!(SecretSource::parse("vault:key").is_err());
+        assert!(SecretSource::parse("file:").is_err());
+
+        let provider = Arc::new(MapProvider(RwLock::new(BTreeMap::from([(
+            "openai".to_string(),
+            "sk-test-secret-1".to_string(),
+        )]))));
+        let secrets = Secrets::new().with_provider(provider.clone());
+        let secret = secrets
+            .load("openai", SecretSource::Provider("openai".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(secret.expose(), "sk-test-secret-1");
+        assert!(!format!("{:?}", secret).contains("sk-test"));
+        assert!(
+            secrets
+                .load("other", SecretSource::Provider("other".to_string()))
+                .await
+                .is_err()
+        );
+
+        let completer = SecretCompleter::new(secret.clone(), |key| {
+            assert!(key.starts_with("sk-test-secret-"));
+            Arc::new(crate::model::MockImplemented)
+        });
+        let model = completer.inner();
+        assert!(Arc::ptr_eq(&model, &completer.inner()));
+
+        provider
+            .0
+            .write()
+            .unwrap()
+            .insert("openai".to_string(), "sk-test-secret-2".to_string());
+        assert_eq!(secrets.refresh().await, vec!["openai".to_string()]);
+        assert_eq!(secret.expose(), "sk-test-secret-2");
+        assert_eq!(secret.version(), 1);
+        assert!(!Arc::ptr_eq(&model, &completer.inner()));
+        assert!(secrets.refresh().await.is_empty());
+
+        assert_eq!(
+            redact("error: bad key sk-test-secret-1, sk-test-secret-2"),
+            "error: bad key [REDACTED], [REDACTED]"
+        );
+        assert_eq!(redact("short"), "short");
+    }
+}
@@ -0,0 +1,247 @@
+//! Snapshots of the engine's runtime state.
+//!
+//! Threads and user states are persisted to the store as they change, but the rest of
+//! the runtime state only lives in memory: the caches of agents and tools, the daily usage
+//! of the tenants and the engine statistics. [`Engine::snapshot`] saves it to the store,
+//! and [`Engine::restore`] loads it back, after a crash or in the new instance of a
+//! blue/green deploy sharing the store. Engines built with
+//! [`EngineBuilder::with_snapshots`] restore the snapshot when built and take one on shutdown.
+//!
+//! Runs in flight when the snapshot is taken can't be resumed, they are reported as
+//! interrupted on restore, so that they can be retried on their threads.
+//!
+//! The caches alone can be persisted with [`CacheConfig::persist`]: a [`CacheSnapshot`] of
+//! the selected caches is saved on shutdown and reloaded in the background when the engine
+//! is built, so that the engine starts without waiting for it.
+//!
+//! [`Engine::snapshot`]: crate::engine::Engine::snapshot
+//! [`Engine::restore`]: crate::engine::Engine::restore
+//! [`EngineBuilder::with_snapshots`]: crate::engine::EngineBuilder::with_snapshots
+//! [`CacheConfig::persist`]: crate::context::CacheConfig::persist
+
+use anda_core::{BoxError, ByteBufB64, CacheExpiry, Path, PutMode};
+use candid::Principal;
+use ic_cose_types::to_cbor_bytes;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    context::{EngineStats, RunInfo, TenantUsage},
+    store::Store,
+};
+
+/// The store namespace of the snapshots.
+pub static SNAPSHOT_PATH: &str = "_snapshot";
+
+/// A cache entry of an agent or a tool.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CacheEntrySnapshot {
+    /// The cache path, "A:{agent}" or "T:{tool}".
+    pub path: String,
+    pub key: String,
+    /// The CBOR-encoded value.
+    pub value: ByteBufB64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tti_ms: Option<u64>,
+}
+
+impl CacheEntrySnapshot {
+    pub(crate) fn new(path: &Path, key: &str, value: &[u8], expiry: &Option<CacheExpiry>) -> Self {
+        let (ttl_ms, tti_ms) = match expiry {
+            Some(CacheExpiry::TTL(du)) => (Some(du.as_millis() as u64), None),
+            Some(CacheExpiry::TTI(du)) => (None, Some(du.as_millis() as u64)),
+            None => (None, None),
+        };
+        Self {
+            path: path.to_string(),
+            key: key.to_string(),
+            value: ByteBufB64(value.to_vec()),
+            ttl_ms,
+            tti_ms,
+        }
+    }
+
+    /// Returns the expiry of the entry, a TTL restarts from the restore.
+    pub(crate) fn expiry(&self) -> Option<CacheExpiry> {
+        match (self.ttl_ms, self.tti_ms) {
+            (Some(ms), _) => Some(CacheExpiry::TTL(Duration::from_millis(ms))),
+            (None, Some(ms)) => Some(CacheExpiry::TTI(Duration::from_millis(ms))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The runtime state of an engine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EngineSnapshot {
+    pub engine: Principal,
+    pub created_at_ms: u64,
+    pub stats: EngineStats,
+    /// Usage of the day by tenant ID.
+    pub tenants: BTreeMap<String, TenantUsage>,
+    /// Runs in flight when the snapshot was taken.
+    pub runs: Vec<RunInfo>,
+    pub cache: Vec<CacheEntrySnapshot>,
+}
+
+/// Summary of a snapshot taken or restored.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotReport {
+    pub created_at_ms: u64,
+    pub cache_entries: usize,
+    pub tenants: usize,
+    /// Runs in flight when the snapshot was taken, interrupted if restored.
+    pub runs: Vec<RunInfo>,
+}
+
+/// The persisted caches of an engine, see [`crate::context::CacheConfig::persist`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CacheSnapshot {
+    pub engine: Principal,
+    pub created_at_ms: u64,
+    pub entries: Vec<CacheEntrySnapshot>,
+}
+
+fn latest_path() -> Path {
+    Path::from("latest")
+}
+
+fn cache_path() -> Path {
+    Path::from("cache")
+}
+
+impl EngineSnapshot {
+    pub fn report(&self) -> SnapshotReport {
+        SnapshotReport {
+            created_at_ms: self.created_at_ms,
+            cache_entries: self.cache.len(),
+            tenants: self.tenants.len(),
+            runs: self.runs.clone(),
+        }
+    }
+
+    /// Saves the snapshot to the store as the latest one.
+    pub async fn save(&self, store: &Store) -> Result<(), BoxError> {
+        store
+            .store_put(
+                &Path::from(SNAPSHOT_PATH),
+                &latest_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(self).into(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Loads the latest snapshot from the store, None if there is none.
+    pub async fn load(store: &Store) -> Result<Option<Self>, BoxError> {
+        match store
+            .store_get(&Path::from(SNAPSHOT_PATH), &latest_path())
+            .await
+        {
+            Ok((data, _)) => Ok(Some(ciborium::from_reader(&data[..])?)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl CacheSnapshot {
+    /// Saves the persisted caches to the store, replacing the previous ones.
+    pub async fn save(&self, store: &Store) -> Result<(), BoxError> {
+        store
+            .store_put(
+                &Path::from(SNAPSHOT_PATH),
+                &cache_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(self).into(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Loads the persisted caches from the store, None if there are none.
+    pub async fn load(store: &Store) -> Result<Option<Self>, BoxError> {
+        match store
+            .store_get(&Path::from(SNAPSHOT_PATH), &cache_path())
+            .await
+        {
+            Ok((data, _)) => Ok(Some(ciborium::from_reader(&data[..])?)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_engine_snapshot() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        assert!(EngineSnapshot::load(&store).await.unwrap().is_none());
+
+        let entry = CacheEntrySnapshot::new(
+            &Path::from("T:memory"),
+            "key",
+            &[1, 2, 3],
+            &Some(CacheExpiry::TTI(Duration::from_secs(60))),
+        );
+        assert_eq!(entry.tti_ms, Some(60_000));
+        assert!(matches!(entry.expiry(), Some(CacheExpiry::TTI(du)) if du.as_secs() == 60));
+
+        let snapshot = EngineSnapshot {
+            engine: Principal::anonymous(),
+            created_at_ms: 42,
+            stats: EngineStats {
+                agent_runs: 3,
+                ..Default::default()
+            },
+            tenants: BTreeMap::from([("acme".to_string(), TenantUsage::default())]),
+            runs: vec![RunInfo {
+                id: 1,
+                kind: "agent".to_string(),
+                name: "a".to_string(),
+                caller: Principal::anonymous(),
+                started_at_ms: 40,
+            }],
+            cache: vec![entry],
+        };
+        snapshot.save(&store).await.unwrap();
+        let loaded = EngineSnapshot::load(&store).await.unwrap().unwrap();
+        assert_eq!(loaded.stats.agent_runs, 3);
+        assert_eq!(loaded.cache[0].value.0, vec![1, 2, 3]);
+        let report = loaded.report();
+        assert_eq!(report.created_at_ms, 42);
+        assert_eq!(report.cache_entries, 1);
+        assert_eq!(report.tenants, 1);
+        assert_eq!(report.runs.len(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cache_snapshot() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        assert!(CacheSnapshot::load(&store).await.unwrap().is_none());
+
+        let snapshot = CacheSnapshot {
+            engine: Principal::anonymous(),
+            created_at_ms: 42,
+            entries: vec![CacheEntrySnapshot::new(
+                &Path::from("T:search"),
+                "query",
+                &[1, 2, 3],
+                &Some(CacheExpiry::TTL(Duration::from_secs(3600))),
+            )],
+        };
+        snapshot.save(&store).await.unwrap();
+        // kept apart from the engine snapshot
+        assert!(EngineSnapshot::load(&store).await.unwrap().is_none());
+        let loaded = CacheSnapshot::load(&store).await.unwrap().unwrap();
+        assert_eq!(loaded.created_at_ms, 42);
+        assert_eq!(loaded.entries[0].path, "T:search");
+        assert_eq!(loaded.entries[0].ttl_ms, Some(3_600_000));
+    }
+}
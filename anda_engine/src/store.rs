@@ -20,6 +20,10 @@
 //!
 //! The module uses the [`ObjectStore`] trait from the `object_store` crate as its backend storage,
 //! allowing for various storage implementations to be used interchangeably.
+//! [`StoreConfig`] builds the backend from the engine config: in memory, the local file system,
+//! S3 (and compatible services), Google Cloud Storage or Azure Blob Storage. The cloud backends
+//! require the `aws`, `gcp` and `azure` features. A [`StoreCipher`] encrypts the objects at
+//! rest, see [`crate::encryption`].
 //!
 //! ## Examples
 //!
@@ -30,15 +34,34 @@
 //! let (content, meta) = store.store_get(&namespace, &path).await?;
 //! ```
 
-use anda_core::{BoxError, BoxPinFut, ObjectMeta, Path, PutMode, PutResult, path_lowercase};
-use futures::TryStreamExt;
-use object_store::PutOptions;
-use std::sync::Arc;
+use anda_core::{
+    BoxError, BoxPinFut, ObjectMeta, Path, PutMode, PutResult, StoreListOptions, StoreListPage,
+    path_lowercase,
+};
+use futures::{TryStreamExt, stream::BoxStream};
+use object_store::{Attribute, AttributeValue, GetOptions, GetRange, PutOptions, WriteMultipart};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, ops::Range, sync::Arc};
+
+use crate::{
+    encryption::{SegmentEncryptor, StoreCipher},
+    secrets::{REDACTED, SecretSource, register_redaction},
+};
 
 pub use object_store::{ObjectStore, local::LocalFileSystem, memory::InMemory};
 
 pub const MAX_STORE_OBJECT_SIZE: usize = 1024 * 1024 * 2; // 2 MB
 
+/// The size of the parts of the multipart uploads, the minimum of S3 except for the last part.
+pub const STORE_PART_SIZE: usize = 1024 * 1024 * 5; // 5 MB
+
+/// The maximum number of parts uploaded concurrently by a multipart upload.
+const MAX_CONCURRENT_PARTS: usize = 8;
+
+/// The default and maximum number of objects of a page listed by [`Store::store_list_page`].
+pub const DEFAULT_LIST_PAGE_SIZE: usize = 100;
+pub const MAX_LIST_PAGE_SIZE: usize = 1000;
+
 /// Trait defining vector search capabilities
 pub trait VectorSearchFeaturesDyn: Send + Sync + 'static {
     /// Find top N similar items based on query string
@@ -162,11 +185,26 @@ impl VectorSearchFeaturesDyn for MockImplemented {
 #[derive(Clone)]
 pub struct Store {
     store: Arc<dyn ObjectStore>,
+    cipher: Option<Arc<StoreCipher>>,
 }
 
 impl Store {
     pub fn new(store: Arc<dyn ObjectStore>) -> Self {
-        Self { store }
+        Self {
+            store,
+            cipher: None,
+        }
+    }
+
+    /// Encrypts the object bodies at rest with the cipher, and decrypts them when read.
+    pub fn with_cipher(mut self, cipher: StoreCipher) -> Self {
+        self.cipher = Some(Arc::new(cipher));
+        self
+    }
+
+    /// Returns true if the objects are encrypted at rest.
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
     }
 
     /// Retrieves data from storage at the specified path
@@ -177,17 +215,44 @@ impl Store {
     ) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
         let path = path_lowercase(&namespace.child(path.as_ref()));
         let res = self.store.get_opts(&path, Default::default()).await?;
-        let data = match res.payload {
-            object_store::GetResultPayload::Stream(mut stream) => {
-                let mut buf = bytes::BytesMut::new();
-                while let Some(data) = stream.try_next().await? {
-                    buf.extend_from_slice(&data);
-                }
-                buf.freeze() // Convert to immutable Bytes
+        let mut meta = res.meta.clone();
+        // a stream from remote backends, a file from the local file system
+        let mut data = res.bytes().await?;
+        if let Some(cipher) = &self.cipher {
+            data = cipher
+                .decrypt(&path_lowercase(namespace), &path, data)
+                .await?;
+            meta.size = data.len() as _;
+        }
+        Ok((data, meta))
+    }
+
+    /// Retrieves a byte range of the object at the specified path, without reading the
+    /// whole object from remote backends. The end of the range is clamped to the size of
+    /// the object.
+    pub async fn store_get_range(
+        &self,
+        namespace: &Path,
+        path: &Path,
+        range: Range<u64>,
+    ) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
+        if range.start >= range.end {
+            return Err(format!("invalid range {:?}", range).into());
+        }
+        let path = path_lowercase(&namespace.child(path.as_ref()));
+        match &self.cipher {
+            Some(cipher) => {
+                cipher
+                    .get_range(
+                        self.store.as_ref(),
+                        &path_lowercase(namespace),
+                        &path,
+                        range,
+                    )
+                    .await
             }
-            _ => return Err("StoreFeatures: unexpected payload from get_opts".into()),
-        };
-        Ok((data, res.meta))
+            None => get_range_raw(self.store.as_ref(), &path, range).await,
+        }
     }
 
     /// Lists objects in storage with optional prefix and offset filters
@@ -212,6 +277,88 @@ impl Store {
         Ok(metas)
     }
 
+    /// Lists a page of objects in storage, ordered by path and filtered by the options.
+    ///
+    /// The continuation token is the location of the last object of the page. Backends don't
+    /// guarantee the order of their listings, so the objects after the token are listed and
+    /// sorted for each page. The metadata filters read the attributes of the candidates, from
+    /// the first one until the page is full. The sizes are those of the stored objects, that
+    /// include the overhead of the encryption.
+    pub async fn store_list_page(
+        &self,
+        namespace: &Path,
+        options: &StoreListOptions,
+    ) -> Result<StoreListPage, BoxError> {
+        let prefix = match &options.prefix {
+            Some(prefix) => path_lowercase(&namespace.child(prefix.as_ref())),
+            None => path_lowercase(namespace),
+        };
+        let page_size = options
+            .page_size
+            .unwrap_or(DEFAULT_LIST_PAGE_SIZE)
+            .clamp(1, MAX_LIST_PAGE_SIZE);
+        let mut res = match &options.page_token {
+            Some(token) => {
+                let offset = Path::parse(token)
+                    .ok()
+                    .filter(|offset| offset.prefix_matches(&prefix))
+                    .ok_or_else(|| format!("invalid page token {:?}", token))?;
+                self.store.list_with_offset(Some(&prefix), &offset)
+            }
+            None => self.store.list(Some(&prefix)),
+        };
+        let mut candidates = Vec::new();
+        while let Some(meta) = res.try_next().await? {
+            if options.matches(&meta) {
+                candidates.push(meta);
+            }
+        }
+        candidates.sort_by(|a, b| a.location.cmp(&b.location));
+
+        let mut page = StoreListPage::default();
+        for meta in candidates {
+            if !options.metadata.is_empty()
+                && !self.has_metadata(&meta.location, &options.metadata).await?
+            {
+                continue;
+            }
+            if page.objects.len() == page_size {
+                page.next_page_token = page.objects.last().map(|m| m.location.to_string());
+                break;
+            }
+            page.objects.push(meta);
+        }
+        Ok(page)
+    }
+
+    async fn has_metadata(
+        &self,
+        path: &Path,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<bool, BoxError> {
+        let res = match self
+            .store
+            .get_opts(
+                path,
+                GetOptions {
+                    head: true,
+                    ..Default::default()
+                },
+            )
+            .await
+        {
+            Ok(res) => res,
+            // deleted since it was listed
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(metadata.iter().all(|(key, value)| {
+            res.attributes
+                .get(&Attribute::Metadata(key.clone().into()))
+                .is_some_and(|v| v.as_ref() == value.as_str())
+        }))
+    }
+
     /// Stores data at the specified path with a given write mode
     ///
     /// # Arguments
@@ -224,8 +371,30 @@ impl Store {
         path: &Path,
         mode: PutMode,
         val: bytes::Bytes,
+    ) -> Result<PutResult, BoxError> {
+        self.store_put_with_metadata(namespace, path, mode, BTreeMap::new(), val)
+            .await
+    }
+
+    /// Stores data at the specified path with user-defined metadata, stored as the
+    /// attributes of the object. The local file system doesn't support them.
+    pub async fn store_put_with_metadata(
+        &self,
+        namespace: &Path,
+        path: &Path,
+        mode: PutMode,
+        metadata: BTreeMap<String, String>,
+        val: bytes::Bytes,
     ) -> Result<PutResult, BoxError> {
         let path = path_lowercase(&namespace.child(path.as_ref()));
+        let val = match &self.cipher {
+            Some(cipher) => {
+                cipher
+                    .encrypt(&path_lowercase(namespace), &path, val)
+                    .await?
+            }
+            None => val,
+        };
         let res = self
             .store
             .put_opts(
@@ -233,6 +402,10 @@ impl Store {
                 val.into(),
                 PutOptions {
                     mode,
+                    attributes: metadata
+                        .into_iter()
+                        .map(|(k, v)| (Attribute::Metadata(k.into()), AttributeValue::from(v)))
+                        .collect(),
                     ..Default::default()
                 },
             )
@@ -240,6 +413,31 @@ impl Store {
         Ok(res)
     }
 
+    /// Stores the chunks of a stream at the specified path with a multipart upload,
+    /// overwriting the object. The object is not bounded by [`MAX_STORE_OBJECT_SIZE`],
+    /// and the upload is aborted if the stream fails.
+    pub async fn store_put_stream(
+        &self,
+        namespace: &Path,
+        path: &Path,
+        chunks: BoxStream<'static, Result<bytes::Bytes, BoxError>>,
+    ) -> Result<PutResult, BoxError> {
+        let path = path_lowercase(&namespace.child(path.as_ref()));
+        let encryptor = match &self.cipher {
+            Some(cipher) => Some(cipher.encryptor(&path_lowercase(namespace), &path).await?),
+            None => None,
+        };
+        let upload = self.store.put_multipart(&path).await?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, STORE_PART_SIZE);
+        match write_chunks(&mut writer, chunks, encryptor).await {
+            Ok(()) => Ok(writer.finish().await?),
+            Err(err) => {
+                let _ = writer.abort().await;
+                Err(err)
+            }
+        }
+    }
+
     /// Renames a storage object if the target path doesn't exist
     ///
     /// # Arguments
@@ -253,6 +451,29 @@ impl Store {
     ) -> Result<(), BoxError> {
         let from = path_lowercase(&namespace.child(from.as_ref()));
         let to = path_lowercase(&namespace.child(to.as_ref()));
+        if let Some(cipher) = &self.cipher {
+            // the path is authenticated, so the object is encrypted again for the target
+            let namespace = path_lowercase(namespace);
+            let res = self.store.get(&from).await?;
+            let attributes = res.attributes.clone();
+            let data = cipher
+                .decrypt(&namespace, &from, res.bytes().await?)
+                .await?;
+            let data = cipher.encrypt(&namespace, &to, data).await?;
+            self.store
+                .put_opts(
+                    &to,
+                    data.into(),
+                    PutOptions {
+                        mode: PutMode::Create,
+                        attributes,
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            self.store.delete(&from).await?;
+            return Ok(());
+        }
         self.store.rename_if_not_exists(&from, &to).await?;
         Ok(())
     }
@@ -266,4 +487,457 @@ impl Store {
         self.store.delete(&path).await?;
         Ok(())
     }
+
+    /// Checks that the backend storage is reachable, used by readiness probes.
+    /// A missing object is a valid answer from a healthy storage.
+    pub async fn health(&self) -> Result<(), BoxError> {
+        match self.store.head(&Path::from("_health")).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Retrieves a byte range of the object from the backend as it is stored.
+pub(crate) async fn get_range_raw(
+    store: &dyn ObjectStore,
+    path: &Path,
+    range: Range<u64>,
+) -> Result<(bytes::Bytes, ObjectMeta), BoxError> {
+    let res = store
+        .get_opts(
+            path,
+            GetOptions {
+                range: Some(GetRange::Bounded(range)),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let meta = res.meta.clone();
+    Ok((res.bytes().await?, meta))
+}
+
+async fn write_chunks(
+    writer: &mut WriteMultipart,
+    mut chunks: BoxStream<'static, Result<bytes::Bytes, BoxError>>,
+    mut encryptor: Option<SegmentEncryptor>,
+) -> Result<(), BoxError> {
+    if let Some(encryptor) = &encryptor {
+        writer.put(encryptor.header());
+    }
+    while let Some(chunk) = chunks.try_next().await? {
+        writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+        match &mut encryptor {
+            Some(encryptor) => {
+                for segment in encryptor.update(&chunk)? {
+                    writer.put(segment);
+                }
+            }
+            None => writer.put(chunk),
+        }
+    }
+    if let Some(encryptor) = encryptor {
+        writer.put(encryptor.finish()?);
+    }
+    Ok(())
+}
+
+/// Returns true if the error is a not found error of the store.
+pub(crate) fn is_not_found(err: &BoxError) -> bool {
+    matches!(
+        err.downcast_ref::<object_store::Error>(),
+        Some(object_store::Error::NotFound { .. })
+    )
+}
+
+/// Object store backend of the engine, selected by `backend`: "memory", "local", "s3", "gcs"
+/// or "azure".
+///
+/// Credentials that are not set are read from the environment by the backends' own conventions,
+/// e.g. `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, or an instance role for S3.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StoreConfig {
+    Memory,
+    Local(LocalStoreConfig),
+    S3(S3StoreConfig),
+    Gcs(GcsStoreConfig),
+    Azure(AzureStoreConfig),
+}
+
+/// Configuration of a store in a directory of the local file system.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalStoreConfig {
+    /// The directory, created if it doesn't exist.
+    pub path: String,
+}
+
+/// Configuration of a store in an S3 bucket, or of an S3-compatible service such as MinIO
+/// or Cloudflare R2 with `endpoint`. Requires the `aws` feature.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3StoreConfig {
+    pub bucket: String,
+    pub region: Option<String>,
+    /// The endpoint of an S3-compatible service, e.g. "http://localhost:9000".
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// A file containing the secret access key, e.g. a mounted secret, instead of
+    /// `secret_access_key`.
+    pub secret_access_key_file: Option<String>,
+    /// Allows plain HTTP endpoints, for local services.
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+/// Configuration of a store in a Google Cloud Storage bucket. Requires the `gcp` feature.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcsStoreConfig {
+    pub bucket: String,
+    /// The path of the service account file.
+    pub service_account_path: Option<String>,
+    /// The service account key in JSON, instead of `service_account_path`.
+    pub service_account_key: Option<String>,
+}
+
+/// Configuration of a store in an Azure Blob Storage container. Requires the `azure` feature.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AzureStoreConfig {
+    pub account: String,
+    pub container: String,
+    pub access_key: Option<String>,
+    /// A file containing the access key, e.g. a mounted secret, instead of `access_key`.
+    pub access_key_file: Option<String>,
+}
+
+impl fmt::Debug for S3StoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3StoreConfig")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field("access_key_id", &self.access_key_id)
+            .field(
+                "secret_access_key",
+                &self.secret_access_key.as_ref().map(|_| REDACTED),
+            )
+            .field("secret_access_key_file", &self.secret_access_key_file)
+            .field("allow_http", &self.allow_http)
+            .finish()
+    }
+}
+
+impl fmt::Debug for GcsStoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcsStoreConfig")
+            .field("bucket", &self.bucket)
+            .field("service_account_path", &self.service_account_path)
+            .field(
+                "service_account_key",
+                &self.service_account_key.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+impl fmt::Debug for AzureStoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AzureStoreConfig")
+            .field("account", &self.account)
+            .field("container", &self.container)
+            .field("access_key", &self.access_key.as_ref().map(|_| REDACTED))
+            .field("access_key_file", &self.access_key_file)
+            .finish()
+    }
+}
+
+/// Resolves a secret from its value or its file, registering it for redaction.
+#[cfg_attr(
+    not(any(feature = "aws", feature = "gcp", feature = "azure")),
+    allow(dead_code)
+)]
+fn resolve_secret(
+    name: &str,
+    value: &Option<String>,
+    file: &Option<String>,
+) -> Result<Option<String>, BoxError> {
+    let secret = match (value, file) {
+        (Some(_), Some(_)) => {
+            return Err(format!("{} and {}_file are mutually exclusive", name, name).into());
+        }
+        (Some(value), None) => value.clone(),
+        (None, Some(path)) => SecretSource::File(path.clone()).read_local()?,
+        (None, None) => return Ok(None),
+    };
+    register_redaction(&secret);
+    Ok(Some(secret))
+}
+
+impl StoreConfig {
+    /// Builds the store.
+    pub fn build(self) -> Result<Store, BoxError> {
+        let store: Arc<dyn ObjectStore> = match self {
+            StoreConfig::Memory => Arc::new(InMemory::new()),
+            StoreConfig::Local(cfg) => {
+                std::fs::create_dir_all(&cfg.path)
+                    .map_err(|err| format!("failed to create {}: {}", cfg.path, err))?;
+                Arc::new(LocalFileSystem::new_with_prefix(&cfg.path)?)
+            }
+            StoreConfig::S3(cfg) => cfg.build()?,
+            StoreConfig::Gcs(cfg) => cfg.build()?,
+            StoreConfig::Azure(cfg) => cfg.build()?,
+        };
+        Ok(Store::new(store))
+    }
+}
+
+impl S3StoreConfig {
+    #[cfg(feature = "aws")]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        use object_store::aws::AmazonS3Builder;
+
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&self.bucket)
+            .with_allow_http(self.allow_http);
+        if let Some(region) = &self.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = &self.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+        if let Some(access_key_id) = &self.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret) = resolve_secret(
+            "secret_access_key",
+            &self.secret_access_key,
+            &self.secret_access_key_file,
+        )? {
+            builder = builder.with_secret_access_key(secret);
+        }
+        Ok(Arc::new(builder.build()?))
+    }
+
+    #[cfg(not(feature = "aws"))]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        Err("the s3 backend requires the `aws` feature of anda_engine".into())
+    }
+}
+
+impl GcsStoreConfig {
+    #[cfg(feature = "gcp")]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        use object_store::gcp::GoogleCloudStorageBuilder;
+
+        let mut builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(&self.bucket);
+        if let Some(path) = &self.service_account_path {
+            builder = builder.with_service_account_path(path);
+        }
+        if let Some(key) = resolve_secret("service_account_key", &self.service_account_key, &None)?
+        {
+            builder = builder.with_service_account_key(key);
+        }
+        Ok(Arc::new(builder.build()?))
+    }
+
+    #[cfg(not(feature = "gcp"))]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        Err("the gcs backend requires the `gcp` feature of anda_engine".into())
+    }
+}
+
+impl AzureStoreConfig {
+    #[cfg(feature = "azure")]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        use object_store::azure::MicrosoftAzureBuilder;
+
+        let mut builder = MicrosoftAzureBuilder::from_env()
+            .with_account(&self.account)
+            .with_container_name(&self.container);
+        if let Some(key) = resolve_secret("access_key", &self.access_key, &self.access_key_file)? {
+            builder = builder.with_access_key(key);
+        }
+        Ok(Arc::new(builder.build()?))
+    }
+
+    #[cfg(not(feature = "azure"))]
+    fn build(self) -> Result<Arc<dyn ObjectStore>, BoxError> {
+        Err("the azure backend requires the `azure` feature of anda_engine".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_store_config() {
+        let cfg: StoreConfig = serde_json::from_value(serde_json::json!({
+            "backend": "s3",
+            "bucket": "anda",
+            "endpoint": "http://localhost:9000",
+            "secret_access_key": "minio-secret-key",
+            "allow_http": true,
+        }))
+        .unwrap();
+        assert!(matches!(&cfg, StoreConfig::S3(s3) if s3.bucket == "anda" && s3.allow_http));
+        assert!(!format!("{:?}", cfg).contains("minio-secret-key"));
+        assert!(resolve_secret("key", &Some("a".to_string()), &Some("b".to_string())).is_err());
+
+        let dir = std::env::temp_dir().join(format!("anda-store-{}", rand::random::<u64>()));
+        let cfg = StoreConfig::Local(LocalStoreConfig {
+            path: dir.to_string_lossy().to_string(),
+        });
+        let store = cfg.build().unwrap();
+        let namespace = Path::from("T:memory");
+        let path = Path::from("notes/a.json");
+        store
+            .store_put(&namespace, &path, PutMode::Create, "hello".into())
+            .await
+            .unwrap();
+        let (data, meta) = store.store_get(&namespace, &path).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+        assert_eq!(meta.size, 5);
+        let metas = store
+            .store_list(&namespace, None, &Path::default())
+            .await
+            .unwrap();
+        assert_eq!(metas.len(), 1);
+        store.health().await.unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_store_stream_and_range() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let namespace = Path::from("T:memory");
+        let path = Path::from("large");
+        let data: Vec<u8> = (0..STORE_PART_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let chunks: Vec<Result<bytes::Bytes, BoxError>> = data
+            .chunks(1024 * 1024)
+            .map(|c| Ok(bytes::Bytes::copy_from_slice(c)))
+            .collect();
+        store
+            .store_put_stream(&namespace, &path, Box::pin(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+
+        let (all, meta) = store.store_get(&namespace, &path).await.unwrap();
+        assert_eq!(&all[..], &data[..]);
+        assert_eq!(meta.size as usize, data.len());
+        let (part, _) = store
+            .store_get_range(&namespace, &path, 100..200)
+            .await
+            .unwrap();
+        assert_eq!(&part[..], &data[100..200]);
+        let end = data.len() as u64;
+        let (tail, _) = store
+            .store_get_range(&namespace, &path, end - 10..end + 10)
+            .await
+            .unwrap();
+        assert_eq!(&tail[..], &data[data.len() - 10..]);
+        assert!(
+            store
+                .store_get_range(&namespace, &path, 10..10)
+                .await
+                .is_err()
+        );
+
+        // a failed stream aborts the upload
+        let chunks: Vec<Result<bytes::Bytes, BoxError>> =
+            vec![Ok("partial".into()), Err("disconnected".into())];
+        let failed = Path::from("failed");
+        assert!(
+            store
+                .store_put_stream(&namespace, &failed, Box::pin(futures::stream::iter(chunks)))
+                .await
+                .is_err()
+        );
+        assert!(store.store_get(&namespace, &failed).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_store_list_page() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let namespace = Path::from("T:memory");
+        for i in 0..5 {
+            let metadata = BTreeMap::from([("kind".to_string(), format!("k{}", i % 2))]);
+            store
+                .store_put_with_metadata(
+                    &namespace,
+                    &Path::from(format!("doc{}", i)),
+                    PutMode::Create,
+                    metadata,
+                    vec![0u8; i * 10].into(),
+                )
+                .await
+                .unwrap();
+        }
+        store
+            .store_put(
+                &Path::from("T:other"),
+                &Path::from("doc"),
+                PutMode::Create,
+                "x".into(),
+            )
+            .await
+            .unwrap();
+
+        let mut options = StoreListOptions::new().with_page_size(2);
+        let mut names = Vec::new();
+        loop {
+            let page = store.store_list_page(&namespace, &options).await.unwrap();
+            assert!(page.objects.len() <= 2);
+            names.extend(page.objects.into_iter().map(|m| m.location.to_string()));
+            match page.next_page_token {
+                Some(token) => options = options.with_page_token(token),
+                None => break,
+            }
+        }
+        assert_eq!(
+            names,
+            (0..5)
+                .map(|i| format!("t:memory/doc{}", i))
+                .collect::<Vec<_>>()
+        );
+
+        let page = store
+            .store_list_page(
+                &namespace,
+                &StoreListOptions::new()
+                    .with_size(Some(10), Some(30))
+                    .with_metadata("kind".to_string(), "k1".to_string()),
+            )
+            .await
+            .unwrap();
+        let names: Vec<_> = page.objects.iter().map(|m| m.location.as_ref()).collect();
+        assert_eq!(names, vec!["t:memory/doc1", "t:memory/doc3"]);
+        assert!(page.next_page_token.is_none());
+
+        let page = store
+            .store_list_page(
+                &namespace,
+                &StoreListOptions::new().with_modified(Some(u64::MAX), None),
+            )
+            .await
+            .unwrap();
+        assert!(page.objects.is_empty());
+
+        // a token can't list another namespace
+        assert!(
+            store
+                .store_list_page(
+                    &namespace,
+                    &StoreListOptions::new().with_page_token("t:other/a".to_string()),
+                )
+                .await
+                .is_err()
+        );
+    }
 }
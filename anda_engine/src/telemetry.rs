@@ -0,0 +1,224 @@
+//! OpenTelemetry tracing for the agent execution path.
+//!
+//! The engine instruments `agent_run`, the completion loop, `tool_call`, canister calls and
+//! outbound HTTP requests with [`tracing`] spans. They cost almost nothing until the application
+//! installs a subscriber. With the `otlp` feature, `init_otlp_tracing` installs one exporting
+//! the spans over OTLP/HTTP, so that a single run can be inspected end-to-end in Jaeger, Tempo, etc.
+//!
+//! The spans of agent runs, completions and tool calls carry the agent or tool name, the
+//! token usage and the redacted error, the spans of canister calls and signed RPCs the error.
+//! Failed spans have the `ERROR` status.
+//!
+//! # Example
+//! ```rust,ignore
+//! // in the application, before building the engines
+//! anda_engine::telemetry::init_otlp_tracing(
+//!     &OtlpConfig::new("my_agent").with_sample_ratio(0.1),
+//! )?;
+//! // or from the `otlp` section of the engine configuration
+//! if let Some(otlp) = cfg.otlp() {
+//!     anda_engine::telemetry::init_otlp_tracing(&otlp)?;
+//! }
+//! // ...
+//! anda_engine::telemetry::shutdown_tracing();
+//! ```
+
+use anda_core::{BoxError, Usage};
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otlp")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "otlp")]
+use opentelemetry_sdk::{
+    Resource,
+    trace::{Sampler, SdkTracerProvider},
+};
+#[cfg(feature = "otlp")]
+use std::sync::OnceLock;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::secrets::redact;
+
+#[cfg(feature = "otlp")]
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Configuration of the OTLP trace exporter.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP traces endpoint, defaults to `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`,
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` or `http://localhost:4318/v1/traces`.
+    pub endpoint: Option<String>,
+    /// The `service.name` resource attribute.
+    pub service_name: String,
+    /// Ratio of root spans to sample, from 0.0 to 1.0. Default is 1.0.
+    pub sample_ratio: f64,
+}
+
+impl OtlpConfig {
+    pub fn new(service_name: &str) -> Self {
+        Self {
+            endpoint: None,
+            service_name: service_name.to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn with_sample_ratio(mut self, sample_ratio: f64) -> Self {
+        self.sample_ratio = sample_ratio.clamp(0.0, 1.0);
+        self
+    }
+}
+
+/// Installs a global [`tracing`] subscriber exporting spans over OTLP, called by the
+/// application. It does nothing if tracing has already been initialized by this function.
+#[cfg(feature = "otlp")]
+pub fn init_otlp_tracing(cfg: &OtlpConfig) -> Result<(), BoxError> {
+    if TRACER_PROVIDER.get().is_some() {
+        return Ok(());
+    }
+
+    let mut exporter = SpanExporter::builder().with_http();
+    if let Some(endpoint) = &cfg.endpoint {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+    let exporter = exporter.build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            cfg.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(cfg.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|err| format!("failed to install tracing subscriber: {err}"))?;
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    let _ = TRACER_PROVIDER.set(provider);
+    Ok(())
+}
+
+/// Flushes the pending spans and shuts down the exporter, should be called before exiting.
+#[cfg(feature = "otlp")]
+pub fn shutdown_tracing() {
+    if let Some(Err(err)) = TRACER_PROVIDER.get().map(|provider| provider.shutdown()) {
+        log::error!("failed to shutdown tracer provider: {err}");
+    }
+}
+
+/// Records the token usage, or the redacted error, of the result on the current span, which
+/// declares the empty fields `input_tokens`, `output_tokens`, `error` and `otel.status_code`.
+pub(crate) fn record_usage<T>(
+    res: Result<T, BoxError>,
+    usage: impl FnOnce(&T) -> &Usage,
+) -> Result<T, BoxError> {
+    if let Ok(output) = &res {
+        let usage = usage(output);
+        let span = tracing::Span::current();
+        span.record("input_tokens", usage.input_tokens);
+        span.record("output_tokens", usage.output_tokens);
+    }
+    record_error(res)
+}
+
+/// Records the redacted error of the result on the current span, which declares the empty
+/// fields `error` and `otel.status_code`.
+pub(crate) fn record_error<T>(res: Result<T, BoxError>) -> Result<T, BoxError> {
+    if let Err(err) = &res {
+        let span = tracing::Span::current();
+        span.record("error", redact(&err.to_string()).as_str());
+        span.record("otel.status_code", "ERROR");
+    }
+    res
+}
+
+/// Returns the host of the URL, recorded in spans instead of the full URL which may carry secrets.
+pub(crate) fn url_host(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::{
+        Subscriber,
+        field::{Empty, Field},
+        span::{Id, Record},
+    };
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(
+            url_host("https://api.example.com/v1/items?token=secret"),
+            "api.example.com"
+        );
+        assert_eq!(url_host("not a url"), "");
+    }
+
+    /// Collects the values recorded on spans after their creation.
+    struct Recorded(Arc<Mutex<Vec<String>>>);
+
+    impl tracing::field::Visit for Recorded {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Recorded {
+        fn on_record(&self, _span: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut Recorded(self.0.clone()));
+        }
+    }
+
+    #[test]
+    fn test_record_usage() {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Recorded(recorded.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "tool_call",
+                input_tokens = Empty,
+                output_tokens = Empty,
+                error = Empty,
+                otel.status_code = Empty,
+            );
+            let _guard = span.enter();
+            let usage = Usage {
+                input_tokens: 3,
+                output_tokens: 5,
+                ..Default::default()
+            };
+            assert!(record_usage(Ok(usage), |u| u).is_ok());
+            assert!(record_error::<()>(Err("tool failed".into())).is_err());
+        });
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![
+                "input_tokens=3",
+                "output_tokens=5",
+                "error=\"tool failed\"",
+                "otel.status_code=\"ERROR\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use anda_core::{
+    BoxError, BoxPinFut, CanisterCaller, Value, VectorDocument, VectorFilter, VectorMatch,
+    VectorStoreFeatures,
+};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+
+use super::VectorStoreFeaturesDyn;
+
+/// The maximum encoded size of an upsert call, below the 2 MiB limit of ingress messages.
+pub const MAX_UPSERT_BYTES: usize = 1024 * 1024 * 3 / 2;
+
+/// The maximum number of documents per `vector_get` query, bounding the size of the reply.
+pub const MAX_GET_DOCUMENTS: usize = 100;
+
+/// A document stored in the vector canister, the metadata is encoded as JSON.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct CanisterVectorDoc {
+    pub id: String,
+    pub vec: Vec<f32>,
+    pub text: String,
+    pub meta: String,
+}
+
+/// A document found by the vector canister, the metadata is encoded as JSON.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct CanisterVectorMatch {
+    pub id: String,
+    pub score: f32,
+    pub text: String,
+    pub meta: String,
+}
+
+/// Arguments of the `vector_search` query.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct VectorSearchArgs {
+    pub query: Vec<f32>,
+    pub top_k: u32,
+    /// The [`VectorFilter`] encoded as JSON.
+    pub filter: Option<String>,
+    /// The cursor returned by the previous page, None for the first page.
+    pub cursor: Option<u64>,
+}
+
+/// A page of the `vector_search` query: the top k matches among the documents scanned
+/// by this call, and the cursor of the next page if the scan isn't complete.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct VectorSearchPage {
+    pub matches: Vec<CanisterVectorMatch>,
+    pub next_cursor: Option<u64>,
+}
+
+/// A vector store persisted in a canister on the Internet Computer, for agents whose
+/// knowledge must live fully on chain.
+///
+/// The canister implements the following interface:
+/// ```candid
+/// type CanisterVectorDoc = record { id : text; vec : vec float32; "text" : text; meta : text };
+/// type CanisterVectorMatch = record { id : text; score : float32; "text" : text; meta : text };
+/// type VectorSearchArgs = record {
+///   "query" : vec float32; top_k : nat32; filter : opt text; cursor : opt nat64
+/// };
+/// type VectorSearchPage = record { matches : vec CanisterVectorMatch; next_cursor : opt nat64 };
+/// service : {
+///   vector_upsert : (vec CanisterVectorDoc) -> (variant { Ok; Err : text });
+///   vector_get : (vec text) -> (variant { Ok : vec CanisterVectorDoc; Err : text }) query;
+///   vector_delete : (vec text) -> (variant { Ok : nat64; Err : text });
+///   vector_search : (VectorSearchArgs) -> (variant { Ok : VectorSearchPage; Err : text }) query;
+/// }
+/// ```
+///
+/// Upserts are split into calls of at most [`MAX_UPSERT_BYTES`], gets into queries of at most
+/// [`MAX_GET_DOCUMENTS`], and a search scans the
+/// documents page by page, as a query can't scan a large store within its instruction limit.
+/// The pages are merged into the top k matches.
+pub struct CanisterVectorStore<C: CanisterCaller + Send + Sync + 'static> {
+    caller: Arc<C>,
+    canister: Principal,
+    max_upsert_bytes: usize,
+}
+
+impl<C> Clone for CanisterVectorStore<C>
+where
+    C: CanisterCaller + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            caller: self.caller.clone(),
+            canister: self.canister,
+            max_upsert_bytes: self.max_upsert_bytes,
+        }
+    }
+}
+
+impl<C> CanisterVectorStore<C>
+where
+    C: CanisterCaller + Send + Sync + 'static,
+{
+    pub fn new(caller: Arc<C>, canister: Principal) -> Self {
+        Self {
+            caller,
+            canister,
+            max_upsert_bytes: MAX_UPSERT_BYTES,
+        }
+    }
+
+    /// Sets the maximum encoded size of an upsert call.
+    pub fn with_max_upsert_bytes(mut self, max_upsert_bytes: usize) -> Self {
+        self.max_upsert_bytes = max_upsert_bytes.max(1);
+        self
+    }
+
+    async fn upsert_docs(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let mut batch: Vec<CanisterVectorDoc> = Vec::new();
+        let mut size = 0;
+        for doc in docs {
+            if doc.id.is_empty() {
+                return Err("vector document id is empty".into());
+            }
+            let doc = CanisterVectorDoc {
+                meta: serde_json::to_string(&doc.meta)?,
+                id: doc.id,
+                vec: doc.vec,
+                text: doc.text,
+            };
+            let doc_size = encoded_size(&doc);
+            if !batch.is_empty() && size + doc_size > self.max_upsert_bytes {
+                self.upsert_batch(std::mem::take(&mut batch)).await?;
+                size = 0;
+            }
+            size += doc_size;
+            batch.push(doc);
+        }
+        if !batch.is_empty() {
+            self.upsert_batch(batch).await?;
+        }
+        Ok(())
+    }
+
+    async fn upsert_batch(&self, batch: Vec<CanisterVectorDoc>) -> Result<(), BoxError> {
+        let res: Result<(), String> = self
+            .caller
+            .canister_update(&self.canister, "vector_upsert", (batch,))
+            .await?;
+        res.map_err(|err| format!("vector canister upsert failed: {}", err).into())
+    }
+
+    async fn get_docs(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        let mut docs = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(MAX_GET_DOCUMENTS) {
+            let res: Result<Vec<CanisterVectorDoc>, String> = self
+                .caller
+                .canister_query(&self.canister, "vector_get", (batch.to_vec(),))
+                .await?;
+            let batch = res.map_err(|err| format!("vector canister get failed: {}", err))?;
+            for doc in batch {
+                docs.push(VectorDocument {
+                    meta: decode_meta(&doc.meta)?,
+                    id: doc.id,
+                    vec: doc.vec,
+                    text: doc.text,
+                });
+            }
+        }
+        Ok(docs)
+    }
+
+    async fn delete_docs(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let res: Result<u64, String> = self
+            .caller
+            .canister_update(&self.canister, "vector_delete", (ids,))
+            .await?;
+        let count = res.map_err(|err| format!("vector canister delete failed: {}", err))?;
+        Ok(count as usize)
+    }
+
+    async fn search_docs(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        if top_k == 0 {
+            return Ok(Vec::new());
+        }
+        let mut args = VectorSearchArgs {
+            query,
+            top_k: top_k.min(u32::MAX as usize) as u32,
+            filter: filter.as_ref().map(serde_json::to_string).transpose()?,
+            cursor: None,
+        };
+        let mut matches: Vec<CanisterVectorMatch> = Vec::new();
+        loop {
+            let res: Result<VectorSearchPage, String> = self
+                .caller
+                .canister_query(&self.canister, "vector_search", (args.clone(),))
+                .await?;
+            let page = res.map_err(|err| format!("vector canister search failed: {}", err))?;
+            matches.extend(page.matches);
+            matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+            matches.truncate(top_k);
+            match page.next_cursor {
+                Some(next) if args.cursor.is_none_or(|cursor| next > cursor) => {
+                    args.cursor = Some(next);
+                }
+                Some(next) => {
+                    return Err(format!("vector canister returned a stale cursor {}", next).into());
+                }
+                None => break,
+            }
+        }
+
+        matches
+            .into_iter()
+            .map(|m| {
+                Ok(VectorMatch {
+                    meta: decode_meta(&m.meta)?,
+                    id: m.id,
+                    score: m.score,
+                    text: m.text,
+                })
+            })
+            .collect()
+    }
+}
+
+impl<C> VectorStoreFeatures for CanisterVectorStore<C>
+where
+    C: CanisterCaller + Send + Sync + 'static,
+{
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.upsert_docs(docs).await
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        self.get_docs(ids).await
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.delete_docs(ids).await
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.search_docs(query, top_k, filter).await
+    }
+}
+
+impl<C> VectorStoreFeaturesDyn for CanisterVectorStore<C>
+where
+    C: CanisterCaller + Send + Sync + 'static,
+{
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.upsert_docs(docs).await })
+    }
+
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.get_docs(ids).await })
+    }
+
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.delete_docs(ids).await })
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.search_docs(query, top_k, filter).await })
+    }
+}
+
+/// Decodes the JSON metadata of a document, empty if it is empty.
+fn decode_meta(meta: &str) -> Result<BTreeMap<String, Value>, BoxError> {
+    if meta.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_json::from_str(meta)?)
+}
+
+/// Estimates the Candid encoded size of a document.
+fn encoded_size(doc: &CanisterVectorDoc) -> usize {
+    doc.id.len() + doc.vec.len() * 4 + doc.text.len() + doc.meta.len() + 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::mock::MockCanisterCaller;
+    use candid::{Decode, encode_args};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_canister_vector_store() {
+        let stored: Arc<Mutex<Vec<CanisterVectorDoc>>> = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let caller = {
+            let stored = stored.clone();
+            let calls = calls.clone();
+            MockCanisterCaller::new(move |_canister, method, args| {
+                calls.lock().unwrap().push(method.to_string());
+                match method {
+                    "vector_upsert" => {
+                        let docs = Decode!(args.as_slice(), Vec<CanisterVectorDoc>).unwrap();
+                        stored.lock().unwrap().extend(docs);
+                        encode_args((Ok::<(), String>(()),)).unwrap()
+                    }
+                    "vector_get" => {
+                        let ids = Decode!(args.as_slice(), Vec<String>).unwrap();
+                        let docs: Vec<CanisterVectorDoc> = stored
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|d| ids.contains(&d.id))
+                            .cloned()
+                            .collect();
+                        encode_args((Ok::<Vec<CanisterVectorDoc>, String>(docs),)).unwrap()
+                    }
+                    "vector_delete" => {
+                        let ids = Decode!(args.as_slice(), Vec<String>).unwrap();
+                        let mut stored = stored.lock().unwrap();
+                        let len = stored.len();
+                        stored.retain(|d| !ids.contains(&d.id));
+                        encode_args((Ok::<u64, String>((len - stored.len()) as u64),)).unwrap()
+                    }
+                    "vector_search" => {
+                        // scans 2 documents per page
+                        let args = Decode!(args.as_slice(), VectorSearchArgs).unwrap();
+                        let filter: Option<VectorFilter> = args
+                            .filter
+                            .as_deref()
+                            .map(|f| serde_json::from_str(f).unwrap());
+                        let stored = stored.lock().unwrap();
+                        let start = args.cursor.unwrap_or(0) as usize;
+                        let end = (start + 2).min(stored.len());
+                        let matches = stored[start..end]
+                            .iter()
+                            .filter(|d| {
+                                let meta = serde_json::from_str(&d.meta).unwrap();
+                                filter.as_ref().is_none_or(|f| f.matches(&meta))
+                            })
+                            .map(|d| CanisterVectorMatch {
+                                id: d.id.clone(),
+                                score: d.vec.iter().zip(&args.query).map(|(a, b)| a * b).sum(),
+                                text: d.text.clone(),
+                                meta: d.meta.clone(),
+                            })
+                            .collect();
+                        let page = VectorSearchPage {
+                            matches,
+                            next_cursor: (end < stored.len()).then_some(end as u64),
+                        };
+                        encode_args((Ok::<VectorSearchPage, String>(page),)).unwrap()
+                    }
+                    _ => panic!("unexpected method {}", method),
+                }
+            })
+        };
+
+        let store = CanisterVectorStore::new(Arc::new(caller), Principal::anonymous())
+            .with_max_upsert_bytes(100);
+        let docs: Vec<VectorDocument> = (0..5)
+            .map(|i| VectorDocument {
+                id: format!("doc{}", i),
+                vec: vec![i as f32, 1.0],
+                text: format!("text {}", i),
+                meta: serde_json::from_value(json!({"n": i})).unwrap(),
+            })
+            .collect();
+        store.vector_upsert(docs).await.unwrap();
+        assert_eq!(stored.lock().unwrap().len(), 5);
+        // each document is about 60 bytes, so one per call
+        assert_eq!(calls.lock().unwrap().len(), 5);
+
+        calls.lock().unwrap().clear();
+        let res = store.vector_search(vec![1.0, 0.0], 2, None).await.unwrap();
+        assert_eq!(
+            res.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(),
+            vec!["doc4", "doc3"]
+        );
+        assert_eq!(res[0].meta["n"], json!(4));
+        assert_eq!(calls.lock().unwrap().len(), 3);
+
+        let filter = VectorFilter::Lt {
+            key: "n".into(),
+            value: 3.0,
+        };
+        let res = store
+            .vector_search(vec![1.0, 0.0], 2, Some(filter))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(),
+            vec!["doc2", "doc1"]
+        );
+
+        let count = store
+            .vector_delete(vec!["doc4".into(), "doc9".into()])
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        let docs = store
+            .vector_get(vec!["doc1".into(), "doc4".into()])
+            .await
+            .unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].vec, vec![1.0, 1.0]);
+        assert_eq!(docs[0].meta["n"], json!(1));
+        let res = store.vector_search(vec![1.0, 0.0], 1, None).await.unwrap();
+        assert_eq!(res[0].id, "doc3");
+    }
+}
@@ -0,0 +1,318 @@
+use anda_core::{
+    BoxError, BoxPinFut, VectorDocument, VectorFilter, VectorMatch, VectorStoreFeatures,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::RwLock};
+
+use super::{
+    VectorStoreFeaturesDyn,
+    hnsw::{check_dim, dot, normalize},
+};
+
+/// The similarity metric of a [`FlatVectorStore`].
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// The cosine similarity, the vectors are normalized when inserted.
+    #[default]
+    Cosine,
+    /// The dot product of the vectors as they are, for embeddings whose norm matters.
+    DotProduct,
+}
+
+/// Configuration of the flat vector store.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct FlatConfig {
+    pub metric: Metric,
+}
+
+/// An in-memory vector store with exact search: the query is scored against every
+/// document matching the filter. Slower than [`super::HnswVectorStore`] on large corpora,
+/// but it never misses a match and supports the dot product.
+pub struct FlatVectorStore {
+    config: FlatConfig,
+    index: RwLock<Index>,
+}
+
+#[derive(Default)]
+struct Index {
+    dim: Option<usize>,
+    docs: Vec<VectorDocument>,
+    ids: HashMap<String, usize>,
+}
+
+impl FlatVectorStore {
+    pub fn new(config: FlatConfig) -> Self {
+        Self {
+            config,
+            index: RwLock::new(Index::default()),
+        }
+    }
+
+    /// Returns the number of documents in the store.
+    pub fn len(&self) -> usize {
+        let index = self.index.read().expect("vector index lock poisoned");
+        index.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the document by ID, its vector is normalized with the cosine metric.
+    pub fn get(&self, id: &str) -> Option<VectorDocument> {
+        let index = self.index.read().expect("vector index lock poisoned");
+        index.ids.get(id).map(|&i| index.docs[i].clone())
+    }
+
+    fn prepare(&self, vec: Vec<f32>) -> Option<Vec<f32>> {
+        match self.config.metric {
+            Metric::Cosine => normalize(vec),
+            Metric::DotProduct => vec.iter().all(|v| v.is_finite()).then_some(vec),
+        }
+    }
+
+    fn upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let mut index = self.index.write().expect("vector index lock poisoned");
+        // the first document sets the dimension of an empty store
+        let dim = index.dim.or(docs.first().map(|d| d.vec.len()));
+        let mut prepared = Vec::with_capacity(docs.len());
+        for mut doc in docs {
+            if doc.id.is_empty() {
+                return Err("vector document id is empty".into());
+            }
+            check_dim(dim, doc.vec.len())?;
+            doc.vec = self
+                .prepare(doc.vec)
+                .ok_or_else(|| format!("vector of document {:?} is invalid", doc.id))?;
+            prepared.push(doc);
+        }
+
+        index.dim = dim;
+        for doc in prepared {
+            match index.ids.get(&doc.id) {
+                Some(&i) => index.docs[i] = doc,
+                None => {
+                    let i = index.docs.len();
+                    index.ids.insert(doc.id.clone(), i);
+                    index.docs.push(doc);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&self, ids: Vec<String>) -> usize {
+        let mut index = self.index.write().expect("vector index lock poisoned");
+        let mut count = 0;
+        for id in ids {
+            if let Some(i) = index.ids.remove(&id) {
+                index.docs.swap_remove(i);
+                if let Some(moved) = index.docs.get(i).map(|d| d.id.clone()) {
+                    index.ids.insert(moved, i);
+                }
+                count += 1;
+            }
+        }
+        if index.docs.is_empty() {
+            index.dim = None;
+        }
+        count
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        let index = self.index.read().expect("vector index lock poisoned");
+        if top_k == 0 || index.docs.is_empty() {
+            return Ok(Vec::new());
+        }
+        check_dim(index.dim, query.len())?;
+        let query = self.prepare(query).ok_or("query vector is invalid")?;
+
+        let mut scored: Vec<(f32, usize)> = index
+            .docs
+            .iter()
+            .enumerate()
+            .filter(|(_, doc)| filter.as_ref().is_none_or(|f| f.matches(&doc.meta)))
+            .map(|(i, doc)| (dot(&query, &doc.vec), i))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        scored.truncate(top_k);
+        Ok(scored
+            .into_iter()
+            .map(|(score, i)| {
+                let doc = &index.docs[i];
+                VectorMatch {
+                    id: doc.id.clone(),
+                    score,
+                    text: doc.text.clone(),
+                    meta: doc.meta.clone(),
+                }
+            })
+            .collect())
+    }
+}
+
+impl Default for FlatVectorStore {
+    fn default() -> Self {
+        Self::new(FlatConfig::default())
+    }
+}
+
+impl VectorStoreFeatures for FlatVectorStore {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.upsert(docs)
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        Ok(ids.iter().filter_map(|id| self.get(id)).collect())
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        Ok(self.delete(ids))
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.search(query, top_k, filter)
+    }
+}
+
+impl VectorStoreFeaturesDyn for FlatVectorStore {
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>> {
+        Box::pin(futures::future::ready(self.upsert(docs)))
+    }
+
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>> {
+        Box::pin(futures::future::ready(Ok(ids
+            .iter()
+            .filter_map(|id| self.get(id))
+            .collect())))
+    }
+
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>> {
+        Box::pin(futures::future::ready(Ok(self.delete(ids))))
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>> {
+        Box::pin(futures::future::ready(self.search(query, top_k, filter)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anda_core::Value;
+    use serde_json::json;
+
+    fn doc(id: &str, vec: Vec<f32>, meta: Value) -> VectorDocument {
+        VectorDocument {
+            id: id.to_string(),
+            vec,
+            text: format!("text {}", id),
+            meta: serde_json::from_value(meta).unwrap(),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_flat_metrics() {
+        let docs = vec![
+            doc("a", vec![1.0, 0.0], json!({"lang": "en"})),
+            doc("b", vec![3.0, 3.0], json!({"lang": "fr"})),
+            doc("c", vec![0.0, -1.0], json!({"lang": "en"})),
+        ];
+
+        let store = FlatVectorStore::default();
+        store.vector_upsert(docs.clone()).await.unwrap();
+        let res = store.vector_search(vec![2.0, 0.0], 3, None).await.unwrap();
+        let ids: Vec<&str> = res.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert!((res[0].score - 1.0).abs() < 1e-6);
+
+        let store = FlatVectorStore::new(FlatConfig {
+            metric: Metric::DotProduct,
+        });
+        store.vector_upsert(docs).await.unwrap();
+        let res = store.vector_search(vec![2.0, 0.0], 2, None).await.unwrap();
+        let ids: Vec<&str> = res.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        assert_eq!(res[0].score, 6.0);
+
+        let filter: VectorFilter =
+            serde_json::from_value(json!({"eq": {"key": "lang", "value": "en"}})).unwrap();
+        let res = store
+            .vector_search(vec![2.0, 0.0], 5, Some(filter))
+            .await
+            .unwrap();
+        let ids: Vec<&str> = res.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["a", "c"]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_flat_upsert_delete() {
+        let store = FlatVectorStore::default();
+        store
+            .vector_upsert(vec![
+                doc("a", vec![1.0, 0.0], json!({})),
+                doc("b", vec![0.0, 1.0], json!({})),
+                doc("c", vec![1.0, 1.0], json!({})),
+            ])
+            .await
+            .unwrap();
+        store
+            .vector_upsert(vec![doc("a", vec![-1.0, 0.0], json!({}))])
+            .await
+            .unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get("a").unwrap().vec, vec![-1.0, 0.0]);
+
+        assert!(
+            store
+                .vector_upsert(vec![doc("d", vec![1.0, 0.0, 0.0], json!({}))])
+                .await
+                .is_err()
+        );
+        assert!(
+            store
+                .vector_upsert(vec![doc("d", vec![0.0, 0.0], json!({}))])
+                .await
+                .is_err()
+        );
+
+        let count = store
+            .vector_delete(vec!["a".into(), "x".into()])
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(store.get("a").is_none());
+        assert_eq!(store.get("c").unwrap().id, "c");
+        let res = store.vector_search(vec![0.0, 1.0], 5, None).await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].id, "b");
+
+        store
+            .vector_delete(vec!["b".into(), "c".into()])
+            .await
+            .unwrap();
+        assert!(store.is_empty());
+        // an empty store accepts another dimension
+        store
+            .vector_upsert(vec![doc("d", vec![1.0, 0.0, 0.0], json!({}))])
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,592 @@
+use anda_core::{
+    BoxError, BoxPinFut, Value, VectorDocument, VectorFilter, VectorMatch, VectorStoreFeatures,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    sync::RwLock,
+};
+
+use super::VectorStoreFeaturesDyn;
+
+/// The maximum level of a node in the HNSW graph.
+const MAX_LEVEL: usize = 16;
+
+/// Configuration of the HNSW index.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HnswConfig {
+    /// The maximum number of neighbors of a node on the upper layers, 2 * m on the layer 0.
+    pub m: usize,
+    /// The size of the dynamic candidate list when inserting, higher is more accurate but slower.
+    pub ef_construction: usize,
+    /// The size of the dynamic candidate list when searching, at least top_k.
+    pub ef_search: usize,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+/// An in-memory vector store with an HNSW index, using the cosine similarity.
+///
+/// Deleted and replaced documents are tombstoned, and the index is rebuilt
+/// when the tombstones exceed half of the nodes.
+pub struct HnswVectorStore {
+    config: HnswConfig,
+    index: RwLock<Index>,
+}
+
+struct Node {
+    id: String,
+    vec: Vec<f32>,
+    text: String,
+    meta: BTreeMap<String, Value>,
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+#[derive(Default)]
+struct Index {
+    dim: Option<usize>,
+    nodes: Vec<Node>,
+    ids: HashMap<String, usize>,
+    entry: Option<usize>,
+    max_level: usize,
+    deleted: usize,
+}
+
+/// A node with its similarity score to the query, ordered by score.
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .total_cmp(&other.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+impl HnswVectorStore {
+    pub fn new(config: HnswConfig) -> Self {
+        Self {
+            config: HnswConfig {
+                m: config.m.max(2),
+                ef_construction: config.ef_construction.max(1),
+                ef_search: config.ef_search.max(1),
+            },
+            index: RwLock::new(Index::default()),
+        }
+    }
+
+    /// Returns the number of documents in the store.
+    pub fn len(&self) -> usize {
+        let index = self.index.read().expect("vector index lock poisoned");
+        index.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the document by ID, its vector is normalized.
+    pub fn get(&self, id: &str) -> Option<VectorDocument> {
+        let index = self.index.read().expect("vector index lock poisoned");
+        index.ids.get(id).map(|&i| {
+            let node = &index.nodes[i];
+            VectorDocument {
+                id: node.id.clone(),
+                vec: node.vec.clone(),
+                text: node.text.clone(),
+                meta: node.meta.clone(),
+            }
+        })
+    }
+
+    fn upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let mut index = self.index.write().expect("vector index lock poisoned");
+        // the first document sets the dimension of an empty store
+        let dim = index.dim.or(docs.first().map(|d| d.vec.len()));
+        for doc in &docs {
+            if doc.id.is_empty() {
+                return Err("vector document id is empty".into());
+            }
+            check_dim(dim, doc.vec.len())?;
+            if normalize(doc.vec.clone()).is_none() {
+                return Err(format!("vector of document {:?} is zero", doc.id).into());
+            }
+        }
+
+        for doc in docs {
+            index.tombstone(&doc.id);
+            let vec = normalize(doc.vec).unwrap();
+            index.insert(&self.config, doc.id, vec, doc.text, doc.meta);
+        }
+        index.compact(&self.config);
+        Ok(())
+    }
+
+    fn delete(&self, ids: Vec<String>) -> usize {
+        let mut index = self.index.write().expect("vector index lock poisoned");
+        let count = ids.iter().filter(|id| index.tombstone(id)).count();
+        index.compact(&self.config);
+        count
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        let index = self.index.read().expect("vector index lock poisoned");
+        if top_k == 0 || index.ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        check_dim(index.dim, query.len())?;
+        let query = normalize(query).ok_or("query vector is zero")?;
+
+        // With a filter, the candidate list grows until enough documents match
+        // or the whole graph is visited.
+        let mut ef = self.config.ef_search.max(top_k);
+        loop {
+            let matched: Vec<Scored> = index
+                .knn(&query, ef)
+                .into_iter()
+                .filter(|s| {
+                    let node = &index.nodes[s.1];
+                    !node.deleted && filter.as_ref().is_none_or(|f| f.matches(&node.meta))
+                })
+                .take(top_k)
+                .collect();
+            if matched.len() >= top_k || ef >= index.nodes.len() {
+                return Ok(matched
+                    .into_iter()
+                    .map(|Scored(score, i)| {
+                        let node = &index.nodes[i];
+                        VectorMatch {
+                            id: node.id.clone(),
+                            score,
+                            text: node.text.clone(),
+                            meta: node.meta.clone(),
+                        }
+                    })
+                    .collect());
+            }
+            ef = ef.saturating_mul(4).min(index.nodes.len());
+        }
+    }
+}
+
+impl Default for HnswVectorStore {
+    fn default() -> Self {
+        Self::new(HnswConfig::default())
+    }
+}
+
+impl VectorStoreFeatures for HnswVectorStore {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.upsert(docs)
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        Ok(ids.iter().filter_map(|id| self.get(id)).collect())
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        Ok(self.delete(ids))
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.search(query, top_k, filter)
+    }
+}
+
+impl VectorStoreFeaturesDyn for HnswVectorStore {
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>> {
+        Box::pin(futures::future::ready(self.upsert(docs)))
+    }
+
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>> {
+        Box::pin(futures::future::ready(Ok(ids
+            .iter()
+            .filter_map(|id| self.get(id))
+            .collect())))
+    }
+
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>> {
+        Box::pin(futures::future::ready(Ok(self.delete(ids))))
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>> {
+        Box::pin(futures::future::ready(self.search(query, top_k, filter)))
+    }
+}
+
+impl Index {
+    fn similarity(&self, query: &[f32], i: usize) -> f32 {
+        dot(query, &self.nodes[i].vec)
+    }
+
+    /// Marks the document as deleted, the node stays in the graph for navigation.
+    fn tombstone(&mut self, id: &str) -> bool {
+        match self.ids.remove(id) {
+            Some(i) => {
+                self.nodes[i].deleted = true;
+                self.deleted += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        config: &HnswConfig,
+        id: String,
+        vec: Vec<f32>,
+        text: String,
+        meta: BTreeMap<String, Value>,
+    ) {
+        let level = random_level(config.m);
+        let idx = self.nodes.len();
+        self.dim = Some(vec.len());
+        self.ids.insert(id.clone(), idx);
+        self.nodes.push(Node {
+            id,
+            vec,
+            text,
+            meta,
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+
+        let mut ep = match self.entry {
+            Some(ep) => ep,
+            None => {
+                self.entry = Some(idx);
+                self.max_level = level;
+                return;
+            }
+        };
+
+        let query = self.nodes[idx].vec.clone();
+        for layer in (level + 1..=self.max_level).rev() {
+            ep = self.greedy(&query, ep, layer);
+        }
+
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, ep, config.ef_construction, layer);
+            let max_links = max_links(config.m, layer);
+            let neighbors: Vec<usize> = candidates.iter().take(config.m).map(|s| s.1).collect();
+            for &n in &neighbors {
+                self.nodes[n].links[layer].push(idx);
+                if self.nodes[n].links[layer].len() > max_links {
+                    self.prune(n, layer, max_links);
+                }
+            }
+            self.nodes[idx].links[layer] = neighbors;
+            if let Some(best) = candidates.first() {
+                ep = best.1;
+            }
+        }
+
+        if level > self.max_level {
+            self.entry = Some(idx);
+            self.max_level = level;
+        }
+    }
+
+    /// Keeps the most similar neighbors of the node on the layer.
+    fn prune(&mut self, node: usize, layer: usize, max_links: usize) {
+        let base = self.nodes[node].vec.clone();
+        let mut links: Vec<Scored> = self.nodes[node].links[layer]
+            .iter()
+            .map(|&n| Scored(self.similarity(&base, n), n))
+            .collect();
+        links.sort_unstable_by(|a, b| b.cmp(a));
+        links.truncate(max_links);
+        self.nodes[node].links[layer] = links.into_iter().map(|s| s.1).collect();
+    }
+
+    /// Moves to the most similar neighbor until no neighbor is more similar.
+    fn greedy(&self, query: &[f32], mut ep: usize, layer: usize) -> usize {
+        let mut best = self.similarity(query, ep);
+        loop {
+            let mut changed = false;
+            for &n in &self.nodes[ep].links[layer] {
+                let score = self.similarity(query, n);
+                if score > best {
+                    best = score;
+                    ep = n;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return ep;
+            }
+        }
+    }
+
+    /// Returns up to ef nodes most similar to the query on the layer, ordered by descending score.
+    fn search_layer(&self, query: &[f32], ep: usize, ef: usize, layer: usize) -> Vec<Scored> {
+        let start = Scored(self.similarity(query, ep), ep);
+        let mut visited = HashSet::from([ep]);
+        let mut candidates = BinaryHeap::from([start]);
+        let mut results = BinaryHeap::from([Reverse(start)]);
+
+        while let Some(current) = candidates.pop() {
+            let worst = results.peek().map(|r| r.0.0).unwrap_or(f32::MIN);
+            if current.0 < worst && results.len() >= ef {
+                break;
+            }
+            for &n in &self.nodes[current.1].links[layer] {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let scored = Scored(self.similarity(query, n), n);
+                let worst = results.peek().map(|r| r.0.0).unwrap_or(f32::MIN);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results: Vec<Scored> = results.into_iter().map(|r| r.0).collect();
+        results.sort_unstable_by(|a, b| b.cmp(a));
+        results
+    }
+
+    fn knn(&self, query: &[f32], ef: usize) -> Vec<Scored> {
+        let mut ep = match self.entry {
+            Some(ep) => ep,
+            None => return Vec::new(),
+        };
+        for layer in (1..=self.max_level).rev() {
+            ep = self.greedy(query, ep, layer);
+        }
+        self.search_layer(query, ep, ef, 0)
+    }
+
+    /// Rebuilds the index without the tombstones when they exceed half of the nodes.
+    fn compact(&mut self, config: &HnswConfig) {
+        if self.deleted * 2 <= self.nodes.len() {
+            return;
+        }
+
+        let nodes = std::mem::take(&mut self.nodes);
+        *self = Index {
+            dim: self.dim,
+            ..Default::default()
+        };
+        for node in nodes.into_iter().filter(|n| !n.deleted) {
+            self.insert(config, node.id, node.vec, node.text, node.meta);
+        }
+    }
+}
+
+pub(super) fn check_dim(expected: Option<usize>, dim: usize) -> Result<(), BoxError> {
+    if dim == 0 {
+        return Err("vector is empty".into());
+    }
+    match expected {
+        Some(d) if d != dim => {
+            Err(format!("vector dimension mismatch, expected {}, got {}", d, dim).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn max_links(m: usize, layer: usize) -> usize {
+    if layer == 0 { m * 2 } else { m }
+}
+
+fn random_level(m: usize) -> usize {
+    let ml = 1.0 / (m as f64).ln();
+    let r: f64 = rand::random();
+    ((-(1.0 - r).ln() * ml).floor() as usize).min(MAX_LEVEL)
+}
+
+pub(super) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub(super) fn normalize(mut vec: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = dot(&vec, &vec).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    vec.iter_mut().for_each(|v| *v /= norm);
+    Some(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn random_vec(dim: usize) -> Vec<f32> {
+        (0..dim).map(|_| rand::random::<f32>() - 0.5).collect()
+    }
+
+    fn doc(id: &str, vec: Vec<f32>, meta: Value) -> VectorDocument {
+        VectorDocument {
+            id: id.to_string(),
+            vec,
+            text: format!("text {}", id),
+            meta: serde_json::from_value(meta).unwrap(),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_hnsw_recall() {
+        let store = HnswVectorStore::default();
+        let docs: Vec<VectorDocument> = (0..1000)
+            .map(|i| doc(&i.to_string(), random_vec(32), json!({"n": i})))
+            .collect();
+        store.vector_upsert(docs.clone()).await.unwrap();
+        assert_eq!(store.len(), 1000);
+
+        let mut hits = 0;
+        for _ in 0..20 {
+            let query = random_vec(32);
+            let q = normalize(query.clone()).unwrap();
+            let mut expected: Vec<(f32, &str)> = docs
+                .iter()
+                .map(|d| (dot(&q, &normalize(d.vec.clone()).unwrap()), d.id.as_str()))
+                .collect();
+            expected.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let res = store.vector_search(query, 10, None).await.unwrap();
+            assert_eq!(res.len(), 10);
+            assert!(res.windows(2).all(|w| w[0].score >= w[1].score));
+            hits += res
+                .iter()
+                .filter(|m| expected[..10].iter().any(|e| e.1 == m.id))
+                .count();
+        }
+        assert!(hits >= 180, "recall too low: {}/200", hits);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_hnsw_upsert_delete() {
+        let store = HnswVectorStore::default();
+        store
+            .vector_upsert(vec![
+                doc("a", vec![1.0, 0.0], json!({})),
+                doc("b", vec![0.0, 1.0], json!({})),
+            ])
+            .await
+            .unwrap();
+        let res = store.vector_search(vec![2.0, 0.1], 1, None).await.unwrap();
+        assert_eq!(res[0].id, "a");
+        assert!(res[0].score > 0.99);
+
+        // replaces the vector of "a"
+        store
+            .vector_upsert(vec![doc("a", vec![-1.0, 0.0], json!({}))])
+            .await
+            .unwrap();
+        assert_eq!(store.len(), 2);
+        let res = store.vector_search(vec![2.0, 0.1], 2, None).await.unwrap();
+        assert_eq!(res[0].id, "b");
+        assert_eq!(res[1].id, "a");
+        assert!(res[1].score < -0.99);
+
+        let err = store
+            .vector_upsert(vec![doc("c", vec![1.0, 0.0, 0.0], json!({}))])
+            .await;
+        assert!(err.is_err());
+        assert!(
+            store
+                .vector_upsert(vec![doc("c", vec![0.0, 0.0], json!({}))])
+                .await
+                .is_err()
+        );
+        assert!(store.vector_search(vec![1.0], 1, None).await.is_err());
+
+        let count = store
+            .vector_delete(vec!["a".into(), "x".into()])
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(store.get("a").is_none());
+        let res = store.vector_search(vec![1.0, 0.0], 5, None).await.unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, "b");
+
+        store.vector_delete(vec!["b".into()]).await.unwrap();
+        assert!(store.is_empty());
+        let res = store.vector_search(vec![1.0, 0.0], 5, None).await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_hnsw_filter() {
+        let store = HnswVectorStore::new(HnswConfig {
+            ef_search: 8,
+            ..Default::default()
+        });
+        let docs: Vec<VectorDocument> = (0..500)
+            .map(|i| {
+                doc(
+                    &i.to_string(),
+                    random_vec(8),
+                    json!({"n": i, "even": i % 2 == 0}),
+                )
+            })
+            .collect();
+        store.vector_upsert(docs).await.unwrap();
+
+        // only 5 documents match, far more than ef_search are visited to find them
+        let filter = VectorFilter::Lt {
+            key: "n".into(),
+            value: 5.0,
+        };
+        let res = store
+            .vector_search(random_vec(8), 10, Some(filter))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 5);
+
+        let filter = VectorFilter::Eq {
+            key: "even".into(),
+            value: json!(true),
+        };
+        let res = store
+            .vector_search(random_vec(8), 20, Some(filter))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 20);
+        assert!(res.iter().all(|m| m.meta["even"] == json!(true)));
+    }
+}
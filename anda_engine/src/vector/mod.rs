@@ -0,0 +1,129 @@
+//! # Vector Module
+//!
+//! This module provides the [`VectorStoreFeatures`] implementations of Anda Engine, for storing
+//! embedding vectors and searching them by similarity in retrieval-augmented generation.
+//!
+//! ## Implementations
+//!
+//! - [`HnswVectorStore`]: An in-memory store with an HNSW (Hierarchical Navigable Small World) index,
+//!   it requires no external infrastructure and is a good start for small and medium corpora.
+//! - [`FlatVectorStore`]: An in-memory store with exact search by cosine similarity or dot product,
+//!   for small corpora that need every match.
+//! - [`PersistentVectorStore`]: An HNSW store persisted in segments to the engine's object store,
+//!   so that the indexes survive restarts without external infrastructure.
+//! - [`QdrantVectorStore`]: A store backed by a [Qdrant](https://qdrant.tech) collection,
+//!   for production-scale retrieval.
+//! - [`CanisterVectorStore`]: A store persisted in a canister on the Internet Computer,
+//!   for agents whose knowledge must live fully on chain.
+//!
+//! The engine's vector store is configured by [`crate::engine::EngineBuilder::with_vector_store`],
+//! and defaults to an in-memory HNSW store.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! let store = HnswVectorStore::new(HnswConfig::default());
+//! store.vector_upsert(vec![VectorDocument { id: "1".into(), vec, ..Default::default() }]).await?;
+//! let matches = store.vector_search(query, 5, None).await?;
+//! ```
+
+use anda_core::{BoxError, BoxPinFut};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+mod canister;
+mod flat;
+mod hnsw;
+mod persistent;
+mod qdrant;
+
+pub use anda_core::{VectorDocument, VectorFilter, VectorMatch, VectorStoreFeatures};
+pub use canister::*;
+pub use flat::*;
+pub use hnsw::*;
+pub use persistent::*;
+pub use qdrant::*;
+
+/// Trait for dynamic vector store features that can be used across threads
+pub trait VectorStoreFeaturesDyn: Send + Sync + 'static {
+    /// Inserts the documents, or replaces the documents with the same IDs
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>>;
+
+    /// Gets the documents by IDs with their vectors, the missing ones are skipped
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>>;
+
+    /// Deletes the documents by IDs, returns the number of deleted documents
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>>;
+
+    /// Finds the top k documents most similar to the query vector and matching the filter
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>>;
+}
+
+/// Wrapper for the vector store of the engine
+#[derive(Clone)]
+pub struct VectorIndex {
+    inner: Arc<dyn VectorStoreFeaturesDyn>,
+}
+
+impl VectorIndex {
+    pub fn new(inner: Arc<dyn VectorStoreFeaturesDyn>) -> Self {
+        Self { inner }
+    }
+
+    /// Creates an in-memory HNSW vector store with the default configuration
+    pub fn in_memory() -> Self {
+        Self {
+            inner: Arc::new(HnswVectorStore::default()),
+        }
+    }
+}
+
+impl VectorStoreFeatures for VectorIndex {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.inner.upsert(docs).await
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        self.inner.get(ids).await
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.inner.delete(ids).await
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.inner.search(query, top_k, filter).await
+    }
+}
+
+/// Vector store configuration, selected by `provider`: "hnsw", "flat" or "qdrant".
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum VectorStoreConfig {
+    Hnsw(HnswConfig),
+    Flat(FlatConfig),
+    Qdrant(QdrantConfig),
+}
+
+impl VectorStoreConfig {
+    /// Builds the vector store
+    pub fn build(self) -> Result<VectorIndex, BoxError> {
+        Ok(match self {
+            VectorStoreConfig::Hnsw(cfg) => VectorIndex::new(Arc::new(HnswVectorStore::new(cfg))),
+            VectorStoreConfig::Flat(cfg) => VectorIndex::new(Arc::new(FlatVectorStore::new(cfg))),
+            VectorStoreConfig::Qdrant(cfg) => {
+                VectorIndex::new(Arc::new(QdrantVectorStore::new(cfg)?))
+            }
+        })
+    }
+}
@@ -0,0 +1,366 @@
+use anda_core::{
+    BoxError, BoxPinFut, Path, PutMode, VectorDocument, VectorFilter, VectorMatch,
+    VectorStoreFeatures,
+};
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, RwLock},
+};
+use tokio::sync::{Mutex, OnceCell};
+
+use super::{HnswConfig, HnswVectorStore, VectorStoreFeaturesDyn};
+use crate::store::Store;
+
+/// The store namespace of the persistent vector stores.
+pub static VECTORS_PATH: &str = "_vectors";
+
+/// The version of the on-disk format.
+const FORMAT_VERSION: u32 = 1;
+
+/// Configuration of the persistent vector store.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PersistentConfig {
+    /// The name of the store, its objects are under `_vectors/{name}`.
+    pub name: String,
+    /// The number of segments of a new store, the documents are spread over them by ID.
+    /// An existing store keeps the number it was created with.
+    pub segments: u32,
+    /// The configuration of the in-memory index.
+    pub hnsw: HnswConfig,
+}
+
+impl Default for PersistentConfig {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            segments: 16,
+            hnsw: HnswConfig::default(),
+        }
+    }
+}
+
+/// The manifest of a store, at `_vectors/{name}/manifest`.
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    version: u32,
+    segments: u32,
+}
+
+/// A vector store persisted to the object store of the engine, so that the indexes
+/// survive restarts.
+///
+/// The documents are spread by ID over a fixed number of segments, each one is a CBOR
+/// encoded list of documents at `_vectors/{name}/segments/{index}`, and a write only
+/// rewrites the segments of the changed documents. The store is loaded lazily on the
+/// first operation, into an in-memory HNSW index that serves the searches.
+#[derive(Clone)]
+pub struct PersistentVectorStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: Store,
+    namespace: Path,
+    config: PersistentConfig,
+    state: OnceCell<State>,
+    // serializes the writes and their persistence
+    write: Mutex<()>,
+}
+
+struct State {
+    segments: u32,
+    index: HnswVectorStore,
+    // the persisted documents by segment, with their original vectors
+    docs: RwLock<Vec<BTreeMap<String, VectorDocument>>>,
+}
+
+impl PersistentVectorStore {
+    /// Creates the store, its documents are loaded on the first operation.
+    pub fn new(store: Store, config: PersistentConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                store,
+                namespace: Path::from(VECTORS_PATH).child(config.name.as_str()),
+                config: PersistentConfig {
+                    segments: config.segments.max(1),
+                    ..config
+                },
+                state: OnceCell::new(),
+                write: Mutex::new(()),
+            }),
+        }
+    }
+}
+
+impl Inner {
+    async fn state(&self) -> Result<&State, BoxError> {
+        self.state.get_or_try_init(|| self.load()).await
+    }
+
+    async fn load(&self) -> Result<State, BoxError> {
+        let manifest: Manifest = match self.get_object(&Path::from("manifest")).await? {
+            Some(data) => ciborium::from_reader(&data[..])?,
+            None => {
+                let manifest = Manifest {
+                    version: FORMAT_VERSION,
+                    segments: self.config.segments,
+                };
+                self.store
+                    .store_put(
+                        &self.namespace,
+                        &Path::from("manifest"),
+                        PutMode::Overwrite,
+                        to_cbor_bytes(&manifest).into(),
+                    )
+                    .await?;
+                manifest
+            }
+        };
+        if manifest.version != FORMAT_VERSION {
+            return Err(format!(
+                "unsupported vector store format version {}",
+                manifest.version
+            )
+            .into());
+        }
+
+        let index = HnswVectorStore::new(self.config.hnsw);
+        let mut docs = Vec::with_capacity(manifest.segments as usize);
+        for i in 0..manifest.segments {
+            let segment: Vec<VectorDocument> = match self.get_object(&segment_path(i)).await? {
+                Some(data) => ciborium::from_reader(&data[..])?,
+                None => Vec::new(),
+            };
+            if !segment.is_empty() {
+                index.vector_upsert(segment.clone()).await?;
+            }
+            docs.push(segment.into_iter().map(|d| (d.id.clone(), d)).collect());
+        }
+
+        Ok(State {
+            segments: manifest.segments,
+            index,
+            docs: RwLock::new(docs),
+        })
+    }
+
+    /// Gets an object of the store, None if it doesn't exist.
+    async fn get_object(&self, path: &Path) -> Result<Option<bytes::Bytes>, BoxError> {
+        match self.store.store_get(&self.namespace, path).await {
+            Ok((data, _)) => Ok(Some(data)),
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<object_store::Error>(),
+                    Some(object_store::Error::NotFound { .. })
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the segments, the documents are updated in memory by the caller.
+    async fn persist(&self, state: &State, segments: BTreeSet<u32>) -> Result<(), BoxError> {
+        for i in segments {
+            let data = {
+                let docs = state.docs.read().expect("vector segments lock poisoned");
+                to_cbor_bytes(&docs[i as usize].values().collect::<Vec<_>>())
+            };
+            self.store
+                .store_put(
+                    &self.namespace,
+                    &segment_path(i),
+                    PutMode::Overwrite,
+                    data.into(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let state = self.state().await?;
+        let _guard = self.write.lock().await;
+        // the index validates the documents before they are persisted
+        state.index.vector_upsert(docs.clone()).await?;
+        let mut changed = BTreeSet::new();
+        {
+            let mut segments = state.docs.write().expect("vector segments lock poisoned");
+            for doc in docs {
+                let i = segment_of(&doc.id, state.segments);
+                changed.insert(i);
+                segments[i as usize].insert(doc.id.clone(), doc);
+            }
+        }
+        self.persist(state, changed).await
+    }
+
+    async fn get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        let state = self.state().await?;
+        let segments = state.docs.read().expect("vector segments lock poisoned");
+        Ok(ids
+            .iter()
+            .filter_map(|id| segments[segment_of(id, state.segments) as usize].get(id))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        let state = self.state().await?;
+        let _guard = self.write.lock().await;
+        let mut changed = BTreeSet::new();
+        {
+            let mut segments = state.docs.write().expect("vector segments lock poisoned");
+            for id in &ids {
+                let i = segment_of(id, state.segments);
+                if segments[i as usize].remove(id).is_some() {
+                    changed.insert(i);
+                }
+            }
+        }
+        let count = state.index.vector_delete(ids).await?;
+        self.persist(state, changed).await?;
+        Ok(count)
+    }
+
+    async fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        let state = self.state().await?;
+        state.index.vector_search(query, top_k, filter).await
+    }
+}
+
+impl VectorStoreFeatures for PersistentVectorStore {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.inner.upsert(docs).await
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        self.inner.get(ids).await
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.inner.delete(ids).await
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.inner.search(query, top_k, filter).await
+    }
+}
+
+impl VectorStoreFeaturesDyn for PersistentVectorStore {
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>> {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.upsert(docs).await })
+    }
+
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>> {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.get(ids).await })
+    }
+
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>> {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.delete(ids).await })
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>> {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.search(query, top_k, filter).await })
+    }
+}
+
+fn segment_path(i: u32) -> Path {
+    Path::from(format!("segments/{:04}", i))
+}
+
+fn segment_of(id: &str, segments: u32) -> u32 {
+    let hash = sha3_256(id.as_bytes());
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) % segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use serde_json::json;
+
+    fn doc(id: &str, vec: Vec<f32>) -> VectorDocument {
+        VectorDocument {
+            id: id.to_string(),
+            vec,
+            text: format!("text {}", id),
+            meta: serde_json::from_value(json!({"id": id})).unwrap(),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_persistent_vector_store() {
+        let store = Store::new(Arc::new(InMemory::new()));
+        let config = PersistentConfig {
+            segments: 4,
+            ..Default::default()
+        };
+        let vectors = PersistentVectorStore::new(store.clone(), config.clone());
+        vectors
+            .vector_upsert(vec![
+                doc("a", vec![1.0, 0.0]),
+                doc("b", vec![0.0, 2.0]),
+                doc("c", vec![1.0, 1.0]),
+            ])
+            .await
+            .unwrap();
+        assert!(
+            vectors
+                .vector_upsert(vec![doc("d", vec![1.0, 0.0, 0.0])])
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            vectors
+                .vector_delete(vec!["c".into(), "x".into()])
+                .await
+                .unwrap(),
+            1
+        );
+
+        // reopened from the persisted segments, with another number of segments
+        let vectors = PersistentVectorStore::new(
+            store,
+            PersistentConfig {
+                segments: 8,
+                ..config
+            },
+        );
+        let docs = vectors
+            .vector_get(vec!["b".into(), "c".into()])
+            .await
+            .unwrap();
+        assert_eq!(docs, vec![doc("b", vec![0.0, 2.0])]);
+        let res = vectors
+            .vector_search(vec![0.1, 1.0], 2, None)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = res.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        assert_eq!(res[0].meta["id"], "b");
+    }
+}
@@ -0,0 +1,471 @@
+use anda_core::{
+    BoxError, BoxPinFut, CONTENT_TYPE_JSON, Value, VectorDocument, VectorFilter, VectorMatch,
+    VectorStoreFeatures,
+};
+use ic_cose_types::cose::sha3_256;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use super::VectorStoreFeaturesDyn;
+use crate::{APP_USER_AGENT, secrets::REDACTED};
+
+/// Configuration of a Qdrant collection.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QdrantConfig {
+    /// The URL of the Qdrant REST API, e.g. "http://localhost:6333".
+    pub url: String,
+    /// The API key of Qdrant Cloud or a secured instance.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    pub collection: String,
+    /// The distance of a new collection: "Cosine" (default), "Dot", "Euclid" or "Manhattan".
+    #[serde(default = "default_distance")]
+    pub distance: String,
+    /// The number of points per upsert request.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_distance() -> String {
+    "Cosine".to_string()
+}
+
+fn default_batch_size() -> usize {
+    256
+}
+
+impl QdrantConfig {
+    pub fn new(url: &str, collection: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: None,
+            collection: collection.to_string(),
+            distance: default_distance(),
+            batch_size: default_batch_size(),
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = Some(api_key);
+        self
+    }
+}
+
+impl fmt::Debug for QdrantConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QdrantConfig")
+            .field("url", &self.url)
+            .field("api_key", &self.api_key.as_ref().map(|_| REDACTED))
+            .field("collection", &self.collection)
+            .field("distance", &self.distance)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+/// A vector store backed by a collection of [Qdrant](https://qdrant.tech), for production-scale retrieval.
+///
+/// Qdrant point IDs are UUIDs derived from the document IDs, the document ID, text and metadata
+/// are stored in the payload, so metadata filters apply to the `meta.{key}` payload fields.
+/// The collection is created with the dimension of the first upserted documents if it doesn't exist.
+#[derive(Clone)]
+pub struct QdrantVectorStore {
+    endpoint: String,
+    distance: String,
+    batch_size: usize,
+    http: reqwest::Client,
+    ready: Arc<AtomicBool>,
+}
+
+#[derive(Deserialize)]
+struct QdrantResponse<T> {
+    result: Option<T>,
+    #[serde(default)]
+    status: Value,
+}
+
+#[derive(Deserialize)]
+struct ScoredPoint {
+    score: f32,
+    #[serde(default)]
+    payload: Option<Payload>,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    id: Value,
+    #[serde(default)]
+    payload: Option<Payload>,
+    #[serde(default)]
+    vector: Option<Vec<f32>>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Payload {
+    id: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    meta: BTreeMap<String, Value>,
+}
+
+impl QdrantVectorStore {
+    pub fn new(config: QdrantConfig) -> Result<Self, BoxError> {
+        if config.collection.is_empty() {
+            return Err("qdrant collection is empty".into());
+        }
+        let mut headers = reqwest::header::HeaderMap::new();
+        let ct: http::HeaderValue = CONTENT_TYPE_JSON.parse()?;
+        headers.insert(http::header::CONTENT_TYPE, ct.clone());
+        headers.insert(http::header::ACCEPT, ct);
+        if let Some(api_key) = &config.api_key {
+            let mut key: http::HeaderValue = api_key.parse()?;
+            key.set_sensitive(true);
+            headers.insert("api-key", key);
+        }
+        let http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(60))
+            .gzip(true)
+            .user_agent(APP_USER_AGENT)
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
+            endpoint: format!(
+                "{}/collections/{}",
+                config.url.trim_end_matches('/'),
+                config.collection
+            ),
+            distance: config.distance,
+            batch_size: config.batch_size.max(1),
+            http,
+            ready: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: http::Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Option<T>, BoxError> {
+        let mut req = self
+            .http
+            .request(method, format!("{}{}", self.endpoint, path));
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        if status == http::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = res.bytes().await?;
+        let res: QdrantResponse<T> = serde_json::from_slice(&body).map_err(|err| {
+            format!(
+                "Qdrant response error, status: {}, error: {}, body: {}",
+                status,
+                err,
+                String::from_utf8_lossy(&body)
+            )
+        })?;
+        if !status.is_success() {
+            return Err(format!("Qdrant error, status: {}, {}", status, res.status).into());
+        }
+        Ok(res.result)
+    }
+
+    /// Creates the collection with the dimension if it doesn't exist.
+    pub async fn ensure_collection(&self, dim: usize) -> Result<(), BoxError> {
+        if self.ready.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let info: Option<Value> = self.request(http::Method::GET, "", None).await?;
+        if info.is_none() {
+            let body = json!({"vectors": {"size": dim, "distance": self.distance}});
+            self.request::<Value>(http::Method::PUT, "", Some(body))
+                .await?
+                .ok_or("Qdrant failed to create the collection")?;
+        }
+        self.ready.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn upsert_points(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        let dim = match docs.first() {
+            Some(doc) => doc.vec.len(),
+            None => return Ok(()),
+        };
+        for doc in &docs {
+            if doc.id.is_empty() {
+                return Err("vector document id is empty".into());
+            }
+            if doc.vec.len() != dim {
+                return Err(format!(
+                    "vector dimension mismatch, expected {}, got {}",
+                    dim,
+                    doc.vec.len()
+                )
+                .into());
+            }
+        }
+        self.ensure_collection(dim).await?;
+
+        for chunk in docs.chunks(self.batch_size) {
+            let points: Vec<Value> = chunk
+                .iter()
+                .map(|doc| {
+                    json!({
+                        "id": point_id(&doc.id),
+                        "vector": doc.vec,
+                        "payload": Payload {
+                            id: doc.id.clone(),
+                            text: doc.text.clone(),
+                            meta: doc.meta.clone(),
+                        },
+                    })
+                })
+                .collect();
+            self.request::<Value>(
+                http::Method::PUT,
+                "/points?wait=true",
+                Some(json!({ "points": points })),
+            )
+            .await?
+            .ok_or("Qdrant collection not found")?;
+        }
+        Ok(())
+    }
+
+    async fn get_points(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let points: Vec<String> = ids.iter().map(|id| point_id(id)).collect();
+        let records: Vec<Record> = self
+            .request(
+                http::Method::POST,
+                "/points",
+                Some(json!({"ids": points, "with_payload": true, "with_vector": true})),
+            )
+            .await?
+            .unwrap_or_default();
+        Ok(records
+            .into_iter()
+            .filter_map(|r| {
+                let payload = r.payload?;
+                Some(VectorDocument {
+                    id: payload.id,
+                    vec: r.vector.unwrap_or_default(),
+                    text: payload.text,
+                    meta: payload.meta,
+                })
+            })
+            .collect())
+    }
+
+    async fn delete_points(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let points: Vec<String> = ids.iter().map(|id| point_id(id)).collect();
+        // Qdrant doesn't return the number of deleted points
+        let existing: Vec<Record> = match self
+            .request(
+                http::Method::POST,
+                "/points",
+                Some(json!({"ids": points, "with_payload": false, "with_vector": false})),
+            )
+            .await?
+        {
+            Some(existing) => existing,
+            None => return Ok(0),
+        };
+        if existing.is_empty() {
+            return Ok(0);
+        }
+        let existing: Vec<Value> = existing.into_iter().map(|r| r.id).collect();
+        self.request::<Value>(
+            http::Method::POST,
+            "/points/delete?wait=true",
+            Some(json!({ "points": existing })),
+        )
+        .await?;
+        Ok(existing.len())
+    }
+
+    async fn search_points(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        if top_k == 0 {
+            return Ok(Vec::new());
+        }
+        let mut body = json!({"vector": query, "limit": top_k, "with_payload": true});
+        if let Some(filter) = &filter {
+            body["filter"] = json!({ "must": [qdrant_condition(filter)] });
+        }
+        let points: Vec<ScoredPoint> = self
+            .request(http::Method::POST, "/points/search", Some(body))
+            .await?
+            .unwrap_or_default();
+        Ok(points
+            .into_iter()
+            .map(|p| {
+                let payload = p.payload.unwrap_or_default();
+                VectorMatch {
+                    id: payload.id,
+                    score: p.score,
+                    text: payload.text,
+                    meta: payload.meta,
+                }
+            })
+            .collect())
+    }
+}
+
+impl VectorStoreFeatures for QdrantVectorStore {
+    async fn vector_upsert(&self, docs: Vec<VectorDocument>) -> Result<(), BoxError> {
+        self.upsert_points(docs).await
+    }
+
+    async fn vector_get(&self, ids: Vec<String>) -> Result<Vec<VectorDocument>, BoxError> {
+        self.get_points(ids).await
+    }
+
+    async fn vector_delete(&self, ids: Vec<String>) -> Result<usize, BoxError> {
+        self.delete_points(ids).await
+    }
+
+    async fn vector_search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> Result<Vec<VectorMatch>, BoxError> {
+        self.search_points(query, top_k, filter).await
+    }
+}
+
+impl VectorStoreFeaturesDyn for QdrantVectorStore {
+    fn upsert(&self, docs: Vec<VectorDocument>) -> BoxPinFut<Result<(), BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.upsert_points(docs).await })
+    }
+
+    fn get(&self, ids: Vec<String>) -> BoxPinFut<Result<Vec<VectorDocument>, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.get_points(ids).await })
+    }
+
+    fn delete(&self, ids: Vec<String>) -> BoxPinFut<Result<usize, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.delete_points(ids).await })
+    }
+
+    fn search(
+        &self,
+        query: Vec<f32>,
+        top_k: usize,
+        filter: Option<VectorFilter>,
+    ) -> BoxPinFut<Result<Vec<VectorMatch>, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move { this.search_points(query, top_k, filter).await })
+    }
+}
+
+/// Derives a stable UUID point ID from the document ID, Qdrant only accepts integers and UUIDs.
+fn point_id(id: &str) -> String {
+    let hash = sha3_256(id.as_bytes());
+    let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Converts a metadata filter to a Qdrant filter condition on the `meta` payload.
+fn qdrant_condition(filter: &VectorFilter) -> Value {
+    let field = |key: &str| format!("meta.{}", key);
+    let range =
+        |key: &str, op: &str, value: f64| json!({"key": field(key), "range": { op: value }});
+    match filter {
+        VectorFilter::Eq { key, value } => json!({"key": field(key), "match": {"value": value}}),
+        VectorFilter::Ne { key, value } => {
+            json!({"must_not": [{"key": field(key), "match": {"value": value}}]})
+        }
+        VectorFilter::In { key, values } => json!({"key": field(key), "match": {"any": values}}),
+        VectorFilter::Gt { key, value } => range(key, "gt", *value),
+        VectorFilter::Gte { key, value } => range(key, "gte", *value),
+        VectorFilter::Lt { key, value } => range(key, "lt", *value),
+        VectorFilter::Lte { key, value } => range(key, "lte", *value),
+        VectorFilter::Exists { key } => json!({"must_not": [{"is_empty": {"key": field(key)}}]}),
+        VectorFilter::And(filters) => {
+            json!({"must": filters.iter().map(qdrant_condition).collect::<Vec<_>>()})
+        }
+        VectorFilter::Or(filters) => {
+            json!({"should": filters.iter().map(qdrant_condition).collect::<Vec<_>>()})
+        }
+        VectorFilter::Not(filter) => json!({"must_not": [qdrant_condition(filter)]}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_point_id() {
+        let id = point_id("doc-1");
+        assert_eq!(id.len(), 36);
+        assert_eq!(id, point_id("doc-1"));
+        assert_ne!(id, point_id("doc-2"));
+        assert_eq!(
+            id.split('-').map(|s| s.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+    }
+
+    #[test]
+    fn test_qdrant_condition() {
+        let filter = VectorFilter::And(vec![
+            VectorFilter::Eq {
+                key: "lang".into(),
+                value: json!("en"),
+            },
+            VectorFilter::Not(Box::new(VectorFilter::Gte {
+                key: "year".into(),
+                value: 2024.0,
+            })),
+        ]);
+        assert_eq!(
+            qdrant_condition(&filter),
+            json!({"must": [
+                {"key": "meta.lang", "match": {"value": "en"}},
+                {"must_not": [{"key": "meta.year", "range": {"gte": 2024.0}}]},
+            ]})
+        );
+        assert_eq!(
+            qdrant_condition(&VectorFilter::Exists { key: "tag".into() }),
+            json!({"must_not": [{"is_empty": {"key": "meta.tag"}}]})
+        );
+    }
+}
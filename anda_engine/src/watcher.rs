@@ -0,0 +1,670 @@
+//! Canister event watcher.
+//!
+//! The Internet Computer has no push notifications for canister events, so agents that
+//! need to react to on-chain activity must poll. This module provides a [`CanisterWatcher`]
+//! that periodically polls configured [`WatchSource`]s and dispatches new events to a
+//! [`WatchHandler`], e.g. [`AgentRunHandler`] which triggers an agent run on the engine.
+//!
+//! Supported sources:
+//! - [`WatchSource::Icrc3`]: ICRC-3 transaction logs via `icrc3_get_blocks`;
+//! - [`WatchSource::Query`]: Any canister query method following the cursor convention
+//!   `(opt nat64, nat64) -> (vec WatchRecord)`.
+//!
+//! # Example
+//! ```rust,ignore
+//! let watcher = CanisterWatcher::new(Arc::new(ctx), Arc::new(AgentRunHandler::new(engine, "watcher_agent".to_string())))
+//!     .with_interval(Duration::from_secs(10))
+//!     .watch(WatchSource::Icrc3 { canister: ledger_id, batch_size: 100 });
+//! let handle = watcher.spawn(cancellation_token);
+//! ```
+
+use anda_core::{AgentInput, BoxError, CanisterCaller, Value};
+use async_trait::async_trait;
+use candid::{CandidType, Nat, Principal};
+use icrc_ledger_types::{
+    icrc::generic_value::ICRC3Value,
+    icrc3::blocks::{GetBlocksRequest, GetBlocksResult},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::engine::Engine;
+
+/// Default polling interval.
+pub static DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A source of canister events to poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchSource {
+    /// ICRC-3 compatible transaction log, polled via `icrc3_get_blocks`.
+    Icrc3 {
+        canister: Principal,
+        /// Maximum number of blocks to fetch per poll.
+        batch_size: u64,
+    },
+    /// A canister query method following the cursor convention:
+    /// `(start: opt nat64, limit: nat64) -> (vec WatchRecord)`.
+    /// When `start` is null, the method should return the latest records.
+    Query {
+        canister: Principal,
+        method: String,
+        /// Maximum number of records to fetch per poll.
+        batch_size: u64,
+    },
+}
+
+impl WatchSource {
+    /// Returns the unique key of the source, used to track the polling cursor.
+    pub fn key(&self) -> String {
+        match self {
+            Self::Icrc3 { canister, .. } => format!("{}:icrc3_get_blocks", canister.to_text()),
+            Self::Query {
+                canister, method, ..
+            } => format!("{}:{}", canister.to_text(), method),
+        }
+    }
+
+    /// Returns the canister being watched.
+    pub fn canister(&self) -> &Principal {
+        match self {
+            Self::Icrc3 { canister, .. } => canister,
+            Self::Query { canister, .. } => canister,
+        }
+    }
+}
+
+/// A record returned by a [`WatchSource::Query`] method.
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct WatchRecord {
+    /// Monotonically increasing index of the record.
+    pub index: u64,
+    /// The record payload, usually a JSON string.
+    pub data: String,
+}
+
+/// A new event observed on a canister.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct WatchEvent {
+    /// The source key, see [`WatchSource::key`].
+    pub source: String,
+    /// The event index (block index for ICRC-3).
+    pub index: u64,
+    /// The event payload.
+    pub data: Value,
+}
+
+/// Handler for new canister events.
+#[async_trait]
+pub trait WatchHandler: Send + Sync {
+    /// Called with the new events of a source, ordered by index.
+    async fn on_events(
+        &self,
+        source: &WatchSource,
+        events: Vec<WatchEvent>,
+    ) -> Result<(), BoxError>;
+}
+
+/// A [`WatchHandler`] that runs an agent on the engine with the new events as prompt.
+pub struct AgentRunHandler {
+    engine: Engine,
+    agent: String,
+    caller: Principal,
+}
+
+impl AgentRunHandler {
+    /// Creates a handler that runs the given agent as the engine itself.
+    pub fn new(engine: Engine, agent: String) -> Self {
+        let caller = engine.id();
+        Self {
+            engine,
+            agent,
+            caller,
+        }
+    }
+
+    /// Sets the caller principal used to run the agent.
+    pub fn with_caller(mut self, caller: Principal) -> Self {
+        self.caller = caller;
+        self
+    }
+}
+
+#[async_trait]
+impl WatchHandler for AgentRunHandler {
+    async fn on_events(
+        &self,
+        source: &WatchSource,
+        events: Vec<WatchEvent>,
+    ) -> Result<(), BoxError> {
+        let prompt = serde_json::to_string(&json!({
+            "canister": source.canister().to_text(),
+            "source": source.key(),
+            "events": events,
+        }))?;
+        let output = self
+            .engine
+            .agent_run(
+                self.caller,
+                AgentInput::new(
+                    self.agent.clone(),
+                    format!("New canister events:\n{prompt}"),
+                ),
+            )
+            .await?;
+        if let Some(reason) = output.failed_reason {
+            return Err(format!("agent {} failed: {}", self.agent, reason).into());
+        }
+        Ok(())
+    }
+}
+
+/// Polls canister sources periodically and dispatches new events to a handler.
+pub struct CanisterWatcher<C: CanisterCaller + Send + Sync + 'static> {
+    caller: Arc<C>,
+    handler: Arc<dyn WatchHandler>,
+    interval: Duration,
+    sources: Vec<WatchSource>,
+    cursors: BTreeMap<String, u64>,
+}
+
+impl<C> CanisterWatcher<C>
+where
+    C: CanisterCaller + Send + Sync + 'static,
+{
+    /// Creates a new watcher with the given canister caller and event handler.
+    pub fn new(caller: Arc<C>, handler: Arc<dyn WatchHandler>) -> Self {
+        Self {
+            caller,
+            handler,
+            interval: DEFAULT_WATCH_INTERVAL,
+            sources: Vec::new(),
+            cursors: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the polling interval.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Adds a source to watch.
+    pub fn watch(mut self, source: WatchSource) -> Self {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+        self
+    }
+
+    /// Sets the start cursor of a source, the next poll will fetch events from it (include).
+    /// Without a cursor, the watcher starts from the latest event at the first poll.
+    pub fn with_cursor(mut self, source: &WatchSource, cursor: u64) -> Self {
+        self.cursors.insert(source.key(), cursor);
+        self
+    }
+
+    /// Returns the current cursors of the sources.
+    pub fn cursors(&self) -> &BTreeMap<String, u64> {
+        &self.cursors
+    }
+
+    /// Polls all sources once, dispatches new events and returns the number of events.
+    /// The sources are polled independently: a failing source is logged and retried at the
+    /// next poll without holding back the others. Returns an error if every source failed.
+    pub async fn poll_once(&mut self) -> Result<usize, BoxError> {
+        let mut total = 0;
+        let mut errors = Vec::new();
+        for source in self.sources.clone() {
+            match self.poll_source(&source).await {
+                Ok(n) => total += n,
+                Err(err) => {
+                    log::warn!("canister watcher: polling {} failed: {}", source.key(), err);
+                    errors.push(format!("{}: {}", source.key(), err));
+                }
+            }
+        }
+        if !errors.is_empty() && errors.len() == self.sources.len() {
+            return Err(errors.join("; ").into());
+        }
+        Ok(total)
+    }
+
+    async fn poll_source(&mut self, source: &WatchSource) -> Result<usize, BoxError> {
+        let key = source.key();
+        let cursor = self.cursors.get(&key).cloned();
+        let (events, next) = match source {
+            WatchSource::Icrc3 {
+                canister,
+                batch_size,
+            } => poll_icrc3(self.caller.as_ref(), canister, &key, cursor, *batch_size).await?,
+            WatchSource::Query {
+                canister,
+                method,
+                batch_size,
+            } => {
+                poll_query(
+                    self.caller.as_ref(),
+                    canister,
+                    method,
+                    &key,
+                    cursor,
+                    *batch_size,
+                )
+                .await?
+            }
+        };
+
+        let n = events.len();
+        if n > 0 {
+            self.handler.on_events(source, events).await?;
+        }
+        // advance the cursor only after the events were handled
+        self.cursors.insert(key, next);
+        Ok(n)
+    }
+
+    /// Runs the polling loop until the cancellation token is triggered.
+    pub async fn run(mut self, cancellation_token: CancellationToken) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    return;
+                }
+                _ = interval.tick() => {
+                    // the failures are logged by source
+                    let _ = self.poll_once().await;
+                }
+            }
+        }
+    }
+
+    /// Spawns the polling loop on the tokio runtime.
+    pub fn spawn(self, cancellation_token: CancellationToken) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.run(cancellation_token))
+    }
+}
+
+async fn poll_icrc3<C: CanisterCaller>(
+    caller: &C,
+    canister: &Principal,
+    key: &str,
+    cursor: Option<u64>,
+    batch_size: u64,
+) -> Result<(Vec<WatchEvent>, u64), BoxError> {
+    let start = match cursor {
+        Some(start) => start,
+        None => {
+            // start from the latest block
+            let res: GetBlocksResult = caller
+                .canister_query(
+                    canister,
+                    "icrc3_get_blocks",
+                    (vec![GetBlocksRequest {
+                        start: Nat::from(0u64),
+                        length: Nat::from(0u64),
+                    }],),
+                )
+                .await?;
+            return Ok((Vec::new(), nat_to_u64(&res.log_length)?));
+        }
+    };
+
+    let res: GetBlocksResult = caller
+        .canister_query(
+            canister,
+            "icrc3_get_blocks",
+            (vec![GetBlocksRequest {
+                start: Nat::from(start),
+                length: Nat::from(batch_size),
+            }],),
+        )
+        .await?;
+
+    // the blocks moved to archive canisters since the cursor precede those of the ledger
+    let mut blocks = res.blocks;
+    for archived in res.archived_blocks {
+        let res: GetBlocksResult = caller
+            .canister_query(
+                &archived.callback.canister_id,
+                &archived.callback.method,
+                (archived.args,),
+            )
+            .await
+            .map_err(|err| {
+                format!(
+                    "failed to query archive {}: {}",
+                    archived.callback.canister_id.to_text(),
+                    err
+                )
+            })?;
+        blocks.extend(res.blocks);
+    }
+
+    let mut indexed = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        indexed.push((nat_to_u64(&block.id)?, block.block));
+    }
+    indexed.sort_by_key(|(index, _)| *index);
+
+    // only the blocks following the cursor without a gap, the rest is fetched at the next poll
+    let mut next = start;
+    let mut events = Vec::with_capacity(indexed.len());
+    for (index, block) in indexed {
+        if index < next {
+            continue;
+        }
+        if index > next {
+            break;
+        }
+        next = index + 1;
+        events.push(WatchEvent {
+            source: key.to_string(),
+            index,
+            data: icrc3_value_to_json(block),
+        });
+    }
+    Ok((events, next))
+}
+
+async fn poll_query<C: CanisterCaller>(
+    caller: &C,
+    canister: &Principal,
+    method: &str,
+    key: &str,
+    cursor: Option<u64>,
+    batch_size: u64,
+) -> Result<(Vec<WatchEvent>, u64), BoxError> {
+    let mut records: Vec<WatchRecord> = caller
+        .canister_query(canister, method, (cursor, batch_size))
+        .await?;
+    records.sort_by_key(|r| r.index);
+
+    let start = match cursor {
+        Some(start) => start,
+        None => {
+            // start from the latest record
+            let next = records.last().map(|r| r.index + 1).unwrap_or_default();
+            return Ok((Vec::new(), next));
+        }
+    };
+
+    let mut next = start;
+    let mut events = Vec::with_capacity(records.len());
+    for record in records {
+        if record.index < start {
+            continue;
+        }
+        next = next.max(record.index + 1);
+        events.push(WatchEvent {
+            source: key.to_string(),
+            index: record.index,
+            data: serde_json::from_str(&record.data).unwrap_or(Value::String(record.data)),
+        });
+    }
+    Ok((events, next))
+}
+
+fn nat_to_u64(n: &Nat) -> Result<u64, BoxError> {
+    n.0.to_string()
+        .parse::<u64>()
+        .map_err(|err| format!("invalid block index {}: {}", n, err).into())
+}
+
+/// Converts an ICRC-3 value to JSON. Numbers are converted to strings to avoid precision loss,
+/// blobs are hex encoded.
+pub fn icrc3_value_to_json(val: ICRC3Value) -> Value {
+    match val {
+        ICRC3Value::Blob(b) => Value::String(b.iter().map(|v| format!("{:02x}", v)).collect()),
+        ICRC3Value::Text(t) => Value::String(t),
+        ICRC3Value::Nat(n) => Value::String(n.0.to_string()),
+        ICRC3Value::Int(i) => Value::String(i.0.to_string()),
+        ICRC3Value::Array(arr) => Value::Array(arr.into_iter().map(icrc3_value_to_json).collect()),
+        ICRC3Value::Map(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| (k, icrc3_value_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::mock::MockCanisterCaller;
+    use candid::{Decode, encode_args};
+    use icrc_ledger_types::icrc3::{
+        archive::QueryArchiveFn,
+        blocks::{ArchivedBlocks, BlockWithId},
+    };
+    use tokio::sync::Mutex;
+
+    struct CollectHandler {
+        events: Mutex<Vec<WatchEvent>>,
+    }
+
+    #[async_trait]
+    impl WatchHandler for CollectHandler {
+        async fn on_events(
+            &self,
+            _source: &WatchSource,
+            events: Vec<WatchEvent>,
+        ) -> Result<(), BoxError> {
+            self.events.lock().await.extend(events);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icrc3_watcher() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let caller = MockCanisterCaller::new(|_canister, method, args| {
+            assert_eq!(method, "icrc3_get_blocks");
+            let req = Decode!(args.as_slice(), Vec<GetBlocksRequest>).unwrap();
+            let start = nat_to_u64(&req[0].start).unwrap();
+            let length = nat_to_u64(&req[0].length).unwrap();
+            let log_length = 12u64;
+            let blocks = (start..log_length.min(start + length))
+                .map(|i| BlockWithId {
+                    id: Nat::from(i),
+                    block: ICRC3Value::Map(BTreeMap::from([(
+                        "amt".to_string(),
+                        ICRC3Value::Nat(Nat::from(i * 100)),
+                    )])),
+                })
+                .collect();
+            encode_args((GetBlocksResult {
+                log_length: Nat::from(log_length),
+                blocks,
+                archived_blocks: vec![],
+            },))
+            .unwrap()
+        });
+
+        let handler = Arc::new(CollectHandler {
+            events: Mutex::new(Vec::new()),
+        });
+        let source = WatchSource::Icrc3 {
+            canister: ledger,
+            batch_size: 2,
+        };
+        let mut watcher =
+            CanisterWatcher::new(Arc::new(caller), handler.clone()).watch(source.clone());
+
+        // the first poll only syncs the cursor
+        assert_eq!(watcher.poll_once().await.unwrap(), 0);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&12));
+
+        let mut watcher = watcher.with_cursor(&source, 9);
+        assert_eq!(watcher.poll_once().await.unwrap(), 2);
+        assert_eq!(watcher.poll_once().await.unwrap(), 1);
+        assert_eq!(watcher.poll_once().await.unwrap(), 0);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&12));
+
+        let events = handler.events.lock().await;
+        assert_eq!(
+            events.iter().map(|e| e.index).collect::<Vec<_>>(),
+            vec![9, 10, 11]
+        );
+        assert_eq!(events[0].data, json!({"amt": "900"}));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_query_watcher() {
+        let canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let caller = MockCanisterCaller::new(|_canister, method, args| {
+            assert_eq!(method, "get_events");
+            let (start, limit) = Decode!(args.as_slice(), Option<u64>, u64).unwrap();
+            let start = start.unwrap_or(3);
+            let records: Vec<WatchRecord> = (start..5u64.min(start + limit))
+                .map(|i| WatchRecord {
+                    index: i,
+                    data: format!("{{\"id\":{}}}", i),
+                })
+                .collect();
+            encode_args((records,)).unwrap()
+        });
+
+        let handler = Arc::new(CollectHandler {
+            events: Mutex::new(Vec::new()),
+        });
+        let source = WatchSource::Query {
+            canister,
+            method: "get_events".to_string(),
+            batch_size: 10,
+        };
+        let mut watcher = CanisterWatcher::new(Arc::new(caller), handler.clone())
+            .watch(source.clone())
+            .with_cursor(&source, 1);
+
+        assert_eq!(watcher.poll_once().await.unwrap(), 4);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&5));
+        let events = handler.events.lock().await;
+        assert_eq!(events[0].data, json!({"id": 1}));
+    }
+
+    fn amount_block(i: u64) -> BlockWithId {
+        BlockWithId {
+            id: Nat::from(i),
+            block: ICRC3Value::Map(BTreeMap::from([(
+                "amt".to_string(),
+                ICRC3Value::Nat(Nat::from(i * 100)),
+            )])),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_icrc3_archived_blocks() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let archive = Principal::from_text("qjdve-lqaaa-aaaaa-aaaeq-cai").unwrap();
+        // the blocks before 6 are archived, the archive returns up to 2 blocks per call
+        let caller = MockCanisterCaller::new(move |canister, method, args| {
+            let req = Decode!(args.as_slice(), Vec<GetBlocksRequest>).unwrap();
+            let start = nat_to_u64(&req[0].start).unwrap();
+            let end = start + nat_to_u64(&req[0].length).unwrap();
+            if canister == &archive {
+                assert_eq!(method, "get_blocks");
+                return encode_args((GetBlocksResult {
+                    log_length: Nat::from(6u64),
+                    blocks: (start..end.min(start + 2).min(6))
+                        .map(amount_block)
+                        .collect(),
+                    archived_blocks: vec![],
+                },))
+                .unwrap();
+            }
+
+            assert_eq!(method, "icrc3_get_blocks");
+            let mut archived_blocks = Vec::new();
+            if start < 6 {
+                archived_blocks.push(ArchivedBlocks {
+                    args: vec![GetBlocksRequest {
+                        start: Nat::from(start),
+                        length: Nat::from(end.min(6) - start),
+                    }],
+                    callback: QueryArchiveFn::new(archive, "get_blocks"),
+                });
+            }
+            encode_args((GetBlocksResult {
+                log_length: Nat::from(12u64),
+                blocks: (start.max(6)..end.min(12)).map(amount_block).collect(),
+                archived_blocks,
+            },))
+            .unwrap()
+        });
+
+        let handler = Arc::new(CollectHandler {
+            events: Mutex::new(Vec::new()),
+        });
+        let source = WatchSource::Icrc3 {
+            canister: ledger,
+            batch_size: 5,
+        };
+        let mut watcher = CanisterWatcher::new(Arc::new(caller), handler.clone())
+            .watch(source.clone())
+            .with_cursor(&source, 3);
+
+        // the ledger blocks after the gap left by the archive are fetched again
+        assert_eq!(watcher.poll_once().await.unwrap(), 2);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&5));
+        assert_eq!(watcher.poll_once().await.unwrap(), 5);
+        assert_eq!(watcher.poll_once().await.unwrap(), 2);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&12));
+
+        let events = handler.events.lock().await;
+        assert_eq!(
+            events.iter().map(|e| e.index).collect::<Vec<_>>(),
+            (3..12).collect::<Vec<_>>()
+        );
+        assert_eq!(events[0].data, json!({"amt": "300"}));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_failing_source() {
+        let canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let caller = Arc::new(MockCanisterCaller::new(|_canister, method, args| {
+            if method == "broken" {
+                return b"not candid".to_vec();
+            }
+            let (start, limit) = Decode!(args.as_slice(), Option<u64>, u64).unwrap();
+            let start = start.unwrap_or(0);
+            let records: Vec<WatchRecord> = (start..5u64.min(start + limit))
+                .map(|i| WatchRecord {
+                    index: i,
+                    data: i.to_string(),
+                })
+                .collect();
+            encode_args((records,)).unwrap()
+        }));
+
+        let handler = Arc::new(CollectHandler {
+            events: Mutex::new(Vec::new()),
+        });
+        let broken = WatchSource::Query {
+            canister,
+            method: "broken".to_string(),
+            batch_size: 10,
+        };
+        let source = WatchSource::Query {
+            canister,
+            method: "get_events".to_string(),
+            batch_size: 10,
+        };
+        let mut watcher = CanisterWatcher::new(caller.clone(), handler.clone())
+            .watch(broken.clone())
+            .watch(source.clone())
+            .with_cursor(&broken, 0)
+            .with_cursor(&source, 0);
+
+        // the failing source doesn't hold back the others
+        assert_eq!(watcher.poll_once().await.unwrap(), 5);
+        assert_eq!(watcher.cursors().get(&source.key()), Some(&5));
+        assert_eq!(watcher.cursors().get(&broken.key()), Some(&0));
+
+        let mut watcher = CanisterWatcher::new(caller, handler).watch(broken);
+        assert!(watcher.poll_once().await.is_err());
+    }
+}
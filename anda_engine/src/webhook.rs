@@ -0,0 +1,202 @@
+//! Outbound webhook notifications of background jobs.
+//!
+//! When a job succeeds or fails for good, the engine POSTs a [`WebhookEvent`] in JSON, with
+//! the job and the output of its agent run or tool call, to the webhook of the job if it was
+//! spawned with one, or else to the webhook registered by the job's caller. Requests are
+//! signed by the engine like its other outbound signed calls, the receiver authenticates the
+//! engine by verifying the signed envelope of the request against the SHA3-256 of the body.
+//! Webhook URLs are subject to the engine's HTTP policy.
+
+use anda_core::{BoxError, HttpFeatures, Path, PutMode};
+use candid::Principal;
+use ic_cose_types::{cose::sha3_256, to_cbor_bytes};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::RwLock, time::Duration};
+use tokio::sync::Mutex;
+
+use crate::{
+    context::BaseCtx,
+    jobs::{JobInfo, JobStatus},
+    store::{Store, is_not_found},
+};
+
+/// The store namespace of the webhooks.
+pub static WEBHOOKS_PATH: &str = "_webhooks";
+
+/// Number of delivery attempts of a notification.
+const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+
+/// The payload of a webhook notification.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookEvent {
+    /// "job.succeeded" or "job.failed".
+    pub event: String,
+    pub engine: Principal,
+    pub job: JobInfo,
+}
+
+impl WebhookEvent {
+    /// Returns the event of a finished job, None if the job is not succeeded or failed.
+    pub fn of(engine: Principal, job: JobInfo) -> Option<Self> {
+        let event = match job.status {
+            JobStatus::Succeeded => "job.succeeded",
+            JobStatus::Failed => "job.failed",
+            _ => return None,
+        };
+        Some(Self {
+            event: event.to_string(),
+            engine,
+            job,
+        })
+    }
+}
+
+/// Checks that a webhook URL is an HTTPS URL.
+pub fn validate_webhook_url(url: &str) -> Result<(), BoxError> {
+    match url::Url::parse(url) {
+        Ok(u) if u.scheme() == "https" && u.host_str().is_some() => Ok(()),
+        _ => Err(format!("invalid webhook URL {url:?}, expected an HTTPS URL").into()),
+    }
+}
+
+fn webhooks_path() -> Path {
+    Path::from("webhooks")
+}
+
+/// The webhooks registered by the callers of an engine.
+pub struct Webhooks {
+    store: Store,
+    namespace: Path,
+    hooks: RwLock<BTreeMap<Principal, String>>,
+    // serializes the updates and their persistence
+    update: Mutex<()>,
+}
+
+impl Webhooks {
+    /// Opens the webhooks persisted in the store.
+    pub async fn open(store: Store) -> Result<Self, BoxError> {
+        let namespace = Path::from(WEBHOOKS_PATH);
+        let hooks: BTreeMap<Principal, String> =
+            match store.store_get(&namespace, &webhooks_path()).await {
+                Ok((data, _)) => ciborium::from_reader(&data[..])?,
+                Err(err) if is_not_found(&err) => BTreeMap::new(),
+                Err(err) => return Err(err),
+            };
+
+        Ok(Self {
+            store,
+            namespace,
+            hooks: RwLock::new(hooks),
+            update: Mutex::new(()),
+        })
+    }
+
+    /// Returns the webhook of the caller.
+    pub fn get(&self, caller: &Principal) -> Option<String> {
+        self.hooks
+            .read()
+            .expect("webhooks lock poisoned")
+            .get(caller)
+            .cloned()
+    }
+
+    /// Registers the webhook of the caller, or removes it if None.
+    pub async fn set(&self, caller: Principal, url: Option<String>) -> Result<(), BoxError> {
+        if let Some(url) = &url {
+            validate_webhook_url(url)?;
+        }
+
+        let _guard = self.update.lock().await;
+        let mut hooks = self.hooks.read().expect("webhooks lock poisoned").clone();
+        match url {
+            Some(url) => hooks.insert(caller, url),
+            None => hooks.remove(&caller),
+        };
+        self.store
+            .store_put(
+                &self.namespace,
+                &webhooks_path(),
+                PutMode::Overwrite,
+                to_cbor_bytes(&hooks).into(),
+            )
+            .await?;
+        *self.hooks.write().expect("webhooks lock poisoned") = hooks;
+        Ok(())
+    }
+}
+
+/// POSTs the signed event to the webhook, retrying failed deliveries with a backoff.
+pub(crate) async fn deliver(
+    ctx: &BaseCtx,
+    url: &str,
+    event: &WebhookEvent,
+) -> Result<(), BoxError> {
+    let body = serde_json::to_vec(event)?;
+    let digest = sha3_256(&body);
+    let mut headers = http::HeaderMap::new();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/json"),
+    );
+
+    let mut attempt = 1;
+    loop {
+        let res = ctx
+            .https_signed_call(
+                url,
+                http::Method::POST,
+                digest,
+                Some(headers.clone()),
+                Some(body.clone()),
+            )
+            .await;
+        let err: BoxError = match res {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => format!("webhook responded {}", res.status()).into(),
+            Err(err) => err,
+        };
+        if attempt >= MAX_DELIVERY_ATTEMPTS || ctx.cancellation_token.is_cancelled() {
+            return Err(err);
+        }
+        tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_webhooks() {
+        assert!(validate_webhook_url("https://example.com/hook").is_ok());
+        assert!(validate_webhook_url("http://example.com/hook").is_err());
+        assert!(validate_webhook_url("example.com").is_err());
+
+        let store = Store::new(Arc::new(InMemory::new()));
+        let caller = Principal::from_slice(&[1]);
+        let hooks = Webhooks::open(store.clone()).await.unwrap();
+        assert!(hooks.get(&caller).is_none());
+        assert!(
+            hooks
+                .set(caller, Some("ftp://example.com".to_string()))
+                .await
+                .is_err()
+        );
+        hooks
+            .set(caller, Some("https://example.com/hook".to_string()))
+            .await
+            .unwrap();
+
+        // reopened from the store
+        let hooks = Webhooks::open(store).await.unwrap();
+        assert_eq!(
+            hooks.get(&caller).as_deref(),
+            Some("https://example.com/hook")
+        );
+        hooks.set(caller, None).await.unwrap();
+        assert!(hooks.get(&caller).is_none());
+    }
+}
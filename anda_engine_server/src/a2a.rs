@@ -0,0 +1,490 @@
+//! Agent2Agent (A2A) protocol server.
+//!
+//! Serves each engine as an A2A agent, so that agents of other frameworks can delegate to it:
+//! - `GET /a2a/{id}/.well-known/agent.json`: the A2A agent card, the exported agents as skills;
+//! - `POST /a2a/{id}`: JSON-RPC `tasks/send`, `tasks/sendSubscribe`, `tasks/get`
+//!   and `tasks/cancel`.
+//!
+//! A task runs the agent named by `{"agent": "..."}` in its metadata, or the default agent.
+//! The sessions of a caller map to the threads of the engine, so that the tasks of a session
+//! continue the same conversation. Tasks are kept in memory for an hour after they end.
+
+use anda_core::{AgentEvent, AgentInput, RequestMeta, Xid};
+use anda_engine::{
+    a2a::{
+        A2aAgentCard, A2aCapabilities, A2aMessage, A2aSkill, Artifact, INVALID_PARAMS,
+        INVALID_REQUEST, JsonRpcRequest, JsonRpcResponse, METHOD_NOT_FOUND, TASK_NOT_CANCELABLE,
+        TASK_NOT_FOUND, Task, TaskArtifactUpdateEvent, TaskQueryParams, TaskSendParams, TaskState,
+        TaskStatus, TaskStatusUpdateEvent, TaskUpdateEvent,
+    },
+    engine::Engine,
+    secrets::redact,
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use candid::Principal;
+use ic_auth_verifier::envelope::unix_ms;
+use ic_tee_agent::http::ContentWithSHA3;
+use serde_json::Value;
+use std::{collections::BTreeMap, convert::Infallible, sync::RwLock};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    APP_VERSION,
+    handler::{Admission, AppState, public_url, request_caller},
+    rate_limit::with_rate_limit_headers,
+};
+
+/// How long the tasks are kept after they end.
+const TASK_TTL_MS: u64 = 3600 * 1000;
+
+/// Tasks and sessions are scoped by engine and caller.
+type TaskKey = (Principal, Principal, String);
+
+struct TaskEntry {
+    task: Task,
+    token: CancellationToken,
+    updated_at: u64,
+}
+
+/// The A2A tasks of the server, and the threads of the sessions.
+#[derive(Default)]
+pub(crate) struct A2aTasks {
+    tasks: RwLock<BTreeMap<TaskKey, TaskEntry>>,
+    sessions: RwLock<BTreeMap<TaskKey, Xid>>,
+}
+
+impl A2aTasks {
+    fn get(&self, key: &TaskKey) -> Option<Task> {
+        let tasks = self.tasks.read().unwrap();
+        tasks.get(key).map(|e| e.task.clone())
+    }
+
+    /// Starts a new task, or continues a task that ended, e.g. because it required input.
+    fn start(&self, key: TaskKey, task: Task, now_ms: u64) -> Result<CancellationToken, String> {
+        let mut tasks = self.tasks.write().unwrap();
+        tasks.retain(|_, e| !e.task.status.state.is_final() || e.updated_at + TASK_TTL_MS > now_ms);
+        let running = tasks.get(&key).is_some_and(|e| {
+            !e.task.status.state.is_final() && e.task.status.state != TaskState::InputRequired
+        });
+        if running {
+            return Err(format!("task {} is running", key.2));
+        }
+        let token = CancellationToken::new();
+        tasks.insert(
+            key,
+            TaskEntry {
+                task,
+                token: token.clone(),
+                updated_at: now_ms,
+            },
+        );
+        Ok(token)
+    }
+
+    fn update(&self, key: &TaskKey, now_ms: u64, f: impl FnOnce(&mut Task)) {
+        let mut tasks = self.tasks.write().unwrap();
+        if let Some(entry) = tasks.get_mut(key) {
+            f(&mut entry.task);
+            entry.updated_at = now_ms;
+        }
+    }
+
+    fn cancel(&self, key: &TaskKey, now_ms: u64) -> Result<Task, (i64, String)> {
+        let mut tasks = self.tasks.write().unwrap();
+        let entry = tasks
+            .get_mut(key)
+            .ok_or_else(|| (TASK_NOT_FOUND, format!("task {} not found", key.2)))?;
+        if entry.task.status.state.is_final() {
+            return Err((
+                TASK_NOT_CANCELABLE,
+                format!("task {} is {:?}", key.2, entry.task.status.state),
+            ));
+        }
+        entry.token.cancel();
+        entry.task.status = TaskStatus::new(TaskState::Canceled);
+        entry.updated_at = now_ms;
+        Ok(entry.task.clone())
+    }
+
+    fn thread(&self, key: &TaskKey) -> Option<Xid> {
+        self.sessions.read().unwrap().get(key).cloned()
+    }
+
+    fn set_thread(&self, key: TaskKey, thread: Xid) {
+        self.sessions.write().unwrap().insert(key, thread);
+    }
+}
+
+fn rpc_response(res: JsonRpcResponse) -> Response {
+    Json(res).into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_engine_id(app: &AppState, id: &str) -> Result<Principal, Response> {
+    if id == "default" {
+        return Ok(app.default_engine);
+    }
+    Principal::from_text(id).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid engine id: {id:?}"),
+        )
+            .into_response()
+    })
+}
+
+/// GET /a2a/{id}/.well-known/agent.json
+pub async fn get_a2a_agent_card(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let id = match parse_engine_id(&app, &id) {
+        Ok(id) => id,
+        Err(res) => return res,
+    };
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("engine {} not found", id.to_text()),
+            )
+                .into_response();
+        }
+    };
+
+    let info = engine.information();
+    Json(A2aAgentCard {
+        name: info.name,
+        description: info.description,
+        url: format!("{}/a2a/{}", public_url(&app, &headers), id.to_text()),
+        version: APP_VERSION.to_string(),
+        capabilities: A2aCapabilities {
+            streaming: true,
+            push_notifications: false,
+            state_transition_history: false,
+        },
+        default_input_modes: vec!["text".to_string(), "file".to_string()],
+        default_output_modes: vec!["text".to_string(), "file".to_string()],
+        skills: info
+            .agents
+            .into_iter()
+            .map(|agent| A2aSkill {
+                id: agent.definition.name.clone(),
+                name: agent.definition.name,
+                description: agent.definition.description,
+                tags: agent.supported_resource_tags,
+            })
+            .collect(),
+    })
+    .into_response()
+}
+
+/// POST /a2a/{id}
+pub async fn a2a_rpc(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Path(id): Path<String>,
+    ct: ContentWithSHA3<JsonRpcRequest>,
+) -> Response {
+    let id = match parse_engine_id(&app, &id) {
+        Ok(id) => id,
+        Err(res) => return res,
+    };
+    let (req, hash) = match ct {
+        ContentWithSHA3::CBOR(req, hash) => (req, hash),
+        ContentWithSHA3::JSON(req, hash) => (req, hash),
+    };
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine.clone(),
+        None => {
+            return rpc_response(JsonRpcResponse::error(
+                req.id,
+                INVALID_REQUEST,
+                format!("engine {} not found", id.to_text()),
+            ));
+        }
+    };
+
+    let caller = request_caller(&app, &headers, Some(id), Some(hash.as_slice()));
+
+    log::info!(
+        method = req.method.as_str(),
+        engine = id.to_text(),
+        caller = caller.to_text();
+        "a2a_rpc",
+    );
+    match req.method.as_str() {
+        "tasks/send" | "tasks/sendSubscribe" => {
+            let params: TaskSendParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => {
+                    return rpc_response(JsonRpcResponse::error(
+                        req.id,
+                        INVALID_PARAMS,
+                        format!("invalid params: {err}"),
+                    ));
+                }
+            };
+            let subscribe = req.method == "tasks/sendSubscribe";
+            send_task(app, engine, caller, req.id, params, subscribe).await
+        }
+        "tasks/get" | "tasks/cancel" => {
+            let params: TaskQueryParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => {
+                    return rpc_response(JsonRpcResponse::error(
+                        req.id,
+                        INVALID_PARAMS,
+                        format!("invalid params: {err}"),
+                    ));
+                }
+            };
+            let key = (id, caller, params.id);
+            let res = if req.method == "tasks/get" {
+                app.a2a
+                    .get(&key)
+                    .ok_or_else(|| (TASK_NOT_FOUND, format!("task {} not found", key.2)))
+            } else {
+                app.a2a.cancel(&key, unix_ms())
+            };
+            rpc_response(match res {
+                Ok(task) => JsonRpcResponse::result(req.id, task),
+                Err((code, message)) => JsonRpcResponse::error(req.id, code, message),
+            })
+        }
+        method => rpc_response(JsonRpcResponse::error(
+            req.id,
+            METHOD_NOT_FOUND,
+            format!("method {method:?} not found"),
+        )),
+    }
+}
+
+/// Runs the task in a background task, and responds with the task when it ends,
+/// or with its updates as server-sent events if `subscribe`.
+async fn send_task(
+    app: AppState,
+    engine: Engine,
+    caller: Principal,
+    rpc_id: Value,
+    params: TaskSendParams,
+    subscribe: bool,
+) -> Response {
+    let id = engine.id();
+    let key = (id, caller, params.id.clone());
+    let session = params.session_id.clone();
+    let thread = session
+        .as_ref()
+        .and_then(|s| app.a2a.thread(&(id, caller, s.clone())));
+    let input = AgentInput {
+        name: match params.agent() {
+            Some(name) if !name.is_empty() => name.to_ascii_lowercase(),
+            _ => engine.default_agent(),
+        },
+        prompt: params.message.text(),
+        resources: params.message.resources(),
+        meta: Some(RequestMeta {
+            engine: Some(id),
+            thread,
+            ..Default::default()
+        }),
+    };
+
+    let Admission { limit, permit } =
+        match app.admit(&caller, &input.name, input.meta.as_ref()).await {
+            Ok(admission) => admission,
+            Err(rejected) => return rejected,
+        };
+    let task = Task {
+        id: params.id.clone(),
+        session_id: session.clone(),
+        status: TaskStatus::new(TaskState::Working),
+        artifacts: None,
+        metadata: None,
+    };
+    let token = match app.a2a.start(key.clone(), task, unix_ms()) {
+        Ok(token) => token,
+        Err(err) => {
+            return rpc_response(JsonRpcResponse::error(rpc_id, INVALID_REQUEST, err));
+        }
+    };
+
+    let (updates, mut rx_updates) = mpsc::unbounded_channel::<TaskUpdateEvent>();
+    let tasks = app.a2a.clone();
+    let task_key = key.clone();
+    tokio::spawn(async move {
+        let _permit = permit;
+        let task_id = task_key.2.clone();
+        let status_update = |status: TaskStatus, is_final: bool| {
+            TaskUpdateEvent::Status(TaskStatusUpdateEvent {
+                id: task_id.clone(),
+                status,
+                is_final,
+                metadata: None,
+            })
+        };
+        let progress = |event: AgentEvent| match event {
+            AgentEvent::Content { content, .. } => Some(status_update(
+                TaskStatus {
+                    state: TaskState::Working,
+                    message: Some(A2aMessage::new("agent", content, None)),
+                    timestamp: None,
+                },
+                false,
+            )),
+            _ => None,
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let run = engine.agent_run_with_events(caller, input, tx);
+        tokio::pin!(run);
+        let res = loop {
+            tokio::select! {
+                res = &mut run => break res,
+                Some(event) = rx.recv() => {
+                    if let Some(update) = progress(event) {
+                        let _ = updates.send(update);
+                    }
+                }
+                _ = token.cancelled() => break Err("task canceled".into()),
+            }
+        };
+        while let Ok(event) = rx.try_recv() {
+            if let Some(update) = progress(event) {
+                let _ = updates.send(update);
+            }
+        }
+
+        let failed = |state: TaskState, reason: String| TaskStatus {
+            state,
+            message: Some(A2aMessage::new("agent", reason, None)),
+            timestamp: None,
+        };
+        let mut session_id = session;
+        let (status, artifact) = match res {
+            Ok(output) => {
+                if let Some(thread) = &output.thread {
+                    let session = session_id.get_or_insert_with(|| thread.to_string());
+                    tasks.set_thread((id, caller, session.clone()), thread.clone());
+                }
+                match &output.failed_reason {
+                    Some(reason) => (failed(TaskState::Failed, reason.clone()), None),
+                    None => (
+                        TaskStatus::new(TaskState::Completed),
+                        Some(Artifact::from(&output)),
+                    ),
+                }
+            }
+            Err(err) if token.is_cancelled() => {
+                (failed(TaskState::Canceled, err.to_string()), None)
+            }
+            Err(err) => (failed(TaskState::Failed, redact(&err.to_string())), None),
+        };
+        tasks.update(&task_key, unix_ms(), |task| {
+            task.session_id = session_id;
+            task.status = status.clone();
+            if let Some(artifact) = &artifact {
+                task.artifacts = Some(vec![artifact.clone()]);
+            }
+        });
+        if let Some(artifact) = artifact {
+            let _ = updates.send(TaskUpdateEvent::Artifact(TaskArtifactUpdateEvent {
+                id: task_key.2.clone(),
+                artifact,
+                metadata: None,
+            }));
+        }
+        let _ = updates.send(status_update(status, true));
+    });
+
+    if !subscribe {
+        // the updates end when the task ends
+        while rx_updates.recv().await.is_some() {}
+        let res = match app.a2a.get(&key) {
+            Some(task) => JsonRpcResponse::result(rpc_id, task),
+            None => {
+                JsonRpcResponse::error(rpc_id, TASK_NOT_FOUND, format!("task {} not found", key.2))
+            }
+        };
+        return with_rate_limit_headers(limit.as_ref(), rpc_response(res));
+    }
+
+    let events = futures::stream::unfold(
+        (rx_updates, rpc_id),
+        |(mut rx_updates, rpc_id)| async move {
+            let update = rx_updates.recv().await?;
+            let res = JsonRpcResponse::result(rpc_id.clone(), update);
+            let data = serde_json::to_string(&res).unwrap_or_default();
+            Some((
+                Ok::<Event, Infallible>(Event::default().data(data)),
+                (rx_updates, rpc_id),
+            ))
+        },
+    );
+    with_rate_limit_headers(
+        limit.as_ref(),
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a2a_tasks() {
+        let tasks = A2aTasks::default();
+        let engine = Principal::from_slice(&[1]);
+        let caller = Principal::from_slice(&[2]);
+        let key = (engine, caller, "t1".to_string());
+        let task = Task {
+            id: "t1".to_string(),
+            session_id: None,
+            status: TaskStatus::new(TaskState::Working),
+            artifacts: None,
+            metadata: None,
+        };
+
+        let token = tasks.start(key.clone(), task.clone(), 1000).unwrap();
+        assert!(tasks.start(key.clone(), task.clone(), 1000).is_err());
+        // tasks are scoped by caller
+        assert!(tasks.get(&(engine, engine, "t1".to_string())).is_none());
+
+        let canceled = tasks.cancel(&key, 2000).unwrap();
+        assert_eq!(canceled.status.state, TaskState::Canceled);
+        assert!(token.is_cancelled());
+        assert_eq!(tasks.cancel(&key, 2000).unwrap_err().0, TASK_NOT_CANCELABLE);
+
+        // a task that ended can be sent again
+        tasks.start(key.clone(), task.clone(), 3000).unwrap();
+        tasks.update(&key, 3000, |t| {
+            t.status = TaskStatus::new(TaskState::Completed)
+        });
+        assert_eq!(tasks.get(&key).unwrap().status.state, TaskState::Completed);
+
+        // ended tasks expire
+        let key2 = (engine, caller, "t2".to_string());
+        tasks.start(key2, task, 3000 + TASK_TTL_MS).unwrap();
+        assert!(tasks.get(&key).is_none());
+
+        let thread = Xid::new();
+        tasks.set_thread((engine, caller, "s1".to_string()), thread.clone());
+        assert_eq!(
+            tasks.thread(&(engine, caller, "s1".to_string())),
+            Some(thread)
+        );
+    }
+}
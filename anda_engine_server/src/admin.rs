@@ -0,0 +1,718 @@
+//! Admin API for introspection and management of the engines.
+//!
+//! Requests are authenticated with a signed envelope in the `Authorization` header (or the
+//! envelope headers), targeting the engine and signing the digest of the request from
+//! [`admin_request_digest`], so an envelope can't be replayed on another route, query or body.
+//! The signer must be the controller or a manager of the engine. Actions are recorded in the engine's audit log when it is enabled.
+//! API keys can't be used for the admin API.
+
+use anda_core::Function;
+use anda_engine::{
+    audit::{AuditEntry, AuditVerification},
+    engine::{
+        ApiKeyInfo, BillingRecord, CacheStats, CollectionInfo, Engine, EngineStats, FeatureFlag,
+        HealthCheck, Information, IngestDocument, IngestReport, JobInfo, KnowledgeDocumentInfo,
+        KnowledgeScope, RunInfo, SnapshotReport, TenantInfo,
+    },
+    secrets::redact,
+};
+use axum::{
+    Extension, Json,
+    body::{Body, to_bytes},
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use candid::Principal;
+use ic_auth_verifier::envelope::{SignedEnvelope, unix_ms};
+use ic_cose_types::cose::sha3_256_n;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::handler::AppState;
+
+/// Remote engines of an engine with their health.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminRemoteEngines {
+    pub engines: BTreeMap<String, Information>,
+    pub health: BTreeMap<String, HealthCheck>,
+}
+
+/// Result of an admin action.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminActionResult {
+    /// False if the target was not found, e.g. the run has completed.
+    pub ok: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EvictCacheQuery {
+    /// The cache path, "A:{agent}" or "T:{tool}".
+    pub path: String,
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub from: u64,
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BillingQuery {
+    /// Start of the first period, in milliseconds.
+    #[serde(default)]
+    pub from: u64,
+    /// End of the export, exclusive, now if not set.
+    pub to: Option<u64>,
+    /// "json" (default) or "csv".
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssueApiKeyRequest {
+    /// A label of the key, e.g. the name of the backend using it.
+    pub name: String,
+}
+
+/// A newly issued API key, the key can't be retrieved later.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub info: ApiKeyInfo,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KnowledgeQuery {
+    /// Lists the collections of the scope only, e.g. "tenant:acme".
+    pub scope: Option<KnowledgeScope>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateCollectionRequest {
+    /// "engine" (default), "tenant:{id}" or "user:{principal}".
+    pub scope: Option<KnowledgeScope>,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A knowledge collection with its documents.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CollectionDetails {
+    pub info: CollectionInfo,
+    pub documents: Vec<KnowledgeDocumentInfo>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemoveDocumentsRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemoveDocumentsResult {
+    pub removed: usize,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, redact(&message)).into_response()
+}
+
+/// The maximum body size of an admin request, as the default limit of the JSON extractor.
+const ADMIN_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The digest of an admin request that its signed envelope signs.
+#[derive(Clone, Copy, Debug)]
+pub struct AdminDigest(pub [u8; 32]);
+
+/// Returns the digest of an admin request: SHA3-256 of the method, the path, the raw query
+/// (empty if none) and the body, each of the first three followed by a newline.
+pub fn admin_request_digest(method: &str, path: &str, query: &str, body: &[u8]) -> [u8; 32] {
+    sha3_256_n([
+        method.as_bytes(),
+        b"\n",
+        path.as_bytes(),
+        b"\n",
+        query.as_bytes(),
+        b"\n",
+        body,
+    ])
+}
+
+/// Middleware of the admin routes that buffers the body and computes the [`AdminDigest`].
+pub async fn admin_digest(req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let body = match to_bytes(body, ADMIN_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(err) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, err.to_string()),
+    };
+    let digest = admin_request_digest(
+        parts.method.as_str(),
+        parts.uri.path(),
+        parts.uri.query().unwrap_or_default(),
+        &body,
+    );
+    parts.extensions.insert(AdminDigest(digest));
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_scope(scope: &str) -> Result<KnowledgeScope, Response> {
+    scope.parse().map_err(|err: anda_core::BoxError| {
+        error_response(StatusCode::BAD_REQUEST, err.to_string())
+    })
+}
+
+/// Resolves the engine and checks that the caller is its controller or a manager.
+#[allow(clippy::result_large_err)]
+fn admin_engine(
+    app: &AppState,
+    headers: &http::HeaderMap,
+    digest: &AdminDigest,
+    id: &str,
+) -> Result<(Engine, Principal), Response> {
+    let id = if id == "default" {
+        app.default_engine
+    } else {
+        Principal::from_text(id).map_err(|_| {
+            error_response(
+                StatusCode::BAD_REQUEST,
+                format!("invalid engine id: {id:?}"),
+            )
+        })?
+    };
+    let engine = app.engines.get(&id).ok_or_else(|| {
+        error_response(
+            StatusCode::NOT_FOUND,
+            format!("engine {} not found", id.to_text()),
+        )
+    })?;
+
+    let se = SignedEnvelope::from_authorization(headers)
+        .or_else(|| SignedEnvelope::from_headers(headers))
+        .ok_or_else(|| {
+            error_response(
+                StatusCode::UNAUTHORIZED,
+                "missing signed envelope".to_string(),
+            )
+        })?;
+    se.verify(unix_ms(), Some(id), Some(&digest.0))
+        .map_err(|err| {
+            error_response(
+                StatusCode::UNAUTHORIZED,
+                format!("invalid signed envelope: {err:?}"),
+            )
+        })?;
+    let caller = se.sender();
+    if !engine.is_manager(&caller) {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            format!("caller {} is not a manager", caller.to_text()),
+        ));
+    }
+
+    log::info!(
+        engine = id.to_text(),
+        caller = caller.to_text();
+        "admin",
+    );
+    Ok((engine.clone(), caller))
+}
+
+/// GET /admin/{id}/agents
+///
+/// Lists all registered agents with their schemas, exported or not.
+pub async fn admin_agents(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Function>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.agents(None)))
+}
+
+/// GET /admin/{id}/tools
+///
+/// Lists all registered tools with their schemas, exported or not.
+pub async fn admin_tools(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Function>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.tools(None)))
+}
+
+/// GET /admin/{id}/remote_engines
+///
+/// Lists the remote engines with the result of their readiness checks.
+pub async fn admin_remote_engines(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<AdminRemoteEngines>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let readiness = engine.readiness().await;
+    Ok(Json(AdminRemoteEngines {
+        engines: engine.remote_engines().engines,
+        health: readiness
+            .checks
+            .into_iter()
+            .filter_map(|(name, check)| {
+                name.strip_prefix("remote:")
+                    .map(|name| (name.to_string(), check))
+            })
+            .collect(),
+    }))
+}
+
+/// GET /admin/{id}/runs
+///
+/// Lists the agent runs and tool calls in flight.
+pub async fn admin_runs(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<RunInfo>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.active_runs()))
+}
+
+/// POST /admin/{id}/runs/{run_id}/cancel
+pub async fn admin_cancel_run(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, run_id)): Path<(String, u64)>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let ok = engine
+        .cancel_run(caller, run_id)
+        .await
+        .map_err(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// GET /admin/{id}/jobs
+///
+/// Lists the background jobs of the engine, from the oldest.
+pub async fn admin_jobs(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<JobInfo>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.jobs()))
+}
+
+/// POST /admin/{id}/jobs/{job_id}/cancel
+pub async fn admin_cancel_job(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, job_id)): Path<(String, String)>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let ok = engine
+        .cancel_job(caller, &job_id)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// GET /admin/{id}/stats
+///
+/// Usage statistics of the engine since it was built.
+pub async fn admin_stats(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<EngineStats>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.stats()))
+}
+
+/// GET /admin/{id}/tenants
+///
+/// Lists the tenants of the engine with their usage of the day.
+pub async fn admin_tenants(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<TenantInfo>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.tenants()))
+}
+
+/// GET /admin/{id}/billing?from={ms}&to={ms}&format={json|csv}
+///
+/// Exports the billing records of the periods starting in `[from, to)`,
+/// responds 404 if usage metering is not enabled.
+pub async fn admin_billing(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Query(q): Query<BillingQuery>,
+) -> Result<Response, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let metering = engine.metering().ok_or_else(|| {
+        error_response(
+            StatusCode::NOT_FOUND,
+            "usage metering not enabled".to_string(),
+        )
+    })?;
+    let records = metering
+        .records(q.from, q.to.unwrap_or_else(unix_ms))
+        .await
+        .map_err(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    match q.format.as_deref() {
+        None | Some("json") => Ok(Json(records).into_response()),
+        Some("csv") => Ok((
+            [(http::header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            BillingRecord::to_csv(&records),
+        )
+            .into_response()),
+        Some(format) => Err(error_response(
+            StatusCode::BAD_REQUEST,
+            format!("unsupported format {format:?}, expected json or csv"),
+        )),
+    }
+}
+
+/// POST /admin/{id}/snapshot
+///
+/// Saves a snapshot of the engine's runtime state to the store.
+pub async fn admin_snapshot(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<SnapshotReport>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let report = engine
+        .snapshot()
+        .await
+        .map_err(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Json(report))
+}
+
+/// GET /admin/{id}/cache/stats
+///
+/// Entries, sizes, hits, misses and evictions of the caches of the agents and tools.
+pub async fn admin_cache_stats(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<CacheStats>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.cache_stats()))
+}
+
+/// POST /admin/{id}/cache/evict?path={path}&key={key}
+pub async fn admin_evict_cache(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Query(q): Query<EvictCacheQuery>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let ok = engine
+        .evict_cache(caller, &q.path, &q.key)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// GET /admin/{id}/audit?from={seq}&limit={limit}
+///
+/// Exports entries of the audit log, responds 404 if it is not enabled.
+pub async fn admin_audit(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Query(q): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let audit = engine.audit_log().ok_or_else(|| {
+        error_response(StatusCode::NOT_FOUND, "audit log not enabled".to_string())
+    })?;
+    let entries = audit
+        .export(q.from, q.limit.unwrap_or(100))
+        .await
+        .map_err(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Json(entries))
+}
+
+/// GET /admin/{id}/audit/verify
+///
+/// Verifies the hash chain of the audit log, responds 409 with the first broken entry.
+pub async fn admin_audit_verify(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<AuditVerification>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let audit = engine.audit_log().ok_or_else(|| {
+        error_response(StatusCode::NOT_FOUND, "audit log not enabled".to_string())
+    })?;
+    let res = audit
+        .verify()
+        .await
+        .map_err(|err| error_response(StatusCode::CONFLICT, err.to_string()))?;
+    Ok(Json(res))
+}
+
+/// GET /admin/{id}/api_keys
+///
+/// Lists the API keys of the engine, including the revoked ones.
+pub async fn admin_api_keys(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<ApiKeyInfo>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.api_keys()))
+}
+
+/// POST /admin/{id}/api_keys
+///
+/// Issues an API key, responds 400 if API keys are not enabled on the engine.
+pub async fn admin_issue_api_key(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Json(req): Json<IssueApiKeyRequest>,
+) -> Result<Json<IssuedApiKey>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let (key, info) = engine
+        .issue_api_key(caller, req.name)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(IssuedApiKey { key, info }))
+}
+
+/// DELETE /admin/{id}/api_keys/{key_id}
+pub async fn admin_revoke_api_key(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, key_id)): Path<(String, String)>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let ok = engine
+        .revoke_api_key(caller, &key_id)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// GET /admin/{id}/flags
+pub async fn admin_feature_flags(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+) -> Result<Json<BTreeMap<String, FeatureFlag>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    Ok(Json(engine.feature_flags()))
+}
+
+/// PUT /admin/{id}/flags/{name}
+///
+/// Sets a feature flag until the next config reload or restart,
+/// responds 400 if the flag is invalid.
+pub async fn admin_set_feature_flag(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, name)): Path<(String, String)>,
+    Json(flag): Json<FeatureFlag>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    engine
+        .set_feature_flag(caller, name, flag)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok: true }))
+}
+
+/// DELETE /admin/{id}/flags/{name}
+pub async fn admin_remove_feature_flag(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, name)): Path<(String, String)>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let ok = engine
+        .remove_feature_flag(caller, &name)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// GET /admin/{id}/knowledge?scope={scope}
+///
+/// Lists the knowledge collections with their statistics, responds 404 if they are not enabled.
+pub async fn admin_knowledge(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Query(q): Query<KnowledgeQuery>,
+) -> Result<Json<Vec<CollectionInfo>>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let kb = engine.knowledge().ok_or_else(|| {
+        error_response(
+            StatusCode::NOT_FOUND,
+            "knowledge collections not enabled".to_string(),
+        )
+    })?;
+    Ok(Json(kb.list(q.scope.as_ref())))
+}
+
+/// POST /admin/{id}/knowledge
+pub async fn admin_create_collection(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path(id): Path<String>,
+    Json(req): Json<CreateCollectionRequest>,
+) -> Result<Json<CollectionInfo>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let info = engine
+        .create_knowledge_collection(
+            caller,
+            req.scope.unwrap_or(KnowledgeScope::Engine),
+            req.name,
+            req.description,
+        )
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(info))
+}
+
+/// GET /admin/{id}/knowledge/{scope}/{name}
+pub async fn admin_collection(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, scope, name)): Path<(String, String, String)>,
+) -> Result<Json<CollectionDetails>, Response> {
+    let (engine, _) = admin_engine(&app, &headers, &digest, &id)?;
+    let scope = parse_scope(&scope)?;
+    let not_found = || {
+        error_response(
+            StatusCode::NOT_FOUND,
+            format!("knowledge collection {scope}/{name} not found"),
+        )
+    };
+    let kb = engine.knowledge().ok_or_else(not_found)?;
+    let info = kb.get(&scope, &name).ok_or_else(not_found)?;
+    let documents = kb.documents(&scope, &name).unwrap_or_default();
+    Ok(Json(CollectionDetails { info, documents }))
+}
+
+/// DELETE /admin/{id}/knowledge/{scope}/{name}
+pub async fn admin_delete_collection(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, scope, name)): Path<(String, String, String)>,
+) -> Result<Json<AdminActionResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let scope = parse_scope(&scope)?;
+    let ok = engine
+        .delete_knowledge_collection(caller, &scope, &name)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(AdminActionResult { ok }))
+}
+
+/// POST /admin/{id}/knowledge/{scope}/{name}/documents
+///
+/// Adds documents to the collection, or replaces the documents with the same IDs.
+pub async fn admin_add_documents(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, scope, name)): Path<(String, String, String)>,
+    Json(docs): Json<Vec<IngestDocument>>,
+) -> Result<Json<IngestReport>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let scope = parse_scope(&scope)?;
+    let report = engine
+        .add_knowledge_documents(caller, &scope, &name, docs)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(report))
+}
+
+/// POST /admin/{id}/knowledge/{scope}/{name}/documents/remove
+pub async fn admin_remove_documents(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Extension(digest): Extension<AdminDigest>,
+    Path((id, scope, name)): Path<(String, String, String)>,
+    Json(req): Json<RemoveDocumentsRequest>,
+) -> Result<Json<RemoveDocumentsResult>, Response> {
+    let (engine, caller) = admin_engine(&app, &headers, &digest, &id)?;
+    let scope = parse_scope(&scope)?;
+    let removed = engine
+        .remove_knowledge_documents(caller, &scope, &name, req.ids)
+        .await
+        .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(RemoveDocumentsResult { removed }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_request_digest() {
+        let digest =
+            admin_request_digest("POST", "/admin/default/cache/evict", "path=T:x&key=k", b"");
+        assert_eq!(
+            digest,
+            admin_request_digest("POST", "/admin/default/cache/evict", "path=T:x&key=k", b"")
+        );
+        assert_ne!(
+            digest,
+            admin_request_digest("GET", "/admin/default/cache/evict", "path=T:x&key=k", b"")
+        );
+        assert_ne!(
+            digest,
+            admin_request_digest("POST", "/admin/default/cache/evict", "path=T:x&key=j", b"")
+        );
+        assert_ne!(
+            digest,
+            admin_request_digest("POST", "/admin/default/snapshot", "path=T:x&key=k", b"")
+        );
+        assert_ne!(
+            digest,
+            admin_request_digest(
+                "POST",
+                "/admin/default/cache/evict",
+                "path=T:x&key=k",
+                b"{}"
+            )
+        );
+    }
+}
@@ -1,9 +1,20 @@
-use anda_core::{AgentInput, ToolInput, Value};
-use anda_engine::engine::{Engine, Information};
+use anda_core::{
+    AgentInput, BoxError, Error, Priority, RequestMeta, ToolInput, Value, anda_error,
+    preferred_locale,
+};
+use anda_engine::{
+    api_key::API_KEY_PREFIX,
+    engine::{Engine, Information, JobSpec},
+    secrets::redact,
+};
 use axum::{
-    extract::{Path, State},
+    Json,
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::IntoResponse,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
 use candid::Principal;
 use ciborium::from_reader;
@@ -14,15 +25,144 @@ use ic_tee_agent::{
     http::{Content, ContentWithSHA3},
 };
 use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::sync::Arc;
 
-use crate::types::*;
+use crate::{
+    a2a::A2aTasks,
+    rate_limit::{
+        RateLimitDecision, RateLimiter, apply_rate_limit_headers, with_rate_limit_headers,
+        with_retry_after,
+    },
+    run_queue::{RunPermit, RunQueue},
+    types::*,
+    ws::WsNonces,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub(crate) engines: Arc<BTreeMap<Principal, Engine>>,
     pub(crate) default_engine: Principal,
     pub(crate) start_time_ms: u64,
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+    pub(crate) run_queue: Option<Arc<RunQueue>>,
+    pub(crate) public_url: Option<String>,
+    pub(crate) attestation_url: Option<String>,
+    pub(crate) a2a: Arc<A2aTasks>,
+    pub(crate) ws_nonces: Arc<WsNonces>,
+}
+
+/// An admitted agent run or tool call, it holds a slot of the run queue until dropped.
+pub(crate) struct Admission {
+    pub(crate) limit: Option<RateLimitDecision>,
+    pub(crate) permit: Option<RunPermit>,
+}
+
+impl AppState {
+    /// Checks the rate limit of the caller for the agent or tool, None if not limited.
+    pub(crate) fn rate_limit(&self, caller: &Principal, name: &str) -> Option<RateLimitDecision> {
+        self.rate_limiter.as_ref()?.check(caller, name)
+    }
+
+    /// Waits for a slot of the run queue for the agent or tool, None if there is no queue.
+    pub(crate) async fn enqueue(
+        &self,
+        name: &str,
+        priority: Priority,
+    ) -> Result<Option<RunPermit>, BoxError> {
+        match &self.run_queue {
+            Some(queue) => Ok(Some(queue.acquire(name, priority).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Admits an agent run or tool call by the rate limiter and the run queue,
+    /// or returns the response rejecting it.
+    pub(crate) async fn admit(
+        &self,
+        caller: &Principal,
+        name: &str,
+        meta: Option<&RequestMeta>,
+    ) -> Result<Admission, Response> {
+        let limit = self.rate_limit(caller, name);
+        if let Some(limit) = limit.as_ref().filter(|l| !l.allowed) {
+            return Err(too_many_requests(limit));
+        }
+        let priority = meta.map(|m| m.priority).unwrap_or_default();
+        let permit = self.enqueue(name, priority).await.map_err(|err| {
+            with_rate_limit_headers(limit.as_ref(), service_unavailable(err.to_string()))
+        })?;
+        Ok(Admission { limit, permit })
+    }
+}
+
+/// Gets the API key from the `x-api-key` header, or the `Authorization: Bearer` header.
+fn api_key(headers: &http::HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key);
+    }
+    headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|key| key.starts_with(API_KEY_PREFIX))
+}
+
+/// Resolves the caller of a request to the engine, or to any engine if None:
+/// the signer of a valid signed envelope, or the pseudo-principal of a valid API key,
+/// otherwise anonymous.
+pub(crate) fn request_caller(
+    app: &AppState,
+    headers: &http::HeaderMap,
+    engine: Option<Principal>,
+    hash: Option<&[u8]>,
+) -> Principal {
+    if let Some(se) = SignedEnvelope::from_authorization(headers)
+        .or_else(|| SignedEnvelope::from_headers(headers))
+    {
+        return match se.verify(unix_ms(), engine, hash) {
+            Ok(_) => se.sender(),
+            Err(_) => ANONYMOUS_PRINCIPAL,
+        };
+    }
+
+    let caller = match (api_key(headers), engine) {
+        (Some(key), Some(id)) => app.engines.get(&id).and_then(|e| e.verify_api_key(key)),
+        (Some(key), None) => app.engines.values().find_map(|e| e.verify_api_key(key)),
+        (None, _) => None,
+    };
+    caller.unwrap_or(ANONYMOUS_PRINCIPAL)
+}
+
+/// Gets the preferred locale of the user from the `Accept-Language` header.
+pub(crate) fn request_locale(headers: &http::HeaderMap) -> Option<String> {
+    headers
+        .get(http::header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(preferred_locale)
+}
+
+/// Responds 429 Too Many Requests with the rate limit headers.
+pub(crate) fn too_many_requests(limit: &RateLimitDecision) -> Response {
+    apply_rate_limit_headers(
+        limit,
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!(
+                "rate limit exceeded, retry after {}s",
+                limit.retry_after_secs
+            ),
+        )
+            .into_response(),
+    )
+}
+
+/// Responds 503 Service Unavailable when the run queue is full.
+pub(crate) fn service_unavailable(message: String) -> Response {
+    let mut res = (StatusCode::SERVICE_UNAVAILABLE, message).into_response();
+    res.headers_mut()
+        .insert(http::header::RETRY_AFTER, http::HeaderValue::from(1));
+    res
 }
 
 /// GET /.well-known/information
@@ -30,16 +170,7 @@ pub async fn get_information(
     State(app): State<AppState>,
     headers: http::HeaderMap,
 ) -> impl IntoResponse {
-    let caller = if let Some(se) = SignedEnvelope::from_authorization(&headers)
-        .or_else(|| SignedEnvelope::from_headers(&headers))
-    {
-        match se.verify(unix_ms(), None, None) {
-            Ok(_) => se.sender(),
-            Err(_) => ANONYMOUS_PRINCIPAL,
-        }
-    } else {
-        ANONYMOUS_PRINCIPAL
-    };
+    let caller = request_caller(&app, &headers, None, None);
 
     let info = AppInformation {
         engines: app
@@ -65,6 +196,97 @@ pub async fn get_information(
     }
 }
 
+/// Returns the public URL of the server, or the URL of the request if not set.
+pub(crate) fn public_url(app: &AppState, headers: &http::HeaderMap) -> String {
+    match &app.public_url {
+        Some(url) => url.clone(),
+        None => {
+            let proto = headers
+                .get("x-forwarded-proto")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("http");
+            let host = headers
+                .get(http::header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("localhost");
+            format!("{proto}://{host}")
+        }
+    }
+}
+
+/// GET /.well-known/agent.json?engine={id}
+///
+/// Agent card of the engine, the default one if not set, with its identity, agents and tools
+/// with their prices, capabilities, endpoints and attestation, for registries and peer engines.
+pub async fn get_agent_card(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Query(q): Query<AgentCardQuery>,
+) -> impl IntoResponse {
+    let id = q.engine.unwrap_or(app.default_engine);
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("engine {} not found", id.to_text()),
+            )
+                .into_response();
+        }
+    };
+
+    let base = public_url(&app, &headers);
+    let id = id.to_text();
+    let mut card = engine.agent_card();
+    card.endpoints = BTreeMap::from([
+        ("rpc".to_string(), format!("{base}/{id}")),
+        ("stream".to_string(), format!("{base}/v1/agent_run")),
+        (
+            "information".to_string(),
+            format!("{base}/.well-known/information/{id}"),
+        ),
+        ("openai".to_string(), format!("{base}/v1/chat/completions")),
+        ("a2a".to_string(), format!("{base}/a2a/{id}")),
+    ]);
+    card.attestation = app.attestation_url.clone();
+    Json(card).into_response()
+}
+
+/// GET /healthz
+///
+/// Liveness probe, the process is up and serving requests, with the metrics of the run queue.
+pub async fn get_healthz(State(app): State<AppState>) -> impl IntoResponse {
+    let now = unix_ms();
+    Json(HealthStatus {
+        status: "ok".to_string(),
+        start_time_ms: app.start_time_ms,
+        uptime_ms: now.saturating_sub(app.start_time_ms),
+        queue: app.run_queue.as_ref().map(|q| q.stats()),
+    })
+}
+
+/// GET /readyz
+///
+/// Readiness probe, all engines can reach their model, store and remote engines.
+/// Responds 503 Service Unavailable with the failed checks if any engine is not ready.
+pub async fn get_readyz(State(app): State<AppState>) -> impl IntoResponse {
+    let engines =
+        futures::future::join_all(app.engines.values().map(|engine| engine.readiness())).await;
+    let ready = engines.iter().all(|r| r.ready);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ReadyStatus {
+            status: if ready { "ok" } else { "unavailable" }.to_string(),
+            engines,
+        }),
+    )
+}
+
 /// GET /.well-known/information/{id}
 pub async fn get_engine_information(
     State(app): State<AppState>,
@@ -123,16 +345,7 @@ pub async fn anda_engine(
         ContentWithSHA3::JSON(req, hash) => (req, hash),
     };
 
-    let caller = if let Some(se) = SignedEnvelope::from_authorization(&headers)
-        .or_else(|| SignedEnvelope::from_headers(&headers))
-    {
-        match se.verify(unix_ms(), Some(id), Some(hash.as_slice())) {
-            Ok(_) => se.sender(),
-            Err(_) => ANONYMOUS_PRINCIPAL,
-        }
-    } else {
-        ANONYMOUS_PRINCIPAL
-    };
+    let caller = request_caller(&app, &headers, Some(id), Some(hash.as_slice()));
 
     log::info!(
         method = req.method.as_str(),
@@ -140,50 +353,187 @@ pub async fn anda_engine(
         caller = caller.to_text();
         "anda_engine",
     );
-    let res = engine_run(req, &app, caller, id).await;
-    match &ct {
+    let (res, limit) = match engine_run(req, &app, caller, id).await {
+        Ok(res) => res,
+        Err(rejected) => return rejected,
+    };
+    let res = match &ct {
         ContentWithSHA3::CBOR(_, _) => Content::CBOR(res, None).into_response(),
         ContentWithSHA3::JSON(_, _) => Content::JSON(res, None).into_response(),
+    };
+    with_rate_limit_headers(limit.as_ref(), res)
+}
+
+/// POST /v1/agent_run
+///
+/// Runs an agent and streams its progress as server-sent events, named by [`anda_core::AgentEvent::name`]:
+/// `delta` (token by token), `content`, `tool_call_start`, `tool_call_end`, and finally `output` or `error`.
+/// The engine is selected by `meta.engine` of the input, or the default engine.
+/// The locale of the user defaults to the `Accept-Language` header.
+pub async fn agent_run_stream(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    ct: ContentWithSHA3<AgentInput>,
+) -> impl IntoResponse {
+    let (mut input, hash) = match ct {
+        ContentWithSHA3::CBOR(input, hash) => (input, hash),
+        ContentWithSHA3::JSON(input, hash) => (input, hash),
+    };
+    if let Some(locale) = request_locale(&headers) {
+        let meta = input.meta.get_or_insert_with(RequestMeta::default);
+        meta.locale.get_or_insert(locale);
     }
+
+    let id = input
+        .meta
+        .as_ref()
+        .and_then(|m| m.engine)
+        .unwrap_or(app.default_engine);
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("engine {} not found", id.to_text()),
+            )
+                .into_response();
+        }
+    };
+
+    let caller = request_caller(&app, &headers, Some(id), Some(hash.as_slice()));
+
+    log::info!(
+        agent = input.name.as_str(),
+        engine = id.to_text(),
+        caller = caller.to_text();
+        "agent_run_stream",
+    );
+    let Admission { limit, permit } =
+        match app.admit(&caller, &input.name, input.meta.as_ref()).await {
+            Ok(admission) => admission,
+            Err(rejected) => return rejected,
+        };
+    let rx = engine.agent_run_events(caller, input);
+    // the slot of the run queue is released when the stream ends
+    let events = futures::stream::unfold((rx, permit), |(mut rx, permit)| async move {
+        let event = rx.recv().await?;
+        let data = serde_json::to_string(&event).unwrap_or_default();
+        Some((
+            Ok::<Event, Infallible>(Event::default().event(event.name()).data(data)),
+            (rx, permit),
+        ))
+    });
+    with_rate_limit_headers(
+        limit.as_ref(),
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+    )
 }
 
+fn is_rate_limited(err: &BoxError) -> bool {
+    matches!(anda_error(err), Some(Error::RateLimited { .. }))
+}
+
+/// Responds 429 Too Many Requests to a request rejected by the engine's rate limiter or the
+/// quotas of the caller's tenant, so that clients retry it as those of the server's limiter.
+fn engine_rate_limited(err: &BoxError) -> Response {
+    with_retry_after(
+        err,
+        (StatusCode::TOO_MANY_REQUESTS, redact(&err.to_string())).into_response(),
+    )
+}
+
+/// Runs the RPC request on the engine, with the rate limit decision of agent runs
+/// and tool calls, or returns the response rejecting them.
 async fn engine_run(
     req: &RPCRequest,
     app: &AppState,
     caller: Principal,
     id: Principal,
-) -> RPCResponse {
-    let engine = app
-        .engines
-        .get(&id)
-        .ok_or_else(|| format!("engine {} not found", id.to_text()))?;
+) -> Result<(RPCResponse, Option<RateLimitDecision>), Response> {
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine,
+        None => return Ok((Err(format!("engine {} not found", id.to_text())), None)),
+    };
 
     match req.method.as_str() {
         "agent_run" => {
-            let args: (AgentInput,) = from_reader(req.params.as_slice())
-                .map_err(|err| format!("failed to decode params: {err:?}"))?;
-            let res = engine
-                .agent_run(caller, args.0)
-                .await
-                .map_err(|err| format!("failed to run agent: {err:?}"))?;
-            Ok(to_cbor_bytes(&res).into())
+            let args: (AgentInput,) = match from_reader(req.params.as_slice()) {
+                Ok(args) => args,
+                Err(err) => return Ok((Err(format!("failed to decode params: {err:?}")), None)),
+            };
+            let admission = app
+                .admit(&caller, &args.0.name, args.0.meta.as_ref())
+                .await?;
+            let res: RPCResponse = match engine.agent_run(caller, args.0).await {
+                Ok(res) => Ok(to_cbor_bytes(&res).into()),
+                Err(err) if is_rate_limited(&err) => return Err(engine_rate_limited(&err)),
+                Err(err) => Err(redact(&format!("failed to run agent: {err:?}"))),
+            };
+            Ok((res, admission.limit))
         }
         "tool_call" => {
-            let args: (ToolInput<Value>,) = from_reader(req.params.as_slice())
-                .map_err(|err| format!("failed to decode params: {err:?}"))?;
-            let res = engine
-                .tool_call(caller, args.0)
+            let args: (ToolInput<Value>,) = match from_reader(req.params.as_slice()) {
+                Ok(args) => args,
+                Err(err) => return Ok((Err(format!("failed to decode params: {err:?}")), None)),
+            };
+            let admission = app
+                .admit(&caller, &args.0.name, args.0.meta.as_ref())
+                .await?;
+            let res: RPCResponse = match engine.tool_call(caller, args.0).await {
+                Ok(res) => Ok(to_cbor_bytes(&res).into()),
+                Err(err) if is_rate_limited(&err) => return Err(engine_rate_limited(&err)),
+                Err(err) => Err(redact(&format!("failed to call tool: {err:?}"))),
+            };
+            Ok((res, admission.limit))
+        }
+        "spawn_job" => {
+            let args: (JobSpec,) = match from_reader(req.params.as_slice()) {
+                Ok(args) => args,
+                Err(err) => return Ok((Err(format!("failed to decode params: {err:?}")), None)),
+            };
+            let limit = app.rate_limit(&caller, args.0.target.name());
+            if let Some(limit) = limit.as_ref().filter(|l| !l.allowed) {
+                return Err(too_many_requests(limit));
+            }
+            let res: RPCResponse = engine
+                .spawn_job(caller, args.0)
+                .await
+                .map(|res| to_cbor_bytes(&res).into())
+                .map_err(|err| format!("failed to spawn job: {err:?}"));
+            Ok((res, limit))
+        }
+        "get_job" => {
+            let args: (String,) = match from_reader(req.params.as_slice()) {
+                Ok(args) => args,
+                Err(err) => return Ok((Err(format!("failed to decode params: {err:?}")), None)),
+            };
+            let res = engine.job(&caller, &args.0);
+            Ok((Ok(to_cbor_bytes(&res).into()), None))
+        }
+        "set_webhook" => {
+            let args: (Option<String>,) = match from_reader(req.params.as_slice()) {
+                Ok(args) => args,
+                Err(err) => return Ok((Err(format!("failed to decode params: {err:?}")), None)),
+            };
+            let res: RPCResponse = engine
+                .set_webhook(caller, args.0)
                 .await
-                .map_err(|err| format!("failed to call tool: {err:?}"))?;
-            Ok(to_cbor_bytes(&res).into())
+                .map(|res| to_cbor_bytes(&res).into())
+                .map_err(|err| format!("failed to set webhook: {err:?}"));
+            Ok((res, None))
         }
         "information" => {
             let res = engine.information();
-            Ok(to_cbor_bytes(&res).into())
+            Ok((Ok(to_cbor_bytes(&res).into()), None))
         }
-        method => Err(format!(
-            "{method} on engine {} not implemented",
-            id.to_text()
+        method => Ok((
+            Err(format!(
+                "{method} on engine {} not implemented",
+                id.to_text()
+            )),
+            None,
         )),
     }
 }
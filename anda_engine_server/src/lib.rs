@@ -1,26 +1,52 @@
 use anda_core::BoxError;
 use anda_engine::engine::Engine;
-use axum::{Router, routing};
+use axum::{Router, middleware, routing};
 use candid::Principal;
-use std::{collections::BTreeMap, future::Future, net::SocketAddr, sync::Arc};
+use futures::future::join_all;
+use std::{collections::BTreeMap, future::Future, net::SocketAddr, sync::Arc, time::Duration};
 use structured_logger::unix_ms;
 use tokio::signal;
 use tokio_util::sync::CancellationToken;
 
+mod a2a;
+mod admin;
 mod handler;
+mod mcp;
+mod openai;
+mod rate_limit;
+mod run_queue;
 mod types;
+mod ws;
 
+use a2a::*;
+use admin::*;
 use handler::*;
+use mcp::*;
+use openai::*;
+use ws::*;
+
+pub use rate_limit::{RateLimit, RateLimitConfig, RateLimitDecision, RateLimiter, UNLIMITED_TIER};
+pub use run_queue::{
+    AgentQueueStats, DEFAULT_QUEUE_TIMEOUT, RunPermit, RunQueue, RunQueueConfig, RunQueueStats,
+};
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Default time left to in-flight runs to finish on shutdown.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct ServerBuilder {
     app_name: String,
     app_version: String,
     addr: String,
     engines: BTreeMap<Principal, Engine>,
     default_engine: Option<Principal>,
+    drain_timeout: Duration,
+    rate_limit: Option<RateLimitConfig>,
+    run_queue: Option<RunQueueConfig>,
+    public_url: Option<String>,
+    attestation_url: Option<String>,
 }
 
 impl Default for ServerBuilder {
@@ -40,6 +66,11 @@ impl ServerBuilder {
             addr: "127.0.0.1:8042".to_string(),
             engines: BTreeMap::new(),
             default_engine: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            rate_limit: None,
+            run_queue: None,
+            public_url: None,
+            attestation_url: None,
         }
     }
 
@@ -68,6 +99,41 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets how long in-flight runs may take to finish on shutdown before they are cancelled.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Limits the agent runs and tool calls of each caller.
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Bounds the concurrency of agent runs and tool calls, globally and per agent,
+    /// with a queue of the requests waiting for a slot.
+    pub fn with_run_queue(mut self, run_queue: RunQueueConfig) -> Self {
+        self.run_queue = Some(run_queue);
+        self
+    }
+
+    /// Sets the public URL of the server, e.g. `https://anda.example.com`, for the endpoints
+    /// in the agent cards. The `Host` header of the request is used if not set.
+    pub fn with_public_url(mut self, public_url: String) -> Self {
+        self.public_url = Some(public_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Sets the URL of the TEE attestation of the engines, referenced by the agent cards.
+    pub fn with_attestation_url(mut self, attestation_url: String) -> Self {
+        self.attestation_url = Some(attestation_url);
+        self
+    }
+
+    /// Serves the engines until the signal resolves, then shuts down gracefully:
+    /// new runs are rejected and `/readyz` fails, in-flight runs are drained up to
+    /// the drain timeout and the rest cancelled, audit logs and traces are flushed.
     pub async fn serve(
         self,
         signal: impl Future<Output = ()> + Send + 'static,
@@ -83,18 +149,101 @@ impl ServerBuilder {
             return Err("default engine not found".into());
         }
 
+        let rate_limiter = self
+            .rate_limit
+            .map(RateLimiter::new)
+            .transpose()?
+            .map(Arc::new);
+        let run_queue = self.run_queue.map(RunQueue::new).transpose()?.map(Arc::new);
+
+        let engines = Arc::new(self.engines);
         let state = AppState {
-            engines: Arc::new(self.engines),
+            engines: engines.clone(),
             default_engine,
             start_time_ms: unix_ms(),
+            rate_limiter,
+            run_queue,
+            public_url: self.public_url,
+            attestation_url: self.attestation_url,
+            a2a: Arc::new(A2aTasks::default()),
+            ws_nonces: Arc::new(WsNonces::default()),
         };
+        let admin = Router::new()
+            .route("/admin/{id}/agents", routing::get(admin_agents))
+            .route("/admin/{id}/tools", routing::get(admin_tools))
+            .route(
+                "/admin/{id}/remote_engines",
+                routing::get(admin_remote_engines),
+            )
+            .route("/admin/{id}/runs", routing::get(admin_runs))
+            .route(
+                "/admin/{id}/runs/{run_id}/cancel",
+                routing::post(admin_cancel_run),
+            )
+            .route("/admin/{id}/jobs", routing::get(admin_jobs))
+            .route(
+                "/admin/{id}/jobs/{job_id}/cancel",
+                routing::post(admin_cancel_job),
+            )
+            .route("/admin/{id}/stats", routing::get(admin_stats))
+            .route("/admin/{id}/tenants", routing::get(admin_tenants))
+            .route("/admin/{id}/billing", routing::get(admin_billing))
+            .route("/admin/{id}/snapshot", routing::post(admin_snapshot))
+            .route("/admin/{id}/cache/stats", routing::get(admin_cache_stats))
+            .route("/admin/{id}/cache/evict", routing::post(admin_evict_cache))
+            .route("/admin/{id}/audit", routing::get(admin_audit))
+            .route("/admin/{id}/audit/verify", routing::get(admin_audit_verify))
+            .route(
+                "/admin/{id}/api_keys",
+                routing::get(admin_api_keys).post(admin_issue_api_key),
+            )
+            .route(
+                "/admin/{id}/api_keys/{key_id}",
+                routing::delete(admin_revoke_api_key),
+            )
+            .route("/admin/{id}/flags", routing::get(admin_feature_flags))
+            .route(
+                "/admin/{id}/flags/{name}",
+                routing::put(admin_set_feature_flag).delete(admin_remove_feature_flag),
+            )
+            .route(
+                "/admin/{id}/knowledge",
+                routing::get(admin_knowledge).post(admin_create_collection),
+            )
+            .route(
+                "/admin/{id}/knowledge/{scope}/{name}",
+                routing::get(admin_collection).delete(admin_delete_collection),
+            )
+            .route(
+                "/admin/{id}/knowledge/{scope}/{name}/documents",
+                routing::post(admin_add_documents),
+            )
+            .route(
+                "/admin/{id}/knowledge/{scope}/{name}/documents/remove",
+                routing::post(admin_remove_documents),
+            )
+            .layer(middleware::from_fn(admin_digest));
         let app = Router::new()
             .route("/", routing::get(get_information))
+            .route("/healthz", routing::get(get_healthz))
+            .route("/readyz", routing::get(get_readyz))
             .route("/.well-known/information", routing::get(get_information))
+            .route("/.well-known/agent.json", routing::get(get_agent_card))
             .route(
                 "/.well-known/information/{id}",
                 routing::get(get_engine_information),
             )
+            .route("/v1/agent_run", routing::post(agent_run_stream))
+            .route("/v1/agent_run/ws", routing::get(agent_run_ws))
+            .route("/a2a/{id}", routing::post(a2a_rpc))
+            .route(
+                "/a2a/{id}/.well-known/agent.json",
+                routing::get(get_a2a_agent_card),
+            )
+            .route("/mcp/{id}", routing::post(mcp_rpc))
+            .route("/v1/models", routing::get(list_models))
+            .route("/v1/chat/completions", routing::post(chat_completions))
+            .merge(admin)
             .route("/{*id}", routing::post(anda_engine))
             .with_state(state);
 
@@ -107,15 +256,44 @@ impl ServerBuilder {
             addr
         );
 
+        let drain_timeout = self.drain_timeout;
+        let shutdown = async move {
+            signal.await;
+            log::warn!(
+                "draining in-flight runs of {} engines, timeout {:?}",
+                engines.len(),
+                drain_timeout
+            );
+            let reports = join_all(engines.values().map(|e| e.shutdown(drain_timeout))).await;
+            for (engine, report) in engines.values().zip(reports) {
+                log::warn!(
+                    engine = engine.id().to_text(),
+                    in_flight = report.in_flight,
+                    cancelled = report.cancelled,
+                    elapsed_ms = report.elapsed_ms;
+                    "engine shutdown",
+                );
+            }
+        };
         axum::serve(listener, app)
-            .with_graceful_shutdown(signal)
+            .with_graceful_shutdown(shutdown)
             .await?;
 
         Ok(())
     }
 }
 
+/// Waits for Ctrl+C or SIGTERM and cancels the token.
+///
+/// Engines built with this token cancel their in-flight runs at once; pass
+/// [`termination_signal`] to [`ServerBuilder::serve`] instead to drain them first.
 pub async fn shutdown_signal(cancel_token: CancellationToken) {
+    termination_signal().await;
+    cancel_token.cancel();
+}
+
+/// Waits for Ctrl+C or SIGTERM.
+pub async fn termination_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -139,7 +317,6 @@ pub async fn shutdown_signal(cancel_token: CancellationToken) {
     }
 
     log::warn!("received termination signal, starting graceful shutdown");
-    cancel_token.cancel();
 }
 
 pub async fn create_reuse_port_listener(
@@ -0,0 +1,169 @@
+//! Model Context Protocol (MCP) server.
+//!
+//! Serves the exported tools of each engine to MCP clients, such as IDEs and desktop
+//! assistants, with the "Streamable HTTP" transport:
+//! - `POST /mcp/{id}`: JSON-RPC `initialize`, `ping`, `tools/list` and `tools/call`,
+//!   answered with JSON responses; notifications are accepted and ignored.
+//!
+//! The server is stateless, it issues no session ID. Callers are identified as for the other
+//! endpoints, by a signed envelope or an API key, and the tool calls go through the rate
+//! limiter, the run queue and the permissions of the engine.
+
+use anda_core::{Error, ToolInput, anda_error};
+use anda_engine::{
+    a2a::{INVALID_PARAMS, INVALID_REQUEST, JsonRpcRequest, JsonRpcResponse, METHOD_NOT_FOUND},
+    engine::Engine,
+    mcp::{MCP_PROTOCOL_VERSION, McpCallToolResult, McpImplementation, McpServerInfo, McpToolInfo},
+    secrets::redact,
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use candid::Principal;
+use ic_tee_agent::http::ContentWithSHA3;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    APP_VERSION,
+    handler::{AppState, request_caller},
+    rate_limit::with_rate_limit_headers,
+};
+
+#[derive(Debug, Deserialize)]
+struct CallToolParams {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+fn rpc_response(res: JsonRpcResponse) -> Response {
+    Json(res).into_response()
+}
+
+/// Returns the exported tools of the engine that the caller may call as MCP tools.
+fn list_tools(engine: &Engine, caller: &Principal) -> Vec<McpToolInfo> {
+    let policy = engine.access_policy();
+    engine
+        .information()
+        .tools
+        .into_iter()
+        .filter(|tool| policy.is_tool_allowed(caller, None, &tool.definition.name))
+        .map(|tool| McpToolInfo {
+            name: tool.definition.name,
+            description: Some(tool.definition.description),
+            input_schema: tool.definition.parameters,
+        })
+        .collect()
+}
+
+/// POST /mcp/{id}
+pub async fn mcp_rpc(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Path(id): Path<String>,
+    ct: ContentWithSHA3<JsonRpcRequest>,
+) -> Response {
+    let id = if id == "default" {
+        app.default_engine
+    } else if let Ok(id) = Principal::from_text(&id) {
+        id
+    } else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid engine id: {id:?}"),
+        )
+            .into_response();
+    };
+    let (req, hash) = match ct {
+        ContentWithSHA3::CBOR(req, hash) => (req, hash),
+        ContentWithSHA3::JSON(req, hash) => (req, hash),
+    };
+    if req.method.starts_with("notifications/") {
+        return StatusCode::ACCEPTED.into_response();
+    }
+    let engine = match app.engines.get(&id) {
+        Some(engine) => engine.clone(),
+        None => {
+            return rpc_response(JsonRpcResponse::error(
+                req.id,
+                INVALID_REQUEST,
+                format!("engine {} not found", id.to_text()),
+            ));
+        }
+    };
+
+    let caller = request_caller(&app, &headers, Some(id), Some(hash.as_slice()));
+
+    log::info!(
+        method = req.method.as_str(),
+        engine = id.to_text(),
+        caller = caller.to_text();
+        "mcp_rpc",
+    );
+    match req.method.as_str() {
+        "initialize" => {
+            let info = engine.information();
+            rpc_response(JsonRpcResponse::result(
+                req.id,
+                McpServerInfo {
+                    protocol_version: MCP_PROTOCOL_VERSION.to_string(),
+                    capabilities: json!({"tools": {"listChanged": false}}),
+                    server_info: McpImplementation {
+                        name: info.name,
+                        version: APP_VERSION.to_string(),
+                    },
+                    instructions: Some(info.description).filter(|d| !d.is_empty()),
+                },
+            ))
+        }
+        "ping" => rpc_response(JsonRpcResponse::result(req.id, json!({}))),
+        "tools/list" => rpc_response(JsonRpcResponse::result(
+            req.id,
+            json!({"tools": list_tools(&engine, &caller)}),
+        )),
+        "tools/call" => {
+            let params: CallToolParams = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(err) => {
+                    return rpc_response(JsonRpcResponse::error(
+                        req.id,
+                        INVALID_PARAMS,
+                        format!("invalid params: {err}"),
+                    ));
+                }
+            };
+            let admission = match app.admit(&caller, &params.name, None).await {
+                Ok(admission) => admission,
+                Err(rejected) => return rejected,
+            };
+            let input = ToolInput::new(params.name.clone(), params.arguments);
+            let res = match engine.tool_call(caller, input).await {
+                Ok(output) => {
+                    JsonRpcResponse::result(req.id, McpCallToolResult::from_output(output))
+                }
+                Err(err) if matches!(anda_error(&err), Some(Error::NotFound(_))) => {
+                    JsonRpcResponse::error(
+                        req.id,
+                        INVALID_PARAMS,
+                        format!("tool {} not found", params.name),
+                    )
+                }
+                // tool errors are reported in the result, so that the model can see them
+                Err(err) => JsonRpcResponse::result(
+                    req.id,
+                    McpCallToolResult::error(redact(&err.to_string())),
+                ),
+            };
+            with_rate_limit_headers(admission.limit.as_ref(), rpc_response(res))
+        }
+        method => rpc_response(JsonRpcResponse::error(
+            req.id,
+            METHOD_NOT_FOUND,
+            format!("method {method:?} not found"),
+        )),
+    }
+}
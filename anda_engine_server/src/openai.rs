@@ -0,0 +1,537 @@
+//! OpenAI-compatible chat completions API.
+//!
+//! Serves `POST /v1/chat/completions` and `GET /v1/models` so that OpenAI-compatible
+//! clients and UIs can talk to Anda engines directly. The model name selects the agent:
+//! - `"{agent}"`: the agent on the default engine, `"default"` or empty for the default agent;
+//! - `"{engine_id}/{agent}"`: the agent on the given engine.
+//!
+//! Agents run with a single prompt, so the last message must come from the user,
+//! and the previous messages are prepended to the prompt as the conversation history.
+
+use anda_core::{
+    AgentEvent, AgentInput, AgentOutput, BoxError, Error, Priority, RequestMeta, Xid, anda_error,
+};
+use anda_engine::{engine::Engine, secrets::redact};
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use candid::Principal;
+use futures::stream::{self, StreamExt};
+use ic_auth_verifier::envelope::unix_ms;
+use ic_tee_agent::http::ContentWithSHA3;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+
+use crate::{
+    handler::{AppState, request_caller, request_locale},
+    rate_limit::{apply_rate_limit_headers, with_rate_limit_headers, with_retry_after},
+};
+
+/// A message of the chat completion request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatMessage {
+    /// "system", "developer", "user", "assistant" or "tool".
+    pub role: String,
+    /// Text or an array of content parts, only the text parts are used.
+    #[serde(default)]
+    pub content: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// The chat completion request, unsupported fields are ignored.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionChoice {
+    pub index: u32,
+    pub message: ChatCompletionMessage,
+    pub finish_reason: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ChatCompletionUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    pub usage: ChatCompletionUsage,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ChatCompletionDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionChunkChoice {
+    pub index: u32,
+    pub delta: ChatCompletionDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatCompletionUsage>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModelObject {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub owned_by: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModelList {
+    pub object: String,
+    pub data: Vec<ModelObject>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiErrorBody {
+    pub message: String,
+    pub r#type: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiError {
+    pub error: ApiErrorBody,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    let r#type = if status.is_client_error() {
+        "invalid_request_error"
+    } else {
+        "server_error"
+    };
+    (
+        status,
+        Json(ApiError {
+            error: ApiErrorBody {
+                message: redact(&message),
+                r#type: r#type.to_string(),
+            },
+        }),
+    )
+        .into_response()
+}
+
+/// Maps the kind of an agent run error to a HTTP status.
+fn error_status(err: &BoxError) -> StatusCode {
+    match anda_error(err) {
+        Some(Error::NotFound(_)) => StatusCode::NOT_FOUND,
+        Some(Error::Unauthorized(_)) => StatusCode::FORBIDDEN,
+        Some(Error::RateLimited { .. }) => StatusCode::TOO_MANY_REQUESTS,
+        Some(Error::Validation(_)) => StatusCode::BAD_REQUEST,
+        Some(Error::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+        Some(Error::Provider { .. }) | Some(Error::Remote { .. }) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Resolves the engine and the agent name from the model name.
+fn resolve_model<'a>(app: &'a AppState, model: &str) -> Result<(&'a Engine, String), String> {
+    let (id, agent) = match model.split_once('/') {
+        Some((id, agent)) => (
+            Principal::from_text(id).map_err(|_| format!("invalid engine id: {id:?}"))?,
+            agent,
+        ),
+        None => (app.default_engine, model),
+    };
+    let engine = app
+        .engines
+        .get(&id)
+        .ok_or_else(|| format!("engine {} not found", id.to_text()))?;
+    let agent = if agent.is_empty() || agent == "default" {
+        engine.default_agent()
+    } else {
+        agent.to_ascii_lowercase()
+    };
+    Ok((engine, agent))
+}
+
+/// Extracts the text of a message content.
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                Value::String(text) => Some(text.as_str()),
+                Value::Object(obj) => obj.get("text").and_then(|v| v.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// Builds the agent prompt from the chat messages.
+fn build_prompt(messages: &[ChatMessage]) -> Result<String, String> {
+    let (last, history) = messages
+        .split_last()
+        .ok_or_else(|| "messages must not be empty".to_string())?;
+    if last.role != "user" {
+        return Err(format!(
+            "the last message must be from the user, got {:?}",
+            last.role
+        ));
+    }
+
+    let prompt = content_text(&last.content);
+    if history.is_empty() {
+        return Ok(prompt);
+    }
+
+    let history = history
+        .iter()
+        .map(|msg| {
+            let role = match &msg.name {
+                Some(name) => format!("{} ({})", msg.role, name),
+                None => msg.role.clone(),
+            };
+            format!("{}: {}", role, content_text(&msg.content))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(format!(
+        "<conversation_history>\n{history}\n</conversation_history>\n\n{prompt}"
+    ))
+}
+
+fn to_usage(output: &AgentOutput) -> ChatCompletionUsage {
+    ChatCompletionUsage {
+        prompt_tokens: output.usage.input_tokens,
+        completion_tokens: output.usage.output_tokens,
+        total_tokens: output
+            .usage
+            .input_tokens
+            .saturating_add(output.usage.output_tokens),
+    }
+}
+
+fn finish_reason(output: &AgentOutput) -> String {
+    if output
+        .tool_calls
+        .as_ref()
+        .is_some_and(|calls| !calls.is_empty())
+    {
+        "tool_calls".to_string()
+    } else {
+        "stop".to_string()
+    }
+}
+
+fn chunk(
+    id: &str,
+    created: u64,
+    model: &str,
+    delta: ChatCompletionDelta,
+    finish_reason: Option<String>,
+    usage: Option<ChatCompletionUsage>,
+) -> Event {
+    let chunk = ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: model.to_string(),
+        choices: vec![ChatCompletionChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+        }],
+        usage,
+    };
+    Event::default().data(serde_json::to_string(&chunk).unwrap_or_default())
+}
+
+/// GET /v1/models
+pub async fn list_models(State(app): State<AppState>) -> impl IntoResponse {
+    let created = app.start_time_ms / 1000;
+    let mut data = Vec::new();
+    for (id, engine) in app.engines.iter() {
+        let info = engine.information();
+        for agent in info.agents {
+            let name = agent.definition.name;
+            if *id == app.default_engine {
+                data.push(ModelObject {
+                    id: name.clone(),
+                    object: "model".to_string(),
+                    created,
+                    owned_by: id.to_text(),
+                });
+            }
+            data.push(ModelObject {
+                id: format!("{}/{}", id.to_text(), name),
+                object: "model".to_string(),
+                created,
+                owned_by: id.to_text(),
+            });
+        }
+    }
+
+    Json(ModelList {
+        object: "list".to_string(),
+        data,
+    })
+}
+
+/// POST /v1/chat/completions
+pub async fn chat_completions(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    ct: ContentWithSHA3<ChatCompletionRequest>,
+) -> Response {
+    let (req, hash) = match ct {
+        ContentWithSHA3::CBOR(req, hash) => (req, hash),
+        ContentWithSHA3::JSON(req, hash) => (req, hash),
+    };
+
+    let (engine, agent) = match resolve_model(&app, &req.model) {
+        Ok(v) => v,
+        Err(err) => return error_response(StatusCode::NOT_FOUND, err),
+    };
+    let prompt = match build_prompt(&req.messages) {
+        Ok(prompt) => prompt,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, err),
+    };
+
+    let caller = request_caller(&app, &headers, Some(engine.id()), Some(hash.as_slice()));
+
+    log::info!(
+        agent = agent.as_str(),
+        engine = engine.id().to_text(),
+        caller = caller.to_text(),
+        stream = req.stream;
+        "chat_completions",
+    );
+    let limit = app.rate_limit(&caller, &agent);
+    if let Some(limit) = limit.as_ref().filter(|l| !l.allowed) {
+        return apply_rate_limit_headers(
+            limit,
+            error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "rate limit exceeded, retry after {}s",
+                    limit.retry_after_secs
+                ),
+            ),
+        );
+    }
+    let permit = match app.enqueue(&agent, Priority::Interactive).await {
+        Ok(permit) => permit,
+        Err(err) => {
+            return with_rate_limit_headers(
+                limit.as_ref(),
+                error_response(StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            );
+        }
+    };
+
+    let input = AgentInput {
+        name: agent.clone(),
+        prompt,
+        resources: None,
+        meta: Some(RequestMeta {
+            engine: Some(engine.id()),
+            thread: None,
+            user: req.user,
+            priority: Priority::Interactive,
+            timeout_ms: None,
+            locale: request_locale(&headers),
+        }),
+    };
+    let id = format!("chatcmpl-{}", Xid::new());
+    let created = unix_ms() / 1000;
+    let model = req.model;
+
+    if !req.stream {
+        let res = match engine.agent_run(caller, input).await {
+            Ok(output) => Json(ChatCompletionResponse {
+                id,
+                object: "chat.completion".to_string(),
+                created,
+                model,
+                usage: to_usage(&output),
+                choices: vec![ChatCompletionChoice {
+                    index: 0,
+                    finish_reason: finish_reason(&output),
+                    message: ChatCompletionMessage {
+                        role: "assistant".to_string(),
+                        content: output.content,
+                    },
+                }],
+            })
+            .into_response(),
+            Err(err) => with_retry_after(
+                &err,
+                error_response(error_status(&err), format!("failed to run agent: {err}")),
+            ),
+        };
+        return with_rate_limit_headers(limit.as_ref(), res);
+    }
+
+    // The stream sends the role first to start the response, then the content as the agent's
+    // model generates it, or at once when the agent completes if the model doesn't stream,
+    // and the finish reason and usage at last.
+    let role = chunk(
+        &id,
+        created,
+        &model,
+        ChatCompletionDelta {
+            role: Some("assistant".to_string()),
+            content: None,
+        },
+        None,
+        None,
+    );
+    let rx = engine.agent_run_events(caller, input);
+    // the slot of the run queue is released when the stream ends
+    let rest = stream::unfold(
+        (rx, permit, false),
+        move |(mut rx, permit, mut streamed)| {
+            let (id, model, agent) = (id.clone(), model.clone(), agent.clone());
+            async move {
+                let content = |content: String| {
+                    chunk(
+                        &id,
+                        created,
+                        &model,
+                        ChatCompletionDelta {
+                            role: None,
+                            content: Some(content),
+                        },
+                        None,
+                        None,
+                    )
+                };
+                let events = match rx.recv().await? {
+                    // the deltas of the agents called by the agent are not part of its output
+                    AgentEvent::Delta {
+                        agent: name,
+                        content: delta,
+                    } if name == agent => {
+                        streamed = true;
+                        vec![content(delta)]
+                    }
+                    AgentEvent::Output(output) => {
+                        let usage = to_usage(&output);
+                        let reason = finish_reason(&output);
+                        let mut events = Vec::new();
+                        if !streamed {
+                            events.push(content(output.content));
+                        }
+                        events.push(chunk(
+                            &id,
+                            created,
+                            &model,
+                            ChatCompletionDelta::default(),
+                            Some(reason),
+                            Some(usage),
+                        ));
+                        events
+                    }
+                    AgentEvent::Error { error } => {
+                        let err = ApiError {
+                            error: ApiErrorBody {
+                                message: format!("failed to run agent: {error}"),
+                                r#type: "server_error".to_string(),
+                            },
+                        };
+                        vec![Event::default().data(serde_json::to_string(&err).unwrap_or_default())]
+                    }
+                    _ => Vec::new(),
+                };
+                Some((stream::iter(events), (rx, permit, streamed)))
+            }
+        },
+    )
+    .flatten();
+
+    let events = stream::once(async move { role })
+        .chain(rest)
+        .chain(stream::once(async { Event::default().data("[DONE]") }))
+        .map(Ok::<Event, Infallible>);
+    with_rate_limit_headers(
+        limit.as_ref(),
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_build_prompt() {
+        let msgs: Vec<ChatMessage> = serde_json::from_value(json!([
+            {"role": "user", "content": "Hello"}
+        ]))
+        .unwrap();
+        assert_eq!(build_prompt(&msgs).unwrap(), "Hello");
+
+        let msgs: Vec<ChatMessage> = serde_json::from_value(json!([
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello!"},
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+                {"type": "text", "text": "ICP?"}
+            ]}
+        ]))
+        .unwrap();
+        assert_eq!(
+            build_prompt(&msgs).unwrap(),
+            "<conversation_history>\nsystem: Be brief.\nuser: Hi\nassistant: Hello!\n</conversation_history>\n\nWhat is\nICP?"
+        );
+
+        assert!(build_prompt(&[]).is_err());
+        assert!(build_prompt(&msgs[..3]).is_err());
+    }
+}
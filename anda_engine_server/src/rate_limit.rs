@@ -0,0 +1,54 @@
+//! Per-caller rate limiting of agent runs and tool calls, see [`anda_engine::rate_limit`].
+//!
+//! Responses carry the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+//! headers, and rejected requests get `429 Too Many Requests` with `Retry-After`.
+
+use anda_core::{BoxError, Error, anda_error};
+use axum::response::Response;
+use http::HeaderValue;
+
+pub use anda_engine::rate_limit::{
+    RateLimit, RateLimitConfig, RateLimitDecision, RateLimiter, UNLIMITED_TIER,
+};
+
+/// Adds the rate limit headers of the decision to the response.
+pub(crate) fn apply_rate_limit_headers(limit: &RateLimitDecision, mut res: Response) -> Response {
+    let headers = res.headers_mut();
+    headers.insert("ratelimit-limit", HeaderValue::from(limit.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(limit.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(limit.reset_secs));
+    if !limit.allowed {
+        headers.insert(
+            http::header::RETRY_AFTER,
+            HeaderValue::from(limit.retry_after_secs),
+        );
+    }
+    res
+}
+
+/// Adds the rate limit headers to the response if the request was limited.
+pub(crate) fn with_rate_limit_headers(
+    limit: Option<&RateLimitDecision>,
+    res: Response,
+) -> Response {
+    match limit {
+        Some(limit) => apply_rate_limit_headers(limit, res),
+        None => res,
+    }
+}
+
+/// Adds the `Retry-After` header to the response if the engine rejected the request with
+/// [`Error::RateLimited`], by its rate limiter or a quota of the caller's tenant.
+pub(crate) fn with_retry_after(err: &BoxError, mut res: Response) -> Response {
+    if let Some(Error::RateLimited {
+        retry_after_ms: Some(ms),
+        ..
+    }) = anda_error(err)
+    {
+        res.headers_mut().insert(
+            http::header::RETRY_AFTER,
+            HeaderValue::from(ms.div_ceil(1000)),
+        );
+    }
+    res
+}
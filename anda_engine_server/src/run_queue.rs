@@ -0,0 +1,421 @@
+//! Bounded execution queue of agent runs and tool calls.
+//!
+//! A request runs once it gets a slot of its agent (or tool), if the agent is limited,
+//! and a slot of the server, if the server is limited. Requests without a free slot wait
+//! in the queue, up to `max_queued` requests at once and `queue_timeout_ms` each, the
+//! others are rejected with `503 Service Unavailable`. This keeps a burst of requests
+//! from exhausting the memory of the server or the quotas of the model providers.
+//!
+//! Batch requests (see [`Priority`]) are bounded by `max_batch_concurrency` and only take
+//! the slots that no interactive request is waiting for, so they never starve user-facing
+//! conversations.
+
+use anda_core::{BoxError, Priority};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{AcquireError, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+/// Default time a request may wait in the queue.
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle agents are pruned beyond this number.
+const MAX_AGENTS: usize = 10_000;
+
+/// Maximum time a waiting batch request sleeps without checking the free slots.
+const BATCH_POLL: Duration = Duration::from_millis(100);
+
+/// Run queue configuration of the server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunQueueConfig {
+    /// Maximum number of requests running at once, unlimited if not set.
+    pub max_concurrency: Option<usize>,
+    /// Maximum number of requests of each agent running at once, unlimited if not set.
+    pub max_concurrency_per_agent: Option<usize>,
+    /// Maximum number of requests running at once by agent name, overriding the above.
+    #[serde(default)]
+    pub agents: BTreeMap<String, usize>,
+    /// Maximum number of requests waiting for a slot, the others are rejected.
+    #[serde(default)]
+    pub max_queued: usize,
+    /// Maximum time a request may wait for a slot, 30s by default.
+    pub queue_timeout_ms: Option<u64>,
+    /// Maximum number of batch requests running at once, unlimited if not set.
+    pub max_batch_concurrency: Option<usize>,
+}
+
+/// Requests running and waiting for an agent.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AgentQueueStats {
+    pub running: usize,
+    pub queued: usize,
+}
+
+/// Metrics of the run queue.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RunQueueStats {
+    pub running: usize,
+    pub queued: usize,
+    pub max_concurrency: Option<usize>,
+    pub max_queued: usize,
+    /// Requests rejected because the queue was full.
+    pub rejected: u64,
+    /// Requests that waited too long in the queue.
+    pub timed_out: u64,
+    /// Agents with requests running or waiting.
+    pub agents: BTreeMap<String, AgentQueueStats>,
+    /// Batch requests running and waiting.
+    pub batch: AgentQueueStats,
+}
+
+struct Slot {
+    semaphore: Option<Arc<Semaphore>>,
+    running: AtomicUsize,
+    queued: AtomicUsize,
+}
+
+impl Slot {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            semaphore: limit.map(|n| Arc::new(Semaphore::new(n))),
+            running: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.running.load(Ordering::Relaxed) == 0 && self.queued.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// Counts a request as queued until it is dropped.
+struct Queued<'a>(&'a [Arc<Slot>]);
+
+impl<'a> Queued<'a> {
+    fn new(slots: &'a [Arc<Slot>]) -> Self {
+        for slot in slots {
+            slot.queued.fetch_add(1, Ordering::Relaxed);
+        }
+        Self(slots)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        for slot in self.0 {
+            slot.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A slot of the run queue, released when dropped.
+pub struct RunPermit {
+    permits: Vec<OwnedSemaphorePermit>,
+    slots: Vec<Arc<Slot>>,
+    released: Arc<Notify>,
+}
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        for slot in &self.slots {
+            slot.running.fetch_sub(1, Ordering::Relaxed);
+        }
+        // wakes up the waiting batch requests once the slots are free
+        self.permits.clear();
+        self.released.notify_waiters();
+    }
+}
+
+/// Run queue with global and per agent concurrency limits.
+pub struct RunQueue {
+    max_concurrency: Option<usize>,
+    max_concurrency_per_agent: Option<usize>,
+    limits: BTreeMap<String, usize>,
+    max_queued: usize,
+    queue_timeout: Duration,
+    global: Arc<Slot>,
+    batch: Arc<Slot>,
+    agents: Mutex<HashMap<String, Arc<Slot>>>,
+    released: Arc<Notify>,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+impl RunQueue {
+    /// Creates a run queue, checking that the limits are valid.
+    pub fn new(cfg: RunQueueConfig) -> Result<Self, BoxError> {
+        if cfg.max_concurrency == Some(0)
+            || cfg.max_concurrency_per_agent == Some(0)
+            || cfg.max_batch_concurrency == Some(0)
+        {
+            return Err("invalid run queue: max_concurrency must be positive".into());
+        }
+        if let Some((name, _)) = cfg.agents.iter().find(|(_, n)| **n == 0) {
+            return Err(
+                format!("invalid run queue: max_concurrency of {name:?} must be positive").into(),
+            );
+        }
+
+        Ok(Self {
+            max_concurrency: cfg.max_concurrency,
+            max_concurrency_per_agent: cfg.max_concurrency_per_agent,
+            limits: cfg.agents,
+            max_queued: cfg.max_queued,
+            queue_timeout: cfg
+                .queue_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_QUEUE_TIMEOUT),
+            global: Arc::new(Slot::new(cfg.max_concurrency)),
+            batch: Arc::new(Slot::new(cfg.max_batch_concurrency)),
+            agents: Mutex::new(HashMap::new()),
+            released: Arc::new(Notify::new()),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        })
+    }
+
+    /// Waits for a slot to run a request of the agent (or tool) with the priority.
+    /// Returns an error if the queue is full or the request waited too long.
+    pub async fn acquire(&self, agent: &str, priority: Priority) -> Result<RunPermit, BoxError> {
+        // the agent's slot first, so that waiting for it doesn't hold a slot of the server
+        let mut slots = vec![self.agent_slot(agent), self.global.clone()];
+        if !priority.is_interactive() {
+            slots.insert(0, self.batch.clone());
+        }
+        let deadline = tokio::time::Instant::now() + self.queue_timeout;
+        let mut queued: Option<Queued> = None;
+        let mut permits = Vec::with_capacity(2);
+        for slot in &slots {
+            let semaphore = match &slot.semaphore {
+                Some(semaphore) => semaphore.clone(),
+                None => continue,
+            };
+            if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                permits.push(permit);
+                continue;
+            }
+
+            if queued.is_none() {
+                let q = Queued::new(&slots);
+                if self.global.queued.load(Ordering::Relaxed) > self.max_queued {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(
+                        format!("run queue is full, {} requests waiting", self.max_queued).into(),
+                    );
+                }
+                queued = Some(q);
+            }
+            let acquired = if priority.is_interactive() {
+                tokio::time::timeout_at(deadline, semaphore.acquire_owned()).await
+            } else {
+                tokio::time::timeout_at(deadline, self.acquire_yielding(semaphore)).await
+            };
+            match acquired {
+                Ok(Ok(permit)) => permits.push(permit),
+                Ok(Err(err)) => return Err(format!("run queue closed: {err}").into()),
+                Err(_) => {
+                    self.timed_out.fetch_add(1, Ordering::Relaxed);
+                    return Err(format!(
+                        "timed out after {}ms in the run queue",
+                        self.queue_timeout.as_millis()
+                    )
+                    .into());
+                }
+            }
+        }
+        drop(queued);
+
+        for slot in &slots {
+            slot.running.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(RunPermit {
+            permits,
+            slots,
+            released: self.released.clone(),
+        })
+    }
+
+    /// Waits for a permit that no interactive request is waiting for.
+    /// Permits released go to the requests waiting in `acquire_owned` first,
+    /// so a batch request only gets the permits left over by them.
+    async fn acquire_yielding(
+        &self,
+        semaphore: Arc<Semaphore>,
+    ) -> Result<OwnedSemaphorePermit, AcquireError> {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => return Ok(permit),
+                Err(TryAcquireError::Closed) => return semaphore.acquire_owned().await,
+                Err(TryAcquireError::NoPermits) => {}
+            }
+            tokio::select! {
+                _ = released => {},
+                _ = tokio::time::sleep(BATCH_POLL) => {},
+            }
+        }
+    }
+
+    pub fn stats(&self) -> RunQueueStats {
+        let agents = self.agents.lock().expect("run queue lock poisoned");
+        RunQueueStats {
+            running: self.global.running.load(Ordering::Relaxed),
+            queued: self.global.queued.load(Ordering::Relaxed),
+            max_concurrency: self.max_concurrency,
+            max_queued: self.max_queued,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            agents: agents
+                .iter()
+                .filter(|(_, slot)| !slot.is_idle())
+                .map(|(name, slot)| {
+                    (
+                        name.clone(),
+                        AgentQueueStats {
+                            running: slot.running.load(Ordering::Relaxed),
+                            queued: slot.queued.load(Ordering::Relaxed),
+                        },
+                    )
+                })
+                .collect(),
+            batch: AgentQueueStats {
+                running: self.batch.running.load(Ordering::Relaxed),
+                queued: self.batch.queued.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    fn agent_slot(&self, agent: &str) -> Arc<Slot> {
+        let mut agents = self.agents.lock().expect("run queue lock poisoned");
+        if let Some(slot) = agents.get(agent) {
+            return slot.clone();
+        }
+        if agents.len() >= MAX_AGENTS {
+            // slots only referenced by the map have no request running or waiting
+            agents.retain(|_, slot| Arc::strong_count(slot) > 1);
+        }
+
+        let limit = self
+            .limits
+            .get(agent)
+            .copied()
+            .or(self.max_concurrency_per_agent);
+        let slot = Arc::new(Slot::new(limit));
+        agents.insert(agent.to_string(), slot.clone());
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_run_queue() {
+        assert!(
+            RunQueue::new(RunQueueConfig {
+                max_concurrency: Some(0),
+                ..Default::default()
+            })
+            .is_err()
+        );
+
+        let queue = RunQueue::new(RunQueueConfig {
+            max_concurrency: Some(2),
+            max_concurrency_per_agent: Some(1),
+            agents: BTreeMap::from([("b".to_string(), 2)]),
+            max_queued: 1,
+            queue_timeout_ms: Some(20),
+            max_batch_concurrency: None,
+        })
+        .unwrap();
+
+        let a1 = queue.acquire("a", Priority::Interactive).await.unwrap();
+        // the agent's limit is reached
+        assert!(queue.acquire("a", Priority::Interactive).await.is_err());
+        let stats = queue.stats();
+        assert_eq!(stats.running, 1);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.timed_out, 1);
+
+        let b1 = queue.acquire("b", Priority::Interactive).await.unwrap();
+        let stats = queue.stats();
+        assert_eq!(stats.running, 2);
+        assert_eq!(
+            stats.agents["b"],
+            AgentQueueStats {
+                running: 1,
+                queued: 0
+            }
+        );
+
+        // the server's limit is reached, one request may wait
+        let waiting = queue.acquire("b", Priority::Interactive);
+        tokio::pin!(waiting);
+        assert!(futures::poll!(&mut waiting).is_pending());
+        assert_eq!(queue.stats().queued, 1);
+        assert!(queue.acquire("c", Priority::Interactive).await.is_err());
+        assert_eq!(queue.stats().rejected, 1);
+
+        drop(a1);
+        let b2 = waiting.await.unwrap();
+        let stats = queue.stats();
+        assert_eq!(stats.running, 2);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.agents.len(), 1);
+        assert_eq!(stats.agents["b"].running, 2);
+
+        drop(b1);
+        drop(b2);
+        let stats = queue.stats();
+        assert_eq!(stats.running, 0);
+        assert!(stats.agents.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_run_queue_priority() {
+        let queue = RunQueue::new(RunQueueConfig {
+            max_concurrency: Some(1),
+            max_queued: 2,
+            max_batch_concurrency: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let i1 = queue.acquire("a", Priority::Interactive).await.unwrap();
+        let batch = queue.acquire("b", Priority::Batch);
+        tokio::pin!(batch);
+        assert!(futures::poll!(&mut batch).is_pending());
+        let interactive = queue.acquire("a", Priority::Interactive);
+        tokio::pin!(interactive);
+        assert!(futures::poll!(&mut interactive).is_pending());
+        assert_eq!(
+            queue.stats().batch,
+            AgentQueueStats {
+                running: 0,
+                queued: 1
+            }
+        );
+
+        // the interactive request waiting after the batch one runs first
+        drop(i1);
+        let i2 = interactive.await.unwrap();
+        assert!(futures::poll!(&mut batch).is_pending());
+        drop(i2);
+        let b1 = batch.await.unwrap();
+        let stats = queue.stats();
+        assert_eq!(stats.running, 1);
+        assert_eq!(stats.batch.running, 1);
+        drop(b1);
+        assert_eq!(queue.stats().batch, AgentQueueStats::default());
+    }
+}
@@ -1,5 +1,7 @@
-use anda_engine::context::Information;
+use anda_engine::context::{Information, Readiness};
 use candid::Principal;
+
+use crate::run_queue::RunQueueStats;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -9,3 +11,24 @@ pub struct AppInformation {
     pub caller: Principal,
     pub start_time_ms: u64,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct AgentCardQuery {
+    /// The engine of the card, the default engine if not set.
+    pub engine: Option<Principal>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HealthStatus {
+    pub status: String,
+    pub start_time_ms: u64,
+    pub uptime_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<RunQueueStats>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReadyStatus {
+    pub status: String,
+    pub engines: Vec<Readiness>,
+}
@@ -0,0 +1,400 @@
+//! WebSocket transport of agent runs.
+//!
+//! `GET /v1/agent_run/ws?engine=...&nonce=...&ts=...` upgrades to a WebSocket on which a client
+//! runs agents of the engine (the default engine if omitted) and receives their progress pushed
+//! by the server, for interactive clients that can't use server-sent events.
+//!
+//! A signed envelope of the upgrade request must target the engine and sign the digest of
+//! [`ws_digest`], with a nonce and a timestamp in milliseconds within 5 minutes of the server
+//! clock. A nonce is accepted once per signer, so a captured envelope can't be replayed.
+//! Without an envelope, the caller is resolved from an API key of the engine, or anonymous.
+//!
+//! Messages are JSON texts:
+//! - `{"id": "...", "method": "agent_run", "params": AgentInput}`: runs an agent of the engine,
+//!   rejected if `meta.engine` of the input is another engine;
+//! - `{"id": "...", "method": "cancel"}`: cancels the run.
+//!
+//! The server pushes the events of each run as `{"id": "...", "event": "...", "data": AgentEvent}`,
+//! named as the events of `POST /v1/agent_run`, and ending with `output` or `error`.
+//! Runs of a connection run concurrently, and are cancelled when it closes.
+//! The server closes a connection whose client doesn't keep up with the pushed messages.
+//! The caller is resolved once from the headers of the upgrade request.
+
+use anda_core::{AgentEvent, AgentInput, RequestMeta};
+use anda_engine::engine::RUN_EVENTS_CAPACITY;
+use axum::{
+    extract::{
+        Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use candid::Principal;
+use futures::{SinkExt, StreamExt};
+use ic_auth_verifier::envelope::{ANONYMOUS_PRINCIPAL, SignedEnvelope, unix_ms};
+use ic_cose_types::cose::sha3_256;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Mutex,
+};
+use tokio::{sync::mpsc, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use crate::handler::{AppState, request_caller, request_locale};
+
+/// The maximum skew of the timestamp of a signed upgrade request from the server clock.
+const WS_AUTH_WINDOW_MS: u64 = 5 * 60 * 1000;
+
+/// The maximum length of a nonce.
+const WS_NONCE_MAX_LEN: usize = 64;
+
+/// The query of the upgrade request.
+#[derive(Debug, Default, Deserialize)]
+pub struct WsQuery {
+    /// The engine to run agents of, the default engine if omitted.
+    pub engine: Option<String>,
+    /// A unique nonce of the signed upgrade request.
+    pub nonce: Option<String>,
+    /// The time of the signed upgrade request, in milliseconds.
+    pub ts: Option<u64>,
+}
+
+/// Returns the digest that a signed envelope of the upgrade request signs.
+pub fn ws_digest(engine: &Principal, nonce: &str, ts: u64) -> [u8; 32] {
+    sha3_256(
+        format!(
+            "GET /v1/agent_run/ws\n{}\n{}\n{}",
+            engine.to_text(),
+            nonce,
+            ts
+        )
+        .as_bytes(),
+    )
+}
+
+/// The nonces of the signed upgrade requests within the time window, to reject replays.
+#[derive(Debug, Default)]
+pub(crate) struct WsNonces(Mutex<HashMap<(Principal, String), u64>>);
+
+impl WsNonces {
+    /// Records the nonce of the signer, false if it has been used.
+    fn insert(&self, signer: Principal, nonce: String, ts: u64, now_ms: u64) -> bool {
+        let mut nonces = self.0.lock().expect("ws nonces lock poisoned");
+        nonces.retain(|_, expires_at| *expires_at > now_ms);
+        match nonces.entry((signer, nonce)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                // the timestamp is rejected after the window, so is the nonce
+                entry.insert(ts + WS_AUTH_WINDOW_MS);
+                true
+            }
+        }
+    }
+}
+
+/// A request of the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum WsRequest {
+    AgentRun { id: String, params: AgentInput },
+    Cancel { id: String },
+}
+
+/// Returns the message of the event of the run.
+fn ws_message(id: &str, event: &AgentEvent) -> String {
+    json!({"id": id, "event": event.name(), "data": event}).to_string()
+}
+
+/// Resolves the caller of the upgrade request to the engine, see the module doc.
+#[allow(clippy::result_large_err)]
+fn ws_caller(
+    app: &AppState,
+    headers: &http::HeaderMap,
+    engine: Principal,
+    query: WsQuery,
+) -> Result<Principal, Response> {
+    let unauthorized = |msg: &str| (StatusCode::UNAUTHORIZED, msg.to_string()).into_response();
+    if SignedEnvelope::from_authorization(headers)
+        .or_else(|| SignedEnvelope::from_headers(headers))
+        .is_none()
+    {
+        return Ok(request_caller(app, headers, Some(engine), None));
+    }
+
+    let (Some(nonce), Some(ts)) = (query.nonce, query.ts) else {
+        return Err(unauthorized(
+            "nonce and ts are required by a signed envelope",
+        ));
+    };
+    if nonce.is_empty() || nonce.len() > WS_NONCE_MAX_LEN {
+        return Err(unauthorized("invalid nonce"));
+    }
+    let now_ms = unix_ms();
+    if ts.abs_diff(now_ms) > WS_AUTH_WINDOW_MS {
+        return Err(unauthorized("ts is out of the time window"));
+    }
+    let digest = ws_digest(&engine, &nonce, ts);
+    let caller = request_caller(app, headers, Some(engine), Some(&digest));
+    if caller == ANONYMOUS_PRINCIPAL {
+        return Err(unauthorized("invalid signed envelope"));
+    }
+    if !app.ws_nonces.insert(caller, nonce, ts, now_ms) {
+        return Err(unauthorized("nonce has been used"));
+    }
+    Ok(caller)
+}
+
+/// GET /v1/agent_run/ws
+pub async fn agent_run_ws(
+    State(app): State<AppState>,
+    headers: http::HeaderMap,
+    Query(query): Query<WsQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let engine = match query.engine.as_deref() {
+        None | Some("default") => app.default_engine,
+        Some(id) => match Principal::from_text(id) {
+            Ok(id) => id,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid engine id: {id:?}"),
+                )
+                    .into_response();
+            }
+        },
+    };
+    if !app.engines.contains_key(&engine) {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("engine {} not found", engine.to_text()),
+        )
+            .into_response();
+    }
+    let caller = match ws_caller(&app, &headers, engine, query) {
+        Ok(caller) => caller,
+        Err(res) => return res,
+    };
+    let locale = request_locale(&headers);
+    log::info!(engine = engine.to_text(), caller = caller.to_text(); "agent_run_ws");
+    ws.on_upgrade(move |socket| serve_socket(app, engine, caller, locale, socket))
+}
+
+/// The queue of the messages pushed to the client.
+#[derive(Clone)]
+struct Outbox {
+    tx: mpsc::Sender<String>,
+    // cancelled when the queue is full
+    overflow: CancellationToken,
+}
+
+impl Outbox {
+    /// Queues a message, or marks the connection to close if the client doesn't keep up.
+    /// Returns false if the message is dropped.
+    fn push(&self, text: String) -> bool {
+        match self.tx.try_send(text) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflow.cancel();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+async fn serve_socket(
+    app: AppState,
+    engine: Principal,
+    caller: Principal,
+    locale: Option<String>,
+    socket: WebSocket,
+) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::channel::<String>(RUN_EVENTS_CAPACITY);
+    let tx = Outbox {
+        tx,
+        overflow: CancellationToken::new(),
+    };
+    // the runs are aborted when the set is dropped
+    let mut runs: JoinSet<()> = JoinSet::new();
+    let mut handles: HashMap<String, tokio::task::AbortHandle> = HashMap::new();
+    loop {
+        tokio::select! {
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    handles.retain(|_, handle| !handle.is_finished());
+                    match serde_json::from_str::<WsRequest>(&text) {
+                        Ok(WsRequest::AgentRun { id, params }) => {
+                            if handles.contains_key(&id) {
+                                let error = format!("run {} is running", id);
+                                tx.push(ws_message(&id, &AgentEvent::Error { error }));
+                                continue;
+                            }
+                            let handle = runs.spawn(run_agent(
+                                app.clone(),
+                                engine,
+                                caller,
+                                locale.clone(),
+                                id.clone(),
+                                params,
+                                tx.clone(),
+                            ));
+                            handles.insert(id, handle);
+                        }
+                        Ok(WsRequest::Cancel { id }) => {
+                            if let Some(handle) = handles.remove(&id) {
+                                handle.abort();
+                                let error = "run cancelled".to_string();
+                                tx.push(ws_message(&id, &AgentEvent::Error { error }));
+                            }
+                        }
+                        Err(err) => {
+                            let error = format!("invalid request: {}", err);
+                            tx.push(ws_message("", &AgentEvent::Error { error }));
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+            Some(text) = rx.recv() => {
+                if sink.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            Some(_) = runs.join_next(), if !runs.is_empty() => {}
+            _ = tx.overflow.cancelled() => {
+                let frame = CloseFrame {
+                    code: close_code::AGAIN,
+                    reason: "too many pending messages".into(),
+                };
+                let _ = sink.send(Message::Close(Some(frame))).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Runs the agent of the engine and sends its events, admitted by the rate limiter and the
+/// run queue.
+async fn run_agent(
+    app: AppState,
+    engine_id: Principal,
+    caller: Principal,
+    locale: Option<String>,
+    id: String,
+    mut input: AgentInput,
+    tx: Outbox,
+) {
+    let send_error = |error: String| {
+        tx.push(ws_message(&id, &AgentEvent::Error { error }));
+    };
+    let meta = input.meta.get_or_insert_with(RequestMeta::default);
+    if meta.engine.is_some_and(|id| id != engine_id) {
+        return send_error(format!(
+            "the connection is bound to engine {}",
+            engine_id.to_text()
+        ));
+    }
+    meta.engine = Some(engine_id);
+    if let Some(locale) = locale {
+        meta.locale.get_or_insert(locale);
+    }
+    let Some(engine) = app.engines.get(&engine_id).cloned() else {
+        return send_error(format!("engine {} not found", engine_id.to_text()));
+    };
+
+    if let Some(limit) = app.rate_limit(&caller, &input.name).filter(|l| !l.allowed) {
+        return send_error(format!(
+            "rate limit exceeded, retry after {}s",
+            limit.retry_after_secs
+        ));
+    }
+    let priority = input.meta.as_ref().map(|m| m.priority).unwrap_or_default();
+    let _permit = match app.enqueue(&input.name, priority).await {
+        Ok(permit) => permit,
+        Err(err) => return send_error(err.to_string()),
+    };
+
+    // the run is cancelled when the receiver is dropped
+    let mut rx = engine.agent_run_events(caller, input);
+    while let Some(event) = rx.recv().await {
+        if !tx.push(ws_message(&id, &event)) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_ws_messages() {
+        let req: WsRequest = serde_json::from_value(json!({
+            "id": "1",
+            "method": "agent_run",
+            "params": {"name": "assistant", "prompt": "hello"},
+        }))
+        .unwrap();
+        assert!(
+            matches!(req, WsRequest::AgentRun { ref id, ref params } if id == "1" && params.prompt == "hello")
+        );
+        let req: WsRequest =
+            serde_json::from_value(json!({"id": "1", "method": "cancel"})).unwrap();
+        assert!(matches!(req, WsRequest::Cancel { ref id } if id == "1"));
+
+        let msg = ws_message(
+            "1",
+            &AgentEvent::Delta {
+                agent: "assistant".to_string(),
+                content: "hi".to_string(),
+            },
+        );
+        let msg: Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["id"], "1");
+        assert_eq!(msg["event"], "delta");
+    }
+
+    #[test]
+    fn test_ws_auth() {
+        let engine = Principal::management_canister();
+        let digest = ws_digest(&engine, "n1", 1000);
+        assert_eq!(digest, ws_digest(&engine, "n1", 1000));
+        assert_ne!(digest, ws_digest(&engine, "n2", 1000));
+        assert_ne!(digest, ws_digest(&engine, "n1", 1001));
+        assert_ne!(digest, ws_digest(&Principal::anonymous(), "n1", 1000));
+
+        let nonces = WsNonces::default();
+        let signer = Principal::management_canister();
+        assert!(nonces.insert(signer, "n1".to_string(), 1000, 1000));
+        assert!(!nonces.insert(signer, "n1".to_string(), 1000, 2000));
+        assert!(nonces.insert(Principal::anonymous(), "n1".to_string(), 1000, 2000));
+        // pruned after the window
+        let later = 1000 + WS_AUTH_WINDOW_MS + 1;
+        assert!(nonces.insert(signer, "n1".to_string(), later, later));
+    }
+
+    #[test]
+    fn test_ws_outbox() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let outbox = Outbox {
+            tx,
+            overflow: CancellationToken::new(),
+        };
+        assert!(outbox.push("1".to_string()));
+        assert!(outbox.push("2".to_string()));
+        assert!(!outbox.overflow.is_cancelled());
+        // a full queue closes the connection
+        assert!(!outbox.push("3".to_string()));
+        assert!(outbox.overflow.is_cancelled());
+        assert_eq!(rx.try_recv().unwrap(), "1");
+        assert_eq!(rx.try_recv().unwrap(), "2");
+        assert!(rx.try_recv().is_err());
+    }
+}
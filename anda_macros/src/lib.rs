@@ -0,0 +1,166 @@
+//! Derive macros for Anda, re-exported by `anda_core`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DataStruct, DeriveInput, Expr, Fields, LitStr, Token, parse_macro_input, spanned::Spanned,
+};
+
+/// Derives `anda_core::ToolArgs` for the arguments of a tool.
+///
+/// The type must also derive `serde::Deserialize` and `schemars::JsonSchema`, which generates
+/// the JSON schema with the doc comments as descriptions. The `#[validate(...)]` attributes of
+/// the named fields, which schemars adds to the schema, are checked by `ToolArgs::validate`:
+/// - `range(min = .., max = ..)`: the number is in the range;
+/// - `length(min = .., max = .., equal = ..)`: the length of the string or collection;
+/// - `required`: the `Option` is `Some`.
+///
+/// Other validations, such as `regex` or `email`, are only in the schema.
+#[proc_macro_derive(ToolArgs, attributes(validate))]
+pub fn derive_tool_args(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut checks = Vec::new();
+    if let Data::Struct(DataStruct {
+        fields: Fields::Named(fields),
+        ..
+    }) = &input.data
+    {
+        for field in &fields.named {
+            let ident = field.ident.as_ref().expect("named field");
+            let field_name = serde_name(field)?
+                .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+            for attr in &field.attrs {
+                if attr.path().is_ident("validate") {
+                    checks.extend(field_checks(attr, ident, &field_name)?);
+                }
+            }
+        }
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::anda_core::ToolArgs for #name #ty_generics #where_clause {
+            fn validate(&self) -> ::core::result::Result<(), ::std::string::String> {
+                #(#checks)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+/// Returns the name of the field given by `#[serde(rename = "...")]`, if any.
+fn serde_name(field: &syn::Field) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("serde") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(Token![=]) {
+                let value: LitStr = meta.value()?.parse()?;
+                name = Some(value.value());
+            } else {
+                skip_meta(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
+
+fn field_checks(
+    attr: &syn::Attribute,
+    ident: &syn::Ident,
+    field_name: &str,
+) -> syn::Result<Vec<TokenStream2>> {
+    let mut checks = Vec::new();
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("range") {
+            let (min, max, _) = parse_bounds(&meta, false)?;
+            let min = option_tokens(min.map(|v| quote!((#v) as f64)));
+            let max = option_tokens(max.map(|v| quote!((#v) as f64)));
+            checks.push(quote! {
+                ::anda_core::validate_range(&self.#ident, #field_name, #min, #max)?;
+            });
+        } else if meta.path.is_ident("length") {
+            let (min, max, equal) = parse_bounds(&meta, true)?;
+            let (min, max) = match equal {
+                Some(equal) => (Some(equal.clone()), Some(equal)),
+                None => (min, max),
+            };
+            let min = option_tokens(min.map(|v| quote!((#v) as usize)));
+            let max = option_tokens(max.map(|v| quote!((#v) as usize)));
+            checks.push(quote! {
+                ::anda_core::validate_length(&self.#ident, #field_name, #min, #max)?;
+            });
+        } else if meta.path.is_ident("required") {
+            checks.push(quote! {
+                if self.#ident.is_none() {
+                    return ::core::result::Result::Err(
+                        ::std::format!("`{}` is required", #field_name),
+                    );
+                }
+            });
+        } else {
+            // only in the schema
+            skip_meta(&meta)?;
+        }
+        Ok(())
+    })?;
+    Ok(checks)
+}
+
+/// Parses `(min = .., max = ..)`, and `equal = ..` for lengths.
+fn parse_bounds(
+    meta: &syn::meta::ParseNestedMeta,
+    with_equal: bool,
+) -> syn::Result<(Option<Expr>, Option<Expr>, Option<Expr>)> {
+    let (mut min, mut max, mut equal) = (None, None, None);
+    meta.parse_nested_meta(|m| {
+        let value: Expr = m.value()?.parse()?;
+        if m.path.is_ident("min") {
+            min = Some(value);
+        } else if m.path.is_ident("max") {
+            max = Some(value);
+        } else if with_equal && m.path.is_ident("equal") {
+            equal = Some(value);
+        } else {
+            return Err(m.error("unsupported bound"));
+        }
+        Ok(())
+    })?;
+    if min.is_none() && max.is_none() && equal.is_none() {
+        return Err(syn::Error::new(
+            meta.path.span(),
+            "expected min, max or equal",
+        ));
+    }
+    Ok((min, max, equal))
+}
+
+/// Skips `name`, `name = value` or `name(...)`.
+fn skip_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        let _: Expr = meta.value()?.parse()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|m| skip_meta(&m))?;
+    }
+    Ok(())
+}
+
+fn option_tokens(value: Option<TokenStream2>) -> TokenStream2 {
+    match value {
+        Some(v) => quote!(::core::option::Option::Some(#v)),
+        None => quote!(::core::option::Option::None),
+    }
+}
@@ -1,5 +1,9 @@
-use anda_core::{BoxError, BoxPinFut, HttpFeatures, RPCRequestRef, cbor_rpc};
-use anda_engine::context::Web3ClientFeatures;
+use anda_core::{
+    BoxError, BoxPinFut, CancellationToken, CanisterCallError, HttpFeatures, HttpOptions,
+    RPCRequestRef, RedirectPolicy, RetryPolicy, WebSocket, WsOptions, apply_http_options, cbor_rpc,
+    domain_match, http_retry,
+};
+use anda_engine::context::{Web3ClientFeatures, websocket_connect};
 use arc_swap::ArcSwap;
 use candid::{
     CandidType, Decode, Principal,
@@ -7,7 +11,10 @@ use candid::{
 };
 use ciborium::from_reader;
 use ed25519_consensus::SigningKey;
-use ic_agent::identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity};
+use ic_agent::{
+    AgentError,
+    identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity},
+};
 use ic_auth_verifier::envelope::SignedEnvelope;
 use ic_cose::client::CoseSDK;
 use ic_cose_types::{
@@ -21,12 +28,22 @@ use ic_cose_types::{
 };
 use ic_tee_gateway_sdk::crypto;
 use serde::{Serialize, de::DeserializeOwned};
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 pub use ic_agent::{Agent, Identity};
 
 use anda_engine::APP_USER_AGENT;
 
+use crate::{
+    proxy::ProxyConfig,
+    signature::sign_http_message,
+    tls::{DomainTls, TlsConfig, pinned_cert_config},
+};
+
 /// Client for interacting with outside services (includes ICP and other blockchains)
 ///
 /// Provides cryptographic operations, canister communication, and HTTP features.
@@ -35,22 +52,46 @@ use anda_engine::APP_USER_AGENT;
 #[derive(Clone)]
 pub struct Client {
     outer_http: reqwest::Client,
+    /// HTTP clients with per-domain TLS trust, ordered by the most specific domain first.
+    domain_http: Arc<Vec<(String, reqwest::Client)>>,
     root_secret: [u8; 48],
     identity: Arc<ArcSwap<Arc<dyn Identity>>>,
     agent: Arc<ArcSwap<Agent>>,
     agent_owned: Agent,
     cose_canister: Principal,
     allow_http: bool,
+    /// Configuration to build HTTP clients for per-request options, `None` for a custom client.
+    http_conf: Option<Arc<(ProxyConfig, TlsConfig)>>,
+    /// HTTP clients built for per-request options, keyed by TLS domain and client-level options.
+    options_http: Arc<RwLock<BTreeMap<OptionsKey, reqwest::Client>>>,
+    /// Emits RFC 9421 HTTP Message Signatures in signed calls.
+    http_signatures: bool,
+    /// Retry policy of the HTTPs calls without per-request options.
+    http_retry: Option<RetryPolicy>,
 }
 
+type OptionsKey = (
+    Option<String>,
+    Option<Duration>,
+    Option<Duration>,
+    RedirectPolicy,
+);
+
+/// Maximum number of HTTP clients cached for per-request options.
+const MAX_OPTIONS_CLIENTS: usize = 64;
+
 /// Builder for creating a new Client with custom configuration
 pub struct ClientBuilder {
     ic_host: String,
     root_secret: [u8; 48],
     identity: Arc<dyn Identity>,
     cose_canister: Principal,
-    outer_http: reqwest::Client,
+    outer_http: Option<reqwest::Client>,
     allow_http: bool,
+    proxy: ProxyConfig,
+    tls: TlsConfig,
+    http_signatures: bool,
+    http_retry: Option<RetryPolicy>,
 }
 
 /// Returns a new Ed25519 identity from a 32-byte secret
@@ -59,6 +100,28 @@ pub fn identity_from_secret(id_secret: [u8; 32]) -> Box<dyn Identity> {
     Box::new(BasicIdentity::from_signing_key(sk))
 }
 
+/// Converts an [`AgentError`] into a boxed [`CanisterCallError`] preserving the reject code.
+fn canister_call_error(
+    canister: Principal,
+    method: String,
+    update: bool,
+    err: AgentError,
+) -> BoxError {
+    match err {
+        AgentError::CertifiedReject { reject, .. }
+        | AgentError::UncertifiedReject { reject, .. } => CanisterCallError::from_reject(
+            canister,
+            method,
+            update,
+            reject.reject_code as u64,
+            reject.reject_message,
+            reject.error_code,
+        )
+        .into(),
+        err => CanisterCallError::new(canister, method, update, err.to_string()).into(),
+    }
+}
+
 /// Loads an ICP identity from a PEM file (generated by dfx or other tools, supports both Ed25519 and Secp256k1)
 pub fn identity_from_pem(path: &str) -> Result<Box<dyn Identity>, BoxError> {
     let content = std::fs::read_to_string(path)?;
@@ -96,19 +159,12 @@ impl Default for ClientBuilder {
             root_secret: [0; 48],
             identity: Arc::new(AnonymousIdentity),
             cose_canister: Principal::anonymous(),
-            outer_http: reqwest::Client::builder()
-                .use_rustls_tls()
-                .https_only(true)
-                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
-                .http2_keep_alive_timeout(Duration::from_secs(15))
-                .http2_keep_alive_while_idle(true)
-                .connect_timeout(Duration::from_secs(10))
-                .timeout(Duration::from_secs(360))
-                .gzip(true)
-                .user_agent(APP_USER_AGENT)
-                .build()
-                .expect("Could not create HTTP client"),
+            outer_http: None,
             allow_http: false,
+            proxy: ProxyConfig::default(),
+            tls: TlsConfig::default(),
+            http_signatures: false,
+            http_retry: None,
         }
     }
 }
@@ -138,9 +194,10 @@ impl ClientBuilder {
         self
     }
 
-    /// Sets the external HTTP client for making requests, default is a secure client
+    /// Sets the external HTTP client for making requests, default is a secure client.
+    /// A custom client ignores the proxy configuration.
     pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
-        self.outer_http = http_client;
+        self.outer_http = Some(http_client);
         self
     }
 
@@ -151,28 +208,59 @@ impl ClientBuilder {
         http_client: Option<reqwest::Client>,
     ) -> Self {
         self.allow_http = allow_http;
-        self.outer_http = http_client.unwrap_or_else(|| {
-            reqwest::Client::builder()
-                .use_rustls_tls()
-                .https_only(!allow_http)
-                .http2_keep_alive_interval(Some(Duration::from_secs(25)))
-                .http2_keep_alive_timeout(Duration::from_secs(15))
-                .http2_keep_alive_while_idle(true)
-                .connect_timeout(Duration::from_secs(10))
-                .timeout(Duration::from_secs(360))
-                .user_agent(APP_USER_AGENT)
-                .build()
-                .expect("Could not create HTTP client")
-        });
+        self.outer_http = http_client;
+        self
+    }
+
+    /// Sets the egress proxy configuration (global and per-domain) for outbound requests
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Sets per-domain root CAs and pinned certificates for outbound HTTPS requests
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Emits RFC 9421 HTTP Message Signatures (`Signature-Input` and `Signature` headers)
+    /// in `https_signed_call`, in addition to the Anda `Authorization` envelope (default is false)
+    pub fn with_http_message_signatures(mut self, enable: bool) -> Self {
+        self.http_signatures = enable;
+        self
+    }
+
+    /// Retries the transient failures of `https_call`, the calls with per-request options
+    /// use their own retry policy (default is no retries)
+    pub fn with_http_retry(mut self, policy: RetryPolicy) -> Self {
+        self.http_retry = Some(policy);
         self
     }
 
     pub async fn build(self) -> Result<Client, BoxError> {
+        let outer_http = match &self.outer_http {
+            Some(client) => client.clone(),
+            None => http_client_builder(self.allow_http, &self.proxy)
+                .redirect(self.tls.redirect_policy(RedirectPolicy::Default, None))
+                .build()?,
+        };
+
+        let mut domain_http = Vec::with_capacity(self.tls.domains().len());
+        for (domain, tls) in self.tls.domains() {
+            let builder = http_client_builder(self.allow_http, &self.proxy).redirect(
+                self.tls
+                    .redirect_policy(RedirectPolicy::Default, Some(domain)),
+            );
+            domain_http.push((domain.clone(), with_domain_tls(builder, tls)?.build()?));
+        }
+        domain_http.sort_by_key(|(domain, _)| std::cmp::Reverse(domain.len()));
+
         let agent = Agent::builder()
             .with_url(&self.ic_host)
             .with_verify_query_signatures(false)
             .with_arc_identity(self.identity.clone())
-            .with_http_client(self.outer_http.clone());
+            .with_http_client(outer_http.clone());
 
         let agent = if self.ic_host.starts_with("https://") {
             agent.with_background_dynamic_routing().build()?
@@ -185,17 +273,58 @@ impl ClientBuilder {
         }
 
         Ok(Client {
-            outer_http: self.outer_http,
+            outer_http,
+            domain_http: Arc::new(domain_http),
             root_secret: self.root_secret,
             identity: Arc::new(ArcSwap::from_pointee(self.identity)),
             agent: Arc::new(ArcSwap::from_pointee(agent.clone())),
             agent_owned: agent,
             cose_canister: self.cose_canister,
             allow_http: self.allow_http,
+            http_conf: match self.outer_http {
+                Some(_) => None,
+                None => Some(Arc::new((self.proxy, self.tls))),
+            },
+            options_http: Arc::new(RwLock::new(BTreeMap::new())),
+            http_signatures: self.http_signatures,
+            http_retry: self.http_retry,
         })
     }
 }
 
+fn http_client_builder(allow_http: bool, proxy: &ProxyConfig) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .https_only(!allow_http)
+        .http2_keep_alive_interval(Some(Duration::from_secs(25)))
+        .http2_keep_alive_timeout(Duration::from_secs(15))
+        .http2_keep_alive_while_idle(true)
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(360))
+        .gzip(true)
+        .user_agent(APP_USER_AGENT);
+    if !proxy.is_empty() {
+        builder = builder.proxy(proxy.to_reqwest_proxy());
+    }
+    builder
+}
+
+fn with_domain_tls(
+    mut builder: reqwest::ClientBuilder,
+    tls: &DomainTls,
+) -> Result<reqwest::ClientBuilder, BoxError> {
+    if !tls.server_certs.is_empty() {
+        return Ok(builder.use_preconfigured_tls(pinned_cert_config(&tls.server_certs)?));
+    }
+    if tls.pinned {
+        builder = builder.tls_built_in_root_certs(false);
+    }
+    for cert in &tls.certs {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    Ok(builder)
+}
+
 impl Client {
     pub fn builder() -> ClientBuilder {
         ClientBuilder::default()
@@ -209,6 +338,174 @@ impl Client {
             .expect("Failed to get sender principal")
     }
 
+    /// Returns the most specific TLS domain and its HTTP client for the URL
+    fn domain_http_client(&self, url: &str) -> Option<&(String, reqwest::Client)> {
+        if self.domain_http.is_empty() {
+            return None;
+        }
+
+        let host = reqwest::Url::parse(url)
+            .ok()?
+            .host_str()?
+            .to_ascii_lowercase();
+        self.domain_http
+            .iter()
+            .find(|(domain, _)| domain_match(domain, &host))
+    }
+
+    /// Returns the HTTP client for the URL, honoring per-domain TLS configuration
+    fn http_client(&self, url: &str) -> &reqwest::Client {
+        match self.domain_http_client(url) {
+            Some((_, client)) => client,
+            None => &self.outer_http,
+        }
+    }
+
+    /// Returns the HTTP client for the URL with client-level options applied
+    /// (connect timeout, read timeout and redirect policy).
+    /// Clients are built on demand and reused. Returns `None` if the options can't be applied,
+    /// i.e. a custom HTTP client is used.
+    fn http_client_with_options(
+        &self,
+        url: &str,
+        opts: &HttpOptions,
+    ) -> Result<Option<reqwest::Client>, BoxError> {
+        if !opts.requires_client() {
+            return Ok(Some(self.http_client(url).clone()));
+        }
+        let conf = match &self.http_conf {
+            Some(conf) => conf,
+            None => return Ok(None),
+        };
+
+        let domain = self.domain_http_client(url).map(|(d, _)| d.clone());
+        let key: OptionsKey = (
+            domain.clone(),
+            opts.connect_timeout,
+            opts.read_timeout,
+            opts.redirect,
+        );
+        if let Some(client) = self.options_http.read().unwrap().get(&key) {
+            return Ok(Some(client.clone()));
+        }
+
+        let (proxy, tls) = conf.as_ref();
+        let mut builder = http_client_builder(self.allow_http, proxy)
+            .redirect(tls.redirect_policy(opts.redirect, domain.as_deref()));
+        if let Some(domain_tls) = domain.as_ref().and_then(|d| tls.domains().get(d)) {
+            builder = with_domain_tls(builder, domain_tls)?;
+        }
+        if let Some(timeout) = opts.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = opts.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        let client = builder.build()?;
+        let mut cache = self.options_http.write().unwrap();
+        // Timeouts come from callers, so bound the number of cached clients.
+        if cache.len() >= MAX_OPTIONS_CLIENTS && !cache.contains_key(&key) {
+            cache.pop_first();
+        }
+        cache.insert(key, client.clone());
+        Ok(Some(client))
+    }
+
+    /// Returns the HTTP client for signed requests to the URL, it doesn't follow redirects
+    /// so that the signature is only sent to the signed URL.
+    /// A custom HTTP client is used as is.
+    fn signed_http_client(&self, url: &str) -> Result<reqwest::Client, BoxError> {
+        let opts = HttpOptions::new().with_redirect(RedirectPolicy::None);
+        Ok(self
+            .http_client_with_options(url, &opts)?
+            .unwrap_or_else(|| self.http_client(url).clone()))
+    }
+
+    /// Adds RFC 9421 HTTP Message Signature headers if enabled
+    fn sign_http_message(
+        &self,
+        method: &http::Method,
+        url: &str,
+        headers: &mut http::HeaderMap,
+        body: Option<&[u8]>,
+    ) -> Result<(), BoxError> {
+        if !self.http_signatures {
+            return Ok(());
+        }
+        sign_http_message(
+            self.identity.load().as_ref().as_ref(),
+            method,
+            url,
+            headers,
+            body,
+        )
+    }
+
+    /// Sends an HTTPs request with per-request options
+    async fn send_with_options(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        if !self.allow_http && !url.starts_with("https://") {
+            return Err("Invalid url, must start with https://".into());
+        }
+
+        // falls back to the default client, only total timeout and size limit are applied
+        let http = self
+            .http_client_with_options(&url, &opts)?
+            .unwrap_or_else(|| self.http_client(&url).clone());
+        let mut req = http.request(method, url);
+        if let Some(headers) = headers {
+            req = req.headers(headers);
+        }
+        if let Some(body) = body {
+            req = req.body(body);
+        }
+
+        apply_http_options(async { req.send().await.map_err(|e| e.into()) }, &opts).await
+    }
+
+    /// Sends the request with options, retrying transient failures if `opts.retry` is set.
+    async fn send_with_retry(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        match opts.retry.clone() {
+            Some(policy) => {
+                http_retry(&policy, &method, &CancellationToken::new(), || {
+                    self.send_with_options(
+                        url.clone(),
+                        method.clone(),
+                        headers.clone(),
+                        body.clone(),
+                        opts.clone(),
+                    )
+                })
+                .await
+            }
+            None => {
+                self.send_with_options(url, method, headers, body, opts)
+                    .await
+            }
+        }
+    }
+
+    /// Returns the options of the HTTPs calls without per-request options.
+    fn default_http_options(&self) -> HttpOptions {
+        HttpOptions {
+            retry: self.http_retry.clone(),
+            ..Default::default()
+        }
+    }
+
     pub fn set_identity(&self, identity: Arc<dyn Identity>) {
         let mut agent = self.agent_owned.clone();
         agent.set_identity(identity.clone());
@@ -394,7 +691,12 @@ impl Web3ClientFeatures for Client {
     ) -> BoxPinFut<Result<Vec<u8>, BoxError>> {
         let agent = self.agent.load().clone();
         Box::pin(async move {
-            let res = agent.query(&canister, method).with_arg(args).call().await?;
+            let res = agent
+                .query(&canister, &method)
+                .with_arg(args)
+                .call()
+                .await
+                .map_err(|err| canister_call_error(canister, method, false, err))?;
             Ok(res)
         })
     }
@@ -408,10 +710,11 @@ impl Web3ClientFeatures for Client {
         let agent = self.agent.load().clone();
         Box::pin(async move {
             let res = agent
-                .update(&canister, method)
+                .update(&canister, &method)
                 .with_arg(args)
                 .call_and_wait()
-                .await?;
+                .await
+                .map_err(|err| canister_call_error(canister, method, true, err))?;
             Ok(res)
         })
     }
@@ -423,23 +726,25 @@ impl Web3ClientFeatures for Client {
         headers: Option<http::HeaderMap>,
         body: Option<Vec<u8>>, // default is empty
     ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
-        if !self.allow_http && !url.starts_with("https://") {
-            return Box::pin(futures::future::ready(Err(
-                "Invalid url, must start with https://".into(),
-            )));
-        }
-
-        let outer_http = self.outer_http.clone();
+        let this = self.clone();
         Box::pin(async move {
-            let mut req = outer_http.request(method, url);
-            if let Some(headers) = headers {
-                req = req.headers(headers);
-            }
-            if let Some(body) = body {
-                req = req.body(body);
-            }
+            let opts = this.default_http_options();
+            this.send_with_retry(url, method, headers, body, opts).await
+        })
+    }
 
-            req.send().await.map_err(|e| e.into())
+    fn https_call_with_options(
+        &self,
+        url: String,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> BoxPinFut<Result<reqwest::Response, BoxError>> {
+        let this = self.clone();
+        Box::pin(async move {
+            this.send_with_options(url, method, headers, body, opts)
+                .await
         })
     }
 
@@ -468,8 +773,14 @@ impl Web3ClientFeatures for Client {
         if let Err(err) = se.to_authorization(&mut headers) {
             return Box::pin(futures::future::ready(Err(err.into())));
         }
+        if let Err(err) = self.sign_http_message(&method, &url, &mut headers, body.as_deref()) {
+            return Box::pin(futures::future::ready(Err(err)));
+        }
 
-        let outer_http = self.outer_http.clone();
+        let outer_http = match self.signed_http_client(&url) {
+            Ok(http) => http,
+            Err(err) => return Box::pin(futures::future::ready(Err(err))),
+        };
         Box::pin(async move {
             let mut req = outer_http.request(method, url);
             req = req.headers(headers);
@@ -511,7 +822,10 @@ impl Web3ClientFeatures for Client {
             return Box::pin(futures::future::ready(Err(err.into())));
         }
 
-        let outer_http = self.outer_http.clone();
+        let outer_http = match self.signed_http_client(&endpoint) {
+            Ok(http) => http,
+            Err(err) => return Box::pin(futures::future::ready(Err(err))),
+        };
         Box::pin(async move {
             let res = cbor_rpc(&outer_http, &endpoint, &method, Some(headers), body).await?;
             Ok(res.into_vec())
@@ -520,7 +834,7 @@ impl Web3ClientFeatures for Client {
 }
 
 impl HttpFeatures for Client {
-    /// Makes an HTTPs request
+    /// Makes an HTTPs request, retrying transient failures if the client has a retry policy
     ///
     /// # Arguments
     /// * `url` - Target URL, should start with `https://`
@@ -534,18 +848,34 @@ impl HttpFeatures for Client {
         headers: Option<http::HeaderMap>,
         body: Option<Vec<u8>>, // default is empty
     ) -> Result<reqwest::Response, BoxError> {
-        if !self.allow_http && !url.starts_with("https://") {
-            return Err("Invalid url, must start with https://".into());
-        }
-        let mut req = self.outer_http.request(method, url);
-        if let Some(headers) = headers {
-            req = req.headers(headers);
-        }
-        if let Some(body) = body {
-            req = req.body(body);
-        }
+        self.send_with_retry(
+            url.to_string(),
+            method,
+            headers,
+            body,
+            self.default_http_options(),
+        )
+        .await
+    }
 
-        req.send().await.map_err(|e| e.into())
+    /// Makes an HTTPs request with per-request options
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `https://`
+    /// * `method` - HTTP method (GET, POST, etc.)
+    /// * `headers` - Optional HTTP headers
+    /// * `body` - Optional request body (default empty)
+    /// * `opts` - Timeouts, redirect policy and response size limit
+    async fn https_call_with_options(
+        &self,
+        url: &str,
+        method: http::Method,
+        headers: Option<http::HeaderMap>,
+        body: Option<Vec<u8>>,
+        opts: HttpOptions,
+    ) -> Result<reqwest::Response, BoxError> {
+        self.send_with_retry(url.to_string(), method, headers, body, opts)
+            .await
     }
 
     /// Makes a signed HTTPs request with message authentication
@@ -574,8 +904,9 @@ impl HttpFeatures for Client {
         )?;
         let mut headers = headers.unwrap_or_default();
         se.to_authorization(&mut headers)?;
+        self.sign_http_message(&method, url, &mut headers, body.as_deref())?;
 
-        let mut req = self.outer_http.request(method, url);
+        let mut req = self.signed_http_client(url)?.request(method, url);
         req = req.headers(headers);
         if let Some(body) = body {
             req = req.body(body);
@@ -613,10 +944,26 @@ impl HttpFeatures for Client {
             SignedEnvelope::sign_digest(self.identity.load().as_ref().as_ref(), digest.into())?;
         let mut headers = http::HeaderMap::new();
         se.to_authorization(&mut headers)?;
-        let res = cbor_rpc(&self.outer_http, endpoint, &method, Some(headers), body).await?;
+        let res = cbor_rpc(
+            &self.signed_http_client(endpoint)?,
+            endpoint,
+            &method,
+            Some(headers),
+            body,
+        )
+        .await?;
         let res = from_reader(&res[..])?;
         Ok(res)
     }
+
+    /// Connects to a WebSocket server
+    ///
+    /// # Arguments
+    /// * `url` - Target URL, should start with `wss://`
+    /// * `opts` - Handshake headers, keepalive and buffer options
+    async fn websocket_connect(&self, url: &str, opts: WsOptions) -> Result<WebSocket, BoxError> {
+        websocket_connect(url, opts, CancellationToken::new(), self.allow_http).await
+    }
 }
 
 /// Implements the `CoseSDK` trait for Client to enable IC-COSE canister API calls
@@ -1,3 +1,9 @@
 pub mod client;
+pub mod proxy;
+pub mod signature;
+pub mod tls;
 
 pub use client::*;
+pub use proxy::*;
+pub use signature::*;
+pub use tls::*;
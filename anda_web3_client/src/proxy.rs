@@ -0,0 +1,138 @@
+//! Egress proxy configuration for outbound HTTP requests.
+//!
+//! Many enterprise and TEE deployments can only reach the internet through an egress proxy.
+//! [`ProxyConfig`] supports HTTP, HTTPS and SOCKS5 proxies, configured globally and/or per domain.
+
+use anda_core::{BoxError, domain_match, normalize_domain};
+use reqwest::Url;
+use std::collections::BTreeMap;
+
+/// Proxy configuration for outbound HTTP requests.
+///
+/// Resolution order for a request host:
+/// 1. hosts matching `no_proxy` connect directly;
+/// 2. the most specific matching per-domain proxy;
+/// 3. the global proxy, if any.
+///
+/// Domains match the host itself and all its subdomains,
+/// e.g. `example.com` matches `example.com` and `api.example.com`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    global: Option<Url>,
+    domains: BTreeMap<String, Url>,
+    no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Creates an empty proxy configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the global proxy, e.g. `http://proxy:3128` or `socks5h://proxy:1080`.
+    pub fn with_global(mut self, proxy: &str) -> Result<Self, BoxError> {
+        self.global = Some(parse_proxy_url(proxy)?);
+        Ok(self)
+    }
+
+    /// Sets the proxy for a domain and its subdomains.
+    pub fn with_domain(mut self, domain: &str, proxy: &str) -> Result<Self, BoxError> {
+        self.domains
+            .insert(normalize_domain(domain)?, parse_proxy_url(proxy)?);
+        Ok(self)
+    }
+
+    /// Bypasses proxies for a domain and its subdomains.
+    pub fn with_no_proxy(mut self, domain: &str) -> Result<Self, BoxError> {
+        self.no_proxy.push(normalize_domain(domain)?);
+        Ok(self)
+    }
+
+    /// Returns true if no proxy is configured.
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none() && self.domains.is_empty()
+    }
+
+    /// Returns the proxy for the given request URL, `None` for a direct connection.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        if self.no_proxy.iter().any(|d| domain_match(d, &host)) {
+            return None;
+        }
+
+        self.domains
+            .iter()
+            .filter(|(d, _)| domain_match(d, &host))
+            .max_by_key(|(d, _)| d.len())
+            .map(|(_, p)| p.clone())
+            .or_else(|| self.global.clone())
+    }
+
+    /// Converts the configuration to a [`reqwest::Proxy`].
+    pub fn to_reqwest_proxy(&self) -> reqwest::Proxy {
+        let cfg = self.clone();
+        reqwest::Proxy::custom(move |url| cfg.proxy_for(url))
+    }
+}
+
+fn parse_proxy_url(proxy: &str) -> Result<Url, BoxError> {
+    let url = Url::parse(proxy).map_err(|err| format!("invalid proxy {}: {}", proxy, err))?;
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Ok(url),
+        scheme => Err(format!("unsupported proxy scheme {} in {}", scheme, proxy).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_config() {
+        let cfg = ProxyConfig::new();
+        assert!(cfg.is_empty());
+        assert!(
+            cfg.proxy_for(&Url::parse("https://example.com").unwrap())
+                .is_none()
+        );
+
+        let cfg = ProxyConfig::new()
+            .with_global("http://proxy:3128")
+            .unwrap()
+            .with_domain("openai.com", "socks5h://127.0.0.1:1080")
+            .unwrap()
+            .with_domain("*.api.openai.com", "https://secure-proxy:443")
+            .unwrap()
+            .with_no_proxy(".internal")
+            .unwrap();
+
+        let proxy = |u: &str| {
+            cfg.proxy_for(&Url::parse(u).unwrap())
+                .map(|p| p.to_string())
+        };
+        assert_eq!(
+            proxy("https://example.com/a"),
+            Some("http://proxy:3128/".to_string())
+        );
+        assert_eq!(
+            proxy("https://openai.com/v1"),
+            Some("socks5h://127.0.0.1:1080".to_string())
+        );
+        assert_eq!(
+            proxy("https://chat.openai.com/v1"),
+            Some("socks5h://127.0.0.1:1080".to_string())
+        );
+        assert_eq!(
+            proxy("https://api.openai.com/v1"),
+            Some("https://secure-proxy/".to_string())
+        );
+        assert_eq!(
+            proxy("https://notopenai.com"),
+            Some("http://proxy:3128/".to_string())
+        );
+        assert_eq!(proxy("https://svc.internal/x"), None);
+
+        assert!(ProxyConfig::new().with_global("ftp://proxy").is_err());
+        assert!(ProxyConfig::new().with_domain("", "http://proxy").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! [RFC 9421](https://www.rfc-editor.org/rfc/rfc9421) HTTP Message Signatures for signed HTTP requests.
+//!
+//! When enabled with `ClientBuilder::with_http_message_signatures`, `https_signed_call` emits
+//! standards-compliant `Signature-Input` and `Signature` headers in addition to the Anda
+//! `Authorization` envelope, so non-Anda services can verify signed requests.
+//!
+//! Covered components are `@method`, `@target-uri`, `content-digest`
+//! ([RFC 9530](https://www.rfc-editor.org/rfc/rfc9530), when there is a body) and `authorization`
+//! (when present). The `keyid` parameter is the signer's principal; verifiers resolve the public key
+//! out of band.
+
+use anda_core::BoxError;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use ic_agent::Identity;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Label of the signature in `Signature-Input` and `Signature` headers.
+pub static SIGNATURE_LABEL: &str = "anda";
+
+pub static HEADER_CONTENT_DIGEST: &str = "content-digest";
+pub static HEADER_SIGNATURE_INPUT: &str = "signature-input";
+pub static HEADER_SIGNATURE: &str = "signature";
+
+/// Signature parameters, serialized as the `@signature-params` component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureParams {
+    /// Covered component identifiers, e.g. `@method` or `content-digest`.
+    pub components: Vec<String>,
+    /// Creation time in seconds since the Unix epoch.
+    pub created: u64,
+    /// Key identifier.
+    pub keyid: String,
+}
+
+impl SignatureParams {
+    /// Serializes the parameters as a structured field inner list.
+    pub fn serialize(&self) -> String {
+        let components: Vec<String> = self
+            .components
+            .iter()
+            .map(|c| format!("\"{}\"", c))
+            .collect();
+        format!(
+            "({});created={};keyid=\"{}\"",
+            components.join(" "),
+            self.created,
+            self.keyid
+        )
+    }
+}
+
+/// Returns the `Content-Digest` header value of the body.
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", BASE64_STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Builds the signature base of the request.
+pub fn signature_base(
+    method: &http::Method,
+    url: &str,
+    headers: &http::HeaderMap,
+    params: &SignatureParams,
+) -> Result<String, BoxError> {
+    let target = reqwest::Url::parse(url).map_err(|err| format!("invalid url {}: {}", url, err))?;
+    let mut base = String::new();
+    for component in &params.components {
+        let value = match component.as_str() {
+            "@method" => method.as_str().to_string(),
+            "@target-uri" => target.as_str().to_string(),
+            "@authority" => match target.port() {
+                Some(port) => format!("{}:{}", target.host_str().unwrap_or_default(), port),
+                None => target.host_str().unwrap_or_default().to_string(),
+            },
+            "@path" => target.path().to_string(),
+            "@query" => format!("?{}", target.query().unwrap_or_default()),
+            name if name.starts_with('@') => {
+                return Err(format!("unsupported derived component {}", name).into());
+            }
+            name => {
+                let values = headers
+                    .get_all(name)
+                    .iter()
+                    .map(|v| v.to_str().map(|v| v.trim()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.is_empty() {
+                    return Err(format!("missing header {} for signature", name).into());
+                }
+                values.join(", ")
+            }
+        };
+        base.push_str(&format!("\"{}\": {}\n", component, value));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", params.serialize()));
+    Ok(base)
+}
+
+/// Signs the request with the identity, adds `Content-Digest`, `Signature-Input` and `Signature` headers.
+pub fn sign_http_message(
+    identity: &dyn Identity,
+    method: &http::Method,
+    url: &str,
+    headers: &mut http::HeaderMap,
+    body: Option<&[u8]>,
+) -> Result<(), BoxError> {
+    let mut components = vec!["@method".to_string(), "@target-uri".to_string()];
+    if let Some(body) = body {
+        headers.insert(HEADER_CONTENT_DIGEST, content_digest(body).parse()?);
+        components.push(HEADER_CONTENT_DIGEST.to_string());
+    }
+    if headers.contains_key(http::header::AUTHORIZATION) {
+        components.push(http::header::AUTHORIZATION.as_str().to_string());
+    }
+
+    let params = SignatureParams {
+        components,
+        created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        keyid: identity.sender()?.to_text(),
+    };
+    let base = signature_base(method, url, headers, &params)?;
+    let sig = identity.sign_arbitrary(base.as_bytes())?;
+    let sig = sig
+        .signature
+        .ok_or("identity does not support message signatures")?;
+
+    headers.insert(
+        HEADER_SIGNATURE_INPUT,
+        format!("{}={}", SIGNATURE_LABEL, params.serialize()).parse()?,
+    );
+    headers.insert(
+        HEADER_SIGNATURE,
+        format!("{}=:{}:", SIGNATURE_LABEL, BASE64_STANDARD.encode(sig)).parse()?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_consensus::SigningKey;
+    use ic_agent::identity::BasicIdentity;
+
+    #[test]
+    fn test_sign_http_message() {
+        let params = SignatureParams {
+            components: vec![
+                "@method".to_string(),
+                "@authority".to_string(),
+                "@path".to_string(),
+                "@query".to_string(),
+                "content-type".to_string(),
+            ],
+            created: 1618884473,
+            keyid: "test-key".to_string(),
+        };
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        let base = signature_base(
+            &http::Method::POST,
+            "https://example.com:8443/foo?param=Value&Pet=dog",
+            &headers,
+            &params,
+        )
+        .unwrap();
+        assert_eq!(
+            base,
+            "\"@method\": POST\n\"@authority\": example.com:8443\n\"@path\": /foo\n\"@query\": ?param=Value&Pet=dog\n\"content-type\": application/json\n\"@signature-params\": (\"@method\" \"@authority\" \"@path\" \"@query\" \"content-type\");created=1618884473;keyid=\"test-key\""
+        );
+
+        let sk = SigningKey::from([8u8; 32]);
+        let vk = sk.verification_key();
+        let identity = BasicIdentity::from_signing_key(sk);
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::AUTHORIZATION, "envelope".parse().unwrap());
+        sign_http_message(
+            &identity,
+            &http::Method::POST,
+            "https://example.com/rpc",
+            &mut headers,
+            Some(b"{\"hello\": \"world\"}"),
+        )
+        .unwrap();
+        assert_eq!(
+            headers.get(HEADER_CONTENT_DIGEST).unwrap(),
+            "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:"
+        );
+
+        let input = headers
+            .get(HEADER_SIGNATURE_INPUT)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let input = input.strip_prefix("anda=").unwrap();
+        assert!(input.starts_with(
+            "(\"@method\" \"@target-uri\" \"content-digest\" \"authorization\");created="
+        ));
+        let created: u64 = input
+            .split(";created=")
+            .nth(1)
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let params = SignatureParams {
+            components: vec![
+                "@method".to_string(),
+                "@target-uri".to_string(),
+                "content-digest".to_string(),
+                "authorization".to_string(),
+            ],
+            created,
+            keyid: identity.sender().unwrap().to_text(),
+        };
+        assert_eq!(params.serialize(), input);
+
+        let base = signature_base(
+            &http::Method::POST,
+            "https://example.com/rpc",
+            &headers,
+            &params,
+        )
+        .unwrap();
+        let sig = headers.get(HEADER_SIGNATURE).unwrap().to_str().unwrap();
+        let sig = sig
+            .strip_prefix("anda=:")
+            .unwrap()
+            .strip_suffix(':')
+            .unwrap();
+        let sig: [u8; 64] = BASE64_STANDARD.decode(sig).unwrap().try_into().unwrap();
+        assert!(vk.verify(&sig.into(), base.as_bytes()).is_ok());
+    }
+}
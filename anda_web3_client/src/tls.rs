@@ -0,0 +1,317 @@
+//! Per-domain TLS trust configuration for outbound HTTPS requests.
+//!
+//! Engine-to-engine and payment API traffic can be hardened against MITM in hostile
+//! environments by trusting additional root CAs, pinning the CAs or pinning the server
+//! certificates per domain.
+//!
+//! Each configured domain gets its own HTTP client. Redirects are never followed across
+//! domains of different TLS trust: the redirect response is returned to the caller instead,
+//! which may follow it with the client of the target domain.
+
+use anda_core::{
+    BoxError, RedirectPolicy, domain_match, normalize_domain, redirect_scheme_allowed,
+};
+use rustls::{
+    DigitallySignedStruct, SignatureScheme,
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        verify_server_name,
+    },
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject},
+    server::ParsedCertificate,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// TLS trust configuration for a domain.
+#[derive(Debug, Clone)]
+pub struct DomainTls {
+    /// Root CAs to trust for the domain.
+    pub certs: Vec<reqwest::Certificate>,
+    /// If true, only `certs` are trusted for the domain and built-in root CAs are disabled.
+    pub pinned: bool,
+    /// Pinned server certificates, if any the server must present one of them
+    /// and `certs` are not used.
+    pub server_certs: Vec<CertificateDer<'static>>,
+}
+
+/// Per-domain TLS trust configuration.
+///
+/// Domains match the host itself and all its subdomains; the most specific domain wins.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    domains: BTreeMap<String, DomainTls>,
+}
+
+impl TlsConfig {
+    /// Creates an empty TLS configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts an additional PEM-encoded root CA for the domain, alongside the built-in roots.
+    pub fn with_root_ca(mut self, domain: &str, pem: &[u8]) -> Result<Self, BoxError> {
+        let cert = reqwest::Certificate::from_pem(pem)
+            .map_err(|err| format!("invalid root CA for {}: {}", domain, err))?;
+        self.domain_mut(domain)?.certs.push(cert);
+        Ok(self)
+    }
+
+    /// Pins a PEM-encoded CA for the domain.
+    /// Connections to the domain will only trust server certificates issued by pinned CAs.
+    pub fn with_pinned_ca(mut self, domain: &str, pem: &[u8]) -> Result<Self, BoxError> {
+        let cert = reqwest::Certificate::from_pem(pem)
+            .map_err(|err| format!("invalid pinned CA for {}: {}", domain, err))?;
+        let tls = self.domain_mut(domain)?;
+        tls.pinned = true;
+        tls.certs.push(cert);
+        Ok(self)
+    }
+
+    /// Pins a PEM-encoded server certificate for the domain.
+    /// Connections to the domain will only accept the pinned server certificates, as is:
+    /// they must be valid for the host, but are not checked against any CA nor for expiry,
+    /// so the pin must be rotated with the certificate.
+    pub fn with_pinned_cert(mut self, domain: &str, pem: &[u8]) -> Result<Self, BoxError> {
+        let cert = CertificateDer::from_pem_slice(pem)
+            .map_err(|err| format!("invalid pinned certificate for {}: {}", domain, err))?;
+        ParsedCertificate::try_from(&cert)
+            .map_err(|err| format!("invalid pinned certificate for {}: {}", domain, err))?;
+        self.domain_mut(domain)?.server_certs.push(cert);
+        Ok(self)
+    }
+
+    /// Returns true if no domain is configured.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Returns the configured domains.
+    pub fn domains(&self) -> &BTreeMap<String, DomainTls> {
+        &self.domains
+    }
+
+    /// Returns the most specific configured domain of the host.
+    pub fn domain_for(&self, host: &str) -> Option<&str> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.domains
+            .keys()
+            .filter(|d| domain_match(d, &host))
+            .max_by_key(|d| d.len())
+            .map(|d| d.as_str())
+    }
+
+    /// Returns the redirect policy of the HTTP client for the domain (`None` for the default
+    /// client). It stops at redirects to hosts of another TLS domain, returning the redirect
+    /// response, so they don't escape the pinning of a domain nor reach a pinned domain
+    /// without it.
+    pub fn redirect_policy(
+        &self,
+        redirect: RedirectPolicy,
+        domain: Option<&str>,
+    ) -> reqwest::redirect::Policy {
+        if self.is_empty() || redirect == RedirectPolicy::None {
+            return redirect.to_reqwest_policy();
+        }
+
+        let tls = self.clone();
+        let domain = domain.map(|d| d.to_string());
+        let max = redirect.max_redirects();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max {
+                return attempt.error(format!("too many redirects, max {}", max));
+            }
+            if let Some(from) = attempt.previous().last()
+                && !redirect_scheme_allowed(from, attempt.url())
+            {
+                let err = format!(
+                    "redirect from {} to {} is not allowed",
+                    from.scheme(),
+                    attempt.url().scheme()
+                );
+                return attempt.error(err);
+            }
+            let target = attempt.url().host_str().and_then(|h| tls.domain_for(h));
+            if target == domain.as_deref() {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        })
+    }
+
+    fn domain_mut(&mut self, domain: &str) -> Result<&mut DomainTls, BoxError> {
+        Ok(self
+            .domains
+            .entry(normalize_domain(domain)?)
+            .or_insert_with(|| DomainTls {
+                certs: Vec::new(),
+                pinned: false,
+                server_certs: Vec::new(),
+            }))
+    }
+}
+
+/// Returns a rustls client configuration that only accepts the pinned server certificates.
+pub(crate) fn pinned_cert_config(
+    certs: &[CertificateDer<'static>],
+) -> Result<rustls::ClientConfig, BoxError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = PinnedCertVerifier {
+        certs: certs.to_vec(),
+        provider: provider.clone(),
+    };
+    let mut cfg = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(cfg)
+}
+
+/// Verifies that the server presents one of the pinned certificates, valid for the host.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    certs: Vec<CertificateDer<'static>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.certs.iter().any(|c| c.as_ref() == end_entity.as_ref()) {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        verify_server_name(&ParsedCertificate::try_from(end_entity)?, server_name)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // self-signed certificates of pinned.example.com
+    const CERT_A: &str = "-----BEGIN CERTIFICATE-----
+MIIBrDCCAVOgAwIBAgIUJQbUrlkpv4YJCZn2pnoAOyG8LqQwCgYIKoZIzj0EAwIw
+HTEbMBkGA1UEAwwScGlubmVkLmV4YW1wbGUuY29tMCAXDTI2MTAxNzAzNTcxNFoY
+DzIxMjYwOTIzMDM1NzE0WjAdMRswGQYDVQQDDBJwaW5uZWQuZXhhbXBsZS5jb20w
+WTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAASB00UYG7HfED8DGLkj03YhNik3AQpy
+yZUdq2tfoqWCzz82yOKB9fuvlaWKsd6XX1nwurOQI8iV21P70aDeuVFSo28wbTAd
+BgNVHQ4EFgQUuretkkGLYRSiokKaQ+hVAE6isOwwHwYDVR0jBBgwFoAUuretkkGL
+YRSiokKaQ+hVAE6isOwwHQYDVR0RBBYwFIIScGlubmVkLmV4YW1wbGUuY29tMAwG
+A1UdEwEB/wQCMAAwCgYIKoZIzj0EAwIDRwAwRAIgR5nMbkkTQjf/FS4HR9Q14k0V
+p2lLqqfNy+xEEpbUdYkCIG7b5Jgi59l+bXPQeY9Lph5ou2dSstDCMJC1tI03w/B9
+-----END CERTIFICATE-----
+";
+    const CERT_B: &str = "-----BEGIN CERTIFICATE-----
+MIIBrTCCAVOgAwIBAgIUCo6DwSV2pBIsksqxtwlQxUxlSYcwCgYIKoZIzj0EAwIw
+HTEbMBkGA1UEAwwScGlubmVkLmV4YW1wbGUuY29tMCAXDTI2MTAxNzAzNTcxNFoY
+DzIxMjYwOTIzMDM1NzE0WjAdMRswGQYDVQQDDBJwaW5uZWQuZXhhbXBsZS5jb20w
+WTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAStECoKnvHuX03Zv8PzbXcil3SdFkGG
++5egXheMjbjiL1wn+xfc4e515lT4yeMs1Hk9ezErsQh1OLfuwPgcVgk8o28wbTAd
+BgNVHQ4EFgQUFEWSHvigdhSZlmqzinThl1McHI4wHwYDVR0jBBgwFoAUFEWSHvig
+dhSZlmqzinThl1McHI4wHQYDVR0RBBYwFIIScGlubmVkLmV4YW1wbGUuY29tMAwG
+A1UdEwEB/wQCMAAwCgYIKoZIzj0EAwIDSAAwRQIgFVNWi41kjG9an/N+4J83u5vn
+6immhTP3vLL/dA2ig78CIQDPL/FsRqxZVviST0ESIgXKdntMTwLgsLslsMZFZzlZ
+Zg==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_pinned_cert() {
+        let tls = TlsConfig::new()
+            .with_pinned_cert("example.com", CERT_A.as_bytes())
+            .unwrap();
+        assert!(
+            TlsConfig::new()
+                .with_pinned_cert("example.com", b"not a cert")
+                .is_err()
+        );
+        let pinned = &tls.domains()["example.com"];
+        assert_eq!(pinned.server_certs.len(), 1);
+        assert!(pinned_cert_config(&pinned.server_certs).is_ok());
+
+        let verifier = PinnedCertVerifier {
+            certs: pinned.server_certs.clone(),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        };
+        let cert_a = CertificateDer::from_pem_slice(CERT_A.as_bytes()).unwrap();
+        let cert_b = CertificateDer::from_pem_slice(CERT_B.as_bytes()).unwrap();
+        let verify = |cert: &CertificateDer<'_>, host: &'static str| {
+            verifier.verify_server_cert(
+                cert,
+                &[],
+                &ServerName::try_from(host).unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+        };
+
+        assert!(verify(&cert_a, "pinned.example.com").is_ok());
+        // same name, another certificate
+        assert!(verify(&cert_b, "pinned.example.com").is_err());
+        // the pinned certificate is not valid for the host
+        assert!(verify(&cert_a, "other.example.com").is_err());
+    }
+
+    #[test]
+    fn test_domain_for() {
+        let tls = TlsConfig::new()
+            .with_pinned_cert("example.com", CERT_A.as_bytes())
+            .unwrap()
+            .with_pinned_ca("api.example.com", CERT_B.as_bytes())
+            .unwrap();
+        assert!(tls.domains()["api.example.com"].pinned);
+        assert_eq!(tls.domain_for("example.com"), Some("example.com"));
+        assert_eq!(tls.domain_for("www.Example.com."), Some("example.com"));
+        assert_eq!(
+            tls.domain_for("v1.api.example.com"),
+            Some("api.example.com")
+        );
+        assert_eq!(tls.domain_for("example.org"), None);
+    }
+}
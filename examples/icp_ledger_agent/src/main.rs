@@ -2,10 +2,10 @@ use anda_core::BoxError;
 use anda_engine::{
     context::Web3SDK,
     engine::{EngineBuilder, ManagementBuilder, Visibility},
-    model::{Model, deepseek, openai, xai},
+    model::{Model, deepseek, gemini, openai, xai},
     store::{InMemory, Store},
 };
-use anda_engine_server::{ServerBuilder, shutdown_signal};
+use anda_engine_server::{ServerBuilder, termination_signal};
 use anda_icp::ledger::BalanceOfTool;
 use anda_web3_client::client::{Client as Web3Client, load_identity};
 use clap::Parser;
@@ -51,6 +51,10 @@ struct Cli {
     #[arg(long, env = "XAI_API_KEY", default_value = "")]
     xai_api_key: String,
 
+    /// Gemini API key for AI model
+    #[arg(long, env = "GEMINI_API_KEY", default_value = "")]
+    gemini_api_key: String,
+
     /// AI model endpoint, empty for default to auto-detect
     #[arg(long, env = "MODEL_ENDPOINT", default_value = "")]
     model_endpoint: String,
@@ -141,6 +145,11 @@ async fn main() -> Result<(), BoxError> {
             xai::Client::new(&cli.xai_api_key, Some(cli.model_endpoint))
                 .completion_model(&cli.model_name),
         )
+    } else if !cli.gemini_api_key.is_empty() {
+        Arc::new(
+            gemini::Client::new(&cli.gemini_api_key, Some(cli.model_endpoint))
+                .completion_model(&cli.model_name),
+        )
     } else {
         return Err("missing AI model API key".into());
     });
@@ -178,8 +187,9 @@ async fn main() -> Result<(), BoxError> {
         .with_app_version(APP_VERSION.to_string())
         .with_addr(format!("127.0.0.1:{}", cli.port))
         .with_engines(engines, None)
-        .serve(shutdown_signal(global_cancel_token))
+        .serve(termination_signal())
         .await?;
+    global_cancel_token.cancel();
 
     Ok(())
 }